@@ -0,0 +1,6 @@
+ALTER TABLE job_configs
+    ADD COLUMN restart_policy JSONB;
+
+ALTER TABLE job_statuses
+    ADD COLUMN restart_backoff_micros BIGINT,
+    ADD COLUMN next_restart_time TIMESTAMPTZ;
@@ -0,0 +1,6 @@
+ALTER TABLE job_configs
+    ADD COLUMN bounds JSONB,
+    ADD COLUMN schedule JSONB;
+
+ALTER TABLE job_statuses
+    ADD COLUMN final_checkpoint_epoch INTEGER;
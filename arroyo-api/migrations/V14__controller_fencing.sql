@@ -0,0 +1,2 @@
+-- each elected controller takes a new, larger id, which fences off writes from previous leaders
+CREATE SEQUENCE controller_ids;
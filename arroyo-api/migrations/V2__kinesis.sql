@@ -0,0 +1,2 @@
+ALTER TYPE source_type ADD VALUE 'kinesis';
+ALTER TYPE sink_type ADD VALUE 'kinesis';
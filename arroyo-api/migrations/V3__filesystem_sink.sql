@@ -0,0 +1 @@
+ALTER TYPE sink_type ADD VALUE 'filesystem';
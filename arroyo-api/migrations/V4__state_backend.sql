@@ -0,0 +1,5 @@
+CREATE TYPE state_backend_type AS ENUM ('memory', 'spill');
+
+ALTER TABLE job_configs
+    ADD COLUMN state_backend state_backend_type NOT NULL DEFAULT 'memory',
+    ADD COLUMN max_in_memory_state_entries BIGINT;
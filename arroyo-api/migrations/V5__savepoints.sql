@@ -0,0 +1,26 @@
+CREATE TYPE savepoint_state AS ENUM ('requested', 'inprogress', 'ready', 'failed');
+
+CREATE TABLE savepoints (
+    id BIGSERIAL PRIMARY KEY,
+    pub_id VARCHAR(8) UNIQUE NOT NULL,
+    organization_id VARCHAR NOT NULL,
+    name TEXT NOT NULL,
+    created_by VARCHAR NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP NOT NULL,
+
+    -- savepoints outlive the job they were taken from, so the pipeline name is denormalized
+    job_id VARCHAR(8) REFERENCES job_configs(id) ON DELETE SET NULL,
+    pipeline_name TEXT NOT NULL,
+
+    state savepoint_state NOT NULL DEFAULT 'requested',
+    epoch INT,
+    finish_time TIMESTAMPTZ,
+    failure_message TEXT,
+
+    UNIQUE (organization_id, name)
+);
+
+CREATE INDEX savepoints_job_id_idx ON savepoints (job_id);
+
+ALTER TABLE job_configs
+    ADD COLUMN restore_savepoint_id BIGINT REFERENCES savepoints(id);
@@ -0,0 +1,4 @@
+CREATE TYPE checkpoint_mode AS ENUM ('aligned', 'unaligned');
+
+ALTER TABLE job_configs
+    ADD COLUMN checkpoint_mode checkpoint_mode NOT NULL DEFAULT 'aligned';
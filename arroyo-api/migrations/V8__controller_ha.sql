@@ -0,0 +1,11 @@
+-- the workers of each job's current run, so that a newly elected controller can reconnect to them
+CREATE TABLE job_workers (
+    job_id VARCHAR(8) NOT NULL REFERENCES job_configs(id) ON DELETE CASCADE,
+    run_id BIGINT NOT NULL,
+    worker_id BIGINT NOT NULL,
+    rpc_address TEXT NOT NULL,
+    data_address TEXT NOT NULL,
+    slots INT NOT NULL,
+
+    PRIMARY KEY (job_id, worker_id)
+);
@@ -0,0 +1,4 @@
+-- the subtasks assigned to each worker, which no longer follow from the set of workers once part
+-- of a job has been restarted on new ones
+ALTER TABLE job_workers
+    ADD COLUMN tasks JSONB NOT NULL DEFAULT '[]';
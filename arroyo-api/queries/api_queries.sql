@@ -124,7 +124,7 @@ WHERE id = :pipeline_id AND organization_id = :organization_id;
 
 ----------- jobs -----------------------
 
---! update_job(checkpoint_interval_micros?, stop?, parallelism_overrides?)
+--! update_job(checkpoint_interval_micros?, stop?, parallelism_overrides?, restart_policy?, autoscaling?, resources?, bounds?, schedule?)
 UPDATE job_configs
 SET
    updated_at = :updated_at,
@@ -132,27 +132,32 @@ SET
 
    stop = COALESCE(:stop, stop),
    checkpoint_interval_micros = COALESCE(:checkpoint_interval_micros, checkpoint_interval_micros),
-   parallelism_overrides = COALESCE(:parallelism_overrides, parallelism_overrides)
+   parallelism_overrides = COALESCE(:parallelism_overrides, parallelism_overrides),
+   restart_policy = COALESCE(:restart_policy, restart_policy),
+   autoscaling = COALESCE(:autoscaling, autoscaling),
+   resources = COALESCE(:resources, resources),
+   bounds = COALESCE(:bounds, bounds),
+   schedule = COALESCE(:schedule, schedule)
 WHERE id = :job_id AND organization_id = :organization_id;
 
---! create_job(ttl_micros?)
+--! create_job(ttl_micros?, max_in_memory_state_entries?, restore_savepoint_id?, checkpoint_url?, restart_policy?, autoscaling?, resources?, bounds?, schedule?)
 INSERT INTO job_configs
-    (id, organization_id, pipeline_name, created_by, pipeline_definition, checkpoint_interval_micros, ttl_micros)
-VALUES (:id, :organization_id, :pipeline_name, :created_by, :pipeline_definition, :checkpoint_interval_micros, :ttl_micros);
+    (id, organization_id, pipeline_name, created_by, pipeline_definition, checkpoint_interval_micros, ttl_micros, state_backend, max_in_memory_state_entries, restore_savepoint_id, checkpoint_mode, checkpoint_url, restart_policy, autoscaling, resources, bounds, schedule)
+VALUES (:id, :organization_id, :pipeline_name, :created_by, :pipeline_definition, :checkpoint_interval_micros, :ttl_micros, :state_backend, :max_in_memory_state_entries, :restore_savepoint_id, :checkpoint_mode, :checkpoint_url, :restart_policy, :autoscaling, :resources, :bounds, :schedule);
 
 --! create_job_status
 INSERT INTO job_statuses (id, organization_id) VALUES (:id, :organization_id);
 
---! get_jobs: (start_time?, finish_time?, state?, tasks?, textual_repr?, failure_message?, run_id?)
-SELECT job_configs.id as id, pipeline_name, stop, textual_repr, start_time, finish_time, state, tasks, pipeline_definition, failure_message, run_id
+--! get_jobs: (start_time?, finish_time?, state?, tasks?, textual_repr?, failure_message?, run_id?, restart_backoff_micros?, next_restart_time?, final_checkpoint_epoch?)
+SELECT job_configs.id as id, pipeline_name, stop, textual_repr, start_time, finish_time, state, tasks, pipeline_definition, failure_message, run_id, restart_backoff_micros, next_restart_time, final_checkpoint_epoch
 FROM job_configs
          LEFT JOIN job_statuses ON job_configs.id = job_statuses.id
          INNER JOIN pipeline_definitions ON pipeline_definition = pipeline_definitions.id
 WHERE job_configs.organization_id = :organization_id AND ttl_micros IS NULL
 ORDER BY COALESCE(job_configs.updated_at, job_configs.created_at) DESC;
 
---! get_job_details: (start_time?, finish_time?, state?, tasks?, textual_repr?, failure_message?, run_id?)
-SELECT pipeline_name, stop, parallelism_overrides, state, start_time, finish_time, tasks, textual_repr, program, pipeline_definition, failure_message, run_id
+--! get_job_details: (start_time?, finish_time?, state?, tasks?, textual_repr?, failure_message?, run_id?, restart_backoff_micros?, next_restart_time?, final_checkpoint_epoch?)
+SELECT pipeline_name, stop, parallelism_overrides, state, start_time, finish_time, tasks, textual_repr, program, pipeline_definition, failure_message, run_id, restart_backoff_micros, next_restart_time, final_checkpoint_epoch
 FROM job_configs
          LEFT JOIN job_statuses ON job_configs.id = job_statuses.id
          INNER JOIN pipeline_definitions ON pipeline_definition = pipeline_definitions.id
@@ -166,8 +171,10 @@ WHERE job_id = :job_id
     AND state != 'failed'
 ORDER BY epoch;
 
---! get_checkpoint_details: (finish_time?, operators?)
-SELECT epoch, state_backend, start_time, finish_time, operators FROM checkpoints
+--! get_checkpoint_details: (finish_time?, operators?, checkpoint_url?)
+SELECT epoch, state_backend, start_time, finish_time, operators,
+    (SELECT checkpoint_url FROM job_configs WHERE job_configs.id = checkpoints.job_id) as checkpoint_url
+FROM checkpoints
 WHERE job_id = :job_id
     AND organization_id = :organization_id
     AND epoch = :epoch
@@ -180,3 +187,22 @@ DELETE FROM pipelines WHERE pipelines.id = (
     INNER JOIN pipeline_definitions as pd ON job_configs.pipeline_definition = pd.id
     INNER JOIN pipelines ON pipelines.id = pd.pipeline_id
     WHERE job_configs.id = :job_id AND job_configs.organization_id = :organization_id);
+
+
+----------- savepoints -----------------
+
+--! create_savepoint
+INSERT INTO savepoints (pub_id, organization_id, name, created_by, job_id, pipeline_name)
+SELECT :pub_id, CAST(:organization_id AS VARCHAR), :name, :created_by, id, pipeline_name
+FROM job_configs
+WHERE id = :job_id AND organization_id = :organization_id;
+
+--! get_savepoints: (job_id?, epoch?, finish_time?, failure_message?)
+SELECT pub_id, name, job_id, pipeline_name, state, epoch, created_at, finish_time, failure_message
+FROM savepoints
+WHERE organization_id = :organization_id
+ORDER BY created_at DESC;
+
+--! get_savepoint
+SELECT id, state FROM savepoints
+WHERE pub_id = :pub_id AND organization_id = :organization_id;
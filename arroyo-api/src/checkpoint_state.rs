@@ -0,0 +1,260 @@
+use arroyo_rpc::grpc::api::{
+    CheckpointTable, CheckpointTablesResp, ExportCheckpointStateReq, ExportCheckpointStateResp,
+    OperatorCheckpointTables, StateExportFormat,
+};
+use arroyo_rpc::grpc::OperatorCheckpointMetadata;
+use arroyo_state::parquet::{ParquetBackend, StateRow, TableFilter};
+use arroyo_state::BackingStore;
+use arroyo_types::{from_micros, to_micros};
+use cornucopia_async::GenericClient;
+use serde_json::{json, Value};
+use tonic::Status;
+
+use crate::{jobs, log_and_map, required_field, AuthData};
+
+const DEFAULT_EXPORT_LIMIT: u64 = 10_000;
+
+pub(crate) async fn get_checkpoint_tables(
+    job_id: &str,
+    epoch: u32,
+    auth: AuthData,
+    client: &impl GenericClient,
+) -> Result<CheckpointTablesResp, Status> {
+    // ensures that the checkpoint exists and belongs to the user's organization
+    jobs::checkpoint_details(job_id, epoch, auth, client).await?;
+
+    let metadata = ParquetBackend::load_checkpoint_metadata(job_id, epoch)
+        .await
+        .map_err(log_and_map)?
+        .ok_or_else(|| {
+            Status::not_found(format!(
+                "Checkpoint {} for job '{}' is no longer available",
+                epoch, job_id
+            ))
+        })?;
+
+    let mut operators = vec![];
+    for operator_id in &metadata.operator_ids {
+        let operator_metadata = load_operator_metadata(job_id, operator_id, epoch).await?;
+
+        let tables = operator_metadata
+            .tables
+            .iter()
+            .map(|table| CheckpointTable {
+                name: table.name.clone(),
+                description: table.description.clone(),
+                table_type: format!("{:?}", table.table_type()),
+                files: operator_metadata
+                    .backend_data
+                    .iter()
+                    .filter(|data| match &data.backend_data {
+                        Some(arroyo_rpc::grpc::backend_data::BackendData::ParquetStore(p)) => {
+                            p.table == table.name
+                        }
+                        None => false,
+                    })
+                    .count() as u64,
+                has_schema: table.schema.is_some(),
+            })
+            .collect();
+
+        operators.push(OperatorCheckpointTables {
+            operator_id: operator_id.clone(),
+            tables,
+        });
+    }
+
+    Ok(CheckpointTablesResp { operators })
+}
+
+pub(crate) async fn export_checkpoint_state(
+    req: ExportCheckpointStateReq,
+    auth: AuthData,
+    client: &impl GenericClient,
+) -> Result<ExportCheckpointStateResp, Status> {
+    if req.operator_id.is_empty() {
+        return Err(required_field("operator_id"));
+    }
+    if req.table.is_empty() {
+        return Err(required_field("table"));
+    }
+
+    jobs::checkpoint_details(&req.job_id, req.epoch, auth, client).await?;
+
+    let metadata = load_operator_metadata(&req.job_id, &req.operator_id, req.epoch).await?;
+    if !metadata.tables.iter().any(|t| t.name == req.table) {
+        return Err(Status::not_found(format!(
+            "Operator {} has no table '{}' in checkpoint {}",
+            req.operator_id, req.table, req.epoch
+        )));
+    }
+
+    let filter = TableFilter {
+        key_range: req.min_key_hash.unwrap_or(0)..=req.max_key_hash.unwrap_or(u64::MAX),
+        start_time: req.start_time.map(from_micros),
+        end_time: req.end_time.map(from_micros),
+    };
+    let limit = req.limit.unwrap_or(DEFAULT_EXPORT_LIMIT) as usize;
+
+    let (rows, truncated) = ParquetBackend::read_table(&metadata, &req.table, &filter, limit)
+        .await
+        .map_err(|e| {
+            Status::failed_precondition(format!("Unable to read table '{}': {}", req.table, e))
+        })?;
+
+    let data = match req.format() {
+        StateExportFormat::StateJson => to_json(&rows),
+        StateExportFormat::StateCsv => to_csv(&rows).map_err(log_and_map)?,
+    };
+
+    Ok(ExportCheckpointStateResp {
+        data,
+        rows: rows.len() as u64,
+        truncated,
+    })
+}
+
+async fn load_operator_metadata(
+    job_id: &str,
+    operator_id: &str,
+    epoch: u32,
+) -> Result<OperatorCheckpointMetadata, Status> {
+    ParquetBackend::load_operator_metadata(job_id, operator_id, epoch)
+        .await
+        .map_err(log_and_map)?
+        .ok_or_else(|| {
+            Status::not_found(format!(
+                "Checkpoint {} for job '{}' has no state for operator {}",
+                epoch, job_id, operator_id
+            ))
+        })
+}
+
+fn to_json(rows: &[StateRow]) -> String {
+    Value::Array(
+        rows.iter()
+            .map(|row| {
+                json!({
+                    "key_hash": row.key_hash,
+                    "timestamp": to_micros(row.timestamp),
+                    "key": row.key,
+                    "value": row.value,
+                })
+            })
+            .collect(),
+    )
+    .to_string()
+}
+
+// struct values are split into a column per field; everything else is written as JSON
+fn to_csv(rows: &[StateRow]) -> anyhow::Result<String> {
+    let fields: Option<Vec<String>> = rows
+        .first()
+        .and_then(|row| row.value.as_object())
+        .map(|value| value.keys().cloned().collect());
+
+    let mut writer = csv::Writer::from_writer(vec![]);
+
+    let mut header = vec![
+        "key_hash".to_string(),
+        "timestamp".to_string(),
+        "key".to_string(),
+    ];
+    match &fields {
+        Some(fields) => header.extend(fields.iter().cloned()),
+        None => header.push("value".to_string()),
+    }
+    writer.write_record(&header)?;
+
+    for row in rows {
+        let mut record = vec![
+            row.key_hash.to_string(),
+            to_micros(row.timestamp).to_string(),
+            csv_value(&row.key),
+        ];
+        match &fields {
+            Some(fields) => record.extend(
+                fields
+                    .iter()
+                    .map(|field| csv_value(row.value.get(field).unwrap_or(&Value::Null))),
+            ),
+            None => record.push(csv_value(&row.value)),
+        }
+        writer.write_record(&record)?;
+    }
+
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+fn csv_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_csv, to_json};
+    use arroyo_state::parquet::StateRow;
+    use arroyo_types::from_micros;
+    use serde_json::{json, Value};
+
+    fn row(key_hash: u64, timestamp: u64, key: Value, value: Value) -> StateRow {
+        StateRow {
+            key_hash,
+            timestamp: from_micros(timestamp),
+            key,
+            value,
+        }
+    }
+
+    #[test]
+    fn test_to_json() {
+        let rows = vec![
+            row(1, 1_000, json!("k1"), json!({"count": 1})),
+            row(2, 2_000, json!(null), json!([1, 2])),
+        ];
+
+        let exported: Value = serde_json::from_str(&to_json(&rows)).unwrap();
+        assert_eq!(
+            json!([
+                {"key_hash": 1, "timestamp": 1_000, "key": "k1", "value": {"count": 1}},
+                {"key_hash": 2, "timestamp": 2_000, "key": null, "value": [1, 2]},
+            ]),
+            exported
+        );
+
+        assert_eq!("[]", to_json(&[]));
+    }
+
+    #[test]
+    fn test_to_csv() {
+        // struct values are split into columns, going by the fields of the first row
+        let rows = vec![
+            row(1, 1_000, json!("k1"), json!({"count": 1, "name": "a,b"})),
+            row(
+                2,
+                2_000,
+                json!({"id": 2}),
+                json!({"count": 2, "other": true}),
+            ),
+        ];
+        assert_eq!(
+            "key_hash,timestamp,key,count,name\n\
+             1,1000,k1,1,\"a,b\"\n\
+             2,2000,\"{\"\"id\"\":2}\",2,\n",
+            to_csv(&rows).unwrap()
+        );
+
+        let rows = vec![
+            row(1, 1_000, json!(1), json!(5)),
+            row(2, 2_000, json!(2), json!(null)),
+        ];
+        assert_eq!(
+            "key_hash,timestamp,key,value\n1,1000,1,5\n2,2000,2,\n",
+            to_csv(&rows).unwrap()
+        );
+    }
+}
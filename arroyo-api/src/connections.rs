@@ -26,8 +26,15 @@ pub(crate) async fn create_connection(
             public::ConnectionType::kafka,
             serde_json::to_value(k).map_err(log_and_map)?,
         ),
-        ReqConnectionType::Kinesis(_) => {
-            return Err(Status::failed_precondition("Kinesis is not yet supported"));
+        ReqConnectionType::Kinesis(k) => {
+            if k.region.is_empty() {
+                return Err(required_field("connection.kinesis.region"));
+            }
+
+            (
+                public::ConnectionType::kinesis,
+                serde_json::to_value(k).map_err(log_and_map)?,
+            )
         }
     };
 
@@ -1,7 +1,12 @@
 use arroyo_datastream::Program;
 use arroyo_rpc::grpc::api::{
-    CheckpointDetailsResp, CheckpointOverview, CreateJobReq, JobDetailsResp, JobStatus,
-    PipelineProgram, StopType,
+    self, restart_policy::Policy, CheckpointDetailsResp, CheckpointMode, CheckpointOverview,
+    CreateJobReq, JobDetailsResp, JobStatus, PipelineProgram, StateBackendType, StopType,
+};
+use arroyo_state::storage::StorageClient;
+use arroyo_types::{
+    AutoscalingConfig, JobBounds, JobResources, JobSchedule, ResourceProfile, RestartPolicy,
+    SourceBounds, SourcePosition,
 };
 use cornucopia_async::GenericClient;
 use deadpool_postgres::{Pool, Transaction};
@@ -13,9 +18,12 @@ use tonic::Status;
 
 const PREVIEW_TTL: Duration = Duration::from_secs(60);
 
-use crate::{log_and_map, pipelines, queries::api_queries, to_micros, types::public, AuthData};
+use crate::{
+    log_and_map, pipelines, queries::api_queries, required_field, savepoints, to_micros,
+    types::public, AuthData,
+};
 
-fn gen_id() -> String {
+pub(crate) fn gen_id() -> String {
     rand::thread_rng()
         .sample_iter(&Alphanumeric)
         .take(8)
@@ -24,6 +32,132 @@ fn gen_id() -> String {
         .collect()
 }
 
+// converts a restart policy from the API into the form it's stored in
+pub(crate) fn restart_policy_json(policy: api::RestartPolicy) -> Result<serde_json::Value, Status> {
+    let policy = match policy
+        .policy
+        .ok_or_else(|| required_field("restart_policy.policy"))?
+    {
+        Policy::FixedDelay(p) => RestartPolicy::FixedDelay {
+            delay_micros: p.delay_micros,
+            max_restarts: p.max_restarts,
+        },
+        Policy::ExponentialBackoff(p) => RestartPolicy::ExponentialBackoff {
+            initial_delay_micros: p.initial_delay_micros,
+            max_delay_micros: p.max_delay_micros,
+            multiplier: p.multiplier,
+            jitter: p.jitter,
+            max_restarts: p.max_restarts,
+        },
+        Policy::FailureRate(p) => RestartPolicy::FailureRate {
+            max_failures: p.max_failures,
+            window_micros: p.window_micros,
+            delay_micros: p.delay_micros,
+        },
+        Policy::NeverFail(p) => RestartPolicy::NeverFail {
+            delay_micros: p.delay_micros,
+        },
+    };
+
+    policy
+        .validate()
+        .map_err(|e| Status::invalid_argument(format!("invalid restart_policy: {}", e)))?;
+
+    serde_json::to_value(policy).map_err(log_and_map)
+}
+
+// converts an autoscaling config from the API into the form it's stored in
+pub(crate) fn autoscaling_json(
+    config: api::AutoscalingConfig,
+) -> Result<serde_json::Value, Status> {
+    let config = AutoscalingConfig {
+        enabled: config.enabled,
+        min_parallelism: config.min_parallelism,
+        max_parallelism: config.max_parallelism,
+        target_utilization: config.target_utilization,
+        scale_up_utilization: config.scale_up_utilization,
+        scale_down_utilization: config.scale_down_utilization,
+        max_source_lag_micros: config.max_source_lag_micros,
+        stabilization_micros: config.stabilization_micros,
+        cooldown_micros: config.cooldown_micros,
+    };
+
+    config
+        .validate()
+        .map_err(|e| Status::invalid_argument(format!("invalid autoscaling config: {}", e)))?;
+
+    serde_json::to_value(config).map_err(log_and_map)
+}
+
+// converts the resources required by a job's subtasks from the API into the form they're stored in
+pub(crate) fn resources_json(resources: api::JobResources) -> Result<serde_json::Value, Status> {
+    let profile = |p: api::ResourceProfile| ResourceProfile::new(p.cpu_millis, p.memory_mb);
+
+    let resources = JobResources {
+        subtask: resources.subtask.map(profile).unwrap_or_default(),
+        operators: resources
+            .operators
+            .into_iter()
+            .map(|(operator_id, p)| (operator_id, profile(p)))
+            .collect(),
+    };
+
+    serde_json::to_value(resources).map_err(log_and_map)
+}
+
+fn source_position(position: api::SourcePosition) -> Result<SourcePosition, Status> {
+    use api::source_position::Position;
+
+    match position.position {
+        Some(Position::Offsets(o)) => Ok(SourcePosition::Offsets { offsets: o.offsets }),
+        Some(Position::TimestampMicros(micros)) => Ok(SourcePosition::Timestamp { micros }),
+        Some(Position::Latest(_)) => Ok(SourcePosition::Latest),
+        None => Err(Status::invalid_argument(
+            "source positions must have offsets, a timestamp or latest",
+        )),
+    }
+}
+
+fn source_bounds(bounds: api::SourceBounds) -> Result<SourceBounds, Status> {
+    Ok(SourceBounds {
+        start: bounds.start.map(source_position).transpose()?,
+        end: bounds.end.map(source_position).transpose()?,
+    })
+}
+
+// converts the bounds of a job's sources from the API into the form they're stored in
+pub(crate) fn bounds_json(bounds: api::JobBounds) -> Result<serde_json::Value, Status> {
+    let bounds = JobBounds {
+        sources: bounds
+            .sources
+            .map(source_bounds)
+            .transpose()?
+            .unwrap_or_default(),
+        operators: bounds
+            .operators
+            .into_iter()
+            .map(|(operator_id, b)| Ok((operator_id, source_bounds(b)?)))
+            .collect::<Result<_, Status>>()?,
+    };
+
+    serde_json::to_value(bounds).map_err(log_and_map)
+}
+
+// converts a job's schedule from the API into the form it's stored in
+pub(crate) fn schedule_json(schedule: api::JobSchedule) -> Result<serde_json::Value, Status> {
+    let schedule = JobSchedule {
+        start_micros: schedule.start_micros,
+        interval_micros: schedule.interval_micros,
+        stop_micros: schedule.stop_micros,
+    };
+
+    schedule
+        .validate()
+        .map_err(|e| Status::invalid_argument(format!("invalid schedule: {}", e)))?;
+
+    serde_json::to_value(schedule).map_err(log_and_map)
+}
+
 pub(crate) async fn create_job<'a>(
     request: CreateJobReq,
     auth: AuthData,
@@ -45,6 +179,53 @@ pub(crate) async fn create_job<'a>(
         ));
     }
 
+    let state_backend = match request.state_backend() {
+        StateBackendType::Memory => public::StateBackendType::memory,
+        StateBackendType::Spill => public::StateBackendType::spill,
+    };
+
+    if request.max_in_memory_state_entries == Some(0) {
+        return Err(Status::invalid_argument(
+            "max_in_memory_state_entries must be greater than 0",
+        ));
+    }
+
+    let checkpoint_mode = match request.checkpoint_mode() {
+        CheckpointMode::Aligned => public::CheckpointMode::aligned,
+        CheckpointMode::Unaligned => public::CheckpointMode::unaligned,
+    };
+
+    if let Some(url) = &request.checkpoint_url {
+        StorageClient::validate_url(url).map_err(|e| {
+            Status::invalid_argument(format!("invalid checkpoint_url '{}': {}", url, e))
+        })?;
+    }
+
+    let restart_policy = request
+        .restart_policy
+        .clone()
+        .map(restart_policy_json)
+        .transpose()?;
+
+    let autoscaling = request
+        .autoscaling
+        .clone()
+        .map(autoscaling_json)
+        .transpose()?;
+
+    let resources = request.resources.clone().map(resources_json).transpose()?;
+
+    let bounds = request.bounds.clone().map(bounds_json).transpose()?;
+
+    let schedule = request.schedule.clone().map(schedule_json).transpose()?;
+
+    let restore_savepoint_id = match &request.savepoint_id {
+        Some(savepoint_id) => {
+            Some(savepoints::get_restorable_savepoint(savepoint_id, &auth, client).await?)
+        }
+        None => None,
+    };
+
     let running_jobs = get_jobs(&auth, client)
         .await?
         .iter()
@@ -74,6 +255,16 @@ pub(crate) async fn create_job<'a>(
             } else {
                 None
             }),
+            &state_backend,
+            &request.max_in_memory_state_entries.map(|n| n as i64),
+            &restore_savepoint_id,
+            &checkpoint_mode,
+            &request.checkpoint_url,
+            &restart_policy,
+            &autoscaling,
+            &resources,
+            &bounds,
+            &schedule,
         )
         .await
         .map_err(log_and_map)?;
@@ -110,6 +301,9 @@ pub(crate) async fn get_jobs(
             definition: rec.textual_repr,
             definition_id: format!("{}", rec.pipeline_definition),
             failure_message: rec.failure_message,
+            restart_backoff_micros: rec.restart_backoff_micros.map(|b| b as u64),
+            next_restart_time: rec.next_restart_time.map(to_micros),
+            final_checkpoint_epoch: rec.final_checkpoint_epoch.map(|e| e as u32),
         })
         .collect())
 }
@@ -155,6 +349,9 @@ pub(crate) async fn get_job_details(
         ("Created", true) => ("Stop", Some(Checkpoint), InProgress),
         ("Created", false) => ("Start", Some(None), Stable),
 
+        ("Scheduled", true) => ("Stop", Some(Checkpoint), Stable),
+        ("Scheduled", false) => ("Stopping", Option::None, InProgress),
+
         ("Compiling", true) => ("Stop", Some(Checkpoint), InProgress),
         ("Compiling", false) => ("Stopping", Option::None, InProgress),
 
@@ -167,11 +364,19 @@ pub(crate) async fn get_job_details(
         ("Rescaling", true) => ("Stop", Some(Checkpoint), InProgress),
         ("Rescaling", false) => ("Stopping", Option::None, InProgress),
 
+        ("Savepointing", true) => ("Stop", Some(Checkpoint), InProgress),
+        ("Savepointing", false) => ("Stopping", Option::None, InProgress),
+
         ("CheckpointStopping", true) => ("Force Stop", Some(Immediate), InProgress),
         ("CheckpointStopping", false) => ("Force Stop", Some(Immediate), InProgress),
 
         ("Recovering", true) => ("Stop", Some(Checkpoint), InProgress),
         ("Recovering", false) => ("Stopping", Option::None, InProgress),
+        ("RecoveringRegion", true) => ("Stop", Some(Checkpoint), InProgress),
+        ("RecoveringRegion", false) => ("Stopping", Option::None, InProgress),
+
+        ("Adopting", true) => ("Stop", Some(Checkpoint), InProgress),
+        ("Adopting", false) => ("Stopping", Option::None, InProgress),
 
         ("Stopping", true) => ("Stopping", Some(Checkpoint), InProgress),
         ("Stopping", false) => ("Stopping", Option::None, InProgress),
@@ -203,6 +408,9 @@ pub(crate) async fn get_job_details(
         definition: res.textual_repr,
         definition_id: format!("{}", res.pipeline_definition),
         failure_message: res.failure_message,
+        restart_backoff_micros: res.restart_backoff_micros.map(|b| b as u64),
+        next_restart_time: res.next_restart_time.map(to_micros),
+        final_checkpoint_epoch: res.final_checkpoint_epoch.map(|e| e as u32),
     };
 
     Ok(JobDetailsResp {
@@ -258,6 +466,9 @@ pub(crate) async fn checkpoint_details(
             ))
         })?;
 
+    // so that the checkpoint's data is read from the job's storage
+    StorageClient::register_job(job_id, res.checkpoint_url.as_deref());
+
     Ok(CheckpointDetailsResp {
         overview: Some(CheckpointOverview {
             epoch,
@@ -1,3 +1,5 @@
+use arroyo_sql::types::StructField;
+use quote::{format_ident, quote};
 use schemars::schema::{RootSchema, Schema};
 use tracing::log::warn;
 use typify::{TypeDetails, TypeSpace, TypeSpaceSettings};
@@ -5,6 +7,7 @@ use typify::{TypeDetails, TypeSpace, TypeSpaceSettings};
 use crate::sources::{PrimitiveType, SchemaField, SchemaFieldType};
 
 pub const ROOT_NAME: &str = "ArroyoJsonRoot";
+pub const METADATA_ROOT_NAME: &str = "ArroyoJsonRootWithMetadata";
 
 fn get_type_space(schema: &str) -> Result<TypeSpace, String> {
     let mut root_schema: RootSchema =
@@ -68,6 +71,48 @@ pub fn get_defs(source_name: &str, schema: &str) -> Result<String, String> {
     ))
 }
 
+/// Generates the schema types along with a struct that wraps the root type and adds the given
+/// metadata fields, so that the typify-generated type (and its serde attributes) is preserved.
+pub fn get_defs_with_metadata(
+    source_name: &str,
+    schema: &str,
+    metadata_fields: &[StructField],
+) -> Result<String, String> {
+    let type_space = get_type_space(schema)?;
+
+    let root = format_ident!("{}", ROOT_NAME);
+    let wrapper = format_ident!("{}", METADATA_ROOT_NAME);
+    let fields = metadata_fields.iter().map(|f| {
+        let name = f.field_ident();
+        let typ = f.get_type();
+        quote!(pub #name: #typ)
+    });
+
+    let wrapper_def = quote! {
+        #[derive(Clone, Debug, bincode::Encode, bincode::Decode, PartialEq, PartialOrd, serde::Serialize, serde::Deserialize)]
+        pub struct #wrapper {
+            #[serde(flatten)]
+            pub json_root: #root,
+            #(#fields),*
+        }
+
+        impl std::ops::Deref for #wrapper {
+            type Target = #root;
+
+            fn deref(&self) -> &Self::Target {
+                &self.json_root
+            }
+        }
+    };
+
+    Ok(format!(
+        "mod {} {{\nuse crate::*;\n{}\n{}\n}}",
+        source_name,
+        type_space.to_string(),
+        wrapper_def
+    ))
+}
+
 fn to_schema_type(
     type_space: &TypeSpace,
     source_name: &str,
@@ -137,7 +182,39 @@ fn to_schema_type(
 
 #[cfg(test)]
 mod test {
-    use super::convert_json_schema;
+    use super::{convert_json_schema, get_defs_with_metadata, METADATA_ROOT_NAME};
+    use crate::sources::{PrimitiveType, SchemaField, SchemaFieldType};
+
+    #[test]
+    fn test_defs_with_metadata() {
+        let schema = r#"
+            {
+                "type": "object",
+                "title": "Root Schema",
+                "required": ["user_id"],
+                "properties": {
+                    "user_id": { "type": "integer" }
+                }
+            }
+            "#;
+
+        let defs = get_defs_with_metadata(
+            "events",
+            schema,
+            &[
+                (&SchemaField::new("offset", SchemaFieldType::Primitive(PrimitiveType::Int64)))
+                    .into(),
+            ],
+        )
+        .unwrap();
+
+        assert!(defs.starts_with("mod events {"));
+        assert!(defs.contains("pub struct ArroyoJsonRoot"));
+        assert!(defs.contains(&format!("pub struct {}", METADATA_ROOT_NAME)));
+        assert!(defs.contains("pub json_root : ArroyoJsonRoot"));
+        assert!(defs.contains("pub offset : i64"));
+        syn::parse_file(&defs).expect("generated defs should be valid rust");
+    }
 
     #[test]
     fn test() {
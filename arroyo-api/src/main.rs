@@ -8,14 +8,16 @@ use arroyo_rpc::grpc::{
     self,
     api::{
         api_grpc_server::{ApiGrpc, ApiGrpcServer},
-        CheckpointDetailsReq, CheckpointDetailsResp, ConfluentSchemaReq, ConfluentSchemaResp,
-        CreateConnectionReq, CreateConnectionResp, CreateJobReq, CreateJobResp, CreatePipelineReq,
-        CreatePipelineResp, CreateSinkReq, CreateSinkResp, CreateSourceReq, CreateSourceResp,
-        GetConnectionsReq, GetConnectionsResp, GetJobsReq, GetJobsResp, GetPipelineReq,
-        GetSourcesReq, GetSourcesResp, GrpcOutputSubscription, JobCheckpointsReq,
-        JobCheckpointsResp, JobDetailsReq, JobDetailsResp, JobMetricsReq, JobMetricsResp,
-        OutputData, PipelineDef, PipelineGraphReq, PipelineGraphResp, StopType, TestSchemaResp,
-        TestSourceMessage, UpdateJobReq, UpdateJobResp,
+        CheckpointDetailsReq, CheckpointDetailsResp, CheckpointMode, CheckpointTablesReq,
+        CheckpointTablesResp, ConfluentSchemaReq, ConfluentSchemaResp, CreateConnectionReq,
+        CreateConnectionResp, CreateJobReq, CreateJobResp, CreatePipelineReq, CreatePipelineResp,
+        CreateSavepointReq, CreateSavepointResp, CreateSinkReq, CreateSinkResp, CreateSourceReq,
+        CreateSourceResp, ExportCheckpointStateReq, ExportCheckpointStateResp, GetConnectionsReq,
+        GetConnectionsResp, GetJobsReq, GetJobsResp, GetPipelineReq, GetSavepointsReq,
+        GetSavepointsResp, GetSourcesReq, GetSourcesResp, GrpcOutputSubscription,
+        JobCheckpointsReq, JobCheckpointsResp, JobDetailsReq, JobDetailsResp, JobMetricsReq,
+        JobMetricsResp, OutputData, PipelineDef, PipelineGraphReq, PipelineGraphResp,
+        StateBackendType, StopType, TestSchemaResp, TestSourceMessage, UpdateJobReq, UpdateJobResp,
     },
     controller_grpc_client::ControllerGrpcClient,
 };
@@ -52,6 +54,7 @@ use tracing::{error, info, warn};
 use crate::jobs::get_job_details;
 use queries::api_queries;
 
+mod checkpoint_state;
 mod cloud;
 mod connections;
 mod jobs;
@@ -59,6 +62,7 @@ mod json_schema;
 mod metrics;
 mod optimizations;
 mod pipelines;
+mod savepoints;
 mod sinks;
 mod sources;
 mod testers;
@@ -328,6 +332,16 @@ impl ApiServer {
             pipeline_id: format!("{}", pipeline_id),
             checkpoint_interval_micros: DEFAULT_CHECKPOINT_INTERVAL.as_micros() as u64,
             preview,
+            state_backend: StateBackendType::Memory as i32,
+            max_in_memory_state_entries: None,
+            savepoint_id: None,
+            checkpoint_mode: CheckpointMode::Aligned as i32,
+            checkpoint_url: None,
+            restart_policy: None,
+            autoscaling: None,
+            resources: None,
+            bounds: None,
+            schedule: None,
         };
 
         let job_id = jobs::create_job(create_job, auth, &transaction).await?;
@@ -663,6 +677,52 @@ impl ApiGrpc for ApiServer {
             .map(Response::new)
     }
 
+    async fn get_checkpoint_tables(
+        &self,
+        request: Request<CheckpointTablesReq>,
+    ) -> Result<Response<CheckpointTablesResp>, Status> {
+        let (request, auth) = self.authenticate(request).await?;
+        let req = request.into_inner();
+
+        checkpoint_state::get_checkpoint_tables(&req.job_id, req.epoch, auth, &self.client().await?)
+            .await
+            .map(Response::new)
+    }
+
+    async fn export_checkpoint_state(
+        &self,
+        request: Request<ExportCheckpointStateReq>,
+    ) -> Result<Response<ExportCheckpointStateResp>, Status> {
+        let (request, auth) = self.authenticate(request).await?;
+
+        checkpoint_state::export_checkpoint_state(request.into_inner(), auth, &self.client().await?)
+            .await
+            .map(Response::new)
+    }
+
+    async fn create_savepoint(
+        &self,
+        request: Request<CreateSavepointReq>,
+    ) -> Result<Response<CreateSavepointResp>, Status> {
+        let (request, auth) = self.authenticate(request).await?;
+
+        let savepoint_id =
+            savepoints::create_savepoint(request.into_inner(), auth, &self.client().await?).await?;
+
+        Ok(Response::new(CreateSavepointResp { savepoint_id }))
+    }
+
+    async fn get_savepoints(
+        &self,
+        request: Request<GetSavepointsReq>,
+    ) -> Result<Response<GetSavepointsResp>, Status> {
+        let (request, auth) = self.authenticate(request).await?;
+
+        savepoints::get_savepoints(request.into_inner().job_id, auth, &self.client().await?)
+            .await
+            .map(|savepoints| Response::new(GetSavepointsResp { savepoints }))
+    }
+
     async fn get_job_metrics(
         &self,
         request: Request<JobMetricsReq>,
@@ -719,6 +779,19 @@ impl ApiGrpc for ApiServer {
             None
         };
 
+        let restart_policy = req
+            .restart_policy
+            .map(jobs::restart_policy_json)
+            .transpose()?;
+
+        let autoscaling = req.autoscaling.map(jobs::autoscaling_json).transpose()?;
+
+        let resources = req.resources.map(jobs::resources_json).transpose()?;
+
+        let bounds = req.bounds.map(jobs::bounds_json).transpose()?;
+
+        let schedule = req.schedule.map(jobs::schedule_json).transpose()?;
+
         let res = api_queries::update_job()
             .bind(
                 &self.client().await?,
@@ -727,6 +800,11 @@ impl ApiGrpc for ApiServer {
                 &stop,
                 &interval.map(|i| i.as_micros() as i64),
                 &parallelism_overrides,
+                &restart_policy,
+                &autoscaling,
+                &resources,
+                &bounds,
+                &schedule,
                 &req.job_id,
                 &auth.organization_id,
             )
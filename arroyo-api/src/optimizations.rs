@@ -442,6 +442,9 @@ mod tests {
             operator: arroyo_datastream::Operator::FileSource {
                 dir: PathBuf::new(),
                 delay: Duration::ZERO,
+                format: arroyo_datastream::FileFormat::Json,
+                monitor_interval: None,
+                timestamp_field: None,
             },
             parallelism: 5,
         });
@@ -15,6 +15,7 @@ use prost::Message;
 use tracing::log::info;
 
 use std::str::FromStr;
+use std::time::Duration;
 use tonic::Status;
 use tracing::warn;
 
@@ -28,6 +29,9 @@ use crate::{
     AuthData,
 };
 
+const DEFAULT_MAX_FILE_SIZE_BYTES: u64 = 128 * 1024 * 1024;
+const DEFAULT_ROLLOVER_SECONDS: u64 = 5 * 60;
+
 async fn compile_sql<'e, E>(
     sql: &CreateSqlJob,
     auth_data: &AuthData,
@@ -36,6 +40,12 @@ async fn compile_sql<'e, E>(
 where
     E: GenericClient,
 {
+    if sql.state_ttl_micros == Some(0) {
+        return Err(Status::invalid_argument(
+            "state_ttl_micros must be greater than 0",
+        ));
+    }
+
     let mut schema_provider = ArroyoSchemaProvider::new();
 
     for source in sources::get_sources(auth_data, tx).await? {
@@ -75,6 +85,36 @@ where
                             .map(|t| t.to_string())
                             .collect(),
                         client_configs: auth_config_to_hashmap(kafka.auth_config),
+                        key_field: k.key_field,
+                        header_fields: k.header_fields,
+                    }
+                }
+                SinkType::Kinesis(k) => {
+                    let connection =
+                        connections::get_connection(auth_data, &k.connection, tx).await?;
+                    let connection::ConnectionType::Kinesis(kinesis) = connection.connection_type.unwrap() else {
+                        panic!("kinesis sink {} [{}] configured with non-kinesis connection", name, auth_data.organization_id);
+                    };
+                    Operator::KinesisSink {
+                        stream_name: k.stream_name,
+                        region: kinesis.region,
+                        endpoint: kinesis.endpoint,
+                        partition_key_field: k.partition_key_field,
+                    }
+                }
+                SinkType::FileSystem(f) => {
+                    let format = f.format().into();
+                    Operator::FileSystemSink {
+                        path: f.path,
+                        format,
+                        partition_template: f.partition_template.unwrap_or_default(),
+                        max_file_size: f
+                            .max_file_size_bytes
+                            .unwrap_or(DEFAULT_MAX_FILE_SIZE_BYTES)
+                            as usize,
+                        rollover: Duration::from_secs(
+                            f.rollover_seconds.unwrap_or(DEFAULT_ROLLOVER_SECONDS),
+                        ),
                     }
                 }
             }
@@ -87,6 +127,8 @@ where
         SqlConfig {
             default_parallelism: sql.parallelism as usize,
             sink,
+            interpreted: true,
+            state_ttl: sql.state_ttl_micros.map(Duration::from_micros),
         },
     )
     .await
@@ -146,6 +188,7 @@ async fn set_default_parallelism(_auth: &AuthData, program: &mut Program) -> Res
                 //     .partitions
                 //     .min(auth.org_metadata.max_parallelism as usize)
             }
+            Operator::KinesisSource { .. } => 1,
             Operator::NexmarkSource {
                 first_event_rate, ..
             } => (*first_event_rate as f32 / 50_000.0).round() as usize,
@@ -332,6 +375,7 @@ pub(crate) async fn sql_graph(
         query: req.query,
         parallelism: 1,
         sink: Some(Sink::Builtin(BuiltinSink::Null as i32)),
+        state_ttl_micros: None,
     };
 
     match compile_sql(&sql, &auth, client).await {
@@ -0,0 +1,107 @@
+use arroyo_rpc::grpc::api::{CreateSavepointReq, Savepoint, SavepointState};
+use cornucopia_async::GenericClient;
+use tonic::Status;
+
+use crate::jobs::{gen_id, get_job_details};
+use crate::types::public;
+use crate::{
+    handle_db_error, log_and_map, queries::api_queries, required_field, to_micros, AuthData,
+};
+
+pub(crate) async fn create_savepoint(
+    req: CreateSavepointReq,
+    auth: AuthData,
+    client: &impl GenericClient,
+) -> Result<String, Status> {
+    if req.name.is_empty() {
+        return Err(required_field("name"));
+    }
+
+    let state = get_job_details(&req.job_id, &auth, client)
+        .await?
+        .job_status
+        .map(|status| status.state)
+        .unwrap_or_default();
+
+    // stopped jobs are saved from their last checkpoint
+    if state != "Running" && state != "Stopped" {
+        return Err(Status::failed_precondition(format!(
+            "Job {} is {}; savepoints can only be taken of running or stopped jobs",
+            req.job_id, state
+        )));
+    }
+
+    let savepoint_id = gen_id();
+
+    api_queries::create_savepoint()
+        .bind(
+            client,
+            &savepoint_id,
+            &auth.organization_id,
+            &req.name,
+            &auth.user_id,
+            &req.job_id,
+        )
+        .await
+        .map_err(|e| handle_db_error("savepoint", e))?;
+
+    Ok(savepoint_id)
+}
+
+pub(crate) async fn get_savepoints(
+    job_id: Option<String>,
+    auth: AuthData,
+    client: &impl GenericClient,
+) -> Result<Vec<Savepoint>, Status> {
+    let res = api_queries::get_savepoints()
+        .bind(client, &auth.organization_id)
+        .all()
+        .await
+        .map_err(log_and_map)?;
+
+    Ok(res
+        .into_iter()
+        .filter(|rec| job_id.is_none() || rec.job_id == job_id)
+        .map(|rec| Savepoint {
+            savepoint_id: rec.pub_id,
+            name: rec.name,
+            job_id: rec.job_id,
+            pipeline_name: rec.pipeline_name,
+            state: match rec.state {
+                public::SavepointState::requested => SavepointState::Requested,
+                public::SavepointState::inprogress => SavepointState::InProgress,
+                public::SavepointState::ready => SavepointState::Ready,
+                public::SavepointState::failed => SavepointState::Failed,
+            } as i32,
+            epoch: rec.epoch.map(|e| e as u32),
+            created_at: to_micros(rec.created_at),
+            finish_time: rec.finish_time.map(to_micros),
+            failure_message: rec.failure_message,
+        })
+        .collect())
+}
+
+/// Returns the database id of a savepoint that a new job can be started from
+pub(crate) async fn get_restorable_savepoint(
+    savepoint_id: &str,
+    auth: &AuthData,
+    client: &impl GenericClient,
+) -> Result<i64, Status> {
+    let savepoint = api_queries::get_savepoint()
+        .bind(client, &savepoint_id, &auth.organization_id)
+        .opt()
+        .await
+        .map_err(log_and_map)?
+        .ok_or_else(|| {
+            Status::not_found(format!("There is no savepoint with id '{}'", savepoint_id))
+        })?;
+
+    if savepoint.state != public::SavepointState::ready {
+        return Err(Status::failed_precondition(format!(
+            "Savepoint '{}' is not ready to be restored from",
+            savepoint_id
+        )));
+    }
+
+    Ok(savepoint.id)
+}
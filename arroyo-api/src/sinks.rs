@@ -48,6 +48,41 @@ pub(crate) async fn create_sink(
                     Some(connection.id),
                 )
             }
+            create_sink_req::SinkType::Kinesis(kinesis) => {
+                let connection = connections
+                    .iter()
+                    .find(|c| c.name == kinesis.connection)
+                    .ok_or_else(|| {
+                        Status::failed_precondition(format!(
+                            "Could not find connection with name '{}'",
+                            kinesis.connection
+                        ))
+                    })?;
+
+                if connection.r#type != public::ConnectionType::kinesis {
+                    return Err(Status::invalid_argument(format!(
+                        "Connection '{}' is not a kinesis connection",
+                        kinesis.connection
+                    )));
+                }
+
+                (
+                    SinkType::kinesis,
+                    serde_json::to_value(&kinesis).unwrap(),
+                    Some(connection.id),
+                )
+            }
+            create_sink_req::SinkType::FileSystem(file_system) => {
+                if file_system.path.is_empty() {
+                    return Err(required_field("path"));
+                }
+
+                (
+                    SinkType::filesystem,
+                    serde_json::to_value(&file_system).unwrap(),
+                    None,
+                )
+            }
         };
 
     api_queries::create_sink()
@@ -85,6 +120,12 @@ pub(crate) async fn get_sinks<E: GenericClient>(
                 SinkType::kafka => {
                     sink::SinkType::Kafka(serde_json::from_value(sink.config).unwrap())
                 }
+                SinkType::kinesis => {
+                    sink::SinkType::Kinesis(serde_json::from_value(sink.config).unwrap())
+                }
+                SinkType::filesystem => {
+                    sink::SinkType::FileSystem(serde_json::from_value(sink.config).unwrap())
+                }
                 SinkType::state => todo!(),
             }),
             producers: sink.producers as i32,
@@ -8,7 +8,8 @@ use std::{
 
 use arrow::datatypes::TimeUnit;
 use arroyo_datastream::{
-    FileSource, ImpulseSpec, NexmarkSource, OffsetMode, Operator, Source as ApiSource,
+    FileFormat, FileSource, ImpulseSpec, KafkaMetadata, KafkaMetadataField, NexmarkSource,
+    OffsetMode, Operator, Source as ApiSource,
 };
 use arroyo_rpc::grpc::api::{
     self,
@@ -17,8 +18,8 @@ use arroyo_rpc::grpc::api::{
     source_def::SourceType,
     source_schema::{self, Schema},
     ConfluentSchemaReq, ConfluentSchemaResp, Connection, CreateSourceReq, DeleteSourceReq,
-    JsonSchemaDef, KafkaAuthConfig, KafkaSourceConfig, KafkaSourceDef, SourceDef, SourceField,
-    SourceMetadataResp, TestSourceMessage,
+    JsonSchemaDef, KafkaAuthConfig, KafkaSourceConfig, KafkaSourceDef, KinesisSourceConfig,
+    KinesisSourceDef, SourceDef, SourceField, SourceMetadataResp, TestSourceMessage,
 };
 use arroyo_sql::{
     types::{StructDef, StructField, TypeDef},
@@ -122,6 +123,7 @@ enum SourceConfig {
         bootstrap_servers: String,
         topic: String,
         client_configs: HashMap<String, String>,
+        metadata_fields: Vec<KafkaMetadataField>,
     },
     Impulse {
         interval: Option<Duration>,
@@ -131,11 +133,19 @@ enum SourceConfig {
     FileSource {
         directory: String,
         interval: Duration,
+        format: FileFormat,
+        monitor_interval: Option<Duration>,
+        timestamp_field: Option<String>,
     },
     NexmarkSource {
         event_rate: u64,
         runtime: Option<Duration>,
     },
+    Kinesis {
+        stream_name: String,
+        region: String,
+        endpoint: Option<String>,
+    },
 }
 
 pub fn auth_config_to_hashmap(config: Option<KafkaAuthConfig>) -> HashMap<String, String> {
@@ -152,15 +162,38 @@ pub fn auth_config_to_hashmap(config: Option<KafkaAuthConfig>) -> HashMap<String
     }
 }
 
+fn kafka_metadata_fields(
+    fields: Vec<api::KafkaMetadataField>,
+) -> Result<Vec<KafkaMetadataField>, String> {
+    fields
+        .into_iter()
+        .map(|f| f.try_into().map_err(|e| format!("{}", e)))
+        .collect()
+}
+
+/// The SQL column for a Kafka metadata field; these are added to the source's schema fields
+fn kafka_metadata_schema_field(field: &KafkaMetadataField) -> SchemaField {
+    use SchemaFieldType::Primitive;
+    let name = field.field_name.clone();
+    match field.metadata {
+        KafkaMetadata::Key => SchemaField::nullable(name, Primitive(PrimitiveType::String)),
+        KafkaMetadata::Partition => SchemaField::new(name, Primitive(PrimitiveType::Int32)),
+        KafkaMetadata::Offset => SchemaField::new(name, Primitive(PrimitiveType::Int64)),
+        KafkaMetadata::Timestamp => SchemaField::new(name, Primitive(PrimitiveType::UnixMillis)),
+        KafkaMetadata::Header(_) => SchemaField::nullable(name, Primitive(PrimitiveType::String)),
+    }
+}
+
 impl SourceConfig {
-    fn from_source_type(t: SourceType) -> SourceConfig {
-        match t {
+    fn from_source_type(t: SourceType) -> Result<SourceConfig, String> {
+        Ok(match t {
             SourceType::Kafka(kafka) => {
                 let Some(connection) = kafka.connection else {panic!("require a connection on a KafkaSourceDef")};
                 SourceConfig::Kafka {
                     bootstrap_servers: connection.bootstrap_servers,
                     topic: kafka.topic,
                     client_configs: auth_config_to_hashmap(connection.auth_config),
+                    metadata_fields: kafka_metadata_fields(kafka.metadata_fields)?,
                 }
             }
             SourceType::Impulse(impulse) => SourceConfig::Impulse {
@@ -171,14 +204,27 @@ impl SourceConfig {
                 total_events: impulse.total_messages.map(|t| t as usize),
             },
             SourceType::File(file) => SourceConfig::FileSource {
+                format: file.format().into(),
                 directory: file.directory,
                 interval: Duration::from_millis(file.interval_ms as u64),
+                monitor_interval: file.monitor_interval_ms.map(Duration::from_millis),
+                timestamp_field: file.timestamp_field,
             },
             SourceType::Nexmark(nexmark) => SourceConfig::NexmarkSource {
                 event_rate: nexmark.events_per_second.into(),
                 runtime: nexmark.runtime_micros.map(Duration::from_micros),
             },
-        }
+            SourceType::Kinesis(kinesis) => {
+                let connection = kinesis
+                    .connection
+                    .ok_or_else(|| "require a connection on a KinesisSourceDef".to_string())?;
+                SourceConfig::Kinesis {
+                    stream_name: kinesis.stream_name,
+                    region: connection.region,
+                    endpoint: connection.endpoint,
+                }
+            }
+        })
     }
 }
 
@@ -222,7 +268,7 @@ pub struct SchemaField {
 }
 
 impl SchemaField {
-    fn new(name: impl Into<String>, typ: SchemaFieldType) -> Self {
+    pub(crate) fn new(name: impl Into<String>, typ: SchemaFieldType) -> Self {
         Self {
             name: name.into(),
             typ,
@@ -454,7 +500,7 @@ impl TryFrom<SourceDef> for Source {
 
     fn try_from(value: SourceDef) -> Result<Self, Self::Error> {
         let schema = match value.source_type.as_ref().unwrap() {
-            SourceType::Kafka(_) | SourceType::File(_) => {
+            SourceType::Kafka(_) | SourceType::File(_) | SourceType::Kinesis(_) => {
                 SourceSchema::try_from(&value.name, value.schema.clone().unwrap())
                     .map_err(|e| format!("Invalid schema: {}", e))?
             }
@@ -466,14 +512,14 @@ impl TryFrom<SourceDef> for Source {
             id: value.id,
             name: value.name,
             schema,
-            config: SourceConfig::from_source_type(value.source_type.unwrap()),
+            config: SourceConfig::from_source_type(value.source_type.unwrap())?,
         })
     }
 }
 
 impl Source {
     pub(crate) fn register(&self, provider: &mut ArroyoSchemaProvider, auth: &AuthData) {
-        let name = match self.schema.format {
+        let mut name = match self.schema.format {
             SourceFormat::Native(_) => None,
             SourceFormat::JsonFields => None,
             SourceFormat::JsonSchema(_) => {
@@ -481,19 +527,52 @@ impl Source {
             }
         };
 
-        let defs = match &self.schema.format {
+        let mut defs = match &self.schema.format {
             SourceFormat::Native(_) => None,
             SourceFormat::JsonFields => None,
             SourceFormat::JsonSchema(s) => Some(json_schema::get_defs(&self.name, s).unwrap()),
         };
 
-        let fields = self.schema.fields.iter().map(|f| f.into()).collect();
+        let mut fields: Vec<StructField> = self.schema.fields.iter().map(|f| f.into()).collect();
         match &self.config {
             SourceConfig::Kafka {
                 bootstrap_servers,
                 topic,
                 client_configs,
+                metadata_fields,
             } => {
+                if !metadata_fields.is_empty() {
+                    // metadata columns are not part of the message schema, so we need a struct
+                    // that contains both
+                    let metadata_fields: Vec<StructField> = metadata_fields
+                        .iter()
+                        .map(|f| (&kafka_metadata_schema_field(f)).into())
+                        .collect();
+
+                    match &self.schema.format {
+                        SourceFormat::JsonSchema(s) => {
+                            name = Some(format!(
+                                "{}::{}",
+                                self.name,
+                                json_schema::METADATA_ROOT_NAME
+                            ));
+                            defs = Some(
+                                json_schema::get_defs_with_metadata(
+                                    &self.name,
+                                    s,
+                                    &metadata_fields,
+                                )
+                                .unwrap(),
+                            );
+                        }
+                        _ => {
+                            name = None;
+                        }
+                    }
+
+                    fields.extend(metadata_fields);
+                }
+
                 let node = Operator::KafkaSource {
                     topic: topic.to_string(),
                     bootstrap_servers: bootstrap_servers
@@ -505,6 +584,26 @@ impl Source {
                     schema_registry: self.schema.kafka_schema,
                     messages_per_second: auth.org_metadata.kafka_qps,
                     client_configs: client_configs.clone(),
+                    metadata_fields: metadata_fields.clone(),
+                };
+
+                provider.add_source_with_type(self.id, &self.name, fields, node, name);
+
+                if let Some(defs) = defs {
+                    provider.add_defs(&self.name, defs);
+                }
+            }
+            SourceConfig::Kinesis {
+                stream_name,
+                region,
+                endpoint,
+            } => {
+                let node = Operator::KinesisSource {
+                    stream_name: stream_name.clone(),
+                    region: region.clone(),
+                    endpoint: endpoint.clone(),
+                    // TODO: allow this to be configured via SQL
+                    offset_mode: OffsetMode::Latest,
                 };
 
                 provider.add_source_with_type(self.id, &self.name, fields, node, name);
@@ -516,8 +615,17 @@ impl Source {
             SourceConfig::FileSource {
                 directory,
                 interval,
+                format,
+                monitor_interval,
+                timestamp_field,
             } => {
-                let node = FileSource::from_dir(PathBuf::from_str(directory).unwrap(), *interval);
+                let node = FileSource::new(
+                    PathBuf::from_str(directory).unwrap(),
+                    *interval,
+                    *format,
+                    *monitor_interval,
+                    timestamp_field.clone(),
+                );
 
                 provider.add_source_with_type(
                     self.id,
@@ -619,6 +727,16 @@ pub(crate) async fn create_source(
         })
         .ok_or_else(|| required_field("schema"))?;
 
+    // used to check that kafka metadata fields don't shadow fields of the message
+    let schema_fields: Vec<String> = match &schema.schema {
+        Some(Schema::JsonSchema(_)) | Some(Schema::JsonFields(_)) => {
+            SourceSchema::try_from(&req.name, schema.clone())
+                .map(|s| s.fields.into_iter().map(|f| f.name).collect())
+                .unwrap_or_default()
+        }
+        _ => vec![],
+    };
+
     let (schema_type, config) = match schema
         .schema
         .ok_or_else(|| required_field("schema.schema"))?
@@ -676,12 +794,48 @@ pub(crate) async fn create_source(
                     )));
                 }
 
+                let metadata_fields = kafka_metadata_fields(kafka.metadata_fields.clone())
+                    .map_err(Status::invalid_argument)?;
+                if let Some(f) = metadata_fields
+                    .iter()
+                    .find(|f| schema_fields.contains(&f.field_name))
+                {
+                    return Err(Status::invalid_argument(format!(
+                        "Metadata field '{}' conflicts with a field in the schema",
+                        f.field_name
+                    )));
+                }
+
                 (
                     public::SourceType::kafka,
                     serde_json::to_value(&kafka).unwrap(),
                     Some(connection.id),
                 )
             }
+            create_source_req::TypeOneof::Kinesis(kinesis) => {
+                let connection = connections
+                    .iter()
+                    .find(|c| c.name == kinesis.connection)
+                    .ok_or_else(|| {
+                        Status::failed_precondition(format!(
+                            "Could not find connection with name '{}'",
+                            kinesis.connection
+                        ))
+                    })?;
+
+                if connection.r#type != public::ConnectionType::kinesis {
+                    return Err(Status::invalid_argument(format!(
+                        "Connection '{}' is not a kinesis connection",
+                        kinesis.connection
+                    )));
+                }
+
+                (
+                    public::SourceType::kinesis,
+                    serde_json::to_value(&kinesis).unwrap(),
+                    Some(connection.id),
+                )
+            }
             create_source_req::TypeOneof::Impulse(impulse) => {
                 if impulse.events_per_second > auth.org_metadata.max_impulse_qps as f32 {
                     return rate_limit_error("impulse", auth.org_metadata.max_impulse_qps as usize);
@@ -758,6 +912,7 @@ pub(crate) async fn get_sources<E: GenericClient>(
             };
 
             let ss = SourceSchema::try_from(&rec.source_name, source_schema.clone()).unwrap();
+            let mut sql_fields = ss.fields();
 
             let source_type = match rec.source_type {
                 public::SourceType::nexmark => {
@@ -773,6 +928,12 @@ pub(crate) async fn get_sources<E: GenericClient>(
                     let config: KafkaSourceConfig =
                         serde_json::from_value(rec.source_config.unwrap()).unwrap();
                     assert_eq!(rec.connection_type.unwrap(), public::ConnectionType::kafka);
+                    sql_fields.extend(
+                        kafka_metadata_fields(config.metadata_fields.clone())
+                            .unwrap()
+                            .iter()
+                            .map(kafka_metadata_schema_field),
+                    );
                     SourceType::Kafka(KafkaSourceDef {
                         connection_name: rec.connection_name.as_ref().unwrap().clone(),
                         connection: serde_json::from_value(
@@ -780,6 +941,23 @@ pub(crate) async fn get_sources<E: GenericClient>(
                         )
                         .unwrap(),
                         topic: config.topic,
+                        metadata_fields: config.metadata_fields,
+                    })
+                }
+                public::SourceType::kinesis => {
+                    let config: KinesisSourceConfig =
+                        serde_json::from_value(rec.source_config.unwrap()).unwrap();
+                    assert_eq!(
+                        rec.connection_type.unwrap(),
+                        public::ConnectionType::kinesis
+                    );
+                    SourceType::Kinesis(KinesisSourceDef {
+                        connection_name: rec.connection_name.as_ref().unwrap().clone(),
+                        connection: serde_json::from_value(
+                            rec.connection_config.as_ref().unwrap().clone(),
+                        )
+                        .unwrap(),
+                        stream_name: config.stream_name,
                     })
                 }
             };
@@ -790,8 +968,7 @@ pub(crate) async fn get_sources<E: GenericClient>(
                 schema: Some(source_schema),
                 connection: rec.connection_name,
                 consumers: rec.consumer_count as i32,
-                sql_fields: ss
-                    .fields()
+                sql_fields: sql_fields
                     .into_iter()
                     .map(|f| f.try_into().unwrap())
                     .collect(),
@@ -872,6 +1049,7 @@ pub(crate) async fn get_source_metadata(
         create_source_req::TypeOneof::Impulse(_) => 1,
         create_source_req::TypeOneof::File(_) => 1,
         create_source_req::TypeOneof::Nexmark(_) => 1,
+        create_source_req::TypeOneof::Kinesis(_) => 1,
     };
 
     Ok(SourceMetadataResp {
@@ -11,21 +11,28 @@ use std::{
 
 use arroyo_rpc::grpc::{
     compiler_grpc_server::{CompilerGrpc, CompilerGrpcServer},
-    CompileQueryReq, CompileQueryResp,
+    CancelCompileReq, CancelCompileResp, CompileQueryReq, CompileQueryResp, CompileState,
+    CompileStatus, CompileStatusReq, StartCompileResp,
 };
 
 use arroyo_server_common::start_admin_server;
-use arroyo_types::{grpc_port, ports, S3_BUCKET_ENV, S3_REGION_ENV};
+use arroyo_types::{grpc_port, ports, worker_version, S3_BUCKET_ENV, S3_REGION_ENV};
 use object_store::aws::AmazonS3Builder;
 use object_store::local::LocalFileSystem;
 use object_store::ObjectStore;
 use prost::Message;
-use tokio::sync::broadcast;
-use tokio::{process::Command, sync::Mutex};
+use queue::{is_finished, CompileQueue, CompileRequest};
+use tokio::process::Command;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{transport::Server, Request, Response, Status};
 use tracing::error;
 use tracing::info;
 
+mod queue;
+
+const DEFAULT_COMPILE_WORKERS: usize = 2;
+
 pub fn to_millis(time: SystemTime) -> u64 {
     time.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
 }
@@ -72,10 +79,10 @@ pub async fn main() {
 
     let service = CompileService {
         build_dir: PathBuf::from_str(&build_dir).unwrap(),
-        lock: Arc::new(Mutex::new(())),
         last_used: last_used.clone(),
         object_store,
         base_path,
+        queue: Arc::new(CompileQueue::new()),
     };
 
     let args = std::env::args().collect::<Vec<_>>();
@@ -102,7 +109,7 @@ pub async fn main() {
 
             let query = CompileQueryReq::decode(&*query).expect("Failed to decode query request");
 
-            let resp = service.compile(query).await.unwrap();
+            let resp = service.compile(&service.build_dir, &query).await.unwrap();
             println!(
                 "{{\"pipeline_path\": \"{}\", \"wasm_fns_path\": \"{}\"}}",
                 resp.pipeline_path, resp.wasm_fns_path
@@ -127,6 +134,16 @@ pub async fn start_service(service: CompileService) {
 
     let last_used = service.last_used.clone();
 
+    let workers = std::env::var("COMPILE_WORKERS")
+        .map(|n| usize::from_str(&n).expect("COMPILE_WORKERS must be a number"))
+        .unwrap_or(DEFAULT_COMPILE_WORKERS)
+        .max(1);
+
+    for build_dir in prepare_build_dirs(&service.build_dir, workers).await {
+        tokio::spawn(run_worker(service.clone(), build_dir));
+    }
+    info!("Started {} compile workers", workers);
+
     if let Some(idle_time) = std::env::var("IDLE_SHUTDOWN_MS")
         .map(|t| Duration::from_millis(u64::from_str(&t).unwrap()))
         .ok()
@@ -156,24 +173,137 @@ pub async fn start_service(service: CompileService) {
     shutdown_tx.send(0).unwrap();
 }
 
+// Each worker builds in its own copy of the base build directory, which starts out with the base
+// pipeline's warm target directory so that only the generated code needs to be compiled
+async fn prepare_build_dirs(base: &Path, workers: usize) -> Vec<PathBuf> {
+    let mut dirs = vec![base.to_path_buf()];
+    for i in 1..workers {
+        let dir = base.with_file_name(format!(
+            "{}-{}",
+            base.file_name().unwrap().to_string_lossy(),
+            i
+        ));
+        if !dir.exists() {
+            // -a preserves the mtimes that cargo's fingerprints depend on
+            let result = Command::new("cp")
+                .arg("-a")
+                .arg("--reflink=auto")
+                .arg(base)
+                .arg(&dir)
+                .output()
+                .await
+                .unwrap_or_else(|e| panic!("Failed to copy build dir to {:?}: {:?}", dir, e));
+            if !result.status.success() {
+                panic!(
+                    "Failed to copy build dir to {:?}: {}",
+                    dir,
+                    String::from_utf8_lossy(&result.stderr)
+                );
+            }
+        }
+        dirs.push(dir);
+    }
+    dirs
+}
+
+async fn run_worker(service: CompileService, build_dir: PathBuf) {
+    loop {
+        let request = service.queue.next().await;
+        info!(
+            "Running compile {} for {} in {:?}",
+            request.id, request.req.job_id, build_dir
+        );
+
+        // dropping the compile kills the build processes
+        tokio::select! {
+            result = service.compile(&build_dir, &request.req) => match result {
+                Ok(resp) => request.finish(CompileState::CompileSucceeded, Some(resp), None),
+                Err(e) => {
+                    error!("Failed to compile: {:?}", e);
+                    let state = match e.kind() {
+                        ErrorKind::InvalidData => CompileState::CompileFailed,
+                        _ => CompileState::CompileError,
+                    };
+                    request.finish(state, None, Some(e.to_string()));
+                }
+            },
+            _ = request.cancelled() => {
+                info!("Cancelled compile {} for {}", request.id, request.req.job_id);
+            }
+        }
+
+        service
+            .last_used
+            .store(to_millis(SystemTime::now()), Ordering::Relaxed);
+    }
+}
+
 async fn rustfmt(file: &Path) -> io::Result<()> {
-    Command::new("rustfmt").arg(file).output().await?;
+    Command::new("rustfmt")
+        .arg(file)
+        .kill_on_drop(true)
+        .output()
+        .await?;
     Ok(())
 }
 
+#[derive(Clone)]
 pub struct CompileService {
     build_dir: PathBuf,
-    lock: Arc<Mutex<()>>,
     last_used: Arc<AtomicU64>,
     object_store: Arc<Box<dyn ObjectStore>>,
     base_path: String,
+    queue: Arc<CompileQueue>,
 }
 
 impl CompileService {
-    async fn compile(&self, req: CompileQueryReq) -> io::Result<CompileQueryResp> {
+    // artifacts are shared by all jobs running the same program against the same worker code
+    fn cached_artifacts_path(req: &CompileQueryReq) -> Option<object_store::path::Path> {
+        if req.program_hash.is_empty() {
+            return None;
+        }
+        let version = worker_version()?;
+        Some(
+            format!("artifacts/programs/{}-{}", req.program_hash, version)
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    fn artifacts_resp(&self, base: &object_store::path::Path) -> CompileQueryResp {
+        let full_path = format!("{}/{}", self.base_path, base);
+
+        CompileQueryResp {
+            pipeline_path: format!("{}/pipeline", full_path),
+            wasm_fns_path: format!("{}/wasm_fns_bg.wasm", full_path),
+        }
+    }
+
+    async fn compile(
+        &self,
+        build_dir: &Path,
+        req: &CompileQueryReq,
+    ) -> io::Result<CompileQueryResp> {
+        let cached_path = Self::cached_artifacts_path(req);
+
+        if let Some(base) = &cached_path {
+            // the wasm is uploaded last, so its presence means the artifacts are complete
+            match self
+                .object_store
+                .head(&base.child("wasm_fns_bg.wasm"))
+                .await
+            {
+                Ok(_) => {
+                    info!("Using cached artifacts at {} for {}", base, req.job_id);
+                    return Ok(self.artifacts_resp(base));
+                }
+                Err(object_store::Error::NotFound { .. }) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
         info!("Starting compilation for {}", req.job_id);
         let start = Instant::now();
-        let build_dir = &self.build_dir;
         tokio::fs::write(build_dir.join("pipeline/src/main.rs"), &req.pipeline).await?;
         rustfmt(&build_dir.join("pipeline/src/main.rs")).await?;
 
@@ -185,6 +315,7 @@ impl CompileService {
             .current_dir(&build_dir)
             .arg("build")
             .arg("--release")
+            .kill_on_drop(true)
             .output()
             .await?;
 
@@ -202,6 +333,7 @@ impl CompileService {
             let result = Command::new("wasm-pack")
                 .arg("build")
                 .current_dir(&build_dir.join("wasm-fns"))
+                .kill_on_drop(true)
                 .output()
                 .await
                 .unwrap();
@@ -222,12 +354,12 @@ impl CompileService {
             start.elapsed().as_secs_f32()
         );
 
-        // TODO: replace this with the SHA of the worker code once that's available
-        let id = (to_millis(SystemTime::now()) / 1000).to_string();
-
-        let base: object_store::path::Path = format!("artifacts/{}/{}", &req.job_id, id)
-            .try_into()
-            .unwrap();
+        let base = cached_path.unwrap_or_else(|| {
+            let id = (to_millis(SystemTime::now()) / 1000).to_string();
+            format!("artifacts/{}/{}", &req.job_id, id)
+                .try_into()
+                .unwrap()
+        });
 
         {
             let pipeline = tokio::fs::read(&build_dir.join("target/release/pipeline")).await?;
@@ -244,12 +376,15 @@ impl CompileService {
                 .await?;
         }
 
-        let full_path = format!("{}/{}", self.base_path, base);
+        Ok(self.artifacts_resp(&base))
+    }
+}
 
-        Ok(CompileQueryResp {
-            pipeline_path: format!("{}/pipeline", full_path),
-            wasm_fns_path: format!("{}/wasm_fns_bg.wasm", full_path),
-        })
+impl CompileService {
+    fn get_request(&self, compile_id: &str) -> Result<Arc<CompileRequest>, Status> {
+        self.queue
+            .get(compile_id)
+            .ok_or_else(|| Status::not_found(format!("No compile with id '{}'", compile_id)))
     }
 }
 
@@ -262,20 +397,93 @@ impl CompilerGrpc for CompileService {
         self.last_used
             .store(to_millis(SystemTime::now()), Ordering::Relaxed);
 
-        // only allow one request to be active at a given time
-        let _guard = self.lock.lock().await;
+        let request = self.queue.submit(request.into_inner());
 
-        let req = request.into_inner();
+        let mut rx = request.subscribe();
+        loop {
+            let status = rx.borrow_and_update().clone();
+            match status.state() {
+                CompileState::CompileQueued | CompileState::CompileRunning => {}
+                CompileState::CompileSucceeded => {
+                    return Ok(Response::new(status.result.unwrap()));
+                }
+                CompileState::CompileFailed => {
+                    return Err(Status::unimplemented(status.error.unwrap_or_default()));
+                }
+                CompileState::CompileError => {
+                    return Err(Status::internal(status.error.unwrap_or_default()));
+                }
+                CompileState::CompileCancelled => {
+                    return Err(Status::cancelled(
+                        "Compile was cancelled or superseded by a later one",
+                    ));
+                }
+            }
+            // the request is retained by the queue, so the sender can't be dropped while it runs
+            let _ = rx.changed().await;
+        }
+    }
 
-        self.compile(req)
-            .await
-            .map(|r| Response::new(r))
-            .map_err(|e| {
-                error!("Failed to compile: {:?}", e);
-                match e.kind() {
-                    ErrorKind::InvalidData => Status::unimplemented(e.to_string()),
-                    _ => Status::internal(e.to_string()),
+    async fn start_compile(
+        &self,
+        request: Request<CompileQueryReq>,
+    ) -> Result<Response<StartCompileResp>, Status> {
+        self.last_used
+            .store(to_millis(SystemTime::now()), Ordering::Relaxed);
+
+        let request = self.queue.submit(request.into_inner());
+        info!("Queued compile {} for {}", request.id, request.req.job_id);
+
+        Ok(Response::new(StartCompileResp {
+            compile_id: request.id.clone(),
+        }))
+    }
+
+    async fn get_compile_status(
+        &self,
+        request: Request<CompileStatusReq>,
+    ) -> Result<Response<CompileStatus>, Status> {
+        let request = self.get_request(&request.into_inner().compile_id)?;
+        Ok(Response::new(request.status()))
+    }
+
+    type SubscribeCompileStatusStream = ReceiverStream<Result<CompileStatus, Status>>;
+
+    async fn subscribe_compile_status(
+        &self,
+        request: Request<CompileStatusReq>,
+    ) -> Result<Response<Self::SubscribeCompileStatusStream>, Status> {
+        let request = self.get_request(&request.into_inner().compile_id)?;
+
+        let (tx, rx) = mpsc::channel(8);
+        tokio::spawn(async move {
+            let mut status_rx = request.subscribe();
+            loop {
+                let status = status_rx.borrow_and_update().clone();
+                let finished = is_finished(status.state());
+                if tx.send(Ok(status)).await.is_err() || finished {
+                    break;
+                }
+                if status_rx.changed().await.is_err() {
+                    break;
                 }
-            })
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn cancel_compile(
+        &self,
+        request: Request<CancelCompileReq>,
+    ) -> Result<Response<CancelCompileResp>, Status> {
+        let compile_id = request.into_inner().compile_id;
+        self.get_request(&compile_id)?;
+
+        let cancelled = self.queue.cancel(&compile_id);
+        if cancelled {
+            info!("Cancelled compile {}", compile_id);
+        }
+        Ok(Response::new(CancelCompileResp { cancelled }))
     }
 }
@@ -0,0 +1,250 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use arroyo_rpc::grpc::{CompileQueryReq, CompileQueryResp, CompileState, CompileStatus};
+use tokio::sync::{watch, Notify};
+
+use crate::to_millis;
+
+// how long the status of a finished compile can still be queried
+const STATUS_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+pub struct CompileRequest {
+    pub id: String,
+    pub req: CompileQueryReq,
+    status: watch::Sender<CompileStatus>,
+    finished_at: Mutex<Option<Instant>>,
+}
+
+impl CompileRequest {
+    pub fn status(&self) -> CompileStatus {
+        self.status.borrow().clone()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<CompileStatus> {
+        self.status.subscribe()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        is_finished(self.status.borrow().state())
+    }
+
+    // resolves once the compile has been cancelled
+    pub async fn cancelled(&self) {
+        let mut rx = self.status.subscribe();
+        while rx.borrow_and_update().state() != CompileState::CompileCancelled {
+            // the sender lives as long as self
+            let _ = rx.changed().await;
+        }
+    }
+
+    /// Records the outcome of the compile, unless it has already been cancelled
+    pub fn finish(
+        &self,
+        state: CompileState,
+        result: Option<CompileQueryResp>,
+        error: Option<String>,
+    ) {
+        let updated = self.status.send_if_modified(|status| {
+            if is_finished(status.state()) {
+                return false;
+            }
+            status.set_state(state);
+            status.result = result;
+            status.error = error;
+            true
+        });
+        if updated {
+            *self.finished_at.lock().unwrap() = Some(Instant::now());
+        }
+    }
+
+    fn cancel(&self) -> bool {
+        let cancelled = self.status.send_if_modified(|status| {
+            if is_finished(status.state()) {
+                return false;
+            }
+            status.set_state(CompileState::CompileCancelled);
+            true
+        });
+        if cancelled {
+            *self.finished_at.lock().unwrap() = Some(Instant::now());
+        }
+        cancelled
+    }
+}
+
+pub fn is_finished(state: CompileState) -> bool {
+    !matches!(
+        state,
+        CompileState::CompileQueued | CompileState::CompileRunning
+    )
+}
+
+#[derive(Default)]
+struct QueueState {
+    pending: VecDeque<Arc<CompileRequest>>,
+    requests: HashMap<String, Arc<CompileRequest>>,
+}
+
+impl QueueState {
+    fn update_positions(&mut self) {
+        for (i, request) in self.pending.iter().enumerate() {
+            request.status.send_if_modified(|status| {
+                let changed = status.queue_position != i as u32;
+                status.queue_position = i as u32;
+                changed
+            });
+        }
+    }
+}
+
+/// Compiles waiting for a build directory, in the order they were submitted
+pub struct CompileQueue {
+    state: Mutex<QueueState>,
+    notify: Notify,
+    next_id: AtomicU64,
+    id_prefix: String,
+}
+
+impl CompileQueue {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(QueueState::default()),
+            notify: Notify::new(),
+            next_id: AtomicU64::new(0),
+            id_prefix: format!("{:x}", to_millis(std::time::SystemTime::now())),
+        }
+    }
+
+    /// Queues the request, cancelling any earlier compiles for the same job that it supersedes
+    pub fn submit(&self, req: CompileQueryReq) -> Arc<CompileRequest> {
+        let id = format!(
+            "{}-{}",
+            self.id_prefix,
+            self.next_id.fetch_add(1, Ordering::Relaxed)
+        );
+
+        let request = Arc::new(CompileRequest {
+            id: id.clone(),
+            status: watch::channel(CompileStatus {
+                compile_id: id.clone(),
+                job_id: req.job_id.clone(),
+                state: CompileState::CompileQueued as i32,
+                queue_position: 0,
+                result: None,
+                error: None,
+            })
+            .0,
+            req,
+            finished_at: Mutex::new(None),
+        });
+
+        let mut state = self.state.lock().unwrap();
+
+        state.requests.retain(|_, r| {
+            r.finished_at
+                .lock()
+                .unwrap()
+                .map(|t| t.elapsed() < STATUS_RETENTION)
+                .unwrap_or(true)
+        });
+
+        for superseded in state
+            .requests
+            .values()
+            .filter(|r| r.req.job_id == request.req.job_id)
+        {
+            superseded.cancel();
+        }
+        state.pending.retain(|r| !r.is_finished());
+
+        state.requests.insert(id, request.clone());
+        state.pending.push_back(request.clone());
+        state.update_positions();
+        drop(state);
+
+        self.notify.notify_one();
+        request
+    }
+
+    /// Waits for the next queued compile, and marks it as running
+    pub async fn next(&self) -> Arc<CompileRequest> {
+        loop {
+            let notified = self.notify.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(request) = state.pending.pop_front() {
+                    request.status.send_modify(|status| {
+                        status.set_state(CompileState::CompileRunning);
+                        status.queue_position = 0;
+                    });
+                    state.update_positions();
+                    if !state.pending.is_empty() {
+                        // let another idle worker pick up the rest
+                        self.notify.notify_one();
+                    }
+                    return request;
+                }
+            }
+            notified.await;
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<CompileRequest>> {
+        self.state.lock().unwrap().requests.get(id).cloned()
+    }
+
+    /// Cancels the compile, returning false if it does not exist or has already finished
+    pub fn cancel(&self, id: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let Some(request) = state.requests.get(id).cloned() else {
+            return false;
+        };
+        let cancelled = request.cancel();
+        state.pending.retain(|r| r.id != request.id);
+        state.update_positions();
+        cancelled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CompileQueue;
+    use arroyo_rpc::grpc::{CompileQueryReq, CompileState};
+
+    fn req(job_id: &str) -> CompileQueryReq {
+        CompileQueryReq {
+            job_id: job_id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_superseded_compiles_are_cancelled() {
+        let queue = CompileQueue::new();
+
+        let first = queue.submit(req("job_a"));
+        let other = queue.submit(req("job_b"));
+        assert_eq!(other.status().queue_position, 1);
+
+        let second = queue.submit(req("job_a"));
+        assert_eq!(first.status().state(), CompileState::CompileCancelled);
+        assert_eq!(other.status().queue_position, 0);
+        assert_eq!(second.status().queue_position, 1);
+
+        let running = queue.next().await;
+        assert_eq!(running.id, other.id);
+        assert_eq!(other.status().state(), CompileState::CompileRunning);
+        assert_eq!(second.status().queue_position, 0);
+
+        assert!(queue.cancel(&other.id));
+        assert!(!queue.cancel(&other.id));
+        other.cancelled().await;
+
+        assert_eq!(queue.next().await.id, second.id);
+        assert!(queue.get(&first.id).is_some());
+    }
+}
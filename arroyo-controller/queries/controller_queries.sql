@@ -1,4 +1,4 @@
---! all_jobs : Job(ttl_micros?, state?, start_time?, finish_time?, tasks?, failure_message?, run_id?, pipeline_path?, wasm_path?)
+--! all_jobs : Job(controller_id?, ttl_micros?, max_in_memory_state_entries?, checkpoint_url?, restart_policy?, autoscaling?, resources?, bounds?, schedule?, requested_savepoint?, restore_savepoint?, state?, start_time?, finish_time?, tasks?, failure_message?, run_id?, pipeline_path?, wasm_path?, restart_backoff_micros?, next_restart_time?, final_checkpoint_epoch?)
 SELECT
     job_configs.id as id,
     job_configs.organization_id as org_id,
@@ -7,6 +7,20 @@ SELECT
     checkpoint_interval_micros,
     ttl_micros,
     parallelism_overrides,
+    state_backend,
+    max_in_memory_state_entries,
+    checkpoint_mode,
+    checkpoint_url,
+    restart_policy,
+    autoscaling,
+    resources,
+    bounds,
+    schedule,
+    (SELECT pub_id FROM savepoints
+        WHERE savepoints.job_id = job_configs.id AND savepoints.state = 'requested'
+        ORDER BY savepoints.id LIMIT 1) as requested_savepoint,
+    (SELECT pub_id FROM savepoints
+        WHERE savepoints.id = job_configs.restore_savepoint_id) as restore_savepoint,
     stop,
     state,
     start_time,
@@ -16,11 +30,20 @@ SELECT
     restarts,
     run_id,
     pipeline_path,
-    wasm_path
+    wasm_path,
+    restart_backoff_micros,
+    next_restart_time,
+    final_checkpoint_epoch,
+    controller_id
 FROM job_configs
 LEFT JOIN job_statuses ON job_configs.id = job_statuses.id;
 
---! update_job_status (start_time?, finish_time?, tasks?, failure_message?, pipeline_path?, wasm_path?)
+--! update_parallelism_overrides
+UPDATE job_configs
+SET parallelism_overrides = :parallelism_overrides
+WHERE id = :job_id;
+
+--! update_job_status (start_time?, finish_time?, tasks?, failure_message?, pipeline_path?, wasm_path?, restart_backoff_micros?, next_restart_time?, final_checkpoint_epoch?)
 UPDATE job_statuses
 SET state = :state,
     start_time = :start_time,
@@ -30,8 +53,11 @@ SET state = :state,
     restarts = :restarts,
     pipeline_path = :pipeline_path,
     wasm_path = :wasm_path,
-    run_id = :run_id
-WHERE id = :job_id;
+    run_id = :run_id,
+    restart_backoff_micros = :restart_backoff_micros,
+    next_restart_time = :next_restart_time,
+    final_checkpoint_epoch = :final_checkpoint_epoch
+WHERE id = :job_id AND controller_id = :controller_id;
 
 --! get_program
 SELECT program FROM pipeline_definitions WHERE id = :id;
@@ -73,3 +99,68 @@ FROM checkpoints
 WHERE job_id = :job_id AND state = 'ready'
 ORDER BY epoch DESC
 LIMIT 1;
+
+--! create_restored_checkpoint
+INSERT INTO checkpoints
+(organization_id, job_id, state_backend, epoch, min_epoch, start_time, finish_time, state)
+VALUES (:organization_id, :job_id, :state_backend, :epoch, :min_epoch, :start_time, :finish_time, 'ready');
+
+--! start_savepoint
+UPDATE savepoints
+SET state = 'inprogress'
+WHERE pub_id = :pub_id AND state = 'requested';
+
+--! finish_savepoint (epoch?, failure_message?)
+UPDATE savepoints
+SET
+    state = :state,
+    epoch = :epoch,
+    finish_time = :finish_time,
+    failure_message = :failure_message
+WHERE pub_id = :pub_id;
+
+--! try_acquire_leadership
+SELECT pg_try_advisory_lock(:lock_id);
+
+--! holds_leadership
+SELECT EXISTS(
+    SELECT 1 FROM pg_locks
+    WHERE locktype = 'advisory' AND granted AND pid = pg_backend_pid() AND objsubid = 1
+        AND ((classid::bigint << 32) | objid::bigint) = :lock_id
+);
+
+--! next_controller_id
+SELECT nextval('controller_ids');
+
+--! claim_jobs
+UPDATE job_statuses
+SET controller_id = :controller_id
+WHERE (controller_id IS NULL OR controller_id < :controller_id)
+    AND :controller_id = (SELECT last_value FROM controller_ids);
+
+--! get_job_controller : (controller_id?)
+SELECT controller_id FROM job_statuses WHERE id = :job_id;
+
+--! delete_job_workers
+DELETE FROM job_workers
+WHERE job_id = :job_id;
+
+--! delete_job_worker
+DELETE FROM job_workers
+WHERE job_id = :job_id AND worker_id = :worker_id;
+
+--! add_job_worker
+INSERT INTO job_workers
+(job_id, run_id, worker_id, rpc_address, data_address, slots, tasks)
+VALUES (:job_id, :run_id, :worker_id, :rpc_address, :data_address, :slots, :tasks);
+
+--! get_job_workers
+SELECT worker_id, rpc_address, data_address, slots, tasks
+FROM job_workers
+WHERE job_id = :job_id AND run_id = :run_id
+ORDER BY worker_id;
+
+--! last_checkpoint_epoch : (epoch?)
+SELECT MAX(epoch) as epoch
+FROM checkpoints
+WHERE job_id = :job_id;
@@ -1,27 +1,36 @@
 use crate::states::fatal;
 use anyhow::{anyhow, Result};
 use arroyo_datastream::{
-    AggregateBehavior, EdgeType, Operator, Program, SlidingAggregatingTopN,
+    AggregateBehavior, EdgeType, KafkaMetadata, Operator, Program, SlidingAggregatingTopN,
     SlidingWindowAggregator, TumblingTopN, TumblingWindowAggregator, WasmBehavior, WatermarkType,
     WindowType,
 };
 use arroyo_rpc::grpc::compiler_grpc_client::CompilerGrpcClient;
 use arroyo_rpc::grpc::CompileQueryReq;
-use arroyo_types::{to_micros, REMOTE_COMPILER_ENDPOINT_ENV};
+use arroyo_types::{to_micros, worker_version, GIT_SHA_ENV, REMOTE_COMPILER_ENDPOINT_ENV};
+use lazy_static::lazy_static;
 use petgraph::Direction;
 use proc_macro2::TokenStream;
 use quote::{format_ident, quote};
+use std::future::Future;
 use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::{fs, io};
+use std::sync::Mutex;
+use std::{collections::HashMap, fs, io};
 use syn::{parse_str, GenericArgument, PathArguments, Type, TypePath};
 use tokio::process::Command;
 use tonic::{Code, Request};
-use tracing::info;
+use tracing::{info, warn};
 
 const OUTPUT_PATH: &str = "/tmp/arroyo_binaries";
 
+lazy_static! {
+    // programs compiled by this controller, by artifact id
+    static ref COMPILED_PROGRAMS: Mutex<HashMap<String, CompiledProgram>> =
+        Mutex::new(HashMap::new());
+}
+
 #[derive(Debug, Clone)]
 pub struct CompiledProgram {
     pub pipeline_path: String,
@@ -34,7 +43,31 @@ pub struct ProgramCompiler {
     program: Program,
 }
 
-fn parse_type(s: &str) -> Type {
+// returns the artifacts previously compiled under `artifact_id`, or compiles and records them
+async fn cached<F, Fut>(artifact_id: Option<String>, compile: F) -> Result<CompiledProgram>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<CompiledProgram>>,
+{
+    if let Some(id) = &artifact_id {
+        if let Some(compiled) = COMPILED_PROGRAMS.lock().unwrap().get(id) {
+            info!("Using previously compiled artifacts {}", id);
+            return Ok(compiled.clone());
+        }
+    }
+
+    let compiled = compile().await?;
+
+    if let Some(id) = artifact_id {
+        COMPILED_PROGRAMS
+            .lock()
+            .unwrap()
+            .insert(id, compiled.clone());
+    }
+    Ok(compiled)
+}
+
+pub(crate) fn parse_type(s: &str) -> Type {
     let s = s
         .replace("arroyo_bench::", "")
         .replace("pipeline::", "")
@@ -71,14 +104,30 @@ impl ProgramCompiler {
             .unwrap_or_else(|| env!("CARGO_MANIFEST_DIR").to_string())
     }
 
+    // identifies the compiled artifacts for the program, if they can be reused across jobs
+    fn artifact_id(&self) -> Option<String> {
+        worker_version().map(|version| format!("{}-{}", self.program.get_hash(), version))
+    }
+
     pub async fn compile(&self) -> Result<CompiledProgram> {
-        if let Ok(endpoint) = std::env::var(REMOTE_COMPILER_ENDPOINT_ENV) {
-            info!("Compiling remotely on {}", endpoint);
-            self.compile_remote(endpoint).await
-        } else {
-            info!("Compiling locally");
-            self.compile_local().await
+        let artifact_id = self.artifact_id();
+        if artifact_id.is_none() {
+            warn!(
+                "{} is not set, so compiled artifacts will not be reused",
+                GIT_SHA_ENV
+            );
         }
+
+        cached(artifact_id, || async {
+            if let Ok(endpoint) = std::env::var(REMOTE_COMPILER_ENDPOINT_ENV) {
+                info!("Compiling remotely on {}", endpoint);
+                self.compile_remote(endpoint).await
+            } else {
+                info!("Compiling locally");
+                self.compile_local().await
+            }
+        })
+        .await
     }
 
     pub async fn compile_remote(&self, endpoint: String) -> Result<CompiledProgram> {
@@ -86,9 +135,10 @@ impl ProgramCompiler {
             job_id: self.job_id.clone(),
             types: self.compile_types().to_string(),
             pipeline: self
-                .compile_pipeline_main(&self.name, &self.program.get_hash())
+                .compile_pipeline_main(&self.program.get_hash())
                 .to_string(),
             wasm_fns: self.compile_wasm_lib().to_string(),
+            program_hash: self.program.get_hash(),
         };
 
         let mut client = CompilerGrpcClient::connect(endpoint)
@@ -117,9 +167,21 @@ impl ProgramCompiler {
     }
 
     pub async fn compile_local(&self) -> Result<CompiledProgram> {
+        let artifact_id = self.artifact_id();
         let bin_dir = PathBuf::from_str(OUTPUT_PATH)
             .unwrap()
-            .join(self.program.get_hash());
+            .join(artifact_id.as_ref().unwrap_or(&self.program.get_hash()));
+
+        let compiled = CompiledProgram {
+            pipeline_path: format!("file://{}/pipeline", bin_dir.to_string_lossy()),
+            wasm_path: format!("file://{}/wasm_fns_bg.wasm", bin_dir.to_string_lossy()),
+        };
+
+        // the wasm is copied last, so its presence means the binaries are complete
+        if artifact_id.is_some() && bin_dir.join("wasm_fns_bg.wasm").exists() {
+            info!("Using cached binaries in {:?}", bin_dir);
+            return Ok(compiled);
+        }
 
         tokio::fs::create_dir_all(&bin_dir)
             .await
@@ -136,7 +198,7 @@ impl ProgramCompiler {
 
         let types = self.compile_types().to_string();
         let main = self
-            .compile_pipeline_main(&self.name, &self.program.get_hash())
+            .compile_pipeline_main(&self.program.get_hash())
             .to_string();
         let wasm = self.compile_wasm_lib().to_string();
 
@@ -256,10 +318,7 @@ wasm-opt = false
         let wasm = dir.join("wasm-fns/pkg/wasm_fns_bg.wasm");
         tokio::fs::copy(wasm, &bin_dir.join("wasm_fns_bg.wasm")).await?;
 
-        Ok(CompiledProgram {
-            pipeline_path: format!("file://{}/pipeline", bin_dir.to_string_lossy()),
-            wasm_path: format!("file://{}/wasm_fns_bg.wasm", bin_dir.to_string_lossy()),
-        })
+        Ok(compiled)
     }
 
     async fn create_subproject(
@@ -306,7 +365,8 @@ wasm-opt = false
         }
     }
 
-    fn compile_pipeline_main(&self, name: &str, hash: &str) -> TokenStream {
+    // the pipeline's name is not compiled in, as the binary may be shared by several pipelines
+    fn compile_pipeline_main(&self, hash: &str) -> TokenStream {
         let imports = quote! {
             use petgraph::graph::DiGraph;
             use arroyo_worker::operators::*;
@@ -331,12 +391,29 @@ wasm-opt = false
             let input = self.program.graph.edges_directed(idx, Direction::Incoming).next();
             let output = self.program.graph.edges_directed(idx, Direction::Outgoing).next();
             let body = match &node.operator {
-                Operator::FileSource { dir, delay } => {
+                Operator::FileSource { dir, delay, format, monitor_interval, timestamp_field } => {
                     let dir = dir.to_string_lossy();
                     let delay = delay.as_millis() as u64;
+                    let format = format_ident!("{}", format!("{:?}", format));
+                    let monitor_interval = match monitor_interval {
+                        Some(interval) => {
+                            let interval = interval.as_millis() as u64;
+                            quote!(Some(std::time::Duration::from_millis(#interval)))
+                        }
+                        None => quote!(None),
+                    };
+                    let timestamp_field = match timestamp_field {
+                        Some(field) => quote!(Some(#field)),
+                        None => quote!(None),
+                    };
                     let out_t = parse_type(&output.unwrap().weight().value);
                     quote! {
-                        Box::new(FileSourceFunc::<#out_t>::from_dir(&std::path::PathBuf::from(#dir), std::time::Duration::from_millis(#delay)))
+                        Box::new(sources::filesystem::FileSourceFunc::<#out_t>::new(
+                            #dir,
+                            sources::filesystem::FileFormat::#format,
+                            #monitor_interval,
+                            #timestamp_field,
+                            std::time::Duration::from_millis(#delay)))
                     }
                 }
                 Operator::ImpulseSource { start_time, spec, total_events } => {
@@ -367,7 +444,7 @@ wasm-opt = false
                         ))
                     }
                 }
-                Operator::KafkaSource { topic, bootstrap_servers, offset_mode, schema_registry, messages_per_second, client_configs } => {
+                Operator::KafkaSource { topic, bootstrap_servers, offset_mode, schema_registry, messages_per_second, client_configs, metadata_fields } => {
                     let offset_mode = format!("{:?}", offset_mode);
                     let offset_mode = format_ident!("{}", offset_mode);
                     let out_t = parse_type(&output.unwrap().weight().value);
@@ -378,6 +455,17 @@ wasm-opt = false
                         format_ident!("Json")
                     };
                     let client_configs: Vec<_> = client_configs.iter().map(|(key, val)| quote!((#key, #val))).collect();
+                    let metadata_fields: Vec<_> = metadata_fields.iter().map(|f| {
+                        let name = &f.field_name;
+                        let field = match &f.metadata {
+                            KafkaMetadata::Key => quote!(sources::kafka::MetadataField::Key),
+                            KafkaMetadata::Partition => quote!(sources::kafka::MetadataField::Partition),
+                            KafkaMetadata::Offset => quote!(sources::kafka::MetadataField::Offset),
+                            KafkaMetadata::Timestamp => quote!(sources::kafka::MetadataField::Timestamp),
+                            KafkaMetadata::Header(header) => quote!(sources::kafka::MetadataField::Header(#header.to_string())),
+                        };
+                        quote!((#name, #field))
+                    }).collect();
 
                     quote! {
                         Box::new(sources::kafka::KafkaSourceFunc::<#out_t>::new(
@@ -386,7 +474,8 @@ wasm-opt = false
                             sources::kafka::OffsetMode::#offset_mode,
                             sources::kafka::SerializationMode::#serialization_mode,
                             #messages_per_second,
-                            vec![#(#client_configs),*]))
+                            vec![#(#client_configs),*],
+                            vec![#(#metadata_fields),*]))
                     }
                 }
                 Operator::FusedWasmUDFs { name, udfs: _ } => {
@@ -546,16 +635,22 @@ wasm-opt = false
                         }
                     }
                 }
-                Operator::KafkaSink { topic, bootstrap_servers, client_configs } => {
+                Operator::KafkaSink { topic, bootstrap_servers, client_configs, key_field, header_fields } => {
                     let in_k = parse_type(&input.unwrap().weight().key);
                     let in_t = parse_type(&input.unwrap().weight().value);
 
                     let bootstrap_servers = bootstrap_servers.join(",");
                     let client_configs: Vec<_> = client_configs.iter().map(|(key, val)| quote!((#key, #val))).collect();
+                    let key_field = match key_field {
+                        Some(field) => quote!(Some(#field)),
+                        None => quote!(None),
+                    };
                     quote! {
                         Box::new(sinks::kafka::KafkaSinkFunc::<#in_k, #in_t>::new(
                             #bootstrap_servers,
                             #topic,
+                            #key_field,
+                            vec![#(#header_fields),*],
                         vec![#(#client_configs ),*]))
                     }
                 }
@@ -795,6 +890,75 @@ wasm-opt = false
                         #max_elements))
                 }
                 }
+                Operator::KinesisSource { stream_name, region, endpoint, offset_mode } => {
+                    let offset_mode = format_ident!("{}", format!("{:?}", offset_mode));
+                    let out_t = parse_type(&output.unwrap().weight().value);
+                    let endpoint = match endpoint {
+                        Some(endpoint) => quote!(Some(#endpoint)),
+                        None => quote!(None),
+                    };
+
+                    quote! {
+                        Box::new(sources::kinesis::KinesisSourceFunc::<#out_t>::new(
+                            #stream_name,
+                            #region,
+                            #endpoint,
+                            sources::kinesis::OffsetMode::#offset_mode))
+                    }
+                }
+                Operator::KinesisSink { stream_name, region, endpoint, partition_key_field } => {
+                    let in_k = parse_type(&input.unwrap().weight().key);
+                    let in_t = parse_type(&input.unwrap().weight().value);
+                    let endpoint = match endpoint {
+                        Some(endpoint) => quote!(Some(#endpoint)),
+                        None => quote!(None),
+                    };
+                    let partition_key_field = match partition_key_field {
+                        Some(field) => quote!(Some(#field)),
+                        None => quote!(None),
+                    };
+
+                    quote! {
+                        Box::new(sinks::kinesis::KinesisSinkFunc::<#in_k, #in_t>::new(
+                            #stream_name,
+                            #region,
+                            #endpoint,
+                            #partition_key_field))
+                    }
+                }
+                Operator::FileSystemSink { path, format, partition_template, max_file_size, rollover } => {
+                    let in_k = parse_type(&input.unwrap().weight().key);
+                    let in_t = parse_type(&input.unwrap().weight().value);
+                    let format = format_ident!("{}", format!("{:?}", format));
+                    let rollover = rollover.as_micros() as u64;
+
+                    quote! {
+                        Box::new(sinks::filesystem::FileSystemSink::<#in_k, #in_t>::new(
+                            #path,
+                            sinks::filesystem::FileFormat::#format,
+                            #partition_template,
+                            #max_file_size,
+                            std::time::Duration::from_micros(#rollover)))
+                    }
+                }
+                Operator::Interpreted(op) => {
+                    unreachable!("interpreted operator {} should run on the interpreted worker", op.name())
+                }
+                Operator::Dedup { ttl } => {
+                    let k = parse_type(&input.unwrap().weight().key);
+                    let t = parse_type(&input.unwrap().weight().value);
+                    let ttl = match ttl {
+                        Some(ttl) => {
+                            let ttl = ttl.as_micros() as u64;
+                            quote!(Some(std::time::Duration::from_micros(#ttl)))
+                        }
+                        None => quote!(None),
+                    };
+
+                    quote! {
+                        Box::new(DedupOperator::<#k, #t>::new(#ttl))
+                    }
+                }
             };
 
             (node.operator_id.clone(), description, body, node.parallelism)
@@ -809,7 +973,7 @@ wasm-opt = false
                         LogicalNode {
                             id: #id.to_string(),
                             description: #description.to_string(),
-                            create_fn: Box::new(|subtask_idx: usize, parallelism: usize| {
+                            create_fn: std::sync::Arc::new(|subtask_idx: usize, parallelism: usize| {
                                 SubtaskNode {
                                     id: #id.to_string(),
                                     subtask_idx,
@@ -879,7 +1043,7 @@ wasm-opt = false
             pub fn main() {
                 let graph = make_graph();
 
-                arroyo_worker::WorkerServer::new(#name, #hash, graph).start().unwrap();
+                arroyo_worker::WorkerServer::new(#hash, graph).start().unwrap();
             }
 
             #(#other_defs )*
@@ -1047,10 +1211,23 @@ wasm-opt = false
 
 #[cfg(test)]
 mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use arroyo_datastream::Program;
+    use arroyo_types::GIT_SHA_ENV;
+    use petgraph::graph::DiGraph;
     use quote::quote;
     use syn::parse_str;
 
-    use super::extract_container_type;
+    use super::{cached, extract_container_type, CompiledProgram, ProgramCompiler};
+
+    fn program() -> Program {
+        Program {
+            types: vec![],
+            other_defs: vec![],
+            graph: DiGraph::new(),
+        }
+    }
 
     #[test]
     fn test_extract_vec_type() {
@@ -1065,4 +1242,56 @@ mod tests {
             extract_container_type("Vec", &parse_str("HashMap<String, u8>").unwrap())
         );
     }
+
+    #[test]
+    fn test_artifacts_are_independent_of_pipeline_name() {
+        std::env::set_var(GIT_SHA_ENV, "abc123");
+        let first = ProgramCompiler::new("first_pipeline", "job_1", program());
+        let second = ProgramCompiler::new("second_pipeline", "job_2", program());
+
+        assert_eq!(
+            first.artifact_id(),
+            Some(format!("{}-abc123", program().get_hash()))
+        );
+        assert_eq!(first.artifact_id(), second.artifact_id());
+
+        let hash = program().get_hash();
+        let main = first.compile_pipeline_main(&hash).to_string();
+        assert_eq!(main, second.compile_pipeline_main(&hash).to_string());
+        assert!(!main.contains("first_pipeline"));
+    }
+
+    #[tokio::test]
+    async fn test_artifact_cache() {
+        let compiles = AtomicUsize::new(0);
+        let compile = || async {
+            compiles.fetch_add(1, Ordering::SeqCst);
+            Ok(CompiledProgram {
+                pipeline_path: "file:///tmp/pipeline".to_string(),
+                wasm_path: "file:///tmp/wasm_fns_bg.wasm".to_string(),
+            })
+        };
+
+        // a miss compiles the program, after which the artifacts are reused
+        let compiled = cached(Some("cache_test-v1".to_string()), compile)
+            .await
+            .unwrap();
+        assert_eq!(compiles.load(Ordering::SeqCst), 1);
+        let reused = cached(Some("cache_test-v1".to_string()), compile)
+            .await
+            .unwrap();
+        assert_eq!(compiles.load(Ordering::SeqCst), 1);
+        assert_eq!(reused.pipeline_path, compiled.pipeline_path);
+
+        // a different worker version misses
+        cached(Some("cache_test-v2".to_string()), compile)
+            .await
+            .unwrap();
+        assert_eq!(compiles.load(Ordering::SeqCst), 2);
+
+        // and without a version nothing is cached
+        cached(None, compile).await.unwrap();
+        cached(None, compile).await.unwrap();
+        assert_eq!(compiles.load(Ordering::SeqCst), 4);
+    }
 }
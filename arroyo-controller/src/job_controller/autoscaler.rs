@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+
+use arroyo_datastream::Program;
+use arroyo_rpc::grpc::TaskLoad;
+use arroyo_types::{from_micros, AutoscalingConfig};
+use petgraph::Direction;
+use tracing::info;
+
+// load reports older than this are not used for decisions
+const MAX_LOAD_AGE: Duration = Duration::from_secs(30);
+
+// queues this full indicate that the operator reading from them can't keep up
+const FULL_QUEUE: f64 = 0.8;
+
+// sources are only scaled up for lag if they aren't being held back by the rest of the job
+const MAX_SOURCE_BACKPRESSURE: f64 = 0.1;
+
+struct LoadReport {
+    received: Instant,
+    load: TaskLoad,
+    source_lag: Option<Duration>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Pending {
+    target: usize,
+    since: Instant,
+}
+
+/// Decides how to rescale a running job from the load its workers report
+pub struct Autoscaler {
+    config: AutoscalingConfig,
+    parallelism: HashMap<String, usize>,
+    upstream: HashMap<String, Vec<String>>,
+    sources: Vec<String>,
+    loads: HashMap<(String, u32), LoadReport>,
+    // operators that need rescaling, and since when, so that short spikes are ignored
+    pending: HashMap<String, Pending>,
+    started: Instant,
+}
+
+impl Autoscaler {
+    pub fn new(config: AutoscalingConfig, program: &Program) -> Self {
+        let graph = &program.graph;
+        Self {
+            config,
+            parallelism: graph
+                .node_weights()
+                .map(|n| (n.operator_id.clone(), n.parallelism))
+                .collect(),
+            upstream: graph
+                .node_indices()
+                .map(|idx| {
+                    (
+                        graph[idx].operator_id.clone(),
+                        graph
+                            .neighbors_directed(idx, Direction::Incoming)
+                            .map(|up| graph[up].operator_id.clone())
+                            .collect(),
+                    )
+                })
+                .collect(),
+            sources: graph
+                .externals(Direction::Incoming)
+                .map(|idx| graph[idx].operator_id.clone())
+                .collect(),
+            loads: HashMap::new(),
+            pending: HashMap::new(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn set_config(&mut self, config: AutoscalingConfig) {
+        self.config = config;
+    }
+
+    pub fn record(&mut self, loads: Vec<TaskLoad>, received: Instant) {
+        let now = SystemTime::now();
+        for load in loads {
+            let source_lag = load
+                .watermark
+                .and_then(|w| now.duration_since(from_micros(w)).ok());
+            self.loads.insert(
+                (load.operator_id.clone(), load.subtask_index),
+                LoadReport {
+                    received,
+                    load,
+                    source_lag,
+                },
+            );
+        }
+    }
+
+    // the current load reports for each subtask of the operator, if all of them have reported
+    fn operator_loads(&self, operator_id: &str, now: Instant) -> Option<Vec<&LoadReport>> {
+        (0..self.parallelism[operator_id])
+            .map(|i| {
+                self.loads
+                    .get(&(operator_id.to_string(), i as u32))
+                    .filter(|r| now.duration_since(r.received) < MAX_LOAD_AGE)
+            })
+            .collect()
+    }
+
+    fn average(loads: &[&LoadReport], f: impl Fn(&TaskLoad) -> f32) -> f64 {
+        loads.iter().map(|r| f(&r.load) as f64).sum::<f64>() / loads.len().max(1) as f64
+    }
+
+    fn clamp(&self, parallelism: usize) -> usize {
+        parallelism.clamp(
+            self.config.min_parallelism as usize,
+            self.config.max_parallelism as usize,
+        )
+    }
+
+    // the parallelism that would bring the operator to the target utilization
+    fn scaled(&self, parallelism: usize, busy: f64) -> usize {
+        (parallelism as f64 * busy / self.config.target_utilization).ceil() as usize
+    }
+
+    fn target_parallelism(&self, operator_id: &str, now: Instant) -> Option<usize> {
+        let parallelism = self.parallelism[operator_id];
+        let loads = self.operator_loads(operator_id, now)?;
+
+        let target = if self.sources.iter().any(|s| s == operator_id) {
+            let lag = loads.iter().filter_map(|r| r.source_lag).max();
+            let backpressure = Self::average(&loads, |l| l.backpressure);
+
+            if matches!(lag, Some(l) if l.as_micros() as u64 > self.config.max_source_lag_micros)
+                && backpressure < MAX_SOURCE_BACKPRESSURE
+            {
+                parallelism + parallelism.div_ceil(2)
+            } else {
+                parallelism
+            }
+        } else {
+            let busy = Self::average(&loads, |l| l.busy);
+
+            // how full the queues into this operator are, from its upstream operators' side
+            let input_fullness = self.upstream[operator_id]
+                .iter()
+                .filter_map(|up| self.operator_loads(up, now))
+                .map(|loads| Self::average(&loads, |l| l.queue_fullness))
+                .fold(0.0, f64::max);
+
+            if busy > self.config.scale_up_utilization
+                || (input_fullness > FULL_QUEUE && busy > self.config.target_utilization)
+            {
+                self.scaled(parallelism, busy).max(parallelism + 1)
+            } else if busy < self.config.scale_down_utilization && input_fullness < FULL_QUEUE {
+                self.scaled(parallelism, busy)
+                    .min(parallelism.saturating_sub(1))
+            } else {
+                parallelism
+            }
+        };
+
+        Some(self.clamp(target))
+    }
+
+    /// The new parallelism of every operator, if the job should be rescaled
+    pub fn evaluate(&mut self, now: Instant) -> Option<HashMap<String, usize>> {
+        if !self.config.enabled
+            || now.duration_since(self.started) < Duration::from_micros(self.config.cooldown_micros)
+        {
+            self.pending.clear();
+            return None;
+        }
+
+        let operators: Vec<_> = self.parallelism.keys().cloned().collect();
+        for operator_id in operators {
+            let current = self.parallelism[&operator_id];
+            match self.target_parallelism(&operator_id, now) {
+                Some(target) if target != current => {
+                    let pending = self
+                        .pending
+                        .entry(operator_id)
+                        .or_insert(Pending { target, since: now });
+
+                    // a change of direction restarts the stabilization period
+                    if (pending.target > current) != (target > current) {
+                        pending.since = now;
+                    }
+                    pending.target = target;
+                }
+                _ => {
+                    self.pending.remove(&operator_id);
+                }
+            }
+        }
+
+        let stabilization = Duration::from_micros(self.config.stabilization_micros);
+        let ready: HashMap<_, _> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| now.duration_since(p.since) >= stabilization)
+            .map(|(op, p)| (op.clone(), p.target))
+            .collect();
+
+        if ready.is_empty() {
+            return None;
+        }
+
+        for (op, target) in &ready {
+            info!(
+                message = "autoscaling operator",
+                operator_id = op,
+                from = self.parallelism[op],
+                to = target
+            );
+        }
+
+        self.parallelism.extend(ready);
+        self.pending.clear();
+        self.loads.clear();
+        self.started = now;
+
+        Some(self.parallelism.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arroyo_datastream::{EdgeType, Operator, StreamEdge, StreamNode};
+    use petgraph::graph::DiGraph;
+
+    fn config() -> AutoscalingConfig {
+        AutoscalingConfig {
+            enabled: true,
+            min_parallelism: 1,
+            max_parallelism: 8,
+            target_utilization: 0.6,
+            scale_up_utilization: 0.8,
+            scale_down_utilization: 0.3,
+            max_source_lag_micros: 10_000_000,
+            stabilization_micros: 60_000_000,
+            cooldown_micros: 0,
+        }
+    }
+
+    fn autoscaler() -> Autoscaler {
+        let node = |id: &str, parallelism| StreamNode {
+            operator_id: id.to_string(),
+            operator: Operator::NullSink,
+            parallelism,
+        };
+
+        let mut graph = DiGraph::new();
+        let source = graph.add_node(node("source", 1));
+        let sink = graph.add_node(node("sink", 2));
+        graph.add_edge(
+            source,
+            sink,
+            StreamEdge::unkeyed_edge("()", EdgeType::Shuffle),
+        );
+
+        Autoscaler::new(
+            config(),
+            &Program {
+                types: vec![],
+                other_defs: vec![],
+                graph,
+            },
+        )
+    }
+
+    // reports the same load for the sink's subtasks, and evaluates after `secs`
+    fn evaluate(
+        autoscaler: &mut Autoscaler,
+        start: Instant,
+        secs: u64,
+        busy: [f32; 2],
+    ) -> Option<HashMap<String, usize>> {
+        let now = start + Duration::from_secs(secs);
+        let load = |operator_id: &str, subtask_index, busy| TaskLoad {
+            operator_id: operator_id.to_string(),
+            subtask_index,
+            busy,
+            backpressure: 0.0,
+            queue_fullness: 0.0,
+            watermark: None,
+        };
+
+        autoscaler.record(
+            vec![
+                load("source", 0, 0.1),
+                load("sink", 0, busy[0]),
+                load("sink", 1, busy[1]),
+            ],
+            now,
+        );
+        autoscaler.evaluate(now)
+    }
+
+    #[test]
+    fn test_scales_busy_operator_after_stabilization() {
+        let mut autoscaler = autoscaler();
+        let start = Instant::now();
+
+        // the load has to persist before anything is rescaled
+        assert_eq!(evaluate(&mut autoscaler, start, 0, [0.9, 0.9]), None);
+        assert_eq!(evaluate(&mut autoscaler, start, 30, [0.9, 0.9]), None);
+
+        let parallelism = evaluate(&mut autoscaler, start, 61, [0.9, 0.9]).unwrap();
+        assert_eq!(parallelism["sink"], 3);
+        assert_eq!(parallelism["source"], 1);
+    }
+
+    #[test]
+    fn test_ignores_short_spikes() {
+        let mut autoscaler = autoscaler();
+        let start = Instant::now();
+
+        assert_eq!(evaluate(&mut autoscaler, start, 0, [0.9, 0.9]), None);
+        assert_eq!(evaluate(&mut autoscaler, start, 30, [0.5, 0.7]), None);
+        assert_eq!(evaluate(&mut autoscaler, start, 61, [0.9, 0.9]), None);
+    }
+
+    #[test]
+    fn test_keeps_parallelism_within_hysteresis_band() {
+        let mut autoscaler = autoscaler();
+        let start = Instant::now();
+
+        assert_eq!(evaluate(&mut autoscaler, start, 0, [0.5, 0.7]), None);
+        assert_eq!(evaluate(&mut autoscaler, start, 120, [0.5, 0.7]), None);
+    }
+
+    #[test]
+    fn test_scales_down_to_min_parallelism() {
+        let mut autoscaler = autoscaler();
+        let start = Instant::now();
+
+        assert_eq!(evaluate(&mut autoscaler, start, 0, [0.05, 0.05]), None);
+        let parallelism = evaluate(&mut autoscaler, start, 61, [0.05, 0.05]).unwrap();
+        assert_eq!(parallelism["sink"], 1);
+    }
+}
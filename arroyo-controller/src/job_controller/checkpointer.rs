@@ -12,7 +12,7 @@ use arroyo_rpc::grpc::{
     SubtaskCheckpointMetadata, TableDescriptor, TaskCheckpointCompletedReq, TaskCheckpointEventReq,
     TaskCheckpointEventType,
 };
-use arroyo_state::{BackingStore, StateBackend};
+use arroyo_state::{schema::OperatorSchemas, BackingStore, StateBackend};
 use arroyo_types::{from_micros, to_micros};
 use deadpool_postgres::Pool;
 use time::OffsetDateTime;
@@ -49,6 +49,9 @@ impl SubtaskState {
     }
 }
 
+/// For each operator, the files that compaction has merged, mapped to the file they were merged into
+pub type CompactedFiles = HashMap<String, HashMap<String, BackendData>>;
+
 pub struct CheckpointState {
     job_id: String,
     checkpoint_id: i64,
@@ -58,6 +61,8 @@ pub struct CheckpointState {
     tasks_per_operator: HashMap<String, usize>,
     tasks: HashMap<String, BTreeMap<u32, SubtaskState>>,
     completed_operators: HashSet<String>,
+    compacted_files: CompactedFiles,
+    table_schemas: OperatorSchemas,
 
     // Used for the web ui -- eventually should be replaced with some other way of tracking / reporting
     // this data
@@ -71,6 +76,8 @@ impl CheckpointState {
         epoch: u32,
         min_epoch: u32,
         program: &Program,
+        compacted_files: CompactedFiles,
+        table_schemas: OperatorSchemas,
         pool: &Pool,
     ) -> anyhow::Result<Self> {
         let tasks_per_operator: HashMap<String, usize> = program
@@ -108,6 +115,8 @@ impl CheckpointState {
             tasks_per_operator,
             tasks: HashMap::new(),
             completed_operators: HashSet::new(),
+            compacted_files,
+            table_schemas,
             operator_details: HashMap::new(),
         })
     }
@@ -160,7 +169,10 @@ impl CheckpointState {
             .event(c);
     }
 
-    pub async fn checkpoint_finished(&mut self, c: TaskCheckpointCompletedReq) {
+    pub async fn checkpoint_finished(
+        &mut self,
+        c: TaskCheckpointCompletedReq,
+    ) -> anyhow::Result<()> {
         // this is just for the UI
         let metadata = c.metadata.as_ref().unwrap();
 
@@ -199,7 +211,7 @@ impl CheckpointState {
                 "Received checkpoint completed message for already finished operator {}",
                 c.operator_id
             );
-            return;
+            return Ok(());
         }
 
         let subtasks = self
@@ -250,10 +262,14 @@ impl CheckpointState {
                 .values()
                 .any(|s| s.metadata.as_ref().unwrap().has_state);
 
+            let schemas = self.table_schemas.get(&operator_id);
             let tables: HashMap<String, TableDescriptor> = subtasks
                 .values()
                 .flat_map(|t| t.metadata.as_ref().unwrap().tables.clone())
-                .map(|t| (t.name.clone(), t))
+                .map(|mut t| {
+                    t.schema = schemas.and_then(|s| s.get(&t.name)).cloned();
+                    (t.name.clone(), t)
+                })
                 .collect();
 
             // the sort here is load-bearing
@@ -262,6 +278,9 @@ impl CheckpointState {
                 .map(|s| (s.metadata.as_ref().unwrap()))
                 .filter(|metadata| metadata.has_state)
                 .flat_map(|metadata| metadata.backend_data.clone())
+                .map(|backend_data| {
+                    Self::replace_compacted(&self.compacted_files, &operator_id, backend_data)
+                })
                 .filter_map(|backend_data| Self::backend_data_to_key(backend_data))
                 .collect();
 
@@ -282,7 +301,7 @@ impl CheckpointState {
                 backend_data: backend_data.into_values().collect(),
                 bytes: size,
             })
-            .await;
+            .await?;
 
             if let Some(op) = self.operator_details.get_mut(&operator_id) {
                 op.finish_time = Some(to_micros(finish_time));
@@ -290,6 +309,24 @@ impl CheckpointState {
 
             self.completed_operators.insert(operator_id);
         }
+        Ok(())
+    }
+
+    // subtasks keep referencing the files that compaction merged until they are restarted, so
+    // point the checkpoint at the merged files instead
+    fn replace_compacted(
+        compacted_files: &CompactedFiles,
+        operator_id: &str,
+        backend_data: BackendData,
+    ) -> BackendData {
+        let Some(backend_data::BackendData::ParquetStore(data)) = &backend_data.backend_data else {
+            return backend_data;
+        };
+        compacted_files
+            .get(operator_id)
+            .and_then(|files| files.get(&data.file))
+            .cloned()
+            .unwrap_or(backend_data)
     }
 
     fn backend_data_to_key(backend_data: BackendData) -> Option<((u32, String), BackendData)> {
@@ -333,7 +370,7 @@ impl CheckpointState {
             min_epoch: self.min_epoch,
             operator_ids: self.completed_operators.iter().cloned().collect(),
         })
-        .await;
+        .await?;
 
         let operator_state = serde_json::to_value(&self.operator_details).unwrap();
 
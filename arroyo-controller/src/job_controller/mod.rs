@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     time::{Duration, Instant, SystemTime},
 };
 
@@ -7,10 +7,11 @@ use crate::types::public::StopMode as SqlStopMode;
 use anyhow::bail;
 use arroyo_datastream::Program;
 use arroyo_rpc::grpc::{
-    worker_grpc_client::WorkerGrpcClient, CheckpointReq, JobFinishedReq, StopExecutionReq, StopMode,
+    backend_data, worker_grpc_client::WorkerGrpcClient, CheckpointReq, CommitReq, JobFinishedReq,
+    StopExecutionReq, StopMode, TaskAssignment,
 };
-use arroyo_state::{BackingStore, StateBackend};
-use arroyo_types::{to_micros, WorkerId};
+use arroyo_state::{schema::OperatorSchemas, BackingStore, StateBackend};
+use arroyo_types::{to_micros, AutoscalingConfig, WorkerId};
 
 use deadpool_postgres::Pool;
 
@@ -18,10 +19,12 @@ use tokio::{sync::mpsc::Receiver, task::JoinHandle};
 use tonic::{transport::Channel, Request};
 use tracing::{error, info, warn};
 
-use crate::{queries::controller_queries, JobConfig, JobMessage, RunningMessage};
+use crate::{queries::controller_queries, state_schemas, JobConfig, JobMessage, RunningMessage};
 
-use self::checkpointer::CheckpointState;
+use self::autoscaler::Autoscaler;
+use self::checkpointer::{CheckpointState, CompactedFiles};
 
+mod autoscaler;
 mod checkpointer;
 
 const CHECKPOINTS_TO_KEEP: u32 = 4;
@@ -58,6 +61,8 @@ pub enum TaskState {
 #[derive(Debug)]
 pub struct TaskStatus {
     state: TaskState,
+    // whether the task is a bounded source that has read all of its input
+    reached_end: bool,
 }
 
 // Stores a model of the current state of a running job to use in the state machine
@@ -78,6 +83,20 @@ pub struct RunningJobModel {
     workers: HashMap<WorkerId, WorkerStatus>,
     tasks: HashMap<(String, u32), TaskStatus>,
     operator_parallelism: HashMap<String, usize>,
+    compacted_files: CompactedFiles,
+    table_schemas: OperatorSchemas,
+    assignments: Vec<TaskAssignment>,
+    // the subtasks that exchange data with each other, which must be restarted together
+    regions: Vec<Vec<(String, usize)>>,
+}
+
+/// The subtasks and workers that must be restarted to recover from a failure without restarting
+/// the rest of the job: the failover regions of the failed subtasks, along with any other regions
+/// that share workers with them.
+#[derive(Debug, Clone, Default)]
+pub struct FailoverGroup {
+    pub tasks: HashSet<(String, u32)>,
+    pub workers: HashSet<WorkerId>,
 }
 
 impl std::fmt::Debug for RunningJobModel {
@@ -127,7 +146,7 @@ impl RunningJobModel {
                             self.job_id,
                         );
                     } else {
-                        checkpoint_state.checkpoint_finished(c).await;
+                        checkpoint_state.checkpoint_finished(c).await?;
                         checkpoint_state.update_db(pool).await?;
                     }
                 } else {
@@ -137,6 +156,18 @@ impl RunningJobModel {
                     )
                 }
             }
+            RunningMessage::TaskFinished { worker_id, .. }
+            | RunningMessage::TaskFailed { worker_id, .. }
+            | RunningMessage::TaskReachedEnd { worker_id, .. }
+                if !self.workers.contains_key(&worker_id) =>
+            {
+                // workers that were replaced after a failure may still report on their tasks
+                warn!(
+                    message = "Received task message from unknown worker",
+                    job_id = self.job_id,
+                    worker_id = worker_id.0
+                );
+            }
             RunningMessage::TaskFinished {
                 worker_id: _,
                 time: _,
@@ -155,6 +186,23 @@ impl RunningJobModel {
                     );
                 }
             }
+            RunningMessage::TaskReachedEnd {
+                operator_id,
+                subtask_index,
+                ..
+            } => {
+                let key = (operator_id, subtask_index);
+                if let Some(status) = self.tasks.get_mut(&key) {
+                    status.reached_end = true;
+                } else {
+                    warn!(
+                        message = "Received task reached end for unknown task",
+                        job_id = self.job_id,
+                        operator_id = key.0,
+                        subtask_index
+                    );
+                }
+            }
             RunningMessage::TaskFailed {
                 operator_id,
                 subtask_index,
@@ -174,7 +222,9 @@ impl RunningJobModel {
                     );
                 }
             }
-            RunningMessage::WorkerHeartbeat { worker_id, time } => {
+            RunningMessage::WorkerHeartbeat {
+                worker_id, time, ..
+            } => {
                 if let Some(worker) = self.workers.get_mut(&worker_id) {
                     worker.last_heartbeat = time;
                 } else {
@@ -250,6 +300,8 @@ impl RunningJobModel {
                 self.epoch,
                 self.min_epoch,
                 &self.program,
+                self.compacted_files.clone(),
+                self.table_schemas.clone(),
                 pool,
             )
             .await?,
@@ -275,6 +327,19 @@ impl RunningJobModel {
                 epoch = self.epoch,
                 duration
             );
+
+            // let sinks know that they can make the data they wrote for this epoch visible
+            for w in self.workers.values_mut() {
+                if let Err(e) = w.connect.commit(CommitReq { epoch: self.epoch }).await {
+                    warn!(
+                        message = "Failed to send commit to worker",
+                        job_id = self.job_id,
+                        worker_id = w.id.0,
+                        epoch = self.epoch,
+                        error = format!("{:?}", e),
+                    )
+                }
+            }
         }
         Ok(())
     }
@@ -315,6 +380,68 @@ impl RunningJobModel {
         false
     }
 
+    fn failover_group(&self) -> Option<FailoverGroup> {
+        let worker_for_task: HashMap<_, _> = self
+            .assignments
+            .iter()
+            .map(|a| {
+                (
+                    (a.operator_id.clone(), a.operator_subtask as u32),
+                    WorkerId(a.worker_id),
+                )
+            })
+            .collect();
+
+        let mut group = FailoverGroup {
+            tasks: self
+                .tasks
+                .iter()
+                .filter(|(_, t)| matches!(t.state, TaskState::Failed(_)))
+                .map(|(task, _)| task.clone())
+                .collect(),
+            workers: self
+                .workers
+                .iter()
+                .filter(|(_, w)| w.heartbeat_timeout())
+                .map(|(id, _)| *id)
+                .collect(),
+        };
+
+        if group.tasks.is_empty() && group.workers.is_empty() {
+            return None;
+        }
+
+        // grow the group until it contains whole regions and every task of its workers
+        loop {
+            let size = (group.tasks.len(), group.workers.len());
+
+            for region in &self.regions {
+                let affected = region.iter().any(|(op, idx)| {
+                    let task = (op.clone(), *idx as u32);
+                    group.tasks.contains(&task)
+                        || worker_for_task
+                            .get(&task)
+                            .map(|w| group.workers.contains(w))
+                            .unwrap_or(false)
+                });
+
+                if affected {
+                    for (op, idx) in region {
+                        let task = (op.clone(), *idx as u32);
+                        if let Some(worker) = worker_for_task.get(&task) {
+                            group.workers.insert(*worker);
+                        }
+                        group.tasks.insert(task);
+                    }
+                }
+            }
+
+            if (group.tasks.len(), group.workers.len()) == size {
+                return Some(group);
+            }
+        }
+    }
+
     pub fn any_finished_sources(&self) -> bool {
         let source_tasks = self.program.sources();
 
@@ -323,6 +450,19 @@ impl RunningJobModel {
         })
     }
 
+    // whether the job's sources are all bounded and have read all of their input
+    pub fn all_sources_reached_end(&self) -> bool {
+        let source_tasks = self.program.sources();
+
+        let mut sources = self
+            .tasks
+            .iter()
+            .filter(|((operator, _), _)| source_tasks.contains(operator.as_str()))
+            .peekable();
+
+        sources.peek().is_some() && sources.all(|(_, t)| t.reached_end)
+    }
+
     pub fn all_tasks_finished(&self) -> bool {
         self.tasks
             .iter()
@@ -334,7 +474,8 @@ pub struct JobController {
     pool: Pool,
     config: JobConfig,
     model: RunningJobModel,
-    compacting_task: Option<JoinHandle<anyhow::Result<u32>>>,
+    compacting_task: Option<JoinHandle<anyhow::Result<(u32, CompactedFiles)>>>,
+    autoscaler: Option<Autoscaler>,
 }
 
 impl std::fmt::Debug for JobController {
@@ -343,6 +484,7 @@ impl std::fmt::Debug for JobController {
             .field("config", &self.config)
             .field("model", &self.model)
             .field("compacting", &self.compacting_task.is_some())
+            .field("autoscaling", &self.autoscaler.is_some())
             .finish()
     }
 }
@@ -360,7 +502,12 @@ impl JobController {
         epoch: u32,
         min_epoch: u32,
         worker_connects: HashMap<WorkerId, WorkerGrpcClient<Channel>>,
+        assignments: Vec<TaskAssignment>,
     ) -> Self {
+        let autoscaler = config
+            .autoscaling
+            .map(|config| Autoscaler::new(config, &program));
+
         Self {
             pool,
             model: RunningJobModel {
@@ -393,6 +540,7 @@ impl JobController {
                                 (node.operator_id.clone(), idx as u32),
                                 TaskStatus {
                                     state: TaskState::Running,
+                                    reached_end: false,
                                 },
                             )
                         })
@@ -403,17 +551,43 @@ impl JobController {
                     .node_weights()
                     .map(|node| (node.operator_id.clone(), node.parallelism))
                     .collect(),
+                table_schemas: state_schemas::table_schemas(&program),
+                regions: program.failover_regions(),
                 program,
+                compacted_files: HashMap::new(),
+                assignments,
             },
             config,
             compacting_task: None,
+            autoscaler,
         }
     }
 
-    pub async fn handle_message(&mut self, msg: RunningMessage) -> anyhow::Result<()> {
+    pub async fn handle_message(&mut self, mut msg: RunningMessage) -> anyhow::Result<()> {
+        if let RunningMessage::WorkerHeartbeat { time, tasks, .. } = &mut msg {
+            if let Some(autoscaler) = &mut self.autoscaler {
+                autoscaler.record(std::mem::take(tasks), *time);
+            }
+        }
+
         self.model.handle_message(msg, &self.pool).await
     }
 
+    /// The new parallelism of each operator, if the autoscaler has decided to rescale the job
+    pub fn autoscale(&mut self) -> Option<HashMap<String, usize>> {
+        self.autoscaler.as_mut()?.evaluate(Instant::now())
+    }
+
+    pub fn update_autoscaling(&mut self, config: Option<AutoscalingConfig>) {
+        match (&mut self.autoscaler, config) {
+            (Some(autoscaler), Some(config)) => autoscaler.set_config(config),
+            (None, Some(config)) => {
+                self.autoscaler = Some(Autoscaler::new(config, &self.model.program));
+            }
+            (_, None) => self.autoscaler = None,
+        }
+    }
+
     pub async fn progress(&mut self) -> anyhow::Result<ControllerProgress> {
         // have any of our workers failed?
         if self.model.failed() {
@@ -430,13 +604,14 @@ impl JobController {
             let task = self.compacting_task.take().unwrap();
 
             match task.await {
-                Ok(Ok(min_epoch)) => {
+                Ok(Ok((min_epoch, compacted_files))) => {
                     info!(
                         message = "setting new min epoch",
                         min_epoch,
                         job_id = self.config.id
                     );
                     self.model.min_epoch = min_epoch;
+                    self.model.compacted_files = compacted_files;
                 }
                 Ok(Err(e)) => {
                     error!(
@@ -461,6 +636,25 @@ impl JobController {
             }
         }
 
+        // have our bounded sources read all of their input? Then the job is finished with a
+        // final checkpoint, once the current one (if any) has completed
+        if self.model.all_sources_reached_end() {
+            if self.model.checkpoint_state.is_some() {
+                self.model.finish_checkpoint_if_done(&self.pool).await?;
+            }
+
+            if self.model.checkpoint_state.is_none() && self.compacting_task.is_none() {
+                info!(
+                    message = "sources have reached their ends, finishing job",
+                    job_id = self.config.id
+                );
+                self.checkpoint(true).await?;
+                return Ok(ControllerProgress::Finishing);
+            }
+
+            return Ok(ControllerProgress::Continue);
+        }
+
         // check on checkpointing
         if self.model.checkpoint_state.is_some() {
             self.model.finish_checkpoint_if_done(&self.pool).await?;
@@ -505,6 +699,11 @@ impl JobController {
 
     pub async fn wait_for_finish(&mut self, rx: &mut Receiver<JobMessage>) -> anyhow::Result<()> {
         loop {
+            // a job that finishes with a final checkpoint completes it before its tasks finish
+            if self.model.checkpoint_state.is_some() {
+                self.model.finish_checkpoint_if_done(&self.pool).await?;
+            }
+
             if self.model.all_tasks_finished() {
                 return Ok(());
             }
@@ -533,15 +732,85 @@ impl JobController {
         }
     }
 
+    pub fn epoch(&self) -> u32 {
+        self.model.epoch
+    }
+
+    pub fn failed(&self) -> bool {
+        self.model.failed()
+    }
+
     pub fn get_workers(&self) -> Vec<WorkerId> {
         self.model.workers.keys().copied().collect()
     }
 
+    pub fn assignments(&self) -> &[TaskAssignment] {
+        &self.model.assignments
+    }
+
+    /// If the job has failed, the part of it that can be restarted on its own to recover, or
+    /// None if the whole job must be restarted
+    pub fn failover_group(&self) -> Option<FailoverGroup> {
+        // compaction rewrites the files that the restarted tasks would restore from
+        if self.compacting_task.is_some() {
+            return None;
+        }
+
+        self.model
+            .failover_group()
+            .filter(|group| group.workers.len() < self.model.workers.len())
+    }
+
+    /// Abandons the in-progress checkpoint, which can't complete once some of the tasks that
+    /// were taking it have been restarted
+    pub async fn abort_checkpoint(&mut self) -> anyhow::Result<()> {
+        if self.model.checkpoint_state.take().is_some() {
+            let c = self.pool.get().await?;
+            controller_queries::mark_failed()
+                .bind(&c, &self.config.id, &(self.model.epoch as i32))
+                .await?;
+            self.model.last_checkpoint = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Replaces the workers of a failover group with the ones its tasks have been restarted on
+    pub fn replace_workers(
+        &mut self,
+        group: &FailoverGroup,
+        worker_connects: HashMap<WorkerId, WorkerGrpcClient<Channel>>,
+        assignments: Vec<TaskAssignment>,
+    ) {
+        self.model
+            .workers
+            .retain(|id, _| !group.workers.contains(id));
+        for (id, connect) in worker_connects {
+            self.model.workers.insert(
+                id,
+                WorkerStatus {
+                    id,
+                    connect,
+                    last_heartbeat: Instant::now(),
+                    state: WorkerState::Running,
+                },
+            );
+        }
+
+        for task in &group.tasks {
+            if let Some(status) = self.model.tasks.get_mut(task) {
+                status.state = TaskState::Running;
+                status.reached_end = false;
+            }
+        }
+
+        self.model.assignments = assignments;
+    }
+
     pub fn operator_parallelism(&self, op: &str) -> Option<usize> {
         self.model.operator_parallelism.get(op).cloned()
     }
 
-    fn start_compaction(&self, new_min: u32) -> JoinHandle<anyhow::Result<u32>> {
+    fn start_compaction(&self, new_min: u32) -> JoinHandle<anyhow::Result<(u32, CompactedFiles)>> {
         let min_epoch = self.model.min_epoch;
         let job_id = self.config.id.clone();
         let pool = self.pool.clone();
@@ -552,7 +821,7 @@ impl JobController {
 
         tokio::spawn(async move {
             let checkpoint = StateBackend::load_checkpoint_metadata(&job_id, cur_epoch)
-                .await
+                .await?
                 .ok_or_else(|| {
                     anyhow::anyhow!("Couldn't find checkpoint for job during compaction")
                 })?;
@@ -562,8 +831,29 @@ impl JobController {
                 .bind(&c, &job_id, &(min_epoch as i32), &(new_min as i32))
                 .await?;
 
+            let operator_ids = checkpoint.operator_ids.clone();
             StateBackend::compact_checkpoint(checkpoint, min_epoch, new_min).await?;
 
+            let mut compacted_files = CompactedFiles::new();
+            for operator_id in operator_ids {
+                let Some(metadata) =
+                    StateBackend::load_operator_metadata(&job_id, &operator_id, new_min).await?
+                else {
+                    continue;
+                };
+                let files = compacted_files.entry(operator_id).or_default();
+                for backend_data in metadata.backend_data {
+                    let Some(backend_data::BackendData::ParquetStore(data)) =
+                        &backend_data.backend_data
+                    else {
+                        continue;
+                    };
+                    for file in &data.compacted_files {
+                        files.insert(file.clone(), backend_data.clone());
+                    }
+                }
+            }
+
             controller_queries::mark_checkpoints_compacted()
                 .bind(&c, &job_id, &(new_min as i32))
                 .await?;
@@ -576,7 +866,7 @@ impl JobController {
                 duration = start.elapsed().as_secs_f32()
             );
 
-            Ok(new_min)
+            Ok((new_min, compacted_files))
         })
     }
 }
@@ -1,7 +1,7 @@
 #![allow(clippy::new_without_default)]
 // TODO: factor out complex types
 #![allow(clippy::type_complexity)]
-use crate::scheduler::{NomadScheduler, ProcessScheduler, Scheduler};
+use crate::scheduler::{KubernetesScheduler, NomadScheduler, ProcessScheduler, Scheduler};
 
 use anyhow::bail;
 use arroyo_rpc::grpc::controller_grpc_server::{ControllerGrpc, ControllerGrpcServer};
@@ -9,13 +9,19 @@ use arroyo_rpc::grpc::{
     GrpcOutputSubscription, HeartbeatNodeReq, HeartbeatNodeResp, HeartbeatReq, HeartbeatResp,
     OutputData, RegisterNodeReq, RegisterNodeResp, RegisterWorkerReq, RegisterWorkerResp,
     TaskCheckpointCompletedReq, TaskCheckpointCompletedResp, TaskFailedReq, TaskFailedResp,
-    TaskFinishedReq, TaskFinishedResp, TaskStartedReq, TaskStartedResp, WorkerFinishedReq,
-    WorkerFinishedResp,
+    TaskFinishedReq, TaskFinishedResp, TaskReachedEndReq, TaskReachedEndResp, TaskStartedReq,
+    TaskStartedResp, WorkerFinishedReq, WorkerFinishedResp,
 };
 use arroyo_rpc::grpc::{
-    SinkDataReq, SinkDataResp, TaskCheckpointEventReq, TaskCheckpointEventResp,
+    QueryableStateReq, QueryableStateResp, SinkDataReq, SinkDataResp, TaskCheckpointEventReq,
+    TaskCheckpointEventResp, TaskLoad,
+};
+use arroyo_state::storage::StorageClient;
+use arroyo_types::{
+    from_micros, ports, AutoscalingConfig, DatabaseConfig, JobBounds, JobResources, JobSchedule,
+    NodeId, RestartPolicy, WorkerId, CHECKPOINT_MODE_ENV, MAX_IN_MEMORY_STATE_ENTRIES_ENV,
+    SOURCE_BOUNDS_ENV, STATE_BACKEND_ENV,
 };
-use arroyo_types::{from_micros, ports, DatabaseConfig, NodeId, WorkerId};
 use deadpool_postgres::{ManagerConfig, Pool, RecyclingMethod};
 use lazy_static::lazy_static;
 use object_store::aws::AmazonS3Builder;
@@ -36,19 +42,24 @@ use tokio::sync::mpsc::Sender;
 use tokio_postgres::NoTls;
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
-use tracing::{debug, info, warn};
+use tracing::{debug, error, info, warn};
 
 mod compiler;
 mod job_controller;
 pub mod scheduler;
+mod state_schemas;
 mod states;
 
 include!(concat!(env!("OUT_DIR"), "/controller-sql.rs"));
 
-use types::public::StopMode;
+use types::public::{CheckpointMode, StateBackendType, StopMode};
 
 pub const CHECKPOINTS_TO_KEEP: u32 = 5;
 
+// the postgres advisory lock held by the leader among the controller replicas
+const LEADER_LOCK_ID: i64 = 0x4172_726f;
+const LEADER_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
 lazy_static! {
     static ref ACTIVE_PIPELINES: Gauge = register_gauge!(
         "arroyo_controller_active_pipelines",
@@ -86,7 +97,7 @@ pub async fn get_from_object_store(path: &str) -> anyhow::Result<Vec<u8>> {
     bail!("Unknown protocol for path {}", path);
 }
 
-#[derive(Eq, PartialEq, Clone, Debug)]
+#[derive(PartialEq, Clone, Debug)]
 pub struct JobConfig {
     id: String,
     organization_id: String,
@@ -96,6 +107,67 @@ pub struct JobConfig {
     checkpoint_interval: Duration,
     ttl: Option<Duration>,
     parallelism_overrides: HashMap<String, usize>,
+    state_backend: StateBackendType,
+    max_in_memory_state_entries: Option<u64>,
+    checkpoint_mode: CheckpointMode,
+    // where the job's checkpoints are stored, if not in the default storage
+    checkpoint_url: Option<String>,
+    // the oldest savepoint that has been requested but not yet started
+    requested_savepoint: Option<String>,
+    // the savepoint the job starts from if it has no checkpoints of its own
+    restore_savepoint: Option<String>,
+    restart_policy: RestartPolicy,
+    autoscaling: Option<AutoscalingConfig>,
+    // what the job's subtasks require from the nodes they're scheduled on
+    resources: JobResources,
+    // where the job's sources start and stop reading
+    bounds: JobBounds,
+    // when the job is run, if not continuously
+    schedule: Option<JobSchedule>,
+}
+
+impl JobConfig {
+    // environment variables that configure the workers' state backend for this job
+    pub fn state_backend_env_vars(&self) -> HashMap<String, String> {
+        let backend = match self.state_backend {
+            StateBackendType::memory => arroyo_state::StateBackendType::Memory,
+            StateBackendType::spill => arroyo_state::StateBackendType::Spill,
+        };
+
+        let mut env = HashMap::new();
+        env.insert(STATE_BACKEND_ENV.to_string(), backend.as_str().to_string());
+        if let Some(entries) = self.max_in_memory_state_entries {
+            env.insert(
+                MAX_IN_MEMORY_STATE_ENTRIES_ENV.to_string(),
+                entries.to_string(),
+            );
+        }
+        env
+    }
+
+    // environment variables that configure how the workers take checkpoints for this job
+    pub fn checkpoint_env_vars(&self) -> HashMap<String, String> {
+        let mode = match self.checkpoint_mode {
+            CheckpointMode::aligned => arroyo_state::CheckpointMode::Aligned,
+            CheckpointMode::unaligned => arroyo_state::CheckpointMode::Unaligned,
+        };
+
+        let mut env = HashMap::new();
+        env.insert(CHECKPOINT_MODE_ENV.to_string(), mode.as_str().to_string());
+        env
+    }
+
+    // environment variables that tell the workers' sources where to start and stop reading
+    pub fn bounds_env_vars(&self) -> HashMap<String, String> {
+        let mut env = HashMap::new();
+        if self.bounds != JobBounds::default() {
+            env.insert(
+                SOURCE_BOUNDS_ENV.to_string(),
+                serde_json::to_string(&self.bounds).unwrap(),
+            );
+        }
+        env
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -110,6 +182,15 @@ pub struct JobStatus {
     restarts: i32,
     pipeline_path: Option<String>,
     wasm_path: Option<String>,
+    // while the job is waiting to be restarted after a failure
+    restart_backoff_micros: Option<i64>,
+    next_restart_time: Option<OffsetDateTime>,
+    // the last checkpoint of a run that has finished or stopped, which is kept so that the job
+    // can be restored from it
+    final_checkpoint_epoch: Option<i32>,
+    // the controller managing the job; updates are only applied while it still owns the job, so
+    // that a controller that has lost leadership can't overwrite the new leader's changes
+    controller_id: i64,
 }
 
 impl JobStatus {
@@ -127,15 +208,36 @@ impl JobStatus {
                 &self.pipeline_path,
                 &self.wasm_path,
                 &self.run_id,
+                &self.restart_backoff_micros,
+                &self.next_restart_time,
+                &self.final_checkpoint_epoch,
                 &self.id,
+                &self.controller_id,
             )
             .await
             .map_err(|e| format!("{:?}", e))?;
 
-        if res == 0 {
-            Err("Job status does not exist".to_string())
-        } else {
-            Ok(())
+        if res > 0 {
+            return Ok(());
+        }
+
+        let owner = queries::controller_queries::get_job_controller()
+            .bind(&c, &self.id)
+            .opt()
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+
+        match owner {
+            None => Err("Job status does not exist".to_string()),
+            Some(owner) => {
+                error!(
+                    message = "job has been claimed by another controller, shutting down",
+                    job_id = self.id,
+                    controller_id = self.controller_id,
+                    owner
+                );
+                std::process::exit(1);
+            }
         }
     }
 }
@@ -156,9 +258,15 @@ pub enum RunningMessage {
         subtask_index: u32,
         reason: String,
     },
+    TaskReachedEnd {
+        worker_id: WorkerId,
+        operator_id: String,
+        subtask_index: u32,
+    },
     WorkerHeartbeat {
         worker_id: WorkerId,
         time: Instant,
+        tasks: Vec<TaskLoad>,
     },
     WorkerFinished {
         worker_id: WorkerId,
@@ -190,6 +298,7 @@ pub struct ControllerServer {
     data_txs: Arc<tokio::sync::Mutex<HashMap<String, Vec<Sender<Result<OutputData, Status>>>>>>,
     scheduler: Arc<dyn Scheduler>,
     db: Pool,
+    controller_id: i64,
 }
 
 #[tonic::async_trait]
@@ -229,6 +338,7 @@ impl ControllerGrpc for ControllerServer {
             JobMessage::RunningMessage(RunningMessage::WorkerHeartbeat {
                 worker_id: WorkerId(req.worker_id),
                 time: Instant::now(),
+                tasks: req.tasks,
             }),
         )
         .await?;
@@ -311,6 +421,25 @@ impl ControllerGrpc for ControllerServer {
         Ok(Response::new(TaskFinishedResp {}))
     }
 
+    async fn task_reached_end(
+        &self,
+        request: Request<TaskReachedEndReq>,
+    ) -> Result<Response<TaskReachedEndResp>, Status> {
+        let req = request.into_inner();
+
+        self.send_to_job_queue(
+            &req.job_id,
+            JobMessage::RunningMessage(RunningMessage::TaskReachedEnd {
+                worker_id: WorkerId(req.worker_id),
+                operator_id: req.operator_id,
+                subtask_index: req.operator_subtask as u32,
+            }),
+        )
+        .await?;
+
+        Ok(Response::new(TaskReachedEndResp {}))
+    }
+
     async fn task_failed(
         &self,
         request: Request<TaskFailedReq>,
@@ -415,6 +544,22 @@ impl ControllerGrpc for ControllerServer {
 
         Ok(Response::new(ReceiverStream::new(rx)))
     }
+
+    async fn get_queryable_state(
+        &self,
+        request: Request<QueryableStateReq>,
+    ) -> Result<Response<QueryableStateResp>, Status> {
+        let job_id = request.into_inner().job_id;
+        let jobs = self.job_state.lock().await;
+
+        let Some(sm) = jobs.get(&job_id) else {
+            return Err(Status::not_found(format!("No job with id {}", job_id)));
+        };
+
+        sm.queryable_state()
+            .map(Response::new)
+            .ok_or_else(|| Status::failed_precondition(format!("Job {} is not running", job_id)))
+    }
 }
 
 impl ControllerServer {
@@ -428,6 +573,10 @@ impl ControllerServer {
                 info!("Using nomad scheduler");
                 Arc::new(NomadScheduler::new())
             }
+            Some("kubernetes") => {
+                info!("Using kubernetes scheduler");
+                Arc::new(KubernetesScheduler::new())
+            }
             _ => {
                 info!("Using process scheduler");
                 Arc::new(ProcessScheduler::new())
@@ -465,9 +614,65 @@ impl ControllerServer {
             data_txs: Arc::new(tokio::sync::Mutex::new(HashMap::new())),
             job_state: Arc::new(tokio::sync::Mutex::new(HashMap::new())),
             db: pool,
+            controller_id: 0,
         }
     }
 
+    /// Blocks until this controller holds the leader lock. Leadership is tied to the session of
+    /// the connection that took the lock, so if that connection is lost the process exits rather
+    /// than risk two controllers managing the same jobs.
+    async fn become_leader(&mut self) {
+        let client = self.db.get().await.unwrap();
+
+        let mut waiting = false;
+        while !queries::controller_queries::try_acquire_leadership()
+            .bind(&client, &LEADER_LOCK_ID)
+            .one()
+            .await
+            .unwrap()
+        {
+            if !waiting {
+                info!("Controller waiting to become leader");
+                waiting = true;
+            }
+            tokio::time::sleep(LEADER_CHECK_INTERVAL).await;
+        }
+
+        // ids increase with every election, so jobs claimed by this controller can't be taken
+        // back by a previous leader that hasn't noticed it lost the lock
+        self.controller_id = queries::controller_queries::next_controller_id()
+            .bind(&client)
+            .one()
+            .await
+            .unwrap();
+
+        info!("Controller {} elected leader", self.controller_id);
+
+        queries::controller_queries::claim_jobs()
+            .bind(&client, &self.controller_id)
+            .await
+            .unwrap();
+
+        let controller_id = self.controller_id;
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(LEADER_CHECK_INTERVAL).await;
+                let held = queries::controller_queries::holds_leadership()
+                    .bind(&client, &LEADER_LOCK_ID)
+                    .one()
+                    .await;
+
+                if !matches!(held, Ok(true)) {
+                    error!(
+                        "Controller {} lost leadership ({:?}), shutting down",
+                        controller_id, held
+                    );
+                    std::process::exit(1);
+                }
+            }
+        });
+    }
+
     async fn send_to_job_queue(&self, job_id: &str, msg: JobMessage) -> Result<(), Status> {
         let mut jobs = self.job_state.lock().await;
 
@@ -493,16 +698,36 @@ impl ControllerServer {
         let db = self.db.clone();
         let jobs = Arc::clone(&self.job_state);
         let scheduler = Arc::clone(&self.scheduler);
+        let controller_id = self.controller_id;
 
         tokio::spawn(async move {
             loop {
                 let client = db.get().await.unwrap();
+                // picks up jobs created since the last round
+                queries::controller_queries::claim_jobs()
+                    .bind(&client, &controller_id)
+                    .await
+                    .unwrap();
+
                 let res = queries::controller_queries::all_jobs()
                     .bind(&client)
                     .all()
                     .await
                     .unwrap();
                 for p in res {
+                    match p.controller_id {
+                        Some(id) if id == controller_id => {}
+                        Some(id) if id > controller_id => {
+                            error!(
+                                "Controller {} has been superseded by controller {}, shutting down",
+                                controller_id, id
+                            );
+                            std::process::exit(1);
+                        }
+                        // not claimed yet; it will be on the next round
+                        _ => continue,
+                    }
+
                     let config = JobConfig {
                         id: p.id.clone(),
                         organization_id: p.org_id,
@@ -520,8 +745,85 @@ impl ControllerServer {
                             .into_iter()
                             .map(|(k, v)| (k.clone(), v.as_u64().unwrap() as usize))
                             .collect(),
+                        state_backend: p.state_backend,
+                        max_in_memory_state_entries: p
+                            .max_in_memory_state_entries
+                            .map(|n| n as u64),
+                        checkpoint_mode: p.checkpoint_mode,
+                        checkpoint_url: p.checkpoint_url,
+                        requested_savepoint: p.requested_savepoint,
+                        restore_savepoint: p.restore_savepoint,
+                        restart_policy: p
+                            .restart_policy
+                            .and_then(|policy| match serde_json::from_value(policy) {
+                                Ok(policy) => Some(policy),
+                                Err(e) => {
+                                    warn!(
+                                        message = "invalid restart policy, using the default",
+                                        job_id = p.id,
+                                        error = format!("{:?}", e)
+                                    );
+                                    None
+                                }
+                            })
+                            .unwrap_or_default(),
+                        autoscaling: p
+                            .autoscaling
+                            .and_then(|c| match serde_json::from_value::<AutoscalingConfig>(c) {
+                                Ok(c) => Some(c),
+                                Err(e) => {
+                                    warn!(
+                                        message = "invalid autoscaling config, ignoring it",
+                                        job_id = p.id,
+                                        error = format!("{:?}", e)
+                                    );
+                                    None
+                                }
+                            })
+                            .filter(|c| c.enabled),
+                        resources: p
+                            .resources
+                            .and_then(|r| match serde_json::from_value(r) {
+                                Ok(r) => Some(r),
+                                Err(e) => {
+                                    warn!(
+                                        message = "invalid resource profiles, ignoring them",
+                                        job_id = p.id,
+                                        error = format!("{:?}", e)
+                                    );
+                                    None
+                                }
+                            })
+                            .unwrap_or_default(),
+                        bounds: p
+                            .bounds
+                            .and_then(|b| match serde_json::from_value(b) {
+                                Ok(b) => Some(b),
+                                Err(e) => {
+                                    warn!(
+                                        message = "invalid source bounds, ignoring them",
+                                        job_id = p.id,
+                                        error = format!("{:?}", e)
+                                    );
+                                    None
+                                }
+                            })
+                            .unwrap_or_default(),
+                        schedule: p.schedule.and_then(|s| match serde_json::from_value(s) {
+                            Ok(s) => Some(s),
+                            Err(e) => {
+                                warn!(
+                                    message = "invalid schedule, running the job continuously",
+                                    job_id = p.id,
+                                    error = format!("{:?}", e)
+                                );
+                                None
+                            }
+                        }),
                     };
 
+                    StorageClient::register_job(&config.id, config.checkpoint_url.as_deref());
+
                     let mut jobs = jobs.lock().await;
 
                     let status = JobStatus {
@@ -535,6 +837,10 @@ impl ControllerServer {
                         restarts: p.restarts,
                         pipeline_path: p.pipeline_path,
                         wasm_path: p.wasm_path,
+                        restart_backoff_micros: p.restart_backoff_micros,
+                        next_restart_time: p.next_restart_time,
+                        final_checkpoint_epoch: p.final_checkpoint_epoch,
+                        controller_id,
                     };
 
                     if let Some(sm) = jobs.get_mut(&config.id) {
@@ -552,7 +858,7 @@ impl ControllerServer {
         });
     }
 
-    pub async fn start(self, addr: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn start(mut self, addr: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
         let reflection = tonic_reflection::server::Builder::configure()
             .register_encoded_file_descriptor_set(arroyo_rpc::grpc::API_FILE_DESCRIPTOR_SET)
             .build()?;
@@ -567,6 +873,10 @@ impl ControllerServer {
             shutdown_rx,
         );
 
+        // only the leader manages jobs and accepts requests from workers; the others wait to take
+        // over if it fails, at which point running jobs are adopted
+        self.become_leader().await;
+
         self.start_updater();
 
         arroyo_server_common::grpc_server()
@@ -580,3 +890,65 @@ impl ControllerServer {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use queries::controller_queries::{holds_leadership, try_acquire_leadership};
+
+    async fn connect() -> tokio_postgres::Client {
+        let config = DatabaseConfig::load();
+        let (client, connection) = tokio_postgres::Config::new()
+            .dbname(&config.name)
+            .host(&config.host)
+            .port(config.port)
+            .user(&config.user)
+            .password(&config.password)
+            .connect(NoTls)
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+        client
+    }
+
+    #[tokio::test]
+    async fn test_holds_leadership() {
+        // postgres stores the high bits of the key in classid, so use an id that has some
+        let lock_id = LEADER_LOCK_ID | (1 << 40);
+        let leader = connect().await;
+        let follower = connect().await;
+
+        assert!(!holds_leadership()
+            .bind(&leader, &lock_id)
+            .one()
+            .await
+            .unwrap());
+
+        assert!(try_acquire_leadership()
+            .bind(&leader, &lock_id)
+            .one()
+            .await
+            .unwrap());
+        assert!(holds_leadership()
+            .bind(&leader, &lock_id)
+            .one()
+            .await
+            .unwrap());
+        assert!(!holds_leadership()
+            .bind(&leader, &LEADER_LOCK_ID)
+            .one()
+            .await
+            .unwrap());
+
+        assert!(!try_acquire_leadership()
+            .bind(&follower, &lock_id)
+            .one()
+            .await
+            .unwrap());
+        assert!(!holds_leadership()
+            .bind(&follower, &lock_id)
+            .one()
+            .await
+            .unwrap());
+    }
+}
@@ -4,7 +4,7 @@ use arroyo_rpc::grpc::{
     HeartbeatNodeReq, RegisterNodeReq, StartWorkerReq, StopWorkerReq, WorkerFinishedReq,
 };
 use arroyo_types::{
-    NodeId, WorkerId, CONTROLLER_ADDR_ENV, JOB_ID_ENV, NODE_ID_ENV, NOMAD_DC_ENV,
+    NodeId, ResourceProfile, WorkerId, CONTROLLER_ADDR_ENV, JOB_ID_ENV, NODE_ID_ENV, NOMAD_DC_ENV,
     NOMAD_ENDPOINT_ENV, RUN_ID_ENV, TASK_SLOTS_ENV, WORKER_ID_ENV,
 };
 use lazy_static::lazy_static;
@@ -12,6 +12,7 @@ use prometheus::{register_gauge, Gauge};
 use rand::Rng;
 use serde_json::{json, Value};
 use std::collections::HashMap;
+use std::ops::Range;
 use std::os::unix::prelude::PermissionsExt;
 use std::path::PathBuf;
 use std::str::FromStr;
@@ -25,6 +26,10 @@ use tracing::{info, warn};
 
 use crate::get_from_object_store;
 
+pub use self::kubernetes::KubernetesScheduler;
+
+mod kubernetes;
+
 lazy_static! {
     static ref FREE_SLOTS: Gauge =
         register_gauge!("arroyo_controller_free_slots", "number of free task slots").unwrap();
@@ -101,9 +106,29 @@ pub struct StartPipelineReq {
     pub hash: String,
     pub run_id: i64,
     pub slots: usize,
+    // what each slot requires, in the order that tasks are assigned to the workers' slots
+    pub slot_resources: Vec<ResourceProfile>,
     pub env_vars: HashMap<String, String>,
 }
 
+impl StartPipelineReq {
+    /// The resources required by the slots in the range
+    pub fn resources(&self, slots: Range<usize>) -> ResourceProfile {
+        self.slot_resources
+            .iter()
+            .skip(slots.start)
+            .take(slots.len())
+            .fold(ResourceProfile::default(), |acc, r| acc.add(r))
+    }
+}
+
+// ids for the workers started for a job, which increase in the order that their slots were
+// scheduled so that tasks are assigned to the slots in that order
+fn worker_ids(count: usize) -> Vec<u64> {
+    let base: u32 = rand::thread_rng().gen_range(0..u32::MAX - count as u32);
+    (0..count).map(|i| base as u64 + i as u64).collect()
+}
+
 #[async_trait::async_trait]
 impl Scheduler for ProcessScheduler {
     async fn start_workers(
@@ -117,9 +142,14 @@ impl Scheduler for ProcessScheduler {
         let pipeline = get_from_object_store(&start_pipeline_req.pipeline_path)
             .await
             .unwrap();
-        let wasm = get_from_object_store(&start_pipeline_req.wasm_path)
-            .await
-            .unwrap();
+        // interpreted pipelines don't have any wasm functions
+        let wasm = if start_pipeline_req.wasm_path.is_empty() {
+            vec![]
+        } else {
+            get_from_object_store(&start_pipeline_req.wasm_path)
+                .await
+                .unwrap()
+        };
         let base_path = PathBuf::from_str(&format!(
             "/tmp/arroyo-process/{}",
             start_pipeline_req.job_id
@@ -239,30 +269,35 @@ impl Scheduler for ProcessScheduler {
 struct NodeStatus {
     id: NodeId,
     free_slots: usize,
-    scheduled_slots: HashMap<WorkerId, usize>,
+    capacity: ResourceProfile,
+    free_resources: ResourceProfile,
+    scheduled_slots: HashMap<WorkerId, (usize, ResourceProfile)>,
     addr: String,
     last_heartbeat: Instant,
 }
 
 impl NodeStatus {
-    fn new(id: NodeId, slots: usize, addr: String) -> NodeStatus {
+    fn new(id: NodeId, slots: usize, capacity: ResourceProfile, addr: String) -> NodeStatus {
         FREE_SLOTS.add(slots as f64);
         REGISTERED_SLOTS.add(slots as f64);
 
         NodeStatus {
             id,
             free_slots: slots,
+            capacity,
+            free_resources: capacity,
             scheduled_slots: HashMap::new(),
             addr,
             last_heartbeat: Instant::now(),
         }
     }
 
-    fn take_slots(&mut self, worker: WorkerId, slots: usize) {
+    fn take_slots(&mut self, worker: WorkerId, slots: usize, resources: ResourceProfile) {
         if let Some(v) = self.free_slots.checked_sub(slots) {
             FREE_SLOTS.sub(slots as f64);
             self.free_slots = v;
-            self.scheduled_slots.insert(worker, slots);
+            self.free_resources = self.free_resources.sub(&resources);
+            self.scheduled_slots.insert(worker, (slots, resources));
         } else {
             panic!(
                 "Attempted to schedule more slots than are available on node {} ({} < {})",
@@ -272,12 +307,13 @@ impl NodeStatus {
     }
 
     fn release_slots(&mut self, worker_id: WorkerId, slots: usize) {
-        if let Some(freed) = self.scheduled_slots.remove(&worker_id) {
+        if let Some((freed, resources)) = self.scheduled_slots.remove(&worker_id) {
             assert_eq!(freed, slots,
                 "Controller and node disagree about how many slots are scheduled for worker {:?} ({} != {})",
                 worker_id, freed, slots);
 
             self.free_slots += slots;
+            self.free_resources = self.free_resources.add(&resources);
 
             FREE_SLOTS.add(slots as f64);
         } else {
@@ -289,6 +325,82 @@ impl NodeStatus {
     }
 }
 
+// the slots and resources of a node that are available to a job
+#[derive(Debug, Clone)]
+struct NodeCapacity {
+    id: NodeId,
+    slots: usize,
+    // zero in a dimension if the node doesn't limit it
+    capacity: ResourceProfile,
+    free: ResourceProfile,
+}
+
+impl NodeCapacity {
+    // how many of the slots, starting from the first, fit on this node
+    fn slots_that_fit(&self, slots: &[ResourceProfile]) -> usize {
+        let mut used = ResourceProfile::default();
+        let mut count = 0;
+        for slot in slots.iter().take(self.slots) {
+            let next = used.add(slot);
+            if !self.fits(&next) {
+                break;
+            }
+            used = next;
+            count += 1;
+        }
+        count
+    }
+
+    fn fits(&self, required: &ResourceProfile) -> bool {
+        (self.capacity.cpu_millis == 0 || required.cpu_millis <= self.free.cpu_millis)
+            && (self.capacity.memory_mb == 0 || required.memory_mb <= self.free.memory_mb)
+    }
+
+    fn take(&mut self, slots: usize, resources: &ResourceProfile) {
+        self.slots -= slots;
+        self.free = self.free.sub(resources);
+    }
+}
+
+/// Packs a job's slots onto the nodes, in order, each time filling the node with the most free
+/// slots that fits the next slot. Returns the node and number of slots of each worker to start,
+/// in slot order, or the number of slots that couldn't be placed and why.
+fn pack_slots(
+    mut nodes: Vec<NodeCapacity>,
+    slots: &[ResourceProfile],
+) -> Result<Vec<(NodeId, usize)>, (usize, String)> {
+    nodes.sort_by_key(|n| n.id.0);
+
+    let mut workers = vec![];
+    let mut next = 0;
+    while next < slots.len() {
+        let Some(node) = nodes
+            .iter_mut()
+            .filter(|n| n.slots_that_fit(&slots[next..]) > 0)
+            .max_by_key(|n| (n.slots, n.free.memory_mb, n.free.cpu_millis))
+        else {
+            return Err((
+                slots.len() - next,
+                format!(
+                    "slot {} requires {}, which no node has available",
+                    next, slots[next]
+                ),
+            ));
+        };
+
+        let count = node.slots_that_fit(&slots[next..]);
+        let resources = slots[next..next + count]
+            .iter()
+            .fold(ResourceProfile::default(), |acc, r| acc.add(r));
+        node.take(count, &resources);
+
+        workers.push((node.id, count));
+        next += count;
+    }
+
+    Ok(workers)
+}
+
 struct NodeWorker {
     job_id: String,
     node_id: NodeId,
@@ -326,6 +438,8 @@ pub struct NodeScheduler {
 
 pub enum SchedulerError {
     NotEnoughSlots { slots_needed: usize },
+    // the job can't be scheduled even on an otherwise empty cluster
+    Unschedulable(String),
     Other(String),
 }
 
@@ -343,9 +457,14 @@ impl Scheduler for NodeScheduler {
         let mut state = self.state.lock().await;
         if let std::collections::hash_map::Entry::Vacant(e) = state.nodes.entry(NodeId(req.node_id))
         {
+            let capacity = req
+                .resources
+                .map(|r| ResourceProfile::new(r.cpu_millis, r.memory_mb))
+                .unwrap_or_default();
             e.insert(NodeStatus::new(
                 NodeId(req.node_id),
                 req.task_slots as usize,
+                capacity,
                 req.addr,
             ));
         }
@@ -406,47 +525,89 @@ impl Scheduler for NodeScheduler {
         let binary = get_from_object_store(&start_pipeline_req.pipeline_path)
             .await
             .unwrap();
-        let wasm = get_from_object_store(&start_pipeline_req.wasm_path)
-            .await
-            .unwrap();
+        // interpreted pipelines don't have any wasm functions
+        let wasm = if start_pipeline_req.wasm_path.is_empty() {
+            vec![]
+        } else {
+            get_from_object_store(&start_pipeline_req.wasm_path)
+                .await
+                .unwrap()
+        };
 
         // TODO: make this locking more fine-grained
         let mut state = self.state.lock().await;
 
         state.expire_nodes(Instant::now() - Duration::from_secs(30));
 
-        let free_slots = state.nodes.values().map(|n| n.free_slots).sum::<usize>();
-        let slots = start_pipeline_req.slots as usize;
-        if slots > free_slots {
+        if state.nodes.is_empty() {
             return Err(SchedulerError::NotEnoughSlots {
-                slots_needed: slots - free_slots,
+                slots_needed: start_pipeline_req.slots,
             });
         }
 
-        let mut to_schedule = slots;
-        let mut slots_assigned = vec![];
-        while to_schedule > 0 {
-            // find the node with the most free slots and fill it
-            let node = {
-                if let Some(status) = state
-                    .nodes
-                    .values()
-                    .filter(|n| {
-                        n.free_slots > 0 && n.last_heartbeat.elapsed() < Duration::from_secs(30)
-                    })
-                    .max_by_key(|n| n.free_slots)
-                    .cloned()
-                {
-                    status
-                } else {
-                    unreachable!();
+        let slot_resources: Vec<_> = (0..start_pipeline_req.slots)
+            .map(|i| {
+                start_pipeline_req
+                    .slot_resources
+                    .get(i)
+                    .copied()
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        // fail fast if the job wouldn't fit even if nothing else were running
+        let total_capacity = state
+            .nodes
+            .values()
+            .map(|n| NodeCapacity {
+                id: n.id,
+                slots: n.free_slots + n.scheduled_slots.values().map(|(s, _)| s).sum::<usize>(),
+                capacity: n.capacity,
+                free: n.capacity,
+            })
+            .collect();
+        if let Err((_, reason)) = pack_slots(total_capacity, &slot_resources) {
+            return Err(SchedulerError::Unschedulable(format!(
+                "the job's {} slots don't fit on the cluster's {} nodes: {}",
+                start_pipeline_req.slots,
+                state.nodes.len(),
+                reason
+            )));
+        }
+
+        let free_capacity = state
+            .nodes
+            .values()
+            .map(|n| NodeCapacity {
+                id: n.id,
+                slots: n.free_slots,
+                capacity: n.capacity,
+                free: n.free_resources,
+            })
+            .collect();
+        let placements =
+            pack_slots(free_capacity, &slot_resources).map_err(|(slots, reason)| {
+                info!(
+                    message = "not enough free resources to schedule job",
+                    job_id = start_pipeline_req.job_id,
+                    reason
+                );
+                SchedulerError::NotEnoughSlots {
+                    slots_needed: slots,
                 }
-            };
+            })?;
+
+        let mut slots_assigned = vec![];
+        let mut next_slot = 0;
+        let worker_ids = worker_ids(placements.len());
+        for ((node_id, slots_for_this_one), worker_id) in placements.into_iter().zip(worker_ids) {
+            let node = state.nodes.get(&node_id).unwrap().clone();
+            let resources = start_pipeline_req.resources(next_slot..next_slot + slots_for_this_one);
+            next_slot += slots_for_this_one;
 
-            let slots_for_this_one = node.free_slots.min(to_schedule);
             info!(
-                "Scheduling {} slots on node {}",
-                slots_for_this_one, node.addr
+                "Scheduling {} slots ({}) on node {}",
+                slots_for_this_one, resources, node.addr
             );
 
             let mut client = NodeGrpcClient::connect(format!("http://{}", node.addr))
@@ -480,6 +641,7 @@ impl Scheduler for NodeScheduler {
                     node_id: node.id.0,
                     run_id: start_pipeline_req.run_id as u64,
                     env_vars: start_pipeline_req.env_vars.clone(),
+                    worker_id,
                 }))
                 .await
                 .map_err(|e| {
@@ -500,11 +662,11 @@ impl Scheduler for NodeScheduler {
                 })?
                 .into_inner();
 
-            state
-                .nodes
-                .get_mut(&node.id)
-                .unwrap()
-                .take_slots(WorkerId(res.worker_id), slots_for_this_one);
+            state.nodes.get_mut(&node.id).unwrap().take_slots(
+                WorkerId(res.worker_id),
+                slots_for_this_one,
+                resources,
+            );
 
             state.workers.insert(
                 WorkerId(res.worker_id),
@@ -515,8 +677,6 @@ impl Scheduler for NodeScheduler {
             );
 
             slots_assigned.push((node.id, WorkerId(res.worker_id), slots_for_this_one));
-
-            to_schedule -= slots_for_this_one;
         }
         Ok(())
     }
@@ -588,10 +748,10 @@ impl Scheduler for NomadScheduler {
         let workers = (slots as f32 / SLOTS_PER_NOMAD_NODE as f32).ceil() as usize;
         let mut slots_scheduled = 0;
 
-        for _ in 0..workers {
+        for worker_id in worker_ids(workers) {
             let slots_here = (slots - slots_scheduled).min(SLOTS_PER_NOMAD_NODE);
-
-            let worker_id: u32 = rand::thread_rng().gen();
+            let resources =
+                start_pipeline_req.resources(slots_scheduled..slots_scheduled + slots_here);
 
             slots_scheduled += slots_here;
 
@@ -652,7 +812,8 @@ impl Scheduler for NomadScheduler {
                                     "Env": env_vars,
                                     "Resources": {
                                         "CPU": CPU_PER_SLOT_MHZ * slots_here,
-                                        "MemoryMB": MEMORY_PER_SLOT_MB * slots_here,
+                                        "MemoryMB": (MEMORY_PER_SLOT_MB * slots_here)
+                                            .max(resources.memory_mb as usize),
                                     }
                                 }
                             ],
@@ -766,3 +927,50 @@ impl Scheduler for NomadScheduler {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: u64, slots: usize, memory_mb: u64) -> NodeCapacity {
+        let capacity = ResourceProfile::new(0, memory_mb);
+        NodeCapacity {
+            id: NodeId(id),
+            slots,
+            capacity,
+            free: capacity,
+        }
+    }
+
+    #[test]
+    fn test_pack_slots_by_count() {
+        let workers = pack_slots(
+            vec![node(1, 4, 0), node(2, 2, 0)],
+            &[ResourceProfile::default(); 5],
+        )
+        .unwrap();
+
+        assert_eq!(workers, vec![(NodeId(1), 4), (NodeId(2), 1)]);
+    }
+
+    #[test]
+    fn test_pack_slots_by_memory() {
+        // the first slot runs a subtask of every operator, so it needs the most memory
+        let mut slots = vec![ResourceProfile::new(0, 600)];
+        slots.extend([ResourceProfile::new(0, 100); 7]);
+
+        let workers = pack_slots(vec![node(1, 8, 1000), node(2, 8, 500)], &slots).unwrap();
+
+        assert_eq!(workers, vec![(NodeId(1), 5), (NodeId(2), 3)]);
+    }
+
+    #[test]
+    fn test_pack_slots_that_dont_fit() {
+        let slots = [ResourceProfile::new(0, 2000), ResourceProfile::new(0, 100)];
+
+        let (unplaced, reason) = pack_slots(vec![node(1, 8, 1000)], &slots).unwrap_err();
+
+        assert_eq!(unplaced, 2);
+        assert!(reason.contains("slot 0"), "{}", reason);
+    }
+}
@@ -0,0 +1,669 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail};
+use arroyo_rpc::grpc::{HeartbeatNodeReq, RegisterNodeReq, StopWorkerReq, WorkerFinishedReq};
+use arroyo_state::storage::STORAGE_CREDENTIAL_ENV;
+use arroyo_types::{
+    ResourceProfile, WorkerId, CONTROLLER_ADDR_ENV, JOB_ID_ENV, K8S_API_URL_ENV, K8S_NAMESPACE_ENV,
+    K8S_WORKER_CPU_PER_SLOT_ENV, K8S_WORKER_IMAGE_ENV, K8S_WORKER_LABELS_ENV,
+    K8S_WORKER_MEMORY_PER_SLOT_ENV, K8S_WORKER_SECRET_ENV, K8S_WORKER_SERVICE_ACCOUNT_ENV,
+    K8S_WORKER_SLOTS_ENV, NODE_ID_ENV, RUN_ID_ENV, TASK_SLOTS_ENV, WORKER_ID_ENV,
+};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use tonic::Status;
+use tracing::{info, warn};
+
+use super::{worker_ids, Scheduler, SchedulerError, StartPipelineReq};
+
+const SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+const JOB_ID_LABEL: &str = "arroyo.dev/job-id";
+const WORKER_ID_LABEL: &str = "arroyo.dev/worker-id";
+const RUN_ID_LABEL: &str = "arroyo.dev/run-id";
+
+// where the worker image finds the pipeline binary and wasm functions to run
+const PIPELINE_PATH_ENV: &str = "PIPELINE_PATH";
+const WASM_PATH_ENV: &str = "WASM_PATH";
+
+#[derive(Debug, Clone)]
+pub struct KubernetesConfig {
+    pub api_url: String,
+    pub token: Option<String>,
+    pub namespace: String,
+    pub image: String,
+    pub service_account: Option<String>,
+    // a Secret holding storage credentials, keyed by the environment variables they're read from
+    pub secret: Option<String>,
+    // added to every worker pod, along with the labels that identify its job and worker
+    pub labels: HashMap<String, String>,
+    pub slots_per_pod: usize,
+    pub cpu_millis_per_slot: usize,
+    pub memory_mb_per_slot: usize,
+}
+
+impl KubernetesConfig {
+    /// Loads the config from the environment, using the pod's service account to talk to the
+    /// API server when running in the cluster
+    pub fn from_env() -> Self {
+        let api_url = std::env::var(K8S_API_URL_ENV).unwrap_or_else(|_| {
+            let host = std::env::var("KUBERNETES_SERVICE_HOST")
+                .unwrap_or_else(|_| "kubernetes.default.svc".to_string());
+            let port =
+                std::env::var("KUBERNETES_SERVICE_PORT").unwrap_or_else(|_| "443".to_string());
+            format!("https://{}:{}", host, port)
+        });
+
+        let namespace = std::env::var(K8S_NAMESPACE_ENV)
+            .ok()
+            .or_else(|| std::fs::read_to_string(format!("{}/namespace", SERVICE_ACCOUNT_DIR)).ok())
+            .map(|ns| ns.trim().to_string())
+            .unwrap_or_else(|| "default".to_string());
+
+        let labels = std::env::var(K8S_WORKER_LABELS_ENV)
+            .map(|labels| {
+                labels
+                    .split(',')
+                    .filter(|l| !l.trim().is_empty())
+                    .map(|l| {
+                        let (k, v) = l.split_once('=').unwrap_or_else(|| {
+                            panic!("invalid label '{}' in {}", l, K8S_WORKER_LABELS_ENV)
+                        });
+                        (k.trim().to_string(), v.trim().to_string())
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let number = |var: &str, default: usize| {
+            std::env::var(var)
+                .map(|v| {
+                    usize::from_str(&v)
+                        .unwrap_or_else(|_| panic!("{} must be a number, was '{}'", var, v))
+                })
+                .unwrap_or(default)
+        };
+
+        Self {
+            api_url,
+            token: std::fs::read_to_string(format!("{}/token", SERVICE_ACCOUNT_DIR))
+                .ok()
+                .map(|t| t.trim().to_string()),
+            namespace,
+            image: std::env::var(K8S_WORKER_IMAGE_ENV).unwrap_or_else(|_| {
+                panic!(
+                    "{} must be set to use the kubernetes scheduler",
+                    K8S_WORKER_IMAGE_ENV
+                )
+            }),
+            service_account: std::env::var(K8S_WORKER_SERVICE_ACCOUNT_ENV).ok(),
+            secret: std::env::var(K8S_WORKER_SECRET_ENV).ok(),
+            labels,
+            slots_per_pod: number(K8S_WORKER_SLOTS_ENV, 16).max(1),
+            cpu_millis_per_slot: number(K8S_WORKER_CPU_PER_SLOT_ENV, 900),
+            memory_mb_per_slot: number(K8S_WORKER_MEMORY_PER_SLOT_ENV, 500),
+        }
+    }
+}
+
+/// This Scheduler runs each worker as a pod in a Kubernetes cluster. The pods are labeled with
+/// their job and worker ids, which is how they are found again to stop them.
+pub struct KubernetesScheduler {
+    client: reqwest::Client,
+    config: KubernetesConfig,
+}
+
+impl KubernetesScheduler {
+    pub fn new() -> Self {
+        let mut builder = reqwest::Client::builder();
+        if let Ok(ca) = std::fs::read(format!("{}/ca.crt", SERVICE_ACCOUNT_DIR)) {
+            builder = builder.add_root_certificate(
+                reqwest::Certificate::from_pem(&ca).expect("invalid kubernetes CA certificate"),
+            );
+        }
+
+        Self::with_config(
+            KubernetesConfig::from_env(),
+            builder.build().expect("failed to build kubernetes client"),
+        )
+    }
+
+    pub fn with_config(config: KubernetesConfig, client: reqwest::Client) -> Self {
+        Self { client, config }
+    }
+
+    fn pods_url(&self) -> String {
+        format!(
+            "{}/api/v1/namespaces/{}/pods",
+            self.config.api_url, self.config.namespace
+        )
+    }
+
+    fn pod_name(job_id: &str, worker_id: u64) -> String {
+        format!("arroyo-worker-{}-{}", job_id, worker_id).to_lowercase()
+    }
+
+    fn request(&self, builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.config.token {
+            Some(token) => builder.bearer_auth(token),
+            None => builder,
+        }
+    }
+
+    fn pod(
+        &self,
+        req: &StartPipelineReq,
+        worker_id: u64,
+        slots: usize,
+        required: ResourceProfile,
+    ) -> Value {
+        let mut labels = self.config.labels.clone();
+        labels.insert(JOB_ID_LABEL.to_string(), req.job_id.clone());
+        labels.insert(WORKER_ID_LABEL.to_string(), worker_id.to_string());
+        labels.insert(RUN_ID_LABEL.to_string(), req.run_id.to_string());
+
+        let mut env_vars = HashMap::new();
+        env_vars.insert("RUST_LOG".to_string(), "info".to_string());
+        env_vars.insert("PROD".to_string(), "true".to_string());
+        env_vars.insert(TASK_SLOTS_ENV.to_string(), slots.to_string());
+        env_vars.insert(WORKER_ID_ENV.to_string(), worker_id.to_string());
+        env_vars.insert(NODE_ID_ENV.to_string(), "1".to_string());
+        env_vars.insert(JOB_ID_ENV.to_string(), req.job_id.clone());
+        env_vars.insert(RUN_ID_ENV.to_string(), req.run_id.to_string());
+        env_vars.insert(
+            CONTROLLER_ADDR_ENV.to_string(),
+            std::env::var(CONTROLLER_ADDR_ENV).unwrap_or_else(|_| "".to_string()),
+        );
+        env_vars.insert(PIPELINE_PATH_ENV.to_string(), req.pipeline_path.clone());
+        env_vars.insert(WASM_PATH_ENV.to_string(), req.wasm_path.clone());
+        for (key, value) in req.env_vars.iter() {
+            env_vars.insert(key.to_string(), value.to_string());
+        }
+
+        let mut env: Vec<Value> = env_vars
+            .into_iter()
+            .map(|(name, value)| json!({"name": name, "value": value}))
+            .collect();
+        // credentials are referenced from the Secret rather than copied into the pod spec; without
+        // one, workers rely on their service account's workload identity
+        if let Some(secret) = &self.config.secret {
+            env.extend(STORAGE_CREDENTIAL_ENV.iter().map(|name| {
+                json!({
+                    "name": name,
+                    "valueFrom": {
+                        "secretKeyRef": {"name": secret, "key": name, "optional": true},
+                    },
+                })
+            }));
+        }
+        env.sort_by_key(|e| e["name"].as_str().unwrap().to_string());
+
+        // pods get at least the configured resources for each slot, or more if the job needs it
+        let cpu_millis =
+            (self.config.cpu_millis_per_slot * slots).max(required.cpu_millis as usize);
+        let memory_mb = (self.config.memory_mb_per_slot * slots).max(required.memory_mb as usize);
+        let resources = json!({
+            "cpu": format!("{}m", cpu_millis),
+            "memory": format!("{}Mi", memory_mb),
+        });
+
+        let mut spec = json!({
+            // the controller takes care of handling failures
+            "restartPolicy": "Never",
+            "containers": [{
+                "name": "worker",
+                "image": self.config.image,
+                "env": env,
+                "resources": {
+                    "requests": resources,
+                    "limits": resources,
+                },
+            }],
+        });
+
+        if let Some(service_account) = &self.config.service_account {
+            spec["serviceAccountName"] = json!(service_account);
+        }
+
+        json!({
+            "apiVersion": "v1",
+            "kind": "Pod",
+            "metadata": {
+                "name": Self::pod_name(&req.job_id, worker_id),
+                "labels": labels,
+                "annotations": {
+                    "arroyo.dev/job-name": req.name,
+                    "arroyo.dev/job-hash": req.hash,
+                },
+            },
+            "spec": spec,
+        })
+    }
+}
+
+async fn error_body(resp: reqwest::Response) -> String {
+    let status = resp.status();
+    match resp.bytes().await {
+        Ok(bytes) => format!("{}: {}", status, String::from_utf8_lossy(&bytes)),
+        Err(e) => format!("{}: {:?}", status, e),
+    }
+}
+
+#[async_trait::async_trait]
+impl Scheduler for KubernetesScheduler {
+    async fn start_workers(
+        &self,
+        start_pipeline_req: StartPipelineReq,
+    ) -> Result<(), SchedulerError> {
+        let slots = start_pipeline_req.slots;
+        let slots_per_pod = self.config.slots_per_pod;
+        let workers = slots.div_ceil(slots_per_pod);
+        let mut slots_scheduled = 0;
+
+        for worker_id in worker_ids(workers) {
+            let slots_here = (slots - slots_scheduled).min(slots_per_pod);
+            let required =
+                start_pipeline_req.resources(slots_scheduled..slots_scheduled + slots_here);
+            slots_scheduled += slots_here;
+
+            let pod = self.pod(&start_pipeline_req, worker_id, slots_here, required);
+
+            let resp = self
+                .request(self.client.post(self.pods_url()))
+                .json(&pod)
+                .send()
+                .await
+                .map_err(|e| SchedulerError::Other(format!("{:?}", e)))?;
+
+            if !resp.status().is_success() {
+                return Err(SchedulerError::Other(format!(
+                    "Error creating worker pod: {}",
+                    error_body(resp).await
+                )));
+            }
+
+            info!(
+                message = "started worker pod",
+                job_id = start_pipeline_req.job_id,
+                worker_id,
+                slots = slots_here
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn register_node(&self, _req: RegisterNodeReq) {
+        // ignore
+    }
+
+    async fn heartbeat_node(&self, _req: HeartbeatNodeReq) -> Result<(), Status> {
+        // ignore
+        Ok(())
+    }
+
+    async fn worker_finished(&self, _req: WorkerFinishedReq) {
+        // ignore
+    }
+
+    async fn workers_for_job(&self, job_id: &str) -> anyhow::Result<Vec<WorkerId>> {
+        let resp = self
+            .request(self.client.get(self.pods_url()))
+            .query(&[("labelSelector", format!("{}={}", JOB_ID_LABEL, job_id))])
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            bail!("failed to list worker pods: {}", error_body(resp).await);
+        }
+
+        let resp: Value = resp.json().await?;
+        let pods = resp
+            .get("items")
+            .and_then(|items| items.as_array())
+            .ok_or_else(|| anyhow!("invalid response from kubernetes api"))?;
+
+        let mut workers = vec![];
+        for pod in pods {
+            let phase = pod.pointer("/status/phase").and_then(|p| p.as_str());
+            if matches!(phase, Some("Succeeded") | Some("Failed"))
+                || pod.pointer("/metadata/deletionTimestamp").is_some()
+            {
+                continue;
+            }
+
+            let worker_id = pod
+                .pointer(&format!(
+                    "/metadata/labels/{}",
+                    WORKER_ID_LABEL.replace('/', "~1")
+                ))
+                .and_then(|id| id.as_str())
+                .ok_or_else(|| anyhow!("worker pod is missing its worker id label"))?;
+
+            workers.push(WorkerId(u64::from_str(worker_id)?));
+        }
+
+        Ok(workers)
+    }
+
+    async fn stop_worker(&self, req: StopWorkerReq) -> anyhow::Result<()> {
+        info!(
+            message = "stopping worker",
+            job_id = req.job_id,
+            worker_id = req.worker_id
+        );
+
+        let mut delete = self.client.delete(format!(
+            "{}/{}",
+            self.pods_url(),
+            Self::pod_name(&req.job_id, req.worker_id)
+        ));
+        if req.force {
+            delete = delete.query(&[("gracePeriodSeconds", "0")]);
+        }
+
+        let resp = self.request(delete).send().await?;
+
+        if !resp.status().is_success() && resp.status() != StatusCode::NOT_FOUND {
+            warn!(
+                message = "failed to stop worker",
+                job_id = req.job_id,
+                worker_id = req.worker_id,
+                status = resp.status().as_u16()
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn clean_cluster(&self, job_id: &str) -> anyhow::Result<()> {
+        // this also removes the pods of workers that have already exited
+        let resp = self
+            .request(self.client.delete(self.pods_url()))
+            .query(&[
+                ("labelSelector", format!("{}={}", JOB_ID_LABEL, job_id)),
+                ("gracePeriodSeconds", "0".to_string()),
+            ])
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            bail!("failed to delete worker pods: {}", error_body(resp).await);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::{Path, Query, State};
+    use axum::routing::{delete, get};
+    use axum::{Json, Router};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    type Pods = Arc<Mutex<Vec<Value>>>;
+
+    fn matches_selector(pod: &Value, query: &HashMap<String, String>) -> bool {
+        query
+            .get("labelSelector")
+            .map(|selector| {
+                selector.split(',').all(|s| {
+                    let (k, v) = s.split_once('=').unwrap();
+                    pod["metadata"]["labels"][k].as_str() == Some(v)
+                })
+            })
+            .unwrap_or(true)
+    }
+
+    async fn list_pods(
+        State(pods): State<Pods>,
+        Query(query): Query<HashMap<String, String>>,
+    ) -> Json<Value> {
+        let items: Vec<Value> = pods
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|p| matches_selector(p, &query))
+            .cloned()
+            .collect();
+        Json(json!({ "kind": "PodList", "items": items }))
+    }
+
+    async fn create_pod(
+        State(pods): State<Pods>,
+        Json(pod): Json<Value>,
+    ) -> (axum::http::StatusCode, Json<Value>) {
+        pods.lock().unwrap().push(pod.clone());
+        (axum::http::StatusCode::CREATED, Json(pod))
+    }
+
+    async fn delete_pods(
+        State(pods): State<Pods>,
+        Query(query): Query<HashMap<String, String>>,
+    ) -> Json<Value> {
+        pods.lock()
+            .unwrap()
+            .retain(|p| !matches_selector(p, &query));
+        Json(json!({ "kind": "Status", "status": "Success" }))
+    }
+
+    async fn delete_pod(
+        State(pods): State<Pods>,
+        Path((_, name)): Path<(String, String)>,
+    ) -> axum::http::StatusCode {
+        let mut pods = pods.lock().unwrap();
+        let before = pods.len();
+        pods.retain(|p| p["metadata"]["name"].as_str() != Some(&name));
+        if pods.len() < before {
+            axum::http::StatusCode::OK
+        } else {
+            axum::http::StatusCode::NOT_FOUND
+        }
+    }
+
+    // a minimal stand-in for the pod endpoints of the kubernetes API server
+    fn mock_api_server() -> (String, Pods) {
+        let pods: Pods = Arc::new(Mutex::new(vec![]));
+
+        let app = Router::new()
+            .route(
+                "/api/v1/namespaces/:namespace/pods",
+                get(list_pods).post(create_pod).delete(delete_pods),
+            )
+            .route(
+                "/api/v1/namespaces/:namespace/pods/:name",
+                delete(delete_pod),
+            )
+            .with_state(pods.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        (format!("http://{}", addr), pods)
+    }
+
+    fn scheduler(api_url: String) -> KubernetesScheduler {
+        KubernetesScheduler::with_config(
+            KubernetesConfig {
+                api_url,
+                token: None,
+                namespace: "arroyo".to_string(),
+                image: "arroyo-worker:test".to_string(),
+                service_account: Some("arroyo-worker".to_string()),
+                secret: Some("arroyo-storage".to_string()),
+                labels: [("team".to_string(), "streaming".to_string())]
+                    .into_iter()
+                    .collect(),
+                slots_per_pod: 4,
+                cpu_millis_per_slot: 500,
+                memory_mb_per_slot: 256,
+            },
+            reqwest::Client::new(),
+        )
+    }
+
+    fn start_req(job_id: &str, slots: usize) -> StartPipelineReq {
+        StartPipelineReq {
+            name: "my_pipeline".to_string(),
+            pipeline_path: "s3://bucket.s3-us-east-1.amazonaws.com/pipeline".to_string(),
+            wasm_path: "".to_string(),
+            job_id: job_id.to_string(),
+            hash: "abc".to_string(),
+            run_id: 3,
+            slots,
+            slot_resources: (0..slots)
+                .map(|i| ResourceProfile::new(100, if i == 0 { 2048 } else { 128 }))
+                .collect(),
+            env_vars: [("STATE_BACKEND".to_string(), "spill".to_string())]
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    fn env(pod: &Value, name: &str) -> Option<String> {
+        pod["spec"]["containers"][0]["env"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|e| e["name"] == name)
+            .map(|e| e["value"].as_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn test_start_workers_creates_pods() {
+        let (url, pods) = mock_api_server();
+        let scheduler = scheduler(url);
+
+        assert!(scheduler.start_workers(start_req("job1", 10)).await.is_ok());
+
+        let pods = pods.lock().unwrap().clone();
+        assert_eq!(pods.len(), 3);
+
+        let mut slots: Vec<String> = pods
+            .iter()
+            .map(|p| env(p, TASK_SLOTS_ENV).unwrap())
+            .collect();
+        slots.sort();
+        assert_eq!(slots, vec!["2", "4", "4"]);
+
+        let pod = pods
+            .iter()
+            .find(|p| env(p, TASK_SLOTS_ENV).as_deref() == Some("2"))
+            .unwrap();
+        assert_eq!(pod["metadata"]["labels"][JOB_ID_LABEL], "job1");
+        assert_eq!(pod["metadata"]["labels"][RUN_ID_LABEL], "3");
+        assert_eq!(pod["metadata"]["labels"]["team"], "streaming");
+        assert_eq!(pod["spec"]["serviceAccountName"], "arroyo-worker");
+        assert_eq!(pod["spec"]["restartPolicy"], "Never");
+
+        let container = &pod["spec"]["containers"][0];
+        assert_eq!(container["image"], "arroyo-worker:test");
+        assert_eq!(container["resources"]["limits"]["cpu"], "1000m");
+        assert_eq!(container["resources"]["requests"]["memory"], "512Mi");
+
+        assert_eq!(env(pod, JOB_ID_ENV).as_deref(), Some("job1"));
+        assert_eq!(env(pod, "STATE_BACKEND").as_deref(), Some("spill"));
+        assert_eq!(
+            env(pod, PIPELINE_PATH_ENV).as_deref(),
+            Some("s3://bucket.s3-us-east-1.amazonaws.com/pipeline")
+        );
+        assert_eq!(
+            env(pod, WORKER_ID_ENV),
+            pod["metadata"]["labels"][WORKER_ID_LABEL]
+                .as_str()
+                .map(|s| s.to_string())
+        );
+
+        // credentials come from the secret, never as literal values
+        let secret_key = container["env"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|e| e["name"] == "AWS_SECRET_ACCESS_KEY")
+            .unwrap();
+        assert!(secret_key.get("value").is_none());
+        assert_eq!(
+            secret_key["valueFrom"]["secretKeyRef"]["name"],
+            "arroyo-storage"
+        );
+
+        // the first worker gets the first slots, which need more memory than the default
+        let first = pods
+            .iter()
+            .min_by_key(|p| env(p, WORKER_ID_ENV).unwrap().parse::<u64>().unwrap())
+            .unwrap();
+        assert_eq!(env(first, TASK_SLOTS_ENV).as_deref(), Some("4"));
+        assert_eq!(
+            first["spec"]["containers"][0]["resources"]["limits"]["memory"],
+            "2432Mi"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_workers_for_job_and_stop_worker() {
+        let (url, pods) = mock_api_server();
+        let scheduler = scheduler(url);
+
+        assert!(scheduler.start_workers(start_req("job1", 8)).await.is_ok());
+        assert!(scheduler.start_workers(start_req("job2", 1)).await.is_ok());
+
+        let workers = scheduler.workers_for_job("job1").await.unwrap();
+        assert_eq!(workers.len(), 2);
+        assert_eq!(scheduler.workers_for_job("job2").await.unwrap().len(), 1);
+
+        // exited pods aren't running workers
+        pods.lock()
+            .unwrap()
+            .iter_mut()
+            .find(|p| p["metadata"]["labels"][WORKER_ID_LABEL] == workers[0].0.to_string().as_str())
+            .unwrap()["status"] = json!({ "phase": "Failed" });
+        assert_eq!(
+            scheduler.workers_for_job("job1").await.unwrap(),
+            vec![workers[1]]
+        );
+
+        scheduler
+            .stop_worker(StopWorkerReq {
+                job_id: "job1".to_string(),
+                worker_id: workers[1].0,
+                force: true,
+            })
+            .await
+            .unwrap();
+        assert!(scheduler.workers_for_job("job1").await.unwrap().is_empty());
+
+        // stopping a worker that's already gone isn't an error
+        scheduler
+            .stop_worker(StopWorkerReq {
+                job_id: "job1".to_string(),
+                worker_id: workers[1].0,
+                force: false,
+            })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_clean_cluster() {
+        let (url, pods) = mock_api_server();
+        let scheduler = scheduler(url);
+
+        assert!(scheduler.start_workers(start_req("job1", 8)).await.is_ok());
+        assert!(scheduler.start_workers(start_req("job2", 4)).await.is_ok());
+
+        scheduler.clean_cluster("job1").await.unwrap();
+
+        let pods = pods.lock().unwrap().clone();
+        assert_eq!(pods.len(), 1);
+        assert_eq!(pods[0]["metadata"]["labels"][JOB_ID_LABEL], "job2");
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::{HashMap, HashSet};
+
+use arroyo_datastream::interpreted::{InterpretedOperator, ROW_TYPE};
+use arroyo_datastream::{
+    EdgeType, Operator, Program, SlidingAggregatingTopN, SlidingWindowAggregator,
+    TumblingWindowAggregator,
+};
+use arroyo_rpc::grpc::{StateField, StateType};
+use arroyo_state::schema::{table_schema, OperatorSchemas};
+use petgraph::Direction;
+use quote::ToTokens;
+use syn::{Fields, GenericArgument, ItemStruct, PathArguments, Type};
+
+use crate::compiler::parse_type;
+
+// types whose bincode layout is known, and which can be migrated through
+const PRIMITIVES: &[&str] = &[
+    "bool",
+    "u8",
+    "u16",
+    "u32",
+    "u64",
+    "u128",
+    "usize",
+    "i8",
+    "i16",
+    "i32",
+    "i64",
+    "i128",
+    "isize",
+    "f32",
+    "f64",
+    "char",
+    "String",
+    "SystemTime",
+    "Duration",
+];
+
+/// Computes the schemas of the tables of the program's operators, for those operators whose state
+/// types are determined by the program's types (windows, joins and SQL aggregates).
+pub fn table_schemas(program: &Program) -> OperatorSchemas {
+    let structs: HashMap<String, ItemStruct> = program
+        .types
+        .iter()
+        .chain(program.other_defs.iter())
+        .filter_map(|def| syn::parse_file(def).ok())
+        .flat_map(|file| file.items)
+        .filter_map(|item| match item {
+            syn::Item::Struct(s) => Some((s.ident.to_string(), s)),
+            _ => None,
+        })
+        .collect();
+    let resolver = TypeResolver { structs };
+
+    let mut schemas = HashMap::new();
+    for idx in program.graph.node_indices() {
+        let node = program.graph.node_weight(idx).unwrap();
+        let mut inputs: Vec<_> = program
+            .graph
+            .edges_directed(idx, Direction::Incoming)
+            .map(|e| e.weight())
+            .collect();
+        inputs.sort_by_key(|e| e.typ.clone());
+        let Some(input) = inputs.first() else {
+            continue;
+        };
+
+        let key = resolver.resolve_str(&input.key);
+        let tables = match &node.operator {
+            Operator::Window { .. } => vec![("w", key, resolver.resolve_str(&input.value))],
+            Operator::WindowJoin { .. } => {
+                let Some(right) = inputs.iter().find(|e| e.typ == EdgeType::ShuffleJoin(1)) else {
+                    continue;
+                };
+                vec![
+                    ("l", key.clone(), resolver.resolve_str(&input.value)),
+                    ("r", key, resolver.resolve_str(&right.value)),
+                ]
+            }
+            Operator::TumblingTopN(_) => vec![(
+                "w",
+                tuple(vec![key, primitive("u64")]),
+                tuple(vec![
+                    primitive("SystemTime"),
+                    resolver.resolve_str(&input.value),
+                ]),
+            )],
+            Operator::SlidingWindowAggregator(SlidingWindowAggregator { bin_type, .. })
+            | Operator::TumblingWindowAggregator(TumblingWindowAggregator { bin_type, .. })
+            | Operator::SlidingAggregatingTopN(SlidingAggregatingTopN { bin_type, .. }) => {
+                vec![("a", key, resolver.resolve_str(bin_type))]
+            }
+            Operator::Interpreted(
+                InterpretedOperator::TumblingAggregate { .. }
+                | InterpretedOperator::SlidingAggregate { .. },
+            ) => vec![("a", key, resolver.resolve_str(ROW_TYPE))],
+            Operator::Dedup { .. } => vec![("d", key, primitive("()"))],
+            _ => vec![],
+        };
+
+        if !tables.is_empty() {
+            schemas.insert(
+                node.operator_id.clone(),
+                tables
+                    .into_iter()
+                    .map(|(name, key, value)| (name.to_string(), table_schema(key, value)))
+                    .collect(),
+            );
+        }
+    }
+
+    schemas
+}
+
+struct TypeResolver {
+    structs: HashMap<String, ItemStruct>,
+}
+
+impl TypeResolver {
+    fn resolve_str(&self, t: &str) -> StateType {
+        self.resolve(&parse_type(t), &mut HashSet::new())
+    }
+
+    fn resolve(&self, t: &Type, visiting: &mut HashSet<String>) -> StateType {
+        match t {
+            Type::Paren(p) => self.resolve(&p.elem, visiting),
+            Type::Group(g) => self.resolve(&g.elem, visiting),
+            Type::Tuple(t) if t.elems.is_empty() => primitive("()"),
+            Type::Tuple(t) => tuple(t.elems.iter().map(|e| self.resolve(e, visiting)).collect()),
+            Type::Path(path) if path.qself.is_none() => {
+                let segment = path.path.segments.last().unwrap();
+                let name = segment.ident.to_string();
+                let params: Vec<_> = match &segment.arguments {
+                    PathArguments::AngleBracketed(args) => args
+                        .args
+                        .iter()
+                        .filter_map(|arg| match arg {
+                            GenericArgument::Type(t) => Some(t),
+                            _ => None,
+                        })
+                        .collect(),
+                    _ => vec![],
+                };
+
+                match (name.as_str(), params.as_slice()) {
+                    ("Box", [inner]) => self.resolve(inner, visiting),
+                    ("Option" | "Vec", [inner]) => StateType {
+                        name,
+                        params: vec![self.resolve(inner, visiting)],
+                        fields: vec![],
+                    },
+                    (name, []) if PRIMITIVES.contains(&name) => primitive(name),
+                    (name, [])
+                        if self.structs.contains_key(name) && visiting.insert(name.into()) =>
+                    {
+                        let resolved = self.resolve_struct(&self.structs[name], visiting);
+                        visiting.remove(name);
+                        resolved
+                    }
+                    _ => opaque(t),
+                }
+            }
+            _ => opaque(t),
+        }
+    }
+
+    fn resolve_struct(&self, s: &ItemStruct, visiting: &mut HashSet<String>) -> StateType {
+        match &s.fields {
+            Fields::Named(fields) => StateType {
+                name: "struct".to_string(),
+                params: vec![],
+                fields: fields
+                    .named
+                    .iter()
+                    .map(|f| StateField {
+                        name: f.ident.as_ref().unwrap().to_string(),
+                        data_type: Some(self.resolve(&f.ty, visiting)),
+                    })
+                    .collect(),
+            },
+            Fields::Unnamed(fields) => tuple(
+                fields
+                    .unnamed
+                    .iter()
+                    .map(|f| self.resolve(&f.ty, visiting))
+                    .collect(),
+            ),
+            Fields::Unit => primitive("()"),
+        }
+    }
+}
+
+fn primitive(name: &str) -> StateType {
+    StateType {
+        name: name.to_string(),
+        params: vec![],
+        fields: vec![],
+    }
+}
+
+fn tuple(params: Vec<StateType>) -> StateType {
+    StateType {
+        name: "tuple".to_string(),
+        params,
+        fields: vec![],
+    }
+}
+
+// types we can't see into are identified by their path, so they're only compatible with themselves
+fn opaque(t: &Type) -> StateType {
+    primitive(&t.to_token_stream().to_string().replace(' ', ""))
+}
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::bail;
+use arroyo_rpc::grpc::{worker_grpc_client::WorkerGrpcClient, TaskAssignment};
+use arroyo_types::WorkerId;
+use tonic::transport::Channel;
+use tracing::{info, warn};
+
+use crate::job_controller::JobController;
+use crate::queries::controller_queries;
+
+use super::scheduling::{assign_tasks, load_workers, program_tasks, queryable_state, WorkerStatus};
+use super::{compiling::Compiling, running::Running, Context, State, StateError, Transition};
+
+/// Takes over a job that was running under a previous controller by reconnecting to its workers.
+/// If the workers can't be reached, the job is restarted from its last checkpoint instead.
+#[derive(Debug)]
+pub struct Adopting {}
+
+impl Adopting {
+    async fn load_workers(
+        ctx: &Context<'_>,
+    ) -> anyhow::Result<(HashMap<WorkerId, WorkerStatus>, Vec<TaskAssignment>)> {
+        let (workers, assignments) = load_workers(ctx).await?;
+
+        if workers.is_empty() {
+            bail!("no workers recorded for run {}", ctx.status.run_id);
+        }
+
+        let assignments = Self::check_assignments(
+            &workers,
+            assignments,
+            program_tasks(ctx.program),
+            ctx.status.tasks,
+        )?;
+
+        Ok((workers, assignments))
+    }
+
+    // checks that the recorded workers can still run the job's tasks, returning what each of
+    // them is running
+    fn check_assignments(
+        workers: &HashMap<WorkerId, WorkerStatus>,
+        assignments: Vec<TaskAssignment>,
+        tasks: Vec<(String, Vec<usize>)>,
+        recorded_tasks: Option<i32>,
+    ) -> anyhow::Result<Vec<TaskAssignment>> {
+        // the job must still have the shape it was scheduled with
+        let slots: usize = workers.values().map(|w| w.slots).sum();
+        let required_slots = tasks.iter().map(|(_, t)| t.len()).max().unwrap_or(0);
+        let task_count: usize = tasks.iter().map(|(_, t)| t.len()).sum();
+        if slots != required_slots || recorded_tasks != Some(task_count as i32) {
+            bail!("the job's parallelism has changed since it was scheduled");
+        }
+
+        // workers recorded before their tasks were stored ran the default assignments
+        if assignments.is_empty() {
+            return Ok(assign_tasks(workers.values().collect(), tasks));
+        }
+
+        Ok(assignments)
+    }
+
+    async fn connect(
+        workers: &HashMap<WorkerId, WorkerStatus>,
+    ) -> anyhow::Result<HashMap<WorkerId, WorkerGrpcClient<Channel>>> {
+        let mut connects = HashMap::new();
+        for worker in workers.values() {
+            let channel = Channel::from_shared(worker.rpc_address.clone())?
+                .connect_timeout(Duration::from_secs(5))
+                .timeout(Duration::from_secs(10))
+                .connect()
+                .await?;
+            connects.insert(worker.id, WorkerGrpcClient::new(channel));
+        }
+
+        Ok(connects)
+    }
+
+    async fn epochs(ctx: &Context<'_>) -> anyhow::Result<(u32, u32)> {
+        let c = ctx.pool.get().await?;
+        let (last_epoch, min_epoch) = controller_queries::last_successful_checkpoint()
+            .bind(&c, &ctx.config.id)
+            .opt()
+            .await?
+            .map(|r| (r.epoch as u32, r.min_epoch as u32))
+            .unwrap_or((0, 0));
+
+        // checkpoints that the previous controller didn't finish will never complete
+        controller_queries::mark_failed()
+            .bind(&c, &ctx.config.id, &(last_epoch as i32 + 1))
+            .await?;
+
+        // continue after any checkpoint the previous controller started, so that epochs aren't
+        // reused by the workers
+        let epoch = controller_queries::last_checkpoint_epoch()
+            .bind(&c, &ctx.config.id)
+            .one()
+            .await?
+            .map(|e| e as u32)
+            .unwrap_or(0);
+
+        Ok((epoch, min_epoch))
+    }
+}
+
+#[async_trait::async_trait]
+impl State for Adopting {
+    fn name(&self) -> &'static str {
+        "Adopting"
+    }
+
+    async fn next(self: Box<Self>, ctx: &mut Context) -> Result<Transition, StateError> {
+        ctx.program
+            .update_parallelism(&ctx.config.parallelism_overrides);
+
+        let (workers, assignments) = match Self::load_workers(ctx).await {
+            Ok(workers) => workers,
+            Err(e) => {
+                info!(
+                    message = "cannot adopt job, restarting it",
+                    job_id = ctx.config.id,
+                    reason = format!("{:?}", e)
+                );
+                return Ok(Transition::next(*self, Compiling {}));
+            }
+        };
+
+        let worker_connects = match Self::connect(&workers).await {
+            Ok(connects) => connects,
+            Err(e) => {
+                warn!(
+                    message = "failed to reconnect to workers, restarting job",
+                    job_id = ctx.config.id,
+                    error = format!("{:?}", e)
+                );
+                return Ok(Transition::next(*self, Compiling {}));
+            }
+        };
+
+        let (epoch, min_epoch) = match Self::epochs(ctx).await {
+            Ok(epochs) => epochs,
+            Err(e) => {
+                return Err(ctx.retryable(self, "failed to load checkpoint epochs", e, 10));
+            }
+        };
+
+        info!(
+            message = "adopted running job",
+            job_id = ctx.config.id,
+            run_id = ctx.status.run_id,
+            workers = workers.len(),
+            epoch,
+        );
+
+        ctx.set_queryable_state(Some(queryable_state(
+            ctx.program,
+            &assignments,
+            &workers,
+            ctx.status.run_id,
+        )));
+
+        ctx.job_controller = Some(JobController::new(
+            ctx.pool.clone(),
+            ctx.config.clone(),
+            ctx.program.clone(),
+            epoch,
+            min_epoch,
+            worker_connects,
+            assignments,
+        ));
+
+        Ok(Transition::next(*self, Running {}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn worker(id: u64, slots: usize) -> (WorkerId, WorkerStatus) {
+        (
+            WorkerId(id),
+            WorkerStatus {
+                id: WorkerId(id),
+                rpc_address: format!("http://worker-{}:6900", id),
+                data_address: format!("worker-{}:6901", id),
+                slots,
+            },
+        )
+    }
+
+    fn tasks() -> Vec<(String, Vec<usize>)> {
+        vec![
+            ("source".to_string(), vec![0, 1, 2]),
+            ("sink".to_string(), vec![0, 1]),
+        ]
+    }
+
+    #[test]
+    fn test_adopt_recorded_assignments() {
+        let workers: HashMap<_, _> = [worker(1, 2), worker(2, 1)].into_iter().collect();
+        let recorded = vec![TaskAssignment {
+            operator_id: "source".to_string(),
+            operator_subtask: 0,
+            worker_id: 2,
+            worker_addr: "worker-2:6901".to_string(),
+        }];
+
+        let assignments =
+            Adopting::check_assignments(&workers, recorded.clone(), tasks(), Some(5)).unwrap();
+
+        assert_eq!(assignments, recorded);
+    }
+
+    #[test]
+    fn test_adopt_without_recorded_assignments() {
+        let workers: HashMap<_, _> = [worker(2, 1), worker(1, 2)].into_iter().collect();
+
+        let assignments = Adopting::check_assignments(&workers, vec![], tasks(), Some(5)).unwrap();
+
+        assert_eq!(
+            assignments,
+            assign_tasks(workers.values().collect(), tasks())
+        );
+        assert_eq!(assignments.len(), 5);
+    }
+
+    #[test]
+    fn test_adopt_after_parallelism_change() {
+        let workers: HashMap<_, _> = [worker(1, 2), worker(2, 1)].into_iter().collect();
+
+        // fewer slots than the job now needs
+        let mut rescaled = tasks();
+        rescaled[0].1.push(3);
+        assert!(Adopting::check_assignments(&workers, vec![], rescaled, Some(6)).is_err());
+
+        // the same number of slots, but a different number of tasks than were scheduled
+        assert!(Adopting::check_assignments(&workers, vec![], tasks(), Some(4)).is_err());
+    }
+}
@@ -1,9 +1,13 @@
+use anyhow::bail;
+use arroyo_datastream::Program;
+use arroyo_state::storage::StorageClient;
+use arroyo_types::INTERPRETED_WORKER_URL_ENV;
 use tracing::info;
 
 use crate::compiler::ProgramCompiler;
 use crate::states::StateError;
 
-use super::{scheduling::Scheduling, Context, State, Transition};
+use super::{fatal, scheduling::Scheduling, Context, State, Transition};
 
 #[derive(Debug)]
 pub struct Compiling;
@@ -23,6 +27,33 @@ impl State for Compiling {
             return Ok(Transition::next(*self, Scheduling {}));
         }
 
+        if ctx.program.is_interpreted() {
+            info!(
+                message = "Using interpreted worker for pipeline",
+                job_id = ctx.config.id,
+            );
+            let worker_url = match interpreted_worker_url() {
+                Ok(url) => url,
+                Err(e) => return Err(fatal("Cannot run interpreted pipeline", e)),
+            };
+
+            // the program is passed to workers through the job's storage, as it can be larger than
+            // the environment allows
+            if let Err(e) = StorageClient::for_job(&ctx.config.id)
+                .write(
+                    interpreted_program_key(&ctx.config.id, ctx.program),
+                    ctx.program.encode(),
+                )
+                .await
+            {
+                return Err(ctx.retryable(self, "Failed to store interpreted program", e, 10));
+            }
+
+            ctx.status.pipeline_path = Some(worker_url);
+            ctx.status.wasm_path = Some(String::new());
+            return Ok(Transition::next(*self, Scheduling {}));
+        }
+
         info!(
             message = "Compiling pipeline",
             job_id = ctx.config.id,
@@ -47,3 +78,32 @@ impl State for Compiling {
         }
     }
 }
+
+// the key of the interpreted program within the job's storage
+pub(super) fn interpreted_program_key(job_id: &str, program: &Program) -> String {
+    format!("{}/programs/{}", job_id, program.get_hash())
+}
+
+// only the process scheduler runs workers on the controller's machine, where they can use the
+// arroyo-interpreted-worker binary next to the controller
+fn interpreted_worker_url() -> anyhow::Result<String> {
+    if let Ok(url) = std::env::var(INTERPRETED_WORKER_URL_ENV) {
+        return Ok(url);
+    }
+
+    match std::env::var("SCHEDULER").ok().as_deref() {
+        Some(scheduler @ ("node" | "nomad" | "kubernetes")) => bail!(
+            "{} must be set to run interpreted pipelines with the {} scheduler",
+            INTERPRETED_WORKER_URL_ENV,
+            scheduler
+        ),
+        _ => {
+            let exe = std::env::current_exe()?;
+            Ok(format!(
+                "file://{}",
+                exe.with_file_name("arroyo-interpreted-worker")
+                    .to_string_lossy()
+            ))
+        }
+    }
+}
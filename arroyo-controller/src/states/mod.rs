@@ -1,9 +1,10 @@
 use std::sync::RwLock;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{fmt::Debug, sync::Arc};
 
 use arroyo_datastream::Program;
 use arroyo_rpc::grpc::api::PipelineProgram;
+use arroyo_rpc::grpc::QueryableStateResp;
 
 use deadpool_postgres::Pool;
 use thiserror::Error;
@@ -20,21 +21,29 @@ use crate::types::public::StopMode;
 use crate::{scheduler::Scheduler, JobConfig, JobMessage, JobStatus};
 use prost::Message;
 
+use self::adopting::Adopting;
 use self::checkpoint_stopping::CheckpointStopping;
 use self::compiling::Compiling;
 use self::finishing::Finishing;
 use self::recovering::Recovering;
+use self::recovering_region::RecoveringRegion;
 use self::rescaling::Rescaling;
 use self::running::Running;
+use self::savepointing::Savepointing;
+use self::scheduled::Scheduled;
 use self::scheduling::Scheduling;
 use self::stopping::Stopping;
 
+mod adopting;
 mod checkpoint_stopping;
 mod compiling;
 mod finishing;
 mod recovering;
+mod recovering_region;
 mod rescaling;
 mod running;
+mod savepointing;
+mod scheduled;
 mod scheduling;
 mod stopping;
 
@@ -75,8 +84,12 @@ impl State for Created {
         "Created"
     }
 
-    async fn next(self: Box<Self>, _: &mut Context) -> Result<Transition, StateError> {
-        Ok(Transition::next(*self, Compiling))
+    async fn next(self: Box<Self>, ctx: &mut Context) -> Result<Transition, StateError> {
+        if ctx.config.schedule.is_some() {
+            Ok(Transition::next(*self, Scheduled {}))
+        } else {
+            Ok(Transition::next(*self, Compiling))
+        }
     }
 }
 
@@ -89,6 +102,7 @@ impl State for Failed {
     }
 
     async fn next(self: Box<Self>, ctx: &mut Context) -> Result<Transition, StateError> {
+        ctx.set_queryable_state(None);
         if let Err(e) = ctx.scheduler.clean_cluster(&ctx.config.id).await {
             warn!(
                 message = "Failed to clean up cluster",
@@ -114,8 +128,16 @@ impl State for Finished {
         "Finished"
     }
 
-    async fn next(self: Box<Self>, _: &mut Context) -> Result<Transition, StateError> {
-        Ok(Transition::Stop)
+    async fn next(self: Box<Self>, ctx: &mut Context) -> Result<Transition, StateError> {
+        if let Err(e) = record_final_checkpoint(ctx).await {
+            return Err(ctx.retryable(self, "failed to record final checkpoint", e, 10));
+        }
+
+        if ctx.config.stop_mode == StopMode::none && scheduled::next_run(ctx).is_some() {
+            Ok(Transition::next(*self, Scheduled {}))
+        } else {
+            Ok(Transition::Stop)
+        }
     }
 
     fn is_terminal(&self) -> bool {
@@ -137,7 +159,25 @@ impl State for Stopped {
             return Err(ctx.retryable(self, "failed to clean cluster", e, 20));
         }
 
-        if ctx.config.stop_mode == StopMode::none && ctx.config.ttl.is_none() {
+        if let Some(savepoint_id) = ctx.config.requested_savepoint.clone() {
+            savepointing::save_last_checkpoint(&ctx.pool, &ctx.config.id, &savepoint_id).await;
+        }
+
+        if let Err(e) = record_final_checkpoint(ctx).await {
+            return Err(ctx.retryable(self, "failed to record final checkpoint", e, 10));
+        }
+
+        // a job that runs on a schedule waits for its next run, rather than being restarted
+        // straight away
+        if ctx.config.stop_mode != StopMode::none {
+            Ok(Transition::Stop)
+        } else if ctx.config.schedule.is_some() {
+            if scheduled::next_run(ctx).is_some() {
+                Ok(Transition::next(*self, Scheduled {}))
+            } else {
+                Ok(Transition::Stop)
+            }
+        } else if ctx.config.ttl.is_none() {
             Ok(Transition::next(*self, Compiling {}))
         } else {
             Ok(Transition::Stop)
@@ -149,10 +189,40 @@ impl State for Stopped {
     }
 }
 
+// records the last checkpoint of the run that has just ended in the job's status, so that it's
+// reported as the run's final checkpoint
+async fn record_final_checkpoint(ctx: &mut Context<'_>) -> anyhow::Result<()> {
+    let c = ctx.pool.get().await?;
+    let epoch = controller_queries::last_successful_checkpoint()
+        .bind(&c, &ctx.config.id)
+        .opt()
+        .await?
+        .map(|r| r.epoch);
+
+    if epoch != ctx.status.final_checkpoint_epoch {
+        ctx.status.final_checkpoint_epoch = epoch;
+        ctx.status
+            .update_db(&ctx.pool)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+    }
+
+    Ok(())
+}
+
 // State transitions
 impl TransitionTo<Compiling> for Created {}
+impl TransitionTo<Scheduled> for Created {}
 
 impl TransitionTo<Compiling> for Stopped {}
+impl TransitionTo<Scheduled> for Stopped {}
+impl TransitionTo<Scheduled> for Finished {}
+
+impl TransitionTo<Compiling> for Scheduled {}
+impl TransitionTo<Stopped> for Scheduled {}
+
+impl TransitionTo<Running> for Adopting {}
+impl TransitionTo<Compiling> for Adopting {}
 
 impl TransitionTo<Scheduling> for Compiling {
     fn update_status(&self) -> TransitionFn {
@@ -170,6 +240,7 @@ impl TransitionTo<Running> for Scheduling {
             if ctx.status.start_time.is_none() || ctx.status.finish_time.is_some() {
                 ctx.status.start_time = Some(OffsetDateTime::now_utc());
                 ctx.status.finish_time = None;
+                ctx.status.final_checkpoint_epoch = None;
             }
         })
     }
@@ -185,7 +256,24 @@ impl TransitionTo<Recovering> for Running {
         })
     }
 }
+impl TransitionTo<RecoveringRegion> for Running {
+    fn update_status(&self) -> TransitionFn {
+        Box::new(|ctx| {
+            ctx.status.restarts += 1;
+        })
+    }
+}
 impl TransitionTo<Rescaling> for Running {}
+impl TransitionTo<Savepointing> for Running {}
+
+impl TransitionTo<Running> for Savepointing {}
+impl TransitionTo<Recovering> for Savepointing {
+    fn update_status(&self) -> TransitionFn {
+        Box::new(|ctx| {
+            ctx.status.restarts += 1;
+        })
+    }
+}
 
 impl TransitionTo<Scheduling> for Rescaling {
     fn update_status(&self) -> TransitionFn {
@@ -196,11 +284,41 @@ impl TransitionTo<Scheduling> for Rescaling {
 }
 impl TransitionTo<Stopping> for Rescaling {}
 
-impl TransitionTo<Compiling> for Recovering {}
+impl TransitionTo<Compiling> for Recovering {
+    fn update_status(&self) -> TransitionFn {
+        Box::new(|ctx| {
+            ctx.status.next_restart_time = None;
+        })
+    }
+}
+impl TransitionTo<Stopped> for Recovering {
+    fn update_status(&self) -> TransitionFn {
+        Box::new(|ctx| {
+            ctx.status.next_restart_time = None;
+            done_transition(ctx);
+        })
+    }
+}
+
+impl TransitionTo<Running> for RecoveringRegion {
+    fn update_status(&self) -> TransitionFn {
+        Box::new(|ctx| {
+            ctx.status.next_restart_time = None;
+        })
+    }
+}
+impl TransitionTo<Recovering> for RecoveringRegion {
+    fn update_status(&self) -> TransitionFn {
+        Box::new(|ctx| {
+            ctx.status.restarts += 1;
+        })
+    }
+}
 
 fn done_transition(ctx: &mut Context) {
     ctx.status.finish_time = Some(OffsetDateTime::now_utc());
     ctx.job_controller = None;
+    ctx.set_queryable_state(None);
 }
 
 impl TransitionTo<Stopped> for Stopping {
@@ -231,6 +349,13 @@ pub struct Context<'a> {
     rx: &'a mut Receiver<JobMessage>,
     retries_attempted: usize,
     job_controller: Option<JobController>,
+    // the last savepoint this state machine has handled, so that it isn't taken twice while the
+    // request is still visible in the config
+    last_savepoint: Option<String>,
+    // where the state of the running job can be queried, shared with the controller server
+    queryable_state: Arc<RwLock<Option<QueryableStateResp>>>,
+    // when the job failed within the restart policy's failure window, if it has one
+    recent_failures: Vec<Instant>,
 }
 
 impl<'a> Context<'a> {
@@ -239,6 +364,40 @@ impl<'a> Context<'a> {
         Ok(())
     }
 
+    pub fn set_queryable_state(&self, queryable_state: Option<QueryableStateResp>) {
+        *self.queryable_state.write().unwrap() = queryable_state;
+    }
+
+    /// Records a failure of the job, returning how long to wait before restarting it according to
+    /// its restart policy, or None if it should be failed instead
+    pub fn record_failure(&mut self) -> Option<Duration> {
+        let policy = self.config.restart_policy;
+        let now = Instant::now();
+        if let Some(window) = policy.failure_window() {
+            self.recent_failures
+                .retain(|t| now.duration_since(*t) < window);
+            self.recent_failures.push(now);
+        }
+
+        let delay = policy.restart_delay(
+            self.status.restarts as u32,
+            self.recent_failures.len() as u32,
+            rand::random(),
+        )?;
+
+        self.status.restart_backoff_micros = Some(delay.as_micros() as i64);
+        self.status.next_restart_time = Some(OffsetDateTime::now_utc() + delay);
+        Some(delay)
+    }
+
+    // how much longer the job must wait before it's restarted
+    pub fn remaining_backoff(&self) -> Duration {
+        self.status
+            .next_restart_time
+            .and_then(|t| (t - OffsetDateTime::now_utc()).try_into().ok())
+            .unwrap_or(Duration::ZERO)
+    }
+
     pub fn retryable(
         &self,
         state: Box<dyn State>,
@@ -370,6 +529,7 @@ pub async fn run_to_completion(
     pool: Pool,
     mut rx: Receiver<JobMessage>,
     scheduler: Arc<dyn Scheduler>,
+    queryable_state: Arc<RwLock<Option<QueryableStateResp>>>,
 ) {
     let c = pool.get().await.unwrap();
     let id = config.read().unwrap().definition_id;
@@ -395,6 +555,9 @@ pub async fn run_to_completion(
         rx: &mut rx,
         retries_attempted: 0,
         job_controller: None,
+        last_savepoint: None,
+        queryable_state,
+        recent_failures: vec![],
     };
 
     loop {
@@ -415,6 +578,7 @@ pub struct StateMachine {
     config: Arc<RwLock<JobConfig>>,
     pool: Pool,
     scheduler: Arc<dyn Scheduler>,
+    queryable_state: Arc<RwLock<Option<QueryableStateResp>>>,
 }
 
 impl StateMachine {
@@ -429,6 +593,7 @@ impl StateMachine {
             config: Arc::new(RwLock::new(config)),
             pool,
             scheduler,
+            queryable_state: Arc::new(RwLock::new(None)),
         };
 
         this.start(status).await;
@@ -448,7 +613,12 @@ impl StateMachine {
             "Stopped" => Some(Box::new(Stopped {})),
             "Finished" => Some(Box::new(Finished {})),
             "Failed" => Some(Box::new(Failed {})),
-            "Compiling" | "Scheduling" | "Running" | "Recovering" => Some(Box::new(Compiling {})),
+            "Scheduled" => Some(Box::new(Scheduled {})),
+            "Compiling" | "Scheduling" | "Savepointing" | "Recovering" | "RecoveringRegion" => {
+                Some(Box::new(Compiling {}))
+            }
+            // the job was running under another controller, so try to take over its workers
+            "Running" | "Adopting" => Some(Box::new(Adopting {})),
             "Stopping" | "CheckpointStopping" => {
                 // TODO: do we need to handle a failure in CheckpointStopping specially?
                 if status.finish_time.is_none() {
@@ -475,10 +645,20 @@ impl StateMachine {
                 let config = self.config.clone();
                 let pool = self.pool.clone();
                 let scheduler = self.scheduler.clone();
+                let queryable_state = self.queryable_state.clone();
                 tokio::spawn(async move {
                     let id = { config.read().unwrap().id.clone() };
                     info!(message = "starting state machine", job_id = id);
-                    run_to_completion(config, status, initial_state, pool, rx, scheduler).await;
+                    run_to_completion(
+                        config,
+                        status,
+                        initial_state,
+                        pool,
+                        rx,
+                        scheduler,
+                        queryable_state,
+                    )
+                    .await;
                     info!(message = "finished state machine", job_id = id);
                 });
             }
@@ -508,6 +688,10 @@ impl StateMachine {
         }
     }
 
+    pub fn queryable_state(&self) -> Option<QueryableStateResp> {
+        self.queryable_state.read().unwrap().clone()
+    }
+
     pub fn done(&self) -> bool {
         if let Some(tx) = &self.tx {
             tx.is_closed()
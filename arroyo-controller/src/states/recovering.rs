@@ -2,10 +2,17 @@ use std::time::{Duration, Instant};
 
 use anyhow::bail;
 use arroyo_rpc::grpc::{StopMode, StopWorkerReq};
+use arroyo_state::{BackingStore, StateBackend};
 use tokio::time::timeout;
 use tracing::{info, warn};
 
-use super::{compiling::Compiling, Context, State, StateError, Transition};
+use crate::queries::controller_queries;
+use crate::JobMessage;
+
+use super::{
+    compiling::Compiling, scheduling::record_restored_checkpoint, Context, State, StateError,
+    Stopped, Transition,
+};
 
 #[derive(Debug)]
 pub struct Recovering {}
@@ -88,6 +95,58 @@ impl Recovering {
 
         bail!("Failed to clean up cluster")
     }
+
+    // We normally restore from the latest successful checkpoint recorded in the database. If
+    // there isn't one (for example because the database was lost or the job was recreated), we
+    // fall back to the newest complete checkpoint that can be found in storage.
+    async fn recover_checkpoint_from_storage(ctx: &Context<'_>) -> anyhow::Result<()> {
+        let c = ctx.pool.get().await?;
+        if controller_queries::last_successful_checkpoint()
+            .bind(&c, &ctx.config.id)
+            .opt()
+            .await?
+            .is_some()
+        {
+            return Ok(());
+        }
+
+        let Some(metadata) = StateBackend::load_latest_checkpoint_metadata(&ctx.config.id).await?
+        else {
+            return Ok(());
+        };
+
+        info!(
+            message = "recovering from checkpoint found in storage",
+            job_id = ctx.config.id,
+            epoch = metadata.epoch,
+            min_epoch = metadata.min_epoch
+        );
+        record_restored_checkpoint(ctx, &metadata).await
+    }
+
+    // waits out the backoff required by the job's restart policy, returning false if the job is
+    // stopped in the meantime
+    async fn backoff(ctx: &mut Context<'_>) -> bool {
+        loop {
+            let remaining = ctx.remaining_backoff();
+            if remaining.is_zero() {
+                return true;
+            }
+
+            match timeout(remaining, ctx.rx.recv()).await {
+                Ok(Some(JobMessage::ConfigUpdate(c)))
+                    if c.stop_mode != crate::types::public::StopMode::none =>
+                {
+                    return false;
+                }
+                Ok(Some(_)) => {
+                    // messages from the stopped workers are no longer relevant
+                }
+                Ok(None) => panic!("job queue shut down"),
+                Err(_) => return true,
+            }
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -102,6 +161,22 @@ impl State for Recovering {
             return Err(ctx.retryable(self, "failed to tear down existing cluster", e, 10));
         }
 
+        if let Err(e) = Self::recover_checkpoint_from_storage(ctx).await {
+            return Err(ctx.retryable(self, "failed to look up checkpoints in storage", e, 10));
+        }
+
+        let backoff = ctx.remaining_backoff();
+        if !backoff.is_zero() {
+            info!(
+                message = "waiting before restarting job",
+                job_id = ctx.config.id,
+                backoff = backoff.as_secs_f32()
+            );
+            if !Self::backoff(ctx).await {
+                return Ok(Transition::next(*self, Stopped {}));
+            }
+        }
+
         Ok(Transition::next(*self, Compiling))
     }
 }
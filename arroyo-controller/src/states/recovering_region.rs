@@ -0,0 +1,275 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail};
+use arroyo_rpc::grpc::{StartExecutionReq, StopWorkerReq, TaskAssignment};
+use tokio::sync::Mutex;
+use tonic::Request;
+use tracing::{info, warn};
+
+use crate::job_controller::FailoverGroup;
+use crate::queries::controller_queries;
+use crate::scheduler::SchedulerError;
+use crate::JobMessage;
+
+use super::scheduling::{
+    assign_tasks, handle_worker_connect, load_workers, queryable_state, record_workers,
+    start_pipeline_req, WorkerStatus,
+};
+use super::{
+    fatal, recovering::Recovering, running::Running, Context, State, StateError, Transition,
+};
+
+const STARTUP_TIME: Duration = Duration::from_secs(5 * 60);
+
+/// Restarts the failover regions affected by a failure from the last checkpoint on new workers,
+/// while the rest of the job keeps running. If that isn't possible, the whole job is recovered.
+#[derive(Debug)]
+pub struct RecoveringRegion {
+    pub group: FailoverGroup,
+}
+
+impl RecoveringRegion {
+    // waits until the deadline for the next message for the job, passing along any from the
+    // parts of the job that are still running
+    async fn next_message(
+        ctx: &mut Context<'_>,
+        deadline: Instant,
+    ) -> anyhow::Result<Option<JobMessage>> {
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+
+            match tokio::time::timeout(timeout, ctx.rx.recv()).await {
+                Ok(Some(JobMessage::RunningMessage(msg))) => {
+                    ctx.job_controller
+                        .as_mut()
+                        .unwrap()
+                        .handle_message(msg)
+                        .await?;
+                }
+                Ok(Some(msg)) => return Ok(Some(msg)),
+                Ok(None) => bail!("job queue shut down"),
+                Err(_) => return Ok(None),
+            }
+        }
+    }
+
+    async fn startup_message(ctx: &mut Context<'_>, start: Instant) -> anyhow::Result<JobMessage> {
+        Self::next_message(ctx, start + STARTUP_TIME)
+            .await?
+            .ok_or_else(|| anyhow!("timed out after {:?} restarting region", STARTUP_TIME))
+    }
+
+    async fn recover(&self, ctx: &mut Context<'_>) -> anyhow::Result<()> {
+        let group = &self.group;
+
+        info!(
+            message = "restarting failover region",
+            job_id = ctx.config.id,
+            tasks = group.tasks.len(),
+            workers = format!("{:?}", group.workers),
+        );
+
+        for worker in &group.workers {
+            if let Err(e) = ctx
+                .scheduler
+                .stop_worker(StopWorkerReq {
+                    job_id: ctx.config.id.clone(),
+                    worker_id: worker.0,
+                    force: true,
+                })
+                .await
+            {
+                warn!(
+                    message = "error while stopping worker",
+                    error = format!("{:?}", e),
+                    worker_id = worker.0,
+                    job_id = ctx.config.id
+                );
+            }
+        }
+
+        ctx.job_controller
+            .as_mut()
+            .unwrap()
+            .abort_checkpoint()
+            .await?;
+
+        // back off as the restart policy requires, while the rest of the job keeps running
+        let backoff = ctx.remaining_backoff();
+        if !backoff.is_zero() {
+            info!(
+                message = "waiting before restarting failover region",
+                job_id = ctx.config.id,
+                backoff = backoff.as_secs_f32()
+            );
+            let deadline = Instant::now() + backoff;
+            while let Some(msg) = Self::next_message(ctx, deadline).await? {
+                ctx.handle(msg)?;
+            }
+        }
+
+        let restore_epoch = {
+            let c = ctx.pool.get().await?;
+            controller_queries::last_successful_checkpoint()
+                .bind(&c, &ctx.config.id)
+                .opt()
+                .await?
+                .map(|r| r.epoch as u32)
+        };
+
+        // the subtasks of each operator that are being restarted
+        let tasks: Vec<(String, Vec<usize>)> = ctx
+            .program
+            .graph
+            .node_weights()
+            .map(|node| {
+                let subtasks: Vec<usize> = (0..node.parallelism)
+                    .filter(|i| group.tasks.contains(&(node.operator_id.clone(), *i as u32)))
+                    .collect();
+                (node.operator_id.clone(), subtasks)
+            })
+            .filter(|(_, subtasks)| !subtasks.is_empty())
+            .collect();
+
+        let slots_needed = tasks.iter().map(|(_, t)| t.len()).max().unwrap_or(0);
+
+        match ctx
+            .scheduler
+            .start_workers(start_pipeline_req(ctx, &tasks))
+            .await
+        {
+            Ok(_) => {}
+            Err(SchedulerError::NotEnoughSlots { slots_needed }) => {
+                bail!(
+                    "not enough slots to restart region, needed {}",
+                    slots_needed
+                );
+            }
+            Err(SchedulerError::Unschedulable(reason)) => {
+                bail!("cannot schedule region: {}", reason);
+            }
+            Err(SchedulerError::Other(s)) => {
+                bail!("scheduling error: {}", s);
+            }
+        }
+
+        // wait for the new workers to connect
+        let start = Instant::now();
+        let mut workers: HashMap<_, WorkerStatus> = HashMap::new();
+        let worker_connects = Arc::new(Mutex::new(HashMap::new()));
+        let mut handles = vec![];
+        while workers.values().map(|w| w.slots).sum::<usize>() < slots_needed {
+            let msg = Self::startup_message(ctx, start).await?;
+            handle_worker_connect(
+                msg,
+                &mut workers,
+                worker_connects.clone(),
+                &mut handles,
+                ctx,
+            )
+            .await?;
+        }
+
+        for h in handles {
+            h.await?;
+        }
+
+        let worker_connects = Arc::try_unwrap(worker_connects)
+            .map_err(|_| anyhow!("worker connections still in use"))?
+            .into_inner();
+
+        // the restarted tasks run on the new workers, and everything else stays where it is
+        let mut assignments: Vec<TaskAssignment> = ctx
+            .job_controller
+            .as_ref()
+            .unwrap()
+            .assignments()
+            .iter()
+            .filter(|a| {
+                !group
+                    .tasks
+                    .contains(&(a.operator_id.clone(), a.operator_subtask as u32))
+            })
+            .cloned()
+            .collect();
+        assignments.extend(assign_tasks(workers.values().collect(), tasks));
+
+        for (id, c) in &worker_connects {
+            c.clone()
+                .start_execution(Request::new(StartExecutionReq {
+                    restore_epoch,
+                    tasks: assignments.clone(),
+                }))
+                .await
+                .map_err(|e| anyhow!("failed to start execution on worker {}: {:?}", id.0, e))?;
+        }
+
+        let mut started = HashSet::new();
+        while started.len() < group.tasks.len() {
+            match Self::startup_message(ctx, start).await? {
+                JobMessage::TaskStarted {
+                    operator_id,
+                    operator_subtask,
+                    ..
+                } => {
+                    started.insert((operator_id, operator_subtask));
+                }
+                msg => {
+                    ctx.handle(msg)?;
+                }
+            }
+        }
+
+        let (mut all_workers, _) = load_workers(ctx).await?;
+        all_workers.retain(|id, _| !group.workers.contains(id));
+        all_workers.extend(workers);
+        record_workers(ctx, &all_workers, &assignments).await?;
+
+        ctx.set_queryable_state(Some(queryable_state(
+            ctx.program,
+            &assignments,
+            &all_workers,
+            ctx.status.run_id,
+        )));
+
+        ctx.job_controller
+            .as_mut()
+            .unwrap()
+            .replace_workers(group, worker_connects, assignments);
+
+        info!(
+            message = "restarted failover region",
+            job_id = ctx.config.id,
+            restore_epoch,
+            duration = start.elapsed().as_secs_f32(),
+        );
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl State for RecoveringRegion {
+    fn name(&self) -> &'static str {
+        "RecoveringRegion"
+    }
+
+    async fn next(self: Box<Self>, ctx: &mut Context) -> Result<Transition, StateError> {
+        match self.recover(ctx).await {
+            Ok(()) => Ok(Transition::next(*self, Running {})),
+            Err(e) => {
+                warn!(
+                    message = "failed to restart failover region, recovering whole job",
+                    job_id = ctx.config.id,
+                    error = format!("{:?}", e)
+                );
+                if ctx.record_failure().is_none() {
+                    return Err(fatal("too many job failures", e));
+                }
+                Ok(Transition::next(*self, Recovering {}))
+            }
+        }
+    }
+}
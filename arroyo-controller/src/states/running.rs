@@ -1,14 +1,19 @@
-use std::time::{Duration, Instant};
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
 
 use arroyo_rpc::grpc::{self};
+use arroyo_types::from_micros;
 
 use time::OffsetDateTime;
 
 use tracing::error;
 
+use crate::queries::controller_queries;
 use crate::states::fatal;
 use crate::states::recovering::Recovering;
+use crate::states::recovering_region::RecoveringRegion;
 use crate::states::rescaling::Rescaling;
+use crate::states::savepointing::{requested_savepoint, Savepointing};
 use crate::JobMessage;
 use crate::{job_controller::ControllerProgress, states::StateError};
 use crate::{states::finishing::Finishing, types::public::StopMode};
@@ -20,9 +25,6 @@ use super::{
 // after this amount of time, we consider the job to be healthy and reset the restarts counter
 const HEALTHY_DURATION: Duration = Duration::from_secs(2 * 60);
 
-// how many times we allow the job to restart before moving it to failed
-const RESTARTS_ALLOWED: usize = 10;
-
 #[derive(Debug)]
 pub struct Running {}
 
@@ -58,6 +60,17 @@ macro_rules! stop_if_desired {
     };
 }
 
+async fn update_parallelism_overrides(
+    ctx: &Context<'_>,
+    overrides: HashMap<String, usize>,
+) -> anyhow::Result<()> {
+    let c = ctx.pool.get().await?;
+    controller_queries::update_parallelism_overrides()
+        .bind(&c, &serde_json::to_value(overrides)?, &ctx.config.id)
+        .await?;
+    Ok(())
+}
+
 #[async_trait::async_trait]
 impl State for Running {
     fn name(&self) -> &'static str {
@@ -67,6 +80,10 @@ impl State for Running {
     async fn next(mut self: Box<Self>, ctx: &mut Context) -> Result<Transition, StateError> {
         stop_if_desired!(self, ctx.config);
 
+        if let Some(savepoint_id) = requested_savepoint(&ctx.config, &ctx.last_savepoint) {
+            return Ok(Transition::next(*self, Savepointing { savepoint_id }));
+        }
+
         let running_start = Instant::now();
 
         loop {
@@ -79,13 +96,25 @@ impl State for Running {
                 t.checked_sub(elapsed).unwrap_or(Duration::ZERO)
             });
 
+            let schedule_end: Option<Duration> =
+                ctx.config.schedule.and_then(|s| s.stop_micros).map(|stop| {
+                    from_micros(stop)
+                        .duration_since(SystemTime::now())
+                        .unwrap_or(Duration::ZERO)
+                });
+
             tokio::select! {
                 msg = ctx.rx.recv() => {
                     match msg {
                         Some(JobMessage::ConfigUpdate(c)) => {
                             stop_if_desired!(self, &c);
 
-                            let job_controller = ctx.job_controller.as_ref().unwrap();
+                            if let Some(savepoint_id) = requested_savepoint(&c, &ctx.last_savepoint) {
+                                return Ok(Transition::next(*self, Savepointing { savepoint_id }));
+                            }
+
+                            let job_controller = ctx.job_controller.as_mut().unwrap();
+                            job_controller.update_autoscaling(c.autoscaling);
                             for (op, p) in &c.parallelism_overrides {
                                 if let Some(actual) = job_controller.operator_parallelism(op){
                                     if actual != *p {
@@ -113,15 +142,26 @@ impl State for Running {
                 _ = tokio::time::sleep(Duration::from_millis(200)) => {
                     if ctx.status.restarts > 0 && running_start.elapsed() > HEALTHY_DURATION {
                         let restarts = ctx.status.restarts;
+                        let backoff = ctx.status.restart_backoff_micros;
                         ctx.status.restarts = 0;
+                        ctx.status.restart_backoff_micros = None;
                         if let Err(e) = ctx.status.update_db(&ctx.pool).await {
                             error!(message = "Failed to update status", error = format!("{:?}", e),
                                 job_id = ctx.config.id);
                             ctx.status.restarts = restarts;
+                            ctx.status.restart_backoff_micros = backoff;
                             // we'll try again on the next round
                         }
                     }
 
+                    // the new parallelism is picked up like any other override, through a config update
+                    if let Some(overrides) = ctx.job_controller.as_mut().unwrap().autoscale() {
+                        if let Err(e) = update_parallelism_overrides(ctx, overrides).await {
+                            error!(message = "Failed to update parallelism overrides", error = format!("{:?}", e),
+                                job_id = ctx.config.id);
+                        }
+                    }
+
                     match ctx.job_controller.as_mut().unwrap().progress().await {
                         Ok(ControllerProgress::Continue) => {
                             // do nothing
@@ -134,12 +174,18 @@ impl State for Running {
                         },
                         Err(err) => {
                             error!(message = "error while running", error = format!("{:?}", err), job_id = ctx.config.id);
-                            if ctx.status.restarts >= RESTARTS_ALLOWED as i32 {
+                            if ctx.record_failure().is_none() {
                                 return Err(fatal(
                                     "too many job failures",
                                     err
                                 ));
                             }
+                            if let Some(group) = ctx.job_controller.as_ref().unwrap().failover_group() {
+                                return Ok(Transition::next(
+                                    *self,
+                                    RecoveringRegion { group }
+                                ));
+                            }
                             return Ok(Transition::next(
                                 *self,
                                 Recovering {}
@@ -147,6 +193,13 @@ impl State for Running {
                         }
                     }
                 }
+                _ = tokio::time::sleep(schedule_end.unwrap_or(Duration::MAX)) => {
+                    // the job's schedule has ended, so stop it with a final checkpoint
+                    return Ok(Transition::next(
+                        *self,
+                        CheckpointStopping {},
+                    ));
+                }
                 _ = tokio::time::sleep(ttl_end.unwrap_or(Duration::MAX)) => {
                     // TTL has expired, stop the job
                     return Ok(Transition::next(
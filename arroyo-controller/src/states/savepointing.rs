@@ -0,0 +1,248 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use arroyo_state::{BackingStore, StateBackend};
+use deadpool_postgres::Pool;
+use time::OffsetDateTime;
+use tracing::{error, info, warn};
+
+use crate::queries::controller_queries;
+use crate::types::public::SavepointState;
+use crate::{JobConfig, JobMessage};
+
+use super::{
+    fatal, recovering::Recovering, running::Running, Context, State, StateError, Transition,
+};
+
+/// Takes a checkpoint of the running job and copies it to savepoint storage, where it is kept
+/// independently of the job's own checkpoints
+#[derive(Debug)]
+pub struct Savepointing {
+    pub savepoint_id: String,
+}
+
+// returns the savepoint that has been requested for the job, unless we have already handled it
+pub(crate) fn requested_savepoint(
+    config: &JobConfig,
+    last_savepoint: &Option<String>,
+) -> Option<String> {
+    config
+        .requested_savepoint
+        .clone()
+        .filter(|id| Some(id) != last_savepoint.as_ref())
+}
+
+// marks the savepoint as in progress, returning false if it was already started
+async fn start_savepoint(pool: &Pool, savepoint_id: &str) -> anyhow::Result<bool> {
+    let c = pool.get().await?;
+    let updated = controller_queries::start_savepoint()
+        .bind(&c, &savepoint_id)
+        .await?;
+    Ok(updated > 0)
+}
+
+async fn finish_savepoint(
+    pool: &Pool,
+    job_id: &str,
+    savepoint_id: &str,
+    epoch: Option<u32>,
+    result: anyhow::Result<()>,
+) {
+    let (state, failure_message) = match result {
+        Ok(()) => {
+            info!(message = "finished savepoint", job_id, savepoint_id, epoch);
+            (SavepointState::ready, None)
+        }
+        Err(e) => {
+            error!(
+                message = "savepoint failed",
+                job_id,
+                savepoint_id,
+                error = format!("{:?}", e)
+            );
+            (SavepointState::failed, Some(e.to_string()))
+        }
+    };
+
+    let res = match pool.get().await {
+        Ok(c) => controller_queries::finish_savepoint()
+            .bind(
+                &c,
+                &state,
+                &epoch.map(|epoch| epoch as i32),
+                &OffsetDateTime::now_utc(),
+                &failure_message,
+                &savepoint_id,
+            )
+            .await
+            .map_err(anyhow::Error::from),
+        Err(e) => Err(e.into()),
+    };
+
+    if let Err(e) = res {
+        warn!(
+            message = "failed to record savepoint result",
+            job_id,
+            savepoint_id,
+            error = format!("{:?}", e)
+        );
+    }
+}
+
+async fn write_savepoint(job_id: String, epoch: u32, savepoint_id: String) -> anyhow::Result<()> {
+    let metadata = StateBackend::load_checkpoint_metadata(&job_id, epoch)
+        .await?
+        .ok_or_else(|| anyhow!("checkpoint {} not found for job {}", epoch, job_id))?;
+    StateBackend::write_savepoint(metadata, &savepoint_id).await
+}
+
+/// Saves the last completed checkpoint of a job that is not running
+pub(crate) async fn save_last_checkpoint(pool: &Pool, job_id: &str, savepoint_id: &str) {
+    match start_savepoint(pool, savepoint_id).await {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            warn!(
+                message = "failed to start savepoint",
+                job_id,
+                savepoint_id,
+                error = format!("{:?}", e)
+            );
+            return;
+        }
+    }
+
+    let result: anyhow::Result<u32> = async {
+        let c = pool.get().await?;
+        let epoch = controller_queries::last_successful_checkpoint()
+            .bind(&c, &job_id)
+            .opt()
+            .await?
+            .ok_or_else(|| anyhow!("job {} has no completed checkpoints", job_id))?
+            .epoch as u32;
+        write_savepoint(job_id.to_string(), epoch, savepoint_id.to_string()).await?;
+        Ok(epoch)
+    }
+    .await;
+
+    let epoch = result.as_ref().ok().copied();
+    finish_savepoint(pool, job_id, savepoint_id, epoch, result.map(|_| ())).await;
+}
+
+impl Savepointing {
+    // takes a new checkpoint for the savepoint, returning its epoch
+    async fn checkpoint(&self, ctx: &mut Context<'_>) -> anyhow::Result<u32> {
+        let job_controller = ctx.job_controller.as_mut().unwrap();
+        let mut epoch = None;
+
+        loop {
+            // wait for any in-progress checkpoint to finish before starting ours
+            if job_controller.checkpoint_finished().await? {
+                match epoch {
+                    Some(epoch) => return Ok(epoch),
+                    None => {
+                        job_controller.checkpoint(false).await?;
+                        epoch = Some(job_controller.epoch());
+                        continue;
+                    }
+                }
+            }
+
+            tokio::select! {
+                msg = ctx.rx.recv() => {
+                    match msg.expect("channel closed while receiving") {
+                        JobMessage::RunningMessage(msg) => {
+                            job_controller.handle_message(msg).await?;
+                        }
+                        _ => {
+                            // config changes are picked up once we're running again
+                        }
+                    }
+                }
+                _ = tokio::time::sleep(Duration::from_millis(200)) => {}
+            }
+
+            if job_controller.failed() {
+                bail!("job failed while taking savepoint");
+            }
+        }
+    }
+
+    // copies the checkpoint to savepoint storage while continuing to handle messages for the job
+    async fn copy(&self, ctx: &mut Context<'_>, epoch: u32) -> anyhow::Result<()> {
+        let job_controller = ctx.job_controller.as_mut().unwrap();
+        let mut copy = tokio::spawn(write_savepoint(
+            ctx.config.id.clone(),
+            epoch,
+            self.savepoint_id.clone(),
+        ));
+
+        loop {
+            tokio::select! {
+                result = &mut copy => {
+                    return result.map_err(anyhow::Error::from).and_then(|r| r);
+                }
+                msg = ctx.rx.recv() => {
+                    if let JobMessage::RunningMessage(msg) = msg.expect("channel closed while receiving") {
+                        if let Err(e) = job_controller.handle_message(msg).await {
+                            warn!(
+                                message = "failed to handle message while copying savepoint",
+                                job_id = ctx.config.id,
+                                error = format!("{:?}", e)
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl State for Savepointing {
+    fn name(&self) -> &'static str {
+        "Savepointing"
+    }
+
+    async fn next(self: Box<Self>, ctx: &mut Context) -> Result<Transition, StateError> {
+        ctx.last_savepoint = Some(self.savepoint_id.clone());
+
+        match start_savepoint(&ctx.pool, &self.savepoint_id).await {
+            Ok(true) => {}
+            Ok(false) => return Ok(Transition::next(*self, Running {})),
+            Err(e) => return Err(ctx.retryable(self, "failed to start savepoint", e, 10)),
+        }
+
+        info!(
+            message = "taking savepoint",
+            job_id = ctx.config.id,
+            savepoint_id = self.savepoint_id
+        );
+
+        let epoch = match self.checkpoint(ctx).await {
+            Ok(epoch) => epoch,
+            Err(e) => {
+                // the checkpoint fails when the job does, which counts against its restart policy
+                let restart = ctx.record_failure();
+                let message = format!("{:?}", e);
+                finish_savepoint(&ctx.pool, &ctx.config.id, &self.savepoint_id, None, Err(e)).await;
+                if restart.is_none() {
+                    return Err(fatal("too many job failures", anyhow!(message)));
+                }
+                return Ok(Transition::next(*self, Recovering {}));
+            }
+        };
+
+        let result = self.copy(ctx, epoch).await;
+        finish_savepoint(
+            &ctx.pool,
+            &ctx.config.id,
+            &self.savepoint_id,
+            Some(epoch),
+            result,
+        )
+        .await;
+
+        Ok(Transition::next(*self, Running {}))
+    }
+}
@@ -0,0 +1,69 @@
+use std::time::{Duration, SystemTime};
+
+use arroyo_types::{from_micros, to_micros};
+
+use crate::types::public::StopMode;
+use crate::JobMessage;
+
+use super::{Compiling, Context, State, StateError, Stopped, Transition};
+
+/// Waits until the next run of a job that runs on a schedule
+#[derive(Debug)]
+pub struct Scheduled {}
+
+// when the job's next run should start according to its schedule, or None if it has no more runs
+pub(super) fn next_run(ctx: &Context) -> Option<SystemTime> {
+    let last_start = ctx.status.start_time.map(|t| to_micros(t.into()));
+    ctx.config
+        .schedule
+        .as_ref()?
+        .next_run(last_start)
+        .map(from_micros)
+}
+
+#[async_trait::async_trait]
+impl State for Scheduled {
+    fn name(&self) -> &'static str {
+        "Scheduled"
+    }
+
+    async fn next(self: Box<Self>, ctx: &mut Context) -> Result<Transition, StateError> {
+        loop {
+            if ctx.config.stop_mode != StopMode::none {
+                return Ok(Transition::next(*self, Stopped {}));
+            }
+
+            let Some(next_run) = next_run(ctx) else {
+                return Ok(Transition::next(*self, Stopped {}));
+            };
+
+            let wait = next_run
+                .duration_since(SystemTime::now())
+                .unwrap_or(Duration::ZERO);
+
+            if wait.is_zero() {
+                return Ok(Transition::next(*self, Compiling {}));
+            }
+
+            tokio::select! {
+                msg = ctx.rx.recv() => {
+                    match msg {
+                        Some(JobMessage::ConfigUpdate(c)) => {
+                            // the job may have been stopped or rescheduled
+                            ctx.config = c;
+                        }
+                        Some(msg) => {
+                            ctx.handle(msg)?;
+                        }
+                        None => {
+                            panic!("job queue shut down");
+                        }
+                    }
+                }
+                _ = tokio::time::sleep(wait) => {
+                    // time for the next run
+                }
+            }
+        }
+    }
+}
@@ -5,37 +5,49 @@ use std::{
 };
 
 use arroyo_datastream::Program;
-use arroyo_rpc::grpc::{worker_grpc_client::WorkerGrpcClient, StartExecutionReq, TaskAssignment};
-use arroyo_types::WorkerId;
+use arroyo_rpc::grpc::{
+    worker_grpc_client::WorkerGrpcClient, CheckpointMetadata, QueryableOperator,
+    QueryableStateResp, QueryableSubtask, QueryableTable, StartExecutionReq, TaskAssignment,
+};
+use arroyo_types::{
+    range_for_server, JobResources, ResourceProfile, WorkerId, INTERPRETED_PROGRAM_ENV,
+    JOB_NAME_ENV,
+};
+use time::OffsetDateTime;
 use tokio::{sync::Mutex, task::JoinHandle};
 use tonic::{transport::Channel, Request};
 use tracing::{error, info, warn};
 
 use anyhow::anyhow;
-use arroyo_state::{parquet::StorageClient, BackingStore, StateBackend};
+use arroyo_state::{
+    schema::{self, OperatorSchemas},
+    storage::StorageClient,
+    BackingStore, StateBackend,
+};
 
-use crate::{job_controller::JobController, queries::controller_queries};
+use crate::{job_controller::JobController, queries::controller_queries, state_schemas};
 use crate::{scheduler::SchedulerError, JobMessage};
 use crate::{
     scheduler::StartPipelineReq,
     states::{fatal, StateError},
 };
 
-use super::{running::Running, Context, State, Transition};
+use super::{compiling::interpreted_program_key, running::Running, Context, State, Transition};
 
 const STARTUP_TIME: Duration = Duration::from_secs(10 * 60);
 
 #[derive(Debug, Clone)]
-struct WorkerStatus {
-    id: WorkerId,
-    data_address: String,
-    slots: usize,
+pub(super) struct WorkerStatus {
+    pub(super) id: WorkerId,
+    pub(super) rpc_address: String,
+    pub(super) data_address: String,
+    pub(super) slots: usize,
 }
 
 #[derive(Debug)]
 pub struct Scheduling {}
 
-fn slots_for_job(job: &Program) -> usize {
+pub(super) fn slots_for_job(job: &Program) -> usize {
     job.graph
         .node_weights()
         .map(|n| n.parallelism)
@@ -43,15 +55,57 @@ fn slots_for_job(job: &Program) -> usize {
         .unwrap_or(0)
 }
 
-fn compute_assignments(workers: Vec<&WorkerStatus>, program: &Program) -> Vec<TaskAssignment> {
+// the subtasks of every operator in the program
+pub(super) fn program_tasks(program: &Program) -> Vec<(String, Vec<usize>)> {
+    program
+        .graph
+        .node_weights()
+        .map(|node| (node.operator_id.clone(), (0..node.parallelism).collect()))
+        .collect()
+}
+
+pub(super) fn compute_assignments(
+    workers: Vec<&WorkerStatus>,
+    program: &Program,
+) -> Vec<TaskAssignment> {
+    assign_tasks(workers, program_tasks(program))
+}
+
+// what each slot requires when the tasks are assigned by `assign_tasks`, which puts the nth
+// subtask of every operator in the nth slot
+pub(super) fn slot_resources(
+    tasks: &[(String, Vec<usize>)],
+    resources: &JobResources,
+) -> Vec<ResourceProfile> {
+    let slots = tasks.iter().map(|(_, t)| t.len()).max().unwrap_or(0);
+    (0..slots)
+        .map(|slot| {
+            tasks
+                .iter()
+                .filter(|(_, subtasks)| subtasks.len() > slot)
+                .fold(ResourceProfile::default(), |acc, (operator_id, _)| {
+                    acc.add(&resources.for_operator(operator_id))
+                })
+        })
+        .collect()
+}
+
+// assigns each operator's subtasks to the workers in order, filling each worker's slots before
+// moving on to the next
+pub(super) fn assign_tasks(
+    mut workers: Vec<&WorkerStatus>,
+    tasks: Vec<(String, Vec<usize>)>,
+) -> Vec<TaskAssignment> {
+    workers.sort_by_key(|w| w.id.0);
+
     let mut assignments = vec![];
-    for node in program.graph.node_weights() {
+    for (operator_id, subtasks) in tasks {
         let mut worker_idx = 0;
         let mut current_count = 0;
 
-        for i in 0..node.parallelism {
+        for i in subtasks {
             assignments.push(TaskAssignment {
-                operator_id: node.operator_id.clone(),
+                operator_id: operator_id.clone(),
                 operator_subtask: i as u64,
                 worker_id: workers[worker_idx].id.0,
                 worker_addr: workers[worker_idx].data_address.clone(),
@@ -68,7 +122,51 @@ fn compute_assignments(workers: Vec<&WorkerStatus>, program: &Program) -> Vec<Ta
     assignments
 }
 
-async fn handle_worker_connect<'a>(
+// describes which worker serves state queries for each subtask, along with the schemas of the
+// tables that can be queried
+pub(super) fn queryable_state(
+    program: &Program,
+    assignments: &[TaskAssignment],
+    workers: &HashMap<WorkerId, WorkerStatus>,
+    run_id: i64,
+) -> QueryableStateResp {
+    let mut schemas = state_schemas::table_schemas(program);
+
+    QueryableStateResp {
+        run_id: run_id as u64,
+        operators: program
+            .graph
+            .node_weights()
+            .map(|node| QueryableOperator {
+                operator_id: node.operator_id.clone(),
+                tables: schemas
+                    .remove(&node.operator_id)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(name, schema)| QueryableTable {
+                        name,
+                        schema: Some(schema),
+                    })
+                    .collect(),
+                subtasks: assignments
+                    .iter()
+                    .filter(|a| a.operator_id == node.operator_id)
+                    .map(|a| {
+                        let range = range_for_server(a.operator_subtask as usize, node.parallelism);
+                        QueryableSubtask {
+                            subtask_index: a.operator_subtask as u32,
+                            min_key_hash: *range.start(),
+                            max_key_hash: *range.end(),
+                            rpc_address: workers[&WorkerId(a.worker_id)].rpc_address.clone(),
+                        }
+                    })
+                    .collect(),
+            })
+            .collect(),
+    }
+}
+
+pub(super) async fn handle_worker_connect<'a>(
     msg: JobMessage,
     workers: &mut HashMap<WorkerId, WorkerStatus>,
     worker_connects: Arc<Mutex<HashMap<WorkerId, WorkerGrpcClient<Channel>>>>,
@@ -87,6 +185,7 @@ async fn handle_worker_connect<'a>(
                 worker_id,
                 WorkerStatus {
                     id: worker_id,
+                    rpc_address: rpc_address.clone(),
                     data_address,
                     slots,
                 },
@@ -142,6 +241,185 @@ async fn handle_worker_connect<'a>(
     Ok(())
 }
 
+// records where the job's workers are and which tasks they run, so that another controller can
+// adopt the running job
+pub(super) async fn record_workers(
+    ctx: &Context<'_>,
+    workers: &HashMap<WorkerId, WorkerStatus>,
+    assignments: &[TaskAssignment],
+) -> anyhow::Result<()> {
+    let mut c = ctx.pool.get().await?;
+    let tx = c.transaction().await?;
+
+    controller_queries::delete_job_workers()
+        .bind(&tx, &ctx.config.id)
+        .await?;
+
+    for worker in workers.values() {
+        let tasks: Vec<_> = assignments
+            .iter()
+            .filter(|a| a.worker_id == worker.id.0)
+            .map(|a| (a.operator_id.clone(), a.operator_subtask))
+            .collect();
+
+        controller_queries::add_job_worker()
+            .bind(
+                &tx,
+                &ctx.config.id,
+                &ctx.status.run_id,
+                &(worker.id.0 as i64),
+                &worker.rpc_address,
+                &worker.data_address,
+                &(worker.slots as i32),
+                &serde_json::to_value(tasks)?,
+            )
+            .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+// loads the workers of the job's current run and the tasks that were assigned to them
+pub(super) async fn load_workers(
+    ctx: &Context<'_>,
+) -> anyhow::Result<(HashMap<WorkerId, WorkerStatus>, Vec<TaskAssignment>)> {
+    let c = ctx.pool.get().await?;
+    let rows = controller_queries::get_job_workers()
+        .bind(&c, &ctx.config.id, &ctx.status.run_id)
+        .all()
+        .await?;
+
+    let mut workers = HashMap::new();
+    let mut assignments = vec![];
+    for row in rows {
+        let id = WorkerId(row.worker_id as u64);
+        let tasks: Vec<(String, u64)> = serde_json::from_value(row.tasks)?;
+        assignments.extend(
+            tasks
+                .into_iter()
+                .map(|(operator_id, subtask)| TaskAssignment {
+                    operator_id,
+                    operator_subtask: subtask,
+                    worker_id: id.0,
+                    worker_addr: row.data_address.clone(),
+                }),
+        );
+
+        workers.insert(
+            id,
+            WorkerStatus {
+                id,
+                rpc_address: row.rpc_address,
+                data_address: row.data_address,
+                slots: row.slots as usize,
+            },
+        );
+    }
+
+    Ok((workers, assignments))
+}
+
+// the request to start workers for the job's tasks, with the environment they need to run them
+pub(super) fn start_pipeline_req(
+    ctx: &Context<'_>,
+    tasks: &[(String, Vec<usize>)],
+) -> StartPipelineReq {
+    let slot_resources = slot_resources(tasks, &ctx.config.resources);
+    StartPipelineReq {
+        pipeline_path: ctx.status.pipeline_path.clone().unwrap(),
+        wasm_path: ctx.status.wasm_path.clone().unwrap(),
+        job_id: ctx.config.id.clone(),
+        run_id: ctx.status.run_id,
+        name: ctx.config.pipeline_name.clone(),
+        hash: ctx.program.get_hash(),
+        slots: slot_resources.len(),
+        slot_resources,
+        env_vars: StorageClient::get_storage_environment_variables(&ctx.config.id)
+            .into_iter()
+            .chain(ctx.config.state_backend_env_vars())
+            .chain(ctx.config.checkpoint_env_vars())
+            .chain(ctx.config.bounds_env_vars())
+            .chain(std::iter::once((
+                JOB_NAME_ENV.to_string(),
+                ctx.config.pipeline_name.clone(),
+            )))
+            .chain(ctx.program.is_interpreted().then(|| {
+                (
+                    INTERPRETED_PROGRAM_ENV.to_string(),
+                    interpreted_program_key(&ctx.config.id, ctx.program),
+                )
+            }))
+            .collect(),
+    }
+}
+
+async fn restore_savepoint(ctx: &Context<'_>, savepoint_id: &str) -> anyhow::Result<(u32, u32)> {
+    info!(
+        message = "restoring savepoint",
+        job_id = ctx.config.id,
+        savepoint_id
+    );
+
+    let metadata = StateBackend::restore_savepoint(savepoint_id, &ctx.config.id).await?;
+    record_restored_checkpoint(ctx, &metadata).await?;
+
+    Ok((metadata.epoch, metadata.min_epoch))
+}
+
+// records a checkpoint that wasn't taken by the current run of the job as the job's latest
+// successful checkpoint, so that it's restored when the job is scheduled
+pub(super) async fn record_restored_checkpoint(
+    ctx: &Context<'_>,
+    metadata: &CheckpointMetadata,
+) -> anyhow::Result<()> {
+    let now = OffsetDateTime::now_utc();
+    let c = ctx.pool.get().await?;
+    controller_queries::create_restored_checkpoint()
+        .bind(
+            &c,
+            &ctx.config.organization_id,
+            &ctx.config.id,
+            &StateBackend::name().to_string(),
+            &(metadata.epoch as i32),
+            &(metadata.min_epoch as i32),
+            &now,
+            &now,
+        )
+        .await?;
+    Ok(())
+}
+
+async fn check_state_compatibility(
+    metadata: &CheckpointMetadata,
+    schemas: &OperatorSchemas,
+) -> anyhow::Result<()> {
+    for operator_id in &metadata.operator_ids {
+        let Some(new_schemas) = schemas.get(operator_id) else {
+            continue;
+        };
+        let operator_metadata =
+            StateBackend::load_operator_metadata(&metadata.job_id, operator_id, metadata.epoch)
+                .await?
+                .ok_or_else(|| {
+                    anyhow!(
+                        "missing metadata for operator {}, epoch {}",
+                        operator_id,
+                        metadata.epoch
+                    )
+                })?;
+
+        // checkpoints taken before schemas were recorded can't be checked
+        for table in &operator_metadata.tables {
+            if let (Some(old), Some(new)) = (&table.schema, new_schemas.get(&table.name)) {
+                schema::check_compatibility(&table.name, old, new)
+                    .map_err(|e| anyhow!("operator {}: {}", operator_id, e))?;
+            }
+        }
+    }
+    Ok(())
+}
+
 #[async_trait::async_trait]
 impl State for Scheduling {
     fn name(&self) -> &'static str {
@@ -150,6 +428,7 @@ impl State for Scheduling {
 
     async fn next(self: Box<Self>, ctx: &mut Context) -> Result<Transition, StateError> {
         // clear out any existing workers for this job
+        ctx.set_queryable_state(None);
         if let Err(e) = ctx.scheduler.clean_cluster(&ctx.config.id).await {
             warn!(
                 message = "failed to clean cluster prior to scheduling",
@@ -161,22 +440,92 @@ impl State for Scheduling {
         ctx.program
             .update_parallelism(&ctx.config.parallelism_overrides);
 
+        let restore_epoch = {
+            // TODO: better error handling
+            let c = ctx.pool.get().await.unwrap();
+            controller_queries::last_successful_checkpoint()
+                .bind(&c, &ctx.config.id)
+                .opt()
+                .await
+                .unwrap()
+                .map(|r| {
+                    info!(
+                        message = "restoring checkpoint",
+                        job_id = ctx.config.id,
+                        epoch = r.epoch,
+                        min_epoch = r.min_epoch
+                    );
+
+                    (r.epoch as u32, r.min_epoch as u32)
+                })
+        };
+
+        // a new job started from a savepoint restores it as its first checkpoint
+        let restore_epoch = match (restore_epoch, &ctx.config.restore_savepoint) {
+            (None, Some(savepoint_id)) => match restore_savepoint(ctx, savepoint_id).await {
+                Ok(epoch) => Some(epoch),
+                Err(e) => {
+                    return Err(ctx.retryable(self, "failed to restore savepoint", e, 10));
+                }
+            },
+            (restore_epoch, _) => restore_epoch,
+        };
+
+        // check that the state we're restoring can be read by the current program before starting
+        // any workers, migrating it if the types of its tables have changed compatibly
+        let restore_epoch = if let Some((epoch, min_epoch)) = restore_epoch {
+            let schemas = state_schemas::table_schemas(ctx.program);
+            let metadata = match StateBackend::load_checkpoint_metadata(&ctx.config.id, epoch).await
+            {
+                Ok(metadata) => metadata.unwrap_or_else(|| {
+                    panic!("epoch {} not found for job {}", epoch, ctx.config.id)
+                }),
+                Err(e) => {
+                    return Err(ctx.retryable(self, "failed to load checkpoint metadata", e, 10));
+                }
+            };
+
+            if let Err(e) = check_state_compatibility(&metadata, &schemas).await {
+                return Err(fatal(
+                    format!(
+                        "The pipeline's state cannot be restored from checkpoint {}: {}",
+                        epoch, e
+                    ),
+                    e,
+                ));
+            }
+
+            // the migrated state is written as a new checkpoint, which is only restored from once
+            // it's been recorded as the job's latest
+            match StateBackend::migrate_checkpoint(&metadata, &schemas).await {
+                Ok(Some(migrated)) => {
+                    if let Err(e) = record_restored_checkpoint(ctx, &migrated).await {
+                        return Err(ctx.retryable(
+                            self,
+                            "failed to record migrated checkpoint",
+                            e,
+                            10,
+                        ));
+                    }
+                    Some((migrated.epoch, migrated.min_epoch))
+                }
+                Ok(None) => Some((epoch, min_epoch)),
+                Err(e) => {
+                    return Err(ctx.retryable(self, "failed to migrate checkpointed state", e, 10));
+                }
+            }
+        } else {
+            None
+        };
+
         let slots_needed = slots_for_job(ctx.program);
+        let tasks = program_tasks(ctx.program);
 
         // start workers
         loop {
             match ctx
                 .scheduler
-                .start_workers(StartPipelineReq {
-                    pipeline_path: ctx.status.pipeline_path.clone().unwrap(),
-                    wasm_path: ctx.status.wasm_path.clone().unwrap(),
-                    job_id: ctx.config.id.clone(),
-                    run_id: ctx.status.run_id,
-                    name: ctx.config.pipeline_name.clone(),
-                    hash: ctx.program.get_hash(),
-                    slots: slots_needed,
-                    env_vars: StorageClient::get_storage_environment_variables(),
-                })
+                .start_workers(start_pipeline_req(ctx, &tasks))
                 .await
             {
                 Ok(_) => break,
@@ -188,6 +537,12 @@ impl State for Scheduling {
                         slots_needed = s
                     );
                 }
+                Err(SchedulerError::Unschedulable(reason)) => {
+                    return Err(fatal(
+                        "job cannot be scheduled on this cluster",
+                        anyhow!(reason),
+                    ));
+                }
                 Err(SchedulerError::Other(s)) => {
                     return Err(ctx.retryable(
                         self,
@@ -245,21 +600,6 @@ impl State for Scheduling {
 
         // TODO: better error handling
         let c = ctx.pool.get().await.unwrap();
-        let restore_epoch = controller_queries::last_successful_checkpoint()
-            .bind(&c, &ctx.config.id)
-            .opt()
-            .await
-            .unwrap()
-            .map(|r| {
-                info!(
-                    message = "restoring checkpoint",
-                    job_id = ctx.config.id,
-                    epoch = r.epoch,
-                    min_epoch = r.min_epoch
-                );
-
-                (r.epoch as u32, r.min_epoch as u32)
-            });
 
         {
             // mark in-progress checkpoints as failed
@@ -272,14 +612,23 @@ impl State for Scheduling {
 
         // clear all of the epochs after the one we're loading so that we don't read in-progress data
         if let Some((epoch, min_epoch)) = restore_epoch {
-            let mut metadata = StateBackend::load_checkpoint_metadata(&ctx.config.id, epoch)
+            let mut metadata = match StateBackend::load_checkpoint_metadata(&ctx.config.id, epoch)
                 .await
-                .unwrap_or_else(|| panic!("epoch {} not found for job {}", epoch, ctx.config.id));
+            {
+                Ok(metadata) => metadata.unwrap_or_else(|| {
+                    panic!("epoch {} not found for job {}", epoch, ctx.config.id)
+                }),
+                Err(e) => {
+                    return Err(ctx.retryable(self, "failed to load checkpoint metadata", e, 10));
+                }
+            };
             if let Err(e) = StateBackend::prepare_checkpoint(&metadata).await {
                 return Err(ctx.retryable(self, "failed to prepare checkpoint for loading", e, 10));
             }
             metadata.min_epoch = min_epoch;
-            StateBackend::complete_checkpoint(metadata).await;
+            if let Err(e) = StateBackend::complete_checkpoint(metadata).await {
+                return Err(ctx.retryable(self, "failed to prepare checkpoint for loading", e, 10));
+            }
         }
 
         let assignments = compute_assignments(workers.values().collect(), ctx.program);
@@ -337,6 +686,11 @@ impl State for Scheduling {
             }
         }
 
+        // record where the workers are so that another controller can adopt the running job
+        if let Err(e) = record_workers(ctx, &workers, &assignments).await {
+            return Err(ctx.retryable(self, "failed to record workers", e, 10));
+        }
+
         // wait until all tasks are running
         let mut started = HashSet::new();
         while started.len() < ctx.program.task_count() {
@@ -358,6 +712,12 @@ impl State for Scheduling {
         }
 
         ctx.status.tasks = Some(ctx.program.task_count() as i32);
+        ctx.set_queryable_state(Some(queryable_state(
+            ctx.program,
+            &assignments,
+            &workers,
+            ctx.status.run_id,
+        )));
 
         let controller = JobController::new(
             ctx.pool.clone(),
@@ -366,6 +726,7 @@ impl State for Scheduling {
             restore_epoch.map(|(epoch, _)| epoch).unwrap_or(0),
             restore_epoch.map(|(_, min_epoch)| min_epoch).unwrap_or(0),
             worker_connects,
+            assignments,
         );
 
         ctx.job_controller = Some(controller);
@@ -373,3 +734,49 @@ impl State for Scheduling {
         Ok(Transition::next(*self, Running {}))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn worker(id: u64, slots: usize) -> WorkerStatus {
+        WorkerStatus {
+            id: WorkerId(id),
+            rpc_address: format!("http://worker-{}:6900", id),
+            data_address: format!("worker-{}:6901", id),
+            slots,
+        }
+    }
+
+    #[test]
+    fn test_assignments_independent_of_worker_order() {
+        // a controller that adopts a job rebuilds the assignments from workers loaded in whatever
+        // order the database returns them, so they must match those the job was scheduled with
+        let workers = [worker(3, 1), worker(1, 2), worker(2, 1)];
+        let tasks = vec![
+            ("source".to_string(), vec![0, 1, 2, 3]),
+            ("sink".to_string(), vec![0, 1]),
+        ];
+
+        let assignments = assign_tasks(workers.iter().collect(), tasks.clone());
+        let reversed = assign_tasks(workers.iter().rev().collect(), tasks);
+
+        assert_eq!(assignments, reversed);
+
+        let placement: Vec<_> = assignments
+            .iter()
+            .map(|a| (a.operator_id.as_str(), a.operator_subtask, a.worker_id))
+            .collect();
+        assert_eq!(
+            placement,
+            vec![
+                ("source", 0, 1),
+                ("source", 1, 1),
+                ("source", 2, 2),
+                ("source", 3, 3),
+                ("sink", 0, 1),
+                ("sink", 1, 1),
+            ]
+        );
+    }
+}
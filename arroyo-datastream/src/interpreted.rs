@@ -0,0 +1,973 @@
+//! Expressions and aggregates that are evaluated over dynamically-typed rows. Operators built from
+//! these can run on the precompiled interpreted worker, so simple SQL pipelines don't need to
+//! go through code generation and cargo.
+
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, SystemTime};
+
+use arroyo_types::functions::{hash, strings};
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+/// The type name used on edges that carry rows between interpreted operators
+pub const ROW_TYPE: &str = "arroyo_datastream::interpreted::Row";
+
+#[derive(Clone, Debug, Encode, Decode, Serialize, Deserialize)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    Timestamp(SystemTime),
+    Struct(Vec<Value>),
+}
+
+/// The values of a record, in the order of the fields of its SQL struct
+pub type Row = Vec<Value>;
+
+impl Value {
+    fn rank(&self) -> u8 {
+        match self {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Int(_) => 2,
+            Value::UInt(_) => 3,
+            Value::Float(_) => 4,
+            Value::String(_) => 5,
+            Value::Bytes(_) => 6,
+            Value::Timestamp(_) => 7,
+            Value::Struct(_) => 8,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            Value::UInt(u) => Some(*u as i64),
+            Value::Float(f) => Some(*f as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(i) => Some(*i as f64),
+            Value::UInt(u) => Some(*u as f64),
+            Value::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    fn as_struct(&self) -> Option<&[Value]> {
+        match self {
+            Value::Struct(fields) => Some(fields),
+            _ => None,
+        }
+    }
+
+    fn to_text(&self) -> Option<String> {
+        match self {
+            Value::Bool(b) => Some(b.to_string()),
+            Value::Int(i) => Some(i.to_string()),
+            Value::UInt(u) => Some(u.to_string()),
+            Value::Float(f) => Some(f.to_string()),
+            Value::String(s) => Some(s.clone()),
+            _ => None,
+        }
+    }
+}
+
+// Equality, ordering and hashing are structural, so that rows can be used as keys. SQL comparisons,
+// which coerce between numeric types and propagate nulls, are implemented by `ComparisonOp`.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Int(a), Value::Int(b)) => a.cmp(b),
+            (Value::UInt(a), Value::UInt(b)) => a.cmp(b),
+            (Value::Float(a), Value::Float(b)) => a.total_cmp(b),
+            (Value::String(a), Value::String(b)) => a.cmp(b),
+            (Value::Bytes(a), Value::Bytes(b)) => a.cmp(b),
+            (Value::Timestamp(a), Value::Timestamp(b)) => a.cmp(b),
+            (Value::Struct(a), Value::Struct(b)) => a.cmp(b),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rank().hash(state);
+        match self {
+            Value::Null => {}
+            Value::Bool(b) => b.hash(state),
+            Value::Int(i) => i.hash(state),
+            Value::UInt(u) => u.hash(state),
+            Value::Float(f) => f.to_bits().hash(state),
+            Value::String(s) => s.hash(state),
+            Value::Bytes(b) => b.hash(state),
+            Value::Timestamp(t) => t.hash(state),
+            Value::Struct(fields) => fields.hash(state),
+        }
+    }
+}
+
+enum Numbers {
+    Int(i64, i64),
+    UInt(u64, u64),
+    Float(f64, f64),
+}
+
+fn numbers(left: &Value, right: &Value) -> Option<Numbers> {
+    match (left, right) {
+        (Value::Int(a), Value::Int(b)) => Some(Numbers::Int(*a, *b)),
+        (Value::UInt(a), Value::UInt(b)) => Some(Numbers::UInt(*a, *b)),
+        (Value::Int(a), Value::UInt(b)) => Some(Numbers::Int(*a, *b as i64)),
+        (Value::UInt(a), Value::Int(b)) => Some(Numbers::Int(*a as i64, *b)),
+        (Value::Float(_), _) | (_, Value::Float(_)) => {
+            Some(Numbers::Float(left.as_f64()?, right.as_f64()?))
+        }
+        _ => None,
+    }
+}
+
+/// Describes a field of a row, used to convert typed records into rows and to serialize rows
+/// with their field names
+#[derive(Clone, Debug, Encode, Decode, Serialize, Deserialize, PartialEq)]
+pub struct RowField {
+    pub name: String,
+    pub typ: RowType,
+}
+
+#[derive(Clone, Debug, Encode, Decode, Serialize, Deserialize, PartialEq)]
+pub enum RowType {
+    Bool,
+    Int,
+    UInt,
+    Float,
+    String,
+    Bytes,
+    Timestamp,
+    Struct(Vec<RowField>),
+}
+
+#[derive(Clone, Debug, Encode, Decode, Serialize, Deserialize, PartialEq)]
+pub enum Expr {
+    Column(usize),
+    StructField(Box<Expr>, usize),
+    Literal(Value),
+    Unary(UnaryOp, Box<Expr>),
+    Comparison(Box<Expr>, ComparisonOp, Box<Expr>),
+    Math(Box<Expr>, MathOp, Box<Expr>),
+    Cast(Box<Expr>, CastType),
+    Numeric(NumericOp, Box<Expr>),
+    String(StringOp, Vec<Expr>),
+    Hash(HashOp, Box<Expr>),
+}
+
+impl Expr {
+    pub fn eval(&self, row: &[Value]) -> Value {
+        match self {
+            Expr::Column(i) => row[*i].clone(),
+            Expr::StructField(expr, i) => match expr.eval(row) {
+                Value::Struct(mut fields) => fields.swap_remove(*i),
+                _ => Value::Null,
+            },
+            Expr::Literal(value) => value.clone(),
+            Expr::Unary(op, expr) => op.eval(expr.eval(row)),
+            Expr::Comparison(left, op, right) => op.eval(left.eval(row), right.eval(row)),
+            Expr::Math(left, op, right) => op.eval(&left.eval(row), &right.eval(row)),
+            Expr::Cast(expr, typ) => typ.cast(expr.eval(row)),
+            Expr::Numeric(op, expr) => match expr.eval(row).as_f64() {
+                Some(x) => Value::Float(op.eval(x)),
+                None => Value::Null,
+            },
+            Expr::String(op, args) => op.eval(args.iter().map(|arg| arg.eval(row)).collect()),
+            Expr::Hash(op, expr) => op.eval(expr.eval(row)),
+        }
+    }
+
+    /// Evaluates a predicate, treating null as false
+    pub fn eval_predicate(&self, row: &[Value]) -> bool {
+        self.eval(row).as_bool().unwrap_or(false)
+    }
+}
+
+#[derive(Clone, Debug, Encode, Decode, Serialize, Deserialize, PartialEq)]
+pub enum UnaryOp {
+    IsNotNull,
+    IsNull,
+    IsTrue,
+    IsFalse,
+    IsUnknown,
+    IsNotTrue,
+    IsNotFalse,
+    IsNotUnknown,
+    Negative,
+}
+
+impl UnaryOp {
+    fn eval(&self, value: Value) -> Value {
+        match self {
+            UnaryOp::IsNotNull | UnaryOp::IsNotUnknown => Value::Bool(!value.is_null()),
+            UnaryOp::IsNull | UnaryOp::IsUnknown => Value::Bool(value.is_null()),
+            UnaryOp::IsTrue => Value::Bool(value.as_bool().unwrap_or(false)),
+            UnaryOp::IsFalse => Value::Bool(!value.as_bool().unwrap_or(true)),
+            UnaryOp::IsNotTrue => Value::Bool(!value.as_bool().unwrap_or(false)),
+            UnaryOp::IsNotFalse => Value::Bool(value.as_bool().unwrap_or(true)),
+            UnaryOp::Negative => match value {
+                Value::Int(i) => Value::Int(i.wrapping_neg()),
+                Value::UInt(u) => Value::Int((u as i64).wrapping_neg()),
+                Value::Float(f) => Value::Float(-f),
+                _ => Value::Null,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Encode, Decode, Serialize, Deserialize, PartialEq)]
+pub enum ComparisonOp {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    IsDistinctFrom,
+    IsNotDistinctFrom,
+    And,
+    Or,
+}
+
+impl ComparisonOp {
+    fn eval(&self, left: Value, right: Value) -> Value {
+        match self {
+            ComparisonOp::IsDistinctFrom => return Value::Bool(left != right),
+            ComparisonOp::IsNotDistinctFrom => return Value::Bool(left == right),
+            _ => {}
+        }
+
+        if left.is_null() || right.is_null() {
+            return Value::Null;
+        }
+
+        let ordering = match numbers(&left, &right) {
+            Some(Numbers::Int(a, b)) => a.cmp(&b),
+            Some(Numbers::UInt(a, b)) => a.cmp(&b),
+            Some(Numbers::Float(a, b)) => match a.partial_cmp(&b) {
+                Some(ordering) => ordering,
+                None => return Value::Bool(matches!(self, ComparisonOp::NotEq)),
+            },
+            None => left.cmp(&right),
+        };
+
+        let result = match self {
+            ComparisonOp::Eq => ordering == Ordering::Equal,
+            ComparisonOp::NotEq => ordering != Ordering::Equal,
+            ComparisonOp::Lt => ordering == Ordering::Less,
+            ComparisonOp::LtEq => ordering != Ordering::Greater,
+            ComparisonOp::Gt => ordering == Ordering::Greater,
+            ComparisonOp::GtEq => ordering != Ordering::Less,
+            ComparisonOp::And | ComparisonOp::Or => {
+                let (Some(left), Some(right)) = (left.as_bool(), right.as_bool()) else {
+                    return Value::Null;
+                };
+                if *self == ComparisonOp::And {
+                    left && right
+                } else {
+                    left || right
+                }
+            }
+            ComparisonOp::IsDistinctFrom | ComparisonOp::IsNotDistinctFrom => unreachable!(),
+        };
+        Value::Bool(result)
+    }
+}
+
+#[derive(Clone, Debug, Encode, Decode, Serialize, Deserialize, PartialEq)]
+pub enum MathOp {
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    Modulo,
+}
+
+impl MathOp {
+    fn eval(&self, left: &Value, right: &Value) -> Value {
+        match numbers(left, right) {
+            Some(Numbers::Int(a, b)) => match self {
+                MathOp::Plus => Some(a.wrapping_add(b)),
+                MathOp::Minus => Some(a.wrapping_sub(b)),
+                MathOp::Multiply => Some(a.wrapping_mul(b)),
+                MathOp::Divide => a.checked_div(b),
+                MathOp::Modulo => a.checked_rem(b),
+            }
+            .map(Value::Int),
+            Some(Numbers::UInt(a, b)) => match self {
+                MathOp::Plus => Some(a.wrapping_add(b)),
+                MathOp::Minus => Some(a.wrapping_sub(b)),
+                MathOp::Multiply => Some(a.wrapping_mul(b)),
+                MathOp::Divide => a.checked_div(b),
+                MathOp::Modulo => a.checked_rem(b),
+            }
+            .map(Value::UInt),
+            Some(Numbers::Float(a, b)) => Some(Value::Float(match self {
+                MathOp::Plus => a + b,
+                MathOp::Minus => a - b,
+                MathOp::Multiply => a * b,
+                MathOp::Divide => a / b,
+                MathOp::Modulo => a % b,
+            })),
+            None => None,
+        }
+        .unwrap_or(Value::Null)
+    }
+}
+
+#[derive(Clone, Debug, Encode, Decode, Serialize, Deserialize, PartialEq)]
+pub enum CastType {
+    Int8,
+    Int16,
+    Int32,
+    Int64,
+    UInt8,
+    UInt16,
+    UInt32,
+    UInt64,
+    Float32,
+    Float64,
+    String,
+}
+
+impl CastType {
+    fn cast(&self, value: Value) -> Value {
+        if value.is_null() {
+            return Value::Null;
+        }
+
+        match self {
+            CastType::String => value.to_text().map(Value::String),
+            CastType::Float32 | CastType::Float64 => {
+                let f = match &value {
+                    Value::String(s) => s.trim().parse::<f64>().ok(),
+                    v => v.as_f64(),
+                };
+                f.map(|f| match self {
+                    CastType::Float32 => Value::Float(f as f32 as f64),
+                    _ => Value::Float(f),
+                })
+            }
+            _ => {
+                let i = match &value {
+                    Value::String(s) => s.trim().parse::<i128>().ok(),
+                    Value::Int(i) => Some(*i as i128),
+                    Value::UInt(u) => Some(*u as i128),
+                    Value::Float(f) => Some(*f as i128),
+                    Value::Bool(b) => Some(*b as i128),
+                    _ => None,
+                };
+                i.map(|i| match self {
+                    CastType::Int8 => Value::Int(i as i8 as i64),
+                    CastType::Int16 => Value::Int(i as i16 as i64),
+                    CastType::Int32 => Value::Int(i as i32 as i64),
+                    CastType::Int64 => Value::Int(i as i64),
+                    CastType::UInt8 => Value::UInt(i as u8 as u64),
+                    CastType::UInt16 => Value::UInt(i as u16 as u64),
+                    CastType::UInt32 => Value::UInt(i as u32 as u64),
+                    CastType::UInt64 => Value::UInt(i as u64),
+                    _ => unreachable!(),
+                })
+            }
+        }
+        .unwrap_or(Value::Null)
+    }
+}
+
+#[derive(Clone, Debug, Encode, Decode, Serialize, Deserialize, PartialEq)]
+pub enum NumericOp {
+    Abs,
+    Acos,
+    Asin,
+    Atan,
+    Cos,
+    Ln,
+    Log10,
+    Log2,
+    Sin,
+    Sqrt,
+    Tan,
+    Ceil,
+    Floor,
+    Round,
+    Signum,
+    Trunc,
+    Exp,
+}
+
+impl NumericOp {
+    fn eval(&self, x: f64) -> f64 {
+        match self {
+            NumericOp::Abs => x.abs(),
+            NumericOp::Acos => x.acos(),
+            NumericOp::Asin => x.asin(),
+            NumericOp::Atan => x.atan(),
+            NumericOp::Cos => x.cos(),
+            NumericOp::Ln => x.ln(),
+            NumericOp::Log10 => x.log10(),
+            NumericOp::Log2 => x.log2(),
+            NumericOp::Sin => x.sin(),
+            NumericOp::Sqrt => x.sqrt(),
+            NumericOp::Tan => x.tan(),
+            NumericOp::Ceil => x.ceil(),
+            NumericOp::Floor => x.floor(),
+            NumericOp::Round => x.round(),
+            NumericOp::Signum => x.signum(),
+            NumericOp::Trunc => x.trunc(),
+            NumericOp::Exp => x.exp(),
+        }
+    }
+}
+
+/// String functions, applied to the arguments of `Expr::String`; the optional trailing arguments
+/// of trims and pads may be omitted
+#[derive(Clone, Debug, Encode, Decode, Serialize, Deserialize, PartialEq)]
+pub enum StringOp {
+    Ascii,
+    BitLength,
+    CharacterLength,
+    OctetLength,
+    Chr,
+    InitCap,
+    Lower,
+    Upper,
+    Reverse,
+    Trim,
+    Ltrim,
+    Rtrim,
+    StartsWith,
+    Strpos,
+    Left,
+    Right,
+    Lpad,
+    Rpad,
+    Repeat,
+    Replace,
+    Substr,
+    Translate,
+    SplitPart,
+    Concat,
+    ConcatWithSeparator,
+}
+
+impl StringOp {
+    fn eval(&self, args: Vec<Value>) -> Value {
+        match self {
+            // nulls are skipped when concatenating
+            StringOp::Concat => {
+                return Value::String(args.iter().filter_map(Value::to_text).collect());
+            }
+            StringOp::ConcatWithSeparator => {
+                let Some(separator) = args[0].to_text() else {
+                    return Value::Null;
+                };
+                let parts: Vec<_> = args[1..].iter().filter_map(Value::to_text).collect();
+                return Value::String(parts.join(&separator));
+            }
+            _ => {}
+        }
+
+        if args.iter().any(Value::is_null) {
+            return Value::Null;
+        }
+
+        let text = |i: usize| args.get(i).and_then(Value::to_text);
+        let int = |i: usize| args.get(i).and_then(Value::as_i64);
+        let Some(s) = text(0) else {
+            return Value::Null;
+        };
+
+        match self {
+            StringOp::Ascii => Value::Int(strings::ascii(s) as i64),
+            StringOp::BitLength => Value::Int(strings::bit_length(s) as i64),
+            StringOp::CharacterLength => Value::Int(s.chars().count() as i64),
+            StringOp::OctetLength => Value::Int(s.len() as i64),
+            StringOp::Chr => match int(0) {
+                Some(i) => Value::String(strings::chr(i)),
+                None => Value::Null,
+            },
+            StringOp::InitCap => Value::String(strings::initcap(s)),
+            StringOp::Lower => Value::String(s.to_lowercase()),
+            StringOp::Upper => Value::String(s.to_uppercase()),
+            StringOp::Reverse => Value::String(s.chars().rev().collect()),
+            StringOp::Trim => {
+                Value::String(strings::trim(s, text(1).unwrap_or_else(|| " ".to_string())))
+            }
+            StringOp::Ltrim => Value::String(strings::ltrim(
+                s,
+                text(1).unwrap_or_else(|| " ".to_string()),
+            )),
+            StringOp::Rtrim => Value::String(strings::rtrim(
+                s,
+                text(1).unwrap_or_else(|| " ".to_string()),
+            )),
+            StringOp::StartsWith => Value::Bool(strings::starts_with(s, text(1).unwrap())),
+            StringOp::Strpos => Value::Int(strings::strpos(s, text(1).unwrap()) as i64),
+            StringOp::Left => Value::String(strings::left(s, int(1).unwrap())),
+            StringOp::Right => Value::String(strings::right(s, int(1).unwrap() as isize)),
+            StringOp::Lpad => Value::String(strings::lpad(
+                s,
+                int(1).unwrap().max(0) as usize,
+                text(2).unwrap_or_else(|| " ".to_string()),
+            )),
+            StringOp::Rpad => Value::String(strings::rpad(
+                s,
+                int(1).unwrap().max(0) as usize,
+                text(2).unwrap_or_else(|| " ".to_string()),
+            )),
+            StringOp::Repeat => Value::String(s.repeat(int(1).unwrap().max(0) as usize)),
+            StringOp::Replace => Value::String(s.replace(&text(1).unwrap(), &text(2).unwrap())),
+            StringOp::Substr => Value::String(strings::substr(
+                s,
+                int(1).unwrap().max(0) as usize,
+                int(2).map(|count| count.max(0) as usize),
+            )),
+            StringOp::Translate => {
+                Value::String(strings::translate(s, text(1).unwrap(), text(2).unwrap()))
+            }
+            StringOp::SplitPart => Value::String(strings::split_part(
+                s,
+                text(1).unwrap(),
+                int(2).unwrap() as isize,
+            )),
+            StringOp::Concat | StringOp::ConcatWithSeparator => unreachable!(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Encode, Decode, Serialize, Deserialize, PartialEq)]
+pub enum HashOp {
+    Md5,
+    Sha224,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HashOp {
+    fn eval(&self, value: Value) -> Value {
+        let bytes = match value {
+            Value::String(s) => s.into_bytes(),
+            Value::Bytes(b) => b,
+            _ => return Value::Null,
+        };
+        let digest = match self {
+            HashOp::Md5 => return Value::String(hash::md5(bytes)),
+            HashOp::Sha224 => hash::sha224(bytes),
+            HashOp::Sha256 => hash::sha256(bytes),
+            HashOp::Sha384 => hash::sha384(bytes),
+            HashOp::Sha512 => hash::sha512(bytes),
+        };
+        Value::String(digest.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+}
+
+#[derive(Clone, Debug, Encode, Decode, Serialize, Deserialize, PartialEq)]
+pub enum AggregateOp {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg,
+}
+
+/// An aggregate computed in two phases, like the generated window aggregators: rows are folded
+/// into a bin per slide, and for sliding windows the bins are combined into an in-memory value
+/// that bins can be removed from as they leave the window.
+#[derive(Clone, Debug, Encode, Decode, Serialize, Deserialize, PartialEq)]
+pub struct Aggregate {
+    pub op: AggregateOp,
+    pub input: Expr,
+}
+
+fn add(left: &Value, right: &Value) -> Value {
+    MathOp::Plus.eval(left, right)
+}
+
+fn sub(left: &Value, right: &Value) -> Value {
+    MathOp::Minus.eval(left, right)
+}
+
+fn average(count: &Value, sum: &Value) -> Value {
+    match (count.as_f64(), sum.as_f64()) {
+        (Some(count), Some(sum)) if count > 0.0 => Value::Float(sum / count),
+        _ => Value::Null,
+    }
+}
+
+impl Aggregate {
+    fn bin_merge(&self, row: &[Value], bin: Option<&Value>) -> Value {
+        let value = self.input.eval(row);
+        let bin = bin.cloned().unwrap_or(Value::Null);
+        if value.is_null() {
+            return match (&self.op, bin) {
+                (AggregateOp::Count, Value::Null) => Value::Int(0),
+                (_, bin) => bin,
+            };
+        }
+
+        match (&self.op, bin) {
+            (AggregateOp::Count, bin) => Value::Int(bin.as_i64().unwrap_or(0) + 1),
+            (AggregateOp::Avg, Value::Struct(bin)) => {
+                Value::Struct(vec![add(&bin[0], &Value::Int(1)), add(&bin[1], &value)])
+            }
+            (AggregateOp::Avg, _) => Value::Struct(vec![Value::Int(1), value]),
+            (_, Value::Null) => value,
+            (AggregateOp::Sum, bin) => add(&bin, &value),
+            (AggregateOp::Min, bin) => bin.min(value),
+            (AggregateOp::Max, bin) => bin.max(value),
+        }
+    }
+
+    fn bin_aggregate(&self, bin: &Value) -> Value {
+        match (&self.op, bin) {
+            (AggregateOp::Avg, Value::Struct(bin)) => average(&bin[0], &bin[1]),
+            (AggregateOp::Avg, _) => Value::Null,
+            (_, bin) => bin.clone(),
+        }
+    }
+
+    fn memory_add(&self, current: Option<Value>, bin: &Value) -> Value {
+        let current = current.unwrap_or_else(|| self.empty_memory());
+        let Value::Struct(mut current) = current else {
+            return match &self.op {
+                AggregateOp::Count => add(&current, bin),
+                _ => unreachable!("invalid memory for {:?}", self.op),
+            };
+        };
+
+        match (&self.op, bin) {
+            (_, Value::Null) => {}
+            (AggregateOp::Sum, bin) => {
+                current[0] = add(&current[0], &Value::Int(1));
+                current[1] = if current[1].is_null() {
+                    bin.clone()
+                } else {
+                    add(&current[1], bin)
+                };
+            }
+            (AggregateOp::Avg, Value::Struct(bin)) => {
+                current[0] = add(&current[0], &bin[0]);
+                current[1] = if current[1].is_null() {
+                    bin[1].clone()
+                } else {
+                    add(&current[1], &bin[1])
+                };
+            }
+            (AggregateOp::Min | AggregateOp::Max, bin) => {
+                // a sorted multiset of the values of the bins in the window
+                match current.binary_search_by(|entry| {
+                    entry.as_struct().and_then(|e| e.first()).cmp(&Some(bin))
+                }) {
+                    Ok(i) => {
+                        let Value::Struct(entry) = &mut current[i] else {
+                            unreachable!()
+                        };
+                        entry[1] = add(&entry[1], &Value::Int(1));
+                    }
+                    Err(i) => current.insert(i, Value::Struct(vec![bin.clone(), Value::Int(1)])),
+                }
+            }
+            (op, bin) => unreachable!("invalid bin {:?} for {:?}", bin, op),
+        }
+        Value::Struct(current)
+    }
+
+    fn memory_remove(&self, current: Value, bin: &Value) -> Value {
+        let Value::Struct(mut current) = current else {
+            return match &self.op {
+                AggregateOp::Count => sub(&current, bin),
+                _ => unreachable!("invalid memory for {:?}", self.op),
+            };
+        };
+
+        match (&self.op, bin) {
+            (_, Value::Null) => {}
+            (AggregateOp::Sum, bin) => {
+                current[0] = sub(&current[0], &Value::Int(1));
+                current[1] = if current[0].as_i64() == Some(0) {
+                    Value::Null
+                } else {
+                    sub(&current[1], bin)
+                };
+            }
+            (AggregateOp::Avg, Value::Struct(bin)) => {
+                current[0] = sub(&current[0], &bin[0]);
+                current[1] = if current[0].as_i64() == Some(0) {
+                    Value::Null
+                } else {
+                    sub(&current[1], &bin[1])
+                };
+            }
+            (AggregateOp::Min | AggregateOp::Max, bin) => {
+                if let Ok(i) = current.binary_search_by(|entry| {
+                    entry.as_struct().and_then(|e| e.first()).cmp(&Some(bin))
+                }) {
+                    let Value::Struct(entry) = &mut current[i] else {
+                        unreachable!()
+                    };
+                    entry[1] = sub(&entry[1], &Value::Int(1));
+                    if entry[1].as_i64() == Some(0) {
+                        current.remove(i);
+                    }
+                }
+            }
+            (op, bin) => unreachable!("invalid bin {:?} for {:?}", bin, op),
+        }
+        Value::Struct(current)
+    }
+
+    fn memory_aggregate(&self, current: &Value) -> Value {
+        let Value::Struct(current) = current else {
+            return current.clone();
+        };
+
+        let entry_value = |entry: Option<&Value>| match entry {
+            Some(Value::Struct(entry)) => entry[0].clone(),
+            _ => Value::Null,
+        };
+
+        match &self.op {
+            AggregateOp::Count => unreachable!("invalid memory for count"),
+            AggregateOp::Sum => current[1].clone(),
+            AggregateOp::Avg => average(&current[0], &current[1]),
+            AggregateOp::Min => entry_value(current.first()),
+            AggregateOp::Max => entry_value(current.last()),
+        }
+    }
+
+    fn empty_memory(&self) -> Value {
+        match &self.op {
+            AggregateOp::Count => Value::Int(0),
+            AggregateOp::Sum | AggregateOp::Avg => Value::Struct(vec![Value::Int(0), Value::Null]),
+            AggregateOp::Min | AggregateOp::Max => Value::Struct(vec![]),
+        }
+    }
+}
+
+/// Folds a row into the bin, which holds a value for each aggregate
+pub fn bin_merge(aggregates: &[Aggregate], row: &Row, bin: Option<&Row>) -> Row {
+    aggregates
+        .iter()
+        .enumerate()
+        .map(|(i, aggregate)| aggregate.bin_merge(row, bin.map(|bin| &bin[i])))
+        .collect()
+}
+
+/// Computes the results of a tumbling window from its bin
+pub fn bin_aggregate(aggregates: &[Aggregate], bin: &Row) -> Row {
+    aggregates
+        .iter()
+        .zip(bin)
+        .map(|(aggregate, bin)| aggregate.bin_aggregate(bin))
+        .collect()
+}
+
+/// Adds a bin to a sliding window; the first value counts the bins in the window
+pub fn memory_add(aggregates: &[Aggregate], current: Option<Row>, bin: Row) -> Row {
+    let mut current = current.map(|current| current.into_iter());
+    let bins = current
+        .as_mut()
+        .and_then(|current| current.next())
+        .unwrap_or(Value::Int(0));
+
+    let mut result = vec![add(&bins, &Value::Int(1))];
+    for (aggregate, bin) in aggregates.iter().zip(bin) {
+        let memory = current.as_mut().and_then(|current| current.next());
+        result.push(aggregate.memory_add(memory, &bin));
+    }
+    result
+}
+
+/// Removes a bin from a sliding window, returning None once no bins are left
+pub fn memory_remove(aggregates: &[Aggregate], current: Row, bin: Row) -> Option<Row> {
+    let mut current = current.into_iter();
+    let bins = current.next().and_then(|bins| bins.as_i64()).unwrap_or(0);
+    if bins <= 1 {
+        return None;
+    }
+
+    let mut result = vec![Value::Int(bins - 1)];
+    for ((aggregate, memory), bin) in aggregates.iter().zip(current).zip(bin) {
+        result.push(aggregate.memory_remove(memory, &bin));
+    }
+    Some(result)
+}
+
+/// Computes the results of a sliding window from its in-memory value
+pub fn memory_aggregate(aggregates: &[Aggregate], current: &Row) -> Row {
+    aggregates
+        .iter()
+        .zip(&current[1..])
+        .map(|(aggregate, memory)| aggregate.memory_aggregate(memory))
+        .collect()
+}
+
+#[derive(Clone, Debug, Encode, Decode, Serialize, Deserialize, PartialEq)]
+pub enum InterpretedOperator {
+    /// Converts the records of a typed source into rows with these fields
+    SourceRows(Vec<RowField>),
+    Filter(Expr),
+    Project(Vec<Expr>),
+    KeyBy(Vec<Expr>),
+    TumblingAggregate {
+        width: Duration,
+        aggregates: Vec<Aggregate>,
+    },
+    SlidingAggregate {
+        width: Duration,
+        slide: Duration,
+        aggregates: Vec<Aggregate>,
+    },
+    /// Combines the key and aggregates of a window result into an unkeyed row, with the window
+    /// inserted at `window_index` if set
+    MergeAggregate {
+        window_index: Option<usize>,
+        width: Duration,
+    },
+    /// Names the fields of rows, so that sinks can serialize them
+    SinkRows(Vec<RowField>),
+}
+
+impl InterpretedOperator {
+    pub fn name(&self) -> &'static str {
+        match self {
+            InterpretedOperator::SourceRows(_) => "source_rows",
+            InterpretedOperator::Filter(_) => "filter",
+            InterpretedOperator::Project(_) => "map",
+            InterpretedOperator::KeyBy(_) => "key",
+            InterpretedOperator::TumblingAggregate { .. } => "tumbling_aggregate",
+            InterpretedOperator::SlidingAggregate { .. } => "sliding_aggregate",
+            InterpretedOperator::MergeAggregate { .. } => "merge_aggregate",
+            InterpretedOperator::SinkRows(_) => "sink_rows",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sum_and_max() -> Vec<Aggregate> {
+        vec![
+            Aggregate {
+                op: AggregateOp::Sum,
+                input: Expr::Column(0),
+            },
+            Aggregate {
+                op: AggregateOp::Max,
+                input: Expr::Column(0),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_eval_expressions() {
+        let row = vec![
+            Value::Int(5),
+            Value::Null,
+            Value::Struct(vec![Value::String("Arroyo".to_string())]),
+        ];
+
+        let gt = Expr::Comparison(
+            Box::new(Expr::Column(0)),
+            ComparisonOp::Gt,
+            Box::new(Expr::Literal(Value::Float(4.5))),
+        );
+        assert_eq!(gt.eval(&row), Value::Bool(true));
+
+        let null_sum = Expr::Math(
+            Box::new(Expr::Column(0)),
+            MathOp::Plus,
+            Box::new(Expr::Column(1)),
+        );
+        assert_eq!(null_sum.eval(&row), Value::Null);
+        assert!(
+            !Expr::Comparison(Box::new(null_sum), ComparisonOp::Eq, Box::new(gt))
+                .eval_predicate(&row)
+        );
+
+        let upper = Expr::String(
+            StringOp::Upper,
+            vec![Expr::StructField(Box::new(Expr::Column(2)), 0)],
+        );
+        assert_eq!(upper.eval(&row), Value::String("ARROYO".to_string()));
+
+        let cast = Expr::Cast(
+            Box::new(Expr::Literal(Value::String("300".to_string()))),
+            CastType::Int8,
+        );
+        assert_eq!(cast.eval(&row), Value::Int(44));
+    }
+
+    #[test]
+    fn test_sliding_aggregates() {
+        let aggregates = sum_and_max();
+
+        let first = [3, 7]
+            .iter()
+            .fold(None, |bin, i| {
+                Some(bin_merge(&aggregates, &vec![Value::Int(*i)], bin.as_ref()))
+            })
+            .unwrap();
+        assert_eq!(
+            bin_aggregate(&aggregates, &first),
+            vec![Value::Int(10), Value::Int(7)]
+        );
+
+        let second = bin_merge(&aggregates, &vec![Value::Int(2)], None);
+
+        let memory = memory_add(&aggregates, None, first.clone());
+        let memory = memory_add(&aggregates, Some(memory), second.clone());
+        assert_eq!(
+            memory_aggregate(&aggregates, &memory),
+            vec![Value::Int(12), Value::Int(7)]
+        );
+
+        let memory = memory_remove(&aggregates, memory, first).unwrap();
+        assert_eq!(
+            memory_aggregate(&aggregates, &memory),
+            vec![Value::Int(2), Value::Int(2)]
+        );
+        assert_eq!(memory_remove(&aggregates, memory, second), None);
+    }
+}
@@ -30,6 +30,7 @@ use tonic::{Request, Status};
 use anyhow::{anyhow, bail, Result};
 use prost::Message;
 
+use crate::interpreted::InterpretedOperator;
 use crate::Operator::FusedWasmUDFs;
 use arroyo_rpc::grpc::api::{
     Aggregator, BuiltinSink, CreateJobReq, CreatePipelineReq, JobEdge, JobGraph, JobNode,
@@ -40,6 +41,8 @@ use rand::distributions::Alphanumeric;
 use rand::rngs::SmallRng;
 use rand::{Rng, SeedableRng};
 
+pub mod interpreted;
+
 pub trait ArroyoData {
     fn get_def() -> String;
 }
@@ -143,6 +146,89 @@ impl From<arroyo_rpc::grpc::api::OffsetMode> for OffsetMode {
     }
 }
 
+#[derive(Copy, Clone, Encode, Decode, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    Json,
+    Csv,
+    Parquet,
+}
+
+impl From<FileFormat> for arroyo_rpc::grpc::api::FileFormat {
+    fn from(format: FileFormat) -> Self {
+        match format {
+            FileFormat::Json => Self::Json,
+            FileFormat::Csv => Self::Csv,
+            FileFormat::Parquet => Self::Parquet,
+        }
+    }
+}
+
+impl From<arroyo_rpc::grpc::api::FileFormat> for FileFormat {
+    fn from(format: arroyo_rpc::grpc::api::FileFormat) -> Self {
+        match format {
+            arroyo_rpc::grpc::api::FileFormat::Json => Self::Json,
+            arroyo_rpc::grpc::api::FileFormat::Csv => Self::Csv,
+            arroyo_rpc::grpc::api::FileFormat::Parquet => Self::Parquet,
+        }
+    }
+}
+
+#[derive(Clone, Encode, Decode, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum KafkaMetadata {
+    Key,
+    Partition,
+    Offset,
+    Timestamp,
+    Header(String),
+}
+
+#[derive(Clone, Encode, Decode, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct KafkaMetadataField {
+    pub field_name: String,
+    pub metadata: KafkaMetadata,
+}
+
+impl From<KafkaMetadataField> for GrpcApi::KafkaMetadataField {
+    fn from(field: KafkaMetadataField) -> Self {
+        let (metadata_type, header) = match field.metadata {
+            KafkaMetadata::Key => (GrpcApi::KafkaMetadataType::Key, None),
+            KafkaMetadata::Partition => (GrpcApi::KafkaMetadataType::Partition, None),
+            KafkaMetadata::Offset => (GrpcApi::KafkaMetadataType::Offset, None),
+            KafkaMetadata::Timestamp => (GrpcApi::KafkaMetadataType::Timestamp, None),
+            KafkaMetadata::Header(header) => (GrpcApi::KafkaMetadataType::Header, Some(header)),
+        };
+
+        GrpcApi::KafkaMetadataField {
+            field_name: field.field_name,
+            metadata_type: metadata_type.into(),
+            header,
+        }
+    }
+}
+
+impl TryFrom<GrpcApi::KafkaMetadataField> for KafkaMetadataField {
+    type Error = anyhow::Error;
+
+    fn try_from(field: GrpcApi::KafkaMetadataField) -> Result<Self> {
+        let metadata = match field.metadata_type() {
+            GrpcApi::KafkaMetadataType::Key => KafkaMetadata::Key,
+            GrpcApi::KafkaMetadataType::Partition => KafkaMetadata::Partition,
+            GrpcApi::KafkaMetadataType::Offset => KafkaMetadata::Offset,
+            GrpcApi::KafkaMetadataType::Timestamp => KafkaMetadata::Timestamp,
+            GrpcApi::KafkaMetadataType::Header => KafkaMetadata::Header(
+                field
+                    .header
+                    .ok_or_else(|| anyhow!("header field {} missing header", field.field_name))?,
+            ),
+        };
+
+        Ok(KafkaMetadataField {
+            field_name: field.field_name,
+            metadata,
+        })
+    }
+}
+
 #[derive(Clone, Encode, Decode, Serialize, Deserialize, PartialEq, Eq)]
 pub enum WindowAgg {
     Count,
@@ -238,6 +324,9 @@ pub enum Operator {
     FileSource {
         dir: PathBuf,
         delay: Duration,
+        format: FileFormat,
+        monitor_interval: Option<Duration>,
+        timestamp_field: Option<String>,
     },
     ImpulseSource {
         start_time: SystemTime,
@@ -251,6 +340,7 @@ pub enum Operator {
         schema_registry: bool,
         messages_per_second: u32,
         client_configs: HashMap<String, String>,
+        metadata_fields: Vec<KafkaMetadataField>,
     },
     FusedWasmUDFs {
         name: String,
@@ -278,6 +368,8 @@ pub enum Operator {
         topic: String,
         bootstrap_servers: Vec<String>,
         client_configs: HashMap<String, String>,
+        key_field: Option<String>,
+        header_fields: Vec<String>,
     },
     NexmarkSource {
         first_event_rate: u64,
@@ -300,6 +392,30 @@ pub enum Operator {
     TumblingWindowAggregator(TumblingWindowAggregator),
     TumblingTopN(TumblingTopN),
     SlidingAggregatingTopN(SlidingAggregatingTopN),
+    KinesisSource {
+        stream_name: String,
+        region: String,
+        endpoint: Option<String>,
+        offset_mode: OffsetMode,
+    },
+    KinesisSink {
+        stream_name: String,
+        region: String,
+        endpoint: Option<String>,
+        partition_key_field: Option<String>,
+    },
+    FileSystemSink {
+        path: String,
+        format: FileFormat,
+        partition_template: String,
+        max_file_size: usize,
+        rollover: Duration,
+    },
+    Interpreted(InterpretedOperator),
+    // emits the first record for each key; keys are forgotten once they haven't been seen for `ttl`
+    Dedup {
+        ttl: Option<Duration>,
+    },
 }
 
 #[derive(Clone, Encode, Decode, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -424,6 +540,13 @@ impl Debug for Operator {
                     }
                 )
             }
+            Operator::KinesisSource { stream_name, .. } => {
+                write!(f, "KinesisSource<{}>", stream_name)
+            }
+            Operator::KinesisSink { stream_name, .. } => write!(f, "KinesisSink<{}>", stream_name),
+            Operator::FileSystemSink { path, .. } => write!(f, "FileSystemSink<{}>", path),
+            Operator::Interpreted(op) => write!(f, "Interpreted<{}>", op.name()),
+            Operator::Dedup { .. } => write!(f, "Dedup"),
         }
     }
 }
@@ -507,11 +630,30 @@ pub trait Source<T: Data> {
 pub struct FileSource {
     dir: PathBuf,
     delay: Duration,
+    format: FileFormat,
+    monitor_interval: Option<Duration>,
+    timestamp_field: Option<String>,
 }
 
 impl FileSource {
     pub fn from_dir(dir: PathBuf, delay: Duration) -> FileSource {
-        FileSource { dir, delay }
+        FileSource::new(dir, delay, FileFormat::Json, None, None)
+    }
+
+    pub fn new(
+        dir: PathBuf,
+        delay: Duration,
+        format: FileFormat,
+        monitor_interval: Option<Duration>,
+        timestamp_field: Option<String>,
+    ) -> FileSource {
+        FileSource {
+            dir,
+            delay,
+            format,
+            monitor_interval,
+            timestamp_field,
+        }
     }
 }
 
@@ -520,6 +662,9 @@ impl Source<String> for FileSource {
         Operator::FileSource {
             dir: self.dir.clone(),
             delay: self.delay,
+            format: self.format,
+            monitor_interval: self.monitor_interval,
+            timestamp_field: self.timestamp_field.clone(),
         }
     }
 }
@@ -595,6 +740,7 @@ impl Source<Vec<u8>> for KafkaSource {
             schema_registry: false,
             messages_per_second: self.messages_per_second,
             client_configs: HashMap::default(),
+            metadata_fields: vec![],
         }
     }
 }
@@ -918,6 +1064,8 @@ impl KeyedSink<Vec<u8>, Vec<u8>> for KeyedKafkaSink {
             topic: self.topic.clone(),
             bootstrap_servers: self.bootstrap_servers.clone(),
             client_configs: HashMap::default(),
+            key_field: None,
+            header_fields: vec![],
         }
     }
 }
@@ -1228,6 +1376,37 @@ impl Program {
         self
     }
 
+    /// Whether every operator can be run by the interpreted worker, so the program doesn't need
+    /// to be compiled
+    pub fn is_interpreted(&self) -> bool {
+        self.graph.node_weights().all(|n| {
+            matches!(
+                n.operator,
+                Operator::Interpreted(_)
+                    | Operator::ImpulseSource { .. }
+                    | Operator::NexmarkSource { .. }
+                    | Operator::Watermark(_)
+                    | Operator::ConsoleSink
+                    | Operator::GrpcSink
+                    | Operator::NullSink
+            )
+        }) && self
+            .graph
+            .node_weights()
+            .any(|n| matches!(n.operator, Operator::Interpreted(_)))
+    }
+
+    /// Encodes the program so that it can be stored for the interpreted worker
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::encode_to_vec(self, bincode::config::standard()).unwrap()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Program> {
+        let (program, _) = bincode::decode_from_slice(bytes, bincode::config::standard())
+            .map_err(|e| anyhow!("invalid program: {:?}", e))?;
+        Ok(program)
+    }
+
     pub fn dot(&self) -> String {
         format!("{:?}", petgraph::dot::Dot::with_config(&self.graph, &[]))
     }
@@ -1273,6 +1452,68 @@ impl Program {
             .collect()
     }
 
+    /// Groups the program's subtasks into failover regions: the sets of subtasks that exchange
+    /// data with each other, directly or transitively. Each region can be restarted from a
+    /// checkpoint without affecting the others.
+    pub fn failover_regions(&self) -> Vec<Vec<(String, usize)>> {
+        let mut offsets = HashMap::new();
+        let mut tasks = vec![];
+        for idx in self.graph.node_indices() {
+            let node = &self.graph[idx];
+            offsets.insert(idx, tasks.len());
+            tasks.extend((0..node.parallelism).map(|i| (node.operator_id.clone(), i)));
+        }
+
+        fn find(parents: &mut [usize], mut i: usize) -> usize {
+            while parents[i] != i {
+                parents[i] = parents[parents[i]];
+                i = parents[i];
+            }
+            i
+        }
+
+        let mut parents: Vec<usize> = (0..tasks.len()).collect();
+        let mut union = |a: usize, b: usize| {
+            let (a, b) = (find(&mut parents, a), find(&mut parents, b));
+            parents[a.max(b)] = a.min(b);
+        };
+
+        for edge in self.graph.edge_references() {
+            let (src, dst) = (offsets[&edge.source()], offsets[&edge.target()]);
+            let src_parallelism = self.graph[edge.source()].parallelism;
+            let dst_parallelism = self.graph[edge.target()].parallelism;
+
+            match edge.weight().typ {
+                EdgeType::Forward => {
+                    for i in 0..src_parallelism.min(dst_parallelism) {
+                        union(src + i, dst + i);
+                    }
+                }
+                EdgeType::Shuffle | EdgeType::ShuffleJoin(_) => {
+                    for i in 0..src_parallelism {
+                        union(src, src + i);
+                    }
+                    for i in 0..dst_parallelism {
+                        union(src, dst + i);
+                    }
+                }
+            }
+        }
+
+        let mut regions: Vec<Vec<(String, usize)>> = vec![];
+        let mut region_for_root = HashMap::new();
+        for (i, task) in tasks.into_iter().enumerate() {
+            let root = find(&mut parents, i);
+            let region = *region_for_root.entry(root).or_insert_with(|| {
+                regions.push(vec![]);
+                regions.len() - 1
+            });
+            regions[region].push(task);
+        }
+
+        regions
+    }
+
     pub fn get_hash(&self) -> String {
         let mut hasher = DefaultHasher::new();
         let bs = bincode::encode_to_vec(self, bincode::config::standard()).unwrap();
@@ -1305,6 +1546,11 @@ impl Program {
                     stop: Some(StopType::None as i32),
                     checkpoint_interval_micros: Some(checkpoint_interval_micros),
                     parallelism: None,
+                    restart_policy: None,
+                    autoscaling: None,
+                    resources: None,
+                    bounds: None,
+                    schedule: None,
                 }))
                 .await?;
             Ok(restore_from)
@@ -1325,6 +1571,7 @@ impl Program {
                     pipeline_id: res.into_inner().pipeline_id,
                     checkpoint_interval_micros,
                     preview: false,
+                    ..Default::default()
                 }))
                 .await?;
 
@@ -1423,9 +1670,18 @@ impl TryFrom<Program> for PipelineProgram {
 impl From<Operator> for GrpcApi::operator::Operator {
     fn from(operator: Operator) -> Self {
         match operator {
-            Operator::FileSource { dir, delay } => GrpcOperator::FileSource(GrpcApi::FileSource {
+            Operator::FileSource {
+                dir,
+                delay,
+                format,
+                monitor_interval,
+                timestamp_field,
+            } => GrpcOperator::FileSource(GrpcApi::FileSource {
                 dir: dir.to_string_lossy().to_string(),
                 micros_delay: delay.as_micros() as u64,
+                format: GrpcApi::FileFormat::from(format).into(),
+                monitor_interval_micros: monitor_interval.map(|i| i.as_micros() as u64),
+                timestamp_field,
             }),
             Operator::ImpulseSource {
                 start_time,
@@ -1443,6 +1699,7 @@ impl From<Operator> for GrpcApi::operator::Operator {
                 schema_registry,
                 messages_per_second,
                 client_configs,
+                metadata_fields,
             } => GrpcOperator::KafkaSource(GrpcApi::KafkaSource {
                 topic,
                 bootstrap_servers,
@@ -1453,6 +1710,7 @@ impl From<Operator> for GrpcApi::operator::Operator {
                 schema_registry,
                 messages_per_second,
                 client_configs,
+                metadata_fields: metadata_fields.into_iter().map(|f| f.into()).collect(),
             }),
             FusedWasmUDFs { name, udfs } => GrpcOperator::WasmUdfs(GrpcApi::WasmUdfs {
                 name,
@@ -1511,10 +1769,14 @@ impl From<Operator> for GrpcApi::operator::Operator {
                 topic,
                 bootstrap_servers,
                 client_configs,
+                key_field,
+                header_fields,
             } => GrpcOperator::KafkaSink(GrpcApi::KafkaSink {
                 topic,
                 bootstrap_servers,
                 client_configs,
+                key_field,
+                header_fields,
             }),
             Operator::NexmarkSource {
                 first_event_rate,
@@ -1612,6 +1874,50 @@ impl From<Operator> for GrpcApi::operator::Operator {
                 sort_key_type,
                 max_elements: max_elements as u64,
             }),
+            Operator::KinesisSource {
+                stream_name,
+                region,
+                endpoint,
+                offset_mode,
+            } => GrpcOperator::KinesisSource(GrpcApi::KinesisSource {
+                stream_name,
+                region,
+                endpoint,
+                offset_mode: match offset_mode {
+                    OffsetMode::Earliest => GrpcApi::OffsetMode::Earliest.into(),
+                    OffsetMode::Latest => GrpcApi::OffsetMode::Latest.into(),
+                },
+            }),
+            Operator::KinesisSink {
+                stream_name,
+                region,
+                endpoint,
+                partition_key_field,
+            } => GrpcOperator::KinesisSink(GrpcApi::KinesisSink {
+                stream_name,
+                region,
+                endpoint,
+                partition_key_field,
+            }),
+            Operator::FileSystemSink {
+                path,
+                format,
+                partition_template,
+                max_file_size,
+                rollover,
+            } => GrpcOperator::FileSystemSink(GrpcApi::FileSystemSink {
+                path,
+                format: GrpcApi::FileFormat::from(format).into(),
+                partition_template,
+                max_file_size_bytes: max_file_size as u64,
+                rollover_micros: rollover.as_micros() as u64,
+            }),
+            Operator::Interpreted(op) => GrpcOperator::Interpreted(GrpcApi::InterpretedOperator {
+                operator: bincode::encode_to_vec(op, bincode::config::standard()).unwrap(),
+            }),
+            Operator::Dedup { ttl } => GrpcOperator::Dedup(GrpcApi::Dedup {
+                ttl_micros: ttl.map(|ttl| ttl.as_micros() as u64),
+            }),
         }
     }
 }
@@ -1733,10 +2039,24 @@ impl TryFrom<arroyo_rpc::grpc::api::Operator> for Operator {
     fn try_from(operator: arroyo_rpc::grpc::api::Operator) -> Result<Self> {
         let result = match operator.operator {
             Some(operator) => match operator {
-                GrpcOperator::FileSource(file_source) => Operator::FileSource {
-                    dir: PathBuf::from(&file_source.dir).canonicalize()?,
-                    delay: Duration::from_micros(file_source.micros_delay),
-                },
+                GrpcOperator::FileSource(file_source) => {
+                    let format = file_source.format().into();
+                    // remote paths (e.g., s3://bucket/prefix) can't be canonicalized locally
+                    let dir = if file_source.dir.contains("://") {
+                        PathBuf::from(&file_source.dir)
+                    } else {
+                        PathBuf::from(&file_source.dir).canonicalize()?
+                    };
+                    Operator::FileSource {
+                        dir,
+                        delay: Duration::from_micros(file_source.micros_delay),
+                        format,
+                        monitor_interval: file_source
+                            .monitor_interval_micros
+                            .map(Duration::from_micros),
+                        timestamp_field: file_source.timestamp_field,
+                    }
+                }
                 GrpcOperator::ImpulseSource(impulse_source) => {
                     let spec = match impulse_source.spec {
                         Some(Spec::MicrosDelay(micros_delay)) => {
@@ -1760,6 +2080,11 @@ impl TryFrom<arroyo_rpc::grpc::api::Operator> for Operator {
                         schema_registry: kafka_source.schema_registry,
                         messages_per_second: kafka_source.messages_per_second,
                         client_configs: kafka_source.client_configs,
+                        metadata_fields: kafka_source
+                            .metadata_fields
+                            .into_iter()
+                            .map(|f| f.try_into())
+                            .collect::<Result<_>>()?,
                     }
                 }
                 GrpcOperator::WasmUdfs(wasm_udfs) => Operator::FusedWasmUDFs {
@@ -1828,6 +2153,8 @@ impl TryFrom<arroyo_rpc::grpc::api::Operator> for Operator {
                     topic: kafka_sink.topic,
                     bootstrap_servers: kafka_sink.bootstrap_servers,
                     client_configs: kafka_sink.client_configs,
+                    key_field: kafka_sink.key_field,
+                    header_fields: kafka_sink.header_fields,
                 },
                 GrpcOperator::NexmarkSource(nexmark_source) => Operator::NexmarkSource {
                     first_event_rate: nexmark_source.first_event_rate,
@@ -1920,6 +2247,42 @@ impl TryFrom<arroyo_rpc::grpc::api::Operator> for Operator {
                     sort_key_type,
                     max_elements: max_elements as usize,
                 }),
+                GrpcOperator::KinesisSource(kinesis_source) => {
+                    let offset_mode = kinesis_source.offset_mode().into();
+                    Operator::KinesisSource {
+                        stream_name: kinesis_source.stream_name,
+                        region: kinesis_source.region,
+                        endpoint: kinesis_source.endpoint,
+                        offset_mode,
+                    }
+                }
+                GrpcOperator::KinesisSink(kinesis_sink) => Operator::KinesisSink {
+                    stream_name: kinesis_sink.stream_name,
+                    region: kinesis_sink.region,
+                    endpoint: kinesis_sink.endpoint,
+                    partition_key_field: kinesis_sink.partition_key_field,
+                },
+                GrpcOperator::FileSystemSink(file_system_sink) => {
+                    let format = file_system_sink.format().into();
+                    Operator::FileSystemSink {
+                        path: file_system_sink.path,
+                        format,
+                        partition_template: file_system_sink.partition_template,
+                        max_file_size: file_system_sink.max_file_size_bytes as usize,
+                        rollover: Duration::from_micros(file_system_sink.rollover_micros),
+                    }
+                }
+                GrpcOperator::Interpreted(interpreted) => {
+                    let (op, _) = bincode::decode_from_slice(
+                        &interpreted.operator,
+                        bincode::config::standard(),
+                    )
+                    .map_err(|e| anyhow!("invalid interpreted operator: {:?}", e))?;
+                    Operator::Interpreted(op)
+                }
+                GrpcOperator::Dedup(dedup) => Operator::Dedup {
+                    ttl: dedup.ttl_micros.map(Duration::from_micros),
+                },
             },
             None => bail!("unset on operator {:?}", operator),
         };
@@ -2006,3 +2369,84 @@ impl From<arroyo_rpc::grpc::api::ProgramEdge> for StreamEdge {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use petgraph::graph::DiGraph;
+
+    use crate::{EdgeType, Operator, Program, StreamEdge, StreamNode};
+
+    fn node(id: &str, parallelism: usize) -> StreamNode {
+        StreamNode {
+            operator_id: id.to_string(),
+            operator: Operator::NullSink,
+            parallelism,
+        }
+    }
+
+    #[test]
+    fn test_failover_regions() {
+        let mut graph = DiGraph::new();
+
+        // a pipeline of forward edges, where each subtask index is its own region
+        let source = graph.add_node(node("source", 2));
+        let map = graph.add_node(node("map", 2));
+        let sink_a = graph.add_node(node("sink_a", 2));
+        let sink_b = graph.add_node(node("sink_b", 2));
+        graph.add_edge(
+            source,
+            map,
+            StreamEdge::unkeyed_edge("()", EdgeType::Forward),
+        );
+        graph.add_edge(
+            map,
+            sink_a,
+            StreamEdge::unkeyed_edge("()", EdgeType::Forward),
+        );
+        graph.add_edge(
+            map,
+            sink_b,
+            StreamEdge::unkeyed_edge("()", EdgeType::Forward),
+        );
+
+        // an independent pipeline with a shuffle, which connects all of its subtasks
+        let other_source = graph.add_node(node("other_source", 2));
+        let other_sink = graph.add_node(node("other_sink", 3));
+        graph.add_edge(
+            other_source,
+            other_sink,
+            StreamEdge::keyed_edge("()", "()", EdgeType::Shuffle),
+        );
+
+        let program = Program {
+            types: vec![],
+            other_defs: vec![],
+            graph,
+        };
+
+        let regions = program.failover_regions();
+        assert_eq!(3, regions.len());
+
+        let task = |id: &str, idx: usize| (id.to_string(), idx);
+        assert_eq!(
+            vec![
+                task("source", 0),
+                task("map", 0),
+                task("sink_a", 0),
+                task("sink_b", 0)
+            ],
+            regions[0]
+        );
+        assert_eq!(
+            vec![
+                task("source", 1),
+                task("map", 1),
+                task("sink_a", 1),
+                task("sink_b", 1)
+            ],
+            regions[1]
+        );
+        assert_eq!(5, regions[2].len());
+        assert!(regions[2].contains(&task("other_sink", 2)));
+    }
+}
@@ -386,6 +386,7 @@ fn impl_stream_node_type(
     };
     let handler_count = handlers.len();
     let mut handle_matchers = vec![];
+    let mut replay_matchers = vec![];
 
     for (i, (in_k, in_t, handle_fn)) in handlers.into_iter().enumerate() {
         let deserialize_error = format!(
@@ -393,6 +394,16 @@ fn impl_stream_node_type(
             quote! { #in_k },
             quote! { #in_t }
         );
+        replay_matchers.push(quote! {
+            #i => {
+                let record: arroyo_types::Record<#in_k, #in_t> =
+                    bincode::decode_from_slice(&record.data, config::standard())
+                        .expect(#deserialize_error)
+                        .0;
+                Self::#handle_fn(&mut (*self), &record, &mut ctx).await;
+            }
+        });
+
         handle_matchers.push(quote! {
             #i => {
                 let message = match item {
@@ -420,6 +431,12 @@ fn impl_stream_node_type(
                         .expect("msg received")
                         .inc();
 
+                    if counter.is_in_flight(idx) {
+                        // the state has already been checkpointed without this record, so it's
+                        // stored with the checkpoint to be replayed on restore
+                        counter.capture(#i, record, &ctx.task_info);
+                    }
+
                     Self::#handle_fn(&mut (*self), record, &mut ctx)
                       .instrument(tracing::trace_span!("handle_fn",
                         name, operator_id=task_info.operator_id, subtask_idx=task_info.task_index))
@@ -473,7 +490,20 @@ fn impl_stream_node_type(
         }
     } else {
         quote! {
-            let mut counter = crate::engine::CheckpointCounter::new(in_qs.len());
+            // replay the records that were in flight when the checkpoint we're restoring from
+            // was taken, before any new input
+            for record in ctx.state.get_in_flight().await {
+                match record.input {
+                    #(#replay_matchers
+                    )*
+                    _ => unreachable!()
+                }
+            }
+
+            let mut counter = crate::engine::CheckpointCounter::new(
+                in_qs.len(),
+                arroyo_state::CheckpointMode::from_env(),
+            );
             let mut closed: std::collections::HashSet<usize> = std::collections::HashSet::new();
 
             let mut sel = crate::inq_reader::InQReader::new();
@@ -493,17 +523,37 @@ fn impl_stream_node_type(
             let mut blocked = vec![];
 
             loop {
-                match sel.next().await {
-                    Some(((idx, item), s)) => {
-                        match idx / (in_partitions / #handler_count) {
-                            #(#handle_matchers
-                            )*
-                            _ => unreachable!()
+                tokio::select! {
+                    Some(control_message) = ctx.control_rx.recv() => {
+                        match control_message {
+                            arroyo_rpc::ControlMessage::Commit { epoch } => {
+                                Self::handle_commit(&mut (*self), epoch, &mut ctx).await;
+                            }
+                            arroyo_rpc::ControlMessage::QueryState { table, key, respond } => {
+                                // the querier may have given up waiting
+                                let _ = respond.send(ctx.state.query(table, &key));
+                            }
+                            c => {
+                                tracing::warn!("[{}] Unexpected control message {:?}", ctx.task_info.operator_name, c);
+                            }
                         }
                     }
-                    None => {
-                        tracing::info!("[{}] Stream completed", ctx.task_info.operator_name);
-                        break;
+                    p = sel.next() => {
+                        match p {
+                            Some(((idx, item), s)) => {
+                                let busy_start = std::time::Instant::now();
+                                match idx / (in_partitions / #handler_count) {
+                                    #(#handle_matchers
+                                    )*
+                                    _ => unreachable!()
+                                }
+                                ctx.load.record_busy(busy_start.elapsed());
+                            }
+                            None => {
+                                tracing::info!("[{}] Stream completed", ctx.task_info.operator_name);
+                                break;
+                            }
+                        }
                     }
                 }
             }
@@ -584,34 +634,61 @@ fn impl_stream_node_type(
                         unreachable!();
                     }
                     Message::Barrier(t) => {
-                        tracing::debug!(
-                            "received barrier in {}-{}-{}-{}",
-                            self.name(),
-                            ctx.task_info.operator_id,
-                            ctx.task_info.task_index,
-                            idx
-                        );
-
-                        if counter.all_clear() {
-                            ctx.control_tx.send(arroyo_rpc::ControlResp::CheckpointEvent(arroyo_rpc::CheckpointEvent {
-                                checkpoint_epoch: t.epoch,
-                                operator_id: ctx.task_info.operator_id.clone(),
-                                subtask_index: ctx.task_info.task_index as u32,
-                                time: std::time::SystemTime::now(),
-                                event_type: arroyo_rpc::grpc::TaskCheckpointEventType::StartedAlignment,
-                            })).await.unwrap();
-                        }
-
-                        if counter.mark(idx, &t) {
+                        let mut barriers = vec![(idx, *t)];
+                        while let Some((idx, t)) = barriers.pop() {
                             tracing::debug!(
-                                "Checkpointing {}-{}-{}",
+                                "received barrier in {}-{}-{}-{}",
                                 self.name(),
                                 ctx.task_info.operator_id,
-                                ctx.task_info.task_index
+                                ctx.task_info.task_index,
+                                idx
                             );
 
-                            if self.checkpoint(*t, ctx).await {
-                                return crate::ControlOutcome::Stop;
+                            if counter.defer(idx, &t) {
+                                continue;
+                            }
+
+                            let first = counter.all_clear();
+                            if first {
+                                ctx.control_tx.send(arroyo_rpc::ControlResp::CheckpointEvent(arroyo_rpc::CheckpointEvent {
+                                    checkpoint_epoch: t.epoch,
+                                    operator_id: ctx.task_info.operator_id.clone(),
+                                    subtask_index: ctx.task_info.task_index as u32,
+                                    time: std::time::SystemTime::now(),
+                                    event_type: arroyo_rpc::grpc::TaskCheckpointEventType::StartedAlignment,
+                                })).await.unwrap();
+                            }
+
+                            let aligned = counter.mark(idx, &t);
+
+                            if counter.is_unaligned() && !t.then_stop {
+                                if first {
+                                    tracing::debug!(
+                                        "Checkpointing {}-{}-{} without alignment",
+                                        self.name(),
+                                        ctx.task_info.operator_id,
+                                        ctx.task_info.task_index
+                                    );
+
+                                    self.checkpoint_int(t, true, ctx).await;
+                                }
+
+                                if aligned {
+                                    ctx.state.write_in_flight(t.epoch, counter.take_in_flight()).await;
+                                    barriers.extend(counter.take_deferred());
+                                }
+                            } else if aligned {
+                                tracing::debug!(
+                                    "Checkpointing {}-{}-{}",
+                                    self.name(),
+                                    ctx.task_info.operator_id,
+                                    ctx.task_info.task_index
+                                );
+
+                                if self.checkpoint(t, ctx).await {
+                                    return crate::ControlOutcome::Stop;
+                                }
+                                barriers.extend(counter.take_deferred());
                             }
                         }
                     }
@@ -646,6 +723,15 @@ fn impl_stream_node_type(
             }
     });
 
+    defs.push(quote! {
+        #[must_use]
+        async fn checkpoint(&mut self,
+            checkpoint_barrier: arroyo_types::CheckpointBarrier,
+            ctx: &mut crate::engine::Context<#out_k, #out_t>) -> bool {
+            self.checkpoint_int(checkpoint_barrier, false, ctx).await
+        }
+    });
+
     defs.push(quote! {
         #[tracing::instrument(
             level = "trace",
@@ -656,9 +742,9 @@ fn impl_stream_node_type(
                 subtask_idx=ctx.task_info.task_index,
             ),
         )]
-        #[must_use]
-        async fn checkpoint(&mut self,
+        async fn checkpoint_int(&mut self,
             checkpoint_barrier: arroyo_types::CheckpointBarrier,
+            unaligned: bool,
             ctx: &mut crate::engine::Context<#out_k, #out_t>) -> bool {
 
             crate::process_fn::ProcessFnUtils::send_event(checkpoint_barrier, ctx, arroyo_rpc::grpc::TaskCheckpointEventType::StartedCheckpointing).await;
@@ -668,7 +754,11 @@ fn impl_stream_node_type(
             crate::process_fn::ProcessFnUtils::send_event(checkpoint_barrier, ctx, arroyo_rpc::grpc::TaskCheckpointEventType::FinishedOperatorSetup).await;
 
             let watermark = ctx.watermark();
-            ctx.state.checkpoint(checkpoint_barrier, watermark).await;
+            if unaligned {
+                ctx.state.checkpoint_unaligned(checkpoint_barrier, watermark).await;
+            } else {
+                ctx.state.checkpoint(checkpoint_barrier, watermark).await;
+            }
 
             crate::process_fn::ProcessFnUtils::send_event(checkpoint_barrier, ctx, arroyo_rpc::grpc::TaskCheckpointEventType::FinishedSync).await;
 
@@ -713,6 +803,12 @@ fn impl_stream_node_type(
         });
     }
 
+    if !methods.contains("handle_commit") {
+        defs.push(quote! {
+            async fn handle_commit(&mut self, epoch: u32, ctx: &mut crate::engine::Context<#out_k, #out_t>) {}
+        });
+    }
+
     if !methods.contains("on_start") {
         defs.push(quote! {
             async fn on_start(&mut self, ctx: &mut crate::engine::Context<#out_k, #out_t>) {}
@@ -10,7 +10,7 @@ use arroyo_rpc::grpc::{
     controller_grpc_client::ControllerGrpcClient, node_grpc_server::NodeGrpc,
     node_grpc_server::NodeGrpcServer, GetWorkersReq, GetWorkersResp, HeartbeatNodeReq,
     RegisterNodeReq, StartWorkerReq, StartWorkerResp, StopWorkerReq, StopWorkerResp,
-    WorkerFinishedReq,
+    WorkerFinishedReq, WorkerResources,
 };
 use arroyo_types::{
     grpc_port, ports, to_millis, NodeId, WorkerId, CONTROLLER_ADDR_ENV, JOB_ID_ENV, NODE_ID_ENV,
@@ -122,7 +122,11 @@ impl NodeGrpc for NodeServer {
         // TODO: Check that we have enough slots to schedule this
         let slots = req.slots;
         let state = Arc::clone(&self.workers);
-        let worker_id = WorkerId(rand::thread_rng().gen());
+        let worker_id = if req.worker_id != 0 {
+            WorkerId(req.worker_id)
+        } else {
+            WorkerId(rand::thread_rng().gen())
+        };
         let node_id = self.id;
         let finished_tx = self.worker_finished_tx.clone();
 
@@ -257,6 +261,15 @@ pub async fn main() {
         .map(|s| usize::from_str(&s).unwrap())
         .unwrap_or(16);
 
+    // the CPU (in millicores) and memory (in MB) available to workers; unset if not limited
+    let resource = |var: &str| {
+        std::env::var(var)
+            .map(|s| u64::from_str(&s).unwrap())
+            .unwrap_or(0)
+    };
+    let cpu_millis = resource("NODE_CPU_MILLIS");
+    let memory_mb = resource("NODE_MEMORY_MB");
+
     let grpc = grpc_port("node", ports::NODE_GRPC);
 
     let node_id = NodeId(rand::thread_rng().gen());
@@ -311,6 +324,11 @@ pub async fn main() {
                         node_id: node_id.0,
                         task_slots: task_slots as u64,
                         addr: req_addr.clone(),
+                        resources: Some(WorkerResources {
+                            slots: task_slots as u64,
+                            cpu_millis,
+                            memory_mb,
+                        }),
                     }))
                     .await
                     .unwrap();
@@ -10,6 +10,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .file_descriptor_set_path(out_dir.join("api_descriptor.bin"))
         .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
         .type_attribute(".", "#[serde(rename_all = \"camelCase\")]")
+        // these are stored as json in the database, so must tolerate rows written before they existed
+        .field_attribute(
+            ".arroyo_api.KafkaSourceConfig.metadata_fields",
+            "#[serde(default)]",
+        )
+        .field_attribute(".arroyo_api.KafkaSinkConfig.header_fields", "#[serde(default)]")
         .compile(&["proto/api.proto"], &["api"])
         .unwrap();
     Ok(())
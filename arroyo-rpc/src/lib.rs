@@ -3,6 +3,7 @@ use std::{fs, time::SystemTime};
 use crate::grpc::SubtaskCheckpointMetadata;
 use arroyo_types::{CheckpointBarrier, API_ADDR_ENV};
 use grpc::{api::api_grpc_client::ApiGrpcClient, StopMode, TaskCheckpointEventType};
+use tokio::sync::oneshot;
 use tonic::{
     codegen::InterceptedService,
     metadata::{Ascii, MetadataValue},
@@ -26,7 +27,27 @@ pub mod grpc {
 #[derive(Debug)]
 pub enum ControlMessage {
     Checkpoint(CheckpointBarrier),
-    Stop { mode: StopMode },
+    Stop {
+        mode: StopMode,
+    },
+    // sent to sinks once the checkpoint for this epoch has completed across the whole job
+    Commit {
+        epoch: u32,
+    },
+    // reads the current values of a key from one of the task's tables
+    QueryState {
+        table: char,
+        key: Vec<u8>,
+        respond: oneshot::Sender<Result<StateQueryResult, String>>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct StateQueryResult {
+    pub key_hash: u64,
+    // the bincode-encoded values for the key along with their timestamps, or None if the key
+    // isn't in the key range of the task that was queried
+    pub values: Option<Vec<(Option<SystemTime>, Vec<u8>)>>,
 }
 
 #[derive(Debug, Clone)]
@@ -53,6 +74,11 @@ pub enum ControlResp {
         operator_id: String,
         task_index: usize,
     },
+    // a bounded source has read all of its input, and is waiting for the job to finish
+    TaskReachedEnd {
+        operator_id: String,
+        task_index: usize,
+    },
     TaskFailed {
         operator_id: String,
         task_index: usize,
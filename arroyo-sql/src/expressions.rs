@@ -4,8 +4,10 @@ use crate::{
     pipeline::SortDirection,
     types::{StructDef, StructField, TypeDef},
 };
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use arrow::datatypes::DataType;
+use arroyo_datastream::interpreted;
+use arroyo_types::from_nanos;
 use datafusion_common::ScalarValue;
 use datafusion_expr::{
     aggregate_function,
@@ -92,6 +94,137 @@ impl ExpressionGenerator for Expression {
 }
 
 impl Expression {
+    /// Converts the expression into one that the interpreted worker evaluates over rows of
+    /// `input_struct`, failing if it uses anything the interpreter doesn't support
+    pub(crate) fn to_interpreted(&self, input_struct: &StructDef) -> Result<interpreted::Expr> {
+        let expr = |e: &Expression| e.to_interpreted(input_struct).map(Box::new);
+        Ok(match self {
+            Expression::Column(ColumnExpression { column_field }) => {
+                interpreted::Expr::Column(field_position(input_struct, column_field)?)
+            }
+            Expression::UnaryBoolean(UnaryBooleanExpression { operator, input }) => {
+                let op = match operator {
+                    UnaryOperator::IsNotNull => interpreted::UnaryOp::IsNotNull,
+                    UnaryOperator::IsNull => interpreted::UnaryOp::IsNull,
+                    UnaryOperator::IsTrue => interpreted::UnaryOp::IsTrue,
+                    UnaryOperator::IsFalse => interpreted::UnaryOp::IsFalse,
+                    UnaryOperator::IsUnknown => interpreted::UnaryOp::IsUnknown,
+                    UnaryOperator::IsNotTrue => interpreted::UnaryOp::IsNotTrue,
+                    UnaryOperator::IsNotFalse => interpreted::UnaryOp::IsNotFalse,
+                    UnaryOperator::IsNotUnknown => interpreted::UnaryOp::IsNotUnknown,
+                    UnaryOperator::Negative => interpreted::UnaryOp::Negative,
+                };
+                interpreted::Expr::Unary(op, expr(input)?)
+            }
+            Expression::Literal(LiteralExpression { literal }) => {
+                interpreted::Expr::Literal(literal_value(literal)?)
+            }
+            Expression::BinaryComparison(BinaryComparisonExpression { left, op, right }) => {
+                let op = match op {
+                    BinaryComparison::Eq => interpreted::ComparisonOp::Eq,
+                    BinaryComparison::NotEq => interpreted::ComparisonOp::NotEq,
+                    BinaryComparison::Lt => interpreted::ComparisonOp::Lt,
+                    BinaryComparison::LtEq => interpreted::ComparisonOp::LtEq,
+                    BinaryComparison::Gt => interpreted::ComparisonOp::Gt,
+                    BinaryComparison::GtEq => interpreted::ComparisonOp::GtEq,
+                    BinaryComparison::IsDistinctFrom => interpreted::ComparisonOp::IsDistinctFrom,
+                    BinaryComparison::IsNotDistinctFrom => {
+                        interpreted::ComparisonOp::IsNotDistinctFrom
+                    }
+                    BinaryComparison::And => interpreted::ComparisonOp::And,
+                    BinaryComparison::Or => interpreted::ComparisonOp::Or,
+                };
+                interpreted::Expr::Comparison(expr(left)?, op, expr(right)?)
+            }
+            Expression::BinaryMath(BinaryMathExpression { left, op, right }) => {
+                let op = match op {
+                    BinaryMathOperator::Plus => interpreted::MathOp::Plus,
+                    BinaryMathOperator::Minus => interpreted::MathOp::Minus,
+                    BinaryMathOperator::Multiply => interpreted::MathOp::Multiply,
+                    BinaryMathOperator::Divide => interpreted::MathOp::Divide,
+                    BinaryMathOperator::Modulo => interpreted::MathOp::Modulo,
+                };
+                interpreted::Expr::Math(expr(left)?, op, expr(right)?)
+            }
+            Expression::StructField(StructFieldExpression {
+                struct_expression,
+                struct_field,
+            }) => {
+                let TypeDef::StructDef(struct_def, _) = struct_expression.return_type() else {
+                    bail!("{:?} doesn't return a struct", struct_expression);
+                };
+                interpreted::Expr::StructField(
+                    expr(struct_expression)?,
+                    field_position(&struct_def, struct_field)?,
+                )
+            }
+            Expression::Aggregation(_) => bail!("aggregations can't be interpreted"),
+            Expression::Cast(CastExpression { input, data_type }) => {
+                if let TypeDef::DataType(input_type, _) = input.return_type() {
+                    if CastExpression::is_date(&input_type) {
+                        bail!("casting from {:?} can't be interpreted", input_type);
+                    }
+                }
+                let cast_type = match data_type {
+                    DataType::Int8 => interpreted::CastType::Int8,
+                    DataType::Int16 => interpreted::CastType::Int16,
+                    DataType::Int32 => interpreted::CastType::Int32,
+                    DataType::Int64 => interpreted::CastType::Int64,
+                    DataType::UInt8 => interpreted::CastType::UInt8,
+                    DataType::UInt16 => interpreted::CastType::UInt16,
+                    DataType::UInt32 => interpreted::CastType::UInt32,
+                    DataType::UInt64 => interpreted::CastType::UInt64,
+                    DataType::Float16 | DataType::Float32 => interpreted::CastType::Float32,
+                    DataType::Float64 => interpreted::CastType::Float64,
+                    DataType::Utf8 | DataType::LargeUtf8 => interpreted::CastType::String,
+                    _ => bail!("casting to {:?} can't be interpreted", data_type),
+                };
+                interpreted::Expr::Cast(expr(input)?, cast_type)
+            }
+            Expression::Numeric(NumericExpression { function, input }) => {
+                let op = match function {
+                    NumericFunction::Abs => interpreted::NumericOp::Abs,
+                    NumericFunction::Acos => interpreted::NumericOp::Acos,
+                    NumericFunction::Asin => interpreted::NumericOp::Asin,
+                    NumericFunction::Atan => interpreted::NumericOp::Atan,
+                    NumericFunction::Cos => interpreted::NumericOp::Cos,
+                    NumericFunction::Ln => interpreted::NumericOp::Ln,
+                    NumericFunction::Log => bail!("log can't be interpreted"),
+                    NumericFunction::Log10 => interpreted::NumericOp::Log10,
+                    NumericFunction::Sin => interpreted::NumericOp::Sin,
+                    NumericFunction::Sqrt => interpreted::NumericOp::Sqrt,
+                    NumericFunction::Tan => interpreted::NumericOp::Tan,
+                    NumericFunction::Ceil => interpreted::NumericOp::Ceil,
+                    NumericFunction::Floor => interpreted::NumericOp::Floor,
+                    NumericFunction::Round => interpreted::NumericOp::Round,
+                    NumericFunction::Signum => interpreted::NumericOp::Signum,
+                    NumericFunction::Trunc => interpreted::NumericOp::Trunc,
+                    NumericFunction::Log2 => interpreted::NumericOp::Log2,
+                    NumericFunction::Exp => interpreted::NumericOp::Exp,
+                };
+                interpreted::Expr::Numeric(op, expr(input)?)
+            }
+            Expression::String(string_function) => {
+                let (op, args) = string_function.interpreted_args()?;
+                let args = args
+                    .into_iter()
+                    .map(|arg| arg.to_interpreted(input_struct))
+                    .collect::<Result<_>>()?;
+                interpreted::Expr::String(op, args)
+            }
+            Expression::Hash(HashExpression { function, input }) => {
+                let op = match function {
+                    HashFunction::MD5 => interpreted::HashOp::Md5,
+                    HashFunction::SHA224 => interpreted::HashOp::Sha224,
+                    HashFunction::SHA256 => interpreted::HashOp::Sha256,
+                    HashFunction::SHA384 => interpreted::HashOp::Sha384,
+                    HashFunction::SHA512 => interpreted::HashOp::Sha512,
+                };
+                interpreted::Expr::Hash(op, expr(input)?)
+            }
+        })
+    }
+
     pub(crate) fn has_max_value(&self, field: &StructField) -> Option<i64> {
         match self {
             Expression::BinaryComparison(BinaryComparisonExpression { left, op, right }) => {
@@ -156,6 +289,55 @@ impl Expression {
     }
 }
 
+fn field_position(struct_def: &StructDef, field: &StructField) -> Result<usize> {
+    struct_def
+        .fields
+        .iter()
+        .position(|f| f == field)
+        .or_else(|| struct_def.fields.iter().position(|f| f.name == field.name))
+        .ok_or_else(|| anyhow!("no field {:?} in struct {:?}", field, struct_def))
+}
+
+fn literal_value(literal: &ScalarValue) -> Result<interpreted::Value> {
+    let timestamp = |nanos: i128| -> Result<interpreted::Value> {
+        let nanos = u128::try_from(nanos)
+            .map_err(|_| anyhow!("timestamps before the epoch can't be interpreted"))?;
+        Ok(interpreted::Value::Timestamp(from_nanos(nanos)))
+    };
+    if literal.is_null() {
+        return Ok(interpreted::Value::Null);
+    }
+    let value = match literal {
+        ScalarValue::Boolean(Some(b)) => interpreted::Value::Bool(*b),
+        ScalarValue::Int8(Some(i)) => interpreted::Value::Int(*i as i64),
+        ScalarValue::Int16(Some(i)) => interpreted::Value::Int(*i as i64),
+        ScalarValue::Int32(Some(i)) => interpreted::Value::Int(*i as i64),
+        ScalarValue::Int64(Some(i)) => interpreted::Value::Int(*i),
+        ScalarValue::UInt8(Some(u)) => interpreted::Value::UInt(*u as u64),
+        ScalarValue::UInt16(Some(u)) => interpreted::Value::UInt(*u as u64),
+        ScalarValue::UInt32(Some(u)) => interpreted::Value::UInt(*u as u64),
+        ScalarValue::UInt64(Some(u)) => interpreted::Value::UInt(*u),
+        ScalarValue::Float32(Some(f)) => interpreted::Value::Float(*f as f64),
+        ScalarValue::Float64(Some(f)) => interpreted::Value::Float(*f),
+        ScalarValue::Utf8(Some(s)) | ScalarValue::LargeUtf8(Some(s)) => {
+            interpreted::Value::String(s.clone())
+        }
+        ScalarValue::Binary(Some(b)) | ScalarValue::LargeBinary(Some(b)) => {
+            interpreted::Value::Bytes(b.clone())
+        }
+        ScalarValue::TimestampSecond(Some(secs), None) => timestamp(*secs as i128 * 1_000_000_000)?,
+        ScalarValue::TimestampMillisecond(Some(millis), None) => {
+            timestamp(*millis as i128 * 1_000_000)?
+        }
+        ScalarValue::TimestampMicrosecond(Some(micros), None) => {
+            timestamp(*micros as i128 * 1_000)?
+        }
+        ScalarValue::TimestampNanosecond(Some(nanos), None) => timestamp(*nanos as i128)?,
+        _ => bail!("literal {:?} can't be interpreted", literal),
+    };
+    Ok(value)
+}
+
 pub fn to_expression_generator(expression: &Expr, input_struct: &StructDef) -> Result<Expression> {
     match expression {
         Expr::Alias(expr, _alias) => to_expression_generator(expr, input_struct),
@@ -1427,6 +1609,77 @@ impl TryFrom<(BuiltinScalarFunction, Vec<Expression>)> for StringFunction {
 }
 
 impl StringFunction {
+    fn interpreted_args(&self) -> Result<(interpreted::StringOp, Vec<&Expression>)> {
+        use interpreted::StringOp;
+        fn with_optional<'a>(
+            mut args: Vec<&'a Expression>,
+            last: &'a Option<Box<Expression>>,
+        ) -> Vec<&'a Expression> {
+            args.extend(last.as_deref());
+            args
+        }
+        Ok(match self {
+            StringFunction::Ascii(e) => (StringOp::Ascii, vec![e.as_ref()]),
+            StringFunction::BitLength(e) => (StringOp::BitLength, vec![e.as_ref()]),
+            StringFunction::CharacterLength(e) => (StringOp::CharacterLength, vec![e.as_ref()]),
+            StringFunction::OctetLength(e) => (StringOp::OctetLength, vec![e.as_ref()]),
+            StringFunction::Chr(e) => (StringOp::Chr, vec![e.as_ref()]),
+            StringFunction::InitCap(e) => (StringOp::InitCap, vec![e.as_ref()]),
+            StringFunction::Lower(e) => (StringOp::Lower, vec![e.as_ref()]),
+            StringFunction::Upper(e) => (StringOp::Upper, vec![e.as_ref()]),
+            StringFunction::Reverse(e) => (StringOp::Reverse, vec![e.as_ref()]),
+            StringFunction::Btrim(e, chars) | StringFunction::Trim(e, chars) => {
+                (StringOp::Trim, with_optional(vec![e.as_ref()], chars))
+            }
+            StringFunction::Ltrim(e, chars) => {
+                (StringOp::Ltrim, with_optional(vec![e.as_ref()], chars))
+            }
+            StringFunction::Rtrim(e, chars) => {
+                (StringOp::Rtrim, with_optional(vec![e.as_ref()], chars))
+            }
+            StringFunction::StartsWith(s, prefix) => {
+                (StringOp::StartsWith, vec![s.as_ref(), prefix.as_ref()])
+            }
+            StringFunction::Strpos(s, sub) => (StringOp::Strpos, vec![s.as_ref(), sub.as_ref()]),
+            StringFunction::Left(s, n) => (StringOp::Left, vec![s.as_ref(), n.as_ref()]),
+            StringFunction::Right(s, n) => (StringOp::Right, vec![s.as_ref(), n.as_ref()]),
+            StringFunction::Repeat(s, n) => (StringOp::Repeat, vec![s.as_ref(), n.as_ref()]),
+            StringFunction::Lpad(s, n, fill) => (
+                StringOp::Lpad,
+                with_optional(vec![s.as_ref(), n.as_ref()], fill),
+            ),
+            StringFunction::Rpad(s, n, fill) => (
+                StringOp::Rpad,
+                with_optional(vec![s.as_ref(), n.as_ref()], fill),
+            ),
+            StringFunction::Substr(s, start, count) => (
+                StringOp::Substr,
+                with_optional(vec![s.as_ref(), start.as_ref()], count),
+            ),
+            StringFunction::Replace(s, from, to) => (
+                StringOp::Replace,
+                vec![s.as_ref(), from.as_ref(), to.as_ref()],
+            ),
+            StringFunction::Translate(s, from, to) => (
+                StringOp::Translate,
+                vec![s.as_ref(), from.as_ref(), to.as_ref()],
+            ),
+            StringFunction::SplitPart(s, delimiter, n) => (
+                StringOp::SplitPart,
+                vec![s.as_ref(), delimiter.as_ref(), n.as_ref()],
+            ),
+            StringFunction::Concat(args) => (StringOp::Concat, args.iter().collect()),
+            StringFunction::ConcatWithSeparator(separator, args) => {
+                let mut all = vec![separator.as_ref()];
+                all.extend(args.iter());
+                (StringOp::ConcatWithSeparator, all)
+            }
+            StringFunction::RegexpMatch(..) | StringFunction::RegexpReplace(..) => {
+                bail!("regular expressions can't be interpreted")
+            }
+        })
+    }
+
     fn return_type(&self) -> TypeDef {
         match self {
             StringFunction::Ascii(expr)
@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use arrow_schema::DataType;
+use arroyo_datastream::interpreted::{InterpretedOperator, RowField, RowType, ROW_TYPE};
+use arroyo_datastream::{
+    EdgeType::Forward, EdgeType::Shuffle, Operator, Program, StreamEdge, StreamNode, WatermarkType,
+    WindowType,
+};
+use petgraph::graph::{DiGraph, NodeIndex};
+
+use crate::{
+    operators::GroupByKind,
+    pipeline::{AggregatingStrategy, RecordTransform, SqlOperator},
+    types::{StructDef, TypeDef},
+    SqlConfig, SqlSource,
+};
+
+/// Builds a program for the interpreted worker, which evaluates expressions over dynamically
+/// typed rows instead of running generated code. Only simple pipelines are supported for now:
+/// impulse and nexmark sources, projections, filters and two-phase window aggregates, written
+/// to the builtin sinks. Anything else returns an error, and should be compiled instead.
+pub fn get_interpreted_program(
+    config: &SqlConfig,
+    operator: &SqlOperator,
+) -> Result<(Program, Vec<SqlSource>)> {
+    let sink = match &config.sink {
+        Operator::ConsoleSink | Operator::GrpcSink | Operator::NullSink => config.sink.clone(),
+        sink => bail!("{:?} can't be interpreted", sink),
+    };
+
+    let mut compiler = InterpretedCompiler {
+        config: config.clone(),
+        graph: DiGraph::new(),
+        sources: HashMap::new(),
+        used_sources: vec![],
+    };
+
+    let final_node = compiler.add_operator(operator)?;
+    let fields = row_fields(&operator.return_type())?;
+    let rows_index = compiler.add_interpreted(final_node, InterpretedOperator::SinkRows(fields));
+    let sink_index = compiler.add_node("sink", sink);
+    compiler.graph.add_edge(
+        rows_index,
+        sink_index,
+        StreamEdge::unkeyed_edge("arroyo_worker::interpreted::NamedRow", Forward),
+    );
+
+    Ok((
+        Program {
+            types: vec![],
+            other_defs: vec![],
+            graph: compiler.graph,
+        },
+        compiler.used_sources,
+    ))
+}
+
+fn row_fields(struct_def: &StructDef) -> Result<Vec<RowField>> {
+    struct_def
+        .fields
+        .iter()
+        .map(|field| {
+            Ok(RowField {
+                name: field.field_name(),
+                typ: row_type(&field.data_type)?,
+            })
+        })
+        .collect()
+}
+
+fn row_type(type_def: &TypeDef) -> Result<RowType> {
+    Ok(match type_def {
+        TypeDef::StructDef(struct_def, _) => RowType::Struct(row_fields(struct_def)?),
+        TypeDef::DataType(data_type, _) => match data_type {
+            DataType::Boolean => RowType::Bool,
+            DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::Int64 => RowType::Int,
+            DataType::UInt8 | DataType::UInt16 | DataType::UInt32 | DataType::UInt64 => {
+                RowType::UInt
+            }
+            DataType::Float16 | DataType::Float32 | DataType::Float64 => RowType::Float,
+            DataType::Utf8 | DataType::LargeUtf8 => RowType::String,
+            DataType::Binary | DataType::LargeBinary => RowType::Bytes,
+            DataType::Timestamp(_, None) => RowType::Timestamp,
+            _ => bail!("{:?} can't be interpreted", data_type),
+        },
+    })
+}
+
+struct InterpretedCompiler {
+    config: SqlConfig,
+    graph: DiGraph<StreamNode, StreamEdge>,
+    sources: HashMap<String, NodeIndex>,
+    used_sources: Vec<SqlSource>,
+}
+
+impl InterpretedCompiler {
+    fn add_operator(&mut self, operator: &SqlOperator) -> Result<NodeIndex> {
+        match operator {
+            SqlOperator::Source(name, source) => self.add_source(name, source),
+            SqlOperator::RecordTransform(input, record_transform) => {
+                let input_index = self.add_operator(input)?;
+                self.add_record_transform(input_index, input.return_type(), record_transform)
+            }
+            SqlOperator::Aggregator(input, aggregate_operator) => {
+                let AggregatingStrategy::TwoPhaseAggregateProjection(two_phase) =
+                    &aggregate_operator.aggregating
+                else {
+                    bail!("only two-phase aggregates can be interpreted");
+                };
+                let input_index = self.add_operator(input)?;
+                let input_struct = input.return_type();
+
+                let key = aggregate_operator
+                    .key
+                    .field_computations
+                    .iter()
+                    .map(|e| e.to_interpreted(&input_struct))
+                    .collect::<Result<_>>()?;
+                let key_index = self.add_interpreted(input_index, InterpretedOperator::KeyBy(key));
+
+                let aggregates = two_phase
+                    .field_computations
+                    .iter()
+                    .map(|a| a.to_interpreted(&input_struct))
+                    .collect::<Result<_>>()?;
+                let (width, slide) = match aggregate_operator.window {
+                    WindowType::Tumbling { width } => (width, width),
+                    WindowType::Sliding { width, slide } => (width, slide),
+                    WindowType::Instant => (Duration::ZERO, Duration::ZERO),
+                };
+                let window_operator = if width == slide {
+                    InterpretedOperator::TumblingAggregate { width, aggregates }
+                } else {
+                    InterpretedOperator::SlidingAggregate {
+                        width,
+                        slide,
+                        aggregates,
+                    }
+                };
+                let window_index = self.add_node(
+                    window_operator.name(),
+                    Operator::Interpreted(window_operator),
+                );
+                self.graph.add_edge(
+                    key_index,
+                    window_index,
+                    StreamEdge::keyed_edge(ROW_TYPE, ROW_TYPE, Shuffle),
+                );
+
+                let window_index_in_output = match &aggregate_operator.merge {
+                    GroupByKind::Basic => None,
+                    GroupByKind::WindowOutput { index, .. } => Some(*index),
+                };
+                let merge = InterpretedOperator::MergeAggregate {
+                    window_index: window_index_in_output,
+                    width,
+                };
+                let merge_index = self.add_node(merge.name(), Operator::Interpreted(merge));
+                self.graph.add_edge(
+                    window_index,
+                    merge_index,
+                    StreamEdge::keyed_edge(ROW_TYPE, ROW_TYPE, Forward),
+                );
+                Ok(merge_index)
+            }
+            _ => bail!("{} operators can't be interpreted", operator_kind(operator)),
+        }
+    }
+
+    fn add_source(&mut self, name: &String, source: &SqlSource) -> Result<NodeIndex> {
+        if let Some(node_index) = self.sources.get(name) {
+            return Ok(*node_index);
+        }
+
+        // the interpreted worker converts the records of these sources to rows through serde
+        let event_type = match &source.operator {
+            Operator::ImpulseSource { .. } => "arroyo_types::ImpulseEvent",
+            Operator::NexmarkSource { .. } => "arroyo_types::nexmark::Event",
+            operator => bail!("{:?} can't be interpreted", operator),
+        };
+        let fields = row_fields(&source.struct_def)?;
+
+        let source_index = self.add_node("source", source.operator.clone());
+        let rows_operator = InterpretedOperator::SourceRows(fields);
+        let rows_index = self.add_node(rows_operator.name(), Operator::Interpreted(rows_operator));
+        self.graph.add_edge(
+            source_index,
+            rows_index,
+            StreamEdge::unkeyed_edge(event_type, Forward),
+        );
+
+        let watermark_index = self.add_node(
+            "watermark",
+            Operator::Watermark(WatermarkType::Periodic {
+                period: Duration::from_secs(1),
+                max_lateness: Duration::from_secs(0),
+            }),
+        );
+        self.graph.add_edge(
+            rows_index,
+            watermark_index,
+            StreamEdge::unkeyed_edge(ROW_TYPE, Forward),
+        );
+
+        self.sources.insert(name.clone(), watermark_index);
+        self.used_sources.push(source.clone());
+        Ok(watermark_index)
+    }
+
+    fn add_record_transform(
+        &mut self,
+        input_index: NodeIndex,
+        input_struct: StructDef,
+        record_transform: &RecordTransform,
+    ) -> Result<NodeIndex> {
+        match record_transform {
+            RecordTransform::ValueProjection(projection) => {
+                let exprs = projection
+                    .field_computations
+                    .iter()
+                    .map(|e| e.to_interpreted(&input_struct))
+                    .collect::<Result<_>>()?;
+                Ok(self.add_interpreted(input_index, InterpretedOperator::Project(exprs)))
+            }
+            RecordTransform::Filter(predicate) => {
+                let predicate = predicate.to_interpreted(&input_struct)?;
+                Ok(self.add_interpreted(input_index, InterpretedOperator::Filter(predicate)))
+            }
+            RecordTransform::Sequence(sequence) => {
+                let mut index = input_index;
+                let mut input_struct = input_struct;
+                for record_transform in sequence {
+                    let output_struct = record_transform.output_struct(input_struct.clone());
+                    index = self.add_record_transform(index, input_struct, record_transform)?;
+                    input_struct = output_struct;
+                }
+                Ok(index)
+            }
+            RecordTransform::KeyProjection(_) => bail!("key projections can't be interpreted"),
+        }
+    }
+
+    /// Adds an operator that reads unkeyed rows from the input node
+    fn add_interpreted(
+        &mut self,
+        input_index: NodeIndex,
+        operator: InterpretedOperator,
+    ) -> NodeIndex {
+        let node_index = self.add_node(operator.name(), Operator::Interpreted(operator));
+        self.graph.add_edge(
+            input_index,
+            node_index,
+            StreamEdge::unkeyed_edge(ROW_TYPE, Forward),
+        );
+        node_index
+    }
+
+    fn add_node(&mut self, descriptor: &str, operator: Operator) -> NodeIndex {
+        self.graph.add_node(StreamNode {
+            operator_id: format!("{}_{}", descriptor, self.graph.node_count()),
+            operator,
+            parallelism: self.config.default_parallelism,
+        })
+    }
+}
+
+fn operator_kind(operator: &SqlOperator) -> &'static str {
+    match operator {
+        SqlOperator::Source(..) => "source",
+        SqlOperator::Aggregator(..) => "aggregate",
+        SqlOperator::JoinOperator(..) => "join",
+        SqlOperator::Window(..) => "window function",
+        SqlOperator::WindowAggregateTopN(..) => "top-n",
+        SqlOperator::RecordTransform(..) => "record transform",
+        SqlOperator::Distinct(..) => "distinct",
+    }
+}
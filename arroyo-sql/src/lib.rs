@@ -10,6 +10,7 @@ use datafusion::optimizer::OptimizerContext;
 use datafusion::physical_plan::functions::make_scalar_function;
 
 mod expressions;
+mod interpreted;
 mod operators;
 mod pipeline;
 pub mod schemas;
@@ -34,7 +35,7 @@ use schemas::window_arrow_struct;
 use syn::{parse_quote, parse_str};
 use types::{StructDef, StructField, TypeDef};
 
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 use std::{collections::HashMap, sync::Arc};
 
 use crate::expressions::ExpressionGenerator;
@@ -210,6 +211,12 @@ pub struct SqlSource {
 pub struct SqlConfig {
     pub default_parallelism: usize,
     pub sink: Operator,
+    /// Run the pipeline on the interpreted worker when it only uses supported operators,
+    /// instead of generating and compiling code for it
+    pub interpreted: bool,
+    /// How long operators that aren't windowed, like SELECT DISTINCT, keep the state for a key
+    /// that hasn't been seen; if unset it's kept forever
+    pub state_ttl: Option<Duration>,
 }
 
 impl Default for SqlConfig {
@@ -217,6 +224,8 @@ impl Default for SqlConfig {
         Self {
             default_parallelism: 4,
             sink: Operator::ConsoleSink,
+            interpreted: true,
+            state_ttl: None,
         }
     }
 }
@@ -246,7 +255,15 @@ pub async fn parse_and_get_program(
 
         let plan = sql_to_rel.sql_statement_to_plan(statement.clone())?;
         let optimizer_config = OptimizerContext::default();
-        let optimizer = Optimizer::new();
+        // DISTINCT is planned as a dedup rather than as the aggregate datafusion rewrites it to,
+        // which would only remove duplicates with the same timestamp
+        let optimizer = Optimizer::with_rules(
+            Optimizer::new()
+                .rules
+                .into_iter()
+                .filter(|rule| rule.name() != "replace_distinct_aggregate")
+                .collect(),
+        );
         let optimized_plan = optimizer.optimize(&plan, &optimizer_config, |_plan, _rule| {})?;
         get_program_from_plan(config, schema_provider, &optimized_plan)
     })
@@ -426,7 +443,9 @@ pub fn get_test_expression(
     let plan = optimizer
         .optimize(&plan, &mut optimizer_config, |_plan, _rule| {})
         .unwrap();
-    let LogicalPlan::Projection(projection) = plan else {panic!("expect projection")};
+    let LogicalPlan::Projection(projection) = plan else {
+        panic!("expect projection")
+    };
     let generating_expression = to_expression_generator(&projection.expr[0], &struct_def).unwrap();
     generate_test_code(
         test_name,
@@ -8,7 +8,7 @@ use crate::{
 use anyhow::bail;
 use anyhow::Result;
 use arrow_schema::DataType;
-use arroyo_datastream::WindowType;
+use arroyo_datastream::{interpreted, WindowType};
 use datafusion_expr::{
     expr::AggregateFunction,
     type_coercion::aggregates::{avg_return_type, sum_return_type},
@@ -573,6 +573,24 @@ impl TwoPhaseAggregation {
         }
     }
 
+    pub(crate) fn to_interpreted(
+        &self,
+        input_struct: &StructDef,
+    ) -> Result<interpreted::Aggregate> {
+        let op = match self.aggregator {
+            Aggregator::Count => interpreted::AggregateOp::Count,
+            Aggregator::Sum => interpreted::AggregateOp::Sum,
+            Aggregator::Min => interpreted::AggregateOp::Min,
+            Aggregator::Max => interpreted::AggregateOp::Max,
+            Aggregator::Avg => interpreted::AggregateOp::Avg,
+            Aggregator::CountDistinct => bail!("count distinct can't be interpreted"),
+        };
+        Ok(interpreted::Aggregate {
+            op,
+            input: self.incoming_expression.to_interpreted(input_struct)?,
+        })
+    }
+
     fn aggregate_type(&self) -> syn::Type {
         self.aggregate_type_def().return_type()
     }
@@ -27,6 +27,7 @@ use crate::{
         to_expression_generator, Column, ColumnExpression, Expression, ExpressionGenerator,
         SortExpression,
     },
+    interpreted::get_interpreted_program,
     operators::{
         AggregateProjection, GroupByKind, Projection, TwoPhaseAggregateProjection,
         TwoPhaseAggregation,
@@ -48,6 +49,7 @@ pub enum SqlOperator {
         SqlWindowOperator,
     ),
     RecordTransform(Box<SqlOperator>, RecordTransform),
+    Distinct(Box<SqlOperator>),
 }
 
 #[derive(Debug)]
@@ -74,7 +76,7 @@ impl RecordTransform {
         }
     }
 
-    fn output_struct(&self, input_struct: StructDef) -> StructDef {
+    pub(crate) fn output_struct(&self, input_struct: StructDef) -> StructDef {
         match self {
             RecordTransform::ValueProjection(projection) => projection.output_struct(),
             RecordTransform::KeyProjection(_) | RecordTransform::Filter(_) => input_struct,
@@ -100,10 +102,10 @@ impl RecordTransform {
 
 #[derive(Debug)]
 pub struct AggregateOperator {
-    key: Projection,
-    window: WindowType,
-    aggregating: AggregatingStrategy,
-    merge: GroupByKind,
+    pub(crate) key: Projection,
+    pub(crate) window: WindowType,
+    pub(crate) aggregating: AggregatingStrategy,
+    pub(crate) merge: GroupByKind,
 }
 impl AggregateOperator {
     fn output_struct(&self) -> StructDef {
@@ -321,6 +323,7 @@ impl SqlOperator {
             SqlOperator::RecordTransform(input, record_transform) => {
                 record_transform.output_struct(input.return_type())
             }
+            SqlOperator::Distinct(input) => input.return_type(),
         }
     }
 
@@ -382,6 +385,7 @@ impl SqlOperator {
             SqlOperator::RecordTransform(_input, record_transform) => {
                 structs.append(&mut record_transform.all_structs());
             }
+            SqlOperator::Distinct(_) => structs.append(&mut self.return_type().all_structs()),
         };
         structs
     }
@@ -413,6 +417,8 @@ impl SqlOperator {
             SqlOperator::RecordTransform(_, record_transform) => {
                 structs.append(&mut record_transform.key_names())
             }
+            // the whole record is the key
+            SqlOperator::Distinct(_) => structs.append(&mut self.return_type().all_names()),
         };
         Ok(structs)
     }
@@ -488,7 +494,9 @@ impl SqlPipelineBuilder {
             LogicalPlan::Explain(_) => bail!("explain is not currently supported"),
             LogicalPlan::Analyze(_) => bail!("analyze is not currently supported"),
             LogicalPlan::Extension(_) => bail!("extensions are not currently supported"),
-            LogicalPlan::Distinct(_) => bail!("distinct is not currently supported"),
+            LogicalPlan::Distinct(distinct) => Ok(SqlOperator::Distinct(Box::new(
+                self.insert_sql_plan(&distinct.input)?,
+            ))),
             LogicalPlan::Window(window) => self.insert_window(window),
             LogicalPlan::Prepare(_) => bail!("prepare commands are not currently supported"),
             LogicalPlan::Dml(_) => bail!("DML statements not currently supported"),
@@ -1240,6 +1248,13 @@ pub fn get_program_from_operator(
     operator: &SqlOperator,
     schema_provider: ArroyoSchemaProvider,
 ) -> Result<(Program, Vec<SqlSource>)> {
+    if config.interpreted {
+        // fall back to code generation for anything the interpreter doesn't support
+        if let Ok(result) = get_interpreted_program(&config, operator) {
+            return Ok(result);
+        }
+    }
+
     let mut graph_compiler = GraphCompiler::new(config.clone());
     let final_node = graph_compiler.add_operator(operator)?;
     let sink_index = graph_compiler.add_node("sink", config.sink);
@@ -1312,6 +1327,7 @@ impl GraphCompiler {
                 let input_index = self.add_operator(input)?;
                 self.add_record_transform(input_index, input.return_type(), record_transform)
             }
+            SqlOperator::Distinct(input) => self.add_distinct(input),
         }
     }
 
@@ -1398,6 +1414,43 @@ impl GraphCompiler {
         Ok(node_index)
     }
 
+    fn add_distinct(&mut self, input: &SqlOperator) -> Result<NodeIndex> {
+        let input_index = self.add_operator(input)?;
+        let struct_name = input.return_type().struct_name();
+
+        let key_operator =
+            MethodCompiler::key_map_operator("distinct_key", parse_quote!(arg.clone()));
+        let key_index = self.add_node("distinct_key", key_operator);
+        self.graph.add_edge(
+            input_index,
+            key_index,
+            StreamEdge::unkeyed_edge(struct_name.clone(), Forward),
+        );
+
+        let dedup_index = self.add_node(
+            "dedup",
+            Operator::Dedup {
+                ttl: self.config.state_ttl,
+            },
+        );
+        self.graph.add_edge(
+            key_index,
+            dedup_index,
+            StreamEdge::keyed_edge(struct_name.clone(), struct_name.clone(), Shuffle),
+        );
+
+        let unkey_operator =
+            MethodCompiler::value_map_operator("distinct", parse_quote!(arg.clone()));
+        let unkey_index = self.add_node("distinct", unkey_operator);
+        self.graph.add_edge(
+            dedup_index,
+            unkey_index,
+            StreamEdge::keyed_edge(struct_name.clone(), struct_name, Forward),
+        );
+
+        Ok(unkey_index)
+    }
+
     fn add_window_func(
         &mut self,
         input: &SqlOperator,
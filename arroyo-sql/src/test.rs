@@ -1,5 +1,7 @@
+use std::time::Duration;
+
 use arrow_schema::{DataType, TimeUnit};
-use arroyo_datastream::{NexmarkSource, Source};
+use arroyo_datastream::{NexmarkSource, Operator, Source};
 
 use crate::{
     parse_and_get_program,
@@ -195,3 +197,74 @@ async fn test_window_function() {
         .await
         .unwrap();
 }
+
+#[tokio::test]
+async fn test_interpreted_program() {
+    let schema_provider = || {
+        let mut schema_provider = ArroyoSchemaProvider::new();
+        schema_provider.add_source_with_type(
+            1,
+            "nexmark".to_string(),
+            test_schema(),
+            NexmarkSource {
+                first_event_rate: 10,
+                num_events: Some(100),
+            }
+            .as_operator(),
+            Some("arroyo_types::nexmark::NexmarkEvent".to_string()),
+        );
+        schema_provider
+    };
+
+    let sql = "SELECT bid.auction + 1 as auction FROM nexmark WHERE bid is not null";
+
+    let (program, _) = parse_and_get_program(sql, schema_provider(), SqlConfig::default())
+        .await
+        .unwrap();
+    assert!(program.is_interpreted());
+
+    let config = SqlConfig {
+        interpreted: false,
+        ..Default::default()
+    };
+    let (program, _) = parse_and_get_program(sql, schema_provider(), config)
+        .await
+        .unwrap();
+    assert!(!program.is_interpreted());
+}
+
+#[tokio::test]
+async fn test_distinct() {
+    let mut schema_provider = ArroyoSchemaProvider::new();
+    schema_provider.add_source_with_type(
+        1,
+        "nexmark".to_string(),
+        test_schema(),
+        NexmarkSource {
+            first_event_rate: 10,
+            num_events: Some(100),
+        }
+        .as_operator(),
+        Some("arroyo_types::nexmark::NexmarkEvent".to_string()),
+    );
+
+    let sql = "SELECT DISTINCT bid.auction as auction FROM nexmark WHERE bid is not null";
+
+    let config = SqlConfig {
+        state_ttl: Some(Duration::from_secs(60)),
+        ..Default::default()
+    };
+    let (program, _) = parse_and_get_program(sql, schema_provider, config)
+        .await
+        .unwrap();
+
+    let dedups: Vec<_> = program
+        .graph
+        .node_weights()
+        .filter_map(|node| match &node.operator {
+            Operator::Dedup { ttl } => Some(*ttl),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(vec![Some(Duration::from_secs(60))], dedups);
+}
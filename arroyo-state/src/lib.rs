@@ -1,29 +1,42 @@
+use crate::parquet::ParquetBackend;
 use anyhow::Result;
 use arroyo_rpc::grpc::{
-    CheckpointMetadata, OperatorCheckpointMetadata, TableDeleteBehavior, TableDescriptor,
-    TableType, TableWriteBehavior,
+    CheckpointMetadata, OperatorCheckpointMetadata, StateTtl, TableDeleteBehavior, TableDescriptor,
+    TableType, TableWriteBehavior, TtlTimeDomain, TtlUpdateType,
+};
+use arroyo_rpc::{ControlResp, StateQueryResult};
+use arroyo_types::{
+    CheckpointBarrier, Data, Key, TaskInfo, CHECKPOINT_MODE_ENV, STATE_BACKEND_ENV,
 };
-use arroyo_rpc::ControlResp;
-use arroyo_types::{CheckpointBarrier, Data, Key, TaskInfo};
 use async_trait::async_trait;
 use bincode::config::Configuration;
-use std::any::Any;
+use schema::OperatorSchemas;
+use spill::{SpillBackend, SpillStore};
 use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
 use std::time::{Duration, SystemTime};
 use tables::{
-    GlobalKeyedState, GlobalKeyedStateCache, KeyTimeMultiMap, KeyTimeMultiMapCache, TimeKeyMap,
-    TimeKeyMapCache,
+    EncodedRow, GlobalKeyedState, GlobalKeyedStateCache, KeyTimeMultiMap, KeyTimeMultiMapCache,
+    TableCache, TimeKeyMap, TimeKeyMapCache,
 };
 use tokio::sync::mpsc::Sender;
 
 pub mod parquet;
+pub mod schema;
+pub mod spill;
+pub mod storage;
 pub mod tables;
 
 pub const BINCODE_CONFIG: Configuration = bincode::config::standard();
 
-pub type StateBackend = parquet::ParquetBackend;
+// holds the records that were overtaken by the barrier of an unaligned checkpoint; only the files
+// of the latest checkpoint are kept, as older ones have already been replayed or superseded
+pub static IN_FLIGHT_TABLE: char = ']';
+
+// how many rows tables that are written in full with each checkpoint hand over at a time, which
+// bounds how much of a spilled table is brought into memory to checkpoint it
+const CHECKPOINT_ROWS_BATCH_SIZE: usize = 1024;
 
 pub fn global_table(name: impl Into<String>, description: impl Into<String>) -> TableDescriptor {
     TableDescriptor {
@@ -33,6 +46,27 @@ pub fn global_table(name: impl Into<String>, description: impl Into<String>) ->
         delete_behavior: TableDeleteBehavior::None as i32,
         write_behavior: TableWriteBehavior::DefaultWrites as i32,
         retention_micros: 0,
+        schema: None,
+        ttl: None,
+    }
+}
+
+// A global table whose entries are dropped once `ttl` has passed since they were last updated,
+// measured against either the operator's watermark or the wall clock
+pub fn global_table_with_ttl(
+    name: impl Into<String>,
+    description: impl Into<String>,
+    ttl: Duration,
+    update_type: TtlUpdateType,
+    time_domain: TtlTimeDomain,
+) -> TableDescriptor {
+    TableDescriptor {
+        ttl: Some(StateTtl {
+            ttl_micros: ttl.as_micros() as u64,
+            update_type: update_type as i32,
+            time_domain: time_domain as i32,
+        }),
+        ..global_table(name, description)
     }
 }
 
@@ -50,20 +84,50 @@ pub fn timestamp_table(
         delete_behavior: delete_behavior as i32,
         write_behavior: write_behavior as i32,
         retention_micros: retention.as_micros() as u64,
+        schema: None,
+        ttl: None,
+    }
+}
+
+pub fn in_flight_table() -> TableDescriptor {
+    TableDescriptor {
+        name: IN_FLIGHT_TABLE.to_string(),
+        description: "records in flight during an unaligned checkpoint".to_string(),
+        table_type: TableType::KeyTimeMultiMap as i32,
+        delete_behavior: TableDeleteBehavior::None as i32,
+        write_behavior: TableWriteBehavior::DefaultWrites as i32,
+        retention_micros: 0,
+        schema: None,
+        ttl: None,
     }
 }
 
+/// A record that was received on an input after another input had delivered the barrier of an
+/// unaligned checkpoint. Its effects aren't part of the checkpointed state, so it's stored with
+/// the checkpoint and replayed when restoring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InFlightRecord {
+    // the logical input of the operator the record was received on
+    pub input: usize,
+    pub key_hash: u64,
+    pub timestamp: SystemTime,
+    pub data: Vec<u8>,
+}
+
 #[async_trait]
 pub trait BackingStore {
-    async fn load_latest_checkpoint_metadata(job_id: &str) -> Option<CheckpointMetadata>;
+    async fn load_latest_checkpoint_metadata(job_id: &str) -> Result<Option<CheckpointMetadata>>;
 
-    async fn load_checkpoint_metadata(job_id: &str, epoch: u32) -> Option<CheckpointMetadata>;
+    async fn load_checkpoint_metadata(
+        job_id: &str,
+        epoch: u32,
+    ) -> Result<Option<CheckpointMetadata>>;
 
     async fn load_operator_metadata(
         job_id: &str,
         operator_id: &str,
         epoch: u32,
-    ) -> Option<OperatorCheckpointMetadata>;
+    ) -> Result<Option<OperatorCheckpointMetadata>>;
 
     async fn new(
         task_info: &TaskInfo,
@@ -82,9 +146,9 @@ pub trait BackingStore {
     // prepares a checkpoint to be loaded, e.g., by deleting future data
     async fn prepare_checkpoint(metadata: &CheckpointMetadata) -> Result<()>;
 
-    async fn complete_operator_checkpoint(metadata: OperatorCheckpointMetadata);
+    async fn complete_operator_checkpoint(metadata: OperatorCheckpointMetadata) -> Result<()>;
 
-    async fn complete_checkpoint(metadata: CheckpointMetadata);
+    async fn complete_checkpoint(metadata: CheckpointMetadata) -> Result<()>;
 
     async fn compact_checkpoint(
         metadata: CheckpointMetadata,
@@ -92,14 +156,56 @@ pub trait BackingStore {
         new_min_epoch: u32,
     ) -> Result<()>;
 
+    // copies a completed checkpoint to savepoint storage, which is untouched by compaction and
+    // job deletion
+    async fn write_savepoint(metadata: CheckpointMetadata, savepoint_id: &str) -> Result<()>;
+
+    // writes a savepoint as a checkpoint of `job_id` that the job can be restored from
+    async fn restore_savepoint(savepoint_id: &str, job_id: &str) -> Result<CheckpointMetadata>;
+
+    // rewrites the state of a checkpoint whose stored table schemas differ from `schemas`, which
+    // must have been checked with `schema::check_compatibility`. The migrated state is written as a
+    // new checkpoint, whose metadata is returned, leaving the original intact; returns None if
+    // nothing needed migrating
+    async fn migrate_checkpoint(
+        metadata: &CheckpointMetadata,
+        schemas: &OperatorSchemas,
+    ) -> Result<Option<CheckpointMetadata>>;
+
     async fn checkpoint(
         &mut self,
         barrier: CheckpointBarrier,
         watermark: Option<SystemTime>,
     ) -> u32;
 
+    // like `checkpoint`, but the checkpoint isn't reported as completed until the records that
+    // were in flight when it was taken have been written with `write_in_flight`
+    async fn checkpoint_unaligned(
+        &mut self,
+        barrier: CheckpointBarrier,
+        watermark: Option<SystemTime>,
+    ) -> u32;
+
+    async fn write_in_flight(&mut self, epoch: u32, records: Vec<InFlightRecord>);
+
+    // the in-flight records of the checkpoint this backend was restored from that fall into its
+    // key range
+    async fn get_in_flight(&self) -> Vec<InFlightRecord>;
+
     async fn get_data_triples<K: Key, V: Data>(&self, table: char) -> Vec<(SystemTime, K, V)>;
 
+    // the number of parts a table's checkpointed data is stored in, which lets tables that are
+    // larger than memory be restored a part at a time
+    fn restore_parts(&self, table: char) -> usize;
+
+    // the rows of one part of a table in the order they were written, without tombstones applied;
+    // `None` values record that the values for the key at that time were removed
+    async fn get_data_triples_part<K: Key, V: Data>(
+        &self,
+        table: char,
+        part: usize,
+    ) -> Vec<(SystemTime, K, Option<V>)>;
+
     async fn write_data_triple<K: Key, V: Data>(
         &mut self,
         table: char,
@@ -109,9 +215,301 @@ pub trait BackingStore {
         value: &mut V,
     );
 
+    // records that the values for `key` at `timestamp` have been removed
+    async fn delete_data_triple<K: Key>(
+        &mut self,
+        table: char,
+        table_type: TableType,
+        timestamp: SystemTime,
+        key: &mut K,
+    );
+
     async fn write_key_value<K: Key, V: Data>(&mut self, table: char, key: &mut K, value: &mut V);
 
+    // writes a row whose key and value have already been encoded by its table
+    async fn write_encoded_row(&mut self, table: char, row: EncodedRow);
+
     async fn get_key_values<K: Key, V: Data>(&self, table: char) -> Vec<(K, V)>;
+
+    // backends that support larger-than-memory state return a store that tables can move cold
+    // data to
+    fn spill_store(&self) -> Option<&SpillStore> {
+        None
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StateBackendType {
+    Memory,
+    Spill,
+}
+
+impl StateBackendType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StateBackendType::Memory => "memory",
+            StateBackendType::Spill => "spill",
+        }
+    }
+
+    pub fn from_env() -> Self {
+        match std::env::var(STATE_BACKEND_ENV).as_deref() {
+            Ok("spill") => StateBackendType::Spill,
+            Ok("memory") | Err(_) => StateBackendType::Memory,
+            Ok(other) => panic!("unknown {}: {}", STATE_BACKEND_ENV, other),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CheckpointMode {
+    Aligned,
+    Unaligned,
+}
+
+impl CheckpointMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CheckpointMode::Aligned => "aligned",
+            CheckpointMode::Unaligned => "unaligned",
+        }
+    }
+
+    pub fn from_env() -> Self {
+        match std::env::var(CHECKPOINT_MODE_ENV).as_deref() {
+            Ok("unaligned") => CheckpointMode::Unaligned,
+            Ok("aligned") | Err(_) => CheckpointMode::Aligned,
+            Ok(other) => panic!("unknown {}: {}", CHECKPOINT_MODE_ENV, other),
+        }
+    }
+}
+
+/// The backend used by workers, selected per job by the controller through `STATE_BACKEND_ENV`.
+/// Both variants write the same checkpoint format, so the checkpoint-level operations don't
+/// depend on which one a job uses.
+pub enum StateBackend {
+    Memory(ParquetBackend),
+    Spill(SpillBackend),
+}
+
+#[async_trait]
+impl BackingStore for StateBackend {
+    fn name() -> &'static str {
+        ParquetBackend::name()
+    }
+
+    async fn load_latest_checkpoint_metadata(job_id: &str) -> Result<Option<CheckpointMetadata>> {
+        ParquetBackend::load_latest_checkpoint_metadata(job_id).await
+    }
+
+    async fn load_checkpoint_metadata(
+        job_id: &str,
+        epoch: u32,
+    ) -> Result<Option<CheckpointMetadata>> {
+        ParquetBackend::load_checkpoint_metadata(job_id, epoch).await
+    }
+
+    async fn load_operator_metadata(
+        job_id: &str,
+        operator_id: &str,
+        epoch: u32,
+    ) -> Result<Option<OperatorCheckpointMetadata>> {
+        ParquetBackend::load_operator_metadata(job_id, operator_id, epoch).await
+    }
+
+    async fn new(
+        task_info: &TaskInfo,
+        tables: Vec<TableDescriptor>,
+        control_tx: Sender<ControlResp>,
+    ) -> Self {
+        match StateBackendType::from_env() {
+            StateBackendType::Memory => {
+                StateBackend::Memory(ParquetBackend::new(task_info, tables, control_tx).await)
+            }
+            StateBackendType::Spill => {
+                StateBackend::Spill(SpillBackend::new(task_info, tables, control_tx).await)
+            }
+        }
+    }
+
+    async fn from_checkpoint(
+        task_info: &TaskInfo,
+        metadata: CheckpointMetadata,
+        tables: Vec<TableDescriptor>,
+        control_tx: Sender<ControlResp>,
+    ) -> Self {
+        match StateBackendType::from_env() {
+            StateBackendType::Memory => StateBackend::Memory(
+                ParquetBackend::from_checkpoint(task_info, metadata, tables, control_tx).await,
+            ),
+            StateBackendType::Spill => StateBackend::Spill(
+                SpillBackend::from_checkpoint(task_info, metadata, tables, control_tx).await,
+            ),
+        }
+    }
+
+    async fn prepare_checkpoint(metadata: &CheckpointMetadata) -> Result<()> {
+        ParquetBackend::prepare_checkpoint(metadata).await
+    }
+
+    async fn complete_operator_checkpoint(metadata: OperatorCheckpointMetadata) -> Result<()> {
+        ParquetBackend::complete_operator_checkpoint(metadata).await
+    }
+
+    async fn complete_checkpoint(metadata: CheckpointMetadata) -> Result<()> {
+        ParquetBackend::complete_checkpoint(metadata).await
+    }
+
+    async fn compact_checkpoint(
+        metadata: CheckpointMetadata,
+        old_min_epoch: u32,
+        new_min_epoch: u32,
+    ) -> Result<()> {
+        ParquetBackend::compact_checkpoint(metadata, old_min_epoch, new_min_epoch).await
+    }
+
+    async fn write_savepoint(metadata: CheckpointMetadata, savepoint_id: &str) -> Result<()> {
+        ParquetBackend::write_savepoint(metadata, savepoint_id).await
+    }
+
+    async fn restore_savepoint(savepoint_id: &str, job_id: &str) -> Result<CheckpointMetadata> {
+        ParquetBackend::restore_savepoint(savepoint_id, job_id).await
+    }
+
+    async fn migrate_checkpoint(
+        metadata: &CheckpointMetadata,
+        schemas: &OperatorSchemas,
+    ) -> Result<Option<CheckpointMetadata>> {
+        ParquetBackend::migrate_checkpoint(metadata, schemas).await
+    }
+
+    async fn checkpoint(
+        &mut self,
+        barrier: CheckpointBarrier,
+        watermark: Option<SystemTime>,
+    ) -> u32 {
+        match self {
+            StateBackend::Memory(b) => b.checkpoint(barrier, watermark).await,
+            StateBackend::Spill(b) => b.checkpoint(barrier, watermark).await,
+        }
+    }
+
+    async fn checkpoint_unaligned(
+        &mut self,
+        barrier: CheckpointBarrier,
+        watermark: Option<SystemTime>,
+    ) -> u32 {
+        match self {
+            StateBackend::Memory(b) => b.checkpoint_unaligned(barrier, watermark).await,
+            StateBackend::Spill(b) => b.checkpoint_unaligned(barrier, watermark).await,
+        }
+    }
+
+    async fn write_in_flight(&mut self, epoch: u32, records: Vec<InFlightRecord>) {
+        match self {
+            StateBackend::Memory(b) => b.write_in_flight(epoch, records).await,
+            StateBackend::Spill(b) => b.write_in_flight(epoch, records).await,
+        }
+    }
+
+    async fn get_in_flight(&self) -> Vec<InFlightRecord> {
+        match self {
+            StateBackend::Memory(b) => b.get_in_flight().await,
+            StateBackend::Spill(b) => b.get_in_flight().await,
+        }
+    }
+
+    async fn get_data_triples<K: Key, V: Data>(&self, table: char) -> Vec<(SystemTime, K, V)> {
+        match self {
+            StateBackend::Memory(b) => b.get_data_triples(table).await,
+            StateBackend::Spill(b) => b.get_data_triples(table).await,
+        }
+    }
+
+    async fn write_data_triple<K: Key, V: Data>(
+        &mut self,
+        table: char,
+        table_type: TableType,
+        timestamp: SystemTime,
+        key: &mut K,
+        value: &mut V,
+    ) {
+        match self {
+            StateBackend::Memory(b) => {
+                b.write_data_triple(table, table_type, timestamp, key, value)
+                    .await
+            }
+            StateBackend::Spill(b) => {
+                b.write_data_triple(table, table_type, timestamp, key, value)
+                    .await
+            }
+        }
+    }
+
+    async fn delete_data_triple<K: Key>(
+        &mut self,
+        table: char,
+        table_type: TableType,
+        timestamp: SystemTime,
+        key: &mut K,
+    ) {
+        match self {
+            StateBackend::Memory(b) => {
+                b.delete_data_triple(table, table_type, timestamp, key)
+                    .await
+            }
+            StateBackend::Spill(b) => {
+                b.delete_data_triple(table, table_type, timestamp, key)
+                    .await
+            }
+        }
+    }
+
+    fn restore_parts(&self, table: char) -> usize {
+        match self {
+            StateBackend::Memory(b) => b.restore_parts(table),
+            StateBackend::Spill(b) => b.restore_parts(table),
+        }
+    }
+
+    async fn get_data_triples_part<K: Key, V: Data>(
+        &self,
+        table: char,
+        part: usize,
+    ) -> Vec<(SystemTime, K, Option<V>)> {
+        match self {
+            StateBackend::Memory(b) => b.get_data_triples_part(table, part).await,
+            StateBackend::Spill(b) => b.get_data_triples_part(table, part).await,
+        }
+    }
+
+    async fn write_key_value<K: Key, V: Data>(&mut self, table: char, key: &mut K, value: &mut V) {
+        match self {
+            StateBackend::Memory(b) => b.write_key_value(table, key, value).await,
+            StateBackend::Spill(b) => b.write_key_value(table, key, value).await,
+        }
+    }
+
+    async fn write_encoded_row(&mut self, table: char, row: EncodedRow) {
+        match self {
+            StateBackend::Memory(b) => b.write_encoded_row(table, row).await,
+            StateBackend::Spill(b) => b.write_encoded_row(table, row).await,
+        }
+    }
+
+    async fn get_key_values<K: Key, V: Data>(&self, table: char) -> Vec<(K, V)> {
+        match self {
+            StateBackend::Memory(b) => b.get_key_values(table).await,
+            StateBackend::Spill(b) => b.get_key_values(table).await,
+        }
+    }
+
+    fn spill_store(&self) -> Option<&SpillStore> {
+        match self {
+            StateBackend::Memory(_) => None,
+            StateBackend::Spill(b) => b.spill_store(),
+        }
+    }
 }
 
 pub struct StateStore<S: BackingStore> {
@@ -119,7 +517,8 @@ pub struct StateStore<S: BackingStore> {
     restore_from: Option<CheckpointMetadata>,
     task_info: TaskInfo,
     table_descriptors: HashMap<char, TableDescriptor>,
-    caches: HashMap<char, Box<dyn Any + Send>>,
+    caches: HashMap<char, Box<dyn TableCache>>,
+    watermark: Option<SystemTime>,
 }
 
 pub fn hash_key<K: Hash>(key: &K) -> u64 {
@@ -145,6 +544,7 @@ impl<S: BackingStore> StateStore<S> {
                 .collect(),
             restore_from: None,
             caches: HashMap::new(),
+            watermark: None,
         }
     }
 
@@ -165,11 +565,15 @@ impl<S: BackingStore> StateStore<S> {
                 .collect(),
             restore_from: Some(checkpoint_metadata),
             caches: HashMap::new(),
+            watermark: None,
         }
     }
 
-    // We now handle this in the individual tables. Don't love it, but they have different behaviors.
-    pub fn handle_watermark(&mut self, _watermark: SystemTime) {}
+    // Time-indexed tables handle watermarks themselves, as they have different behaviors; the
+    // watermark is only kept here to expire global tables with event-time ttls.
+    pub fn handle_watermark(&mut self, watermark: SystemTime) {
+        self.watermark = Some(watermark);
+    }
 
     pub async fn get_time_key_map<K: Key, V: Data>(
         &mut self,
@@ -178,7 +582,7 @@ impl<S: BackingStore> StateStore<S> {
     ) -> TimeKeyMap<K, V, S> {
         // this is done because populating it is async, so can't use or_insert().
         if let std::collections::hash_map::Entry::Vacant(e) = self.caches.entry(table) {
-            let cache: Box<dyn Any + Send> = match &self.restore_from {
+            let cache: Box<dyn TableCache> = match &self.restore_from {
                 Some(_restore_from) => {
                     let cache = TimeKeyMapCache::<K, V>::from_checkpoint(
                         &self.backend,
@@ -196,14 +600,15 @@ impl<S: BackingStore> StateStore<S> {
         }
 
         let cache = self.caches.get_mut(&table).unwrap();
-        let cache: &mut TimeKeyMapCache<K, V> = cache.downcast_mut().unwrap_or_else(|| {
-            panic!(
-                "Failed to get table {} with key {} and value {}",
-                table,
-                std::any::type_name::<K>(),
-                std::any::type_name::<V>()
-            )
-        });
+        let cache: &mut TimeKeyMapCache<K, V> =
+            cache.as_any_mut().downcast_mut().unwrap_or_else(|| {
+                panic!(
+                    "Failed to get table {} with key {} and value {}",
+                    table,
+                    std::any::type_name::<K>(),
+                    std::any::type_name::<V>()
+                )
+            });
         TimeKeyMap::new(table, &mut self.backend, cache)
     }
 
@@ -213,7 +618,7 @@ impl<S: BackingStore> StateStore<S> {
     ) -> KeyTimeMultiMap<K, V, S> {
         // this is done because populating it is async, so can't use or_insert().
         if let std::collections::hash_map::Entry::Vacant(e) = self.caches.entry(table) {
-            let cache: Box<dyn Any + Send> = match &self.restore_from {
+            let cache: Box<dyn TableCache> = match &self.restore_from {
                 Some(restore_from) => {
                     let cache = KeyTimeMultiMapCache::<K, V>::from_checkpoint(
                         &self.backend,
@@ -231,14 +636,15 @@ impl<S: BackingStore> StateStore<S> {
         }
 
         let cache = self.caches.get_mut(&table).unwrap();
-        let cache: &mut KeyTimeMultiMapCache<K, V> = cache.downcast_mut().unwrap_or_else(|| {
-            panic!(
-                "Failed to get table {} with key {} and value {}",
-                table,
-                std::any::type_name::<K>(),
-                std::any::type_name::<V>()
-            )
-        });
+        let cache: &mut KeyTimeMultiMapCache<K, V> =
+            cache.as_any_mut().downcast_mut().unwrap_or_else(|| {
+                panic!(
+                    "Failed to get table {} with key {} and value {}",
+                    table,
+                    std::any::type_name::<K>(),
+                    std::any::type_name::<V>()
+                )
+            });
         KeyTimeMultiMap::new(table, &mut self.backend, cache)
     }
 
@@ -248,48 +654,143 @@ impl<S: BackingStore> StateStore<S> {
     ) -> GlobalKeyedState<K, V, S> {
         // this is done because populating it is async, so can't use or_insert().
         if let std::collections::hash_map::Entry::Vacant(e) = self.caches.entry(table) {
-            let cache: Box<dyn Any + Send> = match &self.restore_from {
+            let ttl = self
+                .table_descriptors
+                .get(&table)
+                .and_then(|table| table.ttl.clone());
+            let cache: Box<dyn TableCache> = match &self.restore_from {
                 Some(_restore_from) => {
                     let cache =
-                        GlobalKeyedStateCache::<K, V>::from_checkpoint(&self.backend, table).await;
+                        GlobalKeyedStateCache::<K, V>::from_checkpoint(&self.backend, table, ttl)
+                            .await;
                     Box::new(cache)
                 }
-                None => Box::<tables::GlobalKeyedStateCache<K, V>>::default(),
+                None => Box::new(GlobalKeyedStateCache::<K, V>::new(ttl)),
             };
             e.insert(cache);
         }
 
         let cache = self.caches.get_mut(&table).unwrap();
-        let cache: &mut GlobalKeyedStateCache<K, V> = cache.downcast_mut().unwrap_or_else(|| {
-            panic!(
-                "Failed to get table {} with key {} and value {}",
-                table,
-                std::any::type_name::<K>(),
-                std::any::type_name::<V>()
-            )
-        });
+        let cache: &mut GlobalKeyedStateCache<K, V> =
+            cache.as_any_mut().downcast_mut().unwrap_or_else(|| {
+                panic!(
+                    "Failed to get table {} with key {} and value {}",
+                    table,
+                    std::any::type_name::<K>(),
+                    std::any::type_name::<V>()
+                )
+            });
+        cache.expire(self.watermark);
         GlobalKeyedState::new(table, &mut self.backend, cache)
     }
 
     pub async fn checkpoint(&mut self, barrier: CheckpointBarrier, watermark: Option<SystemTime>) {
+        self.write_checkpoint_rows().await;
         self.backend.checkpoint(barrier, watermark).await;
     }
+
+    pub async fn checkpoint_unaligned(
+        &mut self,
+        barrier: CheckpointBarrier,
+        watermark: Option<SystemTime>,
+    ) {
+        self.write_checkpoint_rows().await;
+        self.backend.checkpoint_unaligned(barrier, watermark).await;
+    }
+
+    // writes the rows of tables that are written in full with each checkpoint
+    async fn write_checkpoint_rows(&mut self) {
+        for (table, cache) in self.caches.iter_mut() {
+            cache.start_checkpoint(self.watermark);
+            loop {
+                let rows = cache.checkpoint_rows(
+                    *table,
+                    self.backend.spill_store(),
+                    CHECKPOINT_ROWS_BATCH_SIZE,
+                );
+                if rows.is_empty() {
+                    break;
+                }
+                for row in rows {
+                    self.backend.write_encoded_row(*table, row).await;
+                }
+            }
+        }
+    }
+
+    pub async fn write_in_flight(&mut self, epoch: u32, records: Vec<InFlightRecord>) {
+        self.backend.write_in_flight(epoch, records).await;
+    }
+
+    // Returns the records that need to be replayed into the operator before it processes any new
+    // input, which are only present if the checkpoint being restored from was unaligned.
+    pub async fn get_in_flight(&mut self) -> Vec<InFlightRecord> {
+        if self.restore_from.is_none() {
+            return vec![];
+        }
+        self.backend.get_in_flight().await
+    }
+
+    // Reads the current values of `key` from the cache of `table`, including any that have been
+    // spilled. Values are only returned if the key's hash falls into this task's key range, as
+    // otherwise they're owned by another subtask.
+    pub fn query(&self, table: char, key: &[u8]) -> Result<StateQueryResult, String> {
+        let Some(cache) = self.caches.get(&table) else {
+            return Err(if self.table_descriptors.contains_key(&table) {
+                format!(
+                    "table {} of operator {} has not been loaded yet",
+                    table, self.task_info.operator_id
+                )
+            } else {
+                format!(
+                    "operator {} has no table {}",
+                    self.task_info.operator_id, table
+                )
+            });
+        };
+
+        let (key_hash, values) = cache
+            .query(table, key, self.backend.spill_store())
+            .map_err(|e| format!("failed to query table {}: {}", table, e))?;
+
+        Ok(StateQueryResult {
+            key_hash,
+            values: self
+                .task_info
+                .key_range
+                .contains(&key_hash)
+                .then_some(values),
+        })
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use arroyo_rpc::grpc::{TableDeleteBehavior, TableDescriptor, TableWriteBehavior};
+    use arroyo_rpc::grpc::{
+        CheckpointMetadata, OperatorCheckpointMetadata, StateField, StateType, TableDeleteBehavior,
+        TableDescriptor, TableType, TableWriteBehavior, TtlTimeDomain, TtlUpdateType,
+    };
     use test_case::test_case;
     use tokio::sync::mpsc::Receiver;
 
     use arroyo_rpc::ControlResp;
     use rand::RngCore;
+    use std::borrow::Cow;
+    use std::ops::RangeInclusive;
     use std::time::{Duration, SystemTime};
     use tokio::sync::mpsc::channel;
 
-    use crate::parquet::ParquetBackend;
-    use crate::tables::{KeyTimeMultiMap, TimeKeyMap};
-    use crate::{global_table, timestamp_table, BackingStore, StateStore};
+    use crate::parquet::{ParquetBackend, TableFilter};
+    use crate::schema::{self, table_schema};
+    use crate::spill::SpillBackend;
+    use crate::tables::{
+        GlobalKeyedState, GlobalKeyedStateCache, KeyTimeMultiMap, KeyTimeMultiMapCache, TimeKeyMap,
+        TimeKeyMapCache,
+    };
+    use crate::{
+        global_table, global_table_with_ttl, hash_key, in_flight_table, timestamp_table,
+        BackingStore, InFlightRecord, StateStore, BINCODE_CONFIG,
+    };
     use arroyo_types::{CheckpointBarrier, TaskInfo};
 
     fn default_tables() -> Vec<TableDescriptor> {
@@ -323,7 +824,26 @@ mod test {
         )
     }
 
+    async fn spill_for_test() -> (StateStore<SpillBackend>, Receiver<ControlResp>) {
+        let job_id = rand::thread_rng().next_u64();
+        let operator_id = rand::thread_rng().next_u64();
+        let (tx, rx) = channel(10);
+        (
+            StateStore::<SpillBackend>::new(
+                &TaskInfo::for_test(
+                    &format!("test_job_{}", job_id),
+                    &format!("test_op_{}", operator_id),
+                ),
+                default_tables(),
+                tx,
+            )
+            .await,
+            rx,
+        )
+    }
+
     #[test_case(parquet_for_test().await; "parquet store")]
+    #[test_case(spill_for_test().await; "spill store")]
     #[tokio::test]
     async fn test_global(p: (StateStore<impl BackingStore>, Receiver<ControlResp>)) {
         let (mut ss, _rx) = p;
@@ -339,49 +859,255 @@ mod test {
 
         gs.insert("k2".into(), 2).await;
 
-        let mut entries = gs.get_all();
+        let mut entries: Vec<_> = gs.get_all().map(Cow::into_owned).collect();
         entries.sort();
 
-        assert_eq!(entries, vec![&1i64, &2]);
+        assert_eq!(entries, vec![1i64, 2]);
     }
 
-    #[test_case(parquet_for_test().await; "parquet store")]
     #[tokio::test]
-    async fn test_key_time_multi_map(p: (StateStore<impl BackingStore>, Receiver<ControlResp>)) {
-        let (mut ss, mut rx) = p;
-        let mut ks: KeyTimeMultiMap<String, i32, _> = ss.get_key_time_multi_map('t').await;
+    async fn test_global_ttl() {
+        let task_info = TaskInfo::for_test(
+            &format!("test_job_{}", rand::thread_rng().next_u64()),
+            "test_op",
+        );
+        let mut tables = default_tables();
+        let ttl = Duration::from_secs(10);
+        tables.push(global_table_with_ttl(
+            "w",
+            "expires on write",
+            ttl,
+            TtlUpdateType::OnWrite,
+            TtlTimeDomain::EventTime,
+        ));
+        tables.push(global_table_with_ttl(
+            "r",
+            "expires on read and write",
+            ttl,
+            TtlUpdateType::OnReadAndWrite,
+            TtlTimeDomain::EventTime,
+        ));
 
-        let k1 = "k1";
-        let t1 = SystemTime::now();
-        let t2 = t1 + Duration::from_secs(1);
-        let t3 = t1 + Duration::from_secs(2);
-        let t4 = t1 + Duration::from_secs(3);
-        let _t5 = t1 + Duration::from_secs(4);
+        let (tx, mut rx) = channel(10);
+        let mut ss = StateStore::<ParquetBackend>::new(&task_info, tables.clone(), tx).await;
 
-        ks.insert(t1, k1.into(), 1).await;
-        ks.insert(t1, k1.into(), 2).await;
-        ks.insert(t2, k1.into(), 3).await;
-        ks.insert(t3, k1.into(), 4).await;
-        ks.insert(t4, k1.into(), 5).await;
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+        ss.handle_watermark(t0);
+        for table in ['w', 'r'] {
+            let mut gs = ss.get_global_keyed_state::<String, i64>(table).await;
+            gs.insert("k1".into(), 1).await;
+        }
 
+        ss.handle_watermark(t0 + Duration::from_secs(5));
+        for table in ['w', 'r'] {
+            let mut gs = ss.get_global_keyed_state::<String, i64>(table).await;
+            gs.insert("k2".into(), 2).await;
+            assert_eq!(*gs.get(&"k1".into()).unwrap(), 1);
+        }
+
+        // k1 was written 12 seconds ago, but read 7 seconds ago
+        ss.handle_watermark(t0 + Duration::from_secs(12));
+        let mut gs = ss.get_global_keyed_state::<String, i64>('w').await;
+        assert_eq!(gs.get(&"k1".into()), None);
         assert_eq!(
-            ks.get_time_range(&mut k1.into(), t1, t1 + Duration::from_nanos(1))
-                .await,
-            vec![&1, &2]
+            gs.get_all().map(Cow::into_owned).collect::<Vec<_>>(),
+            vec![2]
         );
+
+        let mut gs = ss.get_global_keyed_state::<String, i64>('r').await;
+        let mut entries: Vec<_> = gs.get_all().map(Cow::into_owned).collect();
+        entries.sort();
+        assert_eq!(entries, vec![1, 2]);
+
+        ss.checkpoint(
+            CheckpointBarrier {
+                epoch: 1,
+                min_epoch: 0,
+                timestamp: SystemTime::now(),
+                then_stop: false,
+            },
+            None,
+        )
+        .await;
+        let Some(ControlResp::CheckpointCompleted(c)) = rx.recv().await else {
+            panic!("Received unexpected message on command queue");
+        };
+
+        ParquetBackend::complete_operator_checkpoint(OperatorCheckpointMetadata {
+            job_id: task_info.job_id.clone(),
+            operator_id: task_info.operator_id.clone(),
+            epoch: 1,
+            has_state: true,
+            tables: tables.clone(),
+            backend_data: c.subtask_metadata.backend_data,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        // the checkpoint holds k1 for both tables, but it has expired in 'w' based on the time
+        // it was written
+        let (tx, _rx) = channel(10);
+        let mut restored = StateStore::<ParquetBackend>::from_checkpoint(
+            &task_info,
+            CheckpointMetadata {
+                job_id: task_info.job_id.clone(),
+                epoch: 1,
+                operator_ids: vec![task_info.operator_id.clone()],
+                ..Default::default()
+            },
+            tables,
+            tx,
+        )
+        .await;
+        restored.handle_watermark(t0 + Duration::from_secs(12));
+
+        let mut gs = restored.get_global_keyed_state::<String, i64>('w').await;
         assert_eq!(
-            ks.get_time_range(&mut k1.into(), t1, t4).await,
-            vec![&1, &2, &3, &4]
+            gs.get_all().map(Cow::into_owned).collect::<Vec<_>>(),
+            vec![2]
         );
 
-        ss.backend
-            .checkpoint(
-                CheckpointBarrier {
-                    epoch: 1,
-                    min_epoch: 0,
-                    timestamp: SystemTime::now(),
-                    then_stop: false,
-                },
+        // the time k1 was last read is checkpointed along with it
+        let mut gs = restored.get_global_keyed_state::<String, i64>('r').await;
+        let mut entries: Vec<_> = gs.get_all().map(Cow::into_owned).collect();
+        entries.sort();
+        assert_eq!(entries, vec![1, 2]);
+    }
+
+    async fn checkpoint_and_restore(
+        ss: &mut StateStore<ParquetBackend>,
+        rx: &mut Receiver<ControlResp>,
+        task_info: &TaskInfo,
+        tables: &[TableDescriptor],
+        epoch: u32,
+    ) -> StateStore<ParquetBackend> {
+        ss.checkpoint(
+            CheckpointBarrier {
+                epoch,
+                min_epoch: 0,
+                timestamp: SystemTime::now(),
+                then_stop: false,
+            },
+            None,
+        )
+        .await;
+        let Some(ControlResp::CheckpointCompleted(c)) = rx.recv().await else {
+            panic!("Received unexpected message on command queue");
+        };
+
+        ParquetBackend::complete_operator_checkpoint(OperatorCheckpointMetadata {
+            job_id: task_info.job_id.clone(),
+            operator_id: task_info.operator_id.clone(),
+            epoch,
+            has_state: true,
+            tables: tables.to_vec(),
+            backend_data: c.subtask_metadata.backend_data,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let (tx, _rx) = channel(10);
+        StateStore::<ParquetBackend>::from_checkpoint(
+            task_info,
+            CheckpointMetadata {
+                job_id: task_info.job_id.clone(),
+                epoch,
+                operator_ids: vec![task_info.operator_id.clone()],
+                ..Default::default()
+            },
+            tables.to_vec(),
+            tx,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_global_ttl_across_checkpoints() {
+        let task_info = TaskInfo::for_test(
+            &format!("test_job_{}", rand::thread_rng().next_u64()),
+            "test_op",
+        );
+        let tables = vec![global_table_with_ttl(
+            "w",
+            "expires on write",
+            Duration::from_secs(10),
+            TtlUpdateType::OnWrite,
+            TtlTimeDomain::EventTime,
+        )];
+
+        let (tx, mut rx) = channel(10);
+        let mut ss = StateStore::<ParquetBackend>::new(&task_info, tables.clone(), tx).await;
+
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        ss.handle_watermark(t0);
+        let mut gs = ss.get_global_keyed_state::<String, i64>('w').await;
+        gs.insert("k1".into(), 1).await;
+        gs.insert("k2".into(), 2).await;
+        checkpoint_and_restore(&mut ss, &mut rx, &task_info, &tables, 1).await;
+
+        // only k2 is written in the second epoch, but k1 is still live
+        ss.handle_watermark(t0 + Duration::from_secs(5));
+        let mut gs = ss.get_global_keyed_state::<String, i64>('w').await;
+        gs.insert("k2".into(), 3).await;
+        let mut restored = checkpoint_and_restore(&mut ss, &mut rx, &task_info, &tables, 2).await;
+
+        restored.handle_watermark(t0 + Duration::from_secs(6));
+        let mut gs = restored.get_global_keyed_state::<String, i64>('w').await;
+        let mut entries: Vec<_> = gs.get_all().map(Cow::into_owned).collect();
+        entries.sort();
+        assert_eq!(entries, vec![1, 3]);
+
+        // entries keep the time they were last written, so k1 expires after a restore
+        let mut restored = checkpoint_and_restore(&mut ss, &mut rx, &task_info, &tables, 3).await;
+        restored.handle_watermark(t0 + Duration::from_secs(12));
+        let mut gs = restored.get_global_keyed_state::<String, i64>('w').await;
+        assert_eq!(
+            gs.get_all().map(Cow::into_owned).collect::<Vec<_>>(),
+            vec![3]
+        );
+    }
+
+    #[test_case(parquet_for_test().await; "parquet store")]
+    #[test_case(spill_for_test().await; "spill store")]
+    #[tokio::test]
+    async fn test_key_time_multi_map(p: (StateStore<impl BackingStore>, Receiver<ControlResp>)) {
+        let (mut ss, mut rx) = p;
+        let mut ks: KeyTimeMultiMap<String, i32, _> = ss.get_key_time_multi_map('t').await;
+
+        let k1 = "k1";
+        let t1 = SystemTime::now();
+        let t2 = t1 + Duration::from_secs(1);
+        let t3 = t1 + Duration::from_secs(2);
+        let t4 = t1 + Duration::from_secs(3);
+        let _t5 = t1 + Duration::from_secs(4);
+
+        ks.insert(t1, k1.into(), 1).await;
+        ks.insert(t1, k1.into(), 2).await;
+        ks.insert(t2, k1.into(), 3).await;
+        ks.insert(t3, k1.into(), 4).await;
+        ks.insert(t4, k1.into(), 5).await;
+
+        assert_eq!(
+            ks.get_time_range(&mut k1.into(), t1, t1 + Duration::from_nanos(1))
+                .await,
+            vec![&1, &2]
+        );
+        assert_eq!(
+            ks.get_time_range(&mut k1.into(), t1, t4).await,
+            vec![&1, &2, &3, &4]
+        );
+
+        ss.backend
+            .checkpoint(
+                CheckpointBarrier {
+                    epoch: 1,
+                    min_epoch: 0,
+                    timestamp: SystemTime::now(),
+                    then_stop: false,
+                },
                 Some(SystemTime::now()),
             )
             .await;
@@ -406,6 +1132,7 @@ mod test {
     }
 
     #[test_case(parquet_for_test().await; "parquet store")]
+    #[test_case(spill_for_test().await; "spill store")]
     #[tokio::test]
     async fn test_time_key_map(p: (StateStore<impl BackingStore>, Receiver<ControlResp>)) {
         let (mut ss, mut rx) = p;
@@ -425,8 +1152,11 @@ mod test {
 
         assert_eq!(ks.get_all_for_time(t1), vec![(&1, &2)]);
         assert_eq!(
-            ks.get_all().await,
-            vec![(t1, &1, &2), (t2, &1, &3), (t3, &1, &4), (t4, &1, &5)]
+            ks.get_all()
+                .await
+                .map(|(t, k, v)| (t, *k, *v))
+                .collect::<Vec<_>>(),
+            vec![(t1, 1, 2), (t2, 1, 3), (t3, 1, 4), (t4, 1, 5)]
         );
 
         ss.backend
@@ -451,8 +1181,939 @@ mod test {
 
         assert_eq!(ks.get_all_for_time(t1), vec![(&1, &2)]);
         assert_eq!(
-            ks.get_all().await,
-            vec![(t1, &1, &2), (t2, &1, &3), (t3, &1, &4), (t4, &1, &5)]
+            ks.get_all()
+                .await
+                .map(|(t, k, v)| (t, *k, *v))
+                .collect::<Vec<_>>(),
+            vec![(t1, 1, 2), (t2, 1, 3), (t3, 1, 4), (t4, 1, 5)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cleared_values_are_not_restored() {
+        let task_info = TaskInfo::for_test(
+            &format!("test_job_{}", rand::thread_rng().next_u64()),
+            "test_op",
+        );
+        let (tx, mut rx) = channel(10);
+        let mut backend = ParquetBackend::new(&task_info, default_tables(), tx).await;
+
+        // whole seconds, so that times survive the round trip through the checkpoint
+        let t1 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let t2 = t1 + Duration::from_secs(1);
+
+        let mut cache = KeyTimeMultiMapCache::<String, i32>::default();
+        let mut ks = KeyTimeMultiMap::new('t', &mut backend, &mut cache);
+        ks.insert(t1, "k1".into(), 1).await;
+        ks.insert(t1, "k1".into(), 2).await;
+        ks.insert(t2, "k1".into(), 3).await;
+        ks.clear_time_range(&mut "k1".into(), t1, t2).await;
+
+        backend
+            .checkpoint(
+                CheckpointBarrier {
+                    epoch: 1,
+                    min_epoch: 0,
+                    timestamp: SystemTime::now(),
+                    then_stop: false,
+                },
+                None,
+            )
+            .await;
+        let Some(ControlResp::CheckpointCompleted(c)) = rx.recv().await else {
+            panic!("Received unexpected message on command queue");
+        };
+
+        ParquetBackend::complete_operator_checkpoint(OperatorCheckpointMetadata {
+            job_id: task_info.job_id.clone(),
+            operator_id: task_info.operator_id.clone(),
+            epoch: 1,
+            has_state: true,
+            tables: default_tables(),
+            backend_data: c.subtask_metadata.backend_data,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let (tx, _rx) = channel(10);
+        let restored = ParquetBackend::from_checkpoint(
+            &task_info,
+            CheckpointMetadata {
+                job_id: task_info.job_id.clone(),
+                epoch: 1,
+                operator_ids: vec![task_info.operator_id.clone()],
+                ..Default::default()
+            },
+            default_tables(),
+            tx,
+        )
+        .await;
+
+        assert_eq!(
+            restored.get_data_triples::<String, i32>('t').await,
+            vec![(t2, "k1".to_string(), 3)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unaligned_checkpoint() {
+        let task_info = TaskInfo::for_test(
+            &format!("test_job_{}", rand::thread_rng().next_u64()),
+            "test_op",
+        );
+        let mut tables = default_tables();
+        tables.push(in_flight_table());
+
+        let (tx, mut rx) = channel(10);
+        let mut backend = ParquetBackend::new(&task_info, tables.clone(), tx).await;
+
+        let t1 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let t2 = t1 + Duration::from_secs(1);
+
+        backend
+            .write_data_triple(
+                't',
+                TableType::TimeKeyMap,
+                t1,
+                &mut "k1".to_string(),
+                &mut 1,
+            )
+            .await;
+        backend
+            .checkpoint_unaligned(
+                CheckpointBarrier {
+                    epoch: 1,
+                    min_epoch: 0,
+                    timestamp: SystemTime::now(),
+                    then_stop: false,
+                },
+                None,
+            )
+            .await;
+
+        // written after the snapshot, so it belongs to the next checkpoint
+        backend
+            .write_data_triple(
+                't',
+                TableType::TimeKeyMap,
+                t2,
+                &mut "k2".to_string(),
+                &mut 2,
+            )
+            .await;
+        assert!(
+            rx.try_recv().is_err(),
+            "checkpoint completed before its in-flight records were written"
+        );
+
+        let in_flight = InFlightRecord {
+            input: 1,
+            key_hash: hash_key(&"k3".to_string()),
+            timestamp: t2,
+            data: vec![1, 2, 3],
+        };
+        backend.write_in_flight(1, vec![in_flight.clone()]).await;
+
+        let Some(ControlResp::CheckpointCompleted(c)) = rx.recv().await else {
+            panic!("Received unexpected message on command queue");
+        };
+
+        ParquetBackend::complete_operator_checkpoint(OperatorCheckpointMetadata {
+            job_id: task_info.job_id.clone(),
+            operator_id: task_info.operator_id.clone(),
+            epoch: 1,
+            has_state: true,
+            tables: tables.clone(),
+            backend_data: c.subtask_metadata.backend_data,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let (tx, _rx) = channel(10);
+        let restored = ParquetBackend::from_checkpoint(
+            &task_info,
+            CheckpointMetadata {
+                job_id: task_info.job_id.clone(),
+                epoch: 1,
+                operator_ids: vec![task_info.operator_id.clone()],
+                ..Default::default()
+            },
+            tables,
+            tx,
+        )
+        .await;
+
+        assert_eq!(
+            restored.get_data_triples::<String, i32>('t').await,
+            vec![(t1, "k1".to_string(), 1)]
+        );
+        assert_eq!(restored.get_in_flight().await, vec![in_flight]);
+    }
+
+    #[tokio::test]
+    async fn test_restore_from_savepoint() {
+        let task_info = TaskInfo::for_test(
+            &format!("test_job_{}", rand::thread_rng().next_u64()),
+            "test_op",
+        );
+        let (tx, mut rx) = channel(10);
+        let mut backend = ParquetBackend::new(&task_info, default_tables(), tx).await;
+
+        let t1 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let mut cache = KeyTimeMultiMapCache::<String, i32>::default();
+        let mut ks = KeyTimeMultiMap::new('t', &mut backend, &mut cache);
+        ks.insert(t1, "k1".into(), 1).await;
+
+        backend
+            .checkpoint(
+                CheckpointBarrier {
+                    epoch: 1,
+                    min_epoch: 0,
+                    timestamp: SystemTime::now(),
+                    then_stop: false,
+                },
+                None,
+            )
+            .await;
+        let Some(ControlResp::CheckpointCompleted(c)) = rx.recv().await else {
+            panic!("Received unexpected message on command queue");
+        };
+
+        ParquetBackend::complete_operator_checkpoint(OperatorCheckpointMetadata {
+            job_id: task_info.job_id.clone(),
+            operator_id: task_info.operator_id.clone(),
+            epoch: 1,
+            has_state: true,
+            tables: default_tables(),
+            backend_data: c.subtask_metadata.backend_data,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let metadata = CheckpointMetadata {
+            job_id: task_info.job_id.clone(),
+            epoch: 1,
+            operator_ids: vec![task_info.operator_id.clone()],
+            ..Default::default()
+        };
+
+        let savepoint_id = format!("test_savepoint_{}", rand::thread_rng().next_u64());
+        ParquetBackend::write_savepoint(metadata, &savepoint_id)
+            .await
+            .unwrap();
+
+        let new_task_info = TaskInfo::for_test(
+            &format!("test_job_{}", rand::thread_rng().next_u64()),
+            "test_op",
+        );
+        let metadata = ParquetBackend::restore_savepoint(&savepoint_id, &new_task_info.job_id)
+            .await
+            .unwrap();
+        assert_eq!(metadata.epoch, 1);
+
+        let (tx, _rx) = channel(10);
+        let restored =
+            ParquetBackend::from_checkpoint(&new_task_info, metadata, default_tables(), tx).await;
+        assert_eq!(
+            restored.get_data_triples::<String, i32>('t').await,
+            vec![(t1, "k1".to_string(), 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_table() {
+        let task_info = TaskInfo::for_test(
+            &format!("test_job_{}", rand::thread_rng().next_u64()),
+            "test_op",
+        );
+        let (tx, mut rx) = channel(10);
+        let mut backend = ParquetBackend::new(&task_info, default_tables(), tx).await;
+
+        let t1 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let t2 = t1 + Duration::from_secs(1);
+
+        let mut cache = KeyTimeMultiMapCache::<String, i32>::default();
+        let mut ks = KeyTimeMultiMap::new('t', &mut backend, &mut cache);
+        for i in 0..4 {
+            ks.insert(t1, format!("k{}", i), i).await;
+            ks.insert(t2, format!("k{}", i), 10 + i).await;
+        }
+        ks.clear_time_range(&mut "k0".into(), t1, t2).await;
+
+        backend
+            .checkpoint(
+                CheckpointBarrier {
+                    epoch: 1,
+                    min_epoch: 0,
+                    timestamp: SystemTime::now(),
+                    then_stop: false,
+                },
+                None,
+            )
+            .await;
+        let Some(ControlResp::CheckpointCompleted(c)) = rx.recv().await else {
+            panic!("Received unexpected message on command queue");
+        };
+
+        let mut tables = default_tables();
+        for table in &mut tables {
+            table.schema = Some(table_schema(primitive("String"), primitive("i32")));
+        }
+        let metadata = OperatorCheckpointMetadata {
+            job_id: task_info.job_id.clone(),
+            operator_id: task_info.operator_id.clone(),
+            epoch: 1,
+            has_state: true,
+            tables,
+            backend_data: c.subtask_metadata.backend_data,
+            ..Default::default()
+        };
+
+        let read = |start_time: Option<SystemTime>,
+                    end_time: Option<SystemTime>,
+                    key_range: RangeInclusive<u64>,
+                    limit: usize| {
+            let metadata = metadata.clone();
+            async move {
+                let filter = TableFilter {
+                    key_range,
+                    start_time,
+                    end_time,
+                };
+                let (rows, truncated) = ParquetBackend::read_table(&metadata, "t", &filter, limit)
+                    .await
+                    .unwrap();
+                let rows: Vec<(SystemTime, String, i64)> = rows
+                    .into_iter()
+                    .map(|row| {
+                        (
+                            row.timestamp,
+                            row.key.as_str().unwrap().to_string(),
+                            row.value.as_i64().unwrap(),
+                        )
+                    })
+                    .collect();
+                (rows, truncated)
+            }
+        };
+
+        let mut expected: Vec<(SystemTime, String, i64)> = (1..4)
+            .map(|i| (t1, format!("k{}", i), i))
+            .chain((0..4).map(|i| (t2, format!("k{}", i), 10 + i)))
+            .collect();
+        expected.sort_by_key(|(t, k, _)| (hash_key(k), *t));
+
+        let (rows, truncated) = read(None, None, 0..=u64::MAX, 100).await;
+        assert_eq!(expected, rows);
+        assert!(!truncated);
+
+        let (rows, truncated) = read(None, None, 0..=u64::MAX, 3).await;
+        assert_eq!(expected[..3], rows);
+        assert!(truncated);
+
+        let (rows, _) = read(Some(t2), None, 0..=u64::MAX, 100).await;
+        assert_eq!(
+            expected
+                .iter()
+                .filter(|(t, _, _)| *t == t2)
+                .cloned()
+                .collect::<Vec<_>>(),
+            rows
+        );
+
+        let (rows, _) = read(None, Some(t2), 0..=u64::MAX, 100).await;
+        assert_eq!(
+            expected
+                .iter()
+                .filter(|(t, _, _)| *t == t1)
+                .cloned()
+                .collect::<Vec<_>>(),
+            rows
+        );
+
+        let key_hash = hash_key(&"k2".to_string());
+        let (rows, _) = read(None, None, key_hash..=key_hash, 100).await;
+        assert_eq!(
+            vec![(t1, "k2".to_string(), 2), (t2, "k2".to_string(), 12)],
+            rows
+        );
+    }
+
+    #[tokio::test]
+    async fn test_load_latest_checkpoint() {
+        let task_info = TaskInfo::for_test(
+            &format!("test_job_{}", rand::thread_rng().next_u64()),
+            "test_op",
+        );
+        assert_eq!(
+            ParquetBackend::load_latest_checkpoint_metadata(&task_info.job_id)
+                .await
+                .unwrap(),
+            None
+        );
+
+        let (tx, mut rx) = channel(10);
+        let mut backend = ParquetBackend::new(&task_info, default_tables(), tx).await;
+        backend
+            .write_key_value('g', &mut "k1".to_string(), &mut 1i64)
+            .await;
+        backend
+            .checkpoint(
+                CheckpointBarrier {
+                    epoch: 1,
+                    min_epoch: 0,
+                    timestamp: SystemTime::now(),
+                    then_stop: false,
+                },
+                None,
+            )
+            .await;
+        let Some(ControlResp::CheckpointCompleted(c)) = rx.recv().await else {
+            panic!("Received unexpected message on command queue");
+        };
+
+        ParquetBackend::complete_operator_checkpoint(OperatorCheckpointMetadata {
+            job_id: task_info.job_id.clone(),
+            operator_id: task_info.operator_id.clone(),
+            epoch: 1,
+            has_state: true,
+            tables: default_tables(),
+            backend_data: c.subtask_metadata.backend_data,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let metadata = CheckpointMetadata {
+            job_id: task_info.job_id.clone(),
+            epoch: 1,
+            operator_ids: vec![task_info.operator_id.clone()],
+            ..Default::default()
+        };
+        ParquetBackend::complete_checkpoint(metadata.clone())
+            .await
+            .unwrap();
+
+        // a later checkpoint whose operators never finished can't be restored
+        ParquetBackend::complete_checkpoint(CheckpointMetadata {
+            epoch: 2,
+            ..metadata.clone()
+        })
+        .await
+        .unwrap();
+
+        let latest = ParquetBackend::load_latest_checkpoint_metadata(&task_info.job_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(latest, metadata);
+
+        let (tx, _rx) = channel(10);
+        let restored =
+            ParquetBackend::from_checkpoint(&task_info, latest, default_tables(), tx).await;
+        assert_eq!(
+            restored.get_data_triples::<String, i64>('g').await,
+            vec![(SystemTime::UNIX_EPOCH, "k1".to_string(), 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_spilling() {
+        let (tx, _rx) = channel(10);
+        let mut backend = SpillBackend::new(
+            &TaskInfo::for_test(
+                &format!("test_job_{}", rand::thread_rng().next_u64()),
+                "test_op",
+            ),
+            default_tables(),
+            tx,
+        )
+        .await;
+        backend.set_max_in_memory_entries(2);
+
+        let t1 = SystemTime::now();
+        let t2 = t1 + Duration::from_secs(1);
+        let t3 = t1 + Duration::from_secs(2);
+
+        let mut cache = KeyTimeMultiMapCache::<String, i32>::default();
+        let mut ks = KeyTimeMultiMap::new('t', &mut backend, &mut cache);
+        ks.insert(t1, "k1".into(), 1).await;
+        ks.insert(t2, "k1".into(), 2).await;
+        ks.insert(t1, "k2".into(), 3).await;
+        ks.insert(t1, "k3".into(), 4).await;
+
+        // k1 is the coldest key and has been spilled, but is transparently read back
+        assert_eq!(
+            ks.get_time_range(&mut "k1".into(), t1, t3).await,
+            vec![&1, &2]
+        );
+        ks.insert(t3, "k1".into(), 5).await;
+        assert_eq!(
+            ks.get_time_range(&mut "k1".into(), t1, t3 + Duration::from_secs(1))
+                .await,
+            vec![&1, &2, &5]
+        );
+        ks.clear_time_range(&mut "k2".into(), t1, t2).await;
+        assert!(ks.get_time_range(&mut "k2".into(), t1, t3).await.is_empty());
+        assert_eq!(ks.get_time_range(&mut "k3".into(), t1, t3).await, vec![&4]);
+
+        let mut cache = TimeKeyMapCache::<usize, i32>::default();
+        let mut tm = TimeKeyMap::new('t', &mut backend, &mut cache);
+        tm.insert(t1, 1, 1);
+        tm.insert(t1, 2, 2);
+        tm.insert(t2, 1, 3);
+        tm.insert(t3, 1, 4);
+        tm.flush().await;
+
+        // t1 is the coldest time and has been spilled, and is read from the spill store
+        let mut all: Vec<_> = tm.get_all().await.map(|(t, k, v)| (t, *k, *v)).collect();
+        all.sort();
+        assert_eq!(all, vec![(t1, 1, 1), (t1, 2, 2), (t2, 1, 3), (t3, 1, 4)]);
+
+        assert_eq!(tm.get_min_time(), Some(t1));
+        assert_eq!(tm.get(t2, &mut 1), Some(&3));
+        let mut at_t1 = tm.get_all_for_time(t1);
+        at_t1.sort();
+        assert_eq!(at_t1, vec![(&1, &1), (&2, &2)]);
+        assert_eq!(tm.evict_all_before_watermark(t2).len(), 3);
+        assert_eq!(
+            tm.get_all()
+                .await
+                .map(|(t, k, v)| (t, *k, *v))
+                .collect::<Vec<_>>(),
+            vec![(t3, 1, 4)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_spilling_global_tables() {
+        let task_info = TaskInfo::for_test(
+            &format!("test_job_{}", rand::thread_rng().next_u64()),
+            "test_op",
+        );
+        let (tx, mut rx) = channel(10);
+        let mut backend = SpillBackend::new(&task_info, default_tables(), tx).await;
+        backend.set_max_in_memory_entries(1);
+
+        let mut cache = GlobalKeyedStateCache::<String, i64>::new(None);
+        let mut gs = GlobalKeyedState::new('g', &mut backend, &mut cache);
+        gs.insert("k1".into(), 1).await;
+        gs.insert("k2".into(), 2).await;
+        gs.insert("k3".into(), 3).await;
+
+        // k1 and k2 have been spilled, but are transparently read back
+        assert_eq!(gs.get(&"k1".into()), Some(&1));
+        let mut all: Vec<_> = gs.get_all().map(Cow::into_owned).collect();
+        all.sort();
+        assert_eq!(all, vec![1, 2, 3]);
+
+        // reading every value leaves k2 in the spill store rather than bringing it back
+        let store = backend.spill_store().unwrap();
+        assert_eq!(
+            store
+                .get::<i64>('g', &bincode::encode_to_vec("k2", BINCODE_CONFIG).unwrap())
+                .unwrap(),
+            Some(2)
+        );
+
+        backend
+            .checkpoint(
+                CheckpointBarrier {
+                    epoch: 1,
+                    min_epoch: 0,
+                    timestamp: SystemTime::now(),
+                    then_stop: false,
+                },
+                None,
+            )
+            .await;
+        let Some(ControlResp::CheckpointCompleted(c)) = rx.recv().await else {
+            panic!("Received unexpected message on command queue");
+        };
+
+        SpillBackend::complete_operator_checkpoint(OperatorCheckpointMetadata {
+            job_id: task_info.job_id.clone(),
+            operator_id: task_info.operator_id.clone(),
+            epoch: 1,
+            has_state: true,
+            tables: default_tables(),
+            backend_data: c.subtask_metadata.backend_data,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        // restored as another subtask, so that it doesn't share the open spill store
+        let restored_info = TaskInfo {
+            task_index: 1,
+            ..task_info.clone()
+        };
+        let (tx, _rx) = channel(10);
+        let mut restored = SpillBackend::from_checkpoint(
+            &restored_info,
+            CheckpointMetadata {
+                job_id: task_info.job_id.clone(),
+                epoch: 1,
+                operator_ids: vec![task_info.operator_id.clone()],
+                ..Default::default()
+            },
+            default_tables(),
+            tx,
+        )
+        .await;
+        restored.set_max_in_memory_entries(1);
+
+        let mut cache =
+            GlobalKeyedStateCache::<String, i64>::from_checkpoint(&restored, 'g', None).await;
+
+        // the table was spilled while it was being restored
+        let store = restored.spill_store().unwrap();
+        let spilled = ["k1", "k2", "k3"]
+            .iter()
+            .filter(|k| {
+                store
+                    .get::<i64>('g', &bincode::encode_to_vec(k, BINCODE_CONFIG).unwrap())
+                    .unwrap()
+                    .is_some()
+            })
+            .count();
+        assert_eq!(spilled, 2);
+
+        let mut gs = GlobalKeyedState::new('g', &mut restored, &mut cache);
+        let mut all: Vec<_> = gs.get_all().map(Cow::into_owned).collect();
+        all.sort();
+        assert_eq!(all, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_query() {
+        let (tx, _rx) = channel(10);
+        let key = bincode::encode_to_vec("k1", BINCODE_CONFIG).unwrap();
+        let key_hash = hash_key(&"k1".to_string());
+
+        let mut task_info = TaskInfo::for_test(
+            &format!("test_job_{}", rand::thread_rng().next_u64()),
+            "test_op",
+        );
+        let mut ss =
+            StateStore::<ParquetBackend>::new(&task_info, default_tables(), tx.clone()).await;
+
+        assert!(
+            ss.query('g', &key).is_err(),
+            "unloaded tables can't be queried"
+        );
+        assert!(
+            ss.query('x', &key).is_err(),
+            "unknown tables can't be queried"
+        );
+
+        let mut gs = ss.get_global_keyed_state::<String, i64>('g').await;
+        gs.insert("k1".into(), 5).await;
+
+        let result = ss.query('g', &key).unwrap();
+        assert_eq!(result.key_hash, key_hash);
+        assert_eq!(
+            result.values,
+            Some(vec![(
+                None,
+                bincode::encode_to_vec(5i64, BINCODE_CONFIG).unwrap()
+            )])
+        );
+        assert_eq!(
+            ss.query('g', &bincode::encode_to_vec("k2", BINCODE_CONFIG).unwrap())
+                .unwrap()
+                .values,
+            Some(vec![])
+        );
+
+        // keys outside of the task's range belong to another subtask
+        task_info.key_range = key_hash.wrapping_add(1)..=key_hash.wrapping_add(1);
+        let mut ss = StateStore::<ParquetBackend>::new(&task_info, default_tables(), tx).await;
+        ss.get_global_keyed_state::<String, i64>('g').await;
+        let result = ss.query('g', &key).unwrap();
+        assert_eq!(result.key_hash, key_hash);
+        assert_eq!(result.values, None);
+    }
+
+    #[tokio::test]
+    async fn test_query_spilled() {
+        let value = |v: i32| bincode::encode_to_vec(v, BINCODE_CONFIG).unwrap();
+        let t1 = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+        let t2 = t1 + Duration::from_secs(1);
+
+        let (mut ss, _rx) = spill_for_test().await;
+        ss.backend.set_max_in_memory_entries(1);
+        let mut ks: KeyTimeMultiMap<String, i32, _> = ss.get_key_time_multi_map('t').await;
+        ks.insert(t1, "k1".into(), 1).await;
+        ks.insert(t2, "k1".into(), 2).await;
+        // makes k1 the coldest key, which moves it to the spill store
+        ks.insert(t1, "k2".into(), 3).await;
+
+        let key = bincode::encode_to_vec("k1", BINCODE_CONFIG).unwrap();
+        assert_eq!(
+            ss.query('t', &key).unwrap().values,
+            Some(vec![(Some(t1), value(1)), (Some(t2), value(2))])
+        );
+
+        let (mut ss, _rx) = spill_for_test().await;
+        ss.backend.set_max_in_memory_entries(1);
+        let mut tm: TimeKeyMap<usize, i32, _> = ss.get_time_key_map('t', None).await;
+        tm.insert(t1, 1, 1);
+        tm.insert(t1, 2, 2);
+        tm.insert(t2, 1, 3);
+        // persisting the values moves the coldest time, t1, to the spill store
+        tm.flush().await;
+        tm.insert(t2, 1, 4);
+
+        let key = bincode::encode_to_vec(1usize, BINCODE_CONFIG).unwrap();
+        assert_eq!(
+            ss.query('t', &key).unwrap().values,
+            Some(vec![(Some(t1), value(1)), (Some(t2), value(4))])
+        );
+    }
+
+    fn primitive(name: &str) -> StateType {
+        StateType {
+            name: name.to_string(),
+            params: vec![],
+            fields: vec![],
+        }
+    }
+
+    fn option(t: StateType) -> StateType {
+        StateType {
+            name: "Option".to_string(),
+            params: vec![t],
+            fields: vec![],
+        }
+    }
+
+    fn structure(fields: Vec<(&str, StateType)>) -> StateType {
+        StateType {
+            name: "struct".to_string(),
+            params: vec![],
+            fields: fields
+                .into_iter()
+                .map(|(name, t)| StateField {
+                    name: name.to_string(),
+                    data_type: Some(t),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_migrate_nullable_fields() {
+        #[derive(bincode::Encode)]
+        struct Old {
+            count: i64,
+            name: Option<String>,
+            time: SystemTime,
+            removed: Option<Vec<u64>>,
+        }
+
+        #[derive(bincode::Decode, Debug, PartialEq)]
+        struct New {
+            time: SystemTime,
+            added: Option<f64>,
+            count: i64,
+            name: Option<String>,
+        }
+
+        let old = table_schema(
+            primitive("u64"),
+            structure(vec![
+                ("count", primitive("i64")),
+                ("name", option(primitive("String"))),
+                ("time", primitive("SystemTime")),
+                (
+                    "removed",
+                    option(StateType {
+                        name: "Vec".to_string(),
+                        params: vec![primitive("u64")],
+                        fields: vec![],
+                    }),
+                ),
+            ]),
+        );
+        let new = table_schema(
+            primitive("u64"),
+            structure(vec![
+                ("time", primitive("SystemTime")),
+                ("added", option(primitive("f64"))),
+                ("count", primitive("i64")),
+                ("name", option(primitive("String"))),
+            ]),
+        );
+        schema::check_compatibility("a", &old, &new).unwrap();
+
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let bytes = bincode::encode_to_vec(
+            Old {
+                count: -1000,
+                name: Some("hello".to_string()),
+                time,
+                removed: Some(vec![1, 300, u64::MAX]),
+            },
+            BINCODE_CONFIG,
+        )
+        .unwrap();
+
+        let migrated = schema::migrate(
+            old.value.as_ref().unwrap(),
+            new.value.as_ref().unwrap(),
+            &bytes,
+        )
+        .unwrap();
+        let (value, _): (New, usize) =
+            bincode::decode_from_slice(&migrated, BINCODE_CONFIG).unwrap();
+        assert_eq!(
+            value,
+            New {
+                time,
+                added: None,
+                count: -1000,
+                name: Some("hello".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_incompatible_schemas_are_rejected() {
+        let old = table_schema(
+            primitive("u64"),
+            structure(vec![("count", primitive("i64"))]),
+        );
+
+        let added_required = table_schema(
+            primitive("u64"),
+            structure(vec![("count", primitive("i64")), ("sum", primitive("i64"))]),
+        );
+        assert!(schema::check_compatibility("a", &old, &added_required).is_err());
+
+        let changed_type = table_schema(
+            primitive("u64"),
+            structure(vec![("count", primitive("f64"))]),
+        );
+        assert!(schema::check_compatibility("a", &old, &changed_type).is_err());
+
+        let changed_key = table_schema(
+            primitive("String"),
+            structure(vec![("count", primitive("i64"))]),
+        );
+        assert!(schema::check_compatibility("a", &old, &changed_key).is_err());
+
+        let removed_required = table_schema(primitive("u64"), structure(vec![]));
+        assert!(schema::check_compatibility("a", &old, &removed_required).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_checkpoint() {
+        #[derive(bincode::Encode, bincode::Decode, Debug, Clone, PartialEq)]
+        struct Old {
+            count: i64,
+        }
+
+        #[derive(bincode::Encode, bincode::Decode, Debug, Clone, PartialEq)]
+        struct New {
+            count: i64,
+            name: Option<String>,
+        }
+
+        let old = table_schema(
+            primitive("String"),
+            structure(vec![("count", primitive("i64"))]),
+        );
+        let new = table_schema(
+            primitive("String"),
+            structure(vec![
+                ("count", primitive("i64")),
+                ("name", option(primitive("String"))),
+            ]),
+        );
+
+        let task_info = TaskInfo::for_test(
+            &format!("test_job_{}", rand::thread_rng().next_u64()),
+            "test_op",
+        );
+        let tables = vec![TableDescriptor {
+            schema: Some(old.clone()),
+            ..global_table("g", "test")
+        }];
+        let (tx, mut rx) = channel(10);
+        let mut ss = StateStore::<ParquetBackend>::new(&task_info, tables.clone(), tx).await;
+        ss.get_global_keyed_state::<String, Old>('g')
+            .await
+            .insert("k1".into(), Old { count: 5 })
+            .await;
+        checkpoint_and_restore(&mut ss, &mut rx, &task_info, &tables, 1).await;
+
+        let metadata = CheckpointMetadata {
+            job_id: task_info.job_id.clone(),
+            epoch: 1,
+            min_epoch: 1,
+            operator_ids: vec![task_info.operator_id.clone()],
+            ..Default::default()
+        };
+        ParquetBackend::complete_checkpoint(metadata.clone())
+            .await
+            .unwrap();
+
+        let schemas = |schema: &arroyo_rpc::grpc::TableSchema| {
+            [(
+                task_info.operator_id.clone(),
+                [("g".to_string(), schema.clone())].into_iter().collect(),
+            )]
+            .into_iter()
+            .collect()
+        };
+
+        // nothing is written when the schemas haven't changed
+        assert_eq!(
+            ParquetBackend::migrate_checkpoint(&metadata, &schemas(&old))
+                .await
+                .unwrap(),
+            None
+        );
+
+        let migrated = ParquetBackend::migrate_checkpoint(&metadata, &schemas(&new))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(migrated.epoch, 2);
+        assert_eq!(
+            ParquetBackend::load_latest_checkpoint_metadata(&task_info.job_id)
+                .await
+                .unwrap()
+                .unwrap()
+                .epoch,
+            2
+        );
+
+        // the original checkpoint is left as it was
+        let original =
+            ParquetBackend::load_operator_metadata(&task_info.job_id, &task_info.operator_id, 1)
+                .await
+                .unwrap()
+                .unwrap();
+        assert_eq!(original.tables[0].schema, Some(old));
+
+        let tables = vec![TableDescriptor {
+            schema: Some(new),
+            ..global_table("g", "test")
+        }];
+        let (tx, _rx) = channel(10);
+        let mut ss =
+            StateStore::<ParquetBackend>::from_checkpoint(&task_info, migrated, tables, tx).await;
+        assert_eq!(
+            ss.get_global_keyed_state::<String, New>('g')
+                .await
+                .get(&"k1".into()),
+            Some(&New {
+                count: 5,
+                name: None
+            })
         );
     }
 }
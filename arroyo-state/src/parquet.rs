@@ -1,35 +1,29 @@
-use crate::{hash_key, BackingStore, BINCODE_CONFIG};
-use anyhow::Result;
+use crate::schema::{self, OperatorSchemas};
+use crate::storage::StorageClient;
+use crate::tables::EncodedRow;
+use crate::{hash_key, BackingStore, InFlightRecord, BINCODE_CONFIG, IN_FLIGHT_TABLE};
+use anyhow::{anyhow, Result};
 use arrow_array::RecordBatch;
 use arroyo_rpc::grpc::backend_data::BackendData;
 use arroyo_rpc::grpc::{
-    backend_data, CheckpointMetadata, OperatorCheckpointMetadata, ParquetStoreData,
+    backend_data, CheckpointMetadata, OperatorCheckpointMetadata, ParquetStoreData, StateType,
     SubtaskCheckpointMetadata, TableDeleteBehavior, TableDescriptor, TableType,
 };
 use arroyo_rpc::{CheckpointCompleted, ControlResp};
-use arroyo_types::{
-    from_micros, to_micros, CheckpointBarrier, Data, Key, TaskInfo, S3_BUCKET_ENV, S3_REGION_ENV,
-};
+use arroyo_types::{from_micros, to_micros, CheckpointBarrier, Data, Key, TaskInfo};
 use bincode::config;
 use bytes::Bytes;
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
 use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
 use parquet::arrow::ArrowWriter;
+use parquet::file::metadata::RowGroupMetaData;
 use parquet::file::properties::WriterProperties;
+use parquet::file::statistics::Statistics;
 use prost::Message;
-use rusoto_core::{ByteStream, Region, RusotoError};
-use rusoto_s3::{
-    DeleteObjectRequest, GetObjectError, GetObjectRequest, PutObjectRequest, S3Client, S3,
-};
 use std::collections::{BTreeMap, HashMap, HashSet};
-use std::io::ErrorKind;
 use std::ops::RangeInclusive;
-use std::path::Path;
-use std::str::FromStr;
 use std::time::SystemTime;
-use tokio::fs::{remove_file, File};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::sync::mpsc::{self, Receiver, Sender};
 use tokio::sync::oneshot;
 use tracing::warn;
@@ -53,6 +47,32 @@ fn table_checkpoint_path(task_info: &TaskInfo, table: char, epoch: u32) -> Strin
     )
 }
 
+fn compacted_table_path(
+    job_id: &str,
+    operator_id: &str,
+    table: &str,
+    epoch: u32,
+    min_routing_key: u64,
+) -> String {
+    format!(
+        "{}/checkpoints/checkpoint-{:0>7}/data/operator-id-{}/table-{}/compacted-{:016x}",
+        job_id, epoch, operator_id, table, min_routing_key
+    )
+}
+
+fn migrated_table_path(
+    job_id: &str,
+    operator_id: &str,
+    table: &str,
+    epoch: u32,
+    index: usize,
+) -> String {
+    format!(
+        "{}/checkpoints/checkpoint-{:0>7}/data/operator-id-{}/table-{}/migrated-{:0>5}",
+        job_id, epoch, operator_id, table, index
+    )
+}
+
 fn operator_metadata_path(job_id: &str, operator_id: &str, epoch: u32) -> String {
     format!(
         "{}/checkpoints/checkpoint-{:0>7}/metadata/operators/operator-id-{}",
@@ -67,49 +87,114 @@ fn checkpoint_metadata_path(job_id: &str, epoch: u32) -> String {
     )
 }
 
+// the epoch of the checkpoint whose overall metadata is stored at `key`
+fn checkpoint_epoch(job_id: &str, key: &str) -> Option<u32> {
+    key.strip_prefix(&format!("{}/checkpoints/checkpoint-", job_id))?
+        .strip_suffix("/metadata/checkpoint-overall")?
+        .parse()
+        .ok()
+}
+
+// the epoch of the checkpoint whose directory `key` is in
+fn checkpoint_dir_epoch(job_id: &str, key: &str) -> Option<u32> {
+    key.strip_prefix(&format!("{}/checkpoints/checkpoint-", job_id))?
+        .split('/')
+        .next()?
+        .parse()
+        .ok()
+}
+
+fn savepoint_metadata_path(savepoint_id: &str) -> String {
+    format!("savepoints/{}/metadata/checkpoint-overall", savepoint_id)
+}
+
+fn savepoint_operator_metadata_path(savepoint_id: &str, operator_id: &str) -> String {
+    format!(
+        "savepoints/{}/metadata/operators/operator-id-{}",
+        savepoint_id, operator_id
+    )
+}
+
+fn savepoint_data_path(savepoint_id: &str, operator_id: &str, table: &str, index: usize) -> String {
+    format!(
+        "savepoints/{}/data/operator-id-{}/table-{}/file-{:0>5}",
+        savepoint_id, operator_id, table, index
+    )
+}
+
 #[async_trait::async_trait]
 impl BackingStore for ParquetBackend {
     fn name() -> &'static str {
         "parquet"
     }
 
-    async fn load_latest_checkpoint_metadata(_job_id: &str) -> Option<CheckpointMetadata> {
-        todo!()
+    // finds the newest checkpoint in storage that can be restored, independently of the
+    // controller's record of checkpoints
+    async fn load_latest_checkpoint_metadata(job_id: &str) -> Result<Option<CheckpointMetadata>> {
+        let storage_client = StorageClient::for_job(job_id);
+        let keys: HashSet<String> = storage_client
+            .list(format!("{}/checkpoints/", job_id))
+            .await?
+            .into_iter()
+            .collect();
+
+        let mut epochs: Vec<u32> = keys
+            .iter()
+            .filter_map(|key| checkpoint_epoch(job_id, key))
+            .collect();
+        epochs.sort_unstable();
+
+        for epoch in epochs.into_iter().rev() {
+            let Some(metadata) = Self::load_checkpoint_metadata(job_id, epoch).await? else {
+                continue;
+            };
+            if Self::is_restorable(&storage_client, &metadata, &keys).await? {
+                return Ok(Some(metadata));
+            }
+        }
+        Ok(None)
     }
 
-    // TODO: should this be a Result, rather than an option?
-    async fn load_checkpoint_metadata(job_id: &str, epoch: u32) -> Option<CheckpointMetadata> {
-        let storage_client = StorageClient::new();
-        let data = storage_client
+    async fn load_checkpoint_metadata(
+        job_id: &str,
+        epoch: u32,
+    ) -> Result<Option<CheckpointMetadata>> {
+        let storage_client = StorageClient::for_job(job_id);
+        let Some(data) = storage_client
             .get_bytes(checkpoint_metadata_path(job_id, epoch))
-            .await?;
-        let metadata = CheckpointMetadata::decode(&data[..]).unwrap();
-        Some(metadata)
+            .await?
+        else {
+            return Ok(None);
+        };
+        Ok(Some(CheckpointMetadata::decode(&data[..])?))
     }
 
     async fn load_operator_metadata(
         job_id: &str,
         operator_id: &str,
         epoch: u32,
-    ) -> Option<OperatorCheckpointMetadata> {
-        let storage_client = StorageClient::new();
-        let data = storage_client
+    ) -> Result<Option<OperatorCheckpointMetadata>> {
+        let storage_client = StorageClient::for_job(job_id);
+        let Some(data) = storage_client
             .get_bytes(operator_metadata_path(job_id, operator_id, epoch))
-            .await?;
-        Some(OperatorCheckpointMetadata::decode(&data[..]).unwrap())
+            .await?
+        else {
+            return Ok(None);
+        };
+        Ok(Some(OperatorCheckpointMetadata::decode(&data[..])?))
     }
 
-    async fn complete_operator_checkpoint(metadata: OperatorCheckpointMetadata) {
-        let storage_client = StorageClient::new();
+    async fn complete_operator_checkpoint(metadata: OperatorCheckpointMetadata) -> Result<()> {
+        let storage_client = StorageClient::for_job(&metadata.job_id);
         let path = operator_metadata_path(&metadata.job_id, &metadata.operator_id, metadata.epoch);
-        storage_client.write(path, metadata.encode_to_vec()).await;
+        storage_client.write(path, metadata.encode_to_vec()).await
     }
 
-    async fn complete_checkpoint(metadata: CheckpointMetadata) {
+    async fn complete_checkpoint(metadata: CheckpointMetadata) -> Result<()> {
         debug!("writing checkpoint {:?}", metadata);
-        let storage_client = StorageClient::new();
+        let storage_client = StorageClient::for_job(&metadata.job_id);
         let path = checkpoint_metadata_path(&metadata.job_id, metadata.epoch);
-        storage_client.write(path, metadata.encode_to_vec()).await;
+        storage_client.write(path, metadata.encode_to_vec()).await
     }
 
     async fn new(
@@ -125,7 +210,7 @@ impl BackingStore for ParquetBackend {
                 task_info.clone(),
                 tx,
                 tables.clone(),
-                StorageClient::new(),
+                StorageClient::for_job(&task_info.job_id),
                 HashMap::new(),
             ),
             task_info: task_info.clone(),
@@ -133,7 +218,7 @@ impl BackingStore for ParquetBackend {
                 .into_iter()
                 .map(|table| (table.name.clone().chars().next().unwrap(), table))
                 .collect(),
-            storage_client: StorageClient::new(),
+            storage_client: StorageClient::for_job(&task_info.job_id),
         }
     }
 
@@ -146,6 +231,12 @@ impl BackingStore for ParquetBackend {
         let operator_metadata =
             Self::load_operator_metadata(&task_info.job_id, &task_info.operator_id, metadata.epoch)
                 .await
+                .unwrap_or_else(|e| {
+                    panic!(
+                        "failed to load metadata for operator {}, epoch {}: {:?}",
+                        task_info.operator_id, metadata.epoch, e
+                    )
+                })
                 .expect(&format!(
                     "missing metadata for operator {}, epoch {}",
                     task_info.operator_id, metadata.epoch
@@ -156,7 +247,9 @@ impl BackingStore for ParquetBackend {
             .map(|table| (table.name.clone().chars().next().unwrap(), table))
             .collect();
         for backend_data in operator_metadata.backend_data {
-            let Some(backend_data::BackendData::ParquetStore(parquet_data)) = backend_data.backend_data else {
+            let Some(backend_data::BackendData::ParquetStore(parquet_data)) =
+                backend_data.backend_data
+            else {
                 panic!("expect parquet data")
             };
             let table_descriptor = tables
@@ -189,12 +282,12 @@ impl BackingStore for ParquetBackend {
                 task_info.clone(),
                 control_tx,
                 tables.values().cloned().collect(),
-                StorageClient::new(),
+                StorageClient::for_job(&task_info.job_id),
                 writer_current_files,
             ),
             task_info: task_info.clone(),
             tables,
-            storage_client: StorageClient::new(),
+            storage_client: StorageClient::for_job(&task_info.job_id),
         }
     }
 
@@ -218,11 +311,12 @@ impl BackingStore for ParquetBackend {
                     operator.clone(),
                     old_min_epoch,
                     min_epoch,
+                    metadata.epoch,
                 )
             })
             .collect();
 
-        let storage_client = StorageClient::new();
+        let storage_client = StorageClient::for_job(&metadata.job_id);
 
         // wait for all of the futures to complete
         while let Some(result) = futures.next().await {
@@ -231,7 +325,7 @@ impl BackingStore for ParquetBackend {
             for epoch_to_remove in old_min_epoch..min_epoch {
                 let path = operator_metadata_path(&metadata.job_id, &operator_id, epoch_to_remove);
                 debug!("deleting {}", path);
-                storage_client.remove(path).await;
+                storage_client.remove(path).await?;
             }
             debug!(
                 message = "Finished compacting operator",
@@ -242,13 +336,225 @@ impl BackingStore for ParquetBackend {
         }
 
         for epoch_to_remove in old_min_epoch..min_epoch {
-            StorageClient::new()
+            StorageClient::for_job(&metadata.job_id)
                 .remove(checkpoint_metadata_path(&metadata.job_id, epoch_to_remove))
-                .await;
+                .await?;
         }
         metadata.min_epoch = min_epoch;
-        Self::complete_checkpoint(metadata).await;
-        Ok(())
+        Self::complete_checkpoint(metadata).await
+    }
+
+    async fn write_savepoint(metadata: CheckpointMetadata, savepoint_id: &str) -> Result<()> {
+        let storage_client = StorageClient::for_job(&metadata.job_id);
+
+        for operator_id in &metadata.operator_ids {
+            let mut operator_metadata =
+                Self::load_operator_metadata(&metadata.job_id, operator_id, metadata.epoch)
+                    .await?
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "missing metadata for operator {}, epoch {}",
+                            operator_id,
+                            metadata.epoch
+                        )
+                    })?;
+
+            for (index, backend_data) in operator_metadata.backend_data.iter_mut().enumerate() {
+                let Some(BackendData::ParquetStore(parquet_store)) = &mut backend_data.backend_data
+                else {
+                    unreachable!("expect parquet backends")
+                };
+                let bytes = storage_client
+                    .get_bytes(parquet_store.file.clone())
+                    .await?
+                    .ok_or_else(|| {
+                        anyhow!("unable to find file {} in checkpoint", parquet_store.file)
+                    })?;
+                let path =
+                    savepoint_data_path(savepoint_id, operator_id, &parquet_store.table, index);
+                storage_client.write(path.clone(), bytes).await?;
+                parquet_store.file = path;
+                parquet_store.compacted_files.clear();
+            }
+
+            storage_client
+                .write(
+                    savepoint_operator_metadata_path(savepoint_id, operator_id),
+                    operator_metadata.encode_to_vec(),
+                )
+                .await?;
+        }
+
+        // written last, so that only complete savepoints can be restored
+        storage_client
+            .write(
+                savepoint_metadata_path(savepoint_id),
+                metadata.encode_to_vec(),
+            )
+            .await
+    }
+
+    async fn restore_savepoint(savepoint_id: &str, job_id: &str) -> Result<CheckpointMetadata> {
+        let storage_client = StorageClient::for_job(job_id);
+        let data = storage_client
+            .get_bytes(savepoint_metadata_path(savepoint_id))
+            .await?
+            .ok_or_else(|| anyhow!("savepoint {} not found", savepoint_id))?;
+        let mut metadata = CheckpointMetadata::decode(&data[..])?;
+
+        // the data files stay where they are, so savepoints can only be restored into jobs that
+        // use the same storage; compaction only deletes files under the job's own checkpoint
+        // directory
+        for operator_id in &metadata.operator_ids {
+            let data = storage_client
+                .get_bytes(savepoint_operator_metadata_path(savepoint_id, operator_id))
+                .await?
+                .ok_or_else(|| {
+                    anyhow!(
+                        "savepoint {} is missing metadata for operator {}",
+                        savepoint_id,
+                        operator_id
+                    )
+                })?;
+            let mut operator_metadata = OperatorCheckpointMetadata::decode(&data[..])?;
+            operator_metadata.job_id = job_id.to_string();
+            Self::complete_operator_checkpoint(operator_metadata).await?;
+        }
+
+        metadata.job_id = job_id.to_string();
+        metadata.min_epoch = metadata.epoch;
+        Self::complete_checkpoint(metadata.clone()).await?;
+        Ok(metadata)
+    }
+
+    async fn migrate_checkpoint(
+        metadata: &CheckpointMetadata,
+        schemas: &OperatorSchemas,
+    ) -> Result<Option<CheckpointMetadata>> {
+        let storage_client = StorageClient::for_job(&metadata.job_id);
+
+        // the migrated checkpoint is written as an epoch that no run of the job has used, so that
+        // a failure part way through leaves the original checkpoint as the latest one
+        let new_epoch = storage_client
+            .list(format!("{}/checkpoints/", metadata.job_id))
+            .await?
+            .iter()
+            .filter_map(|key| checkpoint_dir_epoch(&metadata.job_id, key))
+            .fold(metadata.epoch, u32::max)
+            + 1;
+
+        let mut operator_metadatas = vec![];
+        let mut migrated = false;
+        for operator_id in &metadata.operator_ids {
+            let mut operator_metadata =
+                Self::load_operator_metadata(&metadata.job_id, operator_id, metadata.epoch)
+                    .await?
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "missing metadata for operator {}, epoch {}",
+                            operator_id,
+                            metadata.epoch
+                        )
+                    })?;
+            operator_metadata.epoch = new_epoch;
+
+            let mut migrations = HashMap::new();
+            if let Some(new_schemas) = schemas.get(operator_id) {
+                for table in &mut operator_metadata.tables {
+                    let (Some(old), Some(new)) = (&table.schema, new_schemas.get(&table.name))
+                    else {
+                        continue;
+                    };
+                    if old != new {
+                        migrations.insert(table.name.clone(), (old.clone(), new.clone()));
+                        table.schema = Some(new.clone());
+                    }
+                }
+            }
+            if migrations.is_empty() {
+                operator_metadatas.push(operator_metadata);
+                continue;
+            }
+            migrated = true;
+
+            info!(
+                message = "migrating checkpoint state",
+                job_id = metadata.job_id,
+                operator_id,
+                epoch = metadata.epoch,
+                new_epoch,
+                tables = format!("{:?}", migrations.keys().collect::<Vec<_>>())
+            );
+
+            for (index, backend_data) in operator_metadata.backend_data.iter_mut().enumerate() {
+                let Some(BackendData::ParquetStore(parquet_store)) = &mut backend_data.backend_data
+                else {
+                    unreachable!("expect parquet backends")
+                };
+                let Some((old, new)) = migrations.get(&parquet_store.table) else {
+                    continue;
+                };
+                let (Some(old), Some(new)) = (&old.value, &new.value) else {
+                    continue;
+                };
+
+                let bytes = storage_client
+                    .get_bytes(parquet_store.file.clone())
+                    .await?
+                    .ok_or_else(|| {
+                        anyhow!("unable to find file {} in checkpoint", parquet_store.file)
+                    })?;
+
+                let mut builder = RecordBatchBuilder::default();
+                for row in rows_from_parquet_bytes(bytes, &(0..=u64::MAX)) {
+                    let value = if row.tombstone {
+                        row.value
+                    } else {
+                        schema::migrate(old, new, &row.value).map_err(|e| {
+                            anyhow!(
+                                "failed to migrate state of table {} in {}: {}",
+                                parquet_store.table,
+                                parquet_store.file,
+                                e
+                            )
+                        })?
+                    };
+                    builder.insert(row.key_hash, row.timestamp, row.key, value, row.tombstone);
+                }
+                let Some((record_batch, _)) = builder.flush() else {
+                    continue;
+                };
+
+                let path = migrated_table_path(
+                    &metadata.job_id,
+                    operator_id,
+                    &parquet_store.table,
+                    new_epoch,
+                    index,
+                );
+                storage_client
+                    .write(path.clone(), record_batch_to_parquet(&record_batch))
+                    .await?;
+                parquet_store.file = path;
+                parquet_store.compacted_files.clear();
+            }
+
+            operator_metadatas.push(operator_metadata);
+        }
+
+        if !migrated {
+            return Ok(None);
+        }
+
+        for operator_metadata in operator_metadatas {
+            Self::complete_operator_checkpoint(operator_metadata).await?;
+        }
+
+        // writing the overall metadata is what makes the new epoch restorable, so it's done last
+        let mut new_metadata = metadata.clone();
+        new_metadata.epoch = new_epoch;
+        Self::complete_checkpoint(new_metadata.clone()).await?;
+        Ok(Some(new_metadata))
     }
 
     async fn checkpoint(
@@ -258,38 +564,115 @@ impl BackingStore for ParquetBackend {
     ) -> u32 {
         assert_eq!(barrier.epoch, self.epoch);
         self.writer
-            .checkpoint(self.epoch, barrier.timestamp, watermark, barrier.then_stop)
+            .checkpoint(
+                self.epoch,
+                barrier.timestamp,
+                watermark,
+                barrier.then_stop,
+                false,
+            )
+            .await;
+        self.epoch += 1;
+        self.min_epoch = barrier.min_epoch;
+        self.epoch - 1
+    }
+
+    async fn checkpoint_unaligned(
+        &mut self,
+        barrier: CheckpointBarrier,
+        watermark: Option<SystemTime>,
+    ) -> u32 {
+        assert_eq!(barrier.epoch, self.epoch);
+        assert!(
+            !barrier.then_stop,
+            "stopping checkpoints must be aligned, as there's no later input to wait for"
+        );
+        self.writer
+            .checkpoint(self.epoch, barrier.timestamp, watermark, false, true)
             .await;
         self.epoch += 1;
         self.min_epoch = barrier.min_epoch;
         self.epoch - 1
     }
 
+    async fn write_in_flight(&mut self, epoch: u32, records: Vec<InFlightRecord>) {
+        self.writer.write_in_flight(epoch, records).await;
+    }
+
+    async fn get_in_flight(&self) -> Vec<InFlightRecord> {
+        self.read_rows(IN_FLIGHT_TABLE, &self.task_info.key_range)
+            .await
+            .into_iter()
+            .map(|row| InFlightRecord {
+                input: bincode::decode_from_slice::<u32, _>(&row.key, BINCODE_CONFIG)
+                    .unwrap()
+                    .0 as usize,
+                key_hash: row.key_hash,
+                timestamp: row.timestamp,
+                data: row.value,
+            })
+            .collect()
+    }
+
     async fn get_data_triples<K: Key, V: Data>(&self, table: char) -> Vec<(SystemTime, K, V)> {
         let mut result = vec![];
         match self.tables.get(&table).unwrap().table_type() {
-            TableType::Global => todo!(),
-            TableType::TimeKeyMap | TableType::KeyTimeMultiMap => {
-                let Some(files) = self.current_files.get(&table) else {
-                    return vec![];
-                };
-                for file in files.values().flatten() {
-                    let bytes = self
-                        .storage_client
-                        .get_bytes(file.file.clone())
-                        .await
-                        .unwrap_or_else(|| {
-                            panic!("unable to find file {} in checkpoint", file.file)
-                        });
-                    result.append(
-                        &mut self.triples_from_parquet_bytes(bytes, &self.task_info.key_range),
-                    );
-                }
+            // global tables are read in full by every subtask
+            TableType::Global => {
+                let rows = self.read_rows(table, &(0..=u64::MAX)).await;
+                result = decode_rows(apply_tombstones(rows, TableType::Global));
+            }
+            table_type @ (TableType::TimeKeyMap | TableType::KeyTimeMultiMap) => {
+                let rows = self.read_rows(table, &self.task_info.key_range).await;
+                result = decode_rows(apply_tombstones(rows, table_type));
             }
         }
         result
     }
 
+    fn restore_parts(&self, table: char) -> usize {
+        self.current_files
+            .get(&table)
+            .map_or(0, |files| files.values().map(|f| f.len()).sum())
+    }
+
+    async fn get_data_triples_part<K: Key, V: Data>(
+        &self,
+        table: char,
+        part: usize,
+    ) -> Vec<(SystemTime, K, Option<V>)> {
+        let Some(file) = self
+            .current_files
+            .get(&table)
+            .and_then(|files| files.values().flatten().nth(part))
+        else {
+            return vec![];
+        };
+        let range = match self.tables.get(&table).unwrap().table_type() {
+            // global tables are read in full by every subtask
+            TableType::Global => 0..=u64::MAX,
+            TableType::TimeKeyMap | TableType::KeyTimeMultiMap => self.task_info.key_range.clone(),
+        };
+
+        self.read_file(file, &range)
+            .await
+            .into_iter()
+            .map(|row| {
+                let key: K = bincode::decode_from_slice(&row.key, BINCODE_CONFIG)
+                    .unwrap_or_else(|e| panic!("failed to decode key from checkpoint: {:?}", e))
+                    .0;
+                let value = (!row.tombstone).then(|| {
+                    bincode::decode_from_slice(&row.value, BINCODE_CONFIG)
+                        .unwrap_or_else(|e| {
+                            panic!("failed to decode value from checkpoint: {:?}", e)
+                        })
+                        .0
+                });
+                (row.timestamp, key, value)
+            })
+            .collect()
+    }
+
     async fn write_data_triple<K: Key, V: Data>(
         &mut self,
         table: char,
@@ -306,7 +689,21 @@ impl BackingStore for ParquetBackend {
             )
         };
         self.writer
-            .write(table, key_hash, timestamp, key_bytes, value_bytes)
+            .write(table, key_hash, timestamp, key_bytes, value_bytes, false)
+            .await;
+    }
+
+    async fn delete_data_triple<K: Key>(
+        &mut self,
+        table: char,
+        _table_type: TableType,
+        timestamp: SystemTime,
+        key: &mut K,
+    ) {
+        let key_hash = hash_key(key);
+        let key_bytes = bincode::encode_to_vec(&*key, config::standard()).unwrap();
+        self.writer
+            .write(table, key_hash, timestamp, key_bytes, vec![], true)
             .await;
     }
 
@@ -315,121 +712,563 @@ impl BackingStore for ParquetBackend {
             .await
     }
 
+    async fn write_encoded_row(&mut self, table: char, row: EncodedRow) {
+        self.writer
+            .write(
+                table,
+                row.key_hash,
+                row.timestamp,
+                row.key,
+                row.value,
+                false,
+            )
+            .await;
+    }
+
     async fn get_key_values<K: Key, V: Data>(&self, table: char) -> Vec<(K, V)> {
-        let Some(files) = self.current_files.get(&table) else {
-            return vec![];
-        };
+        let rows = self.read_rows(table, &(0..=u64::MAX)).await;
         let mut state_map = HashMap::new();
-        for file in files.values().flatten() {
-            let bytes = self
-                .storage_client
-                .get_bytes(file.file.clone())
-                .await
-                .unwrap_or_else(|| panic!("unable to find file {} in checkpoint", file.file));
-            for (_timestamp, key, value) in self.triples_from_parquet_bytes(bytes, &(0..=u64::MAX))
-            {
-                state_map.insert(key, value);
-            }
+        for (_timestamp, key, value) in decode_rows(apply_tombstones(rows, TableType::Global)) {
+            state_map.insert(key, value);
         }
         state_map.into_iter().collect()
     }
 }
 
+/// A row of a checkpointed table, decoded for inspection outside of a running job
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateRow {
+    pub key_hash: u64,
+    pub timestamp: SystemTime,
+    pub key: serde_json::Value,
+    pub value: serde_json::Value,
+}
+
+/// Selects the rows of a checkpointed table to read with [`ParquetBackend::read_table`]
+#[derive(Debug, Clone)]
+pub struct TableFilter {
+    pub key_range: RangeInclusive<u64>,
+    /// Rows from `start_time` (inclusive) to `end_time` (exclusive); ignored for global tables,
+    /// whose rows aren't timestamped
+    pub start_time: Option<SystemTime>,
+    pub end_time: Option<SystemTime>,
+}
+
+impl TableFilter {
+    fn for_key_range(key_range: &RangeInclusive<u64>) -> Self {
+        Self {
+            key_range: key_range.clone(),
+            start_time: None,
+            end_time: None,
+        }
+    }
+
+    fn contains(&self, key_hash: u64, timestamp: SystemTime) -> bool {
+        self.key_range.contains(&key_hash)
+            && self.start_time.map(|t| timestamp >= t).unwrap_or(true)
+            && self.end_time.map(|t| timestamp < t).unwrap_or(true)
+    }
+
+    // whether the row group may contain matching rows, going by its column statistics
+    fn overlaps(&self, row_group: &RowGroupMetaData) -> bool {
+        let min_max = |column: usize| match row_group.column(column).statistics() {
+            Some(Statistics::Int64(s)) if s.has_min_max_set() => Some((*s.min(), *s.max())),
+            _ => None,
+        };
+
+        // key hashes are unsigned, which the writer takes into account when computing statistics
+        if let Some((min, max)) = min_max(0) {
+            if (max as u64) < *self.key_range.start() || *self.key_range.end() < min as u64 {
+                return false;
+            }
+        }
+
+        match min_max(1) {
+            Some((min, max)) => {
+                self.start_time
+                    .map(|t| from_micros(max as u64) >= t)
+                    .unwrap_or(true)
+                    && self
+                        .end_time
+                        .map(|t| from_micros(min as u64) < t)
+                        .unwrap_or(true)
+            }
+            None => true,
+        }
+    }
+}
+
 impl ParquetBackend {
+    /// Reads the contents of a table as of an operator's checkpoint, sorted by key hash and
+    /// timestamp, and returns at most `limit` rows along with whether there were more. Keys and
+    /// values are decoded with the table's stored schema; tables without one are returned as
+    /// hex-encoded bytes.
+    ///
+    /// Files are read in order of their key ranges, and reading stops once the limit has been
+    /// reached; row groups without matching rows are skipped.
+    pub async fn read_table(
+        metadata: &OperatorCheckpointMetadata,
+        table: &str,
+        filter: &TableFilter,
+        limit: usize,
+    ) -> Result<(Vec<StateRow>, bool)> {
+        let descriptor = metadata
+            .tables
+            .iter()
+            .find(|t| t.name == table)
+            .ok_or_else(|| anyhow!("operator {} has no table '{}'", metadata.operator_id, table))?;
+
+        let filter = match descriptor.table_type() {
+            TableType::Global => TableFilter::for_key_range(&filter.key_range),
+            _ => filter.clone(),
+        };
+
+        let files: Vec<ParquetStoreData> = parquet_files(metadata)
+            .filter(|f| {
+                f.table == table
+                    && f.min_routing_key <= *filter.key_range.end()
+                    && *filter.key_range.start() <= f.max_routing_key
+            })
+            .cloned()
+            .collect();
+
+        let storage_client = StorageClient::for_job(&metadata.job_id);
+        let mut rows = vec![];
+        let mut truncated = false;
+        // the groups cover disjoint key ranges in ascending order, and tombstones only apply
+        // within a group, so each group's rows are final once all of its files have been read
+        for files in group_by_key_range(files) {
+            let mut group_rows = vec![];
+            for file in files {
+                let bytes = storage_client
+                    .get_bytes(file.file.clone())
+                    .await?
+                    .ok_or_else(|| anyhow!("unable to find file {} in checkpoint", file.file))?;
+                group_rows.append(&mut read_parquet_rows(bytes, &filter));
+            }
+
+            let mut group_rows = apply_tombstones(group_rows, descriptor.table_type());
+            group_rows.sort_by_key(|row| (row.key_hash, row.timestamp));
+            rows.append(&mut group_rows);
+
+            if rows.len() > limit {
+                rows.truncate(limit);
+                truncated = true;
+                break;
+            }
+        }
+
+        let unknown = StateType {
+            name: "bytes".to_string(),
+            params: vec![],
+            fields: vec![],
+        };
+        let (key_type, value_type) = match &descriptor.schema {
+            Some(schema) => (
+                schema.key.as_ref().unwrap_or(&unknown),
+                schema.value.as_ref().unwrap_or(&unknown),
+            ),
+            None => (&unknown, &unknown),
+        };
+
+        let rows = rows
+            .into_iter()
+            .map(|row| {
+                Ok(StateRow {
+                    key_hash: row.key_hash,
+                    timestamp: row.timestamp,
+                    key: schema::to_json(key_type, &row.key)?,
+                    value: schema::to_json(value_type, &row.value)?,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok((rows, truncated))
+    }
+
     async fn compact_operator(
         job_id: String,
         operator: String,
         old_min_epoch: u32,
         new_min_epoch: u32,
+        current_epoch: u32,
     ) -> anyhow::Result<String> {
-        let paths_to_keep: HashSet<String> =
-            Self::load_operator_metadata(&job_id, &operator, new_min_epoch)
-                .await
-                .expect("expect new_min_epoch metadata to still be present")
-                .backend_data
-                .iter()
-                .map(|backend_data| {
-                    let Some(BackendData::ParquetStore(parquet_store)) =
-                  &backend_data.backend_data else {unreachable!("expect parquet backends")};
-                    parquet_store.file.clone()
-                })
-                .collect();
+        let storage_client = StorageClient::for_job(&job_id);
+        let metadata = Self::load_operator_metadata(&job_id, &operator, new_min_epoch)
+            .await?
+            .expect("expect new_min_epoch metadata to still be present");
+
+        // running subtasks continue to reference the files that were merged until they restart
+        let mut paths_to_keep: HashSet<String> =
+            parquet_files(&metadata).map(|f| f.file.clone()).collect();
+
+        let metadata = Self::merge_files(&storage_client, metadata).await?;
+        paths_to_keep.extend(parquet_files(&metadata).map(|f| f.file.clone()));
+        Self::complete_operator_checkpoint(metadata).await?;
+
+        for epoch in new_min_epoch + 1..=current_epoch {
+            if let Some(metadata) = Self::load_operator_metadata(&job_id, &operator, epoch).await? {
+                paths_to_keep.extend(parquet_files(&metadata).map(|f| f.file.clone()));
+            }
+        }
 
         let mut deleted_paths = HashSet::new();
-        let storage_client = StorageClient::new();
+        // files outside of the job's checkpoints, like those of a savepoint it was started from,
+        // are never deleted
+        let job_prefix = format!("{}/checkpoints/", job_id);
 
         for epoch_to_remove in old_min_epoch..new_min_epoch {
-            let Some(metadata) = Self::load_operator_metadata(&job_id, &operator, epoch_to_remove)
-            .await else {
+            let Some(metadata) =
+                Self::load_operator_metadata(&job_id, &operator, epoch_to_remove).await?
+            else {
                 continue;
             };
-            for backend_data in metadata.backend_data {
-                let Some(BackendData::ParquetStore(parquet_store)) =  &backend_data.backend_data
-                else {unreachable!("expect parquet backends")};
+            for parquet_store in parquet_files(&metadata) {
                 let file = parquet_store.file.clone();
-                if !paths_to_keep.contains(&file) {
-                    if !deleted_paths.contains(&file) {
-                        deleted_paths.insert(file.clone());
-                        storage_client.remove(file).await;
-                    }
+                if !paths_to_keep.contains(&file)
+                    && file.starts_with(&job_prefix)
+                    && !deleted_paths.contains(&file)
+                {
+                    deleted_paths.insert(file.clone());
+                    storage_client.remove(file).await?;
                 }
             }
         }
         Ok(operator)
     }
-    fn triples_from_parquet_bytes<K: Key, V: Data>(
+
+    /// Merges the files of each table whose key ranges overlap into a single file, applying
+    /// tombstones and dropping data that is behind the watermark, so that restoring from the
+    /// checkpoint reads at most one file per table and key range.
+    async fn merge_files(
+        storage_client: &StorageClient,
+        mut metadata: OperatorCheckpointMetadata,
+    ) -> Result<OperatorCheckpointMetadata> {
+        let tables: HashMap<String, TableDescriptor> = metadata
+            .tables
+            .iter()
+            .map(|table| (table.name.clone(), table.clone()))
+            .collect();
+
+        let mut files_by_table: BTreeMap<String, Vec<ParquetStoreData>> = BTreeMap::new();
+        for file in parquet_files(&metadata) {
+            files_by_table
+                .entry(file.table.clone())
+                .or_default()
+                .push(file.clone());
+        }
+
+        let mut backend_data = vec![];
+        for (table, files) in files_by_table {
+            let descriptor = tables
+                .get(&table)
+                .ok_or_else(|| anyhow!("no descriptor for table {}", table))?;
+
+            // global tables are rewritten in full on every checkpoint
+            if descriptor.table_type() == TableType::Global {
+                backend_data.extend(files);
+                continue;
+            }
+
+            let min_valid_micros = match (descriptor.delete_behavior(), metadata.min_watermark) {
+                (TableDeleteBehavior::NoReadsBeforeWatermark, Some(watermark)) => {
+                    watermark.saturating_sub(descriptor.retention_micros)
+                }
+                _ => 0,
+            };
+
+            for files in group_by_key_range(files) {
+                if files.len() == 1 {
+                    backend_data.extend(files);
+                    continue;
+                }
+
+                let mut rows = vec![];
+                for file in &files {
+                    let bytes = storage_client
+                        .get_bytes(file.file.clone())
+                        .await?
+                        .ok_or_else(|| {
+                            anyhow!("unable to find file {} in checkpoint", file.file)
+                        })?;
+                    rows.append(&mut rows_from_parquet_bytes(bytes, &(0..=u64::MAX)));
+                }
+                let mut rows = apply_tombstones(rows, descriptor.table_type());
+                rows.retain(|row| to_micros(row.timestamp) >= min_valid_micros);
+                rows.sort_by_key(|row| row.key_hash);
+
+                let mut builder = RecordBatchBuilder::default();
+                for row in rows {
+                    builder.insert(row.key_hash, row.timestamp, row.key, row.value, false);
+                }
+                let Some((record_batch, stats)) = builder.flush() else {
+                    // nothing left to restore; the files will be dropped once they expire
+                    backend_data.extend(files);
+                    continue;
+                };
+
+                let path = compacted_table_path(
+                    &metadata.job_id,
+                    &metadata.operator_id,
+                    &table,
+                    metadata.epoch,
+                    stats.min_routing_key,
+                );
+                storage_client
+                    .write(path.clone(), record_batch_to_parquet(&record_batch))
+                    .await?;
+                debug!(
+                    message = "merged checkpoint files",
+                    path,
+                    merged = files.len()
+                );
+
+                backend_data.push(ParquetStoreData {
+                    epoch: metadata.epoch,
+                    file: path,
+                    table: table.clone(),
+                    min_routing_key: stats.min_routing_key,
+                    max_routing_key: stats.max_routing_key,
+                    max_timestamp_micros: to_micros(stats.max_timestamp),
+                    compacted_files: files.into_iter().map(|f| f.file).collect(),
+                });
+            }
+        }
+
+        // files are read back in this order, so later epochs must come last
+        backend_data.sort_by(|a, b| (a.epoch, &a.file).cmp(&(b.epoch, &b.file)));
+        metadata.backend_data = backend_data
+            .into_iter()
+            .map(|file| arroyo_rpc::grpc::BackendData {
+                backend_data: Some(BackendData::ParquetStore(file)),
+            })
+            .collect();
+
+        Ok(metadata)
+    }
+
+    // A checkpoint can be restored if the metadata of each of its operators was written and all of
+    // the files that they reference still exist. `keys` holds the objects under the job's
+    // checkpoint directory; files outside of it (like those of a restored savepoint) are checked
+    // individually.
+    async fn is_restorable(
+        storage_client: &StorageClient,
+        metadata: &CheckpointMetadata,
+        keys: &HashSet<String>,
+    ) -> Result<bool> {
+        for operator_id in &metadata.operator_ids {
+            let Some(operator_metadata) =
+                Self::load_operator_metadata(&metadata.job_id, operator_id, metadata.epoch).await?
+            else {
+                warn!(
+                    "checkpoint {} of job {} is missing metadata for operator {}",
+                    metadata.epoch, metadata.job_id, operator_id
+                );
+                return Ok(false);
+            };
+
+            for file in parquet_files(&operator_metadata) {
+                if !keys.contains(&file.file)
+                    && !storage_client
+                        .list(file.file.clone())
+                        .await?
+                        .contains(&file.file)
+                {
+                    warn!(
+                        "checkpoint {} of job {} is missing file {}",
+                        metadata.epoch, metadata.job_id, file.file
+                    );
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    async fn read_rows(&self, table: char, range: &RangeInclusive<u64>) -> Vec<ParquetRow> {
+        let Some(files) = self.current_files.get(&table) else {
+            return vec![];
+        };
+        let mut rows = vec![];
+        for file in files.values().flatten() {
+            rows.append(&mut self.read_file(file, range).await);
+        }
+        rows
+    }
+
+    async fn read_file(
         &self,
-        bytes: Vec<u8>,
+        file: &ParquetStoreData,
         range: &RangeInclusive<u64>,
-    ) -> Vec<(SystemTime, K, V)> {
-        let reader = ParquetRecordBatchReaderBuilder::try_new(Bytes::copy_from_slice(&bytes))
-            .unwrap()
-            .build()
+    ) -> Vec<ParquetRow> {
+        let bytes = self
+            .storage_client
+            .get_bytes(file.file.clone())
+            .await
+            .unwrap_or_else(|e| panic!("failed to read {}: {:?}", file.file, e))
+            .unwrap_or_else(|| panic!("unable to find file {} in checkpoint", file.file));
+        rows_from_parquet_bytes(bytes, range)
+    }
+}
+
+fn parquet_files(metadata: &OperatorCheckpointMetadata) -> impl Iterator<Item = &ParquetStoreData> {
+    metadata.backend_data.iter().map(|backend_data| {
+        let Some(BackendData::ParquetStore(parquet_store)) = &backend_data.backend_data else {
+            unreachable!("expect parquet backends")
+        };
+        parquet_store
+    })
+}
+
+// groups files with overlapping routing key ranges, keeping the original order within each group
+fn group_by_key_range(files: Vec<ParquetStoreData>) -> Vec<Vec<ParquetStoreData>> {
+    let mut bounds: Vec<(u64, u64)> = files
+        .iter()
+        .map(|file| (file.min_routing_key, file.max_routing_key))
+        .collect();
+    bounds.sort();
+
+    let mut ranges: Vec<(u64, u64)> = vec![];
+    for (min, max) in bounds {
+        match ranges.last_mut() {
+            Some(last) if min <= last.1 => last.1 = last.1.max(max),
+            _ => ranges.push((min, max)),
+        }
+    }
+
+    let mut groups = vec![vec![]; ranges.len()];
+    for file in files {
+        let index = ranges
+            .iter()
+            .position(|(min, max)| (*min..=*max).contains(&file.min_routing_key))
             .unwrap();
+        groups[index].push(file);
+    }
+    groups
+}
 
-        let mut result = vec![];
+struct ParquetRow {
+    key_hash: u64,
+    timestamp: SystemTime,
+    key: Vec<u8>,
+    value: Vec<u8>,
+    tombstone: bool,
+}
 
-        let batches: Vec<RecordBatch> = reader.collect::<Result<Vec<_>, _>>().unwrap();
-        for batch in batches {
-            let num_rows = batch.num_rows();
-            let key_hash_array = batch
-                .column(0)
-                .as_any()
-                .downcast_ref::<arrow_array::UInt64Array>()
-                .unwrap();
-            let time_array = batch
-                .column(1)
-                .as_any()
-                .downcast_ref::<arrow_array::TimestampMicrosecondArray>()
-                .expect("Column 1 is not a TimestampMicrosecondArray");
-            let key_array = batch
-                .column(2)
-                .as_any()
-                .downcast_ref::<arrow_array::BinaryArray>()
-                .unwrap();
-            let value_array = batch
-                .column(3)
+fn rows_from_parquet_bytes(bytes: Vec<u8>, range: &RangeInclusive<u64>) -> Vec<ParquetRow> {
+    read_parquet_rows(bytes, &TableFilter::for_key_range(range))
+}
+
+// reads the rows matching the filter one row group at a time, skipping row groups that can't
+// contain any
+fn read_parquet_rows(bytes: Vec<u8>, filter: &TableFilter) -> Vec<ParquetRow> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(bytes)).unwrap();
+    let row_groups = builder
+        .metadata()
+        .row_groups()
+        .iter()
+        .enumerate()
+        .filter(|(_, row_group)| filter.overlaps(row_group))
+        .map(|(i, _)| i)
+        .collect();
+    let reader = builder.with_row_groups(row_groups).build().unwrap();
+
+    let mut result = vec![];
+
+    for batch in reader {
+        let batch: RecordBatch = batch.unwrap();
+        let num_rows = batch.num_rows();
+        let key_hash_array = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<arrow_array::UInt64Array>()
+            .unwrap();
+        let time_array = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<arrow_array::TimestampMicrosecondArray>()
+            .expect("Column 1 is not a TimestampMicrosecondArray");
+        let key_array = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<arrow_array::BinaryArray>()
+            .unwrap();
+        let value_array = batch
+            .column(3)
+            .as_any()
+            .downcast_ref::<arrow_array::BinaryArray>()
+            .unwrap();
+        // files written before tombstones were introduced only have four columns
+        let tombstone_array = (batch.num_columns() > 4).then(|| {
+            batch
+                .column(4)
                 .as_any()
-                .downcast_ref::<arrow_array::BinaryArray>()
-                .unwrap();
-            for index in 0..num_rows {
-                if !range.contains(&key_hash_array.value(index)) {
-                    continue;
-                }
+                .downcast_ref::<arrow_array::BooleanArray>()
+                .expect("Column 4 is not a BooleanArray")
+        });
+        for index in 0..num_rows {
+            let timestamp = from_micros(time_array.value(index) as u64);
+            if !filter.contains(key_hash_array.value(index), timestamp) {
+                continue;
+            }
 
-                let timestamp = from_micros(time_array.value(index) as u64);
+            result.push(ParquetRow {
+                key_hash: key_hash_array.value(index),
+                timestamp,
+                key: key_array.value(index).to_vec(),
+                value: value_array.value(index).to_vec(),
+                tombstone: tombstone_array.map(|a| a.value(index)).unwrap_or(false),
+            });
+        }
+    }
+    result
+}
 
-                let key: K = bincode::decode_from_slice(key_array.value(index), BINCODE_CONFIG)
-                    .unwrap()
-                    .0;
-                let value: V = bincode::decode_from_slice(value_array.value(index), BINCODE_CONFIG)
-                    .unwrap()
-                    .0;
-                result.push((timestamp, key, value));
+/// Replays rows in the order they were written. Key-time multimaps keep every value, while other
+/// tables keep only the latest value for each key and timestamp; tombstones remove everything
+/// written before them for their key and timestamp and are not returned.
+fn apply_tombstones(rows: Vec<ParquetRow>, table_type: TableType) -> Vec<ParquetRow> {
+    let mut result: Vec<Option<ParquetRow>> = Vec::with_capacity(rows.len());
+    let mut positions: HashMap<(SystemTime, Vec<u8>), Vec<usize>> = HashMap::new();
+    for row in rows {
+        let entry = positions
+            .entry((row.timestamp, row.key.clone()))
+            .or_default();
+        if row.tombstone || table_type != TableType::KeyTimeMultiMap {
+            for index in entry.drain(..) {
+                result[index] = None;
             }
         }
-        result
+        if !row.tombstone {
+            entry.push(result.len());
+            result.push(Some(row));
+        }
     }
+    result.into_iter().flatten().collect()
+}
+
+fn decode_rows<K: Key, V: Data>(rows: Vec<ParquetRow>) -> Vec<(SystemTime, K, V)> {
+    rows.into_iter()
+        .map(|row| {
+            let key: K = bincode::decode_from_slice(&row.key, BINCODE_CONFIG)
+                .unwrap_or_else(|e| panic!("failed to decode key from checkpoint: {:?}", e))
+                .0;
+            let value: V = bincode::decode_from_slice(&row.value, BINCODE_CONFIG)
+                .unwrap_or_else(|e| panic!("failed to decode value from checkpoint: {:?}", e))
+                .0;
+            (row.timestamp, key, value)
+        })
+        .collect()
+}
+
+fn record_batch_to_parquet(record_batch: &RecordBatch) -> Vec<u8> {
+    let props = WriterProperties::builder()
+        .set_compression(parquet::basic::Compression::SNAPPY)
+        .build();
+    let cursor = Vec::new();
+    let mut writer = ArrowWriter::try_new(cursor, record_batch.schema(), Some(props)).unwrap();
+    writer.write(record_batch).expect("Writing batch");
+    writer.flush().unwrap();
+    writer.into_inner().unwrap()
 }
 
 struct ParquetWriter {
@@ -460,6 +1299,7 @@ impl ParquetWriter {
                 .collect(),
             builders: HashMap::new(),
             current_files,
+            pending: None,
         })
         .start();
 
@@ -476,6 +1316,7 @@ impl ParquetWriter {
         timestamp: SystemTime,
         key: Vec<u8>,
         data: Vec<u8>,
+        tombstone: bool,
     ) {
         self.sender
             .send(ParquetQueueItem::Write(ParquetWrite {
@@ -484,6 +1325,7 @@ impl ParquetWriter {
                 timestamp,
                 key,
                 data,
+                tombstone,
             }))
             .await
             .unwrap();
@@ -495,6 +1337,7 @@ impl ParquetWriter {
         time: SystemTime,
         watermark: Option<SystemTime>,
         then_stop: bool,
+        await_in_flight: bool,
     ) {
         self.sender
             .send(ParquetQueueItem::Checkpoint(ParquetCheckpoint {
@@ -502,6 +1345,7 @@ impl ParquetWriter {
                 time,
                 watermark,
                 then_stop,
+                await_in_flight,
             }))
             .await
             .unwrap();
@@ -512,12 +1356,23 @@ impl ParquetWriter {
             }
         }
     }
+
+    async fn write_in_flight(&mut self, epoch: u32, records: Vec<InFlightRecord>) {
+        self.sender
+            .send(ParquetQueueItem::InFlight(ParquetInFlight {
+                epoch,
+                records,
+            }))
+            .await
+            .unwrap();
+    }
 }
 
 #[derive(Debug)]
 enum ParquetQueueItem {
     Write(ParquetWrite),
     Checkpoint(ParquetCheckpoint),
+    InFlight(ParquetInFlight),
 }
 
 #[derive(Debug)]
@@ -527,6 +1382,7 @@ struct ParquetWrite {
     timestamp: SystemTime,
     key: Vec<u8>,
     data: Vec<u8>,
+    tombstone: bool,
 }
 
 #[derive(Debug)]
@@ -535,13 +1391,23 @@ struct ParquetCheckpoint {
     time: SystemTime,
     watermark: Option<SystemTime>,
     then_stop: bool,
+    // whether the checkpoint is held back until its in-flight records are written
+    await_in_flight: bool,
+}
+
+#[derive(Debug)]
+struct ParquetInFlight {
+    epoch: u32,
+    records: Vec<InFlightRecord>,
 }
+
 struct RecordBatchBuilder {
     key_hash_builder: arrow_array::builder::PrimitiveBuilder<arrow_array::types::UInt64Type>,
     start_time_array:
         arrow_array::builder::PrimitiveBuilder<arrow_array::types::TimestampMicrosecondType>,
     key_bytes: arrow_array::builder::BinaryBuilder,
     data_bytes: arrow_array::builder::BinaryBuilder,
+    tombstones: arrow_array::builder::BooleanBuilder,
     parquet_stats: ParquetStats,
 }
 
@@ -562,7 +1428,14 @@ impl Default for ParquetStats {
 }
 
 impl RecordBatchBuilder {
-    fn insert(&mut self, key_hash: u64, timestamp: SystemTime, key: Vec<u8>, data: Vec<u8>) {
+    fn insert(
+        &mut self,
+        key_hash: u64,
+        timestamp: SystemTime,
+        key: Vec<u8>,
+        data: Vec<u8>,
+        tombstone: bool,
+    ) {
         self.parquet_stats.min_routing_key = self.parquet_stats.min_routing_key.min(key_hash);
         self.parquet_stats.max_routing_key = self.parquet_stats.max_routing_key.max(key_hash);
 
@@ -571,6 +1444,7 @@ impl RecordBatchBuilder {
             .append_value(to_micros(timestamp) as i64);
         self.key_bytes.append_value(key);
         self.data_bytes.append_value(data);
+        self.tombstones.append_value(tombstone);
         self.parquet_stats.max_timestamp = self.parquet_stats.max_timestamp.max(timestamp);
     }
 
@@ -585,6 +1459,7 @@ impl RecordBatchBuilder {
         > = self.start_time_array.finish();
         let key_array: arrow_array::BinaryArray = self.key_bytes.finish();
         let data_array: arrow_array::BinaryArray = self.data_bytes.finish();
+        let tombstone_array: arrow_array::BooleanArray = self.tombstones.finish();
         Some((
             arrow_array::RecordBatch::try_new(
                 self.schema(),
@@ -593,6 +1468,7 @@ impl RecordBatchBuilder {
                     std::sync::Arc::new(start_time_array),
                     std::sync::Arc::new(key_array),
                     std::sync::Arc::new(data_array),
+                    std::sync::Arc::new(tombstone_array),
                 ],
             )
             .unwrap(),
@@ -614,6 +1490,11 @@ impl RecordBatchBuilder {
                 arrow::datatypes::DataType::Binary,
                 false,
             ),
+            arrow::datatypes::Field::new(
+                "is_tombstone",
+                arrow::datatypes::DataType::Boolean,
+                false,
+            ),
         ]))
     }
 }
@@ -628,6 +1509,7 @@ impl Default for RecordBatchBuilder {
             >::with_capacity(1024),
             key_bytes: arrow_array::builder::BinaryBuilder::default(),
             data_bytes: arrow_array::builder::BinaryBuilder::default(),
+            tombstones: arrow_array::builder::BooleanBuilder::with_capacity(1024),
             parquet_stats: ParquetStats::default(),
         }
     }
@@ -642,157 +1524,9 @@ struct ParquetFlusher {
     table_descriptors: HashMap<char, TableDescriptor>,
     builders: HashMap<char, RecordBatchBuilder>,
     current_files: HashMap<char, BTreeMap<u32, Vec<ParquetStoreData>>>,
-}
-
-#[derive(Clone)]
-pub enum StorageClient {
-    LocalDirectory(String),
-    S3 {
-        client: S3Client,
-        region: String,
-        bucket: String,
-    },
-}
-
-// TODO: Better way to configure this.
-impl StorageClient {
-    pub fn new() -> Self {
-        let bucket = std::env::var(S3_BUCKET_ENV).ok();
-        let region = std::env::var(S3_REGION_ENV).ok();
-        match (bucket, region) {
-            (Some(bucket), Some(region)) => match bucket.as_str() {
-                "local" => Self::LocalDirectory("/tmp/arroyo-data".to_owned()),
-                bucket => {
-                    let client = S3Client::new(Region::from_str(&region).unwrap());
-                    Self::S3 {
-                        client,
-                        region,
-                        bucket: bucket.to_owned(),
-                    }
-                }
-            },
-            _ => Self::LocalDirectory("/tmp/arroyo-data".to_owned()),
-        }
-    }
-    pub fn get_storage_environment_variables() -> HashMap<String, String> {
-        let (Ok(region), Ok(bucket)) = (std::env::var(S3_REGION_ENV), std::env::var(S3_BUCKET_ENV)) else {
-            return HashMap::new();
-        };
-        match bucket.as_str() {
-            "local" => HashMap::new(),
-            bucket => vec![
-                (S3_BUCKET_ENV.to_string(), bucket.to_string()),
-                (S3_REGION_ENV.to_string(), region.to_string()),
-            ]
-            .into_iter()
-            .collect(),
-        }
-    }
-
-    async fn write(&self, key: String, parquet_bytes: Vec<u8>) {
-        match self {
-            StorageClient::LocalDirectory(directory) => {
-                let file_path = Path::new(directory).join(Path::new(&key));
-
-                if let Some(parent) = file_path.parent() {
-                    tokio::fs::create_dir_all(parent).await.unwrap();
-                }
-                let mut file = File::create(file_path).await.unwrap();
-                file.write_all(&parquet_bytes).await.unwrap();
-            }
-            StorageClient::S3 {
-                client,
-                region: _,
-                bucket,
-            } => {
-                let request = PutObjectRequest {
-                    bucket: bucket.into(),
-                    key,
-                    body: Some(parquet_bytes.into()),
-                    ..Default::default()
-                };
-                client.put_object(request).await.unwrap();
-            }
-        }
-    }
-
-    async fn remove(&self, key: String) {
-        match self {
-            StorageClient::LocalDirectory(directory) => {
-                let file_path = Path::new(directory).join(Path::new(&key));
-                if let Err(e) = remove_file(&file_path).await {
-                    if e.kind() != ErrorKind::NotFound {
-                        panic!("Error deleting file: {:?}", e);
-                    }
-                }
-            }
-            StorageClient::S3 {
-                client,
-                region: _,
-                bucket,
-            } => {
-                let delete_object_request = DeleteObjectRequest {
-                    bucket: bucket.into(),
-                    key: key.clone(),
-                    ..Default::default()
-                };
-                if let Err(error) = client.delete_object(delete_object_request).await {
-                    warn!("failed to delete object, trying again. error: {}", error);
-                    let delete_object_request = DeleteObjectRequest {
-                        bucket: bucket.into(),
-                        key,
-                        ..Default::default()
-                    };
-                    client
-                        .delete_object(delete_object_request)
-                        .await
-                        .expect("second try failed");
-                }
-            }
-        }
-    }
-
-    async fn get_bytes(&self, key: String) -> Option<Vec<u8>> {
-        match self {
-            StorageClient::LocalDirectory(local_directory) => {
-                let file_path = Path::new(local_directory).join(Path::new(&key));
-                match tokio::fs::read(file_path).await {
-                    Ok(bytes) => Some(bytes),
-                    Err(e) => match e.kind() {
-                        ErrorKind::NotFound => None,
-                        _ => panic!("An unexpected error occurred: {}", e),
-                    },
-                }
-            }
-            StorageClient::S3 {
-                client,
-                region: _,
-                bucket,
-            } => {
-                let request = GetObjectRequest {
-                    bucket: bucket.into(),
-                    key: key.clone(),
-                    ..Default::default()
-                };
-                let response = match client.get_object(request).await {
-                    Ok(response) => response,
-                    Err(RusotoError::Service(GetObjectError::NoSuchKey(_message))) => {
-                        warn!("could not find state for {:?}, skipping", key);
-                        return None;
-                    }
-                    Err(err) => panic!("errored on {:?}", err),
-                };
-
-                let data: ByteStream = response.body.unwrap();
-                let mut buffer = Vec::new();
-                data.into_async_read()
-                    .read_to_end(&mut buffer)
-                    .await
-                    .unwrap();
-                Some(buffer)
-            }
-        }
-    }
+    // an unaligned checkpoint whose state has been written, along with its size in bytes, that is
+    // waiting for its in-flight records
+    pending: Option<(ParquetCheckpoint, usize)>,
 }
 
 impl ParquetFlusher {
@@ -810,32 +1544,49 @@ impl ParquetFlusher {
         key: String,
         record_batch: arrow_array::RecordBatch,
     ) -> usize {
-        let props = WriterProperties::builder()
-            .set_compression(parquet::basic::Compression::SNAPPY)
-            .build();
-        let cursor = Vec::new();
-        let mut writer = ArrowWriter::try_new(cursor, record_batch.schema(), Some(props)).unwrap();
-        writer.write(&record_batch).expect("Writing batch");
-        writer.flush().unwrap();
-        let parquet_bytes = writer.into_inner().unwrap();
+        let parquet_bytes = record_batch_to_parquet(&record_batch);
         let bytes = parquet_bytes.len();
-        self.storage_client.write(key, parquet_bytes).await;
+        self.storage_client
+            .write(key.clone(), parquet_bytes)
+            .await
+            .unwrap_or_else(|e| panic!("failed to write checkpoint file {}: {:?}", key, e));
         bytes
     }
 
+    fn add_file(&mut self, table: char, epoch: u32, file: String, stats: ParquetStats) {
+        self.current_files
+            .entry(table)
+            .or_default()
+            .entry(epoch)
+            .or_default()
+            .push(ParquetStoreData {
+                epoch,
+                file,
+                table: table.to_string(),
+                min_routing_key: stats.min_routing_key,
+                max_routing_key: stats.max_routing_key,
+                max_timestamp_micros: to_micros(stats.max_timestamp),
+                compacted_files: vec![],
+            });
+    }
+
     async fn flush_iteration(&mut self) -> bool {
         let mut checkpoint_epoch = None;
+        let mut in_flight = None;
 
-        while checkpoint_epoch.is_none() {
+        while checkpoint_epoch.is_none() && in_flight.is_none() {
             tokio::select! {
                 op = self.queue.recv() => {
                     match op {
-                        Some(ParquetQueueItem::Write( ParquetWrite{table, key_hash, timestamp, key, data})) => {
-                            self.builders.entry(table).or_default().insert(key_hash, timestamp, key, data);
+                        Some(ParquetQueueItem::Write( ParquetWrite{table, key_hash, timestamp, key, data, tombstone})) => {
+                            self.builders.entry(table).or_default().insert(key_hash, timestamp, key, data, tombstone);
                         }
                         Some(ParquetQueueItem::Checkpoint(epoch)) => {
                             checkpoint_epoch = Some(epoch);
                         }
+                        Some(ParquetQueueItem::InFlight(records)) => {
+                            in_flight = Some(records);
+                        }
                         None => {
                             debug!("Parquet flusher closed");
                             return false;
@@ -845,96 +1596,286 @@ impl ParquetFlusher {
             }
         }
 
-        let mut backend_data = vec![];
+        if let Some(in_flight) = in_flight {
+            let (cp, mut bytes) = self.pending.take().unwrap_or_else(|| {
+                panic!(
+                    "received in-flight records for epoch {}, which isn't pending",
+                    in_flight.epoch
+                )
+            });
+            assert_eq!(cp.epoch, in_flight.epoch);
 
-        if let Some(cp) = checkpoint_epoch {
-            let mut bytes = 0;
-            let mut to_write = vec![];
-            for (table, builder) in self.builders.drain() {
-                let Some((record_batch, stats)) = builder.flush() else {
-                    continue;
-                };
-                let s3_key = table_checkpoint_path(&self.task_info, table, cp.epoch);
-                to_write.push((record_batch, s3_key, table, stats));
+            let mut builder = RecordBatchBuilder::default();
+            for record in in_flight.records {
+                let key = bincode::encode_to_vec(record.input as u32, BINCODE_CONFIG).unwrap();
+                builder.insert(record.key_hash, record.timestamp, key, record.data, false);
             }
-
-            for (record_batch, s3_key, table, stats) in to_write {
+            if let Some((record_batch, stats)) = builder.flush() {
+                let s3_key = table_checkpoint_path(&self.task_info, IN_FLIGHT_TABLE, cp.epoch);
                 bytes += self.upload_record_batch(s3_key.clone(), record_batch).await;
-                self.current_files
-                    .entry(table)
-                    .or_default()
-                    .entry(cp.epoch)
-                    .or_default()
-                    .push(ParquetStoreData {
-                        epoch: cp.epoch,
-                        file: s3_key,
-                        table: table.to_string(),
-                        min_routing_key: stats.min_routing_key,
-                        max_routing_key: stats.max_routing_key,
-                        max_timestamp_micros: to_micros(stats.max_timestamp),
-                    });
+                self.add_file(IN_FLIGHT_TABLE, cp.epoch, s3_key, stats);
             }
-            let mut new_file_map: HashMap<char, BTreeMap<u32, Vec<ParquetStoreData>>> =
-                HashMap::new();
-            for (table, epoch_files) in self.current_files.drain() {
-                let table_descriptor = self.table_descriptors.get(&table).unwrap();
-                for (epoch, files) in epoch_files {
-                    if table_descriptor.table_type() == TableType::Global && epoch < cp.epoch {
-                        continue;
-                    }
-                    for file in files {
-                        if table_descriptor.delete_behavior()
-                            == TableDeleteBehavior::NoReadsBeforeWatermark
-                        {
-                            if let Some(checkpoint_watermark) = cp.watermark {
-                                // this file is not needed by the new checkpoint.
-                                if file.max_timestamp_micros
-                                    < to_micros(checkpoint_watermark)
-                                        - table_descriptor.retention_micros
-                                {
-                                    continue;
-                                }
+
+            return self.complete_checkpoint(cp, bytes).await;
+        }
+
+        let cp = checkpoint_epoch.unwrap();
+        let mut bytes = 0;
+        let mut to_write = vec![];
+        for (table, builder) in self.builders.drain() {
+            let Some((record_batch, stats)) = builder.flush() else {
+                continue;
+            };
+            let s3_key = table_checkpoint_path(&self.task_info, table, cp.epoch);
+            to_write.push((record_batch, s3_key, table, stats));
+        }
+
+        for (record_batch, s3_key, table, stats) in to_write {
+            bytes += self.upload_record_batch(s3_key.clone(), record_batch).await;
+            self.add_file(table, cp.epoch, s3_key, stats);
+        }
+
+        if cp.await_in_flight {
+            // writes received from now on belong to the next checkpoint
+            self.pending = Some((cp, bytes));
+            return true;
+        }
+
+        self.complete_checkpoint(cp, bytes).await
+    }
+
+    async fn complete_checkpoint(&mut self, cp: ParquetCheckpoint, bytes: usize) -> bool {
+        let mut backend_data = vec![];
+        let mut new_file_map: HashMap<char, BTreeMap<u32, Vec<ParquetStoreData>>> = HashMap::new();
+        for (table, epoch_files) in self.current_files.drain() {
+            let table_descriptor = self.table_descriptors.get(&table).unwrap();
+            for (epoch, files) in epoch_files {
+                if (table_descriptor.table_type() == TableType::Global || table == IN_FLIGHT_TABLE)
+                    && epoch < cp.epoch
+                {
+                    continue;
+                }
+                for file in files {
+                    if table_descriptor.delete_behavior()
+                        == TableDeleteBehavior::NoReadsBeforeWatermark
+                    {
+                        if let Some(checkpoint_watermark) = cp.watermark {
+                            // this file is not needed by the new checkpoint.
+                            if file.max_timestamp_micros
+                                < to_micros(checkpoint_watermark)
+                                    - table_descriptor.retention_micros
+                            {
+                                continue;
                             }
                         }
-                        backend_data.push(arroyo_rpc::grpc::BackendData {
-                            backend_data: Some(BackendData::ParquetStore(file.clone())),
-                        });
-                        new_file_map
-                            .entry(table)
-                            .or_default()
-                            .entry(cp.epoch)
-                            .or_default()
-                            .push(file);
                     }
+                    backend_data.push(arroyo_rpc::grpc::BackendData {
+                        backend_data: Some(BackendData::ParquetStore(file.clone())),
+                    });
+                    new_file_map
+                        .entry(table)
+                        .or_default()
+                        .entry(cp.epoch)
+                        .or_default()
+                        .push(file);
                 }
             }
+        }
 
-            self.current_files = new_file_map;
-
-            // write checkpoint metadata
-            let subtask_metadata = SubtaskCheckpointMetadata {
-                subtask_index: self.task_info.task_index as u32,
-                start_time: to_micros(cp.time),
-                finish_time: to_micros(SystemTime::now()),
-                has_state: bytes > 0,
-                tables: self.table_descriptors.values().cloned().collect(),
-                watermark: cp.watermark.map(to_micros),
-                backend_data,
-                bytes: bytes as u64,
-            };
-            self.control_tx
-                .send(ControlResp::CheckpointCompleted(CheckpointCompleted {
-                    checkpoint_epoch: cp.epoch,
-                    operator_id: self.task_info.operator_id.clone(),
-                    subtask_metadata,
-                }))
-                .await
-                .unwrap();
-            if cp.then_stop {
-                self.finish_tx.take().unwrap().send(()).unwrap();
-                return false;
-            }
+        self.current_files = new_file_map;
+
+        // write checkpoint metadata
+        let subtask_metadata = SubtaskCheckpointMetadata {
+            subtask_index: self.task_info.task_index as u32,
+            start_time: to_micros(cp.time),
+            finish_time: to_micros(SystemTime::now()),
+            has_state: bytes > 0,
+            tables: self.table_descriptors.values().cloned().collect(),
+            watermark: cp.watermark.map(to_micros),
+            backend_data,
+            bytes: bytes as u64,
+        };
+        self.control_tx
+            .send(ControlResp::CheckpointCompleted(CheckpointCompleted {
+                checkpoint_epoch: cp.epoch,
+                operator_id: self.task_info.operator_id.clone(),
+                subtask_metadata,
+            }))
+            .await
+            .unwrap();
+        if cp.then_stop {
+            self.finish_tx.take().unwrap().send(()).unwrap();
+            return false;
         }
         true
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{group_by_key_range, parquet_files, ParquetBackend};
+    use crate::storage::StorageClient;
+    use crate::tables::{KeyTimeMultiMap, KeyTimeMultiMapCache};
+    use crate::{global_table, timestamp_table, BackingStore};
+    use arroyo_rpc::grpc::{
+        CheckpointMetadata, OperatorCheckpointMetadata, ParquetStoreData, TableDeleteBehavior,
+        TableDescriptor, TableWriteBehavior,
+    };
+    use arroyo_rpc::ControlResp;
+    use arroyo_types::{CheckpointBarrier, TaskInfo};
+    use rand::RngCore;
+    use std::time::{Duration, SystemTime};
+    use tokio::sync::mpsc::{channel, Receiver};
+
+    fn tables() -> Vec<TableDescriptor> {
+        vec![
+            global_table("g", "test"),
+            timestamp_table(
+                "t",
+                "time",
+                TableDeleteBehavior::None,
+                TableWriteBehavior::DefaultWrites,
+                Duration::ZERO,
+            ),
+        ]
+    }
+
+    async fn checkpoint(
+        backend: &mut ParquetBackend,
+        rx: &mut Receiver<ControlResp>,
+        epoch: u32,
+    ) -> OperatorCheckpointMetadata {
+        backend
+            .checkpoint(
+                CheckpointBarrier {
+                    epoch,
+                    min_epoch: 0,
+                    timestamp: SystemTime::now(),
+                    then_stop: false,
+                },
+                None,
+            )
+            .await;
+        let Some(ControlResp::CheckpointCompleted(c)) = rx.recv().await else {
+            panic!("Received unexpected message on command queue");
+        };
+
+        OperatorCheckpointMetadata {
+            job_id: backend.task_info.job_id.clone(),
+            operator_id: backend.task_info.operator_id.clone(),
+            epoch,
+            has_state: true,
+            tables: tables(),
+            backend_data: c.subtask_metadata.backend_data,
+            ..Default::default()
+        }
+    }
+
+    async fn restore(
+        task_info: &TaskInfo,
+        metadata: OperatorCheckpointMetadata,
+    ) -> Vec<(SystemTime, String, i32)> {
+        let epoch = metadata.epoch;
+        ParquetBackend::complete_operator_checkpoint(metadata)
+            .await
+            .unwrap();
+
+        let (tx, _rx) = channel(10);
+        let restored = ParquetBackend::from_checkpoint(
+            task_info,
+            CheckpointMetadata {
+                job_id: task_info.job_id.clone(),
+                epoch,
+                operator_ids: vec![task_info.operator_id.clone()],
+                ..Default::default()
+            },
+            tables(),
+            tx,
+        )
+        .await;
+
+        let mut triples = restored.get_data_triples::<String, i32>('t').await;
+        triples.sort();
+        triples
+    }
+
+    #[tokio::test]
+    async fn test_merge_files() {
+        let task_info = TaskInfo::for_test(
+            &format!("test_job_{}", rand::thread_rng().next_u64()),
+            "test_op",
+        );
+        let (tx, mut rx) = channel(10);
+        let mut backend = ParquetBackend::new(&task_info, tables(), tx).await;
+        let mut cache = KeyTimeMultiMapCache::<String, i32>::default();
+
+        // whole seconds, so that times survive the round trip through the checkpoint
+        let t1 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let t2 = t1 + Duration::from_secs(1);
+        let t3 = t2 + Duration::from_secs(1);
+
+        // every epoch writes keys across the whole key space, so the files of all three overlap
+        let mut ks = KeyTimeMultiMap::new('t', &mut backend, &mut cache);
+        for i in 0..10 {
+            ks.insert(t1, format!("k{}", i), i).await;
+        }
+        checkpoint(&mut backend, &mut rx, 1).await;
+
+        let mut ks = KeyTimeMultiMap::new('t', &mut backend, &mut cache);
+        for i in 0..5 {
+            ks.insert(t2, format!("k{}", i), 100 + i).await;
+        }
+        ks.clear_time_range(&mut "k0".into(), t1, t2).await;
+        checkpoint(&mut backend, &mut rx, 2).await;
+
+        let mut ks = KeyTimeMultiMap::new('t', &mut backend, &mut cache);
+        ks.insert(t1, "k5".into(), 50).await;
+        ks.insert(t3, "k9".into(), 900).await;
+        ks.clear_time_range(&mut "k1".into(), t1, t3).await;
+        let metadata = checkpoint(&mut backend, &mut rx, 3).await;
+
+        let table_files = |metadata: &OperatorCheckpointMetadata| {
+            parquet_files(metadata).filter(|f| f.table == "t").count()
+        };
+        assert_eq!(3, table_files(&metadata));
+
+        let expected = restore(&task_info, metadata.clone()).await;
+        assert!(expected.contains(&(t1, "k5".to_string(), 50)));
+        assert!(!expected
+            .iter()
+            .any(|(t, k, _)| k == "k1" || (k == "k0" && *t == t1)));
+
+        let merged =
+            ParquetBackend::merge_files(&StorageClient::for_job(&task_info.job_id), metadata)
+                .await
+                .unwrap();
+        assert_eq!(1, table_files(&merged));
+
+        assert_eq!(expected, restore(&task_info, merged).await);
+    }
+
+    #[test]
+    fn test_group_by_key_range() {
+        let file = |name: &str, min_routing_key, max_routing_key| ParquetStoreData {
+            file: name.to_string(),
+            min_routing_key,
+            max_routing_key,
+            ..Default::default()
+        };
+
+        let groups = group_by_key_range(vec![
+            file("a", 50, 60),
+            file("b", 0, 10),
+            file("c", 5, 20),
+            file("d", 30, 40),
+            file("e", 20, 25),
+            file("f", 55, 70),
+        ]);
+
+        let names: Vec<Vec<&str>> = groups
+            .iter()
+            .map(|group| group.iter().map(|f| f.file.as_str()).collect())
+            .collect();
+        assert_eq!(vec![vec!["b", "c", "e"], vec!["d"], vec!["a", "f"]], names);
+    }
+}
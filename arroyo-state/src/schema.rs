@@ -0,0 +1,458 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Result};
+use arroyo_rpc::grpc::{StateField, StateType, TableSchema};
+use serde_json::{json, Map, Value};
+
+/// Version of the format keys and values are serialized with (bincode's standard configuration)
+pub const STATE_FORMAT_VERSION: u32 = 1;
+
+/// Schemas of the tables of each operator, keyed by operator id and then table name
+pub type OperatorSchemas = HashMap<String, HashMap<String, TableSchema>>;
+
+pub fn table_schema(key: StateType, value: StateType) -> TableSchema {
+    TableSchema {
+        version: STATE_FORMAT_VERSION,
+        key: Some(key),
+        value: Some(value),
+    }
+}
+
+/// Checks whether state written with the `old` schema can be restored into a table with the `new`
+/// schema. Keys must be unchanged, as they determine how state is partitioned, while struct
+/// values may add or remove nullable fields.
+pub fn check_compatibility(table: &str, old: &TableSchema, new: &TableSchema) -> Result<()> {
+    if old.version > STATE_FORMAT_VERSION {
+        bail!(
+            "state for table '{}' was written with format version {}, but only versions up to {} \
+            are supported",
+            table,
+            old.version,
+            STATE_FORMAT_VERSION
+        );
+    }
+
+    if old.key != new.key {
+        bail!(
+            "the key of table '{}' changed from {} to {}",
+            table,
+            describe(old.key.as_ref()),
+            describe(new.key.as_ref())
+        );
+    }
+
+    let (Some(old_value), Some(new_value)) = (&old.value, &new.value) else {
+        bail!("missing value schema for table '{}'", table);
+    };
+
+    check_type(old_value, new_value)
+        .map_err(|e| anyhow!("state for table '{}' cannot be restored: {}", table, e))?;
+
+    if old_value != new_value && !is_decodable(old_value) {
+        bail!(
+            "state for table '{}' cannot be migrated from {}",
+            table,
+            describe(Some(old_value))
+        );
+    }
+
+    Ok(())
+}
+
+fn check_type(old: &StateType, new: &StateType) -> Result<()> {
+    if old == new {
+        return Ok(());
+    }
+
+    match (old.name.as_str(), new.name.as_str()) {
+        ("struct", "struct") => {
+            for field in &new.fields {
+                match old.fields.iter().find(|f| f.name == field.name) {
+                    Some(old_field) => check_type(field_type(old_field)?, field_type(field)?)
+                        .map_err(|e| anyhow!("field '{}': {}", field.name, e))?,
+                    None if is_nullable(field_type(field)?) => {}
+                    None => bail!("added field '{}' is not nullable", field.name),
+                }
+            }
+            for field in &old.fields {
+                if !new.fields.iter().any(|f| f.name == field.name)
+                    && !is_nullable(field_type(field)?)
+                {
+                    bail!("removed field '{}' is not nullable", field.name);
+                }
+            }
+            Ok(())
+        }
+        ("tuple", "tuple") | ("Option", "Option") | ("Vec", "Vec")
+            if old.params.len() == new.params.len() =>
+        {
+            for (old, new) in old.params.iter().zip(&new.params) {
+                check_type(old, new)?;
+            }
+            Ok(())
+        }
+        _ => bail!(
+            "type changed from {} to {}",
+            describe(Some(old)),
+            describe(Some(new))
+        ),
+    }
+}
+
+/// Re-encodes a value written with the `old` type so that it can be decoded as the `new` type,
+/// which must have been checked with [`check_compatibility`].
+pub fn migrate(old: &StateType, new: &StateType, bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(bytes.len());
+    let read = migrate_into(old, new, bytes, &mut out)?;
+    if read != bytes.len() {
+        bail!(
+            "value was {} bytes, but {} were expected for {}",
+            bytes.len(),
+            read,
+            describe(Some(old))
+        );
+    }
+    Ok(out)
+}
+
+// appends the migrated value at the start of `bytes` to `out`, returning the number of bytes read
+fn migrate_into(
+    old: &StateType,
+    new: &StateType,
+    bytes: &[u8],
+    out: &mut Vec<u8>,
+) -> Result<usize> {
+    if old == new {
+        let len = encoded_len(old, bytes)?;
+        out.extend_from_slice(&bytes[..len]);
+        return Ok(len);
+    }
+
+    match old.name.as_str() {
+        "struct" => {
+            let mut offsets = HashMap::new();
+            let mut position = 0;
+            for field in &old.fields {
+                let len = encoded_len(field_type(field)?, &bytes[position..])?;
+                offsets.insert(field.name.as_str(), (field, position));
+                position += len;
+            }
+            for field in &new.fields {
+                match offsets.get(field.name.as_str()) {
+                    Some((old_field, offset)) => {
+                        migrate_into(
+                            field_type(old_field)?,
+                            field_type(field)?,
+                            &bytes[*offset..],
+                            out,
+                        )?;
+                    }
+                    // added fields are nullable, and start out as None
+                    None => out.push(0),
+                }
+            }
+            Ok(position)
+        }
+        "Option" => {
+            let tag = *bytes
+                .first()
+                .ok_or_else(|| anyhow!("unexpected end of value"))?;
+            out.push(tag);
+            match tag {
+                0 => Ok(1),
+                1 => Ok(1 + migrate_into(&old.params[0], &new.params[0], &bytes[1..], out)?),
+                _ => bail!("invalid option tag {}", tag),
+            }
+        }
+        "Vec" => {
+            let (len, mut position) = read_varint(bytes)?;
+            out.extend_from_slice(&bytes[..position]);
+            for _ in 0..len {
+                position += migrate_into(&old.params[0], &new.params[0], &bytes[position..], out)?;
+            }
+            Ok(position)
+        }
+        "tuple" => {
+            let mut position = 0;
+            for (old, new) in old.params.iter().zip(&new.params) {
+                position += migrate_into(old, new, &bytes[position..], out)?;
+            }
+            Ok(position)
+        }
+        _ => bail!(
+            "cannot migrate {} to {}",
+            describe(Some(old)),
+            describe(Some(new))
+        ),
+    }
+}
+
+/// Decodes a bincode-encoded value of type `t` into JSON. Integers that don't fit in an i64 or
+/// u64 and the bytes of types whose layout isn't known are rendered as strings, while times and
+/// durations are rendered as microseconds.
+pub fn to_json(t: &StateType, bytes: &[u8]) -> Result<Value> {
+    if !is_decodable(t) {
+        return Ok(Value::String(hex(bytes)));
+    }
+
+    let (value, read) = decode_json(t, bytes)?;
+    if read != bytes.len() {
+        bail!(
+            "value was {} bytes, but {} were expected for {}",
+            bytes.len(),
+            read,
+            describe(Some(t))
+        );
+    }
+    Ok(value)
+}
+
+fn decode_json(t: &StateType, bytes: &[u8]) -> Result<(Value, usize)> {
+    let first = || {
+        bytes
+            .first()
+            .copied()
+            .ok_or_else(|| anyhow!("unexpected end of value"))
+    };
+
+    Ok(match t.name.as_str() {
+        "()" => (Value::Null, 0),
+        "bool" => (Value::Bool(first()? != 0), 1),
+        "u8" => (json!(first()?), 1),
+        "i8" => (json!(first()? as i8), 1),
+        "f32" | "f64" => {
+            let width = if t.name == "f32" { 4 } else { 8 };
+            let value = bytes
+                .get(..width)
+                .ok_or_else(|| anyhow!("unexpected end of value"))?;
+            let value = if width == 4 {
+                f32::from_le_bytes(value.try_into().unwrap()) as f64
+            } else {
+                f64::from_le_bytes(value.try_into().unwrap())
+            };
+            (json!(value), width)
+        }
+        "u16" | "u32" | "u64" | "u128" | "usize" => {
+            let (value, len) = read_varint(bytes)?;
+            let value = match u64::try_from(value) {
+                Ok(v) => json!(v),
+                Err(_) => json!(value.to_string()),
+            };
+            (value, len)
+        }
+        "i16" | "i32" | "i64" | "i128" | "isize" => {
+            let (value, len) = read_varint(bytes)?;
+            // signed integers are zigzag encoded
+            let value = (value >> 1) as i128 ^ -((value & 1) as i128);
+            let value = match i64::try_from(value) {
+                Ok(v) => json!(v),
+                Err(_) => json!(value.to_string()),
+            };
+            (value, len)
+        }
+        "char" | "String" => {
+            let (start, len) = if t.name == "char" {
+                (0, encoded_len(t, bytes)?)
+            } else {
+                let (len, position) = read_varint(bytes)?;
+                (position, len as usize)
+            };
+            let value = bytes
+                .get(start..start + len)
+                .ok_or_else(|| anyhow!("unexpected end of value"))?;
+            (json!(std::str::from_utf8(value)?), start + len)
+        }
+        "SystemTime" | "Duration" => {
+            let (secs, secs_len) = read_varint(bytes)?;
+            let (nanos, nanos_len) = read_varint(&bytes[secs_len..])?;
+            (
+                json!((secs * 1_000_000 + nanos / 1_000) as u64),
+                secs_len + nanos_len,
+            )
+        }
+        "Option" => match first()? {
+            0 => (Value::Null, 1),
+            1 => {
+                let (value, len) = decode_json(&t.params[0], &bytes[1..])?;
+                (value, len + 1)
+            }
+            tag => bail!("invalid option tag {}", tag),
+        },
+        "Vec" => {
+            let (count, mut position) = read_varint(bytes)?;
+            let mut values = vec![];
+            for _ in 0..count {
+                let (value, len) = decode_json(&t.params[0], &bytes[position..])?;
+                values.push(value);
+                position += len;
+            }
+            (Value::Array(values), position)
+        }
+        "tuple" => {
+            let mut values = vec![];
+            let mut position = 0;
+            for param in &t.params {
+                let (value, len) = decode_json(param, &bytes[position..])?;
+                values.push(value);
+                position += len;
+            }
+            (Value::Array(values), position)
+        }
+        "struct" => {
+            let mut values = Map::new();
+            let mut position = 0;
+            for field in &t.fields {
+                let (value, len) = decode_json(field_type(field)?, &bytes[position..])?;
+                values.insert(field.name.clone(), value);
+                position += len;
+            }
+            (Value::Object(values), position)
+        }
+        other => bail!("cannot decode {}", other),
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// number of bytes the bincode encoding of a value of type `t` at the start of `bytes` takes up
+fn encoded_len(t: &StateType, bytes: &[u8]) -> Result<usize> {
+    let len = match t.name.as_str() {
+        "()" => 0,
+        "bool" | "u8" | "i8" => 1,
+        "f32" => 4,
+        "f64" => 8,
+        "u16" | "u32" | "u64" | "u128" | "usize" | "i16" | "i32" | "i64" | "i128" | "isize" => {
+            read_varint(bytes)?.1
+        }
+        "char" => match bytes.first() {
+            Some(b) if *b < 0x80 => 1,
+            Some(b) if *b & 0xE0 == 0xC0 => 2,
+            Some(b) if *b & 0xF0 == 0xE0 => 3,
+            Some(_) => 4,
+            None => bail!("unexpected end of value"),
+        },
+        "SystemTime" | "Duration" => {
+            let secs = read_varint(bytes)?.1;
+            secs + read_varint(&bytes[secs..])?.1
+        }
+        "String" => {
+            let (len, position) = read_varint(bytes)?;
+            position + len as usize
+        }
+        "Option" => match bytes.first() {
+            Some(0) => 1,
+            Some(1) => 1 + encoded_len(&t.params[0], &bytes[1..])?,
+            Some(tag) => bail!("invalid option tag {}", tag),
+            None => bail!("unexpected end of value"),
+        },
+        "Vec" => {
+            let (len, mut position) = read_varint(bytes)?;
+            for _ in 0..len {
+                position += encoded_len(&t.params[0], &bytes[position..])?;
+            }
+            position
+        }
+        "tuple" => {
+            let mut position = 0;
+            for param in &t.params {
+                position += encoded_len(param, &bytes[position..])?;
+            }
+            position
+        }
+        "struct" => {
+            let mut position = 0;
+            for field in &t.fields {
+                position += encoded_len(field_type(field)?, &bytes[position..])?;
+            }
+            position
+        }
+        other => bail!("cannot determine the encoded size of {}", other),
+    };
+
+    if len > bytes.len() {
+        bail!("unexpected end of value");
+    }
+    Ok(len)
+}
+
+// decodes a bincode varint, returning the value and the number of bytes it took up
+fn read_varint(bytes: &[u8]) -> Result<(u128, usize)> {
+    let (width, position) = match bytes.first() {
+        Some(b) if *b <= 250 => return Ok((*b as u128, 1)),
+        Some(251) => (2, 1),
+        Some(252) => (4, 1),
+        Some(253) => (8, 1),
+        Some(254) => (16, 1),
+        Some(b) => bail!("invalid varint tag {}", b),
+        None => bail!("unexpected end of value"),
+    };
+
+    let value = bytes
+        .get(position..position + width)
+        .ok_or_else(|| anyhow!("unexpected end of value"))?;
+    let mut buf = [0u8; 16];
+    buf[..width].copy_from_slice(value);
+    Ok((u128::from_le_bytes(buf), position + width))
+}
+
+fn is_nullable(t: &StateType) -> bool {
+    t.name == "Option"
+}
+
+// whether the layout of every value of this type is known, which is required to migrate it
+fn is_decodable(t: &StateType) -> bool {
+    match t.name.as_str() {
+        "()" | "bool" | "u8" | "i8" | "f32" | "f64" | "u16" | "u32" | "u64" | "u128" | "usize"
+        | "i16" | "i32" | "i64" | "i128" | "isize" | "char" | "SystemTime" | "Duration"
+        | "String" => true,
+        "Option" | "Vec" | "tuple" => t.params.iter().all(is_decodable),
+        "struct" => t
+            .fields
+            .iter()
+            .all(|f| f.data_type.as_ref().map(is_decodable).unwrap_or(false)),
+        _ => false,
+    }
+}
+
+fn field_type(field: &StateField) -> Result<&StateType> {
+    field
+        .data_type
+        .as_ref()
+        .ok_or_else(|| anyhow!("missing type for field '{}'", field.name))
+}
+
+fn describe(t: Option<&StateType>) -> String {
+    let Some(t) = t else {
+        return "unknown".to_string();
+    };
+    match t.name.as_str() {
+        "struct" => format!(
+            "{{{}}}",
+            t.fields
+                .iter()
+                .map(|f| format!("{}: {}", f.name, describe(f.data_type.as_ref())))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        "tuple" => format!(
+            "({})",
+            t.params
+                .iter()
+                .map(|p| describe(Some(p)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        name if t.params.is_empty() => name.to_string(),
+        name => format!(
+            "{}<{}>",
+            name,
+            t.params
+                .iter()
+                .map(|p| describe(Some(p)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
@@ -0,0 +1,289 @@
+use crate::parquet::ParquetBackend;
+use crate::schema::OperatorSchemas;
+use crate::tables::EncodedRow;
+use crate::{BackingStore, InFlightRecord, BINCODE_CONFIG};
+use anyhow::Result;
+use arroyo_rpc::grpc::{
+    CheckpointMetadata, OperatorCheckpointMetadata, TableDescriptor, TableType,
+};
+use arroyo_rpc::ControlResp;
+use arroyo_types::{
+    CheckpointBarrier, Data, Key, TaskInfo, MAX_IN_MEMORY_STATE_ENTRIES_ENV, STATE_SPILL_DIR_ENV,
+};
+use bincode::{Decode, Encode};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::sync::mpsc::Sender;
+use tracing::info;
+
+pub const DEFAULT_MAX_IN_MEMORY_ENTRIES: usize = 1_000_000;
+
+/// An embedded on-disk store that tables move their least-recently-used data to once they hold
+/// more than `max_in_memory_entries` entries. Its contents only live as long as the subtask;
+/// everything in it has also been written to the checkpoint writer.
+pub struct SpillStore {
+    db: sled::Db,
+    max_in_memory_entries: usize,
+}
+
+impl SpillStore {
+    pub fn open(path: &Path, max_in_memory_entries: usize) -> Self {
+        let db = sled::Config::new()
+            .path(path)
+            .temporary(true)
+            .open()
+            .unwrap_or_else(|e| panic!("failed to open spill store at {:?}: {:?}", path, e));
+
+        SpillStore {
+            db,
+            max_in_memory_entries,
+        }
+    }
+
+    pub fn max_in_memory_entries(&self) -> usize {
+        self.max_in_memory_entries
+    }
+
+    fn tree(&self, table: char) -> sled::Tree {
+        self.db
+            .open_tree(table.to_string())
+            .unwrap_or_else(|e| panic!("failed to open spill tree for table {}: {:?}", table, e))
+    }
+
+    pub fn put<T: Encode>(&self, table: char, key: &[u8], value: &T) {
+        let bytes = bincode::encode_to_vec(value, BINCODE_CONFIG).unwrap();
+        self.tree(table)
+            .insert(key, bytes)
+            .unwrap_or_else(|e| panic!("failed to spill to table {}: {:?}", table, e));
+    }
+
+    /// Removes and returns the value for `key`, if it has been spilled
+    pub fn take<T: Decode>(&self, table: char, key: &[u8]) -> Option<T> {
+        let bytes = self.tree(table).remove(key).unwrap_or_else(|e| {
+            panic!("failed to read spilled data for table {}: {:?}", table, e)
+        })?;
+        let (value, _) = bincode::decode_from_slice(&bytes, BINCODE_CONFIG)
+            .unwrap_or_else(|e| panic!("corrupt spilled data in table {}: {:?}", table, e));
+        Some(value)
+    }
+
+    /// Returns the value for `key` without moving it out of the store, for readers that can't
+    /// modify the table
+    pub fn get<T: Decode>(&self, table: char, key: &[u8]) -> Result<Option<T>> {
+        let Some(bytes) = self.tree(table).get(key)? else {
+            return Ok(None);
+        };
+        Ok(Some(bincode::decode_from_slice(&bytes, BINCODE_CONFIG)?.0))
+    }
+}
+
+fn spill_path(task_info: &TaskInfo) -> PathBuf {
+    let root = std::env::var(STATE_SPILL_DIR_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| std::env::temp_dir().join("arroyo-spill"));
+
+    root.join(&task_info.job_id).join(format!(
+        "{}-{:0>3}",
+        task_info.operator_id, task_info.task_index
+    ))
+}
+
+fn max_in_memory_entries() -> usize {
+    std::env::var(MAX_IN_MEMORY_STATE_ENTRIES_ENV)
+        .ok()
+        .map(|v| {
+            v.parse()
+                .unwrap_or_else(|_| panic!("invalid {}: {}", MAX_IN_MEMORY_STATE_ENTRIES_ENV, v))
+        })
+        .unwrap_or(DEFAULT_MAX_IN_MEMORY_ENTRIES)
+}
+
+/// A backend for state that is larger than memory. Tables keep their hot data in memory and move
+/// cold data to a local [SpillStore]; checkpoints are written exactly as with the
+/// [ParquetBackend], so jobs can switch between the two when restarting from a checkpoint.
+pub struct SpillBackend {
+    parquet: ParquetBackend,
+    store: SpillStore,
+}
+
+impl SpillBackend {
+    fn open_store(task_info: &TaskInfo) -> SpillStore {
+        let path = spill_path(task_info);
+        let max_entries = max_in_memory_entries();
+        info!(
+            message = "opening spill store",
+            path = path.to_string_lossy().to_string(),
+            max_entries
+        );
+        SpillStore::open(&path, max_entries)
+    }
+
+    pub fn set_max_in_memory_entries(&mut self, max_in_memory_entries: usize) {
+        self.store.max_in_memory_entries = max_in_memory_entries;
+    }
+}
+
+#[async_trait::async_trait]
+impl BackingStore for SpillBackend {
+    fn name() -> &'static str {
+        "spill"
+    }
+
+    async fn load_latest_checkpoint_metadata(job_id: &str) -> Result<Option<CheckpointMetadata>> {
+        ParquetBackend::load_latest_checkpoint_metadata(job_id).await
+    }
+
+    async fn load_checkpoint_metadata(
+        job_id: &str,
+        epoch: u32,
+    ) -> Result<Option<CheckpointMetadata>> {
+        ParquetBackend::load_checkpoint_metadata(job_id, epoch).await
+    }
+
+    async fn load_operator_metadata(
+        job_id: &str,
+        operator_id: &str,
+        epoch: u32,
+    ) -> Result<Option<OperatorCheckpointMetadata>> {
+        ParquetBackend::load_operator_metadata(job_id, operator_id, epoch).await
+    }
+
+    async fn new(
+        task_info: &TaskInfo,
+        tables: Vec<TableDescriptor>,
+        control_tx: Sender<ControlResp>,
+    ) -> Self {
+        SpillBackend {
+            parquet: ParquetBackend::new(task_info, tables, control_tx).await,
+            store: Self::open_store(task_info),
+        }
+    }
+
+    async fn from_checkpoint(
+        task_info: &TaskInfo,
+        metadata: CheckpointMetadata,
+        tables: Vec<TableDescriptor>,
+        control_tx: Sender<ControlResp>,
+    ) -> Self {
+        SpillBackend {
+            parquet: ParquetBackend::from_checkpoint(task_info, metadata, tables, control_tx).await,
+            store: Self::open_store(task_info),
+        }
+    }
+
+    async fn prepare_checkpoint(metadata: &CheckpointMetadata) -> Result<()> {
+        ParquetBackend::prepare_checkpoint(metadata).await
+    }
+
+    async fn complete_operator_checkpoint(metadata: OperatorCheckpointMetadata) -> Result<()> {
+        ParquetBackend::complete_operator_checkpoint(metadata).await
+    }
+
+    async fn complete_checkpoint(metadata: CheckpointMetadata) -> Result<()> {
+        ParquetBackend::complete_checkpoint(metadata).await
+    }
+
+    async fn compact_checkpoint(
+        metadata: CheckpointMetadata,
+        old_min_epoch: u32,
+        new_min_epoch: u32,
+    ) -> Result<()> {
+        ParquetBackend::compact_checkpoint(metadata, old_min_epoch, new_min_epoch).await
+    }
+
+    async fn write_savepoint(metadata: CheckpointMetadata, savepoint_id: &str) -> Result<()> {
+        ParquetBackend::write_savepoint(metadata, savepoint_id).await
+    }
+
+    async fn restore_savepoint(savepoint_id: &str, job_id: &str) -> Result<CheckpointMetadata> {
+        ParquetBackend::restore_savepoint(savepoint_id, job_id).await
+    }
+
+    async fn migrate_checkpoint(
+        metadata: &CheckpointMetadata,
+        schemas: &OperatorSchemas,
+    ) -> Result<Option<CheckpointMetadata>> {
+        ParquetBackend::migrate_checkpoint(metadata, schemas).await
+    }
+
+    async fn checkpoint(
+        &mut self,
+        barrier: CheckpointBarrier,
+        watermark: Option<SystemTime>,
+    ) -> u32 {
+        self.parquet.checkpoint(barrier, watermark).await
+    }
+
+    async fn checkpoint_unaligned(
+        &mut self,
+        barrier: CheckpointBarrier,
+        watermark: Option<SystemTime>,
+    ) -> u32 {
+        self.parquet.checkpoint_unaligned(barrier, watermark).await
+    }
+
+    async fn write_in_flight(&mut self, epoch: u32, records: Vec<InFlightRecord>) {
+        self.parquet.write_in_flight(epoch, records).await
+    }
+
+    async fn get_in_flight(&self) -> Vec<InFlightRecord> {
+        self.parquet.get_in_flight().await
+    }
+
+    async fn get_data_triples<K: Key, V: Data>(&self, table: char) -> Vec<(SystemTime, K, V)> {
+        self.parquet.get_data_triples(table).await
+    }
+
+    fn restore_parts(&self, table: char) -> usize {
+        self.parquet.restore_parts(table)
+    }
+
+    async fn get_data_triples_part<K: Key, V: Data>(
+        &self,
+        table: char,
+        part: usize,
+    ) -> Vec<(SystemTime, K, Option<V>)> {
+        self.parquet.get_data_triples_part(table, part).await
+    }
+
+    async fn write_data_triple<K: Key, V: Data>(
+        &mut self,
+        table: char,
+        table_type: TableType,
+        timestamp: SystemTime,
+        key: &mut K,
+        value: &mut V,
+    ) {
+        self.parquet
+            .write_data_triple(table, table_type, timestamp, key, value)
+            .await
+    }
+
+    async fn delete_data_triple<K: Key>(
+        &mut self,
+        table: char,
+        table_type: TableType,
+        timestamp: SystemTime,
+        key: &mut K,
+    ) {
+        self.parquet
+            .delete_data_triple(table, table_type, timestamp, key)
+            .await
+    }
+
+    async fn write_key_value<K: Key, V: Data>(&mut self, table: char, key: &mut K, value: &mut V) {
+        self.parquet.write_key_value(table, key, value).await
+    }
+
+    async fn write_encoded_row(&mut self, table: char, row: EncodedRow) {
+        self.parquet.write_encoded_row(table, row).await
+    }
+
+    async fn get_key_values<K: Key, V: Data>(&self, table: char) -> Vec<(K, V)> {
+        self.parquet.get_key_values(table).await
+    }
+
+    fn spill_store(&self) -> Option<&SpillStore> {
+        Some(&self.store)
+    }
+}
@@ -0,0 +1,744 @@
+use anyhow::{anyhow, bail, Result};
+use arroyo_types::{CHECKPOINT_URL_ENV, S3_BUCKET_ENV, S3_REGION_ENV};
+use async_trait::async_trait;
+use backoff::ExponentialBackoff;
+use bytes::Bytes;
+use futures::{Future, TryStreamExt};
+use object_store::aws::AmazonS3Builder;
+use object_store::azure::MicrosoftAzureBuilder;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::ObjectStore;
+use once_cell::sync::Lazy;
+use reqwest::StatusCode;
+use std::collections::HashMap;
+use std::io::{ErrorKind, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite};
+use tokio_util::io::StreamReader;
+use tracing::warn;
+
+const DEFAULT_LOCAL_DIRECTORY: &str = "/tmp/arroyo-data";
+
+// environment variables that configure how each kind of store is reached, which are passed on to
+// workers along with the storage url; credentials are deliberately not among them
+const S3_SETTINGS_ENV: [&str; 6] = [
+    S3_REGION_ENV,
+    "AWS_REGION",
+    "AWS_DEFAULT_REGION",
+    "AWS_ENDPOINT",
+    "AWS_VIRTUAL_HOSTED_STYLE_REQUEST",
+    "AWS_ALLOW_HTTP",
+];
+const AZURE_SETTINGS_ENV: [&str; 3] = [
+    "AZURE_STORAGE_ACCOUNT_NAME",
+    "AZURE_STORAGE_USE_EMULATOR",
+    "AZURE_ALLOW_HTTP",
+];
+
+/// Environment variables that hold credentials for the object stores. These are never copied from
+/// the controller to workers; they must be provided to workers by the scheduler's environment
+/// (for example from a Kubernetes Secret), or replaced by workload identity.
+pub const STORAGE_CREDENTIAL_ENV: [&str; 10] = [
+    "AWS_ACCESS_KEY_ID",
+    "AWS_SECRET_ACCESS_KEY",
+    "AWS_SESSION_TOKEN",
+    "GOOGLE_SERVICE_ACCOUNT_KEY",
+    "AZURE_STORAGE_ACCOUNT_KEY",
+    "AZURE_STORAGE_CLIENT_ID",
+    "AZURE_STORAGE_CLIENT_SECRET",
+    "AZURE_STORAGE_TENANT_ID",
+    "AZURE_STORAGE_SAS_KEY",
+    "AZURE_STORAGE_TOKEN",
+];
+
+// storage urls for jobs that don't use the default storage, registered by the controller and api
+static JOB_URLS: Lazy<RwLock<HashMap<String, String>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+// clients are cached by url so that connections are reused across calls
+static CLIENTS: Lazy<Mutex<HashMap<String, StorageClient>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[async_trait]
+pub trait StorageProvider: Send + Sync {
+    async fn put(&self, key: &str, bytes: Bytes) -> Result<()>;
+
+    /// Returns `None` if there is no object at `key`
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Returns a reader that streams the object at `key`, or `None` if there is no object there
+    async fn reader(&self, key: &str) -> Result<Option<Box<dyn AsyncRead + Unpin + Send>>>;
+
+    /// Returns the bytes in `range` of the object at `key`
+    async fn get_range(&self, key: &str, range: Range<usize>) -> Result<Bytes>;
+
+    /// Returns the size in bytes of the object at `key`, or `None` if there is no object there
+    async fn size(&self, key: &str) -> Result<Option<usize>>;
+
+    /// Deleting an object that does not exist is not an error
+    async fn delete(&self, key: &str) -> Result<()>;
+
+    /// Returns the keys of all objects under `prefix`, in any order
+    async fn list(&self, prefix: &str) -> Result<Vec<String>>;
+
+    /// Returns a writer that streams an object to `key`, which is complete once the writer has
+    /// been shut down
+    async fn writer(&self, key: &str) -> Result<Box<dyn AsyncWrite + Unpin + Send>>;
+
+    /// Moves the object at `from` to `to`; returns `false` if there is no object at `from`
+    async fn rename(&self, from: &str, to: &str) -> Result<bool>;
+}
+
+pub struct LocalStorage {
+    directory: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl StorageProvider for LocalStorage {
+    async fn put(&self, key: &str, bytes: Bytes) -> Result<()> {
+        let file_path = self.directory.join(Path::new(key));
+        if let Some(parent) = file_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(file_path, &bytes).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.directory.join(Path::new(key))).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn reader(&self, key: &str) -> Result<Option<Box<dyn AsyncRead + Unpin + Send>>> {
+        match tokio::fs::File::open(self.directory.join(Path::new(key))).await {
+            Ok(file) => Ok(Some(Box::new(file))),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn get_range(&self, key: &str, range: Range<usize>) -> Result<Bytes> {
+        let mut file = tokio::fs::File::open(self.directory.join(Path::new(key))).await?;
+        file.seek(SeekFrom::Start(range.start as u64)).await?;
+        let mut bytes = vec![0; range.len()];
+        file.read_exact(&mut bytes).await?;
+        Ok(bytes.into())
+    }
+
+    async fn size(&self, key: &str) -> Result<Option<usize>> {
+        match tokio::fs::metadata(self.directory.join(Path::new(key))).await {
+            Ok(metadata) => Ok(Some(metadata.len() as usize)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.directory.join(Path::new(key))).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut keys = vec![];
+        let mut dirs = vec![self.directory.join(Path::new(prefix))];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = match tokio::fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                if entry.file_type().await?.is_dir() {
+                    dirs.push(entry.path());
+                } else {
+                    let path = entry.path();
+                    let key = path.strip_prefix(&self.directory)?;
+                    keys.push(key.to_string_lossy().to_string());
+                }
+            }
+        }
+        Ok(keys)
+    }
+
+    async fn writer(&self, key: &str) -> Result<Box<dyn AsyncWrite + Unpin + Send>> {
+        let file_path = self.directory.join(Path::new(key));
+        if let Some(parent) = file_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        Ok(Box::new(tokio::fs::File::create(file_path).await?))
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<bool> {
+        let to_path = self.directory.join(Path::new(to));
+        if let Some(parent) = to_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        match tokio::fs::rename(self.directory.join(Path::new(from)), to_path).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Storage on S3 (or an S3-compatible store), GCS or Azure Blob Storage. Credentials are read from
+/// the standard `AWS_*`, `GOOGLE_*` and `AZURE_*` environment variables.
+pub struct ObjectStorage {
+    store: Box<dyn ObjectStore>,
+}
+
+impl ObjectStorage {
+    /// Supported options are `region` and `endpoint`, for S3-compatible stores like MinIO. Stores
+    /// with a custom endpoint are addressed path-style unless `virtual_hosted_style=true` is set.
+    pub fn s3(bucket: &str, options: &HashMap<String, String>) -> Result<Self> {
+        let region = options
+            .get("region")
+            .cloned()
+            .or_else(|| std::env::var(S3_REGION_ENV).ok())
+            .unwrap_or_else(|| "us-east-1".to_string());
+        let mut builder = AmazonS3Builder::from_env()
+            .with_bucket_name(bucket)
+            .with_region(region);
+        if let Some(endpoint) = options.get("endpoint") {
+            let virtual_hosted_style = options
+                .get("virtual_hosted_style")
+                .map(|v| v == "true")
+                .unwrap_or(false);
+            builder = builder
+                .with_allow_http(endpoint.starts_with("http://"))
+                .with_endpoint(endpoint)
+                .with_virtual_hosted_style_request(virtual_hosted_style);
+        }
+        Ok(Self {
+            store: Box::new(builder.build()?),
+        })
+    }
+
+    pub fn gcs(bucket: &str) -> Result<Self> {
+        Ok(Self {
+            store: Box::new(
+                GoogleCloudStorageBuilder::from_env()
+                    .with_bucket_name(bucket)
+                    .build()?,
+            ),
+        })
+    }
+
+    /// The storage account may be given with the `account` option, or otherwise through
+    /// `AZURE_STORAGE_ACCOUNT_NAME`
+    pub fn azure(container: &str, options: &HashMap<String, String>) -> Result<Self> {
+        let mut builder = MicrosoftAzureBuilder::from_env().with_container_name(container);
+        if let Some(account) = options.get("account") {
+            builder = builder.with_account(account);
+        }
+        Ok(Self {
+            store: Box::new(builder.build()?),
+        })
+    }
+}
+
+#[async_trait]
+impl StorageProvider for ObjectStorage {
+    async fn put(&self, key: &str, bytes: Bytes) -> Result<()> {
+        self.store.put(&key.into(), bytes).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.store.get(&key.into()).await {
+            Ok(result) => Ok(Some(result.bytes().await?.to_vec())),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn reader(&self, key: &str) -> Result<Option<Box<dyn AsyncRead + Unpin + Send>>> {
+        match self.store.get(&key.into()).await {
+            Ok(result) => Ok(Some(Box::new(StreamReader::new(
+                result
+                    .into_stream()
+                    .map_err(std::io::Error::other),
+            )))),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn get_range(&self, key: &str, range: Range<usize>) -> Result<Bytes> {
+        Ok(self.store.get_range(&key.into(), range).await?)
+    }
+
+    async fn size(&self, key: &str) -> Result<Option<usize>> {
+        match self.store.head(&key.into()).await {
+            Ok(meta) => Ok(Some(meta.size)),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match self.store.delete(&key.into()).await {
+            Err(e) if !matches!(e, object_store::Error::NotFound { .. }) => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        Ok(self
+            .store
+            .list(Some(&prefix.into()))
+            .await?
+            .map_ok(|meta| meta.location.to_string())
+            .try_collect()
+            .await?)
+    }
+
+    async fn writer(&self, key: &str) -> Result<Box<dyn AsyncWrite + Unpin + Send>> {
+        // parts are uploaded as the writer fills up, and the upload is completed on shutdown
+        let (_, writer) = self.store.put_multipart(&key.into()).await?;
+        Ok(writer)
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<bool> {
+        match self.store.rename(&from.into(), &to.into()).await {
+            Ok(()) => Ok(true),
+            Err(object_store::Error::NotFound { .. }) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// A storage url split into its scheme, bucket (or container), path, and query options
+#[derive(Debug, PartialEq, Eq)]
+struct StorageUrl {
+    scheme: String,
+    bucket: String,
+    path: String,
+    options: HashMap<String, String>,
+}
+
+impl StorageUrl {
+    fn parse(url: &str) -> Result<Self> {
+        let (url, query) = url.split_once('?').unwrap_or((url, ""));
+        let Some((scheme, rest)) = url.split_once("://") else {
+            // a bare path is a local directory
+            return Ok(Self {
+                scheme: "file".to_string(),
+                bucket: String::new(),
+                path: url.to_string(),
+                options: HashMap::new(),
+            });
+        };
+
+        let options = query
+            .split('&')
+            .filter(|s| !s.is_empty())
+            .map(|option| {
+                let (k, v) = option
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("invalid option '{}' in storage url", option))?;
+                Ok((k.to_string(), v.to_string()))
+            })
+            .collect::<Result<_>>()?;
+
+        let (bucket, path) = match scheme {
+            "file" => ("", rest),
+            _ => rest.split_once('/').unwrap_or((rest, "")),
+        };
+        if scheme != "file" && bucket.is_empty() {
+            bail!("storage url '{}' is missing a bucket", url);
+        }
+
+        Ok(Self {
+            scheme: scheme.to_string(),
+            bucket: bucket.to_string(),
+            path: match scheme {
+                "file" => path.to_string(),
+                _ => path.trim_matches('/').to_string(),
+            },
+            options,
+        })
+    }
+
+    // the environment variables that configure the store for this url, other than credentials
+    fn settings_env(&self) -> &'static [&'static str] {
+        match self.scheme.as_str() {
+            "s3" | "s3a" => &S3_SETTINGS_ENV,
+            "az" | "abfs" => &AZURE_SETTINGS_ENV,
+            _ => &[],
+        }
+    }
+
+    // returns the provider for this url, and the prefix that keys are relative to
+    fn provider(self) -> Result<(Arc<dyn StorageProvider>, String)> {
+        let provider: Arc<dyn StorageProvider> = match self.scheme.as_str() {
+            "file" => return Ok((Arc::new(LocalStorage::new(self.path)), String::new())),
+            "s3" | "s3a" => Arc::new(ObjectStorage::s3(&self.bucket, &self.options)?),
+            "gs" => Arc::new(ObjectStorage::gcs(&self.bucket)?),
+            "az" | "abfs" => Arc::new(ObjectStorage::azure(&self.bucket, &self.options)?),
+            scheme => bail!("unsupported storage scheme '{}'", scheme),
+        };
+        Ok((provider, self.path))
+    }
+}
+
+#[derive(Clone)]
+pub struct StorageClient {
+    provider: Arc<dyn StorageProvider>,
+    // keys are relative to this prefix, if it is not empty
+    prefix: String,
+}
+
+impl StorageClient {
+    /// Constructs the default client, configured by `CHECKPOINT_URL` or, failing that, by
+    /// `S3_BUCKET` and `S3_REGION`
+    pub fn new() -> Self {
+        Self::from_url(&Self::default_url()).unwrap_or_else(|e| panic!("{:?}", e))
+    }
+
+    /// Returns the client that stores checkpoints for the job
+    pub fn for_job(job_id: &str) -> Self {
+        match JOB_URLS.read().unwrap().get(job_id) {
+            Some(url) => Self::from_url(url)
+                .unwrap_or_else(|e| panic!("invalid storage for job {}: {:?}", job_id, e)),
+            None => Self::new(),
+        }
+    }
+
+    /// Sets the storage url for the job's checkpoints; jobs without one use the default storage
+    pub fn register_job(job_id: &str, url: Option<&str>) {
+        let mut urls = JOB_URLS.write().unwrap();
+        match url {
+            Some(url) => urls.insert(job_id.to_string(), url.to_string()),
+            None => urls.remove(job_id),
+        };
+    }
+
+    /// Checks that the url names a supported store, without connecting to it
+    pub fn validate_url(url: &str) -> Result<()> {
+        let url = StorageUrl::parse(url)?;
+        match url.scheme.as_str() {
+            "file" | "s3" | "s3a" | "gs" | "az" | "abfs" => Ok(()),
+            scheme => bail!("unsupported storage scheme '{}'", scheme),
+        }
+    }
+
+    /// Constructs a client whose keys are relative to the path of `url`
+    pub fn from_url(url: &str) -> Result<Self> {
+        let mut clients = CLIENTS.lock().unwrap();
+        if let Some(client) = clients.get(url) {
+            return Ok(client.clone());
+        }
+        let (provider, prefix) = StorageUrl::parse(url)?.provider()?;
+        let client = Self { provider, prefix };
+        clients.insert(url.to_string(), client.clone());
+        Ok(client)
+    }
+
+    /// Constructs a client for a destination given either as a storage url or as a local
+    /// directory, returning it along with the prefix that keys should be written under.
+    pub fn for_url(url: &str) -> (Self, String) {
+        let (provider, prefix) = StorageUrl::parse(url)
+            .and_then(|url| url.provider())
+            .unwrap_or_else(|e| panic!("invalid destination {}: {:?}", url, e));
+        (
+            Self {
+                provider,
+                prefix: String::new(),
+            },
+            prefix,
+        )
+    }
+
+    fn default_url() -> String {
+        if let Ok(url) = std::env::var(CHECKPOINT_URL_ENV) {
+            return url;
+        }
+        match (
+            std::env::var(S3_BUCKET_ENV).ok(),
+            std::env::var(S3_REGION_ENV).ok(),
+        ) {
+            (Some(bucket), Some(region)) if bucket != "local" => {
+                format!("s3://{}?region={}", bucket, region)
+            }
+            _ => DEFAULT_LOCAL_DIRECTORY.to_string(),
+        }
+    }
+
+    /// The environment that workers for the job need in order to find its storage: the url, and
+    /// settings like the endpoint and region for that kind of store. Credentials are not included
+    /// (see [`STORAGE_CREDENTIAL_ENV`]).
+    pub fn get_storage_environment_variables(job_id: &str) -> HashMap<String, String> {
+        let url = JOB_URLS
+            .read()
+            .unwrap()
+            .get(job_id)
+            .cloned()
+            .unwrap_or_else(Self::default_url);
+        if url == DEFAULT_LOCAL_DIRECTORY {
+            return HashMap::new();
+        }
+
+        let settings = StorageUrl::parse(&url)
+            .map(|u| u.settings_env())
+            .unwrap_or(&[]);
+        settings
+            .iter()
+            .filter_map(|k| Some((k.to_string(), std::env::var(k).ok()?)))
+            .chain(std::iter::once((CHECKPOINT_URL_ENV.to_string(), url)))
+            .collect()
+    }
+
+    fn key(&self, key: &str) -> String {
+        if self.prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}/{}", self.prefix, key)
+        }
+    }
+
+    // retries transient failures for up to two minutes; errors that retrying can't fix, like
+    // missing permissions, are returned immediately
+    async fn retry<T, F, Fut>(&self, op: &str, key: &str, mut f: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let policy = ExponentialBackoff {
+            initial_interval: Duration::from_millis(100),
+            max_interval: Duration::from_secs(10),
+            max_elapsed_time: Some(Duration::from_secs(120)),
+            ..Default::default()
+        };
+
+        backoff::future::retry_notify(
+            policy,
+            || {
+                let fut = f();
+                async move {
+                    fut.await.map_err(|e| match is_transient(&e) {
+                        true => backoff::Error::transient(e),
+                        false => backoff::Error::permanent(e),
+                    })
+                }
+            },
+            |e, delay: Duration| {
+                warn!("failed to {} {}, retrying in {:?}: {:?}", op, key, delay, e)
+            },
+        )
+        .await
+        .map_err(|e| e.context(format!("failed to {} {}", op, key)))
+    }
+
+    pub async fn write(&self, key: String, bytes: Vec<u8>) -> Result<()> {
+        let key = self.key(&key);
+        let bytes = Bytes::from(bytes);
+        self.retry("write", &key, || self.provider.put(&key, bytes.clone()))
+            .await
+    }
+
+    pub async fn remove(&self, key: String) -> Result<()> {
+        let key = self.key(&key);
+        self.retry("delete", &key, || self.provider.delete(&key))
+            .await
+    }
+
+    pub async fn get_bytes(&self, key: String) -> Result<Option<Vec<u8>>> {
+        let key = self.key(&key);
+        self.retry("read", &key, || self.provider.get(&key)).await
+    }
+
+    /// Returns a reader that streams the object at `key`, or `None` if there is no object there.
+    /// Only opening the reader is retried, not the reads through it.
+    pub async fn reader(&self, key: String) -> Result<Option<Box<dyn AsyncRead + Unpin + Send>>> {
+        let key = self.key(&key);
+        self.retry("open", &key, || self.provider.reader(&key))
+            .await
+    }
+
+    pub async fn get_range(&self, key: String, range: Range<usize>) -> Result<Bytes> {
+        let key = self.key(&key);
+        self.retry("read", &key, || {
+            self.provider.get_range(&key, range.clone())
+        })
+        .await
+    }
+
+    pub async fn size(&self, key: String) -> Result<Option<usize>> {
+        let key = self.key(&key);
+        self.retry("stat", &key, || self.provider.size(&key)).await
+    }
+
+    /// Returns a writer that streams an object to `key`, which is complete once the writer has
+    /// been shut down. Only opening the writer is retried, not the writes through it.
+    pub async fn writer(&self, key: String) -> Result<Box<dyn AsyncWrite + Unpin + Send>> {
+        let key = self.key(&key);
+        self.retry("open", &key, || self.provider.writer(&key))
+            .await
+    }
+
+    /// Moves the object at `from` to `to`, returning `false` if there is no object at `from`
+    pub async fn rename(&self, from: String, to: String) -> Result<bool> {
+        let from = self.key(&from);
+        let to = self.key(&to);
+        self.retry("rename", &from, || self.provider.rename(&from, &to))
+            .await
+    }
+
+    /// Returns the keys of all objects under `prefix`, in sorted order
+    pub async fn list(&self, prefix: String) -> Result<Vec<String>> {
+        let full_prefix = self.key(&prefix);
+        let mut keys: Vec<String> = self
+            .retry("list", &full_prefix, || self.provider.list(&full_prefix))
+            .await?
+            .into_iter()
+            .map(|key| match key.strip_prefix(&format!("{}/", self.prefix)) {
+                Some(relative) if !self.prefix.is_empty() => relative.to_string(),
+                _ => key,
+            })
+            .collect();
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+// whether an operation that failed with this error may succeed if it's tried again; requests that
+// were rejected by the store (other than for throttling or timeouts), invalid paths and local
+// permission errors are not retried
+fn is_transient(e: &anyhow::Error) -> bool {
+    for cause in e.chain() {
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            if let Some(status) = e.status() {
+                return status.is_server_error()
+                    || status == StatusCode::TOO_MANY_REQUESTS
+                    || status == StatusCode::REQUEST_TIMEOUT;
+            }
+        } else if let Some(e) = cause.downcast_ref::<object_store::Error>() {
+            if !matches!(
+                e,
+                object_store::Error::Generic { .. } | object_store::Error::JoinError { .. }
+            ) {
+                return false;
+            }
+        } else if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            if matches!(
+                e.kind(),
+                ErrorKind::PermissionDenied
+                    | ErrorKind::NotFound
+                    | ErrorKind::InvalidInput
+                    | ErrorKind::AlreadyExists
+            ) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+impl Default for StorageClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_transient, StorageClient, StorageUrl};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse_storage_url() {
+        let url = StorageUrl::parse("s3://bucket/some/prefix/?endpoint=http://minio:9000").unwrap();
+        assert_eq!(url.scheme, "s3");
+        assert_eq!(url.bucket, "bucket");
+        assert_eq!(url.path, "some/prefix");
+        assert_eq!(
+            url.options,
+            HashMap::from([("endpoint".to_string(), "http://minio:9000".to_string())])
+        );
+
+        let url = StorageUrl::parse("gs://bucket").unwrap();
+        assert_eq!((url.bucket.as_str(), url.path.as_str()), ("bucket", ""));
+
+        let url = StorageUrl::parse("/tmp/arroyo-data").unwrap();
+        assert_eq!(
+            (url.scheme.as_str(), url.path.as_str()),
+            ("file", "/tmp/arroyo-data")
+        );
+        let url = StorageUrl::parse("file:///tmp/arroyo-data").unwrap();
+        assert_eq!(
+            (url.scheme.as_str(), url.path.as_str()),
+            ("file", "/tmp/arroyo-data")
+        );
+
+        assert!(StorageUrl::parse("s3:///prefix").is_err());
+        assert!(StorageUrl::parse("s3://bucket?region").is_err());
+        assert!(StorageClient::validate_url("hdfs://cluster/path").is_err());
+        assert!(StorageClient::validate_url("az://container/path?account=arroyo").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_local_storage_with_prefix() {
+        let dir = std::env::temp_dir().join(format!("arroyo-storage-{}", rand::random::<u64>()));
+        let client = StorageClient::from_url(&format!("file://{}", dir.to_string_lossy())).unwrap();
+
+        client.write("a/1".to_string(), vec![1]).await.unwrap();
+        client.write("a/2".to_string(), vec![2]).await.unwrap();
+        client.write("b/1".to_string(), vec![3]).await.unwrap();
+
+        assert_eq!(
+            client.list("a/".to_string()).await.unwrap(),
+            vec!["a/1", "a/2"]
+        );
+        assert_eq!(
+            client.get_bytes("b/1".to_string()).await.unwrap(),
+            Some(vec![3])
+        );
+        client.remove("b/1".to_string()).await.unwrap();
+        client.remove("b/1".to_string()).await.unwrap();
+        assert_eq!(client.get_bytes("b/1".to_string()).await.unwrap(), None);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_storage_environment_excludes_credentials() {
+        std::env::set_var("AWS_ENDPOINT", "http://minio:9000");
+        std::env::set_var("AWS_SECRET_ACCESS_KEY", "secret");
+        StorageClient::register_job("env_test_job", Some("s3://bucket/prefix"));
+
+        let env = StorageClient::get_storage_environment_variables("env_test_job");
+        assert_eq!(env.get("CHECKPOINT_URL").unwrap(), "s3://bucket/prefix");
+        assert_eq!(env.get("AWS_ENDPOINT").unwrap(), "http://minio:9000");
+        assert!(!env.contains_key("AWS_SECRET_ACCESS_KEY"));
+
+        StorageClient::register_job("env_test_job", Some("gs://bucket"));
+        let env = StorageClient::get_storage_environment_variables("env_test_job");
+        assert!(!env.contains_key("AWS_ENDPOINT"));
+    }
+
+    #[test]
+    fn test_permanent_errors() {
+        let denied = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied");
+        assert!(!is_transient(&anyhow::Error::new(denied).context("write")));
+        let interrupted = std::io::Error::new(std::io::ErrorKind::Interrupted, "interrupted");
+        assert!(is_transient(&anyhow::Error::new(interrupted)));
+        let invalid = object_store::Error::NotImplemented;
+        assert!(!is_transient(&invalid.into()));
+    }
+}
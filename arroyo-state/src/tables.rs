@@ -1,11 +1,87 @@
 //use crate::parquet::ParquetBackend;
-use crate::{BackingStore, StateBackend};
-use arroyo_rpc::grpc::{CheckpointMetadata, TableDescriptor, TableType};
-use arroyo_types::{from_micros, Data, Key, TaskInfo};
-use std::collections::{BTreeMap, HashMap};
+use crate::spill::SpillStore;
+use crate::{hash_key, BackingStore, StateBackend, BINCODE_CONFIG};
+use anyhow::{anyhow, Result};
+use arroyo_rpc::grpc::{
+    CheckpointMetadata, StateTtl, TableDescriptor, TableType, TtlTimeDomain, TtlUpdateType,
+};
+use arroyo_types::{from_micros, to_micros, Data, Key, TaskInfo};
+use bincode::Decode;
+use lru::LruCache;
+use std::any::Any;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Duration, SystemTime};
 use tracing::warn;
 
+type QueriedValues = Vec<(Option<SystemTime>, Vec<u8>)>;
+
+/// A row of a table with its key and value already encoded, as it's written to a checkpoint
+pub struct EncodedRow {
+    pub timestamp: SystemTime,
+    pub key_hash: u64,
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+/// The type-erased cache of a table, which allows state queries to be answered without knowing
+/// the table's key and value types. Values that have been moved to the spill store are read from
+/// it without bringing them back into memory.
+pub trait TableCache: Send {
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    // decodes `key`, returning its hash and its current encoded values
+    fn query(
+        &self,
+        table: char,
+        key: &[u8],
+        spill: Option<&SpillStore>,
+    ) -> Result<(u64, QueriedValues)>;
+
+    // called when a checkpoint is taken, before `checkpoint_rows`
+    fn start_checkpoint(&mut self, _watermark: Option<SystemTime>) {}
+
+    // returns the rows that have to be written to the checkpoint being taken in addition to the
+    // writes made since the last one, at most `max` at a time, or none once all have been returned
+    fn checkpoint_rows(
+        &mut self,
+        _table: char,
+        _spill: Option<&SpillStore>,
+        _max: usize,
+    ) -> Vec<EncodedRow> {
+        vec![]
+    }
+}
+
+fn decode_key<K: Key>(key: &[u8]) -> Result<K> {
+    Ok(bincode::decode_from_slice(key, BINCODE_CONFIG)?.0)
+}
+
+fn spill_store_for(table: char, spill: Option<&SpillStore>) -> Result<&SpillStore> {
+    spill.ok_or_else(|| {
+        anyhow!(
+            "table {} has spilled data but the backend has no spill store",
+            table
+        )
+    })
+}
+
+// reads a spilled value without moving it out of the store, for readers that don't need it back in
+// memory
+fn read_spilled<T: Decode>(table: char, spill: Option<&SpillStore>, key: &[u8]) -> T {
+    spill_store_for(table, spill)
+        .and_then(|store| store.get(table, key))
+        .and_then(|value| {
+            value.ok_or_else(|| anyhow!("spilled data for table {} is missing", table))
+        })
+        .unwrap_or_else(|e| panic!("failed to read spilled data: {:?}", e))
+}
+
+fn encode_value<V: Data>(value: &V) -> Vec<u8> {
+    bincode::encode_to_vec(value, BINCODE_CONFIG).unwrap()
+}
+
 pub struct TimeKeyMap<'a, K: Key, V: Data, S: BackingStore> {
     table: char,
     parquet: &'a mut S,
@@ -20,7 +96,8 @@ impl<'a, K: Key, V: Data + PartialEq, S: BackingStore> TimeKeyMap<'a, K, V, S> {
             cache,
         }
     }
-    pub fn get(&self, timestamp: SystemTime, key: &mut K) -> Option<&V> {
+    pub fn get(&mut self, timestamp: SystemTime, key: &mut K) -> Option<&V> {
+        self.load(timestamp);
         let buffered_value = self
             .cache
             .buffered_values
@@ -51,7 +128,8 @@ impl<'a, K: Key, V: Data + PartialEq, S: BackingStore> TimeKeyMap<'a, K, V, S> {
             .insert(key, value);
     }
 
-    pub fn get_all_for_time(&self, timestamp: SystemTime) -> Vec<(&K, &V)> {
+    pub fn get_all_for_time(&mut self, timestamp: SystemTime) -> Vec<(&K, &V)> {
+        self.load(timestamp);
         match (
             self.cache.buffered_values.get(&timestamp),
             self.cache.persisted_values.get(&timestamp),
@@ -68,46 +146,50 @@ impl<'a, K: Key, V: Data + PartialEq, S: BackingStore> TimeKeyMap<'a, K, V, S> {
         }
     }
 
-    pub async fn get_all(&mut self) -> Vec<(SystemTime, &K, &V)> {
+    // reads every entry, with the times that have been spilled read from the spill store one at a
+    // time after those in memory, without bringing them back into memory
+    pub async fn get_all(&mut self) -> impl Iterator<Item = (SystemTime, Cow<'_, K>, Cow<'_, V>)> {
         if !self.cache.buffered_values.is_empty() {
             warn!("buffered values present, flushing them before returning all values");
             self.flush().await;
         }
-        self.cache
-            .persisted_values
-            .iter()
-            .flat_map(|(timestamp, map)| {
-                let tuples: Vec<_> = map
-                    .iter()
-                    .map(|(key, value)| (*timestamp, key, value))
-                    .collect();
-                tuples
-            })
-            .collect()
+        let table = self.table;
+        let store = self.parquet.spill_store();
+        let cache = &*self.cache;
+
+        let persisted = cache.persisted_values.iter().flat_map(|(timestamp, map)| {
+            map.iter()
+                .map(|(key, value)| (*timestamp, Cow::Borrowed(key), Cow::Borrowed(value)))
+        });
+        let spilled = cache.spilled.iter().flat_map(move |timestamp| {
+            let map: HashMap<K, V> = read_spilled(
+                table,
+                store,
+                &TimeKeyMapCache::<K, V>::spill_key(*timestamp),
+            );
+            map.into_iter()
+                .map(|(key, value)| (*timestamp, Cow::Owned(key), Cow::Owned(value)))
+        });
+        persisted.chain(spilled)
     }
 
     pub fn get_min_time(&self) -> Option<SystemTime> {
-        let persisted_time = self.cache.persisted_values.keys().min();
-        let buffered_time = self.cache.buffered_values.keys().min();
-        match (persisted_time, buffered_time) {
-            (None, None) => None,
-            (None, Some(time)) | (Some(time), None) => Some(*time),
-            (Some(persisted_time), Some(buffered_time)) => Some(*persisted_time.min(buffered_time)),
-        }
+        [
+            self.cache.persisted_values.keys().next(),
+            self.cache.buffered_values.keys().next(),
+            self.cache.spilled.iter().next(),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+        .cloned()
     }
 
     pub fn evict_all_before_watermark(&mut self, watermark: SystemTime) -> Vec<(K, V)> {
         let mut result = vec![];
-        loop {
-            let persisted_time = self.cache.persisted_values.keys().min();
-            let buffered_time = self.cache.buffered_values.keys().min();
-            let min_time = match (persisted_time, buffered_time) {
-                (None, None) => break,
-                (None, Some(time)) | (Some(time), None) => time,
-                (Some(persisted_time), Some(buffered_time)) => persisted_time.min(buffered_time),
-            };
-            if *min_time <= watermark {
-                result.append(&mut self.evict_for_timestamp(*min_time))
+        while let Some(min_time) = self.get_min_time() {
+            if min_time <= watermark {
+                result.append(&mut self.evict_for_timestamp(min_time))
             } else {
                 break;
             }
@@ -116,6 +198,8 @@ impl<'a, K: Key, V: Data + PartialEq, S: BackingStore> TimeKeyMap<'a, K, V, S> {
     }
 
     pub fn evict_for_timestamp(&mut self, timestamp: SystemTime) -> Vec<(K, V)> {
+        self.load(timestamp);
+        self.cache.recency.pop(&timestamp);
         match (
             self.cache.persisted_values.remove(&timestamp),
             self.cache.buffered_values.remove(&timestamp),
@@ -144,6 +228,7 @@ impl<'a, K: Key, V: Data + PartialEq, S: BackingStore> TimeKeyMap<'a, K, V, S> {
                 break;
             }
             let (time, mut values) = self.cache.buffered_values.pop_first().unwrap();
+            self.load(time);
             let persisted_map = self.cache.persisted_values.entry(time).or_default();
             let drained = values.drain();
             for (mut key, mut value) in drained {
@@ -158,12 +243,21 @@ impl<'a, K: Key, V: Data + PartialEq, S: BackingStore> TimeKeyMap<'a, K, V, S> {
                     .await;
                 persisted_map.insert(key, value);
             }
+            self.cache.recency.put(time, ());
+        }
+
+        if let Some(store) = self.parquet.spill_store() {
+            self.cache.spill_cold(self.table, store);
         }
     }
 
+    fn load(&mut self, timestamp: SystemTime) {
+        self.cache
+            .load(self.table, self.parquet.spill_store(), timestamp);
+    }
+
     pub async fn flush(&mut self) {
-        let Some(timestamp) = self.cache.buffered_values
-        .keys().max() else {
+        let Some(timestamp) = self.cache.buffered_values.keys().max() else {
             return;
         };
         self.flush_at_watermark(*timestamp).await;
@@ -172,9 +266,46 @@ impl<'a, K: Key, V: Data + PartialEq, S: BackingStore> TimeKeyMap<'a, K, V, S> {
 pub struct TimeKeyMapCache<K: Key, V: Data> {
     persisted_values: BTreeMap<SystemTime, HashMap<K, V>>,
     buffered_values: BTreeMap<SystemTime, HashMap<K, V>>,
+    // times whose persisted values have been moved to the backend's spill store
+    spilled: BTreeSet<SystemTime>,
+    recency: LruCache<SystemTime, ()>,
 }
 
 impl<K: Key, V: Data> TimeKeyMapCache<K, V> {
+    fn spill_key(timestamp: SystemTime) -> [u8; 8] {
+        to_micros(timestamp).to_be_bytes()
+    }
+
+    // brings a time that has been spilled back into memory, marking it as recently used
+    fn load(&mut self, table: char, store: Option<&SpillStore>, timestamp: SystemTime) {
+        if self.spilled.remove(&timestamp) {
+            let values: HashMap<K, V> = store
+                .expect("table has spilled data but the backend has no spill store")
+                .take(table, &Self::spill_key(timestamp))
+                .unwrap_or_else(|| panic!("spilled data for table {} is missing", table));
+            self.persisted_values.insert(timestamp, values);
+        }
+
+        if self.persisted_values.contains_key(&timestamp) {
+            self.recency.put(timestamp, ());
+        }
+    }
+
+    // moves the least recently used times to the spill store until the persisted values fit
+    // within its budget
+    fn spill_cold(&mut self, table: char, store: &SpillStore) {
+        let mut entries: usize = self.persisted_values.values().map(|m| m.len()).sum();
+        while entries > store.max_in_memory_entries() && self.recency.len() > 1 {
+            let (timestamp, _) = self.recency.pop_lru().unwrap();
+            let Some(values) = self.persisted_values.remove(&timestamp) else {
+                continue;
+            };
+            entries -= values.len();
+            store.put(table, &Self::spill_key(timestamp), &values);
+            self.spilled.insert(timestamp);
+        }
+    }
+
     pub async fn from_checkpoint<S: BackingStore>(
         backing_store: &S,
         _task_info: &TaskInfo,
@@ -182,31 +313,90 @@ impl<K: Key, V: Data> TimeKeyMapCache<K, V> {
         table_descriptor: &TableDescriptor,
         watermark: Option<SystemTime>,
     ) -> Self {
-        let mut persisted_values: BTreeMap<SystemTime, HashMap<K, V>> = BTreeMap::new();
         let min_valid_time = watermark.map_or(SystemTime::UNIX_EPOCH, |watermark| {
             watermark - Duration::from_micros(table_descriptor.retention_micros)
         });
-        for (timestamp, key, value) in backing_store.get_data_triples(table).await {
-            if timestamp < min_valid_time {
-                continue;
+
+        // the table is restored a part at a time so that it can be spilled as it's read
+        let store = backing_store.spill_store();
+        let mut cache = Self::default();
+        for part in 0..backing_store.restore_parts(table) {
+            for (timestamp, key, value) in backing_store.get_data_triples_part(table, part).await {
+                if timestamp < min_valid_time {
+                    continue;
+                }
+                cache.load(table, store, timestamp);
+                let map = cache.persisted_values.entry(timestamp).or_default();
+                match value {
+                    Some(value) => {
+                        map.insert(key, value);
+                    }
+                    None => {
+                        map.remove(&key);
+                    }
+                }
+                if map.is_empty() {
+                    cache.persisted_values.remove(&timestamp);
+                    cache.recency.pop(&timestamp);
+                } else {
+                    cache.recency.put(timestamp, ());
+                }
+            }
+
+            if let Some(store) = store {
+                cache.spill_cold(table, store);
             }
-            persisted_values
-                .entry(timestamp)
-                .or_default()
-                .insert(key, value);
         }
+        cache
+    }
+}
+impl<K: Key, V: Data> TableCache for TimeKeyMapCache<K, V> {
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 
-        Self {
-            persisted_values,
-            buffered_values: BTreeMap::default(),
+    fn query(
+        &self,
+        table: char,
+        key: &[u8],
+        spill: Option<&SpillStore>,
+    ) -> Result<(u64, QueriedValues)> {
+        let key: K = decode_key(key)?;
+
+        let mut values = BTreeMap::new();
+        for timestamp in &self.spilled {
+            let map: HashMap<K, V> = spill_store_for(table, spill)?
+                .get(table, &Self::spill_key(*timestamp))?
+                .ok_or_else(|| anyhow!("spilled data for table {} is missing", table))?;
+            if let Some(value) = map.get(&key) {
+                values.insert(*timestamp, encode_value(value));
+            }
+        }
+
+        // buffered values replace the persisted ones for the same time
+        for (timestamp, map) in self.persisted_values.iter().chain(&self.buffered_values) {
+            if let Some(value) = map.get(&key) {
+                values.insert(*timestamp, encode_value(value));
+            }
         }
+
+        Ok((
+            hash_key(&key),
+            values
+                .into_iter()
+                .map(|(timestamp, value)| (Some(timestamp), value))
+                .collect(),
+        ))
     }
 }
+
 impl<K: Key, V: Data> Default for TimeKeyMapCache<K, V> {
     fn default() -> Self {
         Self {
             persisted_values: BTreeMap::default(),
             buffered_values: BTreeMap::default(),
+            spilled: BTreeSet::default(),
+            recency: LruCache::unbounded(),
         }
     }
 }
@@ -230,6 +420,7 @@ impl<'a, K: Key, V: Data, S: BackingStore> KeyTimeMultiMap<'a, K, V, S> {
         }
     }
     pub async fn insert(&mut self, timestamp: SystemTime, mut key: K, mut value: V) {
+        self.load(&key);
         self.backing_store
             .write_data_triple(
                 self.table,
@@ -247,6 +438,11 @@ impl<'a, K: Key, V: Data, S: BackingStore> KeyTimeMultiMap<'a, K, V, S> {
             .entry(timestamp)
             .or_default()
             .push(value);
+        self.cache.entries += 1;
+
+        if let Some(store) = self.backing_store.spill_store() {
+            self.cache.spill_cold(self.table, store);
+        }
     }
 
     pub async fn get_time_range(
@@ -255,6 +451,7 @@ impl<'a, K: Key, V: Data, S: BackingStore> KeyTimeMultiMap<'a, K, V, S> {
         start: SystemTime,
         end: SystemTime,
     ) -> Vec<&V> {
+        self.load(key);
         let Some(key_map) = self.cache.values.get(key) else {
             return vec![];
         };
@@ -265,20 +462,70 @@ impl<'a, K: Key, V: Data, S: BackingStore> KeyTimeMultiMap<'a, K, V, S> {
     }
 
     pub async fn clear_time_range(&mut self, key: &mut K, start: SystemTime, end: SystemTime) {
+        self.load(key);
         if let Some(key_map) = self.cache.values.get_mut(key) {
             let times_to_remove = key_map.range(start..end);
             let times: Vec<_> = times_to_remove.map(|(time, _values)| *time).collect();
             for time in times {
-                key_map.remove(&time);
+                if let Some(values) = key_map.remove(&time) {
+                    self.cache.entries -= values.len();
+                }
+                self.backing_store
+                    .delete_data_triple(self.table, TableType::KeyTimeMultiMap, time, key)
+                    .await;
             }
         };
     }
+
+    fn load(&mut self, key: &K) {
+        self.cache
+            .load(self.table, self.backing_store.spill_store(), key);
+    }
 }
 
 pub struct KeyTimeMultiMapCache<K: Key, V: Data> {
     values: HashMap<K, BTreeMap<SystemTime, Vec<V>>>,
+    // number of values held in memory
+    entries: usize,
+    // keys whose values have been moved to the backend's spill store
+    spilled: HashSet<K>,
+    recency: LruCache<K, ()>,
 }
 impl<K: Key, V: Data> KeyTimeMultiMapCache<K, V> {
+    fn spill_key(key: &K) -> Vec<u8> {
+        bincode::encode_to_vec(key, BINCODE_CONFIG).unwrap()
+    }
+
+    // brings a key that has been spilled back into memory, marking it as recently used
+    fn load(&mut self, table: char, store: Option<&SpillStore>, key: &K) {
+        if self.spilled.remove(key) {
+            let values: BTreeMap<SystemTime, Vec<V>> = store
+                .expect("table has spilled data but the backend has no spill store")
+                .take(table, &Self::spill_key(key))
+                .unwrap_or_else(|| panic!("spilled data for table {} is missing", table));
+            self.entries += values.values().map(|v| v.len()).sum::<usize>();
+            self.values.insert(key.clone(), values);
+        }
+
+        if self.values.contains_key(key) {
+            self.recency.put(key.clone(), ());
+        }
+    }
+
+    // moves the least recently used keys to the spill store until the in-memory values fit
+    // within its budget
+    fn spill_cold(&mut self, table: char, store: &SpillStore) {
+        while self.entries > store.max_in_memory_entries() && self.recency.len() > 1 {
+            let (key, _) = self.recency.pop_lru().unwrap();
+            let Some(values) = self.values.remove(&key) else {
+                continue;
+            };
+            self.entries -= values.values().map(|v| v.len()).sum::<usize>();
+            store.put(table, &Self::spill_key(&key), &values);
+            self.spilled.insert(key);
+        }
+    }
+
     pub async fn from_checkpoint<S: BackingStore>(
         backing_store: &S,
         task_info: &TaskInfo,
@@ -286,7 +533,6 @@ impl<K: Key, V: Data> KeyTimeMultiMapCache<K, V> {
         table_descriptor: &TableDescriptor,
         checkpoint_metadata: &CheckpointMetadata,
     ) -> Self {
-        let mut values: HashMap<K, BTreeMap<SystemTime, Vec<V>>> = HashMap::new();
         // TODO: there may be a race here, as the initial checkpoint_metadata might get stale.
         // This is unlikely as this method is only called on start, but should probably be the domain of the backing store.
         let operator_metadata = StateBackend::load_operator_metadata(
@@ -295,6 +541,12 @@ impl<K: Key, V: Data> KeyTimeMultiMapCache<K, V> {
             checkpoint_metadata.epoch,
         )
         .await
+        .unwrap_or_else(|e| {
+            panic!(
+                "failed to load metadata for restoring from checkpoint: {:?}",
+                e
+            )
+        })
         .expect("expect metadata for restoring from checkpoint");
         let min_valid_time = operator_metadata
             .min_watermark
@@ -302,18 +554,84 @@ impl<K: Key, V: Data> KeyTimeMultiMapCache<K, V> {
                 from_micros(min_watermark - table_descriptor.retention_micros)
             });
 
-        for (timestamp, key, value) in backing_store.get_data_triples(table).await {
-            if timestamp < min_valid_time {
-                continue;
+        // the table is restored a part at a time so that it can be spilled as it's read
+        let store = backing_store.spill_store();
+        let mut cache = Self::default();
+        for part in 0..backing_store.restore_parts(table) {
+            for (timestamp, key, value) in backing_store.get_data_triples_part(table, part).await {
+                if timestamp < min_valid_time {
+                    continue;
+                }
+                cache.load(table, store, &key);
+                match value {
+                    Some(value) => {
+                        cache
+                            .values
+                            .entry(key.clone())
+                            .or_default()
+                            .entry(timestamp)
+                            .or_default()
+                            .push(value);
+                        cache.entries += 1;
+                        cache.recency.put(key, ());
+                    }
+                    None => {
+                        if let Some(values) = cache
+                            .values
+                            .get_mut(&key)
+                            .and_then(|key_map| key_map.remove(&timestamp))
+                        {
+                            cache.entries -= values.len();
+                        }
+                    }
+                }
+            }
+
+            if let Some(store) = store {
+                cache.spill_cold(table, store);
             }
-            values
-                .entry(key)
-                .or_default()
-                .entry(timestamp)
-                .or_default()
-                .push(value);
         }
-        Self { values }
+        cache
+    }
+}
+
+impl<K: Key, V: Data> TableCache for KeyTimeMultiMapCache<K, V> {
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn query(
+        &self,
+        table: char,
+        key: &[u8],
+        spill: Option<&SpillStore>,
+    ) -> Result<(u64, QueriedValues)> {
+        let key: K = decode_key(key)?;
+
+        let spilled: Option<BTreeMap<SystemTime, Vec<V>>> = if self.spilled.contains(&key) {
+            Some(
+                spill_store_for(table, spill)?
+                    .get(table, &Self::spill_key(&key))?
+                    .ok_or_else(|| anyhow!("spilled data for table {} is missing", table))?,
+            )
+        } else {
+            None
+        };
+
+        let values = self
+            .values
+            .get(&key)
+            .or(spilled.as_ref())
+            .into_iter()
+            .flatten()
+            .flat_map(|(timestamp, values)| {
+                values
+                    .iter()
+                    .map(|value| (Some(*timestamp), encode_value(value)))
+            })
+            .collect();
+
+        Ok((hash_key(&key), values))
     }
 }
 
@@ -321,6 +639,9 @@ impl<K: Key, V: Data> Default for KeyTimeMultiMapCache<K, V> {
     fn default() -> Self {
         Self {
             values: Default::default(),
+            entries: 0,
+            spilled: HashSet::new(),
+            recency: LruCache::unbounded(),
         }
     }
 }
@@ -344,38 +665,290 @@ impl<'a, K: Key, V: Data, S: BackingStore> GlobalKeyedState<'a, K, V, S> {
         }
     }
     pub async fn insert(&mut self, mut key: K, mut value: V) {
-        self.parquet
-            .write_key_value(self.table, &mut key, &mut value)
-            .await;
+        if self.cache.ttl.is_some() {
+            // tables with a ttl are written in full with each checkpoint, along with the time each
+            // entry was last updated
+            let timestamp = self.cache.now.map(to_micros).unwrap_or(0);
+            self.cache
+                .updated
+                .insert(key.clone(), AtomicU64::new(timestamp));
+        } else {
+            self.parquet
+                .write_key_value(self.table, &mut key, &mut value)
+                .await;
+        }
+        // any spilled value is replaced, and overwritten in the store if the key is spilled again
+        self.cache.spilled.remove(&key);
+        self.cache.recency.put(key.clone(), ());
         self.cache.values.insert(key, value);
+
+        if let Some(store) = self.parquet.spill_store() {
+            self.cache.spill_cold(self.table, store);
+        }
+    }
+
+    // reads every entry, with the values that have been spilled read from the spill store one at
+    // a time after those in memory, without bringing them back into memory
+    pub fn get_all(&mut self) -> impl Iterator<Item = Cow<'_, V>> {
+        let table = self.table;
+        let store = self.parquet.spill_store();
+        let cache = &*self.cache;
+        for key in cache.values.keys().chain(&cache.spilled) {
+            cache.touch(key);
+        }
+
+        let spilled = cache.spilled.iter().map(move |key| {
+            Cow::Owned(read_spilled(
+                table,
+                store,
+                &GlobalKeyedStateCache::<K, V>::spill_key(key),
+            ))
+        });
+        cache.values.values().map(Cow::Borrowed).chain(spilled)
     }
 
-    pub fn get_all(&mut self) -> Vec<&V> {
-        self.cache.values.values().collect()
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.load(key);
+        let value = self.cache.values.get(key);
+        if value.is_some() {
+            self.cache.touch(key);
+        }
+        value
     }
 
-    pub fn get(&self, key: &K) -> Option<&V> {
-        self.cache.values.get(key)
+    fn load(&mut self, key: &K) {
+        self.cache.load(self.table, self.parquet.spill_store(), key);
     }
 }
 
 pub struct GlobalKeyedStateCache<K: Key, V: Data> {
     values: HashMap<K, V>,
+    ttl: Option<StateTtl>,
+    // for tables with a ttl, when each entry was last updated in micros. Zero for entries updated
+    // before the first watermark of an event-time table.
+    updated: HashMap<K, AtomicU64>,
+    // the keys of a table with a ttl that have yet to be written to the checkpoint being taken
+    to_checkpoint: Vec<K>,
+    // the current time in the table's ttl time domain
+    now: Option<SystemTime>,
+    // keys whose values have been moved to the backend's spill store
+    spilled: HashSet<K>,
+    recency: LruCache<K, ()>,
 }
+
 impl<K: Key, V: Data> GlobalKeyedStateCache<K, V> {
-    pub async fn from_checkpoint<S: BackingStore>(backing_store: &S, table: char) -> Self {
-        let mut values = HashMap::new();
-        for (key, value) in backing_store.get_key_values(table).await {
-            values.insert(key, value);
+    pub fn new(ttl: Option<StateTtl>) -> Self {
+        Self {
+            values: HashMap::new(),
+            ttl,
+            updated: HashMap::new(),
+            to_checkpoint: vec![],
+            now: None,
+            spilled: HashSet::new(),
+            recency: LruCache::unbounded(),
+        }
+    }
+
+    fn spill_key(key: &K) -> Vec<u8> {
+        bincode::encode_to_vec(key, BINCODE_CONFIG).unwrap()
+    }
+
+    // brings a key that has been spilled back into memory, marking it as recently used
+    fn load(&mut self, table: char, store: Option<&SpillStore>, key: &K) {
+        if self.spilled.remove(key) {
+            let value: V = store
+                .expect("table has spilled data but the backend has no spill store")
+                .take(table, &Self::spill_key(key))
+                .unwrap_or_else(|| panic!("spilled data for table {} is missing", table));
+            self.values.insert(key.clone(), value);
+        }
+
+        if self.values.contains_key(key) {
+            self.recency.put(key.clone(), ());
+        }
+    }
+
+    // moves the least recently used keys to the spill store until the in-memory values fit
+    // within its budget; update times for tables with a ttl are always kept in memory
+    fn spill_cold(&mut self, table: char, store: &SpillStore) {
+        while self.values.len() > store.max_in_memory_entries() && self.recency.len() > 1 {
+            let (key, _) = self.recency.pop_lru().unwrap();
+            let Some(value) = self.values.remove(&key) else {
+                continue;
+            };
+            store.put(table, &Self::spill_key(&key), &value);
+            self.spilled.insert(key);
+        }
+    }
+
+    pub async fn from_checkpoint<S: BackingStore>(
+        backing_store: &S,
+        table: char,
+        ttl: Option<StateTtl>,
+    ) -> Self {
+        // the table is restored a part at a time so that it can be spilled as it's read
+        let store = backing_store.spill_store();
+        let mut cache = Self::new(ttl);
+        for part in 0..backing_store.restore_parts(table) {
+            for (timestamp, key, value) in backing_store.get_data_triples_part(table, part).await {
+                let timestamp = to_micros(timestamp);
+                // a key may have been written several times since the last checkpoint; keep the
+                // latest
+                let current = cache.updated.get(&key).map(|u| u.load(Ordering::Relaxed));
+                if cache.ttl.is_some() && current.is_some_and(|current| current > timestamp) {
+                    continue;
+                }
+
+                cache.load(table, store, &key);
+                match value {
+                    Some(value) => {
+                        if cache.ttl.is_some() {
+                            cache.updated.insert(key.clone(), AtomicU64::new(timestamp));
+                        }
+                        cache.recency.put(key.clone(), ());
+                        cache.values.insert(key, value);
+                    }
+                    // deletes only apply to the value written at the same time
+                    None if cache.ttl.is_none() || current == Some(timestamp) => {
+                        cache.updated.remove(&key);
+                        cache.recency.pop(&key);
+                        cache.values.remove(&key);
+                    }
+                    None => {}
+                }
+            }
+
+            if let Some(store) = store {
+                cache.spill_cold(table, store);
+            }
+        }
+        cache
+    }
+
+    // Drops the entries whose ttl has passed. `watermark` is the operator's current watermark,
+    // which is used as the time for tables with event-time ttls.
+    pub fn expire(&mut self, watermark: Option<SystemTime>) {
+        let Some(ttl) = &self.ttl else {
+            return;
+        };
+        self.now = match ttl.time_domain() {
+            TtlTimeDomain::EventTime => watermark,
+            TtlTimeDomain::ProcessingTime => Some(SystemTime::now()),
+        };
+        let Some(now) = self.now else {
+            return;
+        };
+        let now = to_micros(now);
+        let ttl = ttl.ttl_micros;
+
+        let expired: Vec<K> = self
+            .updated
+            .iter_mut()
+            .filter_map(|(key, updated)| {
+                let updated = updated.get_mut();
+                if *updated == 0 {
+                    *updated = now;
+                }
+                (now.saturating_sub(*updated) >= ttl).then(|| key.clone())
+            })
+            .collect();
+
+        // spilled values of expired keys are left in the store, where they're never read again
+        for key in expired {
+            self.updated.remove(&key);
+            self.values.remove(&key);
+            self.spilled.remove(&key);
+            self.recency.pop(&key);
+        }
+    }
+
+    fn touch(&self, key: &K) {
+        let Some(ttl) = &self.ttl else {
+            return;
+        };
+        if ttl.update_type() == TtlUpdateType::OnReadAndWrite {
+            if let Some(updated) = self.updated.get(key) {
+                updated.store(self.now.map(to_micros).unwrap_or(0), Ordering::Relaxed);
+            }
         }
-        Self { values }
+    }
+}
+
+impl<K: Key, V: Data> TableCache for GlobalKeyedStateCache<K, V> {
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn query(
+        &self,
+        table: char,
+        key: &[u8],
+        spill: Option<&SpillStore>,
+    ) -> Result<(u64, QueriedValues)> {
+        let key: K = decode_key(key)?;
+
+        let value = if self.spilled.contains(&key) {
+            let value: V = spill_store_for(table, spill)?
+                .get(table, &Self::spill_key(&key))?
+                .ok_or_else(|| anyhow!("spilled data for table {} is missing", table))?;
+            Some(encode_value(&value))
+        } else {
+            self.values.get(&key).map(encode_value)
+        };
+
+        Ok((
+            hash_key(&key),
+            value.map(|value| (None, value)).into_iter().collect(),
+        ))
+    }
+
+    // only the files of the latest checkpoint of a global table are kept, so tables with a ttl
+    // write all of their live entries to each checkpoint
+    fn start_checkpoint(&mut self, watermark: Option<SystemTime>) {
+        if self.ttl.is_some() {
+            self.expire(watermark);
+            self.to_checkpoint = self.updated.keys().cloned().collect();
+        }
+    }
+
+    fn checkpoint_rows(
+        &mut self,
+        table: char,
+        spill: Option<&SpillStore>,
+        max: usize,
+    ) -> Vec<EncodedRow> {
+        let rest = self.to_checkpoint.len().saturating_sub(max);
+        self.to_checkpoint
+            .split_off(rest)
+            .into_iter()
+            .map(|key| {
+                let value = match self.values.get(&key) {
+                    Some(value) => encode_value(value),
+                    None => {
+                        let value: V = spill_store_for(table, spill)
+                            .and_then(|store| store.get(table, &Self::spill_key(&key)))
+                            .and_then(|value| {
+                                value.ok_or_else(|| anyhow!("spilled data is missing"))
+                            })
+                            .unwrap_or_else(|e| {
+                                panic!("failed to checkpoint table {}: {:?}", table, e)
+                            });
+                        encode_value(&value)
+                    }
+                };
+                EncodedRow {
+                    timestamp: from_micros(self.updated[&key].load(Ordering::Relaxed)),
+                    key_hash: hash_key(&key),
+                    key: Self::spill_key(&key),
+                    value,
+                }
+            })
+            .collect()
     }
 }
 
 impl<K: Key, V: Data> Default for GlobalKeyedStateCache<K, V> {
     fn default() -> Self {
-        Self {
-            values: Default::default(),
-        }
+        Self::new(None)
     }
 }
@@ -40,9 +40,20 @@ pub const NODE_ID_ENV: &str = "NODE_ID_ENV";
 pub const WORKER_ID_ENV: &str = "WORKER_ID_ENV";
 pub const JOB_ID_ENV: &str = "JOB_ID_ENV";
 pub const RUN_ID_ENV: &str = "RUN_ID_ENV";
+pub const JOB_NAME_ENV: &str = "JOB_NAME_ENV";
 pub const REMOTE_COMPILER_ENDPOINT_ENV: &str = "REMOTE_COMPILER_ENDPOINT";
+pub const GIT_SHA_ENV: &str = "ARROYO_GIT_SHA";
 pub const NOMAD_ENDPOINT_ENV: &str = "NOMAD_ENDPOINT";
 pub const NOMAD_DC_ENV: &str = "NOMAD_DC";
+pub const K8S_API_URL_ENV: &str = "K8S_API_URL";
+pub const K8S_NAMESPACE_ENV: &str = "K8S_NAMESPACE";
+pub const K8S_WORKER_IMAGE_ENV: &str = "K8S_WORKER_IMAGE";
+pub const K8S_WORKER_SERVICE_ACCOUNT_ENV: &str = "K8S_WORKER_SERVICE_ACCOUNT";
+pub const K8S_WORKER_LABELS_ENV: &str = "K8S_WORKER_LABELS";
+pub const K8S_WORKER_SLOTS_ENV: &str = "K8S_WORKER_SLOTS";
+pub const K8S_WORKER_CPU_PER_SLOT_ENV: &str = "K8S_WORKER_CPU_PER_SLOT";
+pub const K8S_WORKER_MEMORY_PER_SLOT_ENV: &str = "K8S_WORKER_MEMORY_PER_SLOT";
+pub const K8S_WORKER_SECRET_ENV: &str = "K8S_WORKER_SECRET";
 
 pub const DATABASE_NAME_ENV: &str = "DATABASE_NAME";
 pub const DATABASE_HOST_ENV: &str = "DATABASE_HOST";
@@ -60,6 +71,30 @@ pub const API_ENDPOINT_ENV: &str = "API_ENDPOINT";
 
 pub const S3_REGION_ENV: &str = "S3_REGION";
 pub const S3_BUCKET_ENV: &str = "S3_BUCKET";
+// Where checkpoints are stored, as a file://, s3://, gs:// or az:// URL. Takes precedence over
+// S3_BUCKET and S3_REGION, and is overridden per job by the controller when starting workers
+pub const CHECKPOINT_URL_ENV: &str = "CHECKPOINT_URL";
+
+// Per-job state backend configuration, set by the controller when starting workers
+pub const STATE_BACKEND_ENV: &str = "STATE_BACKEND";
+pub const MAX_IN_MEMORY_STATE_ENTRIES_ENV: &str = "MAX_IN_MEMORY_STATE_ENTRIES";
+pub const STATE_SPILL_DIR_ENV: &str = "STATE_SPILL_DIR";
+
+// Per-job checkpointing configuration, set by the controller when starting workers
+pub const CHECKPOINT_MODE_ENV: &str = "CHECKPOINT_MODE";
+
+// Where each of the job's sources starts and stops reading, as JSON-encoded JobBounds, set by the
+// controller when starting workers
+pub const SOURCE_BOUNDS_ENV: &str = "SOURCE_BOUNDS";
+
+// The key of the program run by the interpreted worker in the job's storage, set by the controller
+// when starting workers
+pub const INTERPRETED_PROGRAM_ENV: &str = "ARROYO_INTERPRETED_PROGRAM";
+// Where schedulers fetch the interpreted worker binary from, as a file:// or s3:// URL. It must be
+// set for every scheduler but the process scheduler, which defaults to the
+// arroyo-interpreted-worker binary next to the controller. For nomad, this must be a URL that
+// serves the binary as `pipeline`
+pub const INTERPRETED_WORKER_URL_ENV: &str = "INTERPRETED_WORKER_URL";
 
 #[derive(Debug, Clone)]
 pub struct DatabaseConfig {
@@ -101,6 +136,16 @@ pub mod ports {
     pub const COMPILER_ADMIN: u16 = 9001;
 }
 
+/// The version of the worker code that pipelines are compiled against, taken from `ARROYO_GIT_SHA`
+/// at runtime or, failing that, at build time. Compiled pipelines are only cached across jobs when
+/// this is known.
+pub fn worker_version() -> Option<String> {
+    std::env::var(GIT_SHA_ENV)
+        .ok()
+        .or_else(|| option_env!("ARROYO_GIT_SHA").map(|sha| sha.to_string()))
+        .filter(|sha| !sha.is_empty())
+}
+
 pub fn grpc_port(service: &str, default: u16) -> u16 {
     service_port(service, default, GRPC_PORT_ENV)
 }
@@ -143,6 +188,310 @@ impl NodeId {
     }
 }
 
+/// How a job is restarted after it fails. Restart counts are reset once the job has been running
+/// healthily for a while.
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RestartPolicy {
+    /// restart after a fixed delay, failing the job after `max_restarts` consecutive restarts
+    FixedDelay {
+        delay_micros: u64,
+        max_restarts: u32,
+    },
+    /// restart after a delay that is multiplied with each consecutive restart up to a maximum,
+    /// varied randomly by up to `jitter` (a fraction of the delay) in either direction, without
+    /// exceeding the maximum
+    ExponentialBackoff {
+        initial_delay_micros: u64,
+        max_delay_micros: u64,
+        multiplier: f64,
+        jitter: f64,
+        max_restarts: Option<u32>,
+    },
+    /// restart after a fixed delay, failing the job once it has failed more than `max_failures`
+    /// times within `window_micros`
+    FailureRate {
+        max_failures: u32,
+        window_micros: u64,
+        delay_micros: u64,
+    },
+    /// always restart after a fixed delay
+    NeverFail { delay_micros: u64 },
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy::FixedDelay {
+            delay_micros: 0,
+            max_restarts: 10,
+        }
+    }
+}
+
+impl RestartPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            RestartPolicy::ExponentialBackoff {
+                initial_delay_micros,
+                max_delay_micros,
+                multiplier,
+                jitter,
+                ..
+            } => {
+                if max_delay_micros < initial_delay_micros {
+                    return Err("max_delay must be at least initial_delay".to_string());
+                }
+                if multiplier.is_nan() || *multiplier < 1.0 {
+                    return Err("multiplier must be at least 1".to_string());
+                }
+                if !(0.0..=1.0).contains(jitter) {
+                    return Err("jitter must be between 0 and 1".to_string());
+                }
+            }
+            RestartPolicy::FailureRate { window_micros, .. } => {
+                if *window_micros == 0 {
+                    return Err("window must be greater than 0".to_string());
+                }
+            }
+            RestartPolicy::FixedDelay { .. } | RestartPolicy::NeverFail { .. } => {}
+        }
+        Ok(())
+    }
+
+    /// The period over which failures are counted, for policies that limit the failure rate
+    pub fn failure_window(&self) -> Option<Duration> {
+        match self {
+            RestartPolicy::FailureRate { window_micros, .. } => {
+                Some(Duration::from_micros(*window_micros))
+            }
+            _ => None,
+        }
+    }
+
+    /// How long to wait before restarting a job that has just failed, or None if it should be
+    /// failed instead. `restarts` is the number of times the job has already been restarted since
+    /// it was last healthy, `failures_in_window` the number of failures (including this one) within
+    /// the failure window, and `random` a value in [0, 1) used to apply jitter.
+    pub fn restart_delay(
+        &self,
+        restarts: u32,
+        failures_in_window: u32,
+        random: f64,
+    ) -> Option<Duration> {
+        match *self {
+            RestartPolicy::FixedDelay {
+                delay_micros,
+                max_restarts,
+            } => (restarts < max_restarts).then(|| Duration::from_micros(delay_micros)),
+            RestartPolicy::ExponentialBackoff {
+                initial_delay_micros,
+                max_delay_micros,
+                multiplier,
+                jitter,
+                max_restarts,
+            } => {
+                if max_restarts.map(|max| restarts >= max).unwrap_or(false) {
+                    return None;
+                }
+
+                let delay = (initial_delay_micros as f64 * multiplier.powi(restarts as i32))
+                    .min(max_delay_micros as f64);
+                let delay = (delay * (1.0 + jitter * (2.0 * random - 1.0)))
+                    .clamp(0.0, max_delay_micros as f64);
+                Some(Duration::from_micros(delay as u64))
+            }
+            RestartPolicy::FailureRate {
+                max_failures,
+                delay_micros,
+                ..
+            } => (failures_in_window <= max_failures).then(|| Duration::from_micros(delay_micros)),
+            RestartPolicy::NeverFail { delay_micros } => Some(Duration::from_micros(delay_micros)),
+        }
+    }
+}
+
+/// Configures the controller to rescale a job's operators as their load changes. Operators busier
+/// than `scale_up_utilization` or less busy than `scale_down_utilization` are rescaled so that they
+/// run at `target_utilization`; sources are scaled up when they fall behind by more than
+/// `max_source_lag_micros`.
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AutoscalingConfig {
+    pub enabled: bool,
+    pub min_parallelism: u32,
+    pub max_parallelism: u32,
+    pub target_utilization: f64,
+    pub scale_up_utilization: f64,
+    pub scale_down_utilization: f64,
+    pub max_source_lag_micros: u64,
+    // how long an operator must need rescaling before it's rescaled
+    pub stabilization_micros: u64,
+    // how long after the job starts (or is rescaled) before it may be rescaled
+    pub cooldown_micros: u64,
+}
+
+impl AutoscalingConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.min_parallelism == 0 || self.min_parallelism > self.max_parallelism {
+            return Err(
+                "min_parallelism must be at least 1 and no more than max_parallelism".to_string(),
+            );
+        }
+
+        if !(0.0 < self.scale_down_utilization
+            && self.scale_down_utilization < self.target_utilization
+            && self.target_utilization < self.scale_up_utilization
+            && self.scale_up_utilization <= 1.0)
+        {
+            return Err(
+                "utilizations must satisfy 0 < scale_down < target < scale_up <= 1".to_string(),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// The CPU and memory a subtask, slot or node requires or provides. Zero means that nothing has
+/// been declared, so that the dimension isn't considered when scheduling.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ResourceProfile {
+    pub cpu_millis: u64,
+    pub memory_mb: u64,
+}
+
+impl ResourceProfile {
+    pub fn new(cpu_millis: u64, memory_mb: u64) -> Self {
+        Self {
+            cpu_millis,
+            memory_mb,
+        }
+    }
+
+    pub fn add(&self, other: &ResourceProfile) -> ResourceProfile {
+        ResourceProfile {
+            cpu_millis: self.cpu_millis + other.cpu_millis,
+            memory_mb: self.memory_mb + other.memory_mb,
+        }
+    }
+
+    pub fn sub(&self, other: &ResourceProfile) -> ResourceProfile {
+        ResourceProfile {
+            cpu_millis: self.cpu_millis.saturating_sub(other.cpu_millis),
+            memory_mb: self.memory_mb.saturating_sub(other.memory_mb),
+        }
+    }
+}
+
+impl std::fmt::Display for ResourceProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}m CPU, {}MB memory", self.cpu_millis, self.memory_mb)
+    }
+}
+
+/// The resources a job's subtasks require: `subtask` for each subtask, unless its operator has
+/// its own profile in `operators`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct JobResources {
+    pub subtask: ResourceProfile,
+    #[serde(default)]
+    pub operators: HashMap<String, ResourceProfile>,
+}
+
+impl JobResources {
+    pub fn for_operator(&self, operator_id: &str) -> ResourceProfile {
+        self.operators
+            .get(operator_id)
+            .copied()
+            .unwrap_or(self.subtask)
+    }
+}
+
+/// A position in the input of a source that reads from a partitioned log, like Kafka
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SourcePosition {
+    // by partition; partitions that aren't listed aren't bounded
+    Offsets { offsets: HashMap<i32, i64> },
+    // the first record with a timestamp at or after this time
+    Timestamp { micros: u64 },
+    // the end of the input at the time the source starts
+    Latest,
+}
+
+/// Where a source starts reading when it has no state to restore from, and the position before
+/// which it stops. A source that reaches its end emits a final watermark, so that all of the
+/// job's windows are flushed, and waits for the job to finish.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SourceBounds {
+    pub start: Option<SourcePosition>,
+    pub end: Option<SourcePosition>,
+}
+
+/// The bounds of a job's sources: `sources` for each source, unless its operator has bounds of
+/// its own in `operators`. A job whose sources are all bounded finishes once they've all reached
+/// their ends, taking a final checkpoint.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct JobBounds {
+    #[serde(default)]
+    pub sources: SourceBounds,
+    #[serde(default)]
+    pub operators: HashMap<String, SourceBounds>,
+}
+
+impl JobBounds {
+    pub fn for_operator(&self, operator_id: &str) -> SourceBounds {
+        self.operators
+            .get(operator_id)
+            .cloned()
+            .unwrap_or_else(|| self.sources.clone())
+    }
+}
+
+/// When a job runs, at a fixed interval (calendar schedules like cron expressions aren't
+/// supported): first at `start_micros` (or as soon as it's created), and then, if it has an
+/// interval, at every following multiple of `interval_micros` after `start_micros`, once the
+/// previous run has finished. Runs that would start at or after `stop_micros` aren't started, and
+/// a run that's still going then is stopped with a final checkpoint.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct JobSchedule {
+    pub start_micros: Option<u64>,
+    pub interval_micros: Option<u64>,
+    pub stop_micros: Option<u64>,
+}
+
+impl JobSchedule {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.interval_micros == Some(0) {
+            return Err("interval must be greater than 0".to_string());
+        }
+
+        if let (Some(start), Some(stop)) = (self.start_micros, self.stop_micros) {
+            if stop <= start {
+                return Err("stop time must be after the start time".to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// When the next run should start, given when the last one started, or None if the schedule
+    /// has no more runs
+    pub fn next_run(&self, last_start_micros: Option<u64>) -> Option<u64> {
+        let start = self.start_micros.unwrap_or(0);
+        let next = match (last_start_micros, self.interval_micros) {
+            (None, _) => start,
+            (Some(last), _) if last < start => start,
+            (Some(_), None) | (Some(_), Some(0)) => return None,
+            (Some(last), Some(interval)) => start + ((last - start) / interval + 1) * interval,
+        };
+
+        match self.stop_micros {
+            Some(stop) if next >= stop => None,
+            _ => Some(next),
+        }
+    }
+}
+
 pub fn to_millis(time: SystemTime) -> u64 {
     time.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
 }
@@ -254,6 +603,23 @@ impl TaskInfo {
     }
 }
 
+// the range of key hashes handled by subtask `i` of an operator with parallelism `n`
+pub fn range_for_server(i: usize, n: usize) -> RangeInclusive<u64> {
+    let range_size = u64::MAX / (n as u64);
+    let start = range_size * (i as u64);
+    let end = if i + 1 == n {
+        u64::MAX
+    } else {
+        start + range_size - 1
+    };
+    start..=end
+}
+
+pub fn server_for_hash(x: u64, n: usize) -> usize {
+    let range_size = u64::MAX / (n as u64);
+    (n - 1).min((x / range_size) as usize)
+}
+
 pub fn get_test_task_info() -> TaskInfo {
     TaskInfo {
         job_id: "instance-1".to_string(),
@@ -367,3 +733,201 @@ pub struct CheckpointBarrier {
     pub timestamp: SystemTime,
     pub then_stop: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{JobSchedule, RestartPolicy};
+    use std::time::Duration;
+
+    fn backoff(jitter: f64, max_restarts: Option<u32>) -> RestartPolicy {
+        RestartPolicy::ExponentialBackoff {
+            initial_delay_micros: 1_000,
+            max_delay_micros: 10_000,
+            multiplier: 2.0,
+            jitter,
+            max_restarts,
+        }
+    }
+
+    #[test]
+    fn test_restart_delay() {
+        let fixed = RestartPolicy::FixedDelay {
+            delay_micros: 500,
+            max_restarts: 2,
+        };
+        let failure_rate = RestartPolicy::FailureRate {
+            max_failures: 2,
+            window_micros: 60_000_000,
+            delay_micros: 500,
+        };
+        let never_fail = RestartPolicy::NeverFail { delay_micros: 500 };
+
+        // (policy, restarts, failures in window, random, expected delay in micros)
+        let cases = [
+            (fixed, 0, 1, 0.5, Some(500)),
+            (fixed, 1, 1, 0.5, Some(500)),
+            (fixed, 2, 1, 0.5, None),
+            (backoff(0.0, None), 0, 1, 0.5, Some(1_000)),
+            (backoff(0.0, None), 1, 1, 0.5, Some(2_000)),
+            (backoff(0.0, None), 3, 1, 0.5, Some(8_000)),
+            // capped at max_delay
+            (backoff(0.0, None), 4, 1, 0.5, Some(10_000)),
+            (backoff(0.0, None), 1_000, 1, 0.5, Some(10_000)),
+            (backoff(0.0, Some(2)), 1, 1, 0.5, Some(2_000)),
+            (backoff(0.0, Some(2)), 2, 1, 0.5, None),
+            // jitter varies the delay by up to the given fraction in either direction
+            (backoff(0.5, None), 1, 1, 0.0, Some(1_000)),
+            (backoff(0.5, None), 1, 1, 0.5, Some(2_000)),
+            (backoff(0.5, None), 1, 1, 0.75, Some(2_500)),
+            // but never beyond max_delay
+            (backoff(0.5, None), 4, 1, 0.99, Some(10_000)),
+            (backoff(0.5, None), 4, 1, 0.0, Some(5_000)),
+            (failure_rate, 100, 1, 0.5, Some(500)),
+            (failure_rate, 100, 2, 0.5, Some(500)),
+            (failure_rate, 0, 3, 0.5, None),
+            (never_fail, 1_000, 1_000, 0.5, Some(500)),
+        ];
+
+        for (policy, restarts, failures, random, expected) in cases {
+            assert_eq!(
+                expected.map(Duration::from_micros),
+                policy.restart_delay(restarts, failures, random),
+                "{:?} after {} restarts and {} failures",
+                policy,
+                restarts,
+                failures
+            );
+        }
+    }
+
+    #[test]
+    fn test_jitter_bounds() {
+        let policy = backoff(0.25, None);
+        for restarts in 0..6 {
+            let base = policy.restart_delay(restarts, 1, 0.5).unwrap().as_micros() as f64;
+            for i in 0..100 {
+                let delay = policy
+                    .restart_delay(restarts, 1, i as f64 / 100.0)
+                    .unwrap()
+                    .as_micros() as f64;
+                assert!(delay >= base * 0.75 - 1.0, "{} < {}", delay, base * 0.75);
+                assert!(delay <= (base * 1.25).min(10_000.0), "{} > {}", delay, base);
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let exponential = |initial_delay_micros, max_delay_micros, multiplier, jitter| {
+            RestartPolicy::ExponentialBackoff {
+                initial_delay_micros,
+                max_delay_micros,
+                multiplier,
+                jitter,
+                max_restarts: None,
+            }
+        };
+
+        let cases = [
+            (RestartPolicy::default(), true),
+            (RestartPolicy::NeverFail { delay_micros: 0 }, true),
+            (exponential(1_000, 10_000, 2.0, 0.1), true),
+            (exponential(1_000, 1_000, 1.0, 0.0), true),
+            (exponential(1_000, 1_000, 1.0, 1.0), true),
+            (exponential(1_000, 999, 2.0, 0.1), false),
+            (exponential(1_000, 10_000, 0.5, 0.1), false),
+            (exponential(1_000, 10_000, f64::NAN, 0.1), false),
+            (exponential(1_000, 10_000, 2.0, -0.1), false),
+            (exponential(1_000, 10_000, 2.0, 1.5), false),
+            (
+                RestartPolicy::FailureRate {
+                    max_failures: 3,
+                    window_micros: 1,
+                    delay_micros: 0,
+                },
+                true,
+            ),
+            (
+                RestartPolicy::FailureRate {
+                    max_failures: 3,
+                    window_micros: 0,
+                    delay_micros: 0,
+                },
+                false,
+            ),
+        ];
+
+        for (policy, valid) in cases {
+            assert_eq!(valid, policy.validate().is_ok(), "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn test_next_run() {
+        let schedule = |start_micros, interval_micros, stop_micros| JobSchedule {
+            start_micros,
+            interval_micros,
+            stop_micros,
+        };
+
+        // (schedule, start of the last run, expected start of the next run)
+        let cases = [
+            // runs as soon as it's created
+            (schedule(None, None, None), None, Some(0)),
+            (schedule(None, None, None), Some(5), None),
+            // the start is in the future
+            (schedule(Some(1_000), None, None), None, Some(1_000)),
+            (schedule(Some(1_000), Some(100), None), None, Some(1_000)),
+            (
+                schedule(Some(1_000), Some(100), None),
+                Some(500),
+                Some(1_000),
+            ),
+            // every interval after the start
+            (
+                schedule(Some(1_000), Some(100), None),
+                Some(1_000),
+                Some(1_100),
+            ),
+            (schedule(None, Some(100), None), Some(0), Some(100)),
+            // a run that overran its slots is followed by the next slot, not the missed ones
+            (
+                schedule(Some(1_000), Some(100), None),
+                Some(1_050),
+                Some(1_100),
+            ),
+            (
+                schedule(Some(1_000), Some(100), None),
+                Some(1_350),
+                Some(1_400),
+            ),
+            // no runs start at or after the stop time
+            (
+                schedule(Some(1_000), Some(100), Some(1_200)),
+                Some(1_000),
+                Some(1_100),
+            ),
+            (
+                schedule(Some(1_000), Some(100), Some(1_200)),
+                Some(1_100),
+                None,
+            ),
+            (
+                schedule(Some(1_000), Some(100), Some(1_150)),
+                Some(1_100),
+                None,
+            ),
+            (schedule(Some(1_000), None, Some(1_000)), None, None),
+        ];
+
+        for (schedule, last_start, expected) in cases {
+            assert_eq!(
+                expected,
+                schedule.next_run(last_start),
+                "{:?} after a run at {:?}",
+                schedule,
+                last_start
+            );
+        }
+    }
+}
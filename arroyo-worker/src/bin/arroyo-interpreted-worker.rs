@@ -0,0 +1,24 @@
+use arroyo_datastream::Program;
+use arroyo_state::storage::StorageClient;
+use arroyo_types::INTERPRETED_PROGRAM_ENV;
+
+pub fn main() {
+    let key = std::env::var(INTERPRETED_PROGRAM_ENV)
+        .unwrap_or_else(|_| panic!("{} is not set", INTERPRETED_PROGRAM_ENV));
+
+    // the program is stored in the job's storage, which the worker is configured for
+    let bytes = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(StorageClient::new().get_bytes(key.clone()))
+        .unwrap_or_else(|e| panic!("failed to read program {}: {:?}", key, e))
+        .unwrap_or_else(|| panic!("program {} does not exist", key));
+    let program =
+        Program::decode(&bytes).unwrap_or_else(|e| panic!("invalid program in {}: {:?}", key, e));
+
+    let graph = arroyo_worker::interpreted::logical_graph(&program);
+    let hash: &'static str = Box::leak(program.get_hash().into_boxed_str());
+
+    arroyo_worker::WorkerServer::new(hash, graph)
+        .start()
+        .unwrap();
+}
@@ -1,14 +1,15 @@
 use std::fmt::{Debug, Formatter};
+use std::future::Future;
 use std::marker::PhantomData;
 
 use std::collections::{BTreeMap, HashMap};
-use std::ops::RangeInclusive;
 
 use std::any::Any;
 use std::process::exit;
+use std::sync::Arc;
 use std::{mem, thread};
 
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
 use arroyo_metrics::{counter_for_task, gauge_for_task};
 use arroyo_state::tables::TimeKeyMap;
@@ -21,12 +22,12 @@ use arroyo_rpc::grpc::controller_grpc_client::ControllerGrpcClient;
 use arroyo_rpc::grpc::{
     CheckpointMetadata, HeartbeatReq, TableDeleteBehavior, TableDescriptor, TableType,
     TableWriteBehavior, TaskAssignment, TaskCheckpointCompletedReq, TaskCheckpointEventReq,
-    TaskFailedReq, TaskFinishedReq, TaskStartedReq,
+    TaskFailedReq, TaskFinishedReq, TaskReachedEndReq, TaskStartedReq,
 };
 use arroyo_rpc::{ControlMessage, ControlResp};
 use arroyo_types::{
-    from_micros, to_micros, CheckpointBarrier, Data, Key, Message, Record, TaskInfo, WorkerId,
-    BYTES_RECV, BYTES_SENT, MESSAGES_RECV, MESSAGES_SENT,
+    from_micros, range_for_server, server_for_hash, to_micros, CheckpointBarrier, Data, Key,
+    Message, Record, TaskInfo, WorkerId, BYTES_RECV, BYTES_SENT, MESSAGES_RECV, MESSAGES_SENT,
 };
 use petgraph::graph::DiGraph;
 use petgraph::visit::EdgeRef;
@@ -36,15 +37,24 @@ use rand::Rng;
 use tokio::select;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::task::JoinHandle;
-use tonic::Request;
+use tonic::transport::Channel;
+use tonic::{Request, Response, Status};
 
+use crate::metrics::{take_task_loads, TaskLoadCounter};
 use crate::network_manager::{NetworkManager, Quad, Senders};
 use crate::TIMER_TABLE;
 use crate::{LogicalEdge, LogicalNode, METRICS_PUSH_INTERVAL, PROMETHEUS_PUSH_GATEWAY};
-use arroyo_state::{hash_key, BackingStore, StateBackend, StateStore};
+use arroyo_state::{
+    hash_key, in_flight_table, BackingStore, CheckpointMode, InFlightRecord, StateBackend,
+    StateStore,
+};
 
 const QUEUE_SIZE: usize = 4 * 1024;
 
+// how long the worker keeps retrying requests to the controller before giving up, which must be
+// long enough for a standby controller to be elected and adopt the job
+const CONTROLLER_FAILOVER_TIMEOUT: Duration = Duration::from_secs(60);
+
 #[derive(Debug)]
 pub enum QueueItem {
     Data(Box<dyn Any + Send>),
@@ -63,21 +73,6 @@ impl<K: Key, T: Data> From<QueueItem> for Message<K, T> {
         }
     }
 }
-fn range_for_server(i: usize, n: usize) -> RangeInclusive<u64> {
-    let range_size = u64::MAX / (n as u64);
-    let start = range_size * (i as u64);
-    let end = if i + 1 == n {
-        u64::MAX
-    } else {
-        start + range_size - 1
-    };
-    start..=end
-}
-
-fn server_for_hash(x: u64, n: usize) -> usize {
-    let range_size = u64::MAX / (n as u64);
-    (n - 1).min((x / range_size) as usize)
-}
 
 #[cfg(test)]
 mod tests {
@@ -115,6 +110,70 @@ mod tests {
             "u64::MAX is not in the correct range"
         );
     }
+
+    fn barrier(epoch: u32) -> CheckpointBarrier {
+        CheckpointBarrier {
+            epoch,
+            min_epoch: 0,
+            timestamp: SystemTime::now(),
+            then_stop: false,
+        }
+    }
+
+    #[test]
+    fn test_aligned_checkpoint_counter() {
+        let mut counter = CheckpointCounter::new(2, CheckpointMode::Aligned);
+
+        assert!(!counter.mark(0, &barrier(1)));
+        assert!(counter.is_blocked(0));
+        assert!(!counter.is_in_flight(1));
+
+        assert!(counter.mark(1, &barrier(1)));
+        assert!(counter.all_clear());
+        assert!(!counter.is_blocked(0));
+    }
+
+    #[test]
+    fn test_unaligned_checkpoint_counter() {
+        let task_info = TaskInfo::for_test("job", "op");
+        let mut counter = CheckpointCounter::new(2, CheckpointMode::Unaligned);
+        assert!(counter.is_unaligned());
+
+        assert!(!counter.mark(0, &barrier(1)));
+        assert!(!counter.is_blocked(0));
+        assert!(!counter.is_in_flight(0));
+        assert!(counter.is_in_flight(1));
+
+        let record = Record {
+            timestamp: SystemTime::UNIX_EPOCH,
+            key: Some(5u32),
+            value: "a".to_string(),
+        };
+        counter.capture(1, &record, &task_info);
+
+        // the next barrier on an input that's already delivered one waits for alignment
+        assert!(counter.defer(0, &barrier(2)));
+        assert!(counter.is_blocked(0));
+
+        assert!(counter.mark(1, &barrier(1)));
+        let in_flight = counter.take_in_flight();
+        assert_eq!(in_flight.len(), 1);
+        assert_eq!(in_flight[0].input, 1);
+        assert_eq!(in_flight[0].key_hash, hash_key(&5u32));
+
+        let deferred = counter.take_deferred();
+        assert_eq!(deferred.len(), 1);
+        assert_eq!(deferred[0].0, 0);
+        assert_eq!(deferred[0].1.epoch, 2);
+        assert!(!counter.is_blocked(0));
+        assert!(!counter.is_in_flight(1));
+    }
+
+    #[test]
+    fn test_single_input_is_never_unaligned() {
+        let counter = CheckpointCounter::new(1, CheckpointMode::Unaligned);
+        assert!(!counter.is_unaligned());
+    }
 }
 
 pub trait StreamNode: Send {
@@ -138,6 +197,7 @@ pub struct Context<K: Key, T: Data, S: BackingStore = StateBackend> {
     pub state: StateStore<S>,
     pub collector: Collector<K, T>,
     pub counters: HashMap<&'static str, IntCounter>,
+    pub load: Arc<TaskLoadCounter>,
     _ts: PhantomData<(K, T)>,
 }
 
@@ -182,6 +242,26 @@ pub struct Collector<K: Key, T: Data> {
     sent_bytes: Option<IntCounter>,
     sent_messages: Option<IntCounter>,
     tx_queue_size: Vec<Vec<Option<IntGauge>>>,
+    load: Arc<TaskLoadCounter>,
+}
+
+// sends to a queue, recording how full it is and how long we're blocked on it if it's full
+async fn send_to_queue(
+    q: &OutQueue,
+    message: Message<impl Key, impl Data>,
+    sent_bytes: &Option<IntCounter>,
+    load: &TaskLoadCounter,
+) {
+    let capacity = q.tx.capacity();
+    load.record_queue(capacity, q.tx.max_capacity());
+
+    if capacity == 0 {
+        let start = Instant::now();
+        q.send(message, sent_bytes).await;
+        load.record_backpressure(start.elapsed());
+    } else {
+        q.send(message, sent_bytes).await;
+    }
 }
 
 impl<K: Key, T: Data> Collector<K, T> {
@@ -206,9 +286,13 @@ impl<K: Key, T: Data> Collector<K, T> {
                 .iter()
                 .for_each(|g| g.set(self.out_qs[0][idx].tx.capacity() as i64));
 
-            self.out_qs[0][idx]
-                .send(Message::Record(record), &self.sent_bytes)
-                .await;
+            send_to_queue(
+                &self.out_qs[0][idx],
+                Message::Record(record),
+                &self.sent_bytes,
+                &self.load,
+            )
+            .await;
         } else {
             let key = record.key.clone();
             let message = Message::Record(record);
@@ -219,17 +303,25 @@ impl<K: Key, T: Data> Collector<K, T> {
                     .iter()
                     .for_each(|c| c.set(self.out_qs[i][idx].tx.capacity() as i64));
 
-                out_node_qs[idx]
-                    .send(message.clone(), &self.sent_bytes)
-                    .await;
+                send_to_queue(
+                    &out_node_qs[idx],
+                    message.clone(),
+                    &self.sent_bytes,
+                    &self.load,
+                )
+                .await;
             }
         }
     }
 
     pub async fn broadcast(&mut self, message: Message<K, T>) {
+        if let Message::Watermark(watermark) = &message {
+            self.load.record_watermark(*watermark);
+        }
+
         for out_node in &self.out_qs {
             for q in out_node {
-                q.send(message.clone(), &self.sent_bytes).await;
+                send_to_queue(q, message.clone(), &self.sent_bytes, &self.load).await;
             }
         }
     }
@@ -252,9 +344,17 @@ impl<K: Key, T: Data> Context<K, T> {
             delete_behavior: TableDeleteBehavior::None as i32,
             write_behavior: TableWriteBehavior::NoWritesBeforeWatermark as i32,
             retention_micros: 0,
+            schema: None,
+            ttl: None,
         });
+        tables.push(in_flight_table());
+
+        // operators that were added since the checkpoint was taken (e.g., when starting a modified
+        // pipeline from a savepoint) start without state
+        let restore_from =
+            restore_from.filter(|metadata| metadata.operator_ids.contains(&task_info.operator_id));
 
-        let (state, watermark) = if let Some(metadata) = restore_from {
+        let (mut state, watermark) = if let Some(metadata) = restore_from {
             let watermark = {
                 let metadata = StateBackend::load_operator_metadata(
                     &task_info.job_id,
@@ -263,6 +363,7 @@ impl<K: Key, T: Data> Context<K, T> {
                 )
                 .await;
                 metadata
+                    .unwrap_or_else(|e| panic!("failed to load operator metadata: {:?}", e))
                     .expect("require metadata")
                     .min_watermark
                     .map(from_micros)
@@ -283,6 +384,10 @@ impl<K: Key, T: Data> Context<K, T> {
             )
         };
 
+        if let Some(watermark) = watermark {
+            state.handle_watermark(watermark);
+        }
+
         let mut counters = HashMap::new();
 
         if let Some(c) = counter_for_task(
@@ -342,6 +447,8 @@ impl<K: Key, T: Data> Context<K, T> {
             })
             .collect();
 
+        let load = TaskLoadCounter::for_task(&task_info);
+
         Context {
             task_info,
             control_rx,
@@ -352,10 +459,12 @@ impl<K: Key, T: Data> Context<K, T> {
                 sent_messages: counters.remove(MESSAGES_SENT),
                 sent_bytes: counters.remove(BYTES_SENT),
                 tx_queue_size,
+                load: load.clone(),
                 _ts: PhantomData,
             },
             state,
             counters,
+            load,
             _ts: PhantomData,
         }
     }
@@ -449,24 +558,53 @@ pub struct TimerValue<K: Key, T: Decode + Encode + Clone + PartialEq + Eq> {
 pub struct CheckpointCounter {
     inputs: Vec<Option<u32>>,
     counter: Option<usize>,
+    unaligned: bool,
+    // records received after the snapshot of the in-progress unaligned checkpoint on inputs that
+    // haven't yet delivered its barrier
+    in_flight: Option<Vec<InFlightRecord>>,
+    // barriers of the next checkpoint that arrived before the current unaligned one was aligned
+    deferred: Vec<(usize, CheckpointBarrier)>,
 }
 
 impl CheckpointCounter {
-    pub fn new(size: usize) -> CheckpointCounter {
+    pub fn new(size: usize, mode: CheckpointMode) -> CheckpointCounter {
         CheckpointCounter {
             inputs: vec![None; size],
             counter: None,
+            // with a single input, there's nothing to wait for
+            unaligned: mode == CheckpointMode::Unaligned && size > 1,
+            in_flight: None,
+            deferred: vec![],
         }
     }
 
+    pub fn is_unaligned(&self) -> bool {
+        self.unaligned
+    }
+
     pub fn is_blocked(&self, idx: usize) -> bool {
-        self.inputs[idx].is_some()
+        if self.unaligned {
+            self.deferred.iter().any(|(i, _)| *i == idx)
+        } else {
+            self.inputs[idx].is_some()
+        }
     }
 
     pub fn all_clear(&self) -> bool {
         self.inputs.iter().all(|x| x.is_none())
     }
 
+    // Holds back a barrier received on an input that already delivered the barrier of the
+    // in-progress unaligned checkpoint, blocking that input until the checkpoint is aligned.
+    pub fn defer(&mut self, idx: usize, checkpoint: &CheckpointBarrier) -> bool {
+        if self.unaligned && self.inputs[idx].is_some() {
+            self.deferred.push((idx, *checkpoint));
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn mark(&mut self, idx: usize, checkpoint: &CheckpointBarrier) -> bool {
         assert!(self.inputs[idx].is_none());
 
@@ -474,6 +612,10 @@ impl CheckpointCounter {
             return true;
         }
 
+        if self.unaligned && !checkpoint.then_stop && self.all_clear() {
+            self.in_flight = Some(vec![]);
+        }
+
         self.inputs[idx] = Some(checkpoint.epoch);
         self.counter = match self.counter {
             None => Some(self.inputs.len() - 1),
@@ -488,6 +630,42 @@ impl CheckpointCounter {
 
         self.counter.is_none()
     }
+
+    // whether a record received on `idx` arrived after the snapshot of an unaligned checkpoint but
+    // before that input's barrier
+    pub fn is_in_flight(&self, idx: usize) -> bool {
+        self.in_flight.is_some() && self.inputs[idx].is_none()
+    }
+
+    pub fn capture<K: Key, T: Data>(
+        &mut self,
+        input: usize,
+        record: &Record<K, T>,
+        task_info: &TaskInfo,
+    ) {
+        let key_hash = match &record.key {
+            Some(key) => hash_key(key),
+            None => *task_info.key_range.start(),
+        };
+
+        self.in_flight
+            .as_mut()
+            .expect("no unaligned checkpoint in progress")
+            .push(InFlightRecord {
+                input,
+                key_hash,
+                timestamp: record.timestamp,
+                data: bincode::encode_to_vec(record, config::standard()).unwrap(),
+            });
+    }
+
+    pub fn take_in_flight(&mut self) -> Vec<InFlightRecord> {
+        self.in_flight.take().unwrap_or_default()
+    }
+
+    pub fn take_deferred(&mut self) -> Vec<(usize, CheckpointBarrier)> {
+        mem::take(&mut self.deferred)
+    }
 }
 
 pub struct SubtaskNode {
@@ -699,6 +877,30 @@ impl Program {
     }
 }
 
+// sends a request to the controller, retrying failures until the controller has been unreachable
+// for longer than CONTROLLER_FAILOVER_TIMEOUT
+async fn send_to_controller<Req: Clone, Resp, F, Fut>(
+    controller: &ControllerGrpcClient<Channel>,
+    req: Req,
+    f: F,
+) -> Result<Resp, Status>
+where
+    F: Fn(ControllerGrpcClient<Channel>, Request<Req>) -> Fut,
+    Fut: Future<Output = Result<Response<Resp>, Status>>,
+{
+    let start = Instant::now();
+    loop {
+        match f(controller.clone(), Request::new(req.clone())).await {
+            Ok(resp) => return Ok(resp.into_inner()),
+            Err(e) if start.elapsed() < CONTROLLER_FAILOVER_TIMEOUT => {
+                warn!("request to controller failed, retrying: {:?}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
 pub struct Engine {
     program: Program,
     worker_id: WorkerId,
@@ -745,6 +947,51 @@ impl RunningEngine {
             .collect()
     }
 
+    pub fn sink_controls(&self) -> Vec<Sender<ControlMessage>> {
+        self.program
+            .graph
+            .externals(Direction::Outgoing)
+            .filter(|idx| {
+                let w = self.program.graph.node_weight(*idx).unwrap();
+                self.assignments
+                    .get(&(w.id().to_string(), w.subtask_idx()))
+                    .unwrap()
+                    .worker_id
+                    == self.worker_id.0
+            })
+            .map(|idx| {
+                self.program
+                    .graph
+                    .node_weight(idx)
+                    .unwrap()
+                    .as_queue()
+                    .tx
+                    .clone()
+            })
+            .collect()
+    }
+
+    // the subtasks of the operator that are running on this worker
+    pub fn operator_controls(&self, operator_id: &str) -> Vec<(TaskInfo, Sender<ControlMessage>)> {
+        self.program
+            .graph
+            .node_weights()
+            .filter(|w| {
+                w.id() == operator_id
+                    && self
+                        .assignments
+                        .get(&(w.id().to_string(), w.subtask_idx()))
+                        .unwrap()
+                        .worker_id
+                        == self.worker_id.0
+            })
+            .map(|w| {
+                let queue = w.as_queue();
+                (queue.task_info.clone(), queue.tx.clone())
+            })
+            .collect()
+    }
+
     pub fn stop(&mut self) {
         self.shutdown_tx.send(true).unwrap();
     }
@@ -809,7 +1056,9 @@ impl Engine {
         //console_subscriber::init();
         let checkpoint_metadata = if let Some(epoch) = config.restore_epoch {
             info!("Restoring checkpoint for job {}", self.job_id);
-            StateBackend::load_checkpoint_metadata(&self.job_id, epoch).await
+            StateBackend::load_checkpoint_metadata(&self.job_id, epoch)
+                .await
+                .unwrap_or_else(|e| panic!("failed to load checkpoint {}: {:?}", epoch, e))
         } else {
             None
         };
@@ -999,6 +1248,16 @@ impl Engine {
                 for edge in self.program.graph.edges_directed(idx, Direction::Incoming) {
                     let source = self.program.graph.node_weight(edge.source()).unwrap();
 
+                    // data between two remote tasks doesn't pass through this worker
+                    let source_is_local = self
+                        .assignments
+                        .get(&(source.id().to_string(), source.subtask_idx()))
+                        .map(|a| a.worker_id == self.worker_id.0)
+                        .unwrap_or(false);
+                    if !source_is_local {
+                        continue;
+                    }
+
                     let quad = Quad {
                         src_id: edge.weight().in_logical_idx,
                         src_idx: source.subtask_idx(),
@@ -1031,72 +1290,94 @@ impl Engine {
         }
 
         tokio::spawn(async move {
+            let mut last_heartbeat = Instant::now();
             loop {
                 select! {
                     msg = control_rx.recv() => {
                         let err = match msg {
                             Some(ControlResp::CheckpointEvent(c)) => {
-                                if let Some(controller) = controller.as_mut() {
-                                    controller.task_checkpoint_event(Request::new(
-                                        TaskCheckpointEventReq {
-                                            worker_id: worker_id.0,
-                                            time: to_micros(c.time),
-                                            job_id: job_id.clone(),
-                                            operator_id: c.operator_id,
-                                            subtask_index: c.subtask_index,
-                                            epoch: c.checkpoint_epoch,
-                                            event_type: c.event_type as i32,
-                                        }
-                                    )).await.err()
+                                if let Some(controller) = &controller {
+                                    let req = TaskCheckpointEventReq {
+                                        worker_id: worker_id.0,
+                                        time: to_micros(c.time),
+                                        job_id: job_id.clone(),
+                                        operator_id: c.operator_id,
+                                        subtask_index: c.subtask_index,
+                                        epoch: c.checkpoint_epoch,
+                                        event_type: c.event_type as i32,
+                                    };
+                                    send_to_controller(controller, req, |mut c, r| async move {
+                                        c.task_checkpoint_event(r).await
+                                    }).await.err()
                                 } else {
                                     None
                                 }
                             }
                             Some(ControlResp::CheckpointCompleted(c)) => {
-                                if let Some(controller) = controller.as_mut() {
-                                    controller.task_checkpoint_completed(Request::new(
-                                        TaskCheckpointCompletedReq {
-                                            worker_id: worker_id.0,
-                                            time: c.subtask_metadata.finish_time,
-                                            job_id: job_id.clone(),
-                                            operator_id: c.operator_id,
-                                            epoch: c.checkpoint_epoch,
-                                            metadata: Some(c.subtask_metadata),
-                                        }
-                                    )).await.err()
+                                if let Some(controller) = &controller {
+                                    let req = TaskCheckpointCompletedReq {
+                                        worker_id: worker_id.0,
+                                        time: c.subtask_metadata.finish_time,
+                                        job_id: job_id.clone(),
+                                        operator_id: c.operator_id,
+                                        epoch: c.checkpoint_epoch,
+                                        metadata: Some(c.subtask_metadata),
+                                    };
+                                    send_to_controller(controller, req, |mut c, r| async move {
+                                        c.task_checkpoint_completed(r).await
+                                    }).await.err()
                                 } else {
                                     None
                                 }
                             }
                             Some(ControlResp::TaskFinished { operator_id, task_index }) => {
                                 info!(message = "Task finished", operator_id, task_index);
-                                if let Some(controller) = controller.as_mut() {
-                                    controller.task_finished(Request::new(
-                                        TaskFinishedReq {
-                                            worker_id: worker_id.0,
-                                            job_id: job_id.clone(),
-                                            time: to_micros(SystemTime::now()),
-                                            operator_id: operator_id.to_string(),
-                                            operator_subtask: task_index as u64,
-                                        }
-                                    )).await.err()
+                                if let Some(controller) = &controller {
+                                    let req = TaskFinishedReq {
+                                        worker_id: worker_id.0,
+                                        job_id: job_id.clone(),
+                                        time: to_micros(SystemTime::now()),
+                                        operator_id: operator_id.to_string(),
+                                        operator_subtask: task_index as u64,
+                                    };
+                                    send_to_controller(controller, req, |mut c, r| async move {
+                                        c.task_finished(r).await
+                                    }).await.err()
+                                } else {
+                                    None
+                                }
+                            }
+                            Some(ControlResp::TaskReachedEnd { operator_id, task_index }) => {
+                                info!(message = "Task reached end of input", operator_id, task_index);
+                                if let Some(controller) = &controller {
+                                    let req = TaskReachedEndReq {
+                                        worker_id: worker_id.0,
+                                        job_id: job_id.clone(),
+                                        time: to_micros(SystemTime::now()),
+                                        operator_id: operator_id.to_string(),
+                                        operator_subtask: task_index as u64,
+                                    };
+                                    send_to_controller(controller, req, |mut c, r| async move {
+                                        c.task_reached_end(r).await
+                                    }).await.err()
                                 } else {
                                     None
                                 }
                             }
                             Some(ControlResp::TaskFailed { operator_id, task_index, error }) => {
                                 info!(message = "Task failed", operator_id, task_index, error);
-                                if let Some(controller) = controller.as_mut() {
-                                    controller.task_failed(Request::new(
-                                        TaskFailedReq {
-                                            worker_id: worker_id.0,
-                                            job_id: job_id.clone(),
-                                            time: to_micros(SystemTime::now()),
-                                            operator_id: operator_id.to_string(),
-                                            operator_subtask: task_index as u64,
-                                            error,
-                                        }
-                                    )).await.err()
+                                if let Some(controller) = &controller {
+                                    let req = TaskFailedReq {
+                                        worker_id: worker_id.0,
+                                        job_id: job_id.clone(),
+                                        time: to_micros(SystemTime::now()),
+                                        operator_id: operator_id.to_string(),
+                                        operator_subtask: task_index as u64,
+                                        error,
+                                    };
+                                    send_to_controller(controller, req, |mut c, r| async move {
+                                        c.task_failed(r).await
+                                    }).await.err()
                                 } else {
                                     None
                                 }
@@ -1113,12 +1394,17 @@ impl Engine {
                         }
                     }
                     _ = tokio::time::sleep(Duration::from_secs(5)) => {
-                        if let Some(controller) = controller.as_mut() {
-                            let result = controller.heartbeat(Request::new(HeartbeatReq {
+                        if let Some(controller) = &controller {
+                            let req = HeartbeatReq {
                                 job_id: job_id.clone(),
                                 time: to_micros(SystemTime::now()),
                                 worker_id: worker_id.0,
-                            })).await;
+                                tasks: take_task_loads(last_heartbeat.elapsed()),
+                            };
+                            last_heartbeat = Instant::now();
+                            let result = send_to_controller(controller, req, |mut c, r| async move {
+                                c.heartbeat(r).await
+                            }).await;
                             if let Err(err) = result {
                                 error!("heartbeat failed {:?}", err);
                                 exit(1);
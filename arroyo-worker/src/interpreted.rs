@@ -0,0 +1,323 @@
+//! Builds the logical graph for programs made up of interpreted operators, so that they can be
+//! run by the precompiled `arroyo-interpreted-worker` binary instead of generated code.
+
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use arroyo_datastream::interpreted::{self, InterpretedOperator, Row, RowField, RowType, Value};
+use arroyo_datastream::{EdgeType, ImpulseSpec, Operator, Program, WatermarkType};
+use arroyo_types::{Data, Record};
+use bincode::{Decode, Encode};
+use petgraph::graph::DiGraph;
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use serde::ser::{SerializeMap, SerializeSeq};
+use serde::{Serialize, Serializer};
+
+use crate::engine::{StreamNode, SubtaskNode};
+use crate::operators::aggregating_window::AggregatingWindowFunc;
+use crate::operators::sinks::{ConsoleSink, GrpcSink, NullSink};
+use crate::operators::sources::nexmark::NexmarkSourceFunc;
+use crate::operators::sources::{self, ImpulseSourceFunc};
+use crate::operators::tumbling_aggregating_window::TumblingAggregatingWindowFunc;
+use crate::operators::{FilterOperator, MapOperator, PeriodicWatermarkGenerator};
+use crate::{LogicalEdge, LogicalNode};
+
+/// A row along with the names of its fields, which is what sinks receive
+#[derive(Clone, Encode, Decode, PartialEq)]
+pub struct NamedRow {
+    fields: Arc<Vec<RowField>>,
+    values: Row,
+}
+
+struct NamedValue<'a>(&'a RowType, &'a Value);
+
+impl Serialize for NamedValue<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match (self.0, self.1) {
+            (_, Value::Null) => serializer.serialize_none(),
+            (_, Value::Bool(b)) => serializer.serialize_bool(*b),
+            (_, Value::Int(i)) => serializer.serialize_i64(*i),
+            (_, Value::UInt(u)) => serializer.serialize_u64(*u),
+            (_, Value::Float(f)) => serializer.serialize_f64(*f),
+            (_, Value::String(s)) => serializer.serialize_str(s),
+            (_, Value::Bytes(b)) => {
+                let mut seq = serializer.serialize_seq(Some(b.len()))?;
+                for byte in b {
+                    seq.serialize_element(byte)?;
+                }
+                seq.end()
+            }
+            (_, Value::Timestamp(t)) => t.serialize(serializer),
+            (RowType::Struct(fields), Value::Struct(values)) => {
+                let mut map = serializer.serialize_map(Some(fields.len()))?;
+                for (field, value) in fields.iter().zip(values) {
+                    map.serialize_entry(&field.name, &NamedValue(&field.typ, value))?;
+                }
+                map.end()
+            }
+            (typ, value) => panic!("value {:?} does not match type {:?}", value, typ),
+        }
+    }
+}
+
+impl Serialize for NamedRow {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.fields.len()))?;
+        for (field, value) in self.fields.iter().zip(&self.values) {
+            map.serialize_entry(&field.name, &NamedValue(&field.typ, value))?;
+        }
+        map.end()
+    }
+}
+
+impl Debug for NamedRow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", serde_json::to_string(self).unwrap())
+    }
+}
+
+fn json_to_value(typ: &RowType, json: &serde_json::Value) -> Value {
+    if json.is_null() {
+        return Value::Null;
+    }
+
+    let value = match typ {
+        RowType::Bool => json.as_bool().map(Value::Bool),
+        RowType::Int => json
+            .as_i64()
+            .or_else(|| json.as_u64().map(|u| u as i64))
+            .map(Value::Int),
+        RowType::UInt => json.as_u64().map(Value::UInt),
+        RowType::Float => json.as_f64().map(Value::Float),
+        RowType::String => json.as_str().map(|s| Value::String(s.to_string())),
+        RowType::Bytes => json.as_array().map(|bytes| {
+            Value::Bytes(
+                bytes
+                    .iter()
+                    .map(|b| b.as_u64().unwrap_or_default() as u8)
+                    .collect(),
+            )
+        }),
+        RowType::Timestamp => {
+            let secs = json.get("secs_since_epoch").and_then(|s| s.as_u64());
+            let nanos = json.get("nanos_since_epoch").and_then(|n| n.as_u64());
+            secs.zip(nanos).map(|(secs, nanos)| {
+                Value::Timestamp(UNIX_EPOCH + Duration::new(secs, nanos as u32))
+            })
+        }
+        RowType::Struct(fields) => Some(Value::Struct(
+            fields
+                .iter()
+                .map(|field| {
+                    json.get(&field.name)
+                        .map(|v| json_to_value(&field.typ, v))
+                        .unwrap_or(Value::Null)
+                })
+                .collect(),
+        )),
+    };
+
+    value.unwrap_or_else(|| panic!("could not convert {} to {:?}", json, typ))
+}
+
+fn source_rows<T: Data + Serialize>(fields: &[RowField]) -> Box<dyn StreamNode> {
+    let typ = RowType::Struct(fields.to_vec());
+    Box::new(MapOperator::<(), T, (), Row> {
+        name: "source_rows".to_string(),
+        map_fn: Box::new(move |record, _| {
+            let json = serde_json::to_value(&record.value).unwrap();
+            let Value::Struct(values) = json_to_value(&typ, &json) else {
+                unreachable!()
+            };
+            Record {
+                timestamp: record.timestamp,
+                key: None,
+                value: values,
+            }
+        }),
+    })
+}
+
+fn window_value(timestamp: SystemTime, width: Duration) -> Value {
+    Value::Struct(vec![
+        Value::Timestamp(timestamp - width + Duration::from_nanos(1)),
+        Value::Timestamp(timestamp + Duration::from_nanos(1)),
+    ])
+}
+
+fn interpreted_node(op: &InterpretedOperator, input_type: &str) -> Box<dyn StreamNode> {
+    match op.clone() {
+        InterpretedOperator::SourceRows(fields) => match input_type {
+            "arroyo_types::ImpulseEvent" => source_rows::<arroyo_types::ImpulseEvent>(&fields),
+            "arroyo_types::nexmark::Event" => source_rows::<arroyo_types::nexmark::Event>(&fields),
+            t => panic!("cannot interpret records of type {}", t),
+        },
+        InterpretedOperator::Filter(predicate) => Box::new(FilterOperator::<(), Row> {
+            name: op.name().to_string(),
+            predicate_fn: Box::new(move |record, _| predicate.eval_predicate(&record.value)),
+        }),
+        InterpretedOperator::Project(exprs) => Box::new(MapOperator::<(), Row, (), Row> {
+            name: op.name().to_string(),
+            map_fn: Box::new(move |record, _| Record {
+                timestamp: record.timestamp,
+                key: None,
+                value: exprs.iter().map(|e| e.eval(&record.value)).collect(),
+            }),
+        }),
+        InterpretedOperator::KeyBy(exprs) => Box::new(MapOperator::<(), Row, Row, Row> {
+            name: op.name().to_string(),
+            map_fn: Box::new(move |record, _| Record {
+                timestamp: record.timestamp,
+                key: Some(exprs.iter().map(|e| e.eval(&record.value)).collect()),
+                value: record.value.clone(),
+            }),
+        }),
+        InterpretedOperator::TumblingAggregate { width, aggregates } => {
+            let merge_aggregates = aggregates.clone();
+            Box::new(TumblingAggregatingWindowFunc::<Row, Row, Row, Row>::new(
+                width,
+                move |bin| interpreted::bin_aggregate(&aggregates, bin),
+                move |row, bin| interpreted::bin_merge(&merge_aggregates, row, bin),
+            ))
+        }
+        InterpretedOperator::SlidingAggregate {
+            width,
+            slide,
+            aggregates,
+        } => {
+            let aggregates = Arc::new(aggregates);
+            let (merge, add, remove) = (aggregates.clone(), aggregates.clone(), aggregates.clone());
+            Box::new(AggregatingWindowFunc::<Row, Row, Row, Row, Row>::new(
+                width,
+                slide,
+                move |memory| interpreted::memory_aggregate(&aggregates, memory),
+                move |row, bin| interpreted::bin_merge(&merge, row, bin),
+                move |current, bin| interpreted::memory_add(&add, current, bin),
+                move |current, bin| interpreted::memory_remove(&remove, current, bin),
+            ))
+        }
+        InterpretedOperator::MergeAggregate {
+            window_index,
+            width,
+        } => Box::new(MapOperator::<Row, Row, (), Row> {
+            name: op.name().to_string(),
+            map_fn: Box::new(move |record, _| {
+                let mut value = record.key.clone().unwrap();
+                if let Some(index) = window_index {
+                    value.insert(index, window_value(record.timestamp, width));
+                }
+                value.extend(record.value.iter().cloned());
+                Record {
+                    timestamp: record.timestamp,
+                    key: None,
+                    value,
+                }
+            }),
+        }),
+        InterpretedOperator::SinkRows(fields) => {
+            let fields = Arc::new(fields);
+            Box::new(MapOperator::<(), Row, (), NamedRow> {
+                name: op.name().to_string(),
+                map_fn: Box::new(move |record, _| Record {
+                    timestamp: record.timestamp,
+                    key: None,
+                    value: NamedRow {
+                        fields: fields.clone(),
+                        values: record.value.clone(),
+                    },
+                }),
+            })
+        }
+    }
+}
+
+fn node(operator: &Operator, input_type: &str) -> Box<dyn StreamNode> {
+    match operator {
+        Operator::ImpulseSource {
+            start_time,
+            spec,
+            total_events,
+        } => {
+            let (interval, spec) = match spec {
+                ImpulseSpec::Delay(d) => (Some(*d), sources::ImpulseSpec::Delay(*d)),
+                ImpulseSpec::EventsPerSecond(eps) => {
+                    (None, sources::ImpulseSpec::EventsPerSecond(*eps))
+                }
+            };
+            Box::new(ImpulseSourceFunc::new(
+                interval,
+                spec,
+                total_events.unwrap_or(usize::MAX),
+                *start_time,
+            ))
+        }
+        Operator::NexmarkSource {
+            first_event_rate,
+            num_events,
+        } => Box::new(NexmarkSourceFunc::new(*first_event_rate, *num_events)),
+        Operator::Watermark(WatermarkType::Periodic {
+            period,
+            max_lateness,
+        }) => Box::new(PeriodicWatermarkGenerator::<(), Row>::new(
+            *period,
+            *max_lateness,
+        )),
+        Operator::ConsoleSink => Box::new(ConsoleSink::<(), NamedRow>::new()),
+        Operator::GrpcSink => Box::new(GrpcSink::<(), NamedRow>::new()),
+        Operator::NullSink => Box::new(NullSink::<(), NamedRow>::new()),
+        Operator::Interpreted(op) => interpreted_node(op, input_type),
+        op => panic!("{:?} cannot be run by the interpreted worker", op),
+    }
+}
+
+pub fn logical_graph(program: &Program) -> DiGraph<LogicalNode, LogicalEdge> {
+    let mut graph = DiGraph::new();
+
+    let indices: Vec<_> = program
+        .graph
+        .node_indices()
+        .map(|idx| {
+            let stream_node = &program.graph[idx];
+            let input_type = program
+                .graph
+                .edges_directed(idx, Direction::Incoming)
+                .next()
+                .map(|e| e.weight().value.clone())
+                .unwrap_or_default();
+
+            // fail on unsupported operators before the worker starts
+            node(&stream_node.operator, &input_type);
+
+            let id = stream_node.operator_id.clone();
+            let operator = stream_node.operator.clone();
+            graph.add_node(LogicalNode {
+                id: id.clone(),
+                description: format!("{:?}", stream_node),
+                create_fn: Arc::new(move |subtask_idx, parallelism| SubtaskNode {
+                    id: id.clone(),
+                    subtask_idx,
+                    parallelism,
+                    node: node(&operator, &input_type),
+                }),
+                initial_parallelism: stream_node.parallelism,
+            })
+        })
+        .collect();
+
+    for edge in program.graph.edge_references() {
+        let typ = match edge.weight().typ {
+            EdgeType::Forward => LogicalEdge::Forward,
+            EdgeType::Shuffle => LogicalEdge::Shuffle,
+            EdgeType::ShuffleJoin(order) => LogicalEdge::ShuffleJoin(order),
+        };
+        graph.add_edge(
+            indices[edge.source().index()],
+            indices[edge.target().index()],
+            typ,
+        );
+    }
+
+    graph
+}
@@ -8,12 +8,14 @@ use crate::network_manager::NetworkManager;
 use arroyo_rpc::grpc::controller_grpc_client::ControllerGrpcClient;
 use arroyo_rpc::grpc::worker_grpc_server::{WorkerGrpc, WorkerGrpcServer};
 use arroyo_rpc::grpc::{
-    CheckpointReq, CheckpointResp, JobFinishedReq, JobFinishedResp, RegisterWorkerReq,
-    StartExecutionReq, StartExecutionResp, StopExecutionReq, StopExecutionResp, WorkerResources,
+    CheckpointReq, CheckpointResp, CommitReq, CommitResp, JobFinishedReq, JobFinishedResp,
+    QueryStateReq, QueryStateResp, RegisterWorkerReq, StartExecutionReq, StartExecutionResp,
+    StateValue, StopExecutionReq, StopExecutionResp, WorkerResources,
 };
-use arroyo_rpc::ControlMessage;
+use arroyo_rpc::{ControlMessage, StateQueryResult};
 use arroyo_types::{
-    from_millis, ports, CheckpointBarrier, NodeId, WorkerId, JOB_ID_ENV, RUN_ID_ENV,
+    from_millis, ports, server_for_hash, to_micros, CheckpointBarrier, NodeId, WorkerId,
+    JOB_ID_ENV, JOB_NAME_ENV, RUN_ID_ENV,
 };
 use engine::RunningEngine;
 use lazy_static::lazy_static;
@@ -26,12 +28,15 @@ use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::net::TcpListener;
 use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
 use tokio_stream::wrappers::TcpListenerStream;
 use tonic::{Request, Response, Status};
-use tracing::info;
+use tracing::{debug, info};
 
 pub mod engine;
 mod inq_reader;
+pub mod interpreted;
+mod metrics;
 mod network_manager;
 pub mod operators;
 mod process_fn;
@@ -82,7 +87,7 @@ impl Display for LogicalEdge {
 pub struct LogicalNode {
     pub id: String,
     pub description: String,
-    pub create_fn: Box<fn(usize, usize) -> SubtaskNode>,
+    pub create_fn: Arc<dyn Fn(usize, usize) -> SubtaskNode + Send + Sync>,
     pub initial_parallelism: usize,
 }
 
@@ -100,9 +105,29 @@ impl Debug for LogicalNode {
 
 struct EngineState {
     sources: Vec<Sender<ControlMessage>>,
+    sinks: Vec<Sender<ControlMessage>>,
     running_engine: RunningEngine,
 }
 
+async fn query_subtask(
+    tx: &Sender<ControlMessage>,
+    table: char,
+    key: Vec<u8>,
+) -> Result<StateQueryResult, Status> {
+    let (respond, rx) = oneshot::channel();
+    tx.send(ControlMessage::QueryState {
+        table,
+        key,
+        respond,
+    })
+    .await
+    .map_err(|_| Status::unavailable("Subtask has finished"))?;
+
+    rx.await
+        .map_err(|_| Status::unavailable("Subtask did not answer the query"))?
+        .map_err(Status::failed_precondition)
+}
+
 pub struct LocalRunner {
     program: Program,
 }
@@ -131,7 +156,7 @@ pub struct WorkerServer {
     id: WorkerId,
     job_id: String,
     run_id: String,
-    name: &'static str,
+    name: String,
     hash: &'static str,
     controller_addr: String,
     logical: DiGraph<LogicalNode, LogicalEdge>,
@@ -140,11 +165,9 @@ pub struct WorkerServer {
 }
 
 impl WorkerServer {
-    pub fn new(
-        name: &'static str,
-        hash: &'static str,
-        logical: DiGraph<LogicalNode, LogicalEdge>,
-    ) -> Self {
+    /// The pipeline's name is read from the environment rather than compiled in, so that the same
+    /// binary can be shared by every pipeline running the same program
+    pub fn new(hash: &'static str, logical: DiGraph<LogicalNode, LogicalEdge>) -> Self {
         let controller_addr = std::env::var(arroyo_types::CONTROLLER_ADDR_ENV)
             .unwrap_or_else(|_| LOCAL_CONTROLLER_ADDR.clone());
 
@@ -155,6 +178,8 @@ impl WorkerServer {
         let run_id =
             std::env::var(RUN_ID_ENV).unwrap_or_else(|_| panic!("{} is not set", RUN_ID_ENV));
 
+        let name = std::env::var(JOB_NAME_ENV).unwrap_or_else(|_| job_id.clone());
+
         Self {
             id,
             name,
@@ -214,6 +239,7 @@ impl WorkerServer {
                     data_address,
                     resources: Some(WorkerResources {
                         slots: std::thread::available_parallelism().unwrap().get() as u64,
+                        ..Default::default()
                     }),
                     job_hash: hash.to_string(),
                     slots: slots as u64,
@@ -276,10 +302,12 @@ impl WorkerGrpc for WorkerServer {
         };
 
         let sources = engine.source_controls();
+        let sinks = engine.sink_controls();
 
         let mut state = self.state.lock().unwrap();
         *state = Some(EngineState {
             sources,
+            sinks,
             running_engine: engine,
         });
 
@@ -341,6 +369,95 @@ impl WorkerGrpc for WorkerServer {
         Ok(Response::new(StopExecutionResp {}))
     }
 
+    async fn commit(&self, request: Request<CommitReq>) -> Result<Response<CommitResp>, Status> {
+        let sinks = {
+            let state = self.state.lock().unwrap();
+
+            if let Some(state) = state.as_ref() {
+                state.sinks.clone()
+            } else {
+                return Err(Status::failed_precondition(
+                    "Worker has not yet started execution",
+                ));
+            }
+        };
+
+        let req = request.into_inner();
+        for s in sinks {
+            // sinks that have already finished no longer accept control messages
+            if s.send(ControlMessage::Commit { epoch: req.epoch })
+                .await
+                .is_err()
+            {
+                debug!(
+                    "Could not send commit for epoch {} to finished sink",
+                    req.epoch
+                );
+            }
+        }
+
+        Ok(Response::new(CommitResp {}))
+    }
+
+    async fn query_state(
+        &self,
+        request: Request<QueryStateReq>,
+    ) -> Result<Response<QueryStateResp>, Status> {
+        let req = request.into_inner();
+        let Some(table) = req.table.chars().next() else {
+            return Err(Status::invalid_argument("table must be set"));
+        };
+
+        let subtasks = {
+            let state = self.state.lock().unwrap();
+
+            if let Some(state) = state.as_ref() {
+                state.running_engine.operator_controls(&req.operator_id)
+            } else {
+                return Err(Status::failed_precondition(
+                    "Worker has not yet started execution",
+                ));
+            }
+        };
+
+        let Some((task_info, tx)) = subtasks.first() else {
+            return Err(Status::not_found(format!(
+                "No subtasks of operator {} are running on this worker",
+                req.operator_id
+            )));
+        };
+
+        // any subtask can hash the key, but only the one whose key range contains it has its
+        // values, so we forward the query there if it's running on this worker
+        let mut subtask_index = task_info.task_index;
+        let mut result = query_subtask(tx, table, req.key.clone()).await?;
+        if result.values.is_none() {
+            subtask_index = server_for_hash(result.key_hash, task_info.parallelism);
+            if let Some((owner, tx)) = subtasks
+                .iter()
+                .find(|(t, _)| t.key_range.contains(&result.key_hash))
+            {
+                subtask_index = owner.task_index;
+                result = query_subtask(tx, table, req.key).await?;
+            }
+        }
+
+        Ok(Response::new(QueryStateResp {
+            key_hash: result.key_hash,
+            subtask_index: subtask_index as u32,
+            owned: result.values.is_some(),
+            values: result
+                .values
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(timestamp, value)| StateValue {
+                    timestamp_micros: timestamp.map(to_micros),
+                    value,
+                })
+                .collect(),
+        }))
+    }
+
     async fn job_finished(
         &self,
         _request: Request<JobFinishedReq>,
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use arroyo_rpc::grpc::TaskLoad;
+use arroyo_types::{to_micros, TaskInfo};
+use lazy_static::lazy_static;
+
+// queue fullness is accumulated in parts per million
+const FULLNESS_SCALE: f64 = 1_000_000.0;
+
+lazy_static! {
+    static ref TASK_LOADS: Mutex<HashMap<(String, u32), Arc<TaskLoadCounter>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Measures how loaded a subtask is. The measurements are reported to the controller with each
+/// heartbeat, which uses them to decide whether the job should be rescaled.
+#[derive(Default)]
+pub struct TaskLoadCounter {
+    busy_nanos: AtomicU64,
+    backpressure_nanos: AtomicU64,
+    fullness: AtomicU64,
+    fullness_samples: AtomicU64,
+    watermark: AtomicU64,
+}
+
+impl TaskLoadCounter {
+    pub fn for_task(task_info: &TaskInfo) -> Arc<Self> {
+        TASK_LOADS
+            .lock()
+            .unwrap()
+            .entry((task_info.operator_id.clone(), task_info.task_index as u32))
+            .or_default()
+            .clone()
+    }
+
+    pub fn record_busy(&self, duration: Duration) {
+        self.busy_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_backpressure(&self, duration: Duration) {
+        self.backpressure_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_queue(&self, capacity: usize, max_capacity: usize) {
+        let fullness = 1.0 - capacity as f64 / max_capacity as f64;
+        self.fullness
+            .fetch_add((fullness * FULLNESS_SCALE) as u64, Ordering::Relaxed);
+        self.fullness_samples.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_watermark(&self, watermark: SystemTime) {
+        self.watermark
+            .fetch_max(to_micros(watermark), Ordering::Relaxed);
+    }
+
+    // the load since the last time this was called, `period` ago
+    fn take(&self, period: Duration) -> (f32, f32, f32) {
+        let period = period.as_nanos().max(1) as f64;
+        let busy = self.busy_nanos.swap(0, Ordering::Relaxed) as f64;
+        let backpressure = self.backpressure_nanos.swap(0, Ordering::Relaxed) as f64;
+        let fullness = self.fullness.swap(0, Ordering::Relaxed) as f64;
+        let samples = self.fullness_samples.swap(0, Ordering::Relaxed);
+
+        // time spent blocked on output queues while processing doesn't count as busy
+        let busy = ((busy - backpressure).max(0.0) / period).min(1.0);
+        let backpressure = (backpressure / period).min(1.0);
+        let fullness = if samples == 0 {
+            0.0
+        } else {
+            fullness / FULLNESS_SCALE / samples as f64
+        };
+
+        (busy as f32, backpressure as f32, fullness as f32)
+    }
+}
+
+/// The load of all of this worker's subtasks over the last `period`, resetting the measurements
+pub fn take_task_loads(period: Duration) -> Vec<TaskLoad> {
+    TASK_LOADS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|((operator_id, subtask_index), counter)| {
+            let (busy, backpressure, queue_fullness) = counter.take(period);
+            let watermark = counter.watermark.load(Ordering::Relaxed);
+            TaskLoad {
+                operator_id: operator_id.clone(),
+                subtask_index: *subtask_index,
+                busy,
+                backpressure,
+                queue_fullness,
+                watermark: (watermark > 0).then_some(watermark),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_load() {
+        let counter = TaskLoadCounter::default();
+        counter.record_busy(Duration::from_millis(600));
+        counter.record_backpressure(Duration::from_millis(200));
+        counter.record_queue(0, 10);
+        counter.record_queue(10, 10);
+
+        let (busy, backpressure, fullness) = counter.take(Duration::from_secs(1));
+        assert!((busy - 0.4).abs() < 1e-6);
+        assert!((backpressure - 0.2).abs() < 1e-6);
+        assert!((fullness - 0.5).abs() < 1e-6);
+
+        assert_eq!(counter.take(Duration::from_secs(1)), (0.0, 0.0, 0.0));
+    }
+}
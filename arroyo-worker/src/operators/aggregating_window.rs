@@ -15,10 +15,10 @@ use tracing::warn;
 pub struct AggregatingWindowFunc<K: Key, T: Data, BinA: Data, MemA: Data, OutT: Data> {
     width: Duration,
     slide: Duration,
-    aggregator: fn(&MemA) -> OutT,
-    bin_merger: fn(&T, Option<&BinA>) -> BinA,
-    in_memory_add: fn(Option<MemA>, BinA) -> MemA,
-    in_memory_remove: fn(MemA, BinA) -> Option<MemA>,
+    aggregator: Box<dyn Fn(&MemA) -> OutT + Send>,
+    bin_merger: Box<dyn Fn(&T, Option<&BinA>) -> BinA + Send>,
+    in_memory_add: Box<dyn Fn(Option<MemA>, BinA) -> MemA + Send>,
+    in_memory_remove: Box<dyn Fn(MemA, BinA) -> Option<MemA> + Send>,
     memory_view: HashMap<K, MemA>,
     state: SlidingWindowState,
 }
@@ -44,18 +44,18 @@ impl<K: Key, T: Data, BinA: Data, MemA: Data, OutT: Data>
     pub fn new(
         width: Duration,
         slide: Duration,
-        aggregator: fn(&MemA) -> OutT,
-        bin_merger: fn(&T, Option<&BinA>) -> BinA,
-        in_memory_add: fn(Option<MemA>, BinA) -> MemA,
-        in_memory_remove: fn(MemA, BinA) -> Option<MemA>,
+        aggregator: impl Fn(&MemA) -> OutT + Send + 'static,
+        bin_merger: impl Fn(&T, Option<&BinA>) -> BinA + Send + 'static,
+        in_memory_add: impl Fn(Option<MemA>, BinA) -> MemA + Send + 'static,
+        in_memory_remove: impl Fn(MemA, BinA) -> Option<MemA> + Send + 'static,
     ) -> Self {
         AggregatingWindowFunc {
             width,
             slide,
-            aggregator,
-            bin_merger,
-            in_memory_add,
-            in_memory_remove,
+            aggregator: Box::new(aggregator),
+            bin_merger: Box::new(bin_merger),
+            in_memory_add: Box::new(in_memory_add),
+            in_memory_remove: Box::new(in_memory_remove),
             memory_view: HashMap::new(),
             state: SlidingWindowState::NoData,
         }
@@ -75,6 +75,8 @@ impl<K: Key, T: Data, BinA: Data, MemA: Data, OutT: Data>
             delete_behavior: TableDeleteBehavior::NoReadsBeforeWatermark as i32,
             write_behavior: TableWriteBehavior::NoWritesBeforeWatermark as i32,
             retention_micros: self.width.as_micros() as u64,
+            schema: None,
+            ttl: None,
         }]
     }
 
@@ -107,7 +109,7 @@ impl<K: Key, T: Data, BinA: Data, MemA: Data, OutT: Data>
 
     async fn on_start(&mut self, ctx: &mut Context<K, OutT>) {
         let watermark = ctx.watermark();
-        let map = ctx.state.get_time_key_map::<K, BinA>('a', watermark).await;
+        let mut map = ctx.state.get_time_key_map::<K, BinA>('a', watermark).await;
 
         let Some(map_min_time) = map.get_min_time()  else {
             self.state = SlidingWindowState::NoData;
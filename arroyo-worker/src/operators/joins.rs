@@ -75,6 +75,8 @@ impl<K: Key, T1: Data, T2: Data, W1: TimeWindowAssigner<K, T1>, W2: TimeWindowAs
                     .safe_retention_duration()
                     .unwrap()
                     .as_micros() as u64,
+                schema: None,
+                ttl: None,
             },
             TableDescriptor {
                 name: "r".to_string(),
@@ -87,6 +89,8 @@ impl<K: Key, T1: Data, T2: Data, W1: TimeWindowAssigner<K, T1>, W2: TimeWindowAs
                     .safe_retention_duration()
                     .unwrap()
                     .as_micros() as u64,
+                schema: None,
+                ttl: None,
             },
         ]
     }
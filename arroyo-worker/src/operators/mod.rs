@@ -7,7 +7,7 @@ use std::ops::Add;
 
 use crate::engine::{Collector, Context, StreamNode};
 use arroyo_macro::process_fn;
-use arroyo_rpc::grpc::TableDescriptor;
+use arroyo_rpc::grpc::{TableDescriptor, TtlTimeDomain, TtlUpdateType};
 use arroyo_types::{
     from_millis, to_millis, CheckpointBarrier, Data, GlobalKey, Key, Message, Record, TaskInfo,
     Window,
@@ -35,7 +35,7 @@ mod test {
     use arroyo_types::{from_millis, to_millis, Message, Record};
     use std::time::{Duration, SystemTime};
 
-    use super::SlidingWindowAssigner;
+    use super::{DedupOperator, SlidingWindowAssigner};
 
     #[tokio::test]
     #[ignore]
@@ -75,6 +75,33 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    async fn test_dedup() {
+        let mut operator = DedupOperator::<String, u64>::new(None);
+        let (mut ctx, mut data_rx) = Context::new_for_test();
+
+        let ts = SystemTime::now();
+        for (key, value) in [("a", 1), ("a", 2), ("b", 3), ("a", 4)] {
+            let record = Record {
+                timestamp: ts,
+                key: Some(key.to_string()),
+                value,
+            };
+            operator.process_element(&record, &mut ctx).await;
+        }
+
+        let mut values = vec![];
+        while let Ok(item) = data_rx.try_recv() {
+            let message: Message<String, u64> = item.into();
+            match message {
+                Message::Record(record) => values.push((record.key.unwrap(), record.value)),
+                _ => unreachable!("received non-record variant"),
+            }
+        }
+
+        assert_eq!(vec![("a".to_string(), 1), ("b".to_string(), 3)], values);
+    }
+
     #[test]
     fn test_sliding_window_assignment() {
         let assigner = SlidingWindowAssigner {
@@ -133,7 +160,7 @@ impl<K: Key, D: Data> PeriodicWatermarkGenerator<K, D> {
     }
 
     async fn on_start(&mut self, ctx: &mut Context<K, D>) {
-        let gs = ctx.state.get_global_keyed_state('s').await;
+        let mut gs = ctx.state.get_global_keyed_state('s').await;
 
         let state =
             *(gs.get(&ctx.task_info.task_index)
@@ -679,6 +706,56 @@ impl<K: Key, V: Data> CountOperator<K, V> {
     }
 }
 
+#[derive(StreamNode)]
+pub struct DedupOperator<K: Key, T: Data> {
+    ttl: Option<Duration>,
+    _t: PhantomData<(K, T)>,
+}
+
+#[process_fn(in_k = K, in_t = T, out_k = K, out_t = T)]
+impl<K: Key, T: Data> DedupOperator<K, T> {
+    pub fn new(ttl: Option<Duration>) -> Self {
+        Self {
+            ttl,
+            _t: PhantomData,
+        }
+    }
+
+    fn name(&self) -> String {
+        "Dedup".to_string()
+    }
+
+    fn tables(&self) -> Vec<TableDescriptor> {
+        let description = "keys that have already been emitted";
+        vec![match self.ttl {
+            // reading a key keeps it alive, so a key is only emitted again once it has gone
+            // unseen for the whole ttl
+            Some(ttl) => arroyo_state::global_table_with_ttl(
+                "d",
+                description,
+                ttl,
+                TtlUpdateType::OnReadAndWrite,
+                TtlTimeDomain::ProcessingTime,
+            ),
+            None => arroyo_state::global_table("d", description),
+        }]
+    }
+
+    async fn process_element(&mut self, record: &Record<K, T>, ctx: &mut Context<K, T>) {
+        let key = record
+            .key
+            .clone()
+            .expect("records must be keyed to be deduplicated");
+        let mut seen = ctx.state.get_global_keyed_state::<K, ()>('d').await;
+        if seen.get(&key).is_some() {
+            return;
+        }
+        seen.insert(key, ()).await;
+
+        ctx.collector.collect(record.clone()).await;
+    }
+}
+
 #[derive(StreamNode)]
 pub struct AggregateFunctionOperator<InKey: Key, N: Data + Copy + Ord> {
     pub name: String,
@@ -0,0 +1,545 @@
+use crate::engine::{Context, StreamNode};
+use arrow::datatypes::{Schema, SchemaRef};
+use arrow::json::reader::{infer_json_schema_from_iterator, Decoder, DecoderOptions};
+use arroyo_macro::process_fn;
+use arroyo_rpc::grpc::TableDescriptor;
+use arroyo_rpc::ControlMessage;
+use arroyo_state::storage::StorageClient;
+use arroyo_types::*;
+use bincode::{Decode, Encode};
+use chrono::{DateTime, Utc};
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tracing::{info, warn};
+
+#[cfg(test)]
+mod test;
+
+// staged files live under this directory of the destination until they are committed; readers
+// conventionally skip paths starting with an underscore
+const STAGING_DIR: &str = "_staging";
+
+// how long a sink that is stopping with a final checkpoint waits for that checkpoint to complete
+const FINAL_COMMIT_TIMEOUT: Duration = Duration::from_secs(60);
+
+// rows are encoded and written out in batches of this many
+const BATCH_SIZE: usize = 1024;
+// parquet row groups are held in memory until they have this many rows
+const ROW_GROUP_SIZE: usize = 64 * 1024;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    Json,
+    Csv,
+    Parquet,
+}
+
+impl FileFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            FileFormat::Json => "json",
+            FileFormat::Csv => "csv",
+            FileFormat::Parquet => "parquet",
+        }
+    }
+
+    // csv and parquet are columnar, so each row needs to be an object
+    fn requires_objects(&self) -> bool {
+        *self != FileFormat::Json
+    }
+}
+
+// the arrow writers are synchronous, so they encode into this buffer, which is then drained into
+// the storage writer
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+enum Encoder {
+    Csv(arrow::csv::Writer<SharedBuffer>),
+    Parquet(ArrowWriter<SharedBuffer>),
+}
+
+// rows can be written with a file's schema if their own schema adds nothing to it; fields that are
+// null throughout a batch are missing from its inferred schema, and are written as nulls
+fn is_compatible(file_schema: &Schema, schema: &Schema) -> bool {
+    Schema::try_merge([file_schema.clone(), schema.clone()])
+        .map(|merged| &merged == file_schema)
+        .unwrap_or(false)
+}
+
+/// A file that has been written to the staging area and will be moved to its final location once
+/// the checkpoint for `epoch` completes
+#[derive(Clone, Debug, Encode, Decode, PartialEq, Eq)]
+pub(crate) struct PendingFile {
+    epoch: u32,
+    staged_key: String,
+    final_key: String,
+}
+
+#[derive(Clone, Debug, Encode, Decode, PartialEq, Eq)]
+pub struct FileSystemSinkState {
+    task_index: usize,
+    pending: Vec<PendingFile>,
+}
+
+/// A file that is being streamed to the staging area. Rows are buffered until there are
+/// `BATCH_SIZE` of them, then encoded and handed to the storage writer.
+struct InProgressFile {
+    path: String,
+    staged_key: String,
+    opened_at: Instant,
+    bytes: usize,
+    rows: Vec<Value>,
+    // for csv and parquet, the schema inferred from the first batch of rows
+    encoder: Option<(SchemaRef, Encoder)>,
+    buffer: SharedBuffer,
+    writer: Box<dyn AsyncWrite + Unpin + Send>,
+}
+
+impl InProgressFile {
+    /// Encodes and writes the buffered rows. If their schema is incompatible with the one the file
+    /// was started with, they're returned so that they can be written to a new file instead.
+    async fn write_rows(&mut self, format: FileFormat) -> Result<(), Vec<Value>> {
+        let rows = std::mem::take(&mut self.rows);
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        if format == FileFormat::Json {
+            let mut buffer = self.buffer.clone();
+            for row in rows {
+                serde_json::to_writer(&mut buffer, &row).unwrap();
+                buffer.write_all(b"\n").unwrap();
+            }
+        } else {
+            let schema = infer_json_schema_from_iterator(rows.iter().map(Ok))
+                .expect("could not infer schema");
+
+            if let Some((file_schema, _)) = &self.encoder {
+                if !is_compatible(file_schema, &schema) {
+                    return Err(rows);
+                }
+            } else {
+                let schema = Arc::new(schema);
+                let encoder = match format {
+                    FileFormat::Csv => Encoder::Csv(arrow::csv::Writer::new(self.buffer.clone())),
+                    _ => {
+                        let props = WriterProperties::builder()
+                            .set_compression(parquet::basic::Compression::SNAPPY)
+                            .set_max_row_group_size(ROW_GROUP_SIZE)
+                            .build();
+                        Encoder::Parquet(
+                            ArrowWriter::try_new(self.buffer.clone(), schema.clone(), Some(props))
+                                .unwrap(),
+                        )
+                    }
+                };
+                self.encoder = Some((schema, encoder));
+            }
+
+            let (schema, encoder) = self.encoder.as_mut().unwrap();
+            let decoder = Decoder::new(
+                schema.clone(),
+                DecoderOptions::new().with_batch_size(BATCH_SIZE),
+            );
+            let mut values = rows.into_iter().map(Ok);
+            while let Some(batch) = decoder
+                .next_batch(&mut values)
+                .expect("could not convert records to arrow")
+            {
+                match encoder {
+                    Encoder::Csv(writer) => writer.write(&batch).expect("Writing batch"),
+                    Encoder::Parquet(writer) => writer.write(&batch).expect("Writing batch"),
+                }
+            }
+        }
+
+        self.write_buffer().await;
+        Ok(())
+    }
+
+    async fn write_buffer(&mut self) {
+        let bytes = self.buffer.take();
+        self.writer
+            .write_all(&bytes)
+            .await
+            .unwrap_or_else(|e| panic!("failed to write {}: {:?}", self.staged_key, e));
+    }
+
+    /// Completes the file; any buffered rows must have been written first
+    async fn finish(mut self) {
+        if let Some((_, Encoder::Parquet(writer))) = self.encoder.take() {
+            // writes the final row group and the footer
+            writer.close().expect("failed to close parquet writer");
+        }
+
+        self.write_buffer().await;
+        self.writer
+            .shutdown()
+            .await
+            .unwrap_or_else(|e| panic!("failed to write {}: {:?}", self.staged_key, e));
+    }
+}
+
+pub fn tables() -> Vec<TableDescriptor> {
+    vec![arroyo_state::global_table("f", "file system sink state")]
+}
+
+/// Returns the partition directory for a record with the given event time; the template uses
+/// strftime syntax (e.g., `%Y/%m/%d/%H`)
+pub(crate) fn partition_path(template: &str, timestamp: SystemTime) -> String {
+    if template.is_empty() {
+        return String::new();
+    }
+
+    let time: DateTime<Utc> = timestamp.into();
+    time.format(template)
+        .to_string()
+        .trim_matches('/')
+        .to_string()
+}
+
+#[derive(StreamNode)]
+pub struct FileSystemSink<K: Key + Serialize, T: Data + Serialize> {
+    destination: String,
+    format: FileFormat,
+    partition_template: String,
+    max_file_size: usize,
+    rollover: Duration,
+    storage: Option<StorageClient>,
+    prefix: String,
+    task_index: usize,
+    open_files: HashMap<String, InProgressFile>,
+    // files that have been staged since the last checkpoint
+    staged: Vec<(String, String)>,
+    // files that have been checkpointed but not yet committed
+    pending: Vec<PendingFile>,
+    final_epoch: Option<u32>,
+    _t: PhantomData<(K, T)>,
+}
+
+impl<K: Key + Serialize, T: Data + Serialize> FileSystemSink<K, T> {
+    pub fn new(
+        destination: &str,
+        format: FileFormat,
+        partition_template: &str,
+        max_file_size: usize,
+        rollover: Duration,
+    ) -> Self {
+        FileSystemSink {
+            destination: destination.to_string(),
+            format,
+            partition_template: partition_template.to_string(),
+            max_file_size,
+            rollover,
+            storage: None,
+            prefix: String::new(),
+            task_index: 0,
+            open_files: HashMap::new(),
+            staged: vec![],
+            pending: vec![],
+            final_epoch: None,
+            _t: PhantomData,
+        }
+    }
+
+    fn key(&self, path: &str) -> String {
+        if self.prefix.is_empty() {
+            path.to_string()
+        } else {
+            format!("{}/{}", self.prefix, path)
+        }
+    }
+
+    async fn open(&mut self, partition: &str) {
+        let name = format!(
+            "part-{:05}-{:016x}.{}",
+            self.task_index,
+            rand::random::<u64>(),
+            self.format.extension()
+        );
+        let path = if partition.is_empty() {
+            name
+        } else {
+            format!("{}/{}", partition, name)
+        };
+
+        let staged_key = self.key(&format!("{}/{}", STAGING_DIR, path));
+        let writer = self
+            .storage
+            .as_ref()
+            .unwrap()
+            .writer(staged_key.clone())
+            .await
+            .unwrap_or_else(|e| panic!("failed to write {}: {:?}", staged_key, e));
+
+        self.open_files.insert(
+            partition.to_string(),
+            InProgressFile {
+                path,
+                staged_key,
+                opened_at: Instant::now(),
+                bytes: 0,
+                rows: vec![],
+                encoder: None,
+                buffer: SharedBuffer::default(),
+                writer,
+            },
+        );
+    }
+
+    async fn write_rows(&mut self, partition: &str) {
+        let file = self.open_files.get_mut(partition).unwrap();
+        let Err(rows) = file.write_rows(self.format).await else {
+            return;
+        };
+
+        // the schema of the records has changed, so they're written to a new file
+        self.close(partition).await;
+        self.open(partition).await;
+        let file = self.open_files.get_mut(partition).unwrap();
+        file.rows = rows;
+        file.write_rows(self.format)
+            .await
+            .expect("rows are compatible with a new file");
+    }
+
+    // completes the file, after which it's committed along with the next checkpoint
+    async fn close(&mut self, partition: &str) {
+        let Some(file) = self.open_files.remove(partition) else {
+            return;
+        };
+
+        let staged_key = file.staged_key.clone();
+        let final_key = self.key(&file.path);
+        file.finish().await;
+
+        self.staged.push((staged_key, final_key));
+    }
+
+    async fn roll(&mut self, partition: &str) {
+        if self.open_files.contains_key(partition) {
+            self.write_rows(partition).await;
+            self.close(partition).await;
+        }
+    }
+
+    async fn roll_all(&mut self) {
+        let partitions: Vec<_> = self.open_files.keys().cloned().collect();
+        for partition in partitions {
+            self.roll(&partition).await;
+        }
+    }
+
+    async fn roll_expired(&mut self) {
+        let expired: Vec<_> = self
+            .open_files
+            .iter()
+            .filter(|(_, f)| f.opened_at.elapsed() >= self.rollover)
+            .map(|(p, _)| p.clone())
+            .collect();
+
+        for partition in expired {
+            self.roll(&partition).await;
+        }
+    }
+
+    /// Moves a staged file to its final location; this is idempotent, so files that were already
+    /// committed before a failure are skipped
+    async fn finalize(&mut self, staged_key: &str, final_key: &str) {
+        let renamed = self
+            .storage
+            .as_ref()
+            .unwrap()
+            .rename(staged_key.to_string(), final_key.to_string())
+            .await
+            .unwrap_or_else(|e| panic!("failed to commit {}: {:?}", staged_key, e));
+
+        if !renamed {
+            info!(
+                "Staged file {} no longer exists; assuming it was already committed",
+                staged_key
+            );
+        }
+    }
+
+    async fn commit_through(&mut self, epoch: u32) {
+        let (committed, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|f| f.epoch <= epoch);
+        self.pending = pending;
+
+        for f in committed {
+            self.finalize(&f.staged_key, &f.final_key).await;
+        }
+    }
+}
+
+#[process_fn(in_k = K, in_t = T)]
+impl<K: Key + Serialize, T: Data + Serialize> FileSystemSink<K, T> {
+    fn name(&self) -> String {
+        "filesystem-sink".to_string()
+    }
+
+    fn tables(&self) -> Vec<TableDescriptor> {
+        tables()
+    }
+
+    async fn on_start(&mut self, ctx: &mut Context<(), ()>) {
+        let (storage, prefix) = StorageClient::for_url(&self.destination);
+        self.storage = Some(storage);
+        self.prefix = prefix;
+        self.task_index = ctx.task_info.task_index;
+
+        // we're restoring from a completed checkpoint, so any files that were pending as of that
+        // checkpoint can be committed; state from subtasks that no longer exist after a
+        // rescale is picked up by the subtask with the same index modulo the new parallelism
+        let task_index = ctx.task_info.task_index;
+        let parallelism = ctx.task_info.parallelism;
+        let restored: Vec<PendingFile> = {
+            let mut state = ctx
+                .state
+                .get_global_keyed_state::<usize, FileSystemSinkState>('f')
+                .await;
+            state
+                .get_all()
+                .filter(|s| s.task_index % parallelism == task_index)
+                .flat_map(|s| s.pending.clone())
+                .collect()
+        };
+
+        for f in restored {
+            self.finalize(&f.staged_key, &f.final_key).await;
+        }
+    }
+
+    async fn process_element(&mut self, record: &Record<K, T>, _ctx: &mut Context<(), ()>) {
+        let mut value = serde_json::to_value(&record.value).unwrap();
+        if self.format.requires_objects() && !value.is_object() {
+            value = json!({ "value": value });
+        }
+
+        let partition = partition_path(&self.partition_template, record.timestamp);
+        let size = value.to_string().len() + 1;
+
+        if !self.open_files.contains_key(&partition) {
+            self.open(&partition).await;
+        }
+
+        let file = self.open_files.get_mut(&partition).unwrap();
+        file.rows.push(value);
+        file.bytes += size;
+
+        if file.bytes >= self.max_file_size {
+            self.roll(&partition).await;
+        } else if file.rows.len() >= BATCH_SIZE {
+            self.write_rows(&partition).await;
+        }
+
+        self.roll_expired().await;
+    }
+
+    async fn handle_watermark(&mut self, _: SystemTime, _: &mut Context<(), ()>) {
+        self.roll_expired().await;
+    }
+
+    async fn handle_checkpoint(&mut self, barrier: &CheckpointBarrier, ctx: &mut Context<(), ()>) {
+        self.roll_all().await;
+
+        let epoch = barrier.epoch;
+        self.pending.extend(
+            self.staged
+                .drain(..)
+                .map(|(staged_key, final_key)| PendingFile {
+                    epoch,
+                    staged_key,
+                    final_key,
+                }),
+        );
+
+        ctx.state
+            .get_global_keyed_state::<usize, FileSystemSinkState>('f')
+            .await
+            .insert(
+                ctx.task_info.task_index,
+                FileSystemSinkState {
+                    task_index: ctx.task_info.task_index,
+                    pending: self.pending.clone(),
+                },
+            )
+            .await;
+
+        if barrier.then_stop {
+            self.final_epoch = Some(epoch);
+        }
+    }
+
+    async fn handle_commit(&mut self, epoch: u32, _: &mut Context<(), ()>) {
+        self.commit_through(epoch).await;
+    }
+
+    async fn on_close(&mut self, ctx: &mut Context<(), ()>) {
+        self.roll_all().await;
+
+        let Some(final_epoch) = self.final_epoch else {
+            // the input has ended without a final checkpoint, so nothing written since the last
+            // checkpoint may be committed; it will be written again when the job is restored from
+            // that checkpoint, which also commits the files that are still pending
+            let storage = self.storage.as_ref().unwrap();
+            for (staged_key, _) in std::mem::take(&mut self.staged) {
+                storage
+                    .remove(staged_key.clone())
+                    .await
+                    .unwrap_or_else(|e| panic!("failed to delete {}: {:?}", staged_key, e));
+            }
+            return;
+        };
+
+        // we're stopping with a final checkpoint; wait for it to complete before committing
+        let wait = tokio::time::timeout(FINAL_COMMIT_TIMEOUT, async {
+            while let Some(msg) = ctx.control_rx.recv().await {
+                if let ControlMessage::Commit { epoch } = msg {
+                    if epoch >= final_epoch {
+                        return;
+                    }
+                }
+            }
+        });
+
+        if wait.await.is_err() {
+            warn!(
+                "Timed out waiting for checkpoint {} to complete; {} files will be committed on restore",
+                final_epoch,
+                self.pending.len()
+            );
+            return;
+        }
+
+        self.commit_through(final_epoch).await;
+    }
+}
@@ -0,0 +1,256 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::engine::{Context, OutQueue};
+use crate::operators::sinks::filesystem::{
+    self, partition_path, FileFormat, FileSystemSink, BATCH_SIZE,
+};
+use arroyo_types::*;
+use rand::Rng;
+use serde::Serialize;
+use tokio::sync::mpsc::channel;
+
+#[derive(Debug, Clone, bincode::Encode, bincode::Decode, Serialize, PartialEq)]
+struct TestData {
+    i: u64,
+}
+
+#[derive(Debug, Clone, bincode::Encode, bincode::Decode, Serialize, PartialEq)]
+struct OptionalData {
+    i: u64,
+    s: Option<String>,
+}
+
+fn files_under(dir: &Path) -> Vec<String> {
+    let mut files = vec![];
+    if let Ok(entries) = std::fs::read_dir(dir) {
+        for entry in entries {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                files.extend(files_under(&path));
+            } else {
+                files.push(path.to_string_lossy().to_string());
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+#[tokio::test]
+async fn test_files_committed_on_checkpoint_completion() {
+    let dir = std::env::temp_dir().join(format!(
+        "arroyo-filesystem-sink-{}",
+        rand::thread_rng().gen::<u64>()
+    ));
+
+    let mut sink: FileSystemSink<(), TestData> = FileSystemSink::new(
+        dir.to_str().unwrap(),
+        FileFormat::Json,
+        "%Y/%m/%d",
+        1024 * 1024,
+        Duration::from_secs(3600),
+    );
+
+    let mut task_info = get_test_task_info();
+    task_info.job_id = format!("filesystem-job-{}", rand::thread_rng().gen::<u64>());
+
+    let (_, control_rx) = channel(128);
+    let (command_tx, _) = channel(128);
+    let (data_tx, _recv) = channel(128);
+
+    let mut ctx: Context<(), ()> = Context::new(
+        task_info,
+        None,
+        control_rx,
+        command_tx,
+        1,
+        vec![vec![OutQueue::new(data_tx, false)]],
+        filesystem::tables(),
+    )
+    .await;
+    sink.on_start(&mut ctx).await;
+
+    let timestamp = from_millis(1_680_000_000_000);
+    for i in 0..10 {
+        sink.process_element(
+            &Record {
+                timestamp,
+                key: None,
+                value: TestData { i },
+            },
+            &mut ctx,
+        )
+        .await;
+    }
+
+    sink.handle_checkpoint(
+        &CheckpointBarrier {
+            epoch: 1,
+            min_epoch: 0,
+            timestamp: SystemTime::now(),
+            then_stop: false,
+        },
+        &mut ctx,
+    )
+    .await;
+
+    // the file is staged, but not visible until the checkpoint completes
+    let final_dir = dir.join("2023/03/28");
+    assert!(files_under(&final_dir).is_empty());
+    assert_eq!(1, files_under(&dir.join("_staging")).len());
+
+    sink.handle_commit(1, &mut ctx).await;
+
+    assert!(files_under(&dir.join("_staging")).is_empty());
+    let files = files_under(&final_dir);
+    assert_eq!(1, files.len());
+
+    let contents = std::fs::read_to_string(&files[0]).unwrap();
+    let expected: Vec<_> = (0..10).map(|i| format!("{{\"i\":{}}}", i)).collect();
+    assert_eq!(expected, contents.lines().collect::<Vec<_>>());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+async fn test_sink<T: Data + Serialize>(
+    format: FileFormat,
+) -> (PathBuf, FileSystemSink<(), T>, Context<(), ()>) {
+    let dir = std::env::temp_dir().join(format!(
+        "arroyo-filesystem-sink-{}",
+        rand::thread_rng().gen::<u64>()
+    ));
+
+    let mut sink: FileSystemSink<(), T> = FileSystemSink::new(
+        dir.to_str().unwrap(),
+        format,
+        "",
+        1024 * 1024,
+        Duration::from_secs(3600),
+    );
+
+    let mut task_info = get_test_task_info();
+    task_info.job_id = format!("filesystem-job-{}", rand::thread_rng().gen::<u64>());
+
+    let (_, control_rx) = channel(128);
+    let (command_tx, _) = channel(128);
+    let (data_tx, _) = channel(128);
+
+    let mut ctx: Context<(), ()> = Context::new(
+        task_info,
+        None,
+        control_rx,
+        command_tx,
+        1,
+        vec![vec![OutQueue::new(data_tx, false)]],
+        filesystem::tables(),
+    )
+    .await;
+    sink.on_start(&mut ctx).await;
+
+    (dir, sink, ctx)
+}
+
+fn barrier(epoch: u32) -> CheckpointBarrier {
+    CheckpointBarrier {
+        epoch,
+        min_epoch: 0,
+        timestamp: SystemTime::now(),
+        then_stop: false,
+    }
+}
+
+#[tokio::test]
+async fn test_uncheckpointed_files_discarded_on_close() {
+    let (dir, mut sink, mut ctx) = test_sink::<TestData>(FileFormat::Json).await;
+
+    let timestamp = from_millis(1_680_000_000_000);
+    for i in 0..20 {
+        if i == 10 {
+            sink.handle_checkpoint(&barrier(1), &mut ctx).await;
+        }
+        sink.process_element(
+            &Record {
+                timestamp,
+                key: None,
+                value: TestData { i },
+            },
+            &mut ctx,
+        )
+        .await;
+    }
+
+    // the input ends without the first checkpoint having completed, or a final checkpoint
+    sink.on_close(&mut ctx).await;
+
+    // the file from the first checkpoint stays staged until it's committed on restore, and the
+    // rows written since are discarded
+    let files = files_under(&dir);
+    assert_eq!(1, files.len());
+    assert!(files[0].starts_with(dir.join("_staging").to_str().unwrap()));
+
+    let contents = std::fs::read_to_string(&files[0]).unwrap();
+    assert_eq!(10, contents.lines().count());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_new_file_on_schema_change() {
+    let (dir, mut sink, mut ctx) = test_sink::<OptionalData>(FileFormat::Csv).await;
+
+    let timestamp = from_millis(1_680_000_000_000);
+    for i in 0..(2 * BATCH_SIZE) as u64 {
+        // `s` is null throughout the first batch, so it's missing from the first file's schema
+        let s = (i >= BATCH_SIZE as u64).then(|| i.to_string());
+        sink.process_element(
+            &Record {
+                timestamp,
+                key: None,
+                value: OptionalData { i, s },
+            },
+            &mut ctx,
+        )
+        .await;
+    }
+
+    sink.handle_checkpoint(&barrier(1), &mut ctx).await;
+    sink.handle_commit(1, &mut ctx).await;
+
+    // returns the (sorted) columns of each file, along with its number of rows
+    let mut files: Vec<(Vec<String>, usize)> = files_under(&dir)
+        .iter()
+        .map(|f| {
+            let contents = std::fs::read_to_string(f).unwrap();
+            let mut lines = contents.lines();
+            let mut columns: Vec<String> = lines
+                .next()
+                .unwrap()
+                .split(',')
+                .map(|c| c.to_string())
+                .collect();
+            columns.sort();
+            (columns, lines.count())
+        })
+        .collect();
+    files.sort();
+
+    assert_eq!(
+        vec![
+            (vec!["i".to_string()], BATCH_SIZE),
+            (vec!["i".to_string(), "s".to_string()], BATCH_SIZE)
+        ],
+        files
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_partition_path() {
+    let timestamp = from_millis(1_680_000_000_000);
+
+    assert_eq!("", partition_path("", timestamp));
+    assert_eq!("2023/03/28/10", partition_path("%Y/%m/%d/%H", timestamp));
+    assert_eq!("dt=2023-03-28", partition_path("/dt=%Y-%m-%d/", timestamp));
+}
@@ -6,6 +6,7 @@ use std::marker::PhantomData;
 
 use tracing::info;
 
+use rdkafka::message::OwnedHeaders;
 use rdkafka::producer::{DeliveryFuture, FutureProducer, FutureRecord};
 use rdkafka::util::Timeout;
 
@@ -15,6 +16,7 @@ use arroyo_types::CheckpointBarrier;
 use rdkafka::error::KafkaError;
 use rdkafka_sys::RDKafkaErrorCode;
 use serde::Serialize;
+use serde_json::Value;
 use std::time::Duration;
 
 #[cfg(test)]
@@ -27,11 +29,29 @@ pub struct KafkaSinkFunc<K: Key + Serialize, T: Data + Serialize> {
     producer: Option<FutureProducer>,
     last_write: Option<DeliveryFuture>,
     client_config: HashMap<String, String>,
+    key_field: Option<String>,
+    header_fields: Vec<String>,
     _t: PhantomData<(K, T)>,
 }
 
+/// Removes `field` from the serialized record, returning it in the form it should be written
+/// to a Kafka key or header. Strings are written as-is, other values as JSON.
+pub(crate) fn take_field(value: &mut Value, field: &str) -> Option<String> {
+    match value.as_object_mut()?.remove(field)? {
+        Value::Null => None,
+        Value::String(s) => Some(s),
+        v => Some(v.to_string()),
+    }
+}
+
 impl<K: Key + Serialize, T: Data + Serialize> KafkaSinkFunc<K, T> {
-    pub fn new(servers: &str, topic: &str, client_config: Vec<(&str, &str)>) -> Self {
+    pub fn new(
+        servers: &str,
+        topic: &str,
+        key_field: Option<&str>,
+        header_fields: Vec<&str>,
+        client_config: Vec<(&str, &str)>,
+    ) -> Self {
         KafkaSinkFunc {
             topic: topic.to_string(),
             bootstrap_servers: servers.to_string(),
@@ -41,6 +61,8 @@ impl<K: Key + Serialize, T: Data + Serialize> KafkaSinkFunc<K, T> {
                 .iter()
                 .map(|(key, value)| (key.to_string(), value.to_string()))
                 .collect(),
+            key_field: key_field.map(|f| f.to_string()),
+            header_fields: header_fields.iter().map(|f| f.to_string()).collect(),
             _t: PhantomData,
         }
     }
@@ -109,20 +131,44 @@ impl<K: Key + Serialize, T: Data + Serialize> KafkaSinkFunc<K, T> {
     }
 
     async fn process_element(&mut self, record: &Record<K, T>, _ctx: &mut Context<(), ()>) {
-        let k = record
-            .key
-            .as_ref()
-            .map(|k| serde_json::to_string(k).unwrap());
-        let v = serde_json::to_string(&record.value).unwrap();
-
-        let future_record = {
-            if let Some(k) = k.as_ref() {
-                FutureRecord::to(&self.topic).key(k).payload(&v)
-            } else {
-                FutureRecord::to(&self.topic).payload(&v)
+        let (k, headers, v) = if self.key_field.is_none() && self.header_fields.is_empty() {
+            (
+                record
+                    .key
+                    .as_ref()
+                    .map(|k| serde_json::to_string(k).unwrap()),
+                None,
+                serde_json::to_string(&record.value).unwrap(),
+            )
+        } else {
+            // columns that are mapped to the key or headers are moved out of the payload
+            let mut value = serde_json::to_value(&record.value).unwrap();
+            let k = match &self.key_field {
+                Some(field) => take_field(&mut value, field),
+                None => record
+                    .key
+                    .as_ref()
+                    .map(|k| serde_json::to_string(k).unwrap()),
+            };
+
+            let mut headers = OwnedHeaders::new();
+            for field in &self.header_fields {
+                if let Some(header) = take_field(&mut value, field) {
+                    headers = headers.add(field, &header);
+                }
             }
+
+            (k, Some(headers), value.to_string())
         };
 
+        let mut future_record = FutureRecord::to(&self.topic).payload(&v);
+        if let Some(k) = k.as_ref() {
+            future_record = future_record.key(k);
+        }
+        if let Some(headers) = headers {
+            future_record = future_record.headers(headers);
+        }
+
         self.last_write = Some(Self::publish(self.producer.as_mut().unwrap(), future_record).await);
     }
 }
@@ -2,7 +2,7 @@
 use std::time::{Duration, SystemTime};
 
 use crate::engine::{Context, OutQueue};
-use crate::operators::sinks::kafka::KafkaSinkFunc;
+use crate::operators::sinks::kafka::{take_field, KafkaSinkFunc};
 use arroyo_types::CheckpointBarrier;
 use arroyo_types::*;
 use rdkafka::admin::{AdminClient, AdminOptions, NewTopic};
@@ -44,7 +44,7 @@ impl KafkaTopicTester {
     }
 
     async fn get_sink_with_writes(&self) -> KafkaSinkWithWrites {
-        let mut kafka = KafkaSinkFunc::new(&self.server, &self.topic, vec![]);
+        let mut kafka = KafkaSinkFunc::new(&self.server, &self.topic, None, vec![], vec![]);
         let (_, control_rx) = channel(128);
         let (command_tx, _) = channel(128);
         let (data_tx, _recv) = channel(128);
@@ -179,3 +179,19 @@ async fn test_kafka() {
         assert_eq!(record.value, result);
     }
 }
+
+#[test]
+fn test_take_field() {
+    let mut value = serde_json::json!({
+        "id": "entity-1",
+        "tenant": 7,
+        "empty": null,
+        "payload": "data"
+    });
+
+    assert_eq!(Some("entity-1".to_string()), take_field(&mut value, "id"));
+    assert_eq!(Some("7".to_string()), take_field(&mut value, "tenant"));
+    assert_eq!(None, take_field(&mut value, "empty"));
+    assert_eq!(None, take_field(&mut value, "not_a_field"));
+    assert_eq!(serde_json::json!({"payload": "data"}), value);
+}
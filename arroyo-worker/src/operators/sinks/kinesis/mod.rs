@@ -0,0 +1,227 @@
+use crate::engine::{Context, StreamNode};
+use crate::operators::sources::kinesis::kinesis_client;
+use arroyo_macro::process_fn;
+use arroyo_rpc::ControlResp;
+use arroyo_types::*;
+use rand::Rng;
+use rusoto_kinesis::{Kinesis, KinesisClient, PutRecordsInput, PutRecordsRequestEntry};
+use serde::Serialize;
+use serde_json::Value;
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+#[cfg(test)]
+mod test;
+
+// limits of the PutRecords API
+const MAX_RECORDS_PER_BATCH: usize = 500;
+const MAX_BYTES_PER_BATCH: usize = 5 * 1024 * 1024;
+const MAX_PARTITION_KEY_CHARS: usize = 256;
+
+// how long a record may be buffered before its batch is sent
+const MAX_BATCH_AGE: Duration = Duration::from_secs(1);
+const MAX_RETRIES: usize = 20;
+
+#[derive(StreamNode)]
+pub struct KinesisSinkFunc<K: Key + Serialize, T: Data + Serialize> {
+    stream_name: String,
+    region: String,
+    endpoint: Option<String>,
+    partition_key_field: Option<String>,
+    client: Option<KinesisClient>,
+    buffer: Vec<PutRecordsRequestEntry>,
+    buffered_bytes: usize,
+    last_flush: Instant,
+    failed: bool,
+    _t: PhantomData<(K, T)>,
+}
+
+/// Returns the partition key for a record: the value of `field` if configured, otherwise the
+/// record's key. Records without either are assigned to a random partition key by the caller.
+pub(crate) fn partition_key(
+    value: &Value,
+    field: Option<&str>,
+    record_key: Option<String>,
+) -> Option<String> {
+    let key = match field {
+        Some(field) => match value.get(field)? {
+            Value::Null => None,
+            Value::String(s) => Some(s.clone()),
+            v => Some(v.to_string()),
+        },
+        None => record_key,
+    }?;
+
+    if key.is_empty() {
+        None
+    } else {
+        Some(key.chars().take(MAX_PARTITION_KEY_CHARS).collect())
+    }
+}
+
+impl<K: Key + Serialize, T: Data + Serialize> KinesisSinkFunc<K, T> {
+    pub fn new(
+        stream_name: &str,
+        region: &str,
+        endpoint: Option<&str>,
+        partition_key_field: Option<&str>,
+    ) -> Self {
+        KinesisSinkFunc {
+            stream_name: stream_name.to_string(),
+            region: region.to_string(),
+            endpoint: endpoint.map(|e| e.to_string()),
+            partition_key_field: partition_key_field.map(|f| f.to_string()),
+            client: None,
+            buffer: vec![],
+            buffered_bytes: 0,
+            last_flush: Instant::now(),
+            failed: false,
+            _t: PhantomData,
+        }
+    }
+}
+
+#[process_fn(in_k = K, in_t = T)]
+impl<K: Key + Serialize, T: Data + Serialize> KinesisSinkFunc<K, T> {
+    fn name(&self) -> String {
+        format!("kinesis-producer-{}", self.stream_name)
+    }
+
+    async fn on_start(&mut self, _ctx: &mut Context<(), ()>) {
+        info!("Creating kinesis client for {}", self.stream_name);
+        self.client = Some(kinesis_client(&self.region, self.endpoint.as_deref()));
+    }
+
+    async fn handle_checkpoint(&mut self, _: &CheckpointBarrier, ctx: &mut Context<(), ()>) {
+        self.flush_or_fail(ctx).await;
+    }
+
+    async fn on_close(&mut self, ctx: &mut Context<(), ()>) {
+        self.flush_or_fail(ctx).await;
+    }
+
+    // Reports a failed flush to the controller, which restarts the job from the last completed
+    // checkpoint. The failure is sent ahead of our checkpoint response so the in-flight
+    // checkpoint can't complete without the unwritten records.
+    async fn flush_or_fail(&mut self, ctx: &mut Context<(), ()>) {
+        if let Err(error) = self.flush().await {
+            error!("{}", error);
+            if !self.failed {
+                self.failed = true;
+                ctx.control_tx
+                    .send(ControlResp::TaskFailed {
+                        operator_id: ctx.task_info.operator_id.clone(),
+                        task_index: ctx.task_info.task_index,
+                        error,
+                    })
+                    .await
+                    .ok();
+            }
+        }
+    }
+
+    /// Writes the buffered records, retrying failures with backoff. Records that still could not
+    /// be written after `MAX_RETRIES` attempts are left in the buffer.
+    async fn flush(&mut self) -> Result<(), String> {
+        let mut records = std::mem::take(&mut self.buffer);
+        self.buffered_bytes = 0;
+        self.last_flush = Instant::now();
+
+        let mut backoff = Duration::from_millis(50);
+        for _ in 0..MAX_RETRIES {
+            if records.is_empty() {
+                return Ok(());
+            }
+
+            match self
+                .client
+                .as_ref()
+                .unwrap()
+                .put_records(PutRecordsInput {
+                    records: records.clone(),
+                    stream_name: self.stream_name.clone(),
+                })
+                .await
+            {
+                Ok(output) if output.failed_record_count.unwrap_or(0) == 0 => {
+                    return Ok(());
+                }
+                Ok(output) => {
+                    // individual records may fail (e.g., due to throttling); only those are retried
+                    warn!(
+                        "Failed to write {} records to kinesis stream {}; retrying",
+                        output.failed_record_count.unwrap_or(0),
+                        self.stream_name
+                    );
+                    records = records
+                        .into_iter()
+                        .zip(output.records)
+                        .filter(|(_, result)| result.error_code.is_some())
+                        .map(|(record, _)| record)
+                        .collect();
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to write to kinesis stream {}: {:?}; retrying",
+                        self.stream_name, e
+                    );
+                }
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(Duration::from_secs(5));
+        }
+
+        let error = format!(
+            "Failed to write {} records to kinesis stream {} after {} attempts",
+            records.len(),
+            self.stream_name,
+            MAX_RETRIES
+        );
+        self.buffered_bytes = records
+            .iter()
+            .map(|r| r.data.len() + r.partition_key.len())
+            .sum();
+        self.buffer = records;
+        Err(error)
+    }
+
+    async fn process_element(&mut self, record: &Record<K, T>, ctx: &mut Context<(), ()>) {
+        if self.failed {
+            // the job is being restarted and will replay this record from the last checkpoint
+            return;
+        }
+
+        let value = serde_json::to_value(&record.value).unwrap();
+        let partition_key = partition_key(
+            &value,
+            self.partition_key_field.as_deref(),
+            record
+                .key
+                .as_ref()
+                .map(|k| serde_json::to_string(k).unwrap()),
+        )
+        .unwrap_or_else(|| rand::thread_rng().gen::<u64>().to_string());
+
+        let data = value.to_string().into_bytes();
+        let size = data.len() + partition_key.len();
+
+        if self.buffer.len() >= MAX_RECORDS_PER_BATCH
+            || self.buffered_bytes + size > MAX_BYTES_PER_BATCH
+        {
+            self.flush_or_fail(ctx).await;
+        }
+
+        self.buffer.push(PutRecordsRequestEntry {
+            data: data.into(),
+            explicit_hash_key: None,
+            partition_key,
+        });
+        self.buffered_bytes += size;
+
+        if self.last_flush.elapsed() > MAX_BATCH_AGE {
+            self.flush_or_fail(ctx).await;
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::time::{Duration, SystemTime};
+
+use crate::engine::{Context, OutQueue};
+use crate::operators::sinks::kinesis::{partition_key, KinesisSinkFunc};
+use crate::operators::sources::kinesis::kinesis_client;
+use arroyo_types::*;
+use rusoto_kinesis::{
+    CreateStreamInput, DeleteStreamInput, DescribeStreamSummaryInput, GetRecordsInput,
+    GetShardIteratorInput, Kinesis, KinesisClient, ListShardsInput,
+};
+use serde_json::json;
+use tokio::sync::mpsc::channel;
+
+// requires a local kinesis emulator (e.g., localstack); see the source tests
+const ENDPOINT: &str = "http://localhost:4566";
+const REGION: &str = "us-east-1";
+
+async fn create_stream(client: &KinesisClient, stream_name: &str) {
+    let _ = client
+        .delete_stream(DeleteStreamInput {
+            stream_name: stream_name.to_string(),
+            ..Default::default()
+        })
+        .await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    client
+        .create_stream(CreateStreamInput {
+            stream_name: stream_name.to_string(),
+            shard_count: 1,
+        })
+        .await
+        .expect("stream creation should have worked");
+
+    loop {
+        let summary = client
+            .describe_stream_summary(DescribeStreamSummaryInput {
+                stream_name: stream_name.to_string(),
+            })
+            .await
+            .unwrap();
+        if summary.stream_description_summary.stream_status == "ACTIVE" {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+async fn read_all(client: &KinesisClient, stream_name: &str) -> Vec<String> {
+    let shards = client
+        .list_shards(ListShardsInput {
+            stream_name: Some(stream_name.to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .shards
+        .unwrap();
+
+    let iterator = client
+        .get_shard_iterator(GetShardIteratorInput {
+            shard_id: shards[0].shard_id.clone(),
+            shard_iterator_type: "TRIM_HORIZON".to_string(),
+            stream_name: stream_name.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .shard_iterator
+        .unwrap();
+
+    client
+        .get_records(GetRecordsInput {
+            limit: None,
+            shard_iterator: iterator,
+        })
+        .await
+        .unwrap()
+        .records
+        .into_iter()
+        .map(|r| String::from_utf8(r.data.to_vec()).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_kinesis_checkpoint_flushes() {
+    let stream_name = "arroyo-sink-checkpoint";
+    let client = kinesis_client(REGION, Some(ENDPOINT));
+    create_stream(&client, stream_name).await;
+
+    let mut sink: KinesisSinkFunc<String, String> =
+        KinesisSinkFunc::new(stream_name, REGION, Some(ENDPOINT), None);
+
+    let (_, control_rx) = channel(128);
+    let (command_tx, _) = channel(128);
+    let (data_tx, _recv) = channel(128);
+
+    let mut ctx: Context<(), ()> = Context::new(
+        arroyo_types::get_test_task_info(),
+        None,
+        control_rx,
+        command_tx,
+        1,
+        vec![vec![OutQueue::new(data_tx, false)]],
+        vec![],
+    )
+    .await;
+    sink.on_start(&mut ctx).await;
+
+    for message in 1u32..200 {
+        let record = Record {
+            timestamp: SystemTime::now(),
+            key: Some(message.to_string()),
+            value: message.to_string(),
+        };
+        sink.process_element(&record, &mut ctx).await;
+    }
+
+    sink.handle_checkpoint(
+        &CheckpointBarrier {
+            epoch: 2,
+            min_epoch: 0,
+            timestamp: SystemTime::now(),
+            then_stop: false,
+        },
+        &mut ctx,
+    )
+    .await;
+
+    let records = read_all(&client, stream_name).await;
+    let expected: Vec<_> = (1u32..200).map(|i| format!("\"{}\"", i)).collect();
+    assert_eq!(expected, records);
+}
+
+#[test]
+fn test_partition_key() {
+    let value = json!({"id": 5, "name": "bob", "missing": null, "empty": ""});
+
+    assert_eq!(
+        Some("5".to_string()),
+        partition_key(&value, Some("id"), Some("k".to_string()))
+    );
+    assert_eq!(
+        Some("bob".to_string()),
+        partition_key(&value, Some("name"), None)
+    );
+    assert_eq!(None, partition_key(&value, Some("missing"), None));
+    assert_eq!(None, partition_key(&value, Some("empty"), None));
+    assert_eq!(None, partition_key(&value, Some("unknown"), None));
+    assert_eq!(
+        Some("k".to_string()),
+        partition_key(&value, None, Some("k".to_string()))
+    );
+    assert_eq!(None, partition_key(&value, None, None));
+
+    let long = json!({ "id": "x".repeat(300) });
+    assert_eq!(
+        256,
+        partition_key(&long, Some("id"), None)
+            .unwrap()
+            .chars()
+            .count()
+    );
+}
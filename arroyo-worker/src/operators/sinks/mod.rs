@@ -16,7 +16,9 @@ use serde::Serialize;
 use tonic::transport::Channel;
 use tracing::info;
 
+pub mod filesystem;
 pub mod kafka;
+pub mod kinesis;
 
 #[derive(StreamNode)]
 pub struct FileSink<K: Key, T: Data> {
@@ -114,6 +114,8 @@ impl<
             delete_behavior: TableDeleteBehavior::NoReadsBeforeWatermark as i32,
             write_behavior: TableWriteBehavior::NoWritesBeforeWatermark as i32,
             retention_micros: self.width.as_micros() as u64,
+            schema: None,
+            ttl: None,
         }]
     }
 
@@ -147,7 +149,7 @@ impl<
 
     async fn on_start(&mut self, ctx: &mut Context<PK, OutT>) {
         let watermark = ctx.watermark();
-        let map: TimeKeyMap<K, BinA, _> = ctx.state.get_time_key_map('a', watermark).await;
+        let mut map: TimeKeyMap<K, BinA, _> = ctx.state.get_time_key_map('a', watermark).await;
 
         let Some(map_min_time) = map.get_min_time()  else {
             self.state = SlidingWindowState::NoData;
@@ -0,0 +1,640 @@
+use crate::engine::{Context, StreamNode};
+use crate::SourceFinishType;
+use arrow::json::writer::record_batches_to_json_rows;
+use arroyo_macro::source_fn;
+use arroyo_rpc::grpc::{StopMode, TableDescriptor};
+use arroyo_rpc::ControlMessage;
+use arroyo_state::storage::StorageClient;
+use arroyo_state::tables::GlobalKeyedState;
+use arroyo_types::*;
+use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+use bincode::{Decode, Encode};
+use bytes::Bytes;
+use chrono::DateTime;
+use csv::StringRecord;
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt};
+use parquet::arrow::async_reader::{
+    fetch_parquet_metadata, AsyncFileReader, ParquetRecordBatchStreamBuilder,
+};
+use parquet::errors::ParquetError;
+use parquet::file::metadata::ParquetMetaData;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+use tokio::select;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::time::sleep;
+use tokio_util::io::SyncIoBridge;
+use tracing::{debug, info};
+
+#[cfg(test)]
+mod test;
+
+// how often we check for control messages while reading a file
+const CONTROL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// how many CSV records may be parsed ahead of the ones we've emitted
+const CSV_BUFFER_SIZE: usize = 1024;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    Json,
+    Csv,
+    Parquet,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub(crate) fn for_key(key: &str) -> Self {
+        if key.ends_with(".gz") || key.ends_with(".gzip") {
+            Compression::Gzip
+        } else if key.ends_with(".zst") || key.ends_with(".zstd") {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Wraps `reader` so that it reads the decompressed contents of the file. Files may be made
+    /// up of several concatenated gzip members or zstd frames.
+    pub(crate) fn reader(
+        &self,
+        reader: Box<dyn AsyncRead + Unpin + Send>,
+    ) -> Box<dyn AsyncBufRead + Unpin + Send> {
+        let reader = BufReader::new(reader);
+        match self {
+            Compression::None => Box::new(reader),
+            Compression::Gzip => {
+                let mut decoder = GzipDecoder::new(reader);
+                decoder.multiple_members(true);
+                Box::new(BufReader::new(decoder))
+            }
+            Compression::Zstd => {
+                let mut decoder = ZstdDecoder::new(reader);
+                decoder.multiple_members(true);
+                Box::new(BufReader::new(decoder))
+            }
+        }
+    }
+}
+
+// reads parquet files with ranged reads, so that only the footer and the row groups that are
+// being read are fetched
+struct ParquetFileReader {
+    storage: StorageClient,
+    key: String,
+    size: usize,
+}
+
+async fn get_range(
+    storage: StorageClient,
+    key: String,
+    range: Range<usize>,
+) -> parquet::errors::Result<Bytes> {
+    storage
+        .get_range(key, range)
+        .await
+        .map_err(|e| ParquetError::External(e.into()))
+}
+
+impl AsyncFileReader for ParquetFileReader {
+    fn get_bytes(&mut self, range: Range<usize>) -> BoxFuture<'_, parquet::errors::Result<Bytes>> {
+        get_range(self.storage.clone(), self.key.clone(), range).boxed()
+    }
+
+    fn get_metadata(&mut self) -> BoxFuture<'_, parquet::errors::Result<Arc<ParquetMetaData>>> {
+        async move {
+            let metadata = fetch_parquet_metadata(
+                |range| get_range(self.storage.clone(), self.key.clone(), range),
+                self.size,
+                None,
+            )
+            .await?;
+            Ok(Arc::new(metadata))
+        }
+        .boxed()
+    }
+}
+
+// reads the records of a CSV file, sending the header row first and then each record that ends
+// after `start` along with its end offset
+fn read_csv_records(reader: impl Read, start: u64, tx: Sender<csv::Result<(u64, StringRecord)>>) {
+    let mut reader = csv::Reader::from_reader(reader);
+    let headers = reader.headers().cloned();
+    let failed = headers.is_err();
+    if tx.blocking_send(headers.map(|h| (0, h))).is_err() || failed {
+        return;
+    }
+
+    let mut record = StringRecord::new();
+    loop {
+        match reader.read_record(&mut record) {
+            Ok(true) => {
+                let offset = reader.position().byte();
+                if offset > start && tx.blocking_send(Ok((offset, record.clone()))).is_err() {
+                    return;
+                }
+            }
+            Ok(false) => return,
+            Err(e) => {
+                let _ = tx.blocking_send(Err(e));
+                return;
+            }
+        }
+    }
+}
+
+/// How far we've read into a file. For JSON and CSV files the offset is in bytes of the
+/// (decompressed) file, for Parquet files it is in rows.
+#[derive(Clone, Debug, Encode, Decode, PartialEq, Eq)]
+pub struct FileReadState {
+    key: String,
+    offset: u64,
+    finished: bool,
+}
+
+pub fn tables() -> Vec<TableDescriptor> {
+    vec![arroyo_state::global_table("f", "file source state")]
+}
+
+pub(crate) fn is_assigned(key: &str, task_index: usize, parallelism: usize) -> bool {
+    arroyo_state::hash_key(&key) as usize % parallelism == task_index
+}
+
+/// Files in hidden directories or that start with `.` or `_` (like the staging files of the file
+/// system sink) are not read
+pub(crate) fn is_hidden(relative_key: &str) -> bool {
+    relative_key
+        .split('/')
+        .any(|part| part.starts_with('.') || part.starts_with('_'))
+}
+
+/// Event times may be given as RFC 3339 strings or as milliseconds since the epoch
+pub(crate) fn parse_timestamp(value: &Value) -> Option<SystemTime> {
+    match value {
+        Value::Number(n) => n.as_u64().map(from_millis),
+        Value::String(s) => match DateTime::parse_from_rfc3339(s) {
+            Ok(time) => Some(time.into()),
+            Err(_) => s.parse().ok().map(from_millis),
+        },
+        _ => None,
+    }
+}
+
+#[derive(StreamNode)]
+pub struct FileSourceFunc<T>
+where
+    T: DeserializeOwned + Data,
+{
+    path: String,
+    format: FileFormat,
+    monitor_interval: Option<Duration>,
+    timestamp_field: Option<String>,
+    // a delay between records, used to throttle reading in tests and demos
+    interval: Duration,
+    storage: Option<StorageClient>,
+    prefix: String,
+    state: HashMap<String, FileReadState>,
+    last_check: Instant,
+    _t: PhantomData<T>,
+}
+
+#[source_fn(out_t = T)]
+impl<T> FileSourceFunc<T>
+where
+    T: DeserializeOwned + Data,
+{
+    pub fn new(
+        path: &str,
+        format: FileFormat,
+        monitor_interval: Option<Duration>,
+        timestamp_field: Option<&str>,
+        interval: Duration,
+    ) -> Self {
+        info!("Creating FileSourceFunc for {}", path);
+
+        Self {
+            path: path.to_string(),
+            format,
+            monitor_interval,
+            timestamp_field: timestamp_field.map(|f| f.to_string()),
+            interval,
+            storage: None,
+            prefix: String::new(),
+            state: HashMap::new(),
+            last_check: Instant::now(),
+            _t: PhantomData,
+        }
+    }
+
+    fn name(&self) -> String {
+        "FileSource".to_string()
+    }
+
+    fn tables(&self) -> Vec<TableDescriptor> {
+        tables()
+    }
+
+    async fn on_start(&mut self, ctx: &mut Context<(), T>) {
+        let (storage, prefix) = StorageClient::for_url(&self.path);
+        self.storage = Some(storage);
+        self.prefix = prefix;
+
+        let task_index = ctx.task_info.task_index;
+        let parallelism = ctx.task_info.parallelism;
+
+        // state is restored from all subtasks, so after a rescale we pick up the files that are
+        // now assigned to us
+        let mut s: GlobalKeyedState<String, FileReadState, _> =
+            ctx.state.get_global_keyed_state('f').await;
+        self.state = s
+            .get_all()
+            .filter(|f| is_assigned(&f.key, task_index, parallelism))
+            .map(|f| (f.key.clone(), f.into_owned()))
+            .collect();
+    }
+
+    async fn list_files(&mut self, ctx: &Context<(), T>) -> Vec<String> {
+        let list_prefix = if self.prefix.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", self.prefix)
+        };
+
+        self.storage
+            .as_ref()
+            .unwrap()
+            .list(list_prefix.clone())
+            .await
+            .unwrap_or_else(|e| panic!("failed to list files in {}: {:?}", self.path, e))
+            .into_iter()
+            .filter(|key| !is_hidden(key.strip_prefix(&list_prefix).unwrap_or(key)))
+            .filter(|key| is_assigned(key, ctx.task_info.task_index, ctx.task_info.parallelism))
+            .collect()
+    }
+
+    async fn process_control_message(
+        &mut self,
+        msg: ControlMessage,
+        ctx: &mut Context<(), T>,
+    ) -> Option<SourceFinishType> {
+        match msg {
+            ControlMessage::Checkpoint(c) => {
+                debug!("starting checkpointing {}", ctx.task_info.task_index);
+                let mut s: GlobalKeyedState<String, FileReadState, _> =
+                    ctx.state.get_global_keyed_state('f').await;
+                for (key, state) in &self.state {
+                    s.insert(key.clone(), state.clone()).await;
+                }
+
+                if self.checkpoint(c, ctx).await {
+                    return Some(SourceFinishType::Immediate);
+                }
+            }
+            ControlMessage::Stop { mode } => {
+                info!("Stopping file source {:?}", mode);
+
+                match mode {
+                    StopMode::Graceful => {
+                        return Some(SourceFinishType::Graceful);
+                    }
+                    StopMode::Immediate => {
+                        return Some(SourceFinishType::Immediate);
+                    }
+                }
+            }
+            ControlMessage::Commit { .. } => {
+                // sources have nothing to commit
+            }
+            ControlMessage::QueryState {
+                table,
+                key,
+                respond,
+            } => {
+                let _ = respond.send(ctx.state.query(table, &key));
+            }
+        }
+        None
+    }
+
+    fn timestamp(&self, key: &str, field_value: Option<&Value>) -> SystemTime {
+        match &self.timestamp_field {
+            Some(field) => field_value.and_then(parse_timestamp).unwrap_or_else(|| {
+                panic!("Record in {} has no valid timestamp field {}", key, field)
+            }),
+            None => SystemTime::now(),
+        }
+    }
+
+    fn decode_json(&self, key: &str, value: Value) -> (SystemTime, T) {
+        let timestamp = self.timestamp(
+            key,
+            self.timestamp_field.as_ref().and_then(|f| value.get(f)),
+        );
+
+        let value = serde_json::from_value(value.clone()).unwrap_or_else(|e| {
+            panic!("Failed to deserialize record {} in {}: {:?}", value, key, e)
+        });
+
+        (timestamp, value)
+    }
+
+    async fn collect(
+        &mut self,
+        key: &str,
+        offset: u64,
+        (timestamp, value): (SystemTime, T),
+        ctx: &mut Context<(), T>,
+    ) -> Option<SourceFinishType> {
+        ctx.collector
+            .collect(Record {
+                timestamp,
+                key: None,
+                value,
+            })
+            .await;
+
+        self.state.get_mut(key).unwrap().offset = offset;
+
+        if !self.interval.is_zero() {
+            sleep(self.interval).await;
+        }
+
+        if self.last_check.elapsed() > CONTROL_CHECK_INTERVAL || !self.interval.is_zero() {
+            self.last_check = Instant::now();
+            if let Ok(msg) = ctx.control_rx.try_recv() {
+                return self.process_control_message(msg, ctx).await;
+            }
+        }
+
+        None
+    }
+
+    async fn read_file(&mut self, key: &str, ctx: &mut Context<(), T>) -> Option<SourceFinishType> {
+        let storage = self.storage.clone().unwrap();
+        let compression = Compression::for_key(key);
+        let start = self.state.get(key).unwrap().offset;
+
+        let finish = if self.format == FileFormat::Parquet && compression == Compression::None {
+            let Some(size) = storage
+                .size(key.to_string())
+                .await
+                .unwrap_or_else(|e| panic!("failed to read {}: {:?}", key, e))
+            else {
+                info!("{} was removed before it could be read", key);
+                return None;
+            };
+
+            info!("Reading {} from row {}", key, start);
+            let reader = ParquetFileReader {
+                storage,
+                key: key.to_string(),
+                size,
+            };
+            self.read_parquet(key, start, Box::new(reader), ctx).await
+        } else {
+            let Some(reader) = storage
+                .reader(key.to_string())
+                .await
+                .unwrap_or_else(|e| panic!("failed to read {}: {:?}", key, e))
+            else {
+                info!("{} was removed before it could be read", key);
+                return None;
+            };
+            let mut reader = compression.reader(reader);
+
+            info!("Reading {} from offset {}", key, start);
+            match self.format {
+                FileFormat::Json => self.read_json(key, start, reader, ctx).await,
+                FileFormat::Csv => self.read_csv(key, start, reader, ctx).await,
+                FileFormat::Parquet => {
+                    // parquet needs random access, which compressed files don't provide, so these
+                    // are decompressed into memory
+                    let mut data = vec![];
+                    reader.read_to_end(&mut data).await.unwrap_or_else(|e| {
+                        panic!("Failed to decompress parquet file {}: {:?}", key, e)
+                    });
+                    self.read_parquet(key, start, Box::new(Cursor::new(data)), ctx)
+                        .await
+                }
+            }
+        };
+
+        if finish.is_some() {
+            return finish;
+        }
+
+        self.state.get_mut(key).unwrap().finished = true;
+        None
+    }
+
+    async fn read_json(
+        &mut self,
+        key: &str,
+        start: u64,
+        mut reader: Box<dyn AsyncBufRead + Unpin + Send>,
+        ctx: &mut Context<(), T>,
+    ) -> Option<SourceFinishType> {
+        tokio::io::copy(&mut (&mut reader).take(start), &mut tokio::io::sink())
+            .await
+            .unwrap_or_else(|e| panic!("Failed to read {}: {:?}", key, e));
+
+        let mut offset = start;
+        let mut line = vec![];
+        loop {
+            line.clear();
+            let read = reader
+                .read_until(b'\n', &mut line)
+                .await
+                .unwrap_or_else(|e| panic!("Failed to read {}: {:?}", key, e));
+            if read == 0 {
+                return None;
+            }
+            offset += read as u64;
+
+            if line.iter().all(|b| b.is_ascii_whitespace()) {
+                continue;
+            }
+
+            let value = serde_json::from_slice(&line).unwrap_or_else(|_| {
+                panic!(
+                    "Failed to deserialize line in {}: {}",
+                    key,
+                    String::from_utf8_lossy(&line)
+                )
+            });
+            let record = self.decode_json(key, value);
+
+            if let Some(finish) = self.collect(key, offset, record, ctx).await {
+                return Some(finish);
+            }
+        }
+    }
+
+    async fn read_csv(
+        &mut self,
+        key: &str,
+        start: u64,
+        reader: Box<dyn AsyncBufRead + Unpin + Send>,
+        ctx: &mut Context<(), T>,
+    ) -> Option<SourceFinishType> {
+        // the csv reader is synchronous, so it runs on a blocking thread and sends the records
+        // back; it stops once we stop receiving them
+        let (tx, mut rx) = channel(CSV_BUFFER_SIZE);
+        let reader = SyncIoBridge::new(reader);
+        let handle = tokio::task::spawn_blocking(move || read_csv_records(reader, start, tx));
+
+        // the header row is always sent first, unless the reader thread panicked
+        let (_, headers) = rx
+            .recv()
+            .await
+            .unwrap_or_else(|| panic!("Failed to read CSV file {}", key))
+            .unwrap_or_else(|e| panic!("Failed to read CSV header of {}: {:?}", key, e));
+        let timestamp_idx = self
+            .timestamp_field
+            .as_ref()
+            .and_then(|f| headers.iter().position(|h| h == f));
+
+        while let Some(next) = rx.recv().await {
+            let (offset, record) =
+                next.unwrap_or_else(|e| panic!("Failed to read CSV record in {}: {:?}", key, e));
+            let timestamp = self.timestamp(
+                key,
+                timestamp_idx
+                    .and_then(|i| record.get(i))
+                    .map(|t| Value::String(t.to_string()))
+                    .as_ref(),
+            );
+            let value = record.deserialize(Some(&headers)).unwrap_or_else(|e| {
+                panic!(
+                    "Failed to deserialize record {:?} in {}: {:?}",
+                    record, key, e
+                )
+            });
+
+            if let Some(finish) = self.collect(key, offset, (timestamp, value), ctx).await {
+                return Some(finish);
+            }
+        }
+
+        // the reader thread may have stopped early by panicking
+        handle
+            .await
+            .unwrap_or_else(|e| panic!("Failed to read CSV file {}: {:?}", key, e));
+        None
+    }
+
+    async fn read_parquet(
+        &mut self,
+        key: &str,
+        start: u64,
+        reader: Box<dyn AsyncFileReader>,
+        ctx: &mut Context<(), T>,
+    ) -> Option<SourceFinishType> {
+        let builder = ParquetRecordBatchStreamBuilder::new(reader)
+            .await
+            .unwrap_or_else(|e| panic!("Failed to read parquet file {}: {:?}", key, e));
+
+        // row groups that we've already read completely aren't fetched again
+        let mut rows = 0;
+        let mut row_groups = vec![];
+        for (i, row_group) in builder.metadata().row_groups().iter().enumerate() {
+            if row_groups.is_empty() && rows + row_group.num_rows() as u64 <= start {
+                rows += row_group.num_rows() as u64;
+            } else {
+                row_groups.push(i);
+            }
+        }
+
+        let mut stream = builder
+            .with_row_groups(row_groups)
+            .build()
+            .unwrap_or_else(|e| panic!("Failed to read parquet file {}: {:?}", key, e));
+
+        while let Some(batch) = stream.next().await {
+            let batch =
+                batch.unwrap_or_else(|e| panic!("Failed to read parquet file {}: {:?}", key, e));
+
+            let batch_rows = batch.num_rows() as u64;
+            if rows + batch_rows <= start {
+                rows += batch_rows;
+                continue;
+            }
+
+            let skip = start.saturating_sub(rows);
+            rows += skip;
+            let batch = batch.slice(skip as usize, (batch_rows - skip) as usize);
+
+            for row in record_batches_to_json_rows(&[batch]).unwrap() {
+                rows += 1;
+                let record = self.decode_json(key, Value::Object(row));
+                if let Some(finish) = self.collect(key, rows, record, ctx).await {
+                    return Some(finish);
+                }
+            }
+        }
+
+        None
+    }
+
+    async fn run(&mut self, ctx: &mut Context<(), T>) -> SourceFinishType {
+        loop {
+            for key in self.list_files(ctx).await {
+                let state = self
+                    .state
+                    .entry(key.clone())
+                    .or_insert_with(|| FileReadState {
+                        key: key.clone(),
+                        offset: 0,
+                        finished: false,
+                    });
+
+                if state.finished {
+                    debug!("skipping already read file: {}", key);
+                    continue;
+                }
+
+                if let Some(finish) = self.read_file(&key, ctx).await {
+                    return finish;
+                }
+            }
+
+            let Some(monitor_interval) = self.monitor_interval else {
+                break;
+            };
+
+            // wait for new files to arrive, handling control messages while we do
+            let deadline = Instant::now() + monitor_interval;
+            while Instant::now() < deadline {
+                select! {
+                    msg = ctx.control_rx.recv() => {
+                        let Some(msg) = msg else {
+                            info!("control channel closed, stopping file source");
+                            return SourceFinishType::Immediate;
+                        };
+                        if let Some(finish) = self.process_control_message(msg, ctx).await {
+                            return finish;
+                        }
+                    }
+                    _ = sleep(deadline - Instant::now()) => {}
+                }
+            }
+        }
+
+        info!("file source finished");
+        SourceFinishType::Final
+    }
+}
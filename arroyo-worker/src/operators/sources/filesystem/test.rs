@@ -0,0 +1,247 @@
+use std::io::Write;
+use std::time::Duration;
+
+use crate::engine::{Context, OutQueue, QueueItem};
+use crate::operators::sources::filesystem::{
+    self, is_hidden, parse_timestamp, Compression, FileFormat, FileSourceFunc,
+};
+use crate::SourceFinishType;
+use arrow::array::{Int64Array, StringArray, UInt64Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use arroyo_rpc::ControlMessage;
+use arroyo_types::{from_millis, Message};
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+#[derive(Debug, Clone, bincode::Encode, bincode::Decode, Serialize, Deserialize, PartialEq)]
+struct TestData {
+    i: u64,
+    name: String,
+}
+
+async fn test_context() -> (
+    Context<(), TestData>,
+    Sender<ControlMessage>,
+    Receiver<QueueItem>,
+) {
+    let mut task_info = arroyo_types::get_test_task_info();
+    task_info.job_id = format!("file-source-{}", rand::thread_rng().gen::<u64>());
+
+    let (control_tx, control_rx) = channel(128);
+    let (command_tx, _from_control_rx) = channel(128);
+    let (data_tx, recv) = channel(128);
+
+    let ctx = Context::new(
+        task_info,
+        None,
+        control_rx,
+        command_tx,
+        1,
+        vec![vec![OutQueue::new(data_tx, false)]],
+        filesystem::tables(),
+    )
+    .await;
+
+    (ctx, control_tx, recv)
+}
+
+async fn read_all(dir: &str, format: FileFormat) -> Vec<(u64, String, u64)> {
+    let mut source: FileSourceFunc<TestData> =
+        FileSourceFunc::new(dir, format, None, Some("ts"), Duration::ZERO);
+
+    let (mut ctx, _to_control_tx, mut recv) = test_context().await;
+
+    tokio::spawn(async move {
+        source.on_start(&mut ctx).await;
+        source.run(&mut ctx).await;
+    });
+
+    let mut records = vec![];
+    while let Some(item) = recv.recv().await {
+        let msg: Message<(), TestData> = item.into();
+        if let Message::Record(record) = msg {
+            records.push((
+                record.value.i,
+                record.value.name,
+                arroyo_types::to_millis(record.timestamp),
+            ));
+        }
+    }
+    records.sort();
+    records
+}
+
+#[tokio::test]
+async fn test_json_and_gzip() {
+    let dir = std::env::temp_dir().join(format!(
+        "arroyo-file-source-{}",
+        rand::thread_rng().gen::<u64>()
+    ));
+    std::fs::create_dir_all(dir.join("_staging")).unwrap();
+
+    std::fs::write(
+        dir.join("a.json"),
+        "{\"i\": 1, \"name\": \"a\", \"ts\": 1000}\n\n{\"i\": 2, \"name\": \"b\", \"ts\": \"1970-01-01T00:00:02Z\"}\n",
+    )
+    .unwrap();
+
+    let mut gz = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    gz.write_all(b"{\"i\": 3, \"name\": \"c\", \"ts\": 3000}")
+        .unwrap();
+    std::fs::write(dir.join("b.json.gz"), gz.finish().unwrap()).unwrap();
+
+    // staged files from a sink writing to the same directory are not read
+    std::fs::write(
+        dir.join("_staging/c.json"),
+        "{\"i\": 4, \"name\": \"d\", \"ts\": 4000}\n",
+    )
+    .unwrap();
+
+    assert_eq!(
+        vec![
+            (1, "a".to_string(), 1000),
+            (2, "b".to_string(), 2000),
+            (3, "c".to_string(), 3000)
+        ],
+        read_all(dir.to_str().unwrap(), FileFormat::Json).await
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_csv() {
+    let dir = std::env::temp_dir().join(format!(
+        "arroyo-file-source-{}",
+        rand::thread_rng().gen::<u64>()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    std::fs::write(
+        dir.join("data.csv"),
+        "name,i,ts\nfirst,1,1000\nsecond,2,2000\n",
+    )
+    .unwrap();
+
+    std::fs::write(
+        dir.join("data.csv.zst"),
+        zstd::encode_all(&b"ts,i,name\n3000,3,third\n"[..], 0).unwrap(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        vec![
+            (1, "first".to_string(), 1000),
+            (2, "second".to_string(), 2000),
+            (3, "third".to_string(), 3000)
+        ],
+        read_all(dir.to_str().unwrap(), FileFormat::Csv).await
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_parquet() {
+    let dir = std::env::temp_dir().join(format!(
+        "arroyo-file-source-{}",
+        rand::thread_rng().gen::<u64>()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("i", DataType::UInt64, false),
+        Field::new("name", DataType::Utf8, false),
+        Field::new("ts", DataType::Int64, false),
+    ]));
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(UInt64Array::from(vec![1, 2, 3])),
+            Arc::new(StringArray::from(vec!["a", "b", "c"])),
+            Arc::new(Int64Array::from(vec![1000, 2000, 3000])),
+        ],
+    )
+    .unwrap();
+
+    // small row groups, so that the file is read through several ranged reads
+    let properties = WriterProperties::builder()
+        .set_max_row_group_size(2)
+        .build();
+    let mut data = vec![];
+    let mut writer = ArrowWriter::try_new(&mut data, schema, Some(properties)).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+    std::fs::write(dir.join("a.parquet"), &data).unwrap();
+    std::fs::write(
+        dir.join("b.parquet.zst"),
+        zstd::encode_all(&data[..], 0).unwrap(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        vec![
+            (1, "a".to_string(), 1000),
+            (1, "a".to_string(), 1000),
+            (2, "b".to_string(), 2000),
+            (2, "b".to_string(), 2000),
+            (3, "c".to_string(), 3000),
+            (3, "c".to_string(), 3000),
+        ],
+        read_all(dir.to_str().unwrap(), FileFormat::Parquet).await
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_stops_when_control_channel_closes() {
+    let dir = std::env::temp_dir().join(format!(
+        "arroyo-file-source-{}",
+        rand::thread_rng().gen::<u64>()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut source: FileSourceFunc<TestData> = FileSourceFunc::new(
+        dir.to_str().unwrap(),
+        FileFormat::Json,
+        Some(Duration::from_millis(10)),
+        Some("ts"),
+        Duration::ZERO,
+    );
+    let (mut ctx, control_tx, _recv) = test_context().await;
+    drop(control_tx);
+
+    source.on_start(&mut ctx).await;
+    let finish = tokio::time::timeout(Duration::from_secs(10), source.run(&mut ctx))
+        .await
+        .expect("file source did not stop");
+    assert!(matches!(finish, SourceFinishType::Immediate));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_helpers() {
+    assert!(is_hidden("_staging/2023/part-0.json"));
+    assert!(is_hidden("2023/.part-0.json"));
+    assert!(!is_hidden("2023/part-0.json"));
+
+    assert_eq!(Compression::Gzip, Compression::for_key("a/b.json.gz"));
+    assert_eq!(Compression::Zstd, Compression::for_key("a/b.csv.zst"));
+    assert_eq!(Compression::None, Compression::for_key("a/b.parquet"));
+
+    assert_eq!(Some(from_millis(1500)), parse_timestamp(&json!(1500)));
+    assert_eq!(Some(from_millis(1500)), parse_timestamp(&json!("1500")));
+    assert_eq!(
+        Some(from_millis(1_680_000_000_000)),
+        parse_timestamp(&json!("2023-03-28T10:40:00Z"))
+    );
+    assert_eq!(None, parse_timestamp(&json!("yesterday")));
+}
@@ -1,21 +1,25 @@
 use crate::engine::{Context, StreamNode};
 use crate::SourceFinishType;
 use arroyo_macro::source_fn;
+use arroyo_metrics::counter_for_task;
 use arroyo_rpc::grpc::TableDescriptor;
-use arroyo_rpc::{grpc::StopMode, ControlMessage};
+use arroyo_rpc::{grpc::StopMode, ControlMessage, ControlResp};
 use arroyo_state::tables::GlobalKeyedState;
 use arroyo_types::*;
 use bincode::{Decode, Encode};
 use governor::{Quota, RateLimiter};
 use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+use rdkafka::error::KafkaError;
+use rdkafka::message::Headers;
 use rdkafka::{ClientConfig, Message as KMessage, Offset, TopicPartitionList};
-use serde::de::DeserializeOwned;
-use std::collections::HashMap;
+use serde::de::{DeserializeOwned, Unexpected};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
 use std::marker::PhantomData;
 use std::num::NonZeroU32;
 use std::time::Duration;
 use tokio::select;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 #[cfg(test)]
 mod test;
@@ -42,6 +46,17 @@ pub enum SerializationMode {
     JsonSchemaRegistry,
 }
 
+/// A property of the Kafka message (rather than its payload) that can be exposed to
+/// the pipeline as a virtual column of the source
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MetadataField {
+    Key,
+    Partition,
+    Offset,
+    Timestamp,
+    Header(String),
+}
+
 #[derive(StreamNode, Clone)]
 pub struct KafkaSourceFunc<T>
 where
@@ -53,6 +68,9 @@ where
     serialization_mode: SerializationMode,
     client_configs: HashMap<String, String>,
     messages_per_second: NonZeroU32,
+    metadata_fields: Vec<(String, MetadataField)>,
+    // where the source starts and stops reading, if the job is bounded
+    bounds: SourceBounds,
     _t: PhantomData<T>,
 }
 
@@ -62,10 +80,86 @@ pub struct KafkaState {
     offset: i64,
 }
 
+// how often we check the topic for partitions that were added after the source started
+const PARTITION_DISCOVERY_INTERVAL: Duration = Duration::from_secs(60);
+
+// how long we wait for kafka to look up offsets when resolving the source's bounds
+const OFFSET_LOOKUP_TIMEOUT: Duration = Duration::from_secs(30);
+
 pub fn tables() -> Vec<TableDescriptor> {
     vec![arroyo_state::global_table("k", "kafka source state")]
 }
 
+/// Partitions are assigned to subtasks by id, which lets every subtask independently arrive
+/// at the same assignment and keeps existing assignments stable as partitions are added
+pub(crate) fn assigned_partitions(
+    partitions: impl IntoIterator<Item = i32>,
+    task_index: usize,
+    parallelism: usize,
+) -> Vec<i32> {
+    let mut assigned: Vec<i32> = partitions
+        .into_iter()
+        .filter(|p| *p as usize % parallelism == task_index)
+        .collect();
+    assigned.sort();
+    assigned
+}
+
+/// The bounds the controller has configured for the source with this operator id
+fn job_source_bounds(operator_id: &str) -> Option<SourceBounds> {
+    let bounds = std::env::var(SOURCE_BOUNDS_ENV).ok()?;
+    let bounds: JobBounds = serde_json::from_str(&bounds)
+        .unwrap_or_else(|e| panic!("invalid {}: {:?}", SOURCE_BOUNDS_ENV, e));
+    Some(bounds.for_operator(operator_id))
+}
+
+fn header_value<M: KMessage>(msg: &M, name: &str) -> Value {
+    msg.headers()
+        .and_then(|headers| {
+            (0..headers.count())
+                .filter_map(|i| headers.get(i))
+                .find(|(k, _)| *k == name)
+        })
+        .map(|(_, v)| Value::String(String::from_utf8_lossy(v).to_string()))
+        .unwrap_or(Value::Null)
+}
+
+/// Adds the requested metadata of `msg` to the deserialized payload `value` as additional
+/// fields, so that it can be deserialized into a type that contains the metadata columns. Fails
+/// like any other message that doesn't match the schema if the payload is not a JSON object.
+pub(crate) fn add_metadata_fields<M: KMessage>(
+    value: &mut Value,
+    fields: &[(String, MetadataField)],
+    msg: &M,
+) -> Result<(), serde_json::Error> {
+    let Some(obj) = value.as_object_mut() else {
+        return Err(serde::de::Error::invalid_type(
+            Unexpected::Other("non-object JSON value"),
+            &"a JSON object",
+        ));
+    };
+
+    for (name, field) in fields {
+        let v = match field {
+            MetadataField::Key => msg
+                .key()
+                .map(|k| Value::String(String::from_utf8_lossy(k).to_string()))
+                .unwrap_or(Value::Null),
+            MetadataField::Partition => msg.partition().into(),
+            MetadataField::Offset => msg.offset().into(),
+            MetadataField::Timestamp => msg
+                .timestamp()
+                .to_millis()
+                .map(|millis| serde_json::to_value(from_millis(millis as u64)).unwrap())
+                .unwrap_or(Value::Null),
+            MetadataField::Header(header) => header_value(msg, header),
+        };
+
+        obj.insert(name.clone(), v);
+    }
+    Ok(())
+}
+
 #[source_fn(out_k = (), out_t = T)]
 impl<T> KafkaSourceFunc<T>
 where
@@ -78,6 +172,7 @@ where
         serialization_mode: SerializationMode,
         messages_per_second: u32,
         client_configs: Vec<(&str, &str)>,
+        metadata_fields: Vec<(&str, MetadataField)>,
     ) -> Self {
         Self {
             topic: topic.to_string(),
@@ -89,6 +184,11 @@ where
                 .map(|(key, value)| (key.to_string(), value.to_string()))
                 .collect(),
             messages_per_second: NonZeroU32::new(messages_per_second).unwrap(),
+            metadata_fields: metadata_fields
+                .into_iter()
+                .map(|(name, field)| (name.to_string(), field))
+                .collect(),
+            bounds: SourceBounds::default(),
             _t: PhantomData,
         }
     }
@@ -101,13 +201,75 @@ where
         tables()
     }
 
-    async fn get_consumer(&mut self, ctx: &mut Context<(), T>) -> Result<StreamConsumer, ()> {
+    /// Deserializes the payload of `msg`, failing if it doesn't match the schema of the source
+    fn deserialize<M: KMessage>(&self, msg: &M, payload: &[u8]) -> Result<T, String> {
+        let payload = match self.serialization_mode {
+            SerializationMode::Json => payload,
+            SerializationMode::JsonSchemaRegistry => payload
+                .get(5..)
+                .ok_or_else(|| "message is missing the schema registry header".to_string())?,
+        };
+
+        let result = if self.metadata_fields.is_empty() {
+            serde_json::from_slice(payload)
+        } else {
+            serde_json::from_slice(payload).and_then(|mut value: Value| {
+                add_metadata_fields(&mut value, &self.metadata_fields, msg)?;
+                serde_json::from_value(value)
+            })
+        };
+
+        result.map_err(|e| {
+            format!(
+                "failed to deserialize message: {:?}: {}",
+                e,
+                String::from_utf8_lossy(payload)
+            )
+        })
+    }
+
+    fn fetch_partitions(&self, consumer: &StreamConsumer) -> Result<Vec<i32>, String> {
+        let metadata = consumer
+            .fetch_metadata(Some(&self.topic), Duration::from_secs(30))
+            .map_err(|e| e.to_string())?;
+
+        let Some(topic) = metadata.topics().iter().find(|t| t.name() == self.topic) else {
+            return Err(format!("topic {} not found in kafka metadata", self.topic));
+        };
+
+        Ok(topic.partitions().iter().map(|p| p.id()).collect())
+    }
+
+    fn assign(&self, consumer: &StreamConsumer, offsets: &HashMap<i32, i64>) {
+        let topic_map: HashMap<_, _> = offsets
+            .iter()
+            .map(|(partition, offset)| {
+                ((self.topic.clone(), *partition), Offset::from_raw(*offset))
+            })
+            .collect();
+
+        let topic_partitions = TopicPartitionList::from_topic_map(&topic_map).unwrap();
+
+        consumer.assign(&topic_partitions).unwrap();
+    }
+
+    /// Creates the consumer and assigns it our partitions, returning the raw offset that will be
+    /// read next for each of them
+    async fn get_consumer(
+        &mut self,
+        ctx: &mut Context<(), T>,
+    ) -> Result<(StreamConsumer, HashMap<i32, i64>), ()> {
         info!("Creating kafka consumer for {}", self.bootstrap_servers);
         let mut client_config = ClientConfig::new();
 
         for (key, value) in &self.client_configs {
             client_config.set(key, value);
         }
+        // a bounded source relies on partition EOFs to notice that it's read everything up to
+        // its end offsets
+        if self.bounds.end.is_some() {
+            client_config.set("enable.partition.eof", "true");
+        }
         let consumer: StreamConsumer = client_config
             .set("bootstrap.servers", &self.bootstrap_servers)
             .set("enable.auto.commit", "false")
@@ -121,69 +283,316 @@ where
             .create()
             .expect("Consumer creation failed");
 
+        // the global table contains the state of all subtasks, so after a rescale we pick up
+        // the partitions that were previously read by other subtasks
         let mut s: GlobalKeyedState<i32, KafkaState, _> =
             ctx.state.get_global_keyed_state('k').await;
-        let state: Vec<&KafkaState> = s.get_all();
-        let state: HashMap<i32, KafkaState> = state.iter().map(|s| (s.partition, **s)).collect();
-        let metadata = consumer
-            .fetch_metadata(Some(&self.topic), Duration::from_secs(30))
+        let state: HashMap<i32, KafkaState> =
+            s.get_all().map(|s| (s.partition, s.into_owned())).collect();
+
+        // every partition we've been assigned is persisted, so when restoring any partition
+        // missing from the state must have been created since and is read from the beginning
+        let default_offset = if state.is_empty() {
+            self.offset_mode.get_offset()
+        } else {
+            Offset::Beginning
+        };
+
+        let partitions = self
+            .fetch_partitions(&consumer)
             .expect("failed to fetch kafka metadata");
 
         info!("Fetched metadata for topic {}", self.topic);
 
-        let our_partitions: HashMap<_, _> = {
-            let partitions = metadata.topics()[0].partitions();
-            partitions
+        let partitions = assigned_partitions(
+            partitions,
+            ctx.task_info.task_index,
+            ctx.task_info.parallelism,
+        );
+
+        // a bounded start only applies to a source that has no state to restore
+        let start_offsets = match &self.bounds.start {
+            Some(start) if state.is_empty() => self.resolve_position(&consumer, start, &partitions),
+            _ => HashMap::new(),
+        };
+
+        let offsets: HashMap<i32, i64> = partitions
+            .into_iter()
+            .map(|p| {
+                let offset = state
+                    .get(&p)
+                    .map(|s| s.offset)
+                    .or_else(|| start_offsets.get(&p).copied())
+                    .unwrap_or_else(|| default_offset.to_raw().unwrap());
+                (p, offset)
+            })
+            .collect();
+
+        self.assign(&consumer, &offsets);
+
+        Ok((consumer, offsets))
+    }
+
+    /// The raw offset of `position` in each of the partitions, leaving out those it doesn't bound.
+    /// Timestamps after the last message of a partition resolve to its end.
+    fn resolve_position(
+        &self,
+        consumer: &StreamConsumer,
+        position: &SourcePosition,
+        partitions: &[i32],
+    ) -> HashMap<i32, i64> {
+        match position {
+            SourcePosition::Offsets { offsets } => partitions
                 .iter()
-                .enumerate()
-                .filter(|(i, _)| i % ctx.task_info.parallelism == ctx.task_info.task_index)
-                .map(|(_, p)| {
-                    let offset = state
-                        .get(&p.id())
-                        .map(|s| Offset::Offset(s.offset))
-                        .unwrap_or_else(|| self.offset_mode.get_offset());
-
-                    ((self.topic.clone(), p.id()), offset)
-                })
-                .collect()
+                .filter_map(|p| Some((*p, *offsets.get(p)?)))
+                .collect(),
+            SourcePosition::Timestamp { micros } => {
+                let mut timestamps = TopicPartitionList::new();
+                for p in partitions {
+                    timestamps
+                        .add_partition_offset(
+                            &self.topic,
+                            *p,
+                            Offset::Offset((micros / 1000) as i64),
+                        )
+                        .unwrap();
+                }
+
+                consumer
+                    .offsets_for_times(timestamps, OFFSET_LOOKUP_TIMEOUT)
+                    .expect("failed to look up kafka offsets for timestamp")
+                    .elements_for_topic(&self.topic)
+                    .iter()
+                    .map(|e| (e.partition(), e.offset().to_raw().unwrap()))
+                    .collect()
+            }
+            SourcePosition::Latest => partitions
+                .iter()
+                .map(|p| (*p, Offset::End.to_raw().unwrap()))
+                .collect(),
+        }
+    }
+
+    /// The offset before which each bounded partition stops, with the end of a partition resolved
+    /// to its current high watermark
+    fn end_offsets(
+        &self,
+        consumer: &StreamConsumer,
+        end: &SourcePosition,
+        partitions: &[i32],
+    ) -> HashMap<i32, i64> {
+        self.resolve_position(consumer, end, partitions)
+            .into_iter()
+            .map(|(p, offset)| {
+                if offset == Offset::End.to_raw().unwrap() {
+                    let (_, high) = consumer
+                        .fetch_watermarks(&self.topic, p, OFFSET_LOOKUP_TIMEOUT)
+                        .expect("failed to fetch kafka watermarks");
+                    (p, high)
+                } else {
+                    (p, offset)
+                }
+            })
+            .collect()
+    }
+
+    // replaces the logical offsets (like the end of a partition) of partitions that haven't read
+    // anything yet with the positions the consumer has resolved them to
+    fn resolve_logical_offsets(&self, consumer: &StreamConsumer, offsets: &mut HashMap<i32, i64>) {
+        let positions = match consumer.position() {
+            Ok(positions) => positions,
+            Err(e) => {
+                warn!(
+                    "Failed to fetch kafka positions for topic {}: {:?}",
+                    self.topic, e
+                );
+                return;
+            }
         };
 
-        let topic_partitions = TopicPartitionList::from_topic_map(&our_partitions).unwrap();
+        for e in positions.elements_for_topic(&self.topic) {
+            if let (Some(offset), Offset::Offset(position)) =
+                (offsets.get_mut(&e.partition()), e.offset())
+            {
+                if *offset < 0 {
+                    *offset = position;
+                }
+            }
+        }
+    }
 
-        consumer.assign(&topic_partitions).unwrap();
+    // stops reading from a partition that has reached its end offset
+    fn finish_partition(
+        &self,
+        consumer: &StreamConsumer,
+        partition: i32,
+        finished: &mut HashSet<i32>,
+    ) {
+        if finished.insert(partition) {
+            debug!(
+                "Partition {} of topic {} reached its end",
+                partition, self.topic
+            );
+            let mut topic_partitions = TopicPartitionList::new();
+            topic_partitions.add_partition(&self.topic, partition);
+            if let Err(e) = consumer.pause(&topic_partitions) {
+                warn!("Failed to pause finished partition {}: {:?}", partition, e);
+            }
+        }
+    }
 
-        Ok(consumer)
+    /// Lets the rest of the job know that this subtask has read all of its input: operators
+    /// downstream get a final watermark, flushing their windows, and the controller finishes the
+    /// job with a final checkpoint once every source subtask has done the same
+    async fn reached_end(&mut self, ctx: &mut Context<(), T>) {
+        info!(
+            "Kafka source {}-{} reached the end of its input",
+            self.topic, ctx.task_info.task_index
+        );
+
+        ctx.collector
+            .broadcast(Message::Watermark(from_millis(u64::MAX)))
+            .await;
+
+        ctx.control_tx
+            .send(ControlResp::TaskReachedEnd {
+                operator_id: ctx.task_info.operator_id.clone(),
+                task_index: ctx.task_info.task_index,
+            })
+            .await
+            .unwrap();
     }
 
     async fn run(&mut self, ctx: &mut Context<(), T>) -> SourceFinishType {
-        let consumer = self.get_consumer(ctx).await.unwrap();
+        if let Some(bounds) = job_source_bounds(&ctx.task_info.operator_id) {
+            self.bounds = bounds;
+        }
+
+        let (consumer, mut offsets) = self.get_consumer(ctx).await.unwrap();
+
+        // partitions that were added after a bounded source started aren't read
+        let end_offsets: Option<HashMap<i32, i64>> = self.bounds.end.as_ref().map(|end| {
+            let partitions: Vec<i32> = offsets.keys().copied().collect();
+            self.end_offsets(&consumer, end, &partitions)
+        });
+
+        let mut finished = HashSet::new();
+        for (p, end) in end_offsets.iter().flatten() {
+            if offsets[p] >= 0 && offsets[p] >= *end {
+                self.finish_partition(&consumer, *p, &mut finished);
+            }
+        }
+        let mut at_end = false;
 
         let rate_limiter = RateLimiter::direct(Quota::per_second(self.messages_per_second));
-        let mut offsets = HashMap::new();
+        let deserialization_errors = counter_for_task(
+            &ctx.task_info,
+            "arroyo_worker_deserialization_errors",
+            "Count of messages that could not be deserialized and were skipped",
+            HashMap::new(),
+        );
+
+        let mut partition_discovery = tokio::time::interval_at(
+            tokio::time::Instant::now() + PARTITION_DISCOVERY_INTERVAL,
+            PARTITION_DISCOVERY_INTERVAL,
+        );
+        partition_discovery.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
         loop {
+            if !at_end && end_offsets.is_some() && offsets.keys().all(|p| finished.contains(p)) {
+                at_end = true;
+                self.reached_end(ctx).await;
+            }
+
             select! {
-                message = consumer.recv() => {
+                message = consumer.recv(), if !at_end => {
                     match message {
                         Ok(msg) => {
-                            if let Some(v) = msg.payload() {
-                                ctx.collector.collect(Record {
-                                    timestamp: from_millis(msg.timestamp().to_millis().unwrap() as u64),
-                                    key: None,
-                                    value: match self.serialization_mode {
-                                        SerializationMode::Json => serde_json::from_slice(v)
-                                            .unwrap_or_else(|_| panic!("Failed to deserialize message: {}", String::from_utf8_lossy(v))),
-                                        SerializationMode::JsonSchemaRegistry => serde_json::from_slice(&v[5..]).unwrap(),
+                            let partition = msg.partition();
+                            let end = end_offsets.as_ref().and_then(|e| e.get(&partition)).copied();
+                            if end.map(|end| msg.offset() >= end).unwrap_or(false) {
+                                // past the end of the partition, so not part of the input
+                                self.finish_partition(&consumer, partition, &mut finished);
+                            } else if let Some(v) = msg.payload() {
+                                match self.deserialize(&msg, v) {
+                                    Ok(value) => {
+                                        ctx.collector.collect(Record {
+                                            timestamp: from_millis(msg.timestamp().to_millis().unwrap() as u64),
+                                            key: None,
+                                            value,
+                                        }).await;
                                     }
-                                }).await;
-                                offsets.insert(msg.partition(), msg.offset());
+                                    Err(e) => {
+                                        // bad data is skipped, so that one message can't stop the job
+                                        warn!("Skipping message at offset {} of partition {} in topic {}: {}",
+                                            msg.offset(), partition, self.topic, e);
+                                        if let Some(c) = &deserialization_errors {
+                                            c.inc();
+                                        }
+                                    }
+                                }
+                                offsets.insert(partition, msg.offset() + 1);
                                 rate_limiter.until_ready().await;
                             }
+
+                            if end == Some(msg.offset() + 1) {
+                                self.finish_partition(&consumer, partition, &mut finished);
+                            }
                         },
+                        Err(KafkaError::PartitionEOF(partition)) => {
+                            // we've read everything that was in the partition, which includes all
+                            // of its input unless its end offset hasn't been written yet
+                            let end = end_offsets.as_ref().and_then(|e| e.get(&partition)).copied();
+                            if let Some(end) = end {
+                                let high = match consumer.fetch_watermarks(&self.topic, partition, OFFSET_LOOKUP_TIMEOUT) {
+                                    Ok((_, high)) => high,
+                                    Err(e) => {
+                                        warn!("Failed to fetch watermarks for partition {}: {:?}", partition, e);
+                                        -1
+                                    }
+                                };
+                                if high >= end {
+                                    // skip anything between the last message and the end, like
+                                    // transaction markers, when restoring
+                                    offsets.insert(partition, offsets[&partition].max(end));
+                                    self.finish_partition(&consumer, partition, &mut finished);
+                                }
+                            }
+                        }
                         Err(err) => {
                             error!("encountered error {}", err)
                         }
                     }
                 }
+                _ = partition_discovery.tick(), if end_offsets.is_none() => {
+                    match self.fetch_partitions(&consumer) {
+                        Ok(partitions) => {
+                            let new_partitions: Vec<i32> = assigned_partitions(
+                                partitions,
+                                ctx.task_info.task_index,
+                                ctx.task_info.parallelism,
+                            )
+                            .into_iter()
+                            .filter(|p| !offsets.contains_key(p))
+                            .collect();
+
+                            if !new_partitions.is_empty() {
+                                info!("Discovered new partitions {:?} for topic {}", new_partitions, self.topic);
+                                // re-assigning resumes the existing partitions from the offsets we've
+                                // tracked, so those that haven't read anything yet must not go back
+                                // to the beginning or end they started from
+                                self.resolve_logical_offsets(&consumer, &mut offsets);
+                                for p in new_partitions {
+                                    offsets.insert(p, Offset::Beginning.to_raw().unwrap());
+                                }
+                                self.assign(&consumer, &offsets);
+                            }
+                        }
+                        Err(e) => {
+                            warn!("Failed to refresh kafka metadata for topic {}: {:?}", self.topic, e);
+                        }
+                    }
+                }
                 control_message = ctx.control_rx.recv() => {
                     match control_message {
                         Some(ControlMessage::Checkpoint(c)) => {
@@ -191,13 +600,14 @@ where
                             let mut topic_partitions = TopicPartitionList::new();
                             let mut s = ctx.state.get_global_keyed_state('k').await;
                             for (partition, offset) in &offsets {
-                                let partition2 = partition;
                                 s.insert(*partition, KafkaState {
-                                    partition: *partition2,
-                                    offset: *offset + 1,
+                                    partition: *partition,
+                                    offset: *offset,
                                 }).await;
-                                topic_partitions.add_partition_offset(
-                                    &self.topic, *partition, Offset::Offset(*offset)).unwrap();
+                                if *offset >= 0 {
+                                    topic_partitions.add_partition_offset(
+                                        &self.topic, *partition, Offset::Offset(*offset)).unwrap();
+                                }
                             }
 
                             consumer.commit(&topic_partitions, CommitMode::Async).unwrap();
@@ -217,6 +627,12 @@ where
                                 }
                             }
                         }
+                        Some(ControlMessage::Commit { .. }) => {
+                            // sources have nothing to commit
+                        }
+                        Some(ControlMessage::QueryState { table, key, respond }) => {
+                            let _ = respond.send(ctx.state.query(table, &key));
+                        }
                         None => {
 
                         }
@@ -4,13 +4,19 @@ use std::time::{Duration, SystemTime};
 
 use crate::engine::{Context, OutQueue, QueueItem};
 use crate::operators::sources::kafka;
-use crate::operators::sources::kafka::{KafkaSourceFunc, OffsetMode};
+use crate::operators::sources::kafka::{
+    add_metadata_fields, assigned_partitions, KafkaSourceFunc, MetadataField, OffsetMode,
+    SerializationMode,
+};
 use arroyo_rpc::grpc::{CheckpointMetadata, OperatorCheckpointMetadata};
 use arroyo_rpc::{CheckpointCompleted, ControlMessage, ControlResp};
-use arroyo_types::{to_micros, CheckpointBarrier, Message, TaskInfo};
+use arroyo_types::{
+    from_millis, to_micros, CheckpointBarrier, Message, SourceBounds, SourcePosition, TaskInfo,
+};
 use rdkafka::admin::{AdminClient, AdminOptions, NewTopic};
-use rdkafka::producer::{BaseProducer, BaseRecord};
-use rdkafka::ClientConfig;
+use rdkafka::message::{OwnedHeaders, OwnedMessage};
+use rdkafka::producer::{BaseProducer, BaseRecord, Producer};
+use rdkafka::{ClientConfig, Timestamp};
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 
@@ -57,6 +63,7 @@ impl KafkaTopicTester {
         &self,
         task_info: TaskInfo,
         restore_from: Option<u32>,
+        bounds: SourceBounds,
     ) -> KafkaSourceWithReads {
         let mut kafka = KafkaSourceFunc::new(
             &self.server,
@@ -65,7 +72,9 @@ impl KafkaTopicTester {
             kafka::SerializationMode::Json,
             100,
             vec![],
+            vec![],
         );
+        kafka.bounds = bounds;
         let (to_control_tx, control_rx) = channel(128);
         let (command_tx, from_control_rx) = channel(128);
         let (data_tx, recv) = channel(128);
@@ -129,6 +138,10 @@ impl KafkaTopicProducer {
             .send(BaseRecord::<(), String>::to(&self.topic).payload(&json))
             .expect("could not send message")
     }
+
+    fn flush(&mut self) {
+        self.base_producer.flush(Duration::from_secs(10));
+    }
 }
 
 struct KafkaSourceWithReads {
@@ -197,7 +210,7 @@ async fn test_kafka() {
 
     kafka_topic_tester.create_topic().await;
     let mut reader = kafka_topic_tester
-        .get_source_with_reader(task_info.clone(), None)
+        .get_source_with_reader(task_info.clone(), None, SourceBounds::default())
         .await;
     let mut producer = kafka_topic_tester.get_producer();
 
@@ -231,7 +244,8 @@ async fn test_kafka() {
         backend_data: checkpoint_completed.subtask_metadata.backend_data,
         bytes: checkpoint_completed.subtask_metadata.bytes,
     })
-    .await;
+    .await
+    .unwrap();
 
     StateBackend::complete_checkpoint(CheckpointMetadata {
         job_id: task_info.job_id.clone(),
@@ -241,7 +255,8 @@ async fn test_kafka() {
         finish_time: 0,
         operator_ids: vec![task_info.operator_id.clone()],
     })
-    .await;
+    .await
+    .unwrap();
 
     reader.assert_next_message_record_value(20).await;
 
@@ -254,7 +269,7 @@ async fn test_kafka() {
         .unwrap();
 
     let mut reader = kafka_topic_tester
-        .get_source_with_reader(task_info, Some(1))
+        .get_source_with_reader(task_info, Some(1), SourceBounds::default())
         .await;
 
     // leftover metric
@@ -262,3 +277,203 @@ async fn test_kafka() {
     producer.send_data(TestData { i: 21 });
     reader.assert_next_message_record_value(21).await;
 }
+
+#[tokio::test]
+async fn test_kafka_bounded() {
+    let mut kafka_topic_tester = KafkaTopicTester {
+        topic: "arroyo-source-bounded".to_string(),
+        server: "0.0.0.0:9092".to_string(),
+    };
+
+    let mut task_info = arroyo_types::get_test_task_info();
+    task_info.job_id = format!("kafka-job-{}", rand::thread_rng().gen::<u64>());
+
+    kafka_topic_tester.create_topic().await;
+    let mut producer = kafka_topic_tester.get_producer();
+    for message in 1u64..=10 {
+        producer.send_data(TestData { i: message });
+    }
+    producer.flush();
+
+    // the end is resolved when the source starts, so later messages aren't read
+    let mut reader = kafka_topic_tester
+        .get_source_with_reader(
+            task_info,
+            None,
+            SourceBounds {
+                start: None,
+                end: Some(SourcePosition::Latest),
+            },
+        )
+        .await;
+    producer.send_data(TestData { i: 11 });
+    producer.flush();
+
+    let mut values = vec![];
+    loop {
+        let msg: Message<(), TestData> = reader.data_recv.recv().await.unwrap().into();
+        match msg {
+            Message::Record(record) => values.push(record.value.i),
+            Message::Watermark(watermark) => {
+                assert_eq!(from_millis(u64::MAX), watermark);
+                break;
+            }
+            msg => unreachable!("expected a record or watermark, got {:?}", msg),
+        }
+    }
+    values.sort();
+    assert_eq!((1u64..=10).collect::<Vec<_>>(), values);
+
+    loop {
+        let resp = reader.from_control_rx.recv().await.unwrap();
+        if let ControlResp::TaskReachedEnd { .. } = resp {
+            break;
+        }
+    }
+
+    // the final checkpoint stops the source
+    let barrier = ControlMessage::Checkpoint(CheckpointBarrier {
+        epoch: 1,
+        min_epoch: 0,
+        timestamp: SystemTime::now(),
+        then_stop: true,
+    });
+    reader.to_control_tx.send(barrier).await.unwrap();
+    reader.assert_control_checkpoint(1).await;
+    reader.assert_next_message_checkpoint(1).await;
+}
+
+#[test]
+fn test_metadata_fields() {
+    #[derive(Deserialize)]
+    struct WithMetadata {
+        i: u64,
+        key: Option<String>,
+        partition: i32,
+        offset: i64,
+        ts: SystemTime,
+        tenant: Option<String>,
+        missing: Option<String>,
+    }
+
+    let msg = OwnedMessage::new(
+        None,
+        Some(b"entity-1".to_vec()),
+        "topic".to_string(),
+        Timestamp::CreateTime(1_000),
+        3,
+        42,
+        Some(OwnedHeaders::new().add("tenant", "acme")),
+    );
+
+    let mut value = serde_json::json!({"i": 5});
+    add_metadata_fields(
+        &mut value,
+        &[
+            ("key".to_string(), MetadataField::Key),
+            ("partition".to_string(), MetadataField::Partition),
+            ("offset".to_string(), MetadataField::Offset),
+            ("ts".to_string(), MetadataField::Timestamp),
+            (
+                "tenant".to_string(),
+                MetadataField::Header("tenant".to_string()),
+            ),
+            (
+                "missing".to_string(),
+                MetadataField::Header("missing".to_string()),
+            ),
+        ],
+        &msg,
+    )
+    .unwrap();
+
+    let result: WithMetadata = serde_json::from_value(value).unwrap();
+    assert_eq!(5, result.i);
+    assert_eq!(Some("entity-1".to_string()), result.key);
+    assert_eq!(3, result.partition);
+    assert_eq!(42, result.offset);
+    assert_eq!(arroyo_types::from_millis(1_000), result.ts);
+    assert_eq!(Some("acme".to_string()), result.tenant);
+    assert_eq!(None, result.missing);
+
+    // payloads that are valid JSON but not objects can't have metadata added to them
+    let mut value = serde_json::json!([1, 2]);
+    assert!(
+        add_metadata_fields(&mut value, &[("key".to_string(), MetadataField::Key)], &msg).is_err()
+    );
+}
+
+#[test]
+fn test_bad_messages_are_rejected() {
+    let msg = OwnedMessage::new(
+        None,
+        None,
+        "topic".to_string(),
+        Timestamp::CreateTime(1_000),
+        0,
+        7,
+        None,
+    );
+
+    let source: KafkaSourceFunc<TestData> = KafkaSourceFunc::new(
+        "localhost:9092",
+        "topic",
+        OffsetMode::Earliest,
+        SerializationMode::Json,
+        100,
+        vec![],
+        vec![],
+    );
+    assert_eq!(
+        TestData { i: 1 },
+        source.deserialize(&msg, b"{\"i\": 1}").unwrap()
+    );
+    assert!(source.deserialize(&msg, b"not json").is_err());
+    assert!(source.deserialize(&msg, b"{\"j\": 1}").is_err());
+
+    #[derive(Debug, Clone, bincode::Encode, bincode::Decode, Serialize, Deserialize, PartialEq)]
+    struct WithOffset {
+        i: u64,
+        offset: i64,
+    }
+
+    let source: KafkaSourceFunc<WithOffset> = KafkaSourceFunc::new(
+        "localhost:9092",
+        "topic",
+        OffsetMode::Earliest,
+        SerializationMode::JsonSchemaRegistry,
+        100,
+        vec![],
+        vec![("offset", MetadataField::Offset)],
+    );
+    assert_eq!(
+        WithOffset { i: 1, offset: 7 },
+        source.deserialize(&msg, b"\0\0\0\0\x01{\"i\": 1}").unwrap()
+    );
+    // a non-object payload can't carry metadata fields, and a payload too short to have a
+    // schema registry header is bad data too
+    assert!(source.deserialize(&msg, b"\0\0\0\0\x01[1, 2]").is_err());
+    assert!(source.deserialize(&msg, b"\0\0").is_err());
+}
+
+#[test]
+fn test_assigned_partitions() {
+    // every partition is assigned to exactly one subtask, regardless of the order of the metadata
+    let partitions = vec![4, 1, 0, 3, 2, 5, 6];
+    let mut all: Vec<i32> = (0..3)
+        .flat_map(|i| assigned_partitions(partitions.clone(), i, 3))
+        .collect();
+    all.sort();
+    assert_eq!(vec![0, 1, 2, 3, 4, 5, 6], all);
+
+    assert_eq!(vec![0, 3, 6], assigned_partitions(partitions.clone(), 0, 3));
+
+    // adding partitions doesn't move any existing ones
+    let mut expanded = partitions.clone();
+    expanded.extend([7, 8, 9]);
+    for i in 0..3 {
+        let before = assigned_partitions(partitions.clone(), i, 3);
+        let after = assigned_partitions(expanded.clone(), i, 3);
+        assert!(before.iter().all(|p| after.contains(p)));
+    }
+}
@@ -0,0 +1,481 @@
+use crate::engine::{Context, StreamNode};
+use crate::SourceFinishType;
+use arroyo_macro::source_fn;
+use arroyo_rpc::grpc::TableDescriptor;
+use arroyo_rpc::{grpc::StopMode, ControlMessage};
+use arroyo_state::tables::GlobalKeyedState;
+use arroyo_types::*;
+use bincode::{Decode, Encode};
+use rusoto_core::Region;
+use rusoto_kinesis::{
+    GetRecordsInput, GetShardIteratorInput, Kinesis, KinesisClient, ListShardsInput, Shard,
+};
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, HashSet};
+use std::marker::PhantomData;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::select;
+use tracing::{debug, info, warn};
+
+#[cfg(test)]
+mod test;
+
+// kinesis allows five GetRecords calls per second per shard
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+// how often we check the stream for shards created by splits and merges
+const SHARD_DISCOVERY_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_RECORDS_PER_CALL: i64 = 10_000;
+
+#[derive(Copy, Clone, Debug)]
+pub enum OffsetMode {
+    Earliest,
+    Latest,
+}
+
+impl OffsetMode {
+    fn get_position(&self) -> ShardPosition {
+        match self {
+            OffsetMode::Earliest => ShardPosition::TrimHorizon,
+            OffsetMode::Latest => ShardPosition::Latest,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Encode, Decode, PartialEq, Eq)]
+pub enum ShardPosition {
+    TrimHorizon,
+    Latest,
+    AfterSequenceNumber(String),
+    // we've read to the end of a shard that was closed by a split or merge
+    Closed,
+}
+
+impl ShardPosition {
+    fn iterator_type(&self) -> Option<(&'static str, Option<String>)> {
+        match self {
+            ShardPosition::TrimHorizon => Some(("TRIM_HORIZON", None)),
+            ShardPosition::Latest => Some(("LATEST", None)),
+            ShardPosition::AfterSequenceNumber(s) => {
+                Some(("AFTER_SEQUENCE_NUMBER", Some(s.clone())))
+            }
+            ShardPosition::Closed => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Encode, Decode, PartialEq)]
+pub struct KinesisState {
+    shard_id: String,
+    position: ShardPosition,
+}
+
+pub(crate) struct ShardReader {
+    shard_id: String,
+    parents: Vec<String>,
+    position: ShardPosition,
+    iterator: Option<String>,
+}
+
+impl ShardReader {
+    /// A shard is only read once the parents it was split or merged from have been read to
+    /// their end, which preserves per-key ordering across resharding. The adjacent parent of a merged
+    /// shard may be read by another subtask; it (like parents that have expired from the stream)
+    /// does not block the child.
+    pub(crate) fn is_ready(&self, readers: &HashMap<String, ShardReader>) -> bool {
+        self.position != ShardPosition::Closed
+            && self.parents.iter().all(|p| {
+                readers
+                    .get(p)
+                    .map(|r| r.position == ShardPosition::Closed)
+                    .unwrap_or(true)
+            })
+    }
+}
+
+#[derive(StreamNode, Clone)]
+pub struct KinesisSourceFunc<T>
+where
+    T: DeserializeOwned + Data,
+{
+    stream_name: String,
+    region: String,
+    endpoint: Option<String>,
+    offset_mode: OffsetMode,
+    _t: PhantomData<T>,
+}
+
+pub fn tables() -> Vec<TableDescriptor> {
+    vec![arroyo_state::global_table("k", "kinesis source state")]
+}
+
+/// Creates a client for `region`; if `endpoint` is set (e.g., for a local emulator), requests
+/// are sent there instead of to AWS
+pub(crate) fn kinesis_client(region: &str, endpoint: Option<&str>) -> KinesisClient {
+    let region = match endpoint {
+        Some(endpoint) => Region::Custom {
+            name: region.to_string(),
+            endpoint: endpoint.to_string(),
+        },
+        None => {
+            Region::from_str(region).unwrap_or_else(|_| panic!("invalid AWS region {}", region))
+        }
+    };
+
+    KinesisClient::new(region)
+}
+
+/// Assigns shards to subtasks. A shard created by a split or merge is read by the same subtask
+/// as its parent (for merges, the primary parent), so that it isn't read until the parent has
+/// been read to its end; other shards are assigned by the hash of their id. Assignments are a
+/// function of the shard lineage, so every subtask arrives at the same assignment independently.
+#[derive(Default)]
+pub(crate) struct ShardAssigner {
+    owners: HashMap<String, usize>,
+}
+
+impl ShardAssigner {
+    pub(crate) fn assign(&mut self, shards: &[Shard], parallelism: usize) {
+        let listed: HashSet<&str> = shards.iter().map(|s| s.shard_id.as_str()).collect();
+
+        // children may be listed before their parents, so we repeat until every shard is assigned
+        loop {
+            let mut assigned = false;
+            for shard in shards {
+                if self.owners.contains_key(&shard.shard_id) {
+                    continue;
+                }
+
+                let owner = match &shard.parent_shard_id {
+                    Some(parent) if self.owners.contains_key(parent) => self.owners[parent],
+                    Some(parent) if listed.contains(parent.as_str()) => continue,
+                    // the parent has expired from the stream
+                    _ => arroyo_state::hash_key(&shard.shard_id) as usize % parallelism,
+                };
+
+                self.owners.insert(shard.shard_id.clone(), owner);
+                assigned = true;
+            }
+
+            if !assigned {
+                return;
+            }
+        }
+    }
+
+    pub(crate) fn owner(&self, shard_id: &str) -> Option<usize> {
+        self.owners.get(shard_id).copied()
+    }
+}
+
+#[source_fn(out_k = (), out_t = T)]
+impl<T> KinesisSourceFunc<T>
+where
+    T: DeserializeOwned + Data,
+{
+    pub fn new(
+        stream_name: &str,
+        region: &str,
+        endpoint: Option<&str>,
+        offset_mode: OffsetMode,
+    ) -> Self {
+        Self {
+            stream_name: stream_name.to_string(),
+            region: region.to_string(),
+            endpoint: endpoint.map(|e| e.to_string()),
+            offset_mode,
+            _t: PhantomData,
+        }
+    }
+
+    fn name(&self) -> String {
+        format!("kinesis-{}", self.stream_name)
+    }
+
+    fn tables(&self) -> Vec<TableDescriptor> {
+        tables()
+    }
+
+    async fn list_shards(&mut self, client: &KinesisClient) -> Result<Vec<Shard>, String> {
+        let mut shards = vec![];
+        let mut next_token = None;
+        loop {
+            // the stream name may not be set along with a next token
+            let input = match next_token.take() {
+                Some(token) => ListShardsInput {
+                    next_token: Some(token),
+                    ..Default::default()
+                },
+                None => ListShardsInput {
+                    stream_name: Some(self.stream_name.clone()),
+                    ..Default::default()
+                },
+            };
+
+            let output = client.list_shards(input).await.map_err(|e| e.to_string())?;
+
+            shards.extend(output.shards.unwrap_or_default());
+
+            match output.next_token {
+                Some(token) => next_token = Some(token),
+                None => return Ok(shards),
+            }
+        }
+    }
+
+    fn add_shards(
+        &self,
+        shards: Vec<Shard>,
+        assigner: &mut ShardAssigner,
+        readers: &mut HashMap<String, ShardReader>,
+        state: &HashMap<String, KinesisState>,
+        default_position: ShardPosition,
+        task_info: &TaskInfo,
+    ) {
+        assigner.assign(&shards, task_info.parallelism);
+
+        for shard in shards {
+            if readers.contains_key(&shard.shard_id)
+                || assigner.owner(&shard.shard_id) != Some(task_info.task_index)
+            {
+                continue;
+            }
+
+            let position = state
+                .get(&shard.shard_id)
+                .map(|s| s.position.clone())
+                .unwrap_or_else(|| default_position.clone());
+
+            info!(
+                "Reading kinesis shard {} of {} from {:?}",
+                shard.shard_id, self.stream_name, position
+            );
+
+            readers.insert(
+                shard.shard_id.clone(),
+                ShardReader {
+                    shard_id: shard.shard_id,
+                    parents: shard
+                        .parent_shard_id
+                        .into_iter()
+                        .chain(shard.adjacent_parent_shard_id)
+                        .collect(),
+                    position,
+                    iterator: None,
+                },
+            );
+        }
+    }
+
+    async fn init_shards(
+        &mut self,
+        client: &KinesisClient,
+        assigner: &mut ShardAssigner,
+        ctx: &mut Context<(), T>,
+    ) -> HashMap<String, ShardReader> {
+        // the global table contains the state of all subtasks, so after a rescale we pick up
+        // the shards that were previously read by other subtasks
+        let mut s: GlobalKeyedState<String, KinesisState, _> =
+            ctx.state.get_global_keyed_state('k').await;
+        let state: HashMap<String, KinesisState> = s
+            .get_all()
+            .map(|s| (s.shard_id.clone(), s.into_owned()))
+            .collect();
+
+        // when restoring, any shard missing from the state was created since and is read
+        // from the beginning
+        let default_position = if state.is_empty() {
+            self.offset_mode.get_position()
+        } else {
+            ShardPosition::TrimHorizon
+        };
+
+        let shards = self
+            .list_shards(client)
+            .await
+            .expect("failed to list kinesis shards");
+
+        let mut readers = HashMap::new();
+        self.add_shards(
+            shards,
+            assigner,
+            &mut readers,
+            &state,
+            default_position,
+            &ctx.task_info,
+        );
+        readers
+    }
+
+    async fn read_shard(
+        &mut self,
+        client: &KinesisClient,
+        reader: &mut ShardReader,
+        ctx: &mut Context<(), T>,
+    ) {
+        if reader.iterator.is_none() {
+            let Some((iterator_type, sequence_number)) = reader.position.iterator_type() else {
+                return;
+            };
+
+            match client
+                .get_shard_iterator(GetShardIteratorInput {
+                    shard_id: reader.shard_id.clone(),
+                    shard_iterator_type: iterator_type.to_string(),
+                    starting_sequence_number: sequence_number,
+                    stream_name: self.stream_name.clone(),
+                    ..Default::default()
+                })
+                .await
+            {
+                Ok(output) => reader.iterator = output.shard_iterator,
+                Err(e) => {
+                    warn!(
+                        "Failed to get iterator for kinesis shard {}: {:?}",
+                        reader.shard_id, e
+                    );
+                    return;
+                }
+            }
+        }
+
+        let Some(iterator) = reader.iterator.take() else {
+            return;
+        };
+
+        match client
+            .get_records(GetRecordsInput {
+                limit: Some(MAX_RECORDS_PER_CALL),
+                shard_iterator: iterator,
+            })
+            .await
+        {
+            Ok(output) => {
+                for record in output.records {
+                    let value = serde_json::from_slice(&record.data).unwrap_or_else(|_| {
+                        panic!(
+                            "Failed to deserialize message: {}",
+                            String::from_utf8_lossy(&record.data)
+                        )
+                    });
+
+                    ctx.collector
+                        .collect(Record {
+                            timestamp: record
+                                .approximate_arrival_timestamp
+                                .map(|secs| UNIX_EPOCH + Duration::from_secs_f64(secs))
+                                .unwrap_or_else(SystemTime::now),
+                            key: None,
+                            value,
+                        })
+                        .await;
+
+                    reader.position = ShardPosition::AfterSequenceNumber(record.sequence_number);
+                }
+
+                match output.next_shard_iterator {
+                    Some(next) => reader.iterator = Some(next),
+                    None => {
+                        info!("Finished reading closed kinesis shard {}", reader.shard_id);
+                        reader.position = ShardPosition::Closed;
+                    }
+                }
+            }
+            Err(e) => {
+                // the iterator will be recreated from our position on the next poll
+                warn!(
+                    "Failed to read from kinesis shard {}: {:?}",
+                    reader.shard_id, e
+                );
+            }
+        }
+    }
+
+    async fn run(&mut self, ctx: &mut Context<(), T>) -> SourceFinishType {
+        let client = kinesis_client(&self.region, self.endpoint.as_deref());
+        let mut assigner = ShardAssigner::default();
+        let mut readers = self.init_shards(&client, &mut assigner, ctx).await;
+
+        let mut poll = tokio::time::interval(POLL_INTERVAL);
+        poll.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        let mut shard_discovery = tokio::time::interval_at(
+            tokio::time::Instant::now() + SHARD_DISCOVERY_INTERVAL,
+            SHARD_DISCOVERY_INTERVAL,
+        );
+        shard_discovery.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            select! {
+                _ = poll.tick() => {
+                    let ready: Vec<String> = readers
+                        .values()
+                        .filter(|r| r.is_ready(&readers))
+                        .map(|r| r.shard_id.clone())
+                        .collect();
+
+                    for shard_id in ready {
+                        self.read_shard(&client, readers.get_mut(&shard_id).unwrap(), ctx).await;
+                    }
+                }
+                _ = shard_discovery.tick() => {
+                    match self.list_shards(&client).await {
+                        Ok(shards) => {
+                            // new shards are the result of a split or merge, so we read all of their data
+                            self.add_shards(
+                                shards,
+                                &mut assigner,
+                                &mut readers,
+                                &HashMap::new(),
+                                ShardPosition::TrimHorizon,
+                                &ctx.task_info,
+                            );
+                        }
+                        Err(e) => {
+                            warn!("Failed to list shards for kinesis stream {}: {:?}", self.stream_name, e);
+                        }
+                    }
+                }
+                control_message = ctx.control_rx.recv() => {
+                    match control_message {
+                        Some(ControlMessage::Checkpoint(c)) => {
+                            debug!("starting checkpointing {}", ctx.task_info.task_index);
+                            let mut s = ctx.state.get_global_keyed_state('k').await;
+                            for reader in readers.values() {
+                                s.insert(reader.shard_id.clone(), KinesisState {
+                                    shard_id: reader.shard_id.clone(),
+                                    position: reader.position.clone(),
+                                }).await;
+                            }
+
+                            if self.checkpoint(c, ctx).await {
+                                return SourceFinishType::Immediate;
+                            }
+                        },
+                        Some(ControlMessage::Stop { mode }) => {
+                            info!("Stopping kinesis source: {:?}", mode);
+
+                            match mode {
+                                StopMode::Graceful => {
+                                    return SourceFinishType::Graceful;
+                                }
+                                StopMode::Immediate => {
+                                    return SourceFinishType::Immediate;
+                                }
+                            }
+                        }
+                        Some(ControlMessage::Commit { .. }) => {
+                            // sources have nothing to commit
+                        }
+                        Some(ControlMessage::QueryState { table, key, respond }) => {
+                            let _ = respond.send(ctx.state.query(table, &key));
+                        }
+                        None => {
+
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
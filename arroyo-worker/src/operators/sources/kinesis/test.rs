@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use crate::engine::{Context, OutQueue, QueueItem};
+use crate::operators::sources::kinesis;
+use crate::operators::sources::kinesis::{
+    kinesis_client, KinesisSourceFunc, OffsetMode, ShardAssigner, ShardPosition, ShardReader,
+};
+use arroyo_rpc::grpc::{CheckpointMetadata, OperatorCheckpointMetadata};
+use arroyo_rpc::{CheckpointCompleted, ControlMessage, ControlResp};
+use arroyo_state::{BackingStore, StateBackend};
+use arroyo_types::{to_micros, CheckpointBarrier, Message, TaskInfo};
+use rand::Rng;
+use rusoto_kinesis::{
+    CreateStreamInput, DeleteStreamInput, DescribeStreamSummaryInput, Kinesis, KinesisClient,
+    PutRecordInput, Shard,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+// these tests expect a kinesis emulator (e.g., localstack) to be running locally; the AWS
+// credential environment variables must be set, although their values are not checked
+const ENDPOINT: &str = "http://localhost:4566";
+const REGION: &str = "us-east-1";
+
+#[derive(Debug, Clone, bincode::Encode, bincode::Decode, Serialize, Deserialize, PartialEq)]
+struct TestData {
+    i: u64,
+}
+
+struct KinesisStreamTester {
+    stream_name: String,
+    client: KinesisClient,
+}
+
+impl KinesisStreamTester {
+    fn new(stream_name: &str) -> Self {
+        Self {
+            stream_name: stream_name.to_string(),
+            client: kinesis_client(REGION, Some(ENDPOINT)),
+        }
+    }
+
+    async fn create_stream(&self) {
+        let _ = self
+            .client
+            .delete_stream(DeleteStreamInput {
+                stream_name: self.stream_name.clone(),
+                ..Default::default()
+            })
+            .await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        self.client
+            .create_stream(CreateStreamInput {
+                stream_name: self.stream_name.clone(),
+                shard_count: 2,
+            })
+            .await
+            .expect("stream creation should have worked");
+
+        loop {
+            let summary = self
+                .client
+                .describe_stream_summary(DescribeStreamSummaryInput {
+                    stream_name: self.stream_name.clone(),
+                })
+                .await
+                .unwrap();
+            if summary.stream_description_summary.stream_status == "ACTIVE" {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    async fn send_data(&self, data: TestData) {
+        self.client
+            .put_record(PutRecordInput {
+                stream_name: self.stream_name.clone(),
+                // all data goes to the same shard so that it's read in order
+                partition_key: "key".to_string(),
+                data: serde_json::to_vec(&data).unwrap().into(),
+                ..Default::default()
+            })
+            .await
+            .expect("could not send message");
+    }
+
+    async fn get_source_with_reader(
+        &self,
+        task_info: TaskInfo,
+        restore_from: Option<u32>,
+    ) -> KinesisSourceWithReads {
+        let mut kinesis = KinesisSourceFunc::new(
+            &self.stream_name,
+            REGION,
+            Some(ENDPOINT),
+            OffsetMode::Earliest,
+        );
+        let (to_control_tx, control_rx) = channel(128);
+        let (command_tx, from_control_rx) = channel(128);
+        let (data_tx, recv) = channel(128);
+
+        let checkpoint_metadata = restore_from.map(|epoch| CheckpointMetadata {
+            job_id: task_info.job_id.to_string(),
+            epoch,
+            min_epoch: 1,
+            start_time: to_micros(SystemTime::now()),
+            finish_time: to_micros(SystemTime::now()),
+            operator_ids: vec![task_info.operator_id.clone()],
+        });
+
+        let mut ctx: Context<(), TestData> = Context::new(
+            task_info,
+            checkpoint_metadata,
+            control_rx,
+            command_tx,
+            1,
+            vec![vec![OutQueue::new(data_tx, false)]],
+            kinesis::tables(),
+        )
+        .await;
+
+        tokio::spawn(async move {
+            kinesis.on_start(&mut ctx).await;
+            kinesis.run(&mut ctx).await;
+        });
+
+        KinesisSourceWithReads {
+            to_control_tx,
+            from_control_rx,
+            data_recv: recv,
+        }
+    }
+}
+
+struct KinesisSourceWithReads {
+    to_control_tx: Sender<ControlMessage>,
+    from_control_rx: Receiver<ControlResp>,
+    data_recv: Receiver<QueueItem>,
+}
+
+impl KinesisSourceWithReads {
+    async fn assert_next_message_record_value(&mut self, expected_value: u64) {
+        let msg: Message<(), TestData> = self.data_recv.recv().await.unwrap().into();
+        if let Message::Record(record) = msg {
+            assert_eq!(expected_value, record.value.i);
+        } else {
+            unreachable!("expected a record, got {:?}", msg);
+        }
+    }
+
+    async fn assert_control_checkpoint(&mut self, expected_epoch: u32) -> CheckpointCompleted {
+        loop {
+            let control_response = self
+                .from_control_rx
+                .recv()
+                .await
+                .expect("should be a valid message");
+
+            if let ControlResp::CheckpointCompleted(checkpoint) = control_response {
+                assert_eq!(expected_epoch, checkpoint.checkpoint_epoch);
+                return checkpoint;
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_kinesis() {
+    let tester = KinesisStreamTester::new("arroyo-source");
+
+    let mut task_info = arroyo_types::get_test_task_info();
+    task_info.job_id = format!("kinesis-job-{}", rand::thread_rng().gen::<u64>());
+
+    tester.create_stream().await;
+    let mut reader = tester.get_source_with_reader(task_info.clone(), None).await;
+
+    for message in 1u64..20 {
+        tester.send_data(TestData { i: message }).await;
+        reader.assert_next_message_record_value(message).await;
+    }
+
+    reader
+        .to_control_tx
+        .send(ControlMessage::Checkpoint(CheckpointBarrier {
+            epoch: 1,
+            min_epoch: 0,
+            timestamp: SystemTime::now(),
+            then_stop: false,
+        }))
+        .await
+        .unwrap();
+    let checkpoint_completed = reader.assert_control_checkpoint(1).await;
+
+    StateBackend::complete_operator_checkpoint(OperatorCheckpointMetadata {
+        job_id: task_info.job_id.clone(),
+        operator_id: task_info.operator_id.clone(),
+        epoch: 1,
+        start_time: 0,
+        finish_time: 0,
+        min_watermark: Some(0),
+        max_watermark: Some(0),
+        has_state: true,
+        tables: kinesis::tables(),
+        backend_data: checkpoint_completed.subtask_metadata.backend_data,
+        bytes: checkpoint_completed.subtask_metadata.bytes,
+    })
+    .await
+    .unwrap();
+
+    StateBackend::complete_checkpoint(CheckpointMetadata {
+        job_id: task_info.job_id.clone(),
+        epoch: 1,
+        min_epoch: 1,
+        start_time: 0,
+        finish_time: 0,
+        operator_ids: vec![task_info.operator_id.clone()],
+    })
+    .await
+    .unwrap();
+
+    reader
+        .to_control_tx
+        .send(ControlMessage::Stop {
+            mode: arroyo_rpc::grpc::StopMode::Graceful,
+        })
+        .await
+        .unwrap();
+
+    tester.send_data(TestData { i: 20 }).await;
+
+    // restoring resumes after the last record that was read before the checkpoint
+    let mut reader = tester.get_source_with_reader(task_info, Some(1)).await;
+    reader.assert_next_message_record_value(20).await;
+}
+
+#[test]
+fn test_shard_readiness() {
+    fn reader(shard_id: &str, parents: Vec<&str>, position: ShardPosition) -> ShardReader {
+        ShardReader {
+            shard_id: shard_id.to_string(),
+            parents: parents.into_iter().map(|p| p.to_string()).collect(),
+            position,
+            iterator: None,
+        }
+    }
+
+    let mut readers = HashMap::new();
+    for r in [
+        reader("parent", vec![], ShardPosition::TrimHorizon),
+        // split
+        reader("child-1", vec!["parent"], ShardPosition::TrimHorizon),
+        // merge with a shard owned by another subtask
+        reader(
+            "child-2",
+            vec!["parent", "other"],
+            ShardPosition::TrimHorizon,
+        ),
+        reader("orphan", vec!["expired"], ShardPosition::TrimHorizon),
+    ] {
+        readers.insert(r.shard_id.clone(), r);
+    }
+
+    let ready = |readers: &HashMap<String, ShardReader>| {
+        let mut ready: Vec<_> = readers
+            .values()
+            .filter(|r| r.is_ready(readers))
+            .map(|r| r.shard_id.clone())
+            .collect();
+        ready.sort();
+        ready
+    };
+
+    assert_eq!(vec!["orphan", "parent"], ready(&readers));
+
+    readers.get_mut("parent").unwrap().position = ShardPosition::Closed;
+    assert_eq!(vec!["child-1", "child-2", "orphan"], ready(&readers));
+}
+
+#[test]
+fn test_shard_assignment() {
+    fn shard(shard_id: &str, parent: Option<&str>, adjacent_parent: Option<&str>) -> Shard {
+        Shard {
+            shard_id: shard_id.to_string(),
+            parent_shard_id: parent.map(|p| p.to_string()),
+            adjacent_parent_shard_id: adjacent_parent.map(|p| p.to_string()),
+            ..Default::default()
+        }
+    }
+
+    let parallelism = 4;
+    // children are listed before their parents to check that the order doesn't matter
+    let shards = vec![
+        shard("merged", Some("split-1"), Some("root-2")),
+        shard("split-1", Some("root-1"), None),
+        shard("split-2", Some("root-1"), None),
+        shard("root-1", None, None),
+        shard("root-2", None, None),
+        shard("orphan", Some("expired"), None),
+    ];
+
+    let mut assigner = ShardAssigner::default();
+    assigner.assign(&shards, parallelism);
+
+    let root = assigner.owner("root-1").unwrap();
+    for child in ["split-1", "split-2", "merged"] {
+        assert_eq!(Some(root), assigner.owner(child), "{}", child);
+    }
+
+    for id in ["root-2", "orphan"] {
+        assert_eq!(
+            Some(arroyo_state::hash_key(&id) as usize % parallelism),
+            assigner.owner(id)
+        );
+    }
+
+    // every subtask arrives at the same assignment, and shards discovered later follow their
+    // parents
+    let mut other = ShardAssigner::default();
+    other.assign(&shards[3..], parallelism);
+    other.assign(&shards, parallelism);
+    for s in &shards {
+        assert_eq!(assigner.owner(&s.shard_id), other.owner(&s.shard_id));
+    }
+}
@@ -1,27 +1,16 @@
-use std::collections::{HashMap, HashSet};
-use std::fs::File;
-use std::marker::PhantomData;
-use std::path::{Path, PathBuf};
-
-use std::fmt::Debug;
-use std::io;
-use std::io::BufRead;
-
 use crate::engine::{Context, StreamNode};
 use crate::SourceFinishType;
 use arroyo_macro::source_fn;
 use arroyo_rpc::grpc::{StopMode, TableDescriptor};
 use arroyo_rpc::ControlMessage;
-use arroyo_state::tables::GlobalKeyedState;
 use arroyo_types::*;
 use bincode::{Decode, Encode};
-use serde::de::DeserializeOwned;
-use std::time::{Duration, Instant, SystemTime};
-use tokio::select;
-use tokio::time::sleep;
+use std::time::{Duration, SystemTime};
 use tracing::{debug, info};
 
+pub mod filesystem;
 pub mod kafka;
+pub mod kinesis;
 pub mod nexmark;
 
 #[derive(Encode, Decode, Debug, Copy, Clone, Eq, PartialEq)]
@@ -89,7 +78,7 @@ impl ImpulseSourceFunc {
     }
 
     async fn on_start(&mut self, ctx: &mut Context<(), ImpulseEvent>) {
-        let s = ctx
+        let mut s = ctx
             .state
             .get_global_keyed_state::<usize, ImpulseSourceState>('i')
             .await;
@@ -150,6 +139,16 @@ impl ImpulseSourceFunc {
                         }
                     }
                 }
+                Ok(ControlMessage::Commit { .. }) => {
+                    // sources have nothing to commit
+                }
+                Ok(ControlMessage::QueryState {
+                    table,
+                    key,
+                    respond,
+                }) => {
+                    let _ = respond.send(ctx.state.query(table, &key));
+                }
                 Err(_) => {
                     // no messages
                 }
@@ -163,158 +162,3 @@ impl ImpulseSourceFunc {
         SourceFinishType::Final
     }
 }
-
-#[derive(StreamNode)]
-pub struct FileSourceFunc<T>
-where
-    T: DeserializeOwned + Data,
-{
-    input_files: Vec<PathBuf>,
-    interval: Duration,
-    state: HashMap<PathBuf, usize>,
-    _t: PhantomData<T>,
-}
-
-#[source_fn(out_t = T)]
-impl<T> FileSourceFunc<T>
-where
-    T: DeserializeOwned + Data,
-{
-    pub fn from_dir(dir: &Path, interval: Duration) -> Self {
-        info!("Creating FileSourceFunc from dir {:?}", dir);
-
-        if !dir.is_dir() {
-            panic!("{:?} is not dir", dir);
-        }
-
-        let input_files = dir
-            .read_dir()
-            .unwrap()
-            .filter_map(|f| f.ok())
-            .filter(|f| f.metadata().map(|m| m.is_file()).unwrap_or(false))
-            .map(|f| f.path())
-            .collect();
-
-        Self {
-            input_files,
-            interval,
-            state: HashMap::new(),
-            _t: PhantomData,
-        }
-    }
-
-    pub fn from_files(input_files: Vec<PathBuf>, interval: Duration) -> Self {
-        info!("Creating FileSourceFunc from files {:?}", input_files);
-        Self {
-            input_files,
-            interval,
-            state: HashMap::new(),
-            _t: PhantomData,
-        }
-    }
-
-    fn name(&self) -> String {
-        "FileSource".to_string()
-    }
-
-    async fn on_start(&mut self, ctx: &mut Context<(), T>) {
-        self.state = {
-            let mut store: GlobalKeyedState<String, _, _> =
-                ctx.state.get_global_keyed_state('f').await;
-            let state = store.get_all();
-            let state: HashMap<PathBuf, usize> = state
-                .into_iter()
-                .map(|v: &(PathBuf, usize)| (*v).clone())
-                .collect();
-            state
-        };
-    }
-
-    async fn run(&mut self, ctx: &mut Context<(), T>) -> SourceFinishType {
-        let input_files: Vec<PathBuf> = self
-            .input_files
-            .iter()
-            .enumerate()
-            .filter(|(i, _)| i % ctx.task_info.parallelism == ctx.task_info.task_index)
-            .map(|(_, x)| x.clone())
-            .collect();
-
-        let interval = self.interval;
-        // TODO: split input files amongst tasks
-        for path in input_files {
-            if self.state.get(&path) == Some(&usize::MAX) {
-                info!("skipping already read file: {:?}", path);
-                continue;
-            }
-
-            let to_skip = *self.state.get(&path).unwrap_or(&0);
-
-            let file = File::open(&path).unwrap();
-
-            let mut last_check = Instant::now();
-            let mut i = 0;
-            for line in io::BufReader::new(file).lines() {
-                while i < to_skip {
-                    i += 1;
-                    continue;
-                }
-
-                let s = line.unwrap();
-                let value = serde_json::from_str(&s).unwrap();
-                ctx.collector
-                    .collect(Record::<(), T>::from_value(SystemTime::now(), value).unwrap())
-                    .await;
-
-                i += 1;
-
-                if !interval.is_zero() || last_check.elapsed() > Duration::from_millis(500) {
-                    {
-                        let mut store = ctx.state.get_global_keyed_state('f').await;
-                        store
-                            .insert(path.to_string_lossy().to_string(), (path.clone(), i))
-                            .await;
-                    }
-                    select! {
-                        val = ctx.control_rx.recv() => {
-                            match val {
-                                Some(ControlMessage::Checkpoint(c)) => {
-                                    // checkpoint our state
-                                    debug!("starting checkpointing {}", ctx.task_info.task_index);
-                                    if self.checkpoint(c, ctx).await {
-                                        return SourceFinishType::Immediate;
-                                    }
-                                },
-                                Some(ControlMessage::Stop { mode }) => {
-                                    debug!("Stopping file source {:?}", mode);
-
-                                    match mode {
-                                        StopMode::Graceful => {
-                                            return SourceFinishType::Graceful;
-                                        }
-                                        StopMode::Immediate => {
-                                            return SourceFinishType::Immediate;
-                                        }
-                                    }
-                                }
-                                _ => {},
-                            }
-                        },
-                        _val = sleep(interval) => {
-                            // continue
-                        }
-                    }
-                    last_check = Instant::now();
-                }
-            }
-        }
-
-        info!("file source finished");
-        SourceFinishType::Final
-    }
-}
-
-#[derive(Encode, Decode)]
-struct FileSourceState {
-    completed: HashSet<PathBuf>,
-    current_file: Option<(PathBuf, usize)>,
-}
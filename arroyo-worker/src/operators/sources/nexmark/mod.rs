@@ -94,7 +94,7 @@ impl NexmarkSourceFunc {
     async fn on_start(&mut self, ctx: &mut Context<(), Event>) {
         // load state
         self.state = Some({
-            let ss = ctx
+            let mut ss = ctx
                 .state
                 .get_global_keyed_state::<usize, NexmarkSourceState>('s')
                 .await;
@@ -171,6 +171,13 @@ impl NexmarkSourceFunc {
                             }
                         }
                     }
+                    Ok(ControlMessage::QueryState {
+                        table,
+                        key,
+                        respond,
+                    }) => {
+                        let _ = respond.send(ctx.state.query(table, &key));
+                    }
                     Err(TryRecvError::Empty) => {}
                     x => {
                         warn!("{:?}", x);
@@ -10,8 +10,8 @@ use tracing::debug;
 #[derive(StreamNode)]
 pub struct TumblingAggregatingWindowFunc<K: Key, T: Data, BinA: Data, OutT: Data> {
     width: Duration,
-    aggregator: fn(&BinA) -> OutT,
-    bin_merger: fn(&T, Option<&BinA>) -> BinA,
+    aggregator: Box<dyn Fn(&BinA) -> OutT + Send>,
+    bin_merger: Box<dyn Fn(&T, Option<&BinA>) -> BinA + Send>,
     state: TumblingWindowState,
     _t: PhantomData<K>,
 }
@@ -32,13 +32,13 @@ impl<K: Key, T: Data, BinA: Data, OutT: Data> TumblingAggregatingWindowFunc<K, T
     pub fn new(
         width: Duration,
         // TODO: this can consume the bin, as we drop it right after.
-        aggregator: fn(&BinA) -> OutT,
-        bin_merger: fn(&T, Option<&BinA>) -> BinA,
+        aggregator: impl Fn(&BinA) -> OutT + Send + 'static,
+        bin_merger: impl Fn(&T, Option<&BinA>) -> BinA + Send + 'static,
     ) -> Self {
         TumblingAggregatingWindowFunc {
             width,
-            aggregator,
-            bin_merger,
+            aggregator: Box::new(aggregator),
+            bin_merger: Box::new(bin_merger),
             state: TumblingWindowState::NoData,
             _t: PhantomData,
         }
@@ -63,6 +63,8 @@ impl<K: Key, T: Data, BinA: Data, OutT: Data> TumblingAggregatingWindowFunc<K, T
             delete_behavior: TableDeleteBehavior::NoReadsBeforeWatermark as i32,
             write_behavior: TableWriteBehavior::NoWritesBeforeWatermark as i32,
             retention_micros: self.width.as_micros() as u64,
+            schema: None,
+            ttl: None,
         }]
     }
 
@@ -91,6 +91,8 @@ impl<K: Key, T: Data, SK: Ord + Send + 'static, OutT: Data> TumblingTopNWindowFu
             delete_behavior: TableDeleteBehavior::NoReadsBeforeWatermark as i32,
             write_behavior: TableWriteBehavior::NoWritesBeforeWatermark as i32,
             retention_micros: self.width.as_micros() as u64,
+            schema: None,
+            ttl: None,
         }]
     }
 
@@ -192,8 +194,9 @@ impl<K: Key, T: Data, SK: Ord + Send + 'static, OutT: Data> TumblingTopNWindowFu
         let watermark = ctx.watermark();
         let mut state: TimeKeyMap<(K, u64), (SystemTime, T), _> =
             ctx.state.get_time_key_map('w', ctx.watermark()).await;
-        for (_bin_time, (key, _rank), (timestamp, value)) in state.get_all().await {
-            self.insert(key.clone(), *timestamp, value.clone(), watermark);
+        for (_bin_time, key, value) in state.get_all().await {
+            let ((key, _rank), (timestamp, value)) = (key.into_owned(), value.into_owned());
+            self.insert(key, timestamp, value, watermark);
         }
     }
 
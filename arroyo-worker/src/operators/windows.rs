@@ -104,6 +104,8 @@ impl<K: Key, T: Data, OutT: Data, W: TimeWindowAssigner<K, T>> KeyedWindowFunc<K
             delete_behavior: TableDeleteBehavior::NoReadsBeforeWatermark as i32,
             write_behavior: TableWriteBehavior::NoWritesBeforeWatermark as i32,
             retention_micros: self.assigner.safe_retention_duration().unwrap().as_micros() as u64,
+            schema: None,
+            ttl: None,
         }]
     }
 
@@ -18,7 +18,7 @@ pub fn make_graph() -> DiGraph<LogicalNode, LogicalEdge> {
 
 pub fn main() {
     let graph = make_graph();
-    arroyo_worker::WorkerServer::new("test", "dpw9h0g9sczp7b2b", graph)
+    arroyo_worker::WorkerServer::new("dpw9h0g9sczp7b2b", graph)
         .start()
         .unwrap();
 }